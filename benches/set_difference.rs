@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+
+use saber::integer_set::ops::MergeDifference;
+
+const NUM_INTERVALS: usize = 100_000;
+
+/// Intervals of length `len` starting every `period` elements from `offset`
+fn get_periodic_set(
+    offset: usize,
+    len: usize,
+    period: usize,
+) -> OrderedIntegerSet<usize> {
+    OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+        (0..NUM_INTERVALS)
+            .map(|i| {
+                let start = offset + i * period;
+                ContiguousIntegerSet::new(start, start + len - 1)
+            })
+            .collect(),
+    )
+}
+
+fn set_difference(c: &mut Criterion) {
+    let a = get_periodic_set(0, 7, 10);
+    let b = get_periodic_set(5, 4, 10);
+    c.bench_function("sub", |bench| bench.iter(|| a.clone() - &b));
+    c.bench_function("merge_difference", |bench| {
+        bench.iter(|| a.merge_difference(&b))
+    });
+}
+
+criterion_group!(benches, set_difference);
+criterion_main!(benches);
@@ -0,0 +1,143 @@
+//! The heritability estimation and phenotype simulation library of saber,
+//! without clap, colored or any of the command line tools.
+
+pub use saber::error::Error;
+
+/// Readers for the PLINK genotype files consumed by the estimators
+pub mod genotype {
+    pub use biofile::{
+        plink_bed::{PlinkBed, PlinkSnpType},
+        plink_bim::PlinkBim,
+    };
+}
+
+/// Projecting fixed-effect covariates out of the phenotypes and kernels
+pub mod covariate {
+    pub use saber::covariate::*;
+}
+
+/// The environment variables of the gene-by-environment components
+pub mod environment {
+    pub use saber::environment::*;
+}
+
+/// The bivariate estimates of the genetic correlation of two traits
+pub mod genetic_correlation {
+    pub use saber::genetic_correlation::*;
+}
+
+pub mod estimators {
+    pub use saber::{
+        accumulation::*, genotype_sketch::*, heritability_estimator::*,
+        jackknife::*, linear_operator::*, nnls::*, trace_estimator::*,
+    };
+}
+
+/// Configuring the heritability estimation with a builder
+pub mod estimator_builder {
+    pub use saber::estimator_builder::*;
+}
+
+/// Reporting the progress of the estimators, or silencing it
+pub mod progress {
+    pub use saber::progress::*;
+}
+
+pub mod simulation {
+    pub use saber::simulation::*;
+}
+
+/// The trace and quadratic-form primitives for custom variance component
+/// models
+pub mod stochastic {
+    pub use saber::stochastic::*;
+}
+
+/// Accumulating the heritability statistics over batches of SNPs that are
+/// genotyped over time
+pub mod incremental {
+    pub use saber::incremental::*;
+}
+
+/// Saving and resuming the progress of long heritability runs
+pub mod checkpoint {
+    pub use saber::checkpoint::*;
+}
+
+/// The threads, the memory budget, the CPU or GPU backend and the probe
+/// workspaces of the estimators
+pub mod compute {
+    pub use saber::{compute::*, gpu::*, workspace::*};
+}
+
+/// Stochastic AI-REML estimates of the variance components
+pub mod reml {
+    pub use saber::reml::*;
+}
+
+/// Computing the GRM out of core and exporting it in the GCTA format
+pub mod grm {
+    pub use saber::grm::*;
+}
+
+/// Partitioning the SNPs into minor allele frequency bins
+pub mod maf_bins {
+    pub use saber::maf_bins::*;
+}
+
+/// Regressing large-effect SNPs out of the phenotypes as fixed effects
+pub mod fixed_snps {
+    pub use saber::fixed_snps::*;
+}
+
+/// The X chromosome component and the dosage compensation of its male
+/// genotypes
+pub mod sex_chrom {
+    pub use saber::sex_chrom::*;
+}
+
+/// The MAF, missingness and monomorphism QC of the SNPs before the
+/// estimation
+pub mod snp_qc {
+    pub use saber::snp_qc::*;
+}
+
+/// The policies for the missing genotypes in the standardized SNP chunks
+pub mod missing_genotype {
+    pub use saber::missing_genotype::*;
+}
+
+/// The exact traces from the explicit kernels for small cohorts
+pub mod exact {
+    pub use saber::exact::*;
+}
+
+/// Per-SNP weights of the kernels, e.g. the LDAK weights
+pub mod snp_weights {
+    pub use saber::snp_weights::*;
+}
+
+/// Haseman-Elston regression estimates of the variance components
+pub mod he_regression {
+    pub use saber::he_regression::*;
+}
+
+/// Stochastic Lanczos quadrature estimates of the spectral sums of the
+/// kernels, e.g. the log determinant or the eigenvalue histogram of the GRM
+pub mod spectral {
+    pub use saber::spectral::*;
+}
+
+pub mod results {
+    pub use saber::heritability_estimate::{
+        GxgHeritabilityResult, HeritabilityEstimate,
+    };
+    pub use saber::parametric_bootstrap::ParametricBootstrapInterval;
+    pub use saber::partitioned_jackknife_estimates::{
+        LocoEstimates, PartitionHeritability, PartitionedHeritabilityEstimate,
+        PartitionedJackknifeEstimates, PhenoEstimateTable,
+    };
+    pub use saber::resampling::ResamplingBlocks;
+}
+
+pub use saber::{integer_set, matrix_ops, util};
@@ -0,0 +1,266 @@
+//! The precision in which the trace estimators accumulate their sums. The
+//! genotypes and the chunk products stay in f32, but a trace sums the squares
+//! of every entry of `K Z` over all the SNP chunks, and with millions of terms
+//! a plain f32 sum loses most of its significant digits to rounding.
+//! `AccumulationPrecision::F64` adds the f32 terms in f64, and
+//! `AccumulationPrecision::KahanF32` keeps an f32 sum together with the
+//! running compensation of its rounding error.
+
+use std::{fmt, str::FromStr};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AccumulationPrecision {
+    F32,
+    KahanF32,
+    F64,
+}
+
+impl Default for AccumulationPrecision {
+    fn default() -> AccumulationPrecision {
+        AccumulationPrecision::F32
+    }
+}
+
+impl FromStr for AccumulationPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccumulationPrecision, String> {
+        match s {
+            "f32" => Ok(AccumulationPrecision::F32),
+            "kahan" => Ok(AccumulationPrecision::KahanF32),
+            "f64" => Ok(AccumulationPrecision::F64),
+            _ => Err(format!(
+                "unrecognized accumulation precision {}, expected one of f32, \
+                kahan and f64",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AccumulationPrecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccumulationPrecision::F32 => write!(f, "f32"),
+            AccumulationPrecision::KahanF32 => write!(f, "kahan"),
+            AccumulationPrecision::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+impl AccumulationPrecision {
+    /// The sum of the `terms` in this precision. The terms are passed as f64
+    /// so that the f32 values and their exact products can be given alike,
+    /// and each term is rounded to f32 before it is added in the f32
+    /// precisions.
+    pub fn sum<I: IntoIterator<Item = f64>>(self, terms: I) -> f64 {
+        match self {
+            AccumulationPrecision::F32 => {
+                terms.into_iter().fold(0f32, |acc, x| acc + x as f32) as f64
+            }
+            AccumulationPrecision::KahanF32 => {
+                let mut sum = KahanSum::new();
+                terms.into_iter().for_each(|x| sum.add(x as f32));
+                sum.value() as f64
+            }
+            AccumulationPrecision::F64 => terms.into_iter().sum(),
+        }
+    }
+
+    pub fn sum_of_squares<I: IntoIterator<Item = f64>>(self, values: I) -> f64 {
+        self.sum(values.into_iter().map(|x| x * x))
+    }
+
+    pub fn dot<I, J>(self, a: I, b: J) -> f64
+    where
+        I: IntoIterator<Item = f64>,
+        J: IntoIterator<Item = f64>, {
+        self.sum(a.into_iter().zip(b.into_iter()).map(|(x, y)| x * y))
+    }
+}
+
+/// The Kahan compensated sum of f32 values, whose error does not grow with
+/// the number of terms
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct KahanSum {
+    sum: f32,
+    compensation: f32,
+}
+
+impl KahanSum {
+    pub fn new() -> KahanSum {
+        KahanSum::default()
+    }
+
+    #[inline]
+    pub fn add(&mut self, x: f32) {
+        let y = x - self.compensation;
+        let t = self.sum + y;
+        self.compensation = (t - self.sum) - y;
+        self.sum = t;
+    }
+
+    /// Adds the sum of another accumulator, e.g. of another thread
+    pub fn merge(&mut self, other: &KahanSum) {
+        self.add(other.sum);
+        self.add(-other.compensation);
+    }
+
+    pub fn value(&self) -> f32 {
+        self.sum
+    }
+}
+
+/// The element-wise sums of f32 arrays of the same length, e.g. the products
+/// `X_c X_c^T Z` of the SNP chunks `c`, in a given precision
+#[derive(Clone, PartialEq, Debug)]
+pub enum ArrayAccumulator {
+    F32(Vec<f32>),
+    KahanF32(Vec<KahanSum>),
+    F64(Vec<f64>),
+}
+
+impl ArrayAccumulator {
+    pub fn zeros(precision: AccumulationPrecision, len: usize) -> Self {
+        match precision {
+            AccumulationPrecision::F32 => ArrayAccumulator::F32(vec![0.; len]),
+            AccumulationPrecision::KahanF32 => {
+                ArrayAccumulator::KahanF32(vec![KahanSum::new(); len])
+            }
+            AccumulationPrecision::F64 => ArrayAccumulator::F64(vec![0.; len]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ArrayAccumulator::F32(acc) => acc.len(),
+            ArrayAccumulator::KahanF32(acc) => acc.len(),
+            ArrayAccumulator::F64(acc) => acc.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the `values` element-wise. Extra values beyond the length of the
+    /// accumulator are ignored.
+    pub fn add<'a, I: IntoIterator<Item = &'a f32>>(&mut self, values: I) {
+        match self {
+            ArrayAccumulator::F32(acc) => acc
+                .iter_mut()
+                .zip(values.into_iter())
+                .for_each(|(a, &x)| *a += x),
+            ArrayAccumulator::KahanF32(acc) => acc
+                .iter_mut()
+                .zip(values.into_iter())
+                .for_each(|(a, &x)| a.add(x)),
+            ArrayAccumulator::F64(acc) => acc
+                .iter_mut()
+                .zip(values.into_iter())
+                .for_each(|(a, &x)| *a += x as f64),
+        }
+    }
+
+    /// Adds the sums of another accumulator of the same precision and length
+    pub fn merge(self, other: ArrayAccumulator) -> ArrayAccumulator {
+        match (self, other) {
+            (ArrayAccumulator::F32(mut a), ArrayAccumulator::F32(b)) => {
+                a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x += y);
+                ArrayAccumulator::F32(a)
+            }
+            (
+                ArrayAccumulator::KahanF32(mut a),
+                ArrayAccumulator::KahanF32(b),
+            ) => {
+                a.iter_mut().zip(b.iter()).for_each(|(x, y)| x.merge(y));
+                ArrayAccumulator::KahanF32(a)
+            }
+            (ArrayAccumulator::F64(mut a), ArrayAccumulator::F64(b)) => {
+                a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x += y);
+                ArrayAccumulator::F64(a)
+            }
+            _ => panic!("cannot merge accumulators of different precisions"),
+        }
+    }
+
+    pub fn get_precision(&self) -> AccumulationPrecision {
+        match self {
+            ArrayAccumulator::F32(_) => AccumulationPrecision::F32,
+            ArrayAccumulator::KahanF32(_) => AccumulationPrecision::KahanF32,
+            ArrayAccumulator::F64(_) => AccumulationPrecision::F64,
+        }
+    }
+
+    /// The sums as f64, which are exactly the f32 sums in the f32 precisions
+    pub fn into_f64(self) -> Vec<f64> {
+        match self {
+            ArrayAccumulator::F32(acc) => {
+                acc.into_iter().map(|x| x as f64).collect()
+            }
+            ArrayAccumulator::KahanF32(acc) => {
+                acc.into_iter().map(|x| x.value() as f64).collect()
+            }
+            ArrayAccumulator::F64(acc) => acc,
+        }
+    }
+
+    /// The sum of the squares of the accumulated sums, in the same precision
+    pub fn sum_of_squares(self) -> f64 {
+        let precision = self.get_precision();
+        precision.sum_of_squares(self.into_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccumulationPrecision, ArrayAccumulator, KahanSum};
+
+    #[test]
+    fn test_accumulation_precision() {
+        // a million terms of 0.1, the scale of the sums of squares of a trace
+        let terms = vec![0.1f32 as f64; 1_000_000];
+        let exact = 1e6 * 0.1f32 as f64;
+        let relative_error = |precision: AccumulationPrecision| {
+            (precision.sum(terms.iter().cloned()) - exact).abs() / exact
+        };
+        // the plain f32 sum drifts by about a percent
+        assert!(relative_error(AccumulationPrecision::F32) > 1e-3);
+        assert!(relative_error(AccumulationPrecision::KahanF32) < 1e-7);
+        assert!(relative_error(AccumulationPrecision::F64) < 1e-12);
+
+        assert_eq!(
+            AccumulationPrecision::F64.sum_of_squares(vec![1., 2., 3.]),
+            14.
+        );
+        assert_eq!(
+            AccumulationPrecision::KahanF32.dot(vec![1., 2.], vec![3., 4.]),
+            11.
+        );
+
+        let mut a = KahanSum::new();
+        let mut b = KahanSum::new();
+        for _ in 0..500_000 {
+            a.add(0.1);
+            b.add(0.1);
+        }
+        a.merge(&b);
+        assert!((a.value() as f64 - exact).abs() / exact < 1e-7);
+
+        let mut acc = ArrayAccumulator::zeros(AccumulationPrecision::F64, 2);
+        let mut other = acc.clone();
+        acc.add(&[1f32, 2.]);
+        other.add(&[0.5f32, 0.25]);
+        let acc = acc.merge(other);
+        assert_eq!(acc.len(), 2);
+        assert_eq!(acc.clone().into_f64(), vec![1.5, 2.25]);
+        assert_eq!(acc.sum_of_squares(), 1.5 * 1.5 + 2.25 * 2.25);
+
+        assert_eq!(
+            "kahan".parse::<AccumulationPrecision>(),
+            Ok(AccumulationPrecision::KahanF32)
+        );
+        assert!("f16".parse::<AccumulationPrecision>().is_err());
+        assert_eq!(AccumulationPrecision::default().to_string(), "f32");
+    }
+}
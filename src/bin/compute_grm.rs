@@ -0,0 +1,155 @@
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_numeric_arg, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_str_arg, extract_str_vec_arg,
+    },
+    OrExit,
+};
+
+use saber::{
+    grm::{get_gcta_grm_paths, write_gcta_grm},
+    progress::StdoutProgress,
+    snp_chunk_filter::SnpChunkFilter,
+    snp_weights::SnpWeights,
+    util::{
+        get_bed_bim_fam_path, get_bed_bim_from_prefix_and_partition,
+        get_fid_iid_list,
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let mut app = clap_app!(compute_grm =>
+        (version: "0.1")
+    );
+    app = app
+        .about(
+            "Computes the GRM of the standardized genotypes a block of rows at a time and\n\
+            writes it in the binary GRM format of GCTA, i.e. the files\n\
+            <out>.grm.bin <out>.grm.N.bin <out>.grm.id"
+        )
+        .arg(
+            Arg::with_name("plink_filename_prefix")
+                .long("bfile").short("b").takes_value(true).required(true)
+                .multiple(true).number_of_values(1)
+                .help(
+                    "If we have files named \n\
+                    PATH/TO/x.bed PATH/TO/x.bim PATH/TO/x.fam \n\
+                    then the <plink_filename_prefix> should be path/to/x"
+                )
+        )
+        .arg(
+            Arg::with_name("out_prefix")
+                .long("out").short("o").takes_value(true).required(true)
+                .help("The prefix of the GCTA GRM files to write")
+        )
+        .arg(
+            Arg::with_name("snp_weights_path")
+                .long("snp-weights").takes_value(true)
+                .help(
+                    "A file with a SNP_ID WEIGHT pair on every line, e.g. the weights.short\n\
+                    file of LDAK, to weight the SNPs in the GRM. The SNPs missing from\n\
+                    the file get a weight of zero"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_maf")
+                .long("qc-maf").takes_value(true)
+                .help(
+                    "The SNPs with a minor allele frequency below <qc_maf> fail the QC, which\n\
+                    is applied to every SNP chunk as the genotypes are streamed"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_max_missing")
+                .long("qc-max-missing").takes_value(true)
+                .help(
+                    "The SNPs with a missing rate above <qc_max_missing> fail the QC.\n\
+                    The monomorphic SNPs always fail, and the failing SNPs are left out of\n\
+                    the GRM"
+                )
+        )
+        .arg(
+            Arg::with_name("no_qc")
+                .long("no-qc")
+                .conflicts_with_all(&["qc_maf", "qc_max_missing"])
+                .help("Skips the SNP QC")
+        )
+        .arg(
+            Arg::with_name("num_people_per_block")
+                .long("block-size").takes_value(true).default_value("512")
+                .help(
+                    "The number of rows of the GRM computed from every pass over the\n\
+                    genotypes and held in memory"
+                )
+        );
+    let matches = app.get_matches();
+
+    let plink_filename_prefixes =
+        extract_str_vec_arg(&matches, "plink_filename_prefix")
+            .unwrap_or_exit(Some("failed to parse the bfile list".to_string()));
+    let out_prefix = extract_str_arg(&matches, "out_prefix");
+    let snp_weights_path =
+        extract_optional_str_arg(&matches, "snp_weights_path");
+    let num_people_per_block =
+        extract_numeric_arg::<usize>(&matches, "num_people_per_block")
+            .unwrap_or_exit(Some("failed to extract num_people_per_block"));
+    // the SNPs failing the QC are dropped from the SNP chunks as they are
+    // streamed, so there is no separate QC pass over the genotypes
+    let snp_qc_filter = if matches.is_present("no_qc") {
+        None
+    } else {
+        let qc_maf = extract_optional_numeric_arg::<f32>(&matches, "qc_maf")
+            .unwrap_or_exit(Some("failed to extract qc_maf"));
+        let qc_max_missing =
+            extract_optional_numeric_arg::<f32>(&matches, "qc_max_missing")
+                .unwrap_or_exit(Some("failed to extract qc_max_missing"));
+        Some(SnpChunkFilter::new(qc_maf, qc_max_missing).dropping_monomorphic())
+    };
+    println!(
+        "PLINK bfile prefixes: {:?}\n\
+        out_prefix: {}\n\
+        snp_weights_path: {}\n\
+        num_people_per_block: {}\n\
+        snp_qc_filter: {:?}",
+        plink_filename_prefixes,
+        out_prefix,
+        snp_weights_path.as_ref().unwrap_or(&"".to_string()),
+        num_people_per_block,
+        snp_qc_filter
+    );
+
+    let (bed, _bim) = get_bed_bim_from_prefix_and_partition::<usize>(
+        &plink_filename_prefixes,
+        &None,
+        &None,
+    )
+    .unwrap_or_exit(None::<String>);
+    let snp_weights = snp_weights_path.as_ref().map(|path| {
+        let bim_paths: Vec<String> = plink_filename_prefixes
+            .iter()
+            .map(|prefix| get_bed_bim_fam_path(prefix).1)
+            .collect();
+        SnpWeights::from_file(path, &bim_paths).unwrap_or_exit(None::<String>)
+    });
+    let fam_path = get_bed_bim_fam_path(&plink_filename_prefixes[0]).2;
+    let fid_iid_list = get_fid_iid_list(&fam_path).unwrap_or_exit(Some(
+        format!("failed to read the people in {}", fam_path),
+    ));
+
+    write_gcta_grm(
+        &bed,
+        None,
+        snp_qc_filter.as_ref(),
+        snp_weights.as_ref(),
+        &fid_iid_list,
+        &out_prefix,
+        Some(num_people_per_block),
+        None,
+        &StdoutProgress,
+    )
+    .unwrap_or_exit(None::<String>);
+    let (bin_path, n_bin_path, id_path) = get_gcta_grm_paths(&out_prefix);
+    println!("\n=> wrote {}\n{}\n{}", bin_path, n_bin_path, id_path);
+}
@@ -1,29 +1,49 @@
 use biofile::{
     plink_bed::{PlinkBed, PlinkSnpType},
-    plink_bim::PlinkBim,
+    plink_bim::{FilelinePartitions, PlinkBim},
 };
 use clap::{clap_app, Arg};
 use program_flow::{
     argparse::{
-        extract_numeric_arg, extract_optional_str_arg, extract_str_arg,
-        extract_str_vec_arg,
+        extract_numeric_arg, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_str_arg, extract_str_vec_arg,
     },
     OrExit,
 };
 
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
 use saber::{
-    heritability_estimator::estimate_g_gxg_heritability,
-    util::get_bed_bim_fam_path,
+    exact::{TraceMode, DEFAULT_EXACT_MAX_NUM_PEOPLE},
+    heritability_estimator::{
+        estimate_g_gxg_heritability, DEFAULT_PARTITION_NAME,
+    },
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    model_spec::{ComponentKind, Grouping, ModelSpec, SnpSelector},
+    progress::StdoutProgress,
+    resampling::Resampling,
+    sex_chrom::{
+        get_fam_male_flags, DosageCompensation, SexChromSnps, XChromDosage,
+    },
+    snp_chunk_filter::SnpChunkFilter,
+    snp_overlap::SnpOverlap,
+    snp_qc::SnpQc,
+    util::{
+        get_bed_bim_fam_path,
+        matrix_util::ProbeType,
+        recipe::{format_recipe_list, get_recipe, RECIPE_LIST_NAME},
+        summary_table::print_summary_table,
+    },
 };
 
 fn main() {
+    env_logger::init();
     let mut app = clap_app!(estimate_g_gxg_heritability =>
         (version: "0.1")
     );
     app = app
         .arg(
             Arg::with_name("plink_filename_prefix")
-                .long("bfile").short("b").takes_value(true).required(true)
+                .long("bfile").short("b").takes_value(true).required_unless("recipe")
                 .help(
                     "If we have files named \n\
                     PATH/TO/x.bed PATH/TO/x.bim PATH/TO/x.fam \n\
@@ -32,7 +52,7 @@ fn main() {
         )
         .arg(
             Arg::with_name("le_snps_filename_prefix")
-                .long("le").takes_value(true).required(true)
+                .long("le").takes_value(true).required_unless("recipe")
                 .help(
                     "The SNPs that are in linkage equilibrium.\n\
                     To be used to construct the GxG matrix.\n\
@@ -43,7 +63,7 @@ fn main() {
         )
         .arg(
             Arg::with_name("num_random_vecs")
-                .long("nrv").takes_value(true).required(true)
+                .long("nrv").takes_value(true).required_unless("recipe")
                 .help(
                     "The number of random vectors used to estimate traces\n\
                     Recommends at least 100 for small datasets, and 10 for huge datasets"
@@ -51,14 +71,42 @@ fn main() {
         )
         .arg(
             Arg::with_name("num_rand_vecs_gxg")
-                .long("nrv-gxg").takes_value(true).required(true)
+                .long("nrv-gxg").takes_value(true).required_unless("recipe")
                 .help(
                     "The number of random vectors used to estimate traces related to the GxG matrix"
                 )
         )
+        .arg(
+            Arg::with_name("probe_type")
+                .long("probe-type").takes_value(true).default_value("rademacher")
+                .possible_values(&["rademacher", "gaussian", "srht"])
+                .help(
+                    "The distribution of the random vectors of the G traces: independent +-1\n\
+                    entries, independent standard normal entries, or the columns of a subsampled\n\
+                    randomized Hadamard transform. The GxG sketches always use +-1 entries"
+                )
+        )
+        .arg(
+            Arg::with_name("trace_mode")
+                .long("trace-mode").takes_value(true).default_value("auto")
+                .possible_values(&["auto", "exact", "randomized"])
+                .help(
+                    "exact forms the G and GxG kernels in memory and computes the traces exactly,\n\
+                    randomized estimates them with the random vectors,\n\
+                    and auto is exact for fewer than <exact_max_people> people"
+                )
+        )
+        .arg(
+            Arg::with_name("exact_max_people")
+                .long("exact-max-people").takes_value(true)
+                .help(
+                    "The number of people from which --trace-mode auto estimates the\n\
+                    traces with the random vectors, 2000 by default"
+                )
+        )
         .arg(
             Arg::with_name("pheno_path")
-                .long("pheno").short("e").takes_value(true).required(true)
+                .long("pheno").short("e").takes_value(true).required_unless("recipe")
                 .multiple(true).number_of_values(1)
                 .help(
                     "The header line should be\n\
@@ -88,40 +136,248 @@ fn main() {
                     Taking the same file format as the --partition option"
                                                 )
         )
+        .arg(
+            Arg::with_name("model")
+                .long("model").takes_value(true).conflicts_with("model_file")
+                .help(
+                    "The variance components to fit, e.g.\n\
+                    'y ~ G(all) + G(maf<0.05) + GxG(le, within=chrom) + E'\n\
+                    where the G components are over the SNPs selected by all, maf<THRESHOLD,\n\
+                    maf>=THRESHOLD or partition=NAME of the --partition file, replacing its partitions,\n\
+                    and the single GxG component is over the LE SNPs, split within=chrom or\n\
+                    within=partition of the --gxg-partition file"
+                )
+        )
+        .arg(
+            Arg::with_name("model_file")
+                .long("model-file").takes_value(true)
+                .help(
+                    "A file with the --model specification, which may span multiple lines.\n\
+                    Lines starting with # are ignored"
+                )
+        )
+        .arg(
+            Arg::with_name("prune_overlap")
+                .long("prune-overlap")
+                .help(
+                    "Remove from the G components the SNPs that are also LE SNPs, matched by\n\
+                    chromosome, base pair coordinate and alleles, so that their variance is not\n\
+                    counted by both the G and the GxG components. Without this flag, the\n\
+                    overlapping SNPs only trigger a warning"
+                )
+        )
+        .arg(
+            Arg::with_name("dosage_compensation")
+                .long("dosage-compensation").takes_value(true).default_value("full")
+                .possible_values(&["full", "none", "equal-variance"])
+                .help(
+                    "The G SNPs on the X chromosome form a component of their own, where the\n\
+                    male genotypes are coded under the dosage compensation model\n\
+                    full: the males are coded 0/2 as stored by PLINK\n\
+                    none: the males are coded 0/1\n\
+                    equal-variance: the males and the females are standardized separately"
+                )
+        )
+        .arg(
+            Arg::with_name("include_y")
+                .long("include-y")
+                .help(
+                    "Fit the G SNPs on the Y chromosome as a component of their own instead of\n\
+                    leaving them out"
+                )
+        )
+        .arg(
+            Arg::with_name("include_mt")
+                .long("include-mt")
+                .help(
+                    "Fit the mitochondrial G SNPs as a component of their own instead of\n\
+                    leaving them out"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_maf")
+                .long("qc-maf").takes_value(true)
+                .help(
+                    "The G SNPs with a minor allele frequency below <qc_maf> fail the QC pass\n\
+                    that runs over the genotypes before the estimation"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_max_missing")
+                .long("qc-max-missing").takes_value(true)
+                .help(
+                    "The G SNPs with a missing rate above <qc_max_missing> fail the QC pass.\n\
+                    The monomorphic SNPs always fail, and the failing SNPs are left out of\n\
+                    every G component"
+                )
+        )
+        .arg(
+            Arg::with_name("no_qc")
+                .long("no-qc")
+                .conflicts_with_all(&["qc_maf", "qc_max_missing"])
+                .help("Skips the SNP QC pass over the G genotypes")
+        )
         .arg(
             Arg::with_name("num_jackknife_partitions")
                 .long("--num-jackknifes").short("k").takes_value(true).default_value("20")
                 .help("The number of jackknife partitions")
+        )
+        .arg(
+            Arg::with_name("resampling")
+                .long("resampling").takes_value(true).default_value("block-jackknife")
+                .possible_values(&["random-subset", "block-jackknife", "none"])
+                .help(
+                    "How the SNP blocks are resampled to estimate the standard errors\n\
+                    random-subset: leave out each of k random subsets of the SNPs\n\
+                    block-jackknife: leave out each of k contiguous SNP blocks\n\
+                    none: point estimates only"
+                )
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random probe vectors and the random jackknife subsets, so that the\n\
+                    estimates are reproducible regardless of the number of threads"
+                )
+        )
+        .arg(
+            Arg::with_name("recipe")
+                .long("recipe").takes_value(true)
+                .help(
+                    "Use the default options of a curated preset, e.g. g-plus-gxg-chrwise\n\
+                    Options given explicitly take precedence over the preset.\n\
+                    Use --recipe list to describe the available presets"
+                )
         );
     let matches = app.get_matches();
 
+    let recipe = match extract_optional_str_arg(&matches, "recipe") {
+        Some(name) => {
+            if name == RECIPE_LIST_NAME {
+                println!("{}", format_recipe_list());
+                return;
+            }
+            Some(
+                get_recipe(&name, "estimate_g_gxg_heritability")
+                    .unwrap_or_exit(None::<String>),
+            )
+        }
+        None => None,
+    };
+
+    // the recipes do not name the genotypes or the phenotypes, so these are
+    // only optional for --recipe list
+    for (arg, flag) in [
+        ("plink_filename_prefix", "--bfile"),
+        ("le_snps_filename_prefix", "--le"),
+        ("pheno_path", "--pheno"),
+    ]
+    .iter()
+    {
+        if matches.occurrences_of(arg) == 0 {
+            eprintln!("{} is required unless listing the recipes", flag);
+            std::process::exit(1);
+        }
+    }
     let plink_filename_prefix =
         extract_str_arg(&matches, "plink_filename_prefix");
     let le_snps_filename_prefix =
         extract_str_arg(&matches, "le_snps_filename_prefix");
     let pheno_path_vec = extract_str_vec_arg(&matches, "pheno_path")
         .unwrap_or_exit(None::<String>);
-    let num_jackknife_partitions =
-        extract_numeric_arg::<usize>(&matches, "num_jackknife_partitions")
+    let num_jackknife_partitions = match recipe {
+        Some(r) if matches.occurrences_of("num_jackknife_partitions") == 0 => {
+            r.num_jackknife_partitions
+        }
+        _ => extract_numeric_arg::<usize>(&matches, "num_jackknife_partitions")
             .unwrap_or_exit(Some(format!(
                 "failed to extract num_jackknife_partitions"
-            )));
+            ))),
+    };
+    let resampling = Resampling::from_str(
+        &extract_str_arg(&matches, "resampling"),
+        num_jackknife_partitions,
+        0,
+    )
+    .unwrap_or_exit(None::<String>);
+    let seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
 
     let (bed_path, bim_path, fam_path) =
         get_bed_bim_fam_path(&plink_filename_prefix);
     let (le_snps_bed_path, le_snps_bim_path, le_snps_fam_path) =
         get_bed_bim_fam_path(&le_snps_filename_prefix);
 
-    let num_random_vecs = extract_str_arg(&matches, "num_random_vecs")
-        .parse::<usize>()
-        .unwrap_or_exit(Some("failed to parse num_random_vecs"));
-    let num_rand_vecs_gxg = extract_str_arg(&matches, "num_rand_vecs_gxg")
-        .parse::<usize>()
-        .unwrap_or_exit(Some("failed to parse num_rand_vecs_gxg"));
+    let num_random_vecs = match (matches.value_of("num_random_vecs"), recipe) {
+        (None, Some(r)) => r.num_random_vecs,
+        _ => extract_str_arg(&matches, "num_random_vecs")
+            .parse::<usize>()
+            .unwrap_or_exit(Some("failed to parse num_random_vecs")),
+    };
+    let num_rand_vecs_gxg = match (
+        matches.value_of("num_rand_vecs_gxg"),
+        recipe.and_then(|r| r.num_rand_vecs_gxg),
+    ) {
+        (None, Some(n)) => n,
+        _ => extract_str_arg(&matches, "num_rand_vecs_gxg")
+            .parse::<usize>()
+            .unwrap_or_exit(Some("failed to parse num_rand_vecs_gxg")),
+    };
+    let probe_type = extract_str_arg(&matches, "probe_type")
+        .parse::<ProbeType>()
+        .unwrap_or_exit(None::<String>);
+    let trace_mode = match extract_str_arg(&matches, "trace_mode").as_str() {
+        "auto" => TraceMode::Auto {
+            max_num_people: extract_optional_numeric_arg::<usize>(
+                &matches,
+                "exact_max_people",
+            )
+            .unwrap_or_exit(Some("failed to extract exact_max_people"))
+            .unwrap_or(DEFAULT_EXACT_MAX_NUM_PEOPLE),
+        },
+        mode => mode.parse::<TraceMode>().unwrap_or_exit(None::<String>),
+    };
     let g_partition_filepath =
         extract_optional_str_arg(&matches, "partition_file");
     let gxg_partition_filepath =
         extract_optional_str_arg(&matches, "gxg_partition_file");
+    let prune_overlap = matches.is_present("prune_overlap");
+    let dosage_compensation = extract_str_arg(&matches, "dosage_compensation")
+        .parse::<DosageCompensation>()
+        .unwrap_or_exit(None::<String>);
+    let include_y = matches.is_present("include_y");
+    let include_mt = matches.is_present("include_mt");
+    let snp_qc_filter = if matches.is_present("no_qc") {
+        None
+    } else {
+        Some(SnpChunkFilter::new(
+            extract_optional_numeric_arg::<f32>(&matches, "qc_maf")
+                .unwrap_or_exit(Some("failed to extract qc_maf")),
+            extract_optional_numeric_arg::<f32>(&matches, "qc_max_missing")
+                .unwrap_or_exit(Some("failed to extract qc_max_missing")),
+        ))
+    };
+    let model =
+        match (
+            extract_optional_str_arg(&matches, "model"),
+            extract_optional_str_arg(&matches, "model_file"),
+        ) {
+            (Some(m), _) => Some(m.parse::<ModelSpec>().unwrap_or_exit(Some(
+                format!("failed to parse the model {}", m),
+            ))),
+            (None, Some(path)) => {
+                Some(ModelSpec::from_file(&path).unwrap_or_exit(None::<String>))
+            }
+            (None, None) => None,
+        };
+    let gxg_grouping = match &model {
+        Some(model) => Some(
+            check_g_gxg_model(model, gxg_partition_filepath.is_some())
+                .unwrap_or_exit(None::<String>),
+        ),
+        None => None,
+    };
 
     println!(
         "PLINK bed path: {}\n\
@@ -139,24 +395,36 @@ fn main() {
     for (i, path) in pheno_path_vec.iter().enumerate() {
         println!("[{}/{}] {}", i + 1, pheno_path_vec.len(), path);
     }
-    println!("num_random_vecs: {}\nnum_rand_vecs_gxg: {}\nnum_jackknife_partitions: {}",
-             num_random_vecs, num_rand_vecs_gxg, num_jackknife_partitions);
+    if let Some(model) = &model {
+        println!("model: {}", model);
+    }
+    println!(
+        "num_random_vecs: {}\nnum_rand_vecs_gxg: {}\nprobe_type: {}\n\
+        trace_mode: {}\nresampling: {}",
+        num_random_vecs, num_rand_vecs_gxg, probe_type, trace_mode, resampling
+    );
     println!(
         "G partition filepath: {}\n\
         gxg_partition_filepath: {}",
         g_partition_filepath.as_ref().unwrap_or(&"".to_string()),
         gxg_partition_filepath.as_ref().unwrap_or(&"".to_string())
     );
+    println!("prune_overlap: {}", prune_overlap);
+    println!(
+        "dosage_compensation: {}\ninclude_y: {}\ninclude_mt: {}",
+        dosage_compensation, include_y, include_mt
+    );
+    println!("snp_qc_filter: {:?}", snp_qc_filter);
 
     println!("\n=> generating the phenotype array and the genotype matrix");
     let geno_bed = PlinkBed::new(&vec![(
         bed_path,
         bim_path.clone(),
-        fam_path,
+        fam_path.clone(),
         PlinkSnpType::Additive,
     )])
     .unwrap_or_exit(None::<String>);
-    let geno_bim = match &g_partition_filepath {
+    let mut geno_bim = match &g_partition_filepath {
         Some(p) => PlinkBim::new_with_partition_file(vec![bim_path.clone()], p)
             .unwrap_or_exit(Some(format!(
                 "failed to create PlinkBim from bim file: {} and partition file: {}",
@@ -173,7 +441,7 @@ fn main() {
         PlinkSnpType::Additive,
     )])
     .unwrap_or_exit(None::<String>);
-    let le_snps_bim = match &gxg_partition_filepath {
+    let mut le_snps_bim = match &gxg_partition_filepath {
         Some(p) => PlinkBim::new_with_partition_file(vec![le_snps_bim_path.clone()], p)
             .unwrap_or_exit(Some(format!(
                 "failed to create PlinkBim from bim file: {} and partition file: {}",
@@ -184,6 +452,145 @@ fn main() {
                 "failed to create PlinkBim for {}", le_snps_bim_path
             ))),
     };
+    if let Some(model) = &model {
+        let all_snps = OrderedIntegerSet::from_slice(&[[
+            0,
+            geno_bed.total_num_snps() - 1,
+        ]]);
+        let mafs = if model.needs_maf() {
+            println!("\n=> computing minor allele frequencies");
+            Some(geno_bed.get_minor_allele_frequencies(None))
+        } else {
+            None
+        };
+        let g_partitions = model
+            .get_component_snps(
+                ComponentKind::Additive,
+                &all_snps,
+                mafs.as_ref().map(|m| m.as_slice()),
+                &geno_bim
+                    .get_fileline_partitions_or(
+                        DEFAULT_PARTITION_NAME,
+                        all_snps.clone(),
+                    )
+                    .into_hash_map(),
+            )
+            .unwrap_or_exit(None::<String>);
+        geno_bim.set_fileline_partitions(Some(FilelinePartitions::new(
+            g_partitions.into_iter().collect(),
+        )));
+    }
+    println!("\n=> checking the overlap of the G SNPs and the LE SNPs");
+    let overlap = SnpOverlap::from_bim_files(&bim_path, &le_snps_bim_path)
+        .unwrap_or_exit(None::<String>);
+    if let Some(warning) = overlap.get_warning(prune_overlap) {
+        println!("WARNING {}", warning);
+    }
+    if prune_overlap && !overlap.is_empty() {
+        let all_snps = OrderedIntegerSet::from_slice(&[[
+            0,
+            geno_bed.total_num_snps() - 1,
+        ]]);
+        let g_partitions = geno_bim
+            .get_fileline_partitions_or(DEFAULT_PARTITION_NAME, all_snps)
+            .into_hash_map()
+            .into_iter()
+            .map(|(name, snps)| (name, overlap.prune(&snps)))
+            .filter(|(_, snps)| snps.size() > 0)
+            .collect();
+        geno_bim.set_fileline_partitions(Some(FilelinePartitions::new(
+            g_partitions,
+        )));
+    }
+    let sex_chrom_snps =
+        SexChromSnps::from_bim_file(&bim_path).unwrap_or_exit(None::<String>);
+    let x_dosage = if sex_chrom_snps.is_empty() {
+        None
+    } else {
+        println!(
+            "\n=> {} X SNPs form a G component of their own, {} Y SNPs and \
+            {} MT SNPs are {} and {}",
+            sex_chrom_snps.x.size(),
+            sex_chrom_snps.y.size(),
+            sex_chrom_snps.mt.size(),
+            if include_y { "included" } else { "left out" },
+            if include_mt { "included" } else { "left out" }
+        );
+        let all_snps = OrderedIntegerSet::from_slice(&[[
+            0,
+            geno_bed.total_num_snps() - 1,
+        ]]);
+        let g_partitions = sex_chrom_snps
+            .split_partitions(
+                geno_bim
+                    .get_fileline_partitions_or(
+                        DEFAULT_PARTITION_NAME,
+                        all_snps,
+                    )
+                    .into_hash_map(),
+                include_y,
+                include_mt,
+            )
+            .unwrap_or_exit(None::<String>);
+        geno_bim.set_fileline_partitions(Some(FilelinePartitions::new(
+            g_partitions,
+        )));
+        if sex_chrom_snps.x.size() > 0 {
+            let is_male =
+                get_fam_male_flags(&fam_path).unwrap_or_exit(None::<String>);
+            let x_dosage = XChromDosage::new(
+                sex_chrom_snps.x.clone(),
+                is_male,
+                dosage_compensation,
+            );
+            println!(
+                "{} males under {} dosage compensation",
+                x_dosage.num_males(),
+                dosage_compensation
+            );
+            Some(x_dosage)
+        } else {
+            None
+        }
+    };
+    if let Some(filter) = &snp_qc_filter {
+        println!("\n=> running the SNP QC pass over the G SNPs");
+        let qc = SnpQc::new(&geno_bed, DEFAULT_NUM_SNPS_PER_CHUNK);
+        println!("{}", qc.summarize(filter));
+        let all_snps = OrderedIntegerSet::from_slice(&[[
+            0,
+            geno_bed.total_num_snps() - 1,
+        ]]);
+        let g_partitions = qc.filter_partitions(
+            geno_bim
+                .get_fileline_partitions_or(DEFAULT_PARTITION_NAME, all_snps)
+                .into_hash_map(),
+            filter,
+        );
+        if g_partitions.is_empty() {
+            eprintln!("no G SNP is left in any component after the QC");
+            std::process::exit(1);
+        }
+        geno_bim.set_fileline_partitions(Some(FilelinePartitions::new(
+            g_partitions,
+        )));
+    }
+    if gxg_grouping == Some(Some(Grouping::Chrom))
+        || (model.is_none()
+            && gxg_partition_filepath.is_none()
+            && recipe.map_or(false, |r| r.gxg_by_chrom))
+    {
+        println!("\n=> forming one GxG component per chromosome");
+        let chrom_partitions = le_snps_bim
+            .get_chrom_to_fileline_positions()
+            .unwrap_or_exit(Some(format!(
+                "failed to get chrom partitions from {}",
+                le_snps_bim_path
+            )));
+        le_snps_bim.set_fileline_partitions(Some(FilelinePartitions::new(
+            chrom_partitions,
+        )));
+    }
     match estimate_g_gxg_heritability(
         geno_bed,
         geno_bim,
@@ -192,19 +599,72 @@ fn main() {
         pheno_path_vec.clone(),
         num_random_vecs,
         num_rand_vecs_gxg,
-        num_jackknife_partitions,
+        probe_type,
+        trace_mode,
+        resampling,
+        x_dosage.as_ref(),
+        seed,
+        &StdoutProgress,
     ) {
         Err(why) => println!("failed to get heritability estimate: {}", why),
         Ok(est) => {
             for (pheno_index, pheno_path) in pheno_path_vec.iter().enumerate() {
-                println!(
-                    "\n=> [{}/{}] phenotype {} heritability estimate: {}",
-                    pheno_index + 1,
-                    pheno_path_vec.len(),
-                    pheno_path,
-                    est[pheno_path]
+                print_summary_table(
+                    &format!(
+                        "=> [{}/{}] phenotype {} heritability estimate:",
+                        pheno_index + 1,
+                        pheno_path_vec.len(),
+                        pheno_path
+                    ),
+                    &est[pheno_path],
                 );
             }
         }
     };
 }
+
+/// Checks that the model has G components and a single GxG component over
+/// the LE SNPs, returning how the GxG component is split.
+fn check_g_gxg_model(
+    model: &ModelSpec,
+    has_gxg_partition_file: bool,
+) -> Result<Option<Grouping>, String> {
+    model.check_supported_kinds(
+        &[ComponentKind::Additive, ComponentKind::Gxg],
+        "estimate_g_gxg_heritability",
+    )?;
+    if model.components_of_kind(ComponentKind::Additive).count() == 0 {
+        return Err("the model needs at least one G component".to_string());
+    }
+    let gxg: Vec<_> = model.components_of_kind(ComponentKind::Gxg).collect();
+    if gxg.len() != 1 {
+        return Err(format!(
+            "the model needs exactly one GxG component, found {}; \
+            use estimate_multi_gxg_heritability for more",
+            gxg.len()
+        ));
+    }
+    match &gxg[0].snps {
+        SnpSelector::All => {}
+        SnpSelector::Named(name) if name == "le" => {}
+        _ => {
+            return Err(format!(
+                "{} has to be over all the LE SNPs, written as GxG(le)",
+                gxg[0]
+            ))
+        }
+    }
+    match gxg[0].within {
+        Some(Grouping::Partition) if !has_gxg_partition_file => {
+            Err("within=partition requires --gxg-partition".to_string())
+        }
+        Some(Grouping::Chrom) if has_gxg_partition_file => {
+            Err("within=chrom conflicts with --gxg-partition".to_string())
+        }
+        None if has_gxg_partition_file => Err(
+            "--gxg-partition requires GxG(le, within=partition) in the model"
+                .to_string(),
+        ),
+        within => Ok(within),
+    }
+}
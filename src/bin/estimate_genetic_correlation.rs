@@ -0,0 +1,115 @@
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_numeric_arg, extract_optional_numeric_arg, extract_str_arg,
+    },
+    OrExit,
+};
+
+use saber::{
+    genetic_correlation::estimate_genetic_correlation,
+    progress::StdoutProgress,
+    util::{
+        get_bed_bim_fam_path, get_bed_bim_from_prefix_and_partition,
+        get_fam_aligned_pheno_arr,
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let mut app = clap_app!(estimate_genetic_correlation =>
+        (version: "0.1")
+    );
+    app = app
+        .about(
+            "Estimates the genetic covariance and correlation of two traits\n\
+            measured on the same people from the bivariate HE normal equations"
+        )
+        .arg(
+            Arg::with_name("plink_filename_prefix")
+                .long("bfile").short("b").takes_value(true).required(true)
+                .help(
+                    "If we have files named \n\
+                    PATH/TO/x.bed PATH/TO/x.bim PATH/TO/x.fam \n\
+                    then the <plink_filename_prefix> should be path/to/x"
+                )
+        )
+        .arg(
+            Arg::with_name("pheno_path_1")
+                .long("pheno1").takes_value(true).required(true)
+                .help("The phenotype file of the first trait, in the format of --pheno")
+        )
+        .arg(
+            Arg::with_name("pheno_path_2")
+                .long("pheno2").takes_value(true).required(true)
+                .help("The phenotype file of the second trait, in the format of --pheno")
+        )
+        .arg(
+            Arg::with_name("num_random_vecs")
+                .long("nrv").short("n").takes_value(true).required(true)
+                .help(
+                    "The number of random vectors used to estimate traces\n\
+                    Recommends at least 100 for small datasets, and 10 for huge datasets"
+                )
+        )
+        .arg(
+            Arg::with_name("num_jackknife_partitions")
+                .long("num-jackknifes").short("k").takes_value(true).default_value("20")
+                .help(
+                    "The number of contiguous blocks of SNPs left out in turn\n\
+                    to estimate the standard errors"
+                )
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random vectors, so that the estimates are reproducible regardless\n\
+                    of the number of threads"
+                )
+        );
+    let matches = app.get_matches();
+
+    let plink_filename_prefix =
+        extract_str_arg(&matches, "plink_filename_prefix");
+    let pheno_path_1 = extract_str_arg(&matches, "pheno_path_1");
+    let pheno_path_2 = extract_str_arg(&matches, "pheno_path_2");
+    let num_random_vecs =
+        extract_numeric_arg::<usize>(&matches, "num_random_vecs")
+            .unwrap_or_exit(Some("failed to extract num_random_vecs"));
+    let num_jackknife_partitions =
+        extract_numeric_arg::<usize>(&matches, "num_jackknife_partitions")
+            .unwrap_or_exit(Some("failed to extract num_jackknife_partitions"));
+    let seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
+    println!(
+        "pheno_path_1: {}\n\
+        pheno_path_2: {}\n\
+        num_random_vecs: {}\n\
+        num_jackknife_partitions: {}",
+        pheno_path_1, pheno_path_2, num_random_vecs, num_jackknife_partitions
+    );
+
+    let fam_path = get_bed_bim_fam_path(&plink_filename_prefix).2;
+    let (bed, _bim) = get_bed_bim_from_prefix_and_partition(
+        &vec![plink_filename_prefix],
+        &None,
+        &None,
+    )
+    .unwrap_or_exit(None::<String>);
+    let pheno_1 = get_fam_aligned_pheno_arr(&fam_path, &pheno_path_1)
+        .unwrap_or_exit(None::<String>);
+    let pheno_2 = get_fam_aligned_pheno_arr(&fam_path, &pheno_path_2)
+        .unwrap_or_exit(None::<String>);
+    let est = estimate_genetic_correlation(
+        &bed,
+        &pheno_1,
+        &pheno_2,
+        num_random_vecs,
+        num_jackknife_partitions,
+        seed,
+        &StdoutProgress,
+    )
+    .unwrap_or_exit(None::<String>);
+    println!("{}", est);
+}
@@ -0,0 +1,143 @@
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{
+        extract_numeric_arg, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_str_arg,
+    },
+    OrExit,
+};
+
+use saber::{
+    environment::Environment,
+    heritability_estimator::estimate_g_and_gxe_heritability,
+    progress::StdoutProgress,
+    util::{
+        get_bed_bim_fam_path, get_bed_bim_from_prefix_and_partition,
+        get_fam_aligned_pheno_arr, get_plink_covariate_arr,
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let mut app = clap_app!(estimate_gxe_heritability =>
+        (version: "0.1")
+    );
+    app = app
+        .about(
+            "Estimates the G, gene-by-environment (GxE) and noise variance components,\n\
+            where the GxE kernel is the GRM multiplied elementwise by E E^T"
+        )
+        .arg(
+            Arg::with_name("plink_filename_prefix")
+                .long("bfile").short("b").takes_value(true).required(true)
+                .help(
+                    "If we have files named \n\
+                    PATH/TO/x.bed PATH/TO/x.bim PATH/TO/x.fam \n\
+                    then the <plink_filename_prefix> should be path/to/x"
+                )
+        )
+        .arg(
+            Arg::with_name("pheno_path")
+                .long("pheno").short("e").takes_value(true).required(true)
+                .help(
+                    "The header line should be\n\
+                    FID IID PHENOTYPE_NAME\n\
+                    where PHENOTYPE_NAME can be any string without white spaces.\n\
+                    The rest of the lines are of the form:\n\
+                    1000011 1000011 -12.11363"
+                )
+        )
+        .arg(
+            Arg::with_name("env_path")
+                .long("env").takes_value(true).required(true)
+                .help(
+                    "The environment of every person, in the format of --pheno.\n\
+                    The environment is continuous unless --discrete-env is given"
+                )
+        )
+        .arg(
+            Arg::with_name("discrete_env")
+                .long("discrete-env")
+                .help(
+                    "Treat the environment values as the labels of discrete levels,\n\
+                    e.g. cohorts or sexes"
+                )
+        )
+        .arg(
+            Arg::with_name("covariate_path")
+                .long("covariate").short("c").takes_value(true)
+                .help(
+                    "A plink-style covariate file with the header line\n\
+                    FID IID COV_1 COV_2 ...\n\
+                    The covariates are projected out together with the intercept and\n\
+                    the main effects of the environment"
+                )
+        )
+        .arg(
+            Arg::with_name("num_random_vecs")
+                .long("nrv").short("n").takes_value(true).required(true)
+                .help(
+                    "The number of random vectors used to estimate traces\n\
+                    Recommends at least 100 for small datasets, and 10 for huge datasets"
+                )
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random vectors, so that the estimates are reproducible regardless\n\
+                    of the number of threads"
+                )
+        );
+    let matches = app.get_matches();
+
+    let plink_filename_prefix =
+        extract_str_arg(&matches, "plink_filename_prefix");
+    let pheno_path = extract_str_arg(&matches, "pheno_path");
+    let env_path = extract_str_arg(&matches, "env_path");
+    let discrete_env = matches.is_present("discrete_env");
+    let covariate_path = extract_optional_str_arg(&matches, "covariate_path");
+    let num_random_vecs =
+        extract_numeric_arg::<usize>(&matches, "num_random_vecs")
+            .unwrap_or_exit(Some("failed to extract num_random_vecs"));
+    let seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
+    println!(
+        "pheno_path: {}\n\
+        env_path: {}\n\
+        discrete_env: {}\n\
+        covariate_path: {}\n\
+        num_random_vecs: {}",
+        pheno_path,
+        env_path,
+        discrete_env,
+        covariate_path.as_ref().unwrap_or(&"".to_string()),
+        num_random_vecs
+    );
+
+    let fam_path = get_bed_bim_fam_path(&plink_filename_prefix).2;
+    let (bed, _bim) = get_bed_bim_from_prefix_and_partition(
+        &vec![plink_filename_prefix],
+        &None,
+        &None,
+    )
+    .unwrap_or_exit(None::<String>);
+    let pheno = get_fam_aligned_pheno_arr(&fam_path, &pheno_path)
+        .unwrap_or_exit(None::<String>);
+    let environment = Environment::from_file(&env_path, discrete_env)
+        .unwrap_or_exit(None::<String>);
+    let covariates = covariate_path.as_ref().map(|path| {
+        get_plink_covariate_arr(path).unwrap_or_exit(None::<String>)
+    });
+    let est = estimate_g_and_gxe_heritability(
+        &bed,
+        &environment,
+        pheno,
+        num_random_vecs,
+        covariates.as_ref(),
+        seed,
+        &StdoutProgress,
+    )
+    .unwrap_or_exit(None::<String>);
+    println!("\nvariance estimates on the normalized phenotype:\n{}", est);
+}
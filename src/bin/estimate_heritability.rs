@@ -1,9 +1,8 @@
+use std::collections::HashMap;
+
 use biofile::plink_bim::FilelinePartitions;
 use clap::{clap_app, Arg};
-use math::{
-    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
-    traits::Collecting,
-};
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
 use program_flow::{
     argparse::{
         extract_numeric_arg, extract_optional_numeric_arg,
@@ -14,18 +13,53 @@ use program_flow::{
 };
 
 use saber::{
-    heritability_estimator::{estimate_heritability, DEFAULT_PARTITION_NAME},
-    util::{get_bed_bim_from_prefix_and_partition, get_file_line_tokens},
+    checkpoint::CheckpointConfig,
+    compute::{ComputeConfig, BYTES_PER_MB},
+    covariate::CovariateProjection,
+    estimator_builder::HeritabilityEstimatorBuilder,
+    exact::{TraceMode, DEFAULT_EXACT_MAX_NUM_PEOPLE},
+    fixed_snps::{read_snp_id_list, FixedSnps},
+    gpu::ComputeBackend,
+    he_regression::estimate_heritability_he,
+    heritability_estimator::{DEFAULT_PARTITION_NAME, DOMINANCE_PARTITION_NAME},
+    integer_set::{
+        collect::SortedCollecting,
+        ops::{MergeDifference, MergeIntersect},
+    },
+    liability::{get_case_fraction, is_case_control, LiabilityScaleEstimates},
+    maf_bins::MafBins,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    missing_genotype::MissingGenotypePolicy,
+    model_spec::{ComponentKind, ModelSpec},
+    resampling::{Resampling, DEFAULT_NUM_BOOTSTRAP_REPS},
+    snp_chunk_filter::SnpChunkFilter,
+    snp_qc::SnpQc,
+    snp_weights::SnpWeights,
+    stochastic::PeopleSubset,
+    util::{
+        get_additive_and_dominance_snps, get_bed_bim_fam_path,
+        get_bed_bim_from_prefix_and_partition,
+        get_fam_aligned_multi_pheno_matrix,
+        get_fam_aligned_multi_pheno_matrix_of_people,
+        get_fam_aligned_pheno_matrix, get_fam_aligned_pheno_matrix_of_people,
+        get_file_line_tokens, get_keep_indices,
+        matrix_util::{ProbeType, DEFAULT_ALPHA},
+        recipe::{format_recipe_list, get_recipe, RECIPE_LIST_NAME},
+        summary_table::print_summary_table,
+        trace_metadata::Standardization,
+        write_estimates_json, write_jackknife_replicates_tsv,
+    },
 };
 
 fn main() {
+    env_logger::init();
     let mut app = clap_app!(estimate_heritability =>
         (version: "0.1")
     );
     app = app
         .arg(
             Arg::with_name("plink_filename_prefix")
-                .long("bfile").short("b").takes_value(true).required(true)
+                .long("bfile").short("b").takes_value(true).required_unless("recipe")
                 .multiple(true).number_of_values(1)
                 .help(
                     "If we have files named \n\
@@ -38,7 +72,10 @@ fn main() {
                 .long("dominance-bfile").short("d").takes_value(true)
                 .multiple(true).number_of_values(1)
                 .help(
-                    "The SNPs for the dominance component. Same format as plink_filename_prefix."
+                    "The SNPs for the dominance component. Same format as plink_filename_prefix.\n\
+                    The genotypes are coded by the dominance deviations, and without a model\n\
+                    all the dominance SNPs are fit as a single dominance component next to\n\
+                    the additive partitions"
                 )
         )
         .arg(
@@ -53,6 +90,17 @@ fn main() {
                     1000011 1000011 -12.11363"
                 )
         )
+        .arg(
+            Arg::with_name("pheno_name")
+                .long("pheno-name").takes_value(true)
+                .multiple(true).number_of_values(1)
+                .requires("pheno_path").conflicts_with("pheno_paths_file")
+                .help(
+                    "Read a single --pheno file with the header line\n\
+                    FID IID PHENOTYPE_NAME_1 PHENOTYPE_NAME_2 ...\n\
+                    and estimate the heritability of the columns with these names"
+                )
+        )
         .arg(
             Arg::with_name("pheno_paths_file")
                 .long("pheno-paths-file").short("f").takes_value(true)
@@ -62,12 +110,69 @@ fn main() {
         )
         .arg(
             Arg::with_name("num_random_vecs")
-                .long("nrv").short("n").takes_value(true).required(true)
+                .long("nrv").short("n").takes_value(true).required_unless("recipe")
                 .help(
                     "The number of random vectors used to estimate traces\n\
                     Recommends at least 100 for small datasets, and 10 for huge datasets"
                 )
         )
+        .arg(
+            Arg::with_name("max_num_random_vecs")
+                .long("max-nrv").takes_value(true)
+                .help(
+                    "If provided, partitions whose estimates are dominated by the Monte Carlo error\n\
+                    of the trace estimates get their traces re-estimated with more random vectors,\n\
+                    up to this many, before solving for the final estimates"
+                )
+        )
+        .arg(
+            Arg::with_name("trace_tolerance")
+                .long("trace-tolerance").takes_value(true)
+                .help(
+                    "If provided, batches of <num_random_vecs> random vectors are added until the\n\
+                    relative Monte Carlo standard error of tr(K K) is at most this tolerance for\n\
+                    every partition, up to <max_num_random_vecs> random vectors if it is provided.\n\
+                    The Monte Carlo standard errors of the traces are in the JSON output"
+                )
+        )
+        .arg(
+            Arg::with_name("probe_type")
+                .long("probe-type").takes_value(true).default_value("rademacher")
+                .possible_values(&["rademacher", "gaussian", "srht"])
+                .help(
+                    "The distribution of the random vectors: independent +-1 entries,\n\
+                    independent standard normal entries, or the columns of a subsampled\n\
+                    randomized Hadamard transform, which are nearly orthogonal to each other"
+                )
+        )
+        .arg(
+            Arg::with_name("probe_sparsity")
+                .long("probe-sparsity").takes_value(true)
+                .help(
+                    "Use sparse random vectors in which only a fraction <probe_sparsity>\n\
+                    of the entries are nonzero, scaled to keep the trace estimates unbiased.\n\
+                    Must be in (0, 1]. Reduces the cost of the products with the random vectors\n\
+                    at the expense of a larger variance. Requires --probe-type rademacher"
+                )
+        )
+        .arg(
+            Arg::with_name("trace_mode")
+                .long("trace-mode").takes_value(true).default_value("auto")
+                .possible_values(&["auto", "exact", "randomized"])
+                .help(
+                    "exact computes the traces exactly with one probe vector per person,\n\
+                    randomized estimates them with <num_random_vecs> random vectors,\n\
+                    and auto is exact for fewer than <exact_max_people> people"
+                )
+        )
+        .arg(
+            Arg::with_name("exact_max_people")
+                .long("exact-max-people").takes_value(true)
+                .help(
+                    "The number of people from which --trace-mode auto estimates the\n\
+                    traces with the random vectors, 2000 by default"
+                )
+        )
         .arg(
             Arg::with_name("num_jackknife_partitions")
                 .long("--num-jackknifes").short("k").takes_value(true).default_value("20")
@@ -77,6 +182,23 @@ fn main() {
                     where each partition will be treated as a single point of observation"
                 )
         )
+        .arg(
+            Arg::with_name("resampling")
+                .long("resampling").takes_value(true).default_value("block-jackknife")
+                .possible_values(&["random-subset", "block-jackknife", "block-bootstrap", "none"])
+                .help(
+                    "How the SNP blocks are resampled to estimate the standard errors\n\
+                    random-subset: leave out each of k random subsets of the SNPs\n\
+                    block-jackknife: leave out each of k contiguous SNP blocks\n\
+                    block-bootstrap: draw k of the k contiguous SNP blocks with replacement\n\
+                    none: point estimates only"
+                )
+        )
+        .arg(
+            Arg::with_name("num_bootstrap_reps")
+                .long("num-bootstrap-reps").takes_value(true)
+                .help("The number of block bootstrap replicates, 100 by default")
+        )
         .arg(
             Arg::with_name("partition_file")
                 .long("partition").short("p").takes_value(true)
@@ -89,6 +211,36 @@ fn main() {
                     will assign SNP with ID rs3115860 in the BIM file to a partition named 1"
                 )
         )
+        .arg(
+            Arg::with_name("model")
+                .long("model").takes_value(true).conflicts_with("model_file")
+                .help(
+                    "The variance components to fit, e.g.\n\
+                    'y ~ G(all) + G(maf<0.05) + D(partition=coding) + E'\n\
+                    where G and D are additive and dominance components over the SNPs\n\
+                    selected by all, maf<THRESHOLD, maf>=THRESHOLD or partition=NAME,\n\
+                    the latter referring to the --partition file.\n\
+                    Each component replaces the partitions of the partition file"
+                )
+        )
+        .arg(
+            Arg::with_name("model_file")
+                .long("model-file").takes_value(true)
+                .help(
+                    "A file with the --model specification, which may span multiple lines.\n\
+                    Lines starting with # are ignored"
+                )
+        )
+        .arg(
+            Arg::with_name("maf_bins")
+                .long("maf-bins").takes_value(true).conflicts_with_all(&["model", "model_file"])
+                .help(
+                    "Comma-separated MAF bin boundaries, e.g. 0.01,0.05,0.2, to fit one component\n\
+                    per bin [0, 0.01), [0.01, 0.05), [0.05, 0.2), [0.2, 0.5] as in GREML-MS.\n\
+                    The MAFs are computed from the bed files, and the bins are crossed with the\n\
+                    partitions of the partition file if there is one"
+                )
+        )
         .arg(
             Arg::with_name("lowest_allowed_maf")
                 .long("lowest-maf").takes_value(true)
@@ -96,9 +248,255 @@ fn main() {
                     "Lowest allowed minor allele frequency (MAF)\n\
                     Any SNPs with a MAF less than <lowest_allowed_maf> will be ignored"
                 )
+        )
+        .arg(
+            Arg::with_name("snp_weights_path")
+                .long("snp-weights").takes_value(true).conflicts_with("he")
+                .help(
+                    "A file with a SNP_ID WEIGHT pair on every line, e.g. the weights.short\n\
+                    file of LDAK, to weight the SNPs in the kernels. The SNPs missing from\n\
+                    the file get a weight of zero"
+                )
+        )
+        .arg(
+            Arg::with_name("alpha")
+                .long("alpha").takes_value(true).allow_hyphen_values(true)
+                .conflicts_with_all(&["he", "plink_dominance_prefix"])
+                .help(
+                    "Scale the centered genotypes by [2p(1-p)]^(alpha/2) for the allele frequency p\n\
+                    instead of to unit variance, e.g. -0.25 as in LDAK. The default of -1 is\n\
+                    the unit-variance standardization of GCTA"
+                )
+        )
+        .arg(
+            Arg::with_name("covariate_path")
+                .long("covariate").short("c").takes_value(true)
+                .help(
+                    "A plink-style covariate file with the header line\n\
+                    FID IID COV_1 COV_2 ...\n\
+                    The covariates and an intercept are projected out of the phenotypes\n\
+                    and the kernels before the variance components are estimated"
+                )
+        )
+        .arg(
+            Arg::with_name("fixed_snps_path")
+                .long("fixed-snps").takes_value(true)
+                .help(
+                    "A file with one SNP ID per line, e.g. the large-effect loci of a preliminary\n\
+                    GWAS. The SNPs are fit as fixed effects and regressed out of the phenotypes\n\
+                    along with an intercept, and the variance components are estimated on the\n\
+                    residuals with the SNPs left out of the kernels"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_maf")
+                .long("qc-maf").takes_value(true)
+                .help(
+                    "The SNPs with a minor allele frequency below <qc_maf> fail the QC pass\n\
+                    that runs over the genotypes before the estimation"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_max_missing")
+                .long("qc-max-missing").takes_value(true)
+                .help(
+                    "The SNPs with a missing rate above <qc_max_missing> fail the QC pass.\n\
+                    The monomorphic SNPs always fail, and the failing SNPs are left out of\n\
+                    every component"
+                )
+        )
+        .arg(
+            Arg::with_name("no_qc")
+                .long("no-qc")
+                .conflicts_with_all(&["qc_maf", "qc_max_missing"])
+                .help("Skips the SNP QC pass over the genotypes")
+        )
+        .arg(
+            Arg::with_name("missing_genotypes")
+                .long("missing-genotypes").takes_value(true)
+                .default_value("mean-impute")
+                .possible_values(&["mean-impute", "drop-people", "fail"])
+                .help(
+                    "How the missing genotypes are handled when the SNPs are standardized:\n\
+                    mean-impute replaces them by the mean of the called genotypes of the SNP,\n\
+                    drop-people leaves out the people with a missing genotype in any of the\n\
+                    SNPs of the components, and fail exits if any genotype is missing"
+                )
+        )
+        .arg(
+            Arg::with_name("keep_path")
+                .long("keep").takes_value(true)
+                .help(
+                    "A file with the FID and IID of one person per line, as for plink --keep.\n\
+                    Only these people are in the estimation, and the people among them\n\
+                    without a value for every phenotype are dropped"
+                )
+        )
+        .arg(
+            Arg::with_name("loco")
+                .long("loco")
+                .help(
+                    "Leave out one chromosome of the bim files at a time, and report the total\n\
+                    heritability without each chromosome, the heritability attributed to each\n\
+                    chromosome and their sum. The standard errors are from the jackknife over\n\
+                    the chromosomes, replacing the --resampling option"
+                )
+        )
+        .arg(
+            Arg::with_name("he")
+                .long("he")
+                .help(
+                    "Also print the Haseman-Elston regression estimates of every phenotype as a\n\
+                    cross-check, from the exact GRM entries computed one strip of rows at a time"
+                )
+        )
+        .arg(
+            Arg::with_name("nonnegative")
+                .long("nonnegative")
+                .help(
+                    "Solve the normal equations under the constraint that the variance components,\n\
+                    including the noise, are nonnegative, in place of the unconstrained solve that\n\
+                    can return negative variance components"
+                )
+        )
+        .arg(
+            Arg::with_name("analytical_se")
+                .long("analytical-se")
+                .help(
+                    "Also report the analytical sandwich standard errors and the full covariance\n\
+                    of the variance components, which costs an extra pass over the genotypes\n\
+                    per partition and phenotype"
+                )
+        )
+        .arg(
+            Arg::with_name("prevalence")
+                .long("prevalence").takes_value(true).conflicts_with("loco")
+                .help(
+                    "The population prevalence of the case-control phenotypes, coded 0 for the\n\
+                    controls and 1 for the cases, to also report the heritability on the\n\
+                    liability scale"
+                )
+        )
+        .arg(
+            Arg::with_name("num_parametric_bootstrap_reps")
+                .long("parametric-bootstrap").takes_value(true).conflicts_with("loco")
+                .help(
+                    "Also report the 95% percentile intervals of the variance components and h2\n\
+                    from this many parametric bootstrap draws of the normal equations, whose\n\
+                    covariance is estimated from the resampling replicates, e.g. 1000"
+                )
+        )
+        .arg(
+            Arg::with_name("keep_resampling_blocks")
+                .long("keep-resampling-blocks").requires("json_out_path").conflicts_with("loco")
+                .help(
+                    "Also write the SNP index ranges of the blocks of every resampling replicate\n\
+                    to the --json-out file, next to the estimates of every replicate"
+                )
+        )
+        .arg(
+            Arg::with_name("checkpoint_dir")
+                .long("checkpoint-dir").takes_value(true).conflicts_with("loco")
+                .help(
+                    "Save the progress to this directory after every streamed jackknife block
+                    of SNPs and periodically during the resampling, so that an interrupted run
+                    can be resumed with --resume"
+                )
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume").requires("checkpoint_dir")
+                .help(
+                    "Resume from the checkpoint in --checkpoint-dir if there is one, which has
+                    to be from a run on the same bfiles, phenotypes, partitions and --nrv"
+                )
+        )
+        .arg(
+            Arg::with_name("num_threads")
+                .long("num-threads").takes_value(true)
+                .help("The number of threads, all the cores by default")
+        )
+        .arg(
+            Arg::with_name("max_memory_mb")
+                .long("max-memory-mb").takes_value(true)
+                .help(
+                    "The memory budget in MB of the streamed SNP chunks, from which the number of\n\
+                    SNPs per chunk is derived"
+                )
+        )
+        .arg(
+            Arg::with_name("chunk_size")
+                .long("chunk-size").takes_value(true)
+                .help("The number of SNPs per streamed chunk, overriding --max-memory-mb")
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend").takes_value(true).default_value("cpu")
+                .possible_values(&["cpu", "cuda"])
+                .help(
+                    "The backend of the products of the SNP chunks. cuda offloads them to the\n\
+                    GPU if saber is built with the cuda feature and a device is available, and\n\
+                    falls back on the CPU otherwise"
+                )
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random probe vectors, the random jackknife subsets and the bootstrap\n\
+                    replicates, so that the estimates are reproducible regardless of the number\n\
+                    of threads"
+                )
+        )
+        .arg(
+            Arg::with_name("recipe")
+                .long("recipe").takes_value(true)
+                .help(
+                    "Use the default options of a curated preset, e.g. quick-h2\n\
+                    Options given explicitly take precedence over the preset.\n\
+                    Use --recipe list to describe the available presets"
+                )
+        )
+        .arg(
+            Arg::with_name("json_out_path")
+                .long("json-out").takes_value(true)
+                .help(
+                    "Also write the estimates, including the jackknife covariance matrix\n\
+                    of the partition estimates and the estimates of every resampling replicate,\n\
+                    as JSON to <json_out_path>"
+                )
+        )
+        .arg(
+            Arg::with_name("jackknife_tsv_path")
+                .long("jackknife-tsv").takes_value(true)
+                .help(
+                    "Also write the estimate of every component in every jackknife replicate\n\
+                    to <jackknife_tsv_path> as long-format TSV with the columns\n\
+                    phenotype, replicate, component and estimate, for plotting"
+                )
         );
     let matches = app.get_matches();
 
+    let recipe = match extract_optional_str_arg(&matches, "recipe") {
+        Some(name) => {
+            if name == RECIPE_LIST_NAME {
+                println!("{}", format_recipe_list());
+                return;
+            }
+            Some(
+                get_recipe(&name, "estimate_heritability")
+                    .unwrap_or_exit(None::<String>),
+            )
+        }
+        None => None,
+    };
+
+    // the recipes do not name the genotypes, so --bfile is only optional for
+    // --recipe list
+    if matches.occurrences_of("plink_filename_prefix") == 0 {
+        eprintln!("--bfile is required unless listing the recipes");
+        std::process::exit(1);
+    }
     let plink_filename_prefixes =
         extract_str_vec_arg(&matches, "plink_filename_prefix")
             .unwrap_or_exit(Some("failed to parse the bfile list".to_string()));
@@ -108,30 +506,233 @@ fn main() {
         .unwrap_or(Vec::<String>::new());
     let pheno_paths_file =
         extract_optional_str_arg(&matches, "pheno_paths_file");
+    let pheno_names = extract_optional_str_vec_arg(&matches, "pheno_name");
     let partition_filepath =
         extract_optional_str_arg(&matches, "partition_file");
+    let covariate_path = extract_optional_str_arg(&matches, "covariate_path");
+    let fixed_snps_path = extract_optional_str_arg(&matches, "fixed_snps_path");
+    let snp_weights_path =
+        extract_optional_str_arg(&matches, "snp_weights_path");
+    let standardization = Standardization::from_alpha(
+        extract_optional_numeric_arg::<f64>(&matches, "alpha")
+            .unwrap_or_exit(Some("failed to extract alpha"))
+            .unwrap_or(DEFAULT_ALPHA),
+    );
+    let keep_path = extract_optional_str_arg(&matches, "keep_path");
+    let analytical_se = matches.is_present("analytical_se");
+    let missing_genotypes = extract_str_arg(&matches, "missing_genotypes")
+        .parse::<MissingGenotypePolicy>()
+        .unwrap_or_exit(None::<String>);
+    let snp_qc_filter = if matches.is_present("no_qc") {
+        None
+    } else {
+        Some(SnpChunkFilter::new(
+            extract_optional_numeric_arg::<f32>(&matches, "qc_maf")
+                .unwrap_or_exit(Some("failed to extract qc_maf")),
+            extract_optional_numeric_arg::<f32>(&matches, "qc_max_missing")
+                .unwrap_or_exit(Some("failed to extract qc_max_missing")),
+        ))
+    };
+    let nonnegative = matches.is_present("nonnegative");
+    let he = matches.is_present("he");
+    let loco = matches.is_present("loco");
+    let compute = ComputeConfig::new(
+        extract_optional_numeric_arg::<usize>(&matches, "num_threads")
+            .unwrap_or_exit(Some("failed to extract num_threads")),
+        extract_optional_numeric_arg::<usize>(&matches, "max_memory_mb")
+            .unwrap_or_exit(Some("failed to extract max_memory_mb"))
+            .map(|mb| mb * BYTES_PER_MB),
+        extract_optional_numeric_arg::<usize>(&matches, "chunk_size")
+            .unwrap_or_exit(Some("failed to extract chunk_size")),
+    )
+    .with_backend(
+        extract_str_arg(&matches, "backend")
+            .parse::<ComputeBackend>()
+            .unwrap_or_exit(None::<String>),
+    );
+    let checkpoint = extract_optional_str_arg(&matches, "checkpoint_dir")
+        .map(|dir| CheckpointConfig::new(dir, matches.is_present("resume")));
+    let model =
+        match (
+            extract_optional_str_arg(&matches, "model"),
+            extract_optional_str_arg(&matches, "model_file"),
+        ) {
+            (Some(m), _) => Some(m.parse::<ModelSpec>().unwrap_or_exit(Some(
+                format!("failed to parse the model {}", m),
+            ))),
+            (None, Some(path)) => {
+                Some(ModelSpec::from_file(&path).unwrap_or_exit(None::<String>))
+            }
+            (None, None) => None,
+        };
+    let maf_bins = match extract_optional_str_arg(&matches, "maf_bins") {
+        None if model.is_none() => {
+            recipe.and_then(|r| r.maf_bins).map(String::from)
+        }
+        maf_bins => maf_bins,
+    };
+    let maf_bins = maf_bins.map(|b| {
+        b.parse::<MafBins>()
+            .unwrap_or_exit(Some(format!("failed to parse the MAF bins {}", b)))
+    });
+    if let Some(model) = &model {
+        model
+            .check_supported_kinds(
+                &[ComponentKind::Additive, ComponentKind::Dominance],
+                "estimate_heritability",
+            )
+            .unwrap_or_exit(None::<String>);
+        if model.components_of_kind(ComponentKind::Dominance).count() > 0
+            && plink_dominance_prefixes.is_none()
+        {
+            eprintln!("D components require --dominance-bfile");
+            std::process::exit(1);
+        }
+    }
+    let json_out_path = extract_optional_str_arg(&matches, "json_out_path");
+    let jackknife_tsv_path =
+        extract_optional_str_arg(&matches, "jackknife_tsv_path");
+
+    let num_jackknife_partitions = match recipe {
+        Some(r) if matches.occurrences_of("num_jackknife_partitions") == 0 => {
+            r.num_jackknife_partitions
+        }
+        _ => extract_numeric_arg::<usize>(&matches, "num_jackknife_partitions")
+            .unwrap_or_exit(Some("failed to extract num_jackknife_partitions")),
+    };
+    let prevalence =
+        extract_optional_numeric_arg::<f64>(&matches, "prevalence")
+            .unwrap_or_exit(Some("failed to extract the prevalence"));
+    let num_parametric_bootstrap_reps = extract_optional_numeric_arg::<usize>(
+        &matches,
+        "num_parametric_bootstrap_reps",
+    )
+    .unwrap_or_exit(Some("failed to extract num_parametric_bootstrap_reps"));
+    let keep_resampling_blocks = matches.is_present("keep_resampling_blocks");
+    let num_bootstrap_reps =
+        extract_optional_numeric_arg::<usize>(&matches, "num_bootstrap_reps")
+            .unwrap_or_exit(Some("failed to extract num_bootstrap_reps"))
+            .unwrap_or(DEFAULT_NUM_BOOTSTRAP_REPS);
+    let resampling = Resampling::from_str(
+        &extract_str_arg(&matches, "resampling"),
+        num_jackknife_partitions,
+        num_bootstrap_reps,
+    )
+    .unwrap_or_exit(None::<String>);
 
-    let num_jackknife_partitions =
-        extract_numeric_arg::<usize>(&matches, "num_jackknife_partitions")
-            .unwrap_or_exit(Some("failed to extract num_jackknife_partitions"));
+    let seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
 
     let lowest_allowed_maf =
         extract_optional_numeric_arg::<f32>(&matches, "lowest_allowed_maf")
-            .unwrap_or_exit(Some("failed to extract lowest_allowed_maf"));
+            .unwrap_or_exit(Some("failed to extract lowest_allowed_maf"))
+            .or(recipe.and_then(|r| r.lowest_allowed_maf));
+
+    let probe_type = extract_str_arg(&matches, "probe_type")
+        .parse::<ProbeType>()
+        .unwrap_or_exit(None::<String>);
+    let probe_sparsity =
+        extract_optional_numeric_arg::<f64>(&matches, "probe_sparsity")
+            .unwrap_or_exit(Some("failed to extract probe_sparsity"));
+    if let Some(q) = probe_sparsity {
+        if !(q > 0. && q <= 1.) {
+            eprintln!("--probe-sparsity has to be in (0, 1], received {}", q);
+            std::process::exit(1);
+        }
+        if probe_type != ProbeType::Rademacher {
+            eprintln!(
+                "--probe-sparsity requires --probe-type rademacher, received {}",
+                probe_type
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let trace_mode = match extract_str_arg(&matches, "trace_mode").as_str() {
+        "auto" => TraceMode::Auto {
+            max_num_people: extract_optional_numeric_arg::<usize>(
+                &matches,
+                "exact_max_people",
+            )
+            .unwrap_or_exit(Some("failed to extract exact_max_people"))
+            .unwrap_or(DEFAULT_EXACT_MAX_NUM_PEOPLE),
+        },
+        mode => mode.parse::<TraceMode>().unwrap_or_exit(None::<String>),
+    };
 
-    let num_random_vecs = extract_str_arg(&matches, "num_random_vecs")
-        .parse::<usize>()
-        .unwrap_or_exit(Some("failed to parse num_random_vecs"));
+    let num_random_vecs = match (matches.value_of("num_random_vecs"), recipe) {
+        (None, Some(r)) => r.num_random_vecs,
+        _ => extract_str_arg(&matches, "num_random_vecs")
+            .parse::<usize>()
+            .unwrap_or_exit(Some("failed to parse num_random_vecs")),
+    };
+    let max_num_random_vecs =
+        extract_optional_numeric_arg::<usize>(&matches, "max_num_random_vecs")
+            .unwrap_or_exit(Some("failed to extract max_num_random_vecs"));
+    let trace_tolerance =
+        extract_optional_numeric_arg::<f64>(&matches, "trace_tolerance")
+            .unwrap_or_exit(Some("failed to extract trace_tolerance"));
+    if let Some(tolerance) = trace_tolerance {
+        if !(tolerance > 0.) {
+            eprintln!(
+                "--trace-tolerance has to be positive, received {}",
+                tolerance
+            );
+            std::process::exit(1);
+        }
+    }
 
+    if let Some(model) = &model {
+        println!("model: {}", model);
+    }
+    if let Some(maf_bins) = &maf_bins {
+        println!("maf_bins: {}", maf_bins);
+    }
     println!(
         "num_random_vecs: {}\n\
+        max_num_random_vecs: {:?}\n\
+        trace_tolerance: {:?}\n\
+        probe_type: {}\n\
+        trace_mode: {}\n\
         partition_filepath: {}\n\
-        num_jackknife_partitions: {}\n\
-        pheno_paths_file: {}",
+        resampling: {}\n\
+        pheno_paths_file: {}\n\
+        covariate_path: {}\n\
+        fixed_snps_path: {}\n\
+        snp_qc_filter: {:?}\n\
+        missing_genotypes: {}\n\
+        snp_weights_path: {}\n\
+        standardization: {}\n\
+        keep_path: {}\n\
+        nonnegative: {}\n\
+        analytical_se: {}\n\
+        num_parametric_bootstrap_reps: {:?}\n\
+        keep_resampling_blocks: {}\n\
+        prevalence: {:?}\n\
+        compute: {:?}\n\
+        checkpoint: {:?}",
         num_random_vecs,
+        max_num_random_vecs,
+        trace_tolerance,
+        probe_type,
+        trace_mode,
         partition_filepath.as_ref().unwrap_or(&"".to_string()),
-        num_jackknife_partitions,
+        resampling,
         pheno_paths_file.as_ref().unwrap_or(&"".to_string()),
+        covariate_path.as_ref().unwrap_or(&"".to_string()),
+        fixed_snps_path.as_ref().unwrap_or(&"".to_string()),
+        snp_qc_filter,
+        missing_genotypes,
+        snp_weights_path.as_ref().unwrap_or(&"".to_string()),
+        standardization,
+        keep_path.as_ref().unwrap_or(&"".to_string()),
+        nonnegative,
+        analytical_se,
+        num_parametric_bootstrap_reps,
+        keep_resampling_blocks,
+        prevalence,
+        compute,
+        checkpoint,
     );
     let pheno_path_list = match &pheno_paths_file {
         None => pheno_path_list,
@@ -148,6 +749,22 @@ fn main() {
             paths
         }
     };
+    // the phenotypes of a multi-column file are named by their columns
+    let (pheno_path_list, multi_pheno_path) = match &pheno_names {
+        None => (pheno_path_list, None),
+        Some(names) => {
+            if pheno_path_list.len() != 1 {
+                eprintln!(
+                    "--pheno-name selects the columns of a single --pheno file, \
+                    received {} files",
+                    pheno_path_list.len()
+                );
+                std::process::exit(1);
+            }
+            println!("phenotype columns of {}:", pheno_path_list[0]);
+            (names.clone(), Some(pheno_path_list[0].clone()))
+        }
+    };
     let num_phenos = pheno_path_list.len();
     if num_phenos == 0 {
         eprintln!(
@@ -167,48 +784,403 @@ fn main() {
     )
     .unwrap_or_exit(None::<String>);
 
-    let mut filtered_partitions = bim
+    let file_partitions = bim
         .get_fileline_partitions_or(
             DEFAULT_PARTITION_NAME,
             OrderedIntegerSet::from_slice(&[[0, bed.total_num_snps() - 1]]),
         )
         .into_hash_map();
 
-    if let Some(l) = lowest_allowed_maf {
+    let mafs = if lowest_allowed_maf.is_some()
+        || maf_bins.is_some()
+        || standardization != Standardization::UnitVariance
+        || model.as_ref().map_or(false, |m| m.needs_maf())
+    {
         println!("=> computing minor allele frequencies");
-        let mut low_maf = OrderedIntegerSet::new();
-        bed.get_minor_allele_frequencies(None)
+        Some(bed.get_minor_allele_frequencies(None))
+    } else {
+        None
+    };
+
+    // the dominance SNPs follow the additive SNPs in the bed
+    let (additive_snps, dominance_snps) = get_additive_and_dominance_snps(
+        &plink_filename_prefixes,
+        bed.total_num_snps(),
+    )
+    .unwrap_or_exit(None::<String>);
+    let mut filtered_partitions = match &model {
+        None if dominance_snps.size() > 0 => {
+            // the dominance SNPs form a variance component of their own next
+            // to the additive partitions
+            println!(
+                "\n=> fitting {} dominance SNPs as the {} component",
+                dominance_snps.size(),
+                DOMINANCE_PARTITION_NAME
+            );
+            let mut partitions: HashMap<String, OrderedIntegerSet<usize>> =
+                file_partitions
+                    .into_iter()
+                    .map(|(name, snps)| {
+                        (name, snps.merge_intersect(&additive_snps))
+                    })
+                    .filter(|(_, snps)| snps.size() > 0)
+                    .collect();
+            partitions
+                .insert(DOMINANCE_PARTITION_NAME.to_string(), dominance_snps);
+            partitions
+        }
+        None => file_partitions,
+        Some(model) => {
+            let mut components = Vec::new();
+            for (kind, candidates) in [
+                (ComponentKind::Additive, additive_snps),
+                (ComponentKind::Dominance, dominance_snps),
+            ]
+            .iter()
+            {
+                components.extend(
+                    model
+                        .get_component_snps(
+                            *kind,
+                            candidates,
+                            mafs.as_ref().map(|m| m.as_slice()),
+                            &file_partitions,
+                        )
+                        .unwrap_or_exit(None::<String>),
+                );
+            }
+            components.into_iter().collect()
+        }
+    };
+
+    if let Some(maf_bins) = &maf_bins {
+        // every partition is split into the bins, except for the dominance
+        // component whose SNPs are coded differently
+        let mafs = mafs.as_ref().unwrap();
+        filtered_partitions = filtered_partitions
             .into_iter()
-            .enumerate()
-            .for_each(|(i, f)| {
-                if f < l {
-                    low_maf.collect(i);
+            .flat_map(|(name, snps)| {
+                if name == DOMINANCE_PARTITION_NAME {
+                    return vec![(name, snps)];
                 }
-            });
+                maf_bins
+                    .partition(&snps, mafs)
+                    .into_iter()
+                    .map(|(bin_name, bin)| {
+                        if name == DEFAULT_PARTITION_NAME {
+                            (bin_name, bin)
+                        } else {
+                            (format!("{}_{}", name, bin_name), bin)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        println!(
+            "\n=> split the SNPs into {} MAF bins, fitting {} components",
+            maf_bins.num_bins(),
+            filtered_partitions.len()
+        );
+    }
+
+    if let Some(l) = lowest_allowed_maf {
+        let low_maf = OrderedIntegerSet::collect_from_sorted_iter(
+            mafs.as_ref()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| **f < l)
+                .map(|(i, _)| i),
+        );
         println!("removing {} alleles with frequency < {}", low_maf.size(), l);
         filtered_partitions
             .values_mut()
-            .for_each(|v| *v -= &low_maf);
+            .for_each(|v| *v = v.merge_difference(&low_maf));
     };
 
+    let fixed_snps = fixed_snps_path.as_ref().map(|path| {
+        let bim_paths: Vec<String> = plink_filename_prefixes
+            .iter()
+            .map(|prefix| get_bed_bim_fam_path(prefix).1)
+            .collect();
+        let snp_ids = read_snp_id_list(path).unwrap_or_exit(None::<String>);
+        let fixed_snps = FixedSnps::from_bim_files(&snp_ids, &bim_paths)
+            .unwrap_or_exit(None::<String>);
+        println!(
+            "\n=> leaving the {} fixed-effect SNPs out of the kernels",
+            fixed_snps.num_snps()
+        );
+        let fixed_snp_set = fixed_snps.to_set();
+        filtered_partitions
+            .values_mut()
+            .for_each(|v| *v = v.merge_difference(&fixed_snp_set));
+        fixed_snps
+    });
+
+    if let Some(filter) = &snp_qc_filter {
+        println!("\n=> running the SNP QC pass");
+        let qc = SnpQc::new(&bed, DEFAULT_NUM_SNPS_PER_CHUNK);
+        println!("{}", qc.summarize(filter));
+        filtered_partitions = qc.filter_partitions(filtered_partitions, filter);
+        if filtered_partitions.is_empty() {
+            eprintln!("no SNP is left in any component after the QC");
+            std::process::exit(1);
+        }
+    }
+
+    let mut components: Vec<(String, OrderedIntegerSet<usize>)> =
+        filtered_partitions.clone().into_iter().collect();
+    components.sort_by(|a, b| a.0.cmp(&b.0));
     bim.set_fileline_partitions(Some(FilelinePartitions::new(
         filtered_partitions,
     )));
 
-    let pheno_path_to_est = estimate_heritability(
-        bed,
-        bim,
-        pheno_path_list.clone(),
-        num_random_vecs,
-        num_jackknife_partitions,
-    )
-    .unwrap_or_exit(None::<String>);
-    pheno_path_list.iter().for_each(|path| {
+    let fam_path = get_bed_bim_fam_path(&plink_filename_prefixes[0]).2;
+    let (mut pheno_matrix, people) = match (&multi_pheno_path, &keep_path) {
+        (None, None) => (
+            get_fam_aligned_pheno_matrix(&fam_path, &pheno_path_list)
+                .unwrap_or_exit(None::<String>),
+            None,
+        ),
+        (Some(path), None) => (
+            get_fam_aligned_multi_pheno_matrix(
+                &fam_path,
+                path,
+                Some(&pheno_path_list),
+            )
+            .unwrap_or_exit(None::<String>)
+            .1,
+            None,
+        ),
+        (Some(path), Some(keep_path)) => {
+            let keep = get_keep_indices(&fam_path, keep_path)
+                .unwrap_or_exit(None::<String>);
+            let (_, pheno_matrix, people) =
+                get_fam_aligned_multi_pheno_matrix_of_people(
+                    &fam_path,
+                    path,
+                    Some(&pheno_path_list),
+                    &keep,
+                )
+                .unwrap_or_exit(None::<String>);
+            (pheno_matrix, Some(people))
+        }
+        (None, Some(keep_path)) => {
+            let keep = get_keep_indices(&fam_path, keep_path)
+                .unwrap_or_exit(None::<String>);
+            let (pheno_matrix, people) =
+                get_fam_aligned_pheno_matrix_of_people(
+                    &fam_path,
+                    &pheno_path_list,
+                    &keep,
+                )
+                .unwrap_or_exit(None::<String>);
+            (pheno_matrix, Some(people))
+        }
+    };
+
+    // the fraction of cases of every phenotype if the estimates are to be
+    // converted to the liability scale
+    let case_fractions: Option<Vec<f64>> = match prevalence {
+        Some(_) => Some(
+            pheno_matrix
+                .gencolumns()
+                .into_iter()
+                .zip(pheno_path_list.iter())
+                .map(|(pheno, path)| {
+                    get_case_fraction(&pheno)
+                        .map_err(|why| format!("{}: {}", path, why))
+                })
+                .collect::<Result<Vec<f64>, String>>()
+                .unwrap_or_exit(None::<String>),
+        ),
+        None => {
+            for (pheno, path) in pheno_matrix
+                .gencolumns()
+                .into_iter()
+                .zip(pheno_path_list.iter())
+            {
+                if is_case_control(&pheno) {
+                    println!(
+                        "\n=> {} is a case-control phenotype, its estimates are \
+                        on the observed scale without --prevalence",
+                        path
+                    );
+                }
+            }
+            None
+        }
+    };
+
+    let covariates = covariate_path.as_ref().map(|path| {
+        match &people {
+            None => CovariateProjection::from_file(path),
+            Some(people) => {
+                CovariateProjection::from_file_of_people(path, people)
+            }
+        }
+        .unwrap_or_exit(None::<String>)
+    });
+
+    if let Some(fixed_snps) = &fixed_snps {
         println!(
-            "heritability estimates for {}:\n{}",
-            path, pheno_path_to_est[path]
+            "\n=> regressing the {} fixed-effect SNPs out of the phenotypes",
+            fixed_snps.num_snps()
         );
-    })
+        let geno = PeopleSubset::new(&bed, people.as_ref())
+            .unwrap_or_exit(None::<String>);
+        let effects = fixed_snps
+            .regress_out_inplace(&geno, &mut pheno_matrix)
+            .unwrap_or_exit(None::<String>);
+        for (p, path) in pheno_path_list.iter().enumerate() {
+            println!("fixed SNP effects on {}:", path);
+            for (i, id) in effects.snp_ids.iter().enumerate() {
+                println!("{}\t{}", id, effects.effects[[i, p]]);
+            }
+        }
+    }
+
+    // the weights of the dominance SNPs follow those of the additive SNPs
+    let snp_weights = snp_weights_path.as_ref().map(|path| {
+        let bim_paths: Vec<String> = plink_filename_prefixes
+            .iter()
+            .chain(plink_dominance_prefixes.iter().flatten())
+            .map(|prefix| get_bed_bim_fam_path(prefix).1)
+            .collect();
+        SnpWeights::from_file(path, &bim_paths).unwrap_or_exit(None::<String>)
+    });
+    let snp_weights = match standardization {
+        Standardization::UnitVariance => snp_weights,
+        Standardization::Alpha(alpha) => {
+            let alpha_weights =
+                SnpWeights::from_alpha(alpha, mafs.as_ref().unwrap())
+                    .unwrap_or_exit(None::<String>);
+            Some(match snp_weights {
+                Some(w) => {
+                    w.product(&alpha_weights).unwrap_or_exit(None::<String>)
+                }
+                None => alpha_weights,
+            })
+        }
+    };
+
+    if he {
+        if covariates.is_some() {
+            eprintln!("--he does not adjust for the covariates");
+            std::process::exit(1);
+        }
+        let geno = PeopleSubset::new(&bed, people.as_ref())
+            .unwrap_or_exit(None::<String>);
+        for (p, path) in pheno_path_list.iter().enumerate() {
+            println!("\n=> HE regression for {}", path);
+            let est = estimate_heritability_he(
+                &geno,
+                components.clone(),
+                &pheno_matrix.column(p).to_owned(),
+                None,
+                None,
+            )
+            .unwrap_or_exit(None::<String>);
+            println!("{}", est);
+        }
+    }
+
+    let mut builder = HeritabilityEstimatorBuilder::new()
+        .plink(bed, bim)
+        .phenotype_matrix(pheno_matrix, pheno_path_list.clone())
+        .num_random_vecs(num_random_vecs)
+        .probe_type(probe_type)
+        .trace_mode(trace_mode)
+        .missing_genotypes(missing_genotypes)
+        .nonnegative(nonnegative)
+        .compute(compute);
+    if let Some(probe_sparsity) = probe_sparsity {
+        builder = builder.probe_sparsity(probe_sparsity);
+    }
+    if let Some(covariates) = covariates {
+        builder = builder.covariates(covariates);
+    }
+    if let Some(people) = people {
+        builder = builder.keep(people);
+    }
+    if let Some(snp_weights) = snp_weights {
+        builder = builder.snp_weights(snp_weights);
+    }
+    if let Some(seed) = seed {
+        builder = builder.seed(seed);
+    }
+
+    if loco {
+        let loco_estimates =
+            builder.run_loco().unwrap_or_exit(None::<String>);
+        for est in loco_estimates.iter() {
+            print_summary_table(
+                &format!("heritability estimates for {}:", est.pheno_name),
+                &est.estimates,
+            );
+            println!(
+                "\nleave-one-chromosome-out estimates for {}:\n{}",
+                est.pheno_name, est
+            );
+        }
+        return;
+    }
+    builder = builder
+        .resampling(resampling)
+        .analytical_se(analytical_se)
+        .keep_resampling_blocks(keep_resampling_blocks);
+    if let Some(max_num_random_vecs) = max_num_random_vecs {
+        builder = builder.max_num_random_vecs(max_num_random_vecs);
+    }
+    if let Some(trace_tolerance) = trace_tolerance {
+        builder = builder.trace_tolerance(trace_tolerance);
+    }
+    if let Some(num_reps) = num_parametric_bootstrap_reps {
+        builder = builder.parametric_bootstrap(num_reps);
+    }
+    if let Some(checkpoint) = checkpoint {
+        builder = builder.checkpoint(checkpoint);
+    }
+    let mut pheno_table = builder.run().unwrap_or_exit(None::<String>);
+    if let (Some(prevalence), Some(case_fractions)) =
+        (prevalence, &case_fractions)
+    {
+        for (est, &case_fraction) in
+            pheno_table.estimates.iter_mut().zip(case_fractions.iter())
+        {
+            est.liability_scale = Some(
+                LiabilityScaleEstimates::new(est, prevalence, case_fraction)
+                    .unwrap_or_exit(None::<String>),
+            );
+        }
+    }
+    pheno_path_list.iter().for_each(|path| {
+        print_summary_table(
+            &format!("heritability estimates for {}:", path),
+            pheno_table.get(path).unwrap(),
+        );
+        if pheno_table.partition_sizes.len() > 1 {
+            let partitioned = pheno_table
+                .get_partitioned_heritability(path)
+                .unwrap()
+                .unwrap_or_exit(None::<String>);
+            println!(
+                "\npartitioned heritability for {}:\n{}",
+                path, partitioned
+            );
+        }
+    });
+    let pheno_path_to_est = pheno_table.into_hash_map();
+    if let Some(out_path) = json_out_path {
+        println!("\n=> writing the estimates to {}", out_path);
+        write_estimates_json(&pheno_path_to_est, &out_path)
+            .unwrap_or_exit(None::<String>);
+    }
+    if let Some(out_path) = jackknife_tsv_path {
+        println!("\n=> writing the jackknife replicates to {}", out_path);
+        write_jackknife_replicates_tsv(&pheno_path_to_est, &out_path)
+            .unwrap_or_exit(None::<String>);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,286 @@
+use clap::{clap_app, Arg};
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use program_flow::{
+    argparse::{
+        extract_numeric_arg, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_optional_str_vec_arg,
+        extract_str_vec_arg,
+    },
+    OrExit,
+};
+
+use saber::{
+    grm::{get_gcta_grm_paths, Grm},
+    heritability_estimator::DEFAULT_PARTITION_NAME,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    reml::{
+        estimate_heritability_reml, estimate_heritability_reml_from_grms,
+        RemlConfig, RemlEstimates,
+    },
+    snp_chunk_filter::SnpChunkFilter,
+    snp_qc::SnpQc,
+    util::{
+        get_bed_bim_fam_path, get_bed_bim_from_prefix_and_partition,
+        get_fam_aligned_pheno_arr, get_fid_iid_list,
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let mut app = clap_app!(estimate_heritability_reml =>
+        (version: "0.1")
+    );
+    app = app
+        .about(
+            "Estimates the variance components by stochastic AI-REML, for\n\
+            comparison with the method of moments estimates of estimate_heritability"
+        )
+        .arg(
+            Arg::with_name("plink_filename_prefix")
+                .long("bfile").short("b").takes_value(true).required_unless("grm_prefix")
+                .multiple(true).number_of_values(1)
+                .help(
+                    "If we have files named \n\
+                    PATH/TO/x.bed PATH/TO/x.bim PATH/TO/x.fam \n\
+                    then the <plink_filename_prefix> should be path/to/x"
+                )
+        )
+        .arg(
+            Arg::with_name("pheno_path")
+                .long("pheno").short("e").takes_value(true).required(true)
+                .multiple(true).number_of_values(1)
+                .help(
+                    "The header line should be\n\
+                    FID IID PHENOTYPE_NAME\n\
+                    where PHENOTYPE_NAME can be any string without white spaces.\n\
+                    The rest of the lines are of the form:\n\
+                    1000011 1000011 -12.11363"
+                )
+        )
+        .arg(
+            Arg::with_name("grm_prefix")
+                .long("grm").takes_value(true).multiple(true).number_of_values(1)
+                .conflicts_with_all(&[
+                    "plink_filename_prefix", "partition_file", "qc_maf", "qc_max_missing", "no_qc"
+                ])
+                .help(
+                    "The prefix of a precomputed GRM in the binary format of GCTA, e.g. written
+                    by compute_grm, to fit as a component in place of the bfile.
+                    Every GRM is a component of its own, and the people are those in the
+                    <grm_prefix>.grm.id file of the first GRM"
+                )
+        )
+        .arg(
+            Arg::with_name("partition_file")
+                .long("partition").short("p").takes_value(true)
+                .help(
+                    "A file to partition the SNPs into multiple components.\n\
+                    Each line consists of two values of the form:\n\
+                    SNP_ID PARTITION"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_maf")
+                .long("qc-maf").takes_value(true)
+                .help(
+                    "The SNPs with a minor allele frequency below <qc_maf> fail the QC pass\n\
+                    that runs over the genotypes before the estimation"
+                )
+        )
+        .arg(
+            Arg::with_name("qc_max_missing")
+                .long("qc-max-missing").takes_value(true)
+                .help(
+                    "The SNPs with a missing rate above <qc_max_missing> fail the QC pass.\n\
+                    The monomorphic SNPs always fail, and the failing SNPs are left out of\n\
+                    every component"
+                )
+        )
+        .arg(
+            Arg::with_name("no_qc")
+                .long("no-qc")
+                .conflicts_with_all(&["qc_maf", "qc_max_missing"])
+                .help("Skips the SNP QC pass over the genotypes")
+        )
+        .arg(
+            Arg::with_name("num_random_vecs")
+                .long("nrv").short("n").takes_value(true).default_value("30")
+                .help("The number of random vectors used to estimate the traces in the REML scores")
+        )
+        .arg(
+            Arg::with_name("max_iterations")
+                .long("max-iter").takes_value(true).default_value("20")
+                .help("The maximum number of AI-REML iterations")
+        )
+        .arg(
+            Arg::with_name("tolerance")
+                .long("tol").takes_value(true).default_value("1e-4")
+                .help(
+                    "Stop once no variance changes by more than this fraction of the\n\
+                    total variance in an iteration"
+                )
+        )
+        .arg(
+            Arg::with_name("cg_tolerance")
+                .long("cg-tol").takes_value(true).default_value("1e-5")
+                .help("The relative residual at which the conjugate gradient solves stop")
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random vectors of the traces in the REML scores, so that the\n\
+                    estimates are reproducible regardless of the number of threads"
+                )
+        );
+    let matches = app.get_matches();
+
+    let plink_filename_prefixes =
+        extract_optional_str_vec_arg(&matches, "plink_filename_prefix");
+    let grm_prefixes = extract_optional_str_vec_arg(&matches, "grm_prefix");
+    let pheno_path_list = extract_str_vec_arg(&matches, "pheno_path")
+        .unwrap_or_exit(Some("failed to parse the pheno paths".to_string()));
+    let partition_filepath =
+        extract_optional_str_arg(&matches, "partition_file");
+    let mut config = RemlConfig::new(
+        extract_numeric_arg::<usize>(&matches, "num_random_vecs")
+            .unwrap_or_exit(Some("failed to extract num_random_vecs")),
+    );
+    config.max_iterations =
+        extract_numeric_arg::<usize>(&matches, "max_iterations")
+            .unwrap_or_exit(Some("failed to extract max_iterations"));
+    config.tolerance = extract_numeric_arg::<f64>(&matches, "tolerance")
+        .unwrap_or_exit(Some("failed to extract tolerance"));
+    config.cg_tolerance = extract_numeric_arg::<f64>(&matches, "cg_tolerance")
+        .unwrap_or_exit(Some("failed to extract cg_tolerance"));
+    config.seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
+    let snp_qc_filter = if matches.is_present("no_qc") {
+        None
+    } else {
+        Some(SnpChunkFilter::new(
+            extract_optional_numeric_arg::<f32>(&matches, "qc_maf")
+                .unwrap_or_exit(Some("failed to extract qc_maf")),
+            extract_optional_numeric_arg::<f32>(&matches, "qc_max_missing")
+                .unwrap_or_exit(Some("failed to extract qc_max_missing")),
+        ))
+    };
+    println!(
+        "num_random_vecs: {}\n\
+        max_iterations: {}\n\
+        tolerance: {}\n\
+        cg_tolerance: {}\n\
+        partition_filepath: {}\n\
+        snp_qc_filter: {:?}",
+        config.num_random_vecs,
+        config.max_iterations,
+        config.tolerance,
+        config.cg_tolerance,
+        partition_filepath.as_ref().unwrap_or(&"".to_string()),
+        snp_qc_filter,
+    );
+
+    if let Some(grm_prefixes) = grm_prefixes {
+        let grms: Vec<(String, Grm)> = grm_prefixes
+            .iter()
+            .map(|prefix| {
+                (
+                    prefix.to_string(),
+                    Grm::from_gcta_files(prefix).unwrap_or_exit(None::<String>),
+                )
+            })
+            .collect();
+        // the phenotypes are aligned to the people of the first GRM
+        let id_path = get_gcta_grm_paths(&grm_prefixes[0]).2;
+        let fid_iid_list = get_fid_iid_list(&id_path).unwrap_or_exit(Some(
+            format!("failed to read the people in {}", id_path),
+        ));
+        for prefix in grm_prefixes.iter().skip(1) {
+            let path = get_gcta_grm_paths(prefix).2;
+            if get_fid_iid_list(&path)
+                .unwrap_or_exit(Some(format!("failed to read {}", path)))
+                != fid_iid_list
+            {
+                eprintln!("{} and {} list different people", path, id_path);
+                std::process::exit(1);
+            }
+        }
+        for (i, pheno_path) in pheno_path_list.iter().enumerate() {
+            println!(
+                "\n=> [{}/{}] estimating the REML variance components for {}",
+                i + 1,
+                pheno_path_list.len(),
+                pheno_path
+            );
+            let pheno = get_fam_aligned_pheno_arr(&id_path, pheno_path)
+                .unwrap_or_exit(None::<String>);
+            let est =
+                estimate_heritability_reml_from_grms(&grms, &pheno, &config)
+                    .unwrap_or_exit(None::<String>);
+            print_reml_estimates(&est);
+        }
+        return;
+    }
+
+    let plink_filename_prefixes = plink_filename_prefixes.unwrap();
+    let (bed, mut bim) = get_bed_bim_from_prefix_and_partition(
+        &plink_filename_prefixes,
+        &None,
+        &partition_filepath,
+    )
+    .unwrap_or_exit(None::<String>);
+    let mut partitions = bim
+        .get_fileline_partitions_or(
+            DEFAULT_PARTITION_NAME,
+            OrderedIntegerSet::from_slice(&[[0, bed.total_num_snps() - 1]]),
+        )
+        .into_hash_map();
+    if let Some(filter) = &snp_qc_filter {
+        println!("\n=> running the SNP QC pass");
+        let qc = SnpQc::new(&bed, DEFAULT_NUM_SNPS_PER_CHUNK);
+        println!("{}", qc.summarize(filter));
+        partitions = qc.filter_partitions(partitions, filter);
+        if partitions.is_empty() {
+            eprintln!("no SNP is left in any component after the QC");
+            std::process::exit(1);
+        }
+    }
+    let mut components: Vec<(String, OrderedIntegerSet<usize>)> =
+        partitions.into_iter().collect();
+    components.sort_by(|a, b| a.0.cmp(&b.0));
+    let fam_path = get_bed_bim_fam_path(&plink_filename_prefixes[0]).2;
+
+    for (i, pheno_path) in pheno_path_list.iter().enumerate() {
+        println!(
+            "\n=> [{}/{}] estimating the REML variance components for {}",
+            i + 1,
+            pheno_path_list.len(),
+            pheno_path
+        );
+        let pheno = get_fam_aligned_pheno_arr(&fam_path, pheno_path)
+            .unwrap_or_exit(None::<String>);
+        let est = estimate_heritability_reml(
+            &bed,
+            components.clone(),
+            &pheno,
+            &config,
+        )
+        .unwrap_or_exit(None::<String>);
+        print_reml_estimates(&est);
+    }
+}
+
+fn print_reml_estimates(est: &RemlEstimates) {
+    for (k, iteration) in est.iterations.iter().enumerate() {
+        println!(
+            "iteration {}: variances {:?}, max relative change {:.3e}, \
+            {} CG iterations, CG relative residual {:.3e}",
+            k + 1,
+            iteration.variances,
+            iteration.max_relative_change,
+            iteration.cg.num_iterations,
+            iteration.cg.max_relative_residual
+        );
+    }
+    println!("{}", est);
+}
@@ -3,25 +3,76 @@ use biofile::{
     plink_bim::PlinkBim,
 };
 use clap::{clap_app, Arg};
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
 use program_flow::{
     argparse::{
-        extract_optional_str_arg, extract_str_arg, extract_str_vec_arg,
+        extract_optional_numeric_arg, extract_optional_str_arg,
+        extract_optional_str_vec_arg, extract_str_arg, extract_str_vec_arg,
     },
     OrExit,
 };
+use rand::thread_rng;
 
 use saber::{
+    covariate::CovariateProjection,
+    grm::{get_gcta_grm_paths, Grm},
+    gxg_pair_exclusion::{
+        get_bim_snp_positions, GxgPairExclusion, GxgPairExclusionCriteria,
+        SnpPosition,
+    },
     heritability_estimator::{
         estimate_g_and_multi_gxg_heritability,
+        estimate_g_and_multi_gxg_heritability_from_beds,
         estimate_g_and_multi_gxg_heritability_from_saved_traces,
     },
+    integer_set::{chromosomal::ChromosomalIntegerSet, iter::Iter},
+    progress::StdoutProgress,
+    snp_overlap::SnpOverlap,
+    snp_weights::SnpWeights,
     util::{
-        get_bed_bim_fam_path, get_pheno_arr, load_trace_estimates,
-        write_trace_estimates,
+        check_num_people, get_bed_bim_fam_path, get_fam_aligned_pheno_arr,
+        get_fid_iid_list,
+        matrix_util::{ProbeType, DEFAULT_ALPHA},
+        trace_metadata::{
+            get_file_checksum, ProbeShard, Standardization, TraceEstimates,
+            TraceMetadata,
+        },
     },
 };
 
+/// The weights of the SNPs in the file at `weights_path` if any, times the
+/// alpha model weights if the `standardization` is not to unit variance
+fn get_snp_weights(
+    bed: &PlinkBed,
+    bim_path: &str,
+    weights_path: Option<&String>,
+    standardization: Standardization,
+) -> Option<SnpWeights> {
+    let snp_weights = weights_path.map(|path| {
+        SnpWeights::from_file(path, &[bim_path.to_string()])
+            .unwrap_or_exit(None::<String>)
+    });
+    match standardization {
+        Standardization::UnitVariance => snp_weights,
+        Standardization::Alpha(alpha) => {
+            println!("=> computing the allele frequencies of {}", bim_path);
+            let alpha_weights = SnpWeights::from_alpha(
+                alpha,
+                &bed.get_minor_allele_frequencies(None),
+            )
+            .unwrap_or_exit(None::<String>);
+            Some(match snp_weights {
+                Some(w) => {
+                    w.product(&alpha_weights).unwrap_or_exit(None::<String>)
+                }
+                None => alpha_weights,
+            })
+        }
+    }
+}
+
 fn main() {
+    env_logger::init();
     let mut app = clap_app!(estimate_multi_gxg_heritability =>
         (version: "0.1")
         (author: "Aaron Zhou")
@@ -66,6 +117,17 @@ fn main() {
                     Recommends at least 100 for small datasets, and 10 for huge datasets"
                 )
         )
+        .arg(
+            Arg::with_name("probe_type")
+                .long("probe-type").takes_value(true).default_value("rademacher")
+                .possible_values(&["rademacher", "gaussian", "srht"])
+                .help(
+                    "The distribution of the random vectors of the G sketch and of the\n\
+                    Hutch++ estimates of the GxG traces: independent +-1 entries, independent\n\
+                    standard normal entries, or the columns of a subsampled randomized\n\
+                    Hadamard transform"
+                )
+        )
         .arg(
             Arg::with_name("trace_outpath")
                 .long("save-trace").takes_value(true)
@@ -79,6 +141,109 @@ fn main() {
                     "Use the previously saved trace estimates\n\
                     instead of estimating them from scratch"
                 )
+        )
+        .arg(
+            Arg::with_name("shard")
+                .long("shard").takes_value(true).requires("trace_outpath")
+                .conflicts_with_all(&["load_trace", "stream_le"])
+                .help(
+                    "Estimate the traces on only the shard INDEX of NUM_SHARDS shards of the\n\
+                    random vectors, given as INDEX/NUM_SHARDS with INDEX in 0..NUM_SHARDS, e.g.\n\
+                    the task of a cluster array job. The partial traces saved with --save-trace\n\
+                    by all the shards are combined by the merge_partial_traces binary"
+                )
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed").takes_value(true)
+                .help(
+                    "Seed the random probe vectors and sketches, so that the estimates are\n\
+                    reproducible regardless of the number of threads. The shards of a seeded run\n\
+                    draw independent probes"
+                )
+        )
+        .arg(
+            Arg::with_name("gxg_exclude_within_bp")
+                .long("gxg-exclude-within-bp").takes_value(true)
+                .help(
+                    "Exclude from the GxG kernels the pairs of LE SNPs on the same chromosome\n\
+                    that are at most this many base pairs apart"
+                )
+        )
+        .arg(
+            Arg::with_name("gxg_exclude_r_sq")
+                .long("gxg-exclude-r-sq").takes_value(true)
+                .help(
+                    "Exclude from the GxG kernels the pairs of LE SNPs\n\
+                    whose squared correlation is at least this threshold"
+                )
+        )
+        .arg(
+            Arg::with_name("gxg_max_pairs")
+                .long("gxg-max-pairs").takes_value(true)
+                .help(
+                    "Cap the number of SNP pairs in each GxG kernel by keeping only this many\n\
+                    pairs sampled uniformly at random from the pairs that are not excluded"
+                )
+        )
+        .arg(
+            Arg::with_name("stream_le")
+                .long("stream-le")
+                .conflicts_with_all(&[
+                    "load_trace", "gxg_exclude_within_bp", "gxg_exclude_r_sq", "gxg_max_pairs"
+                ])
+                .help(
+                    "Stream the LE SNPs from the bed file in chunks instead of loading them\n\
+                    into memory, for biobank-scale data. All the pairs of LE SNPs are in the\n\
+                    GxG kernels, and the traces are re-estimated for every phenotype"
+                )
+        )
+        .arg(
+            Arg::with_name("snp_weights_path")
+                .long("snp-weights").takes_value(true).requires("stream_le")
+                .help(
+                    "A file with a SNP_ID WEIGHT pair on every line, e.g. the weights.short\n\
+                    file of LDAK, to weight the SNPs in the G kernel. Requires --stream-le"
+                )
+        )
+        .arg(
+            Arg::with_name("le_snp_weights_path")
+                .long("le-snp-weights").takes_value(true).requires("stream_le")
+                .help(
+                    "A file of SNP_ID WEIGHT pairs to weight the LE SNPs in the GxG kernels,\n\
+                    where a pair is weighted by the product of the weights of its SNPs.\n\
+                    Requires --stream-le"
+                )
+        )
+        .arg(
+            Arg::with_name("alpha")
+                .long("alpha").takes_value(true).allow_hyphen_values(true).requires("stream_le")
+                .help(
+                    "Scale the centered genotypes and LE SNPs by [2p(1-p)]^(alpha/2) for the\n\
+                    allele frequency p instead of to unit variance, e.g. -0.25 as in LDAK.\n\
+                    Requires --stream-le"
+                )
+        )
+        .arg(
+            Arg::with_name("grm_prefix")
+                .long("grm").takes_value(true).multiple(true).number_of_values(1)
+                .requires("stream_le")
+                .help(
+                    "The prefix of a precomputed GRM in the binary format of GCTA, e.g. of a\n\
+                    functional category, to fit as a further component next to G and GxG.\n\
+                    The <grm_prefix>.grm.id file has to list the people of the fam file in\n\
+                    the same order. Requires --stream-le"
+                )
+        )
+        .arg(
+            Arg::with_name("covariate_path")
+                .long("covariate").short("c").takes_value(true)
+                .help(
+                    "A plink-style covariate file with the header line\n\
+                    FID IID COV_1 COV_2 ...\n\
+                    The covariates and an intercept are projected out of the phenotypes\n\
+                    and the kernels before the variance components are estimated"
+                )
         );
     let matches = app.get_matches();
 
@@ -88,6 +253,19 @@ fn main() {
         extract_str_arg(&matches, "le_snps_filename_prefix");
     let trace_outpath = extract_optional_str_arg(&matches, "trace_outpath");
     let load_trace = extract_optional_str_arg(&matches, "load_trace");
+    let covariate_path = extract_optional_str_arg(&matches, "covariate_path");
+    let stream_le = matches.is_present("stream_le");
+    let snp_weights_path =
+        extract_optional_str_arg(&matches, "snp_weights_path");
+    let le_snp_weights_path =
+        extract_optional_str_arg(&matches, "le_snp_weights_path");
+    let grm_prefixes = extract_optional_str_vec_arg(&matches, "grm_prefix")
+        .unwrap_or_default();
+    let standardization = Standardization::from_alpha(
+        extract_optional_numeric_arg::<f64>(&matches, "alpha")
+            .unwrap_or_exit(Some("failed to extract alpha"))
+            .unwrap_or(DEFAULT_ALPHA),
+    );
     let pheno_path_vec = extract_str_vec_arg(&matches, "pheno_path")
         .unwrap_or_exit(None::<String>);
 
@@ -99,6 +277,41 @@ fn main() {
     let num_random_vecs = extract_str_arg(&matches, "num_random_vecs")
         .parse::<usize>()
         .unwrap_or_exit(Some("failed to parse num_random_vecs"));
+    let shard = extract_optional_str_arg(&matches, "shard").map(|s| {
+        s.parse::<ProbeShard>()
+            .unwrap_or_exit(Some("failed to parse the shard"))
+    });
+    let seed = extract_optional_numeric_arg::<u64>(&matches, "seed")
+        .unwrap_or_exit(Some("failed to extract the seed"));
+    // a shard estimates the traces on its share of the random vectors only,
+    // which are drawn from the seed of the shard
+    let (num_random_vecs, probe_seed) = match shard {
+        Some(shard) => {
+            let num_shard_random_vecs =
+                shard.get_num_random_vecs(num_random_vecs);
+            if num_shard_random_vecs == 0 {
+                eprintln!(
+                    "the shard {} has none of the {} random vectors",
+                    shard, num_random_vecs
+                );
+                std::process::exit(1);
+            }
+            (num_shard_random_vecs, shard.get_seed(seed, num_random_vecs))
+        }
+        None => (num_random_vecs, seed),
+    };
+    let probe_type = extract_str_arg(&matches, "probe_type")
+        .parse::<ProbeType>()
+        .unwrap_or_exit(None::<String>);
+    let gxg_pair_exclusion_criteria = GxgPairExclusionCriteria::new(
+        extract_optional_numeric_arg::<u64>(&matches, "gxg_exclude_within_bp")
+            .unwrap_or_exit(Some("failed to extract gxg_exclude_within_bp")),
+        extract_optional_numeric_arg::<f64>(&matches, "gxg_exclude_r_sq")
+            .unwrap_or_exit(Some("failed to extract gxg_exclude_r_sq")),
+    );
+    let gxg_max_pairs =
+        extract_optional_numeric_arg::<usize>(&matches, "gxg_max_pairs")
+            .unwrap_or_exit(Some("failed to extract gxg_max_pairs"));
 
     println!(
         "PLINK bed path: {}\nPLINK bim path: {}\nPLINK fam path: {}",
@@ -115,13 +328,39 @@ fn main() {
         println!("[{}/{}] {}", i + 1, pheno_path_vec.len(), path);
     }
     println!("num_random_vecs: {}", num_random_vecs);
+    if let Some(shard) = shard {
+        println!(
+            "probe shard: {}, the estimates below are from the partial traces \
+            of the shard",
+            shard
+        );
+    }
+    println!("probe_type: {}", probe_type);
+    println!("GxG pair exclusion: {:?}", gxg_pair_exclusion_criteria);
+    println!("GxG max pairs: {:?}", gxg_max_pairs);
+    println!("stream LE SNPs: {}", stream_le);
+    println!("SNP weights: {:?}", snp_weights_path);
+    println!("LE SNP weights: {:?}", le_snp_weights_path);
+    println!("precomputed GRMs: {:?}", grm_prefixes);
+    println!("standardization: {}", standardization);
+
+    // the saved traces are only reused for the same genotypes
+    let bed_checksum = if trace_outpath.is_some() || load_trace.is_some() {
+        println!("\n=> computing the checksum of the bed files");
+        Some(
+            get_file_checksum(&[bed_path.clone(), le_snps_bed_path.clone()])
+                .unwrap_or_exit(None::<String>),
+        )
+    } else {
+        None
+    };
 
     println!("\n=> generating the phenotype array and the genotype matrix");
 
     let mut geno_bed = PlinkBed::new(&vec![(
         bed_path,
-        bim_path,
-        fam_path,
+        bim_path.clone(),
+        fam_path.clone(),
         PlinkSnpType::Additive,
     )])
     .unwrap_or_exit(None::<String>);
@@ -129,36 +368,258 @@ fn main() {
     let le_snps_bed = PlinkBed::new(&vec![(
         le_snps_bed_path,
         le_snps_bim_path.clone(),
-        le_snps_fam_path,
+        le_snps_fam_path.clone(),
         PlinkSnpType::Additive,
     )])
     .unwrap_or_exit(None::<String>);
+    check_num_people(
+        le_snps_bed.num_people,
+        &le_snps_fam_path,
+        geno_bed.num_people,
+        &fam_path,
+    )
+    .unwrap_or_exit(None::<String>);
     let mut le_snps_bim =
         PlinkBim::new(vec![le_snps_bim_path.clone()]).unwrap_or_exit(Some(
             format!("failed to create PlinkBim for {}", le_snps_bim_path),
         ));
-    let le_snps_partition = le_snps_bim
-        .get_chrom_to_fileline_positions()
-        .unwrap_or_exit(Some(format!(
-            "failed to get chrom partitions from {}",
-            le_snps_bim_path
-        )));
-    let le_snps_partition_keys = {
-        let mut keys: Vec<String> =
-            le_snps_partition.keys().map(|s| s.to_string()).collect();
-        keys.sort();
-        keys
-    };
+    let le_snps_partition = ChromosomalIntegerSet::from(
+        le_snps_bim
+            .get_chrom_to_fileline_positions()
+            .unwrap_or_exit(Some(format!(
+                "failed to get chrom partitions from {}",
+                le_snps_bim_path
+            ))),
+    );
+    let le_snps_partition_keys: Vec<String> =
+        le_snps_partition.chroms().cloned().collect();
     let mut le_snps_arr_vec = Vec::new();
-    for key in le_snps_partition_keys.iter() {
-        let range = &le_snps_partition[key];
-        le_snps_arr_vec.push(
-            le_snps_bed
-                .get_genotype_matrix(Some(range.clone()))
-                .unwrap(),
+    if !stream_le {
+        for (_, range) in le_snps_partition.iter() {
+            le_snps_arr_vec.push(
+                le_snps_bed
+                    .get_genotype_matrix(Some(range.clone()))
+                    .unwrap(),
+            );
+        }
+    }
+    let le_snps_positions: Option<Vec<SnpPosition>> =
+        if gxg_pair_exclusion_criteria.is_empty() {
+            None
+        } else {
+            Some(
+                get_bim_snp_positions(&le_snps_bim_path).unwrap_or_exit(Some(
+                    format!(
+                        "failed to read the SNP positions from {}",
+                        le_snps_bim_path
+                    ),
+                )),
+            )
+        };
+    let gxg_pair_exclusions: Vec<GxgPairExclusion> = le_snps_partition_keys
+        .iter()
+        .zip(le_snps_arr_vec.iter())
+        .map(|(key, arr)| {
+            let exclusion = match &le_snps_positions {
+                None => GxgPairExclusion::none(arr.dim().1),
+                Some(positions) => {
+                    let component_positions: Vec<SnpPosition> =
+                        le_snps_partition[key.as_str()]
+                            .iter()
+                            .map(|i| positions[i].clone())
+                            .collect();
+                    GxgPairExclusion::new(
+                        &gxg_pair_exclusion_criteria,
+                        arr,
+                        Some(component_positions.as_slice()),
+                    )
+                    .unwrap_or_exit(Some(format!(
+                        "failed to exclude the GxG pairs of component {}",
+                        key
+                    )))
+                }
+            };
+            match gxg_max_pairs {
+                Some(max_pairs) => {
+                    exclusion.cap_num_pairs(max_pairs, &mut thread_rng())
+                }
+                None => exclusion,
+            }
+        })
+        .collect();
+    for (key, exclusion) in le_snps_partition_keys
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+    {
+        println!(
+            "GxG component {}: {} pairs in the kernel{}",
+            key,
+            exclusion.num_included_pairs(),
+            if exclusion.is_capped() {
+                format!(
+                    ", sampled from {} pairs",
+                    exclusion.num_uncapped_pairs()
+                )
+            } else {
+                String::new()
+            }
         );
     }
-    let num_gxg_components = le_snps_arr_vec.len();
+
+    if let Some(warning) =
+        SnpOverlap::from_bim_files(&bim_path, &le_snps_bim_path)
+            .unwrap_or_exit(None::<String>)
+            .get_warning(false)
+    {
+        println!("WARNING {}", warning);
+    }
+
+    let covariates = covariate_path.as_ref().map(|path| {
+        CovariateProjection::from_file(path).unwrap_or_exit(None::<String>)
+    });
+
+    let trace_metadata = {
+        let mut component_labels = vec!["G".to_string()];
+        let mut snp_sets = vec![(
+            "G".to_string(),
+            OrderedIntegerSet::from_slice(&[[
+                0,
+                geno_bed.total_num_snps() - 1,
+            ]]),
+        )];
+        for key in le_snps_partition_keys.iter() {
+            let label = format!("GxG {}", key);
+            component_labels.push(label.clone());
+            snp_sets.push((label, le_snps_partition[key.as_str()].clone()));
+        }
+        for prefix in grm_prefixes.iter() {
+            component_labels.push(format!("GRM {}", prefix));
+        }
+        component_labels.push(match &covariates {
+            Some(covariates) => format!(
+                "noise adjusted for {} covariates",
+                covariates.num_covariates()
+            ),
+            None => "noise".to_string(),
+        });
+        let metadata = TraceMetadata::new(
+            component_labels,
+            snp_sets,
+            num_random_vecs,
+            standardization,
+        );
+        let metadata = match bed_checksum {
+            Some(checksum) => metadata.with_bed_checksum(checksum),
+            None => metadata,
+        };
+        let metadata = match seed {
+            Some(seed) => metadata.with_seed(seed),
+            None => metadata,
+        };
+        match shard {
+            Some(shard) => metadata.with_shard(shard),
+            None => metadata,
+        }
+    };
+
+    if stream_le {
+        let snp_weights = get_snp_weights(
+            &geno_bed,
+            &bim_path,
+            snp_weights_path.as_ref(),
+            standardization,
+        );
+        let le_snp_weights = get_snp_weights(
+            &le_snps_bed,
+            &le_snps_bim_path,
+            le_snp_weights_path.as_ref(),
+            standardization,
+        );
+        let le_snps_ranges: Vec<OrderedIntegerSet<usize>> = le_snps_partition
+            .iter()
+            .map(|(_, range)| range.clone())
+            .collect();
+        let fid_iid_list = get_fid_iid_list(&fam_path)
+            .unwrap_or_exit(Some(format!("failed to read {}", fam_path)));
+        let grms: Vec<(String, Grm)> = grm_prefixes
+            .iter()
+            .map(|prefix| {
+                let id_path = get_gcta_grm_paths(prefix).2;
+                if get_fid_iid_list(&id_path)
+                    .unwrap_or_exit(Some(format!("failed to read {}", id_path)))
+                    != fid_iid_list
+                {
+                    eprintln!(
+                        "{} does not list the people of {} in the same order",
+                        id_path, fam_path
+                    );
+                    std::process::exit(1);
+                }
+                println!("=> loading the GRM {}", prefix);
+                (
+                    prefix.to_string(),
+                    Grm::from_gcta_files(prefix).unwrap_or_exit(None::<String>),
+                )
+            })
+            .collect();
+        for (pheno_index, pheno_path) in pheno_path_vec.iter().enumerate() {
+            println!(
+                "\n=> [{}/{}] estimating the heritability for the phenotype at {}",
+                pheno_index + 1,
+                pheno_path_vec.len(),
+                pheno_path
+            );
+            let pheno_arr = get_fam_aligned_pheno_arr(&fam_path, pheno_path)
+                .unwrap_or_exit(None::<String>);
+            match estimate_g_and_multi_gxg_heritability_from_beds(
+                &geno_bed,
+                &le_snps_bed,
+                &le_snps_ranges,
+                snp_weights.as_ref(),
+                le_snp_weights.as_ref(),
+                &grms,
+                pheno_arr,
+                num_random_vecs,
+                covariates.as_ref(),
+                probe_seed,
+                &StdoutProgress,
+            ) {
+                Ok(result) => {
+                    println!(
+                        "\nvariance estimates on the normalized phenotype at {}:\n{}",
+                        pheno_path, result
+                    );
+                    for (i, key) in le_snps_partition_keys.iter().enumerate() {
+                        println!(
+                            "{}: {} with {} LE SNPs",
+                            result.component_names[i + 1],
+                            key,
+                            le_snps_ranges[i].size()
+                        );
+                    }
+                    if pheno_index == 0 {
+                        if let Some(outpath) = &trace_outpath {
+                            println!(
+                                "\n=> writing the trace estimates to {}",
+                                outpath
+                            );
+                            TraceEstimates::new(
+                                result.normal_eq_a.clone(),
+                                trace_metadata.clone(),
+                            )
+                            .and_then(|estimates| estimates.write(outpath))
+                            .unwrap_or_exit(None::<String>);
+                        }
+                    }
+                }
+                Err(why) => {
+                    eprintln!("{}", why);
+                    return ();
+                }
+            }
+        }
+        return ();
+    }
 
     let mut saved_traces_in_memory = None;
     for (pheno_index, pheno_path) in pheno_path_vec.iter().enumerate() {
@@ -168,64 +629,78 @@ fn main() {
             pheno_path_vec.len(),
             pheno_path
         );
-        let pheno_arr =
-            get_pheno_arr(pheno_path).unwrap_or_exit(None::<String>);
+        let pheno_arr = get_fam_aligned_pheno_arr(&fam_path, pheno_path)
+            .unwrap_or_exit(None::<String>);
 
         let heritability_estimate_result = match saved_traces_in_memory {
             Some(saved_traces) => {
                 estimate_g_and_multi_gxg_heritability_from_saved_traces(
                     &mut geno_bed,
                     le_snps_arr_vec,
+                    &gxg_pair_exclusions,
                     pheno_arr,
                     num_random_vecs,
                     saved_traces,
+                    covariates.as_ref(),
+                    probe_seed,
+                    &StdoutProgress,
                 )
             }
             None => match &load_trace {
                 None => estimate_g_and_multi_gxg_heritability(
                     &mut geno_bed,
                     le_snps_arr_vec,
+                    &gxg_pair_exclusions,
                     pheno_arr,
                     num_random_vecs,
+                    probe_type,
+                    covariates.as_ref(),
+                    probe_seed,
+                    &StdoutProgress,
                 ),
                 Some(load_path) => {
-                    let trace_estimates = load_trace_estimates(load_path)
-                        .unwrap_or_exit(Some(format!(
-                            "failed to load the trace estimates from {}",
-                            load_path
-                        )));
-                    let expected_dim =
-                        (num_gxg_components + 2, num_gxg_components + 2);
-                    assert_eq!(trace_estimates.dim(), expected_dim,
-                                   "the loaded trace has dim: {:?} which does not match the expected dimension of {:?}",
-                                   trace_estimates.dim(), expected_dim);
+                    let trace_estimates = TraceEstimates::load_for_run(
+                        load_path,
+                        &trace_metadata,
+                    )
+                    .unwrap_or_exit(Some(format!(
+                        "cannot reuse the trace estimates from {}",
+                        load_path
+                    )))
+                    .traces;
                     estimate_g_and_multi_gxg_heritability_from_saved_traces(
                         &mut geno_bed,
                         le_snps_arr_vec,
+                        &gxg_pair_exclusions,
                         pheno_arr,
                         num_random_vecs,
                         trace_estimates,
+                        covariates.as_ref(),
+                        probe_seed,
+                        &StdoutProgress,
                     )
                 }
             },
         };
 
         match heritability_estimate_result {
-            Ok((a, _b, h, normalized_le_snps_arr, _)) => {
-                println!("\nvariance estimates on the normalized phenotype at {}:\nG variance: {}", pheno_path, h[0]);
-                let mut gxg_var_sum = 0.;
-                for (i, key) in
-                    (1..=num_gxg_components).zip(le_snps_partition_keys.iter())
-                {
-                    println!("GxG component {}: {} variance: {}", i, key, h[i]);
-                    gxg_var_sum += h[i];
+            Ok(result) => {
+                println!(
+                    "\nvariance estimates on the normalized phenotype at {}:\n{}",
+                    pheno_path, result
+                );
+                for (i, key) in le_snps_partition_keys.iter().enumerate() {
+                    println!(
+                        "{}: {} with {} pairs",
+                        result.component_names[i + 1],
+                        key,
+                        gxg_pair_exclusions[i].num_included_pairs()
+                    );
                 }
-                println!("noise variance: {}", h[num_gxg_components + 1]);
-                println!("total GxG variance: {}", gxg_var_sum);
 
                 // reassign for the remaining phenotypes' heritability
                 // estimation
-                le_snps_arr_vec = normalized_le_snps_arr;
+                le_snps_arr_vec = result.normalized_le_snps_arr;
 
                 // only write the trace out to a file once
                 if pheno_index == 0 {
@@ -234,14 +709,18 @@ fn main() {
                             "\n=> writing the trace estimates to {}",
                             outpath
                         );
-                        write_trace_estimates(&a, outpath)
-                            .unwrap_or_exit(None::<String>);
+                        TraceEstimates::new(
+                            result.normal_eq_a.clone(),
+                            trace_metadata.clone(),
+                        )
+                        .and_then(|estimates| estimates.write(outpath))
+                        .unwrap_or_exit(None::<String>);
                     }
                 }
 
                 // save the trace to a temporary file for the remaining
                 // phenotypes' heritability estimation
-                saved_traces_in_memory = Some(a);
+                saved_traces_in_memory = Some(result.normal_eq_a);
             }
             Err(why) => {
                 eprintln!("{}", why);
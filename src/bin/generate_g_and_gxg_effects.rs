@@ -5,18 +5,23 @@ use std::{
 
 use biofile::plink_bed::{PlinkBed, PlinkSnpType};
 use clap::clap_app;
-use ndarray::s;
+use ndarray::{s, Array, Ix1};
 use program_flow::{
     argparse::{
-        extract_optional_numeric_arg, extract_optional_str_arg, extract_str_arg,
+        extract_boolean_flag, extract_optional_numeric_arg,
+        extract_optional_str_arg, extract_str_arg,
     },
     OrExit,
 };
 
 use saber::{
+    gxg_pair_exclusion::{
+        get_bim_snp_positions, GxgPairExclusion, GxgPairExclusionCriteria,
+    },
     simulation::sim_pheno::{
         generate_g_contribution, generate_gxg_contribution_from_gxg_basis,
-        get_sim_output_path, write_effects_to_file, SimEffectMechanism,
+        get_sim_output_path, write_effects_to_file, write_wide_effects_to_file,
+        EffectsDelimiter, EffectsFileFormat, SimEffectMechanism,
     },
     util::{get_bed_bim_fam_path, get_fid_iid_list},
 };
@@ -57,7 +62,14 @@ fn main() {
         (@arg gxg_component_count_filename: --counts -c [COUNTS]
         "If provided, will generate GxG effects; this is a file where each line is the number of LE SNPs for the corresponding GxG component, a whitespace, and the variance due to that component")
         (@arg g_var: --g [G_VAR] "G variance; If provided, will generate G effects")
+        (@arg gxg_exclude_within_bp: --("gxg-exclude-within-bp") [BP] "If provided, pairs of LE SNPs on the same chromosome at most BP base pairs apart have no GxG effect")
+        (@arg gxg_exclude_r_sq: --("gxg-exclude-r-sq") [R_SQ] "If provided, pairs of LE SNPs with squared correlation at least R_SQ have no GxG effect")
         (@arg out_path_prefix: --out -o <OUT> "required; output file path prefix; output will be named OUT.gxg0.effects etc.")
+        (@arg delimiter: --delimiter [DELIMITER] possible_values(&["space", "tab"]) default_value("space") "the delimiter between the columns of the effects files")
+        (@arg no_header: --("no-header") "omit the header line in the effects files")
+        (@arg named_columns: --("named-columns") "name the effects column in the header after the component, e.g. G or GxG1, instead of pheno")
+        (@arg sci_precision: --("sci-precision") [PRECISION] "write the effects in scientific notation with PRECISION decimals")
+        (@arg wide: --wide "instead of one file per component, write all the components to OUT.effects with one column per component")
     );
     let matches = app.get_matches();
 
@@ -68,6 +80,24 @@ fn main() {
     let out_path_prefix = extract_str_arg(&matches, "out_path_prefix");
     println!("out_path_prefix: {}", out_path_prefix);
 
+    let effects_format = EffectsFileFormat {
+        delimiter: EffectsDelimiter::from_str(&extract_str_arg(
+            &matches,
+            "delimiter",
+        ))
+        .unwrap_or_exit(None::<String>),
+        include_header: !extract_boolean_flag(&matches, "no_header"),
+        named_columns: extract_boolean_flag(&matches, "named_columns"),
+        scientific_precision: extract_optional_numeric_arg::<usize>(
+            &matches,
+            "sci_precision",
+        )
+        .unwrap_or_exit(Some("failed to extract sci_precision")),
+    };
+    let wide = extract_boolean_flag(&matches, "wide");
+    let mut wide_columns: Vec<(String, Array<f32, Ix1>)> = Vec::new();
+    let mut wide_fid_iid_list = None;
+
     if let Some(g_var) = g_var {
         if g_var > 0. {
             println!("\n=> generating G effects");
@@ -97,12 +127,23 @@ fn main() {
             let out_path =
                 get_sim_output_path(&out_path_prefix, SimEffectMechanism::G);
             let effects = generate_g_contribution(geno_arr, g_var);
-            println!("\n=> writing the effects due to G to {}", out_path);
-            write_effects_to_file(&effects, &fid_iid_list, &out_path)
+            if wide {
+                wide_columns.push(("G".to_string(), effects));
+                wide_fid_iid_list = Some(fid_iid_list);
+            } else {
+                println!("\n=> writing the effects due to G to {}", out_path);
+                write_effects_to_file(
+                    &effects,
+                    &fid_iid_list,
+                    &out_path,
+                    "G",
+                    &effects_format,
+                )
                 .unwrap_or_exit(Some(format!(
                     "failed to write the simulated effects to file: {}",
                     out_path
                 )));
+            }
         }
     }
 
@@ -126,7 +167,7 @@ fn main() {
             get_fid_iid_list(&le_snps_fam_path).unwrap_or_exit(None::<String>);
         let le_snps_bed = PlinkBed::new(&vec![(
             le_snps_bed_path,
-            le_snps_bim_path,
+            le_snps_bim_path.clone(),
             le_snps_fam_path,
             PlinkSnpType::Additive,
         )])
@@ -142,6 +183,28 @@ fn main() {
             ));
         let num_gxg_components = counts_and_effect_sizes.len();
 
+        let gxg_pair_exclusion_criteria = GxgPairExclusionCriteria::new(
+            extract_optional_numeric_arg::<u64>(
+                &matches,
+                "gxg_exclude_within_bp",
+            )
+            .unwrap_or_exit(Some("failed to extract gxg_exclude_within_bp")),
+            extract_optional_numeric_arg::<f64>(&matches, "gxg_exclude_r_sq")
+                .unwrap_or_exit(Some("failed to extract gxg_exclude_r_sq")),
+        );
+        println!("GxG pair exclusion: {:?}", gxg_pair_exclusion_criteria);
+        let le_snps_positions =
+            if gxg_pair_exclusion_criteria.is_empty() {
+                None
+            } else {
+                Some(get_bim_snp_positions(&le_snps_bim_path).unwrap_or_exit(
+                    Some(format!(
+                        "failed to read the SNP positions from {}",
+                        le_snps_bim_path
+                    )),
+                ))
+            };
+
         let mut acc = 0usize;
         for (i, (c, effect_size)) in
             counts_and_effect_sizes.into_iter().enumerate()
@@ -157,21 +220,73 @@ fn main() {
                 SimEffectMechanism::GxG(i + 1),
             );
             let gxg_basis = le_snps_arr.slice(s![.., acc..acc + c]).to_owned();
+            let gxg_pair_exclusion = match &le_snps_positions {
+                None => GxgPairExclusion::none(c),
+                Some(positions) => GxgPairExclusion::new(
+                    &gxg_pair_exclusion_criteria,
+                    &gxg_basis,
+                    Some(&positions[acc..acc + c]),
+                )
+                .unwrap_or_exit(Some(format!(
+                    "failed to exclude the GxG pairs of component {}",
+                    i + 1
+                ))),
+            };
+            println!(
+                "excluding {} of the GxG pairs",
+                gxg_pair_exclusion.num_excluded_pairs()
+            );
             let effects = generate_gxg_contribution_from_gxg_basis(
                 gxg_basis,
                 effect_size,
+                &gxg_pair_exclusion,
             );
+            acc += c;
+            if wide {
+                wide_columns.push((format!("GxG{}", i + 1), effects));
+                continue;
+            }
             println!(
                 "\n=> writing the effects due to GxG component {} to {}",
                 i + 1,
                 out_path
             );
-            write_effects_to_file(&effects, &fid_iid_list, &out_path)
-                .unwrap_or_exit(Some(format!(
-                    "failed to write the simulated effects to file: {}",
-                    out_path
-                )));
-            acc += c;
+            write_effects_to_file(
+                &effects,
+                &fid_iid_list,
+                &out_path,
+                &format!("GxG{}", i + 1),
+                &effects_format,
+            )
+            .unwrap_or_exit(Some(format!(
+                "failed to write the simulated effects to file: {}",
+                out_path
+            )));
         }
+        if wide_fid_iid_list.is_none() {
+            wide_fid_iid_list = Some(fid_iid_list);
+        }
+    }
+
+    if let (true, Some(fid_iid_list)) = (wide, wide_fid_iid_list) {
+        let out_path = format!("{}.effects", out_path_prefix);
+        println!(
+            "\n=> writing the effects of all the components to {}",
+            out_path
+        );
+        let columns: Vec<(&str, &Array<f32, Ix1>)> = wide_columns
+            .iter()
+            .map(|(name, effects)| (name.as_str(), effects))
+            .collect();
+        write_wide_effects_to_file(
+            &columns,
+            &fid_iid_list,
+            &out_path,
+            &effects_format,
+        )
+        .unwrap_or_exit(Some(format!(
+            "failed to write the simulated effects to file: {}",
+            out_path
+        )));
     }
 }
@@ -19,6 +19,7 @@ use program_flow::{
 use saber::{
     simulation::sim_pheno::{
         generate_g_contribution_from_bed_bim, write_effects_to_file,
+        write_wide_effects_to_file, EffectsDelimiter, EffectsFileFormat,
     },
     util::{
         get_bed_bim_from_prefix_and_partition, get_fid_iid_list,
@@ -119,6 +120,43 @@ fn main() {
                 .long("chunk-size")
                 .takes_value(true)
                 .default_value("100")
+        )
+        .arg(
+            Arg::with_name("delimiter")
+                .long("delimiter")
+                .takes_value(true)
+                .possible_values(&["space", "tab"])
+                .default_value("space")
+                .help("The delimiter between the columns of the effects files")
+        )
+        .arg(
+            Arg::with_name("no_header")
+                .long("no-header")
+                .help("Omit the header line in the effects files")
+        )
+        .arg(
+            Arg::with_name("named_columns")
+                .long("named-columns")
+                .help(
+                    "Name the effects column in the header after the replicate, e.g.\n\
+                     x_rep1 for the first replicate of the variance file x, instead of pheno"
+                )
+        )
+        .arg(
+            Arg::with_name("sci_precision")
+                .long("sci-precision")
+                .takes_value(true)
+                .value_name("PRECISION")
+                .help("Write the effects in scientific notation with PRECISION decimals")
+        )
+        .arg(
+            Arg::with_name("wide_out_path")
+                .long("wide-out")
+                .takes_value(true)
+                .help(
+                    "Instead of one file per replicate, write all the replicates to\n\
+                     <wide_out_path> with one column per replicate"
+                )
         );
     let matches = app.get_matches();
 
@@ -148,6 +186,21 @@ fn main() {
     let out_dir = extract_str_arg(&matches, "out_dir");
     let chunk_size = extract_numeric_arg::<usize>(&matches, "chunk_size")
         .unwrap_or_exit(Some(format!("failed to extract chunk_size")));
+    let effects_format = EffectsFileFormat {
+        delimiter: EffectsDelimiter::from_str(&extract_str_arg(
+            &matches,
+            "delimiter",
+        ))
+        .unwrap_or_exit(None::<String>),
+        include_header: !extract_boolean_flag(&matches, "no_header"),
+        named_columns: extract_boolean_flag(&matches, "named_columns"),
+        scientific_precision: extract_optional_numeric_arg::<usize>(
+            &matches,
+            "sci_precision",
+        )
+        .unwrap_or_exit(Some(format!("failed to extract sci_precision"))),
+    };
+    let wide_out_path = extract_optional_str_arg(&matches, "wide_out_path");
 
     println!(
         "partition_filepath: {}\n\
@@ -297,6 +350,17 @@ fn main() {
             .unwrap_or_exit(None::<String>);
 
     assert_eq!(effects.dim().1, num_out_paths);
+    let column_names: Vec<String> = out_paths
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(path)
+                .to_string()
+        })
+        .collect();
+    let mut wide_columns = Vec::new();
     for (i, y) in effects.gencolumns().into_iter().enumerate() {
         let pheno_output = match binary_ratio {
             None => y.to_owned(),
@@ -316,13 +380,41 @@ fn main() {
                 y.mapv(|e| if e >= lowest_positive_score { 1. } else { 0. })
             }
         };
+        if wide_out_path.is_some() {
+            wide_columns.push(pheno_output);
+            continue;
+        }
         let path = &out_paths[i];
         println!("=> writing the effects due to {}", path);
-        write_effects_to_file(&pheno_output, &fid_iid_list, path)
-            .unwrap_or_exit(Some(format!(
-                "failed to write the simulated effects to file: {}",
-                path
-            )));
+        write_effects_to_file(
+            &pheno_output,
+            &fid_iid_list,
+            path,
+            &column_names[i],
+            &effects_format,
+        )
+        .unwrap_or_exit(Some(format!(
+            "failed to write the simulated effects to file: {}",
+            path
+        )));
+    }
+    if let Some(path) = wide_out_path {
+        println!("=> writing the effects of all the replicates to {}", path);
+        let columns: Vec<(&str, _)> = column_names
+            .iter()
+            .map(|name| name.as_str())
+            .zip(wide_columns.iter())
+            .collect();
+        write_wide_effects_to_file(
+            &columns,
+            &fid_iid_list,
+            &path,
+            &effects_format,
+        )
+        .unwrap_or_exit(Some(format!(
+            "failed to write the simulated effects to file: {}",
+            path
+        )));
     }
 }
 
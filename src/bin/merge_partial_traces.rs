@@ -0,0 +1,51 @@
+use clap::{clap_app, Arg};
+use program_flow::{
+    argparse::{extract_str_arg, extract_str_vec_arg},
+    OrExit,
+};
+
+use saber::util::trace_metadata::{merge_partial_traces, TraceEstimates};
+
+fn main() {
+    let mut app = clap_app!(merge_partial_traces =>
+        (version: "0.1")
+        (author: "Aaron Zhou")
+        (@arg out_path: --out -o <OUT> "required; the output path of the merged trace estimates")
+    );
+    app = app.arg(
+        Arg::with_name("partial_path")
+            .long("partial").short("p").takes_value(true).required(true)
+            .multiple(true).number_of_values(1)
+            .help(
+                "The partial trace estimates saved with --save-trace by a shard of\n\
+                estimate_multi_gxg_heritability --shard. Pass the file of every shard\n\
+                one by one as follows: -p SHARD_0 -p SHARD_1 ..."
+            )
+    );
+    let matches = app.get_matches();
+
+    let out_path = extract_str_arg(&matches, "out_path");
+    let partial_paths = extract_str_vec_arg(&matches, "partial_path")
+        .unwrap_or_exit(None::<String>);
+
+    println!("\n=> loading {} partial trace estimates", partial_paths.len());
+    let partials: Vec<TraceEstimates> = partial_paths
+        .iter()
+        .map(|path| {
+            TraceEstimates::load(path).unwrap_or_exit(Some(format!(
+                "failed to load the partial trace estimates from {}",
+                path
+            )))
+        })
+        .collect();
+
+    let merged = merge_partial_traces(partials)
+        .unwrap_or_exit(Some("failed to merge the partial trace estimates"));
+    println!(
+        "merged the traces of {} random vectors",
+        merged.metadata.num_random_vecs
+    );
+
+    println!("\n=> writing the merged trace estimates to {}", out_path);
+    merged.write(&out_path).unwrap_or_exit(None::<String>);
+}
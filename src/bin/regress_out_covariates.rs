@@ -9,7 +9,7 @@ use program_flow::argparse::{extract_optional_str_vec_arg, extract_str_arg};
 
 use program_flow::OrExit;
 use saber::util::{
-    get_plink_covariate_arr, get_plink_pheno_data,
+    check_num_people, get_plink_covariate_arr, get_plink_pheno_data,
     get_plink_pheno_data_replace_missing_with_mean,
     matrix_util::normalize_vector_inplace,
 };
@@ -57,6 +57,13 @@ fn main() {
         }
     };
     println!("pheno_arr.dim: {:?}", pheno_arr.dim());
+    check_num_people(
+        cov_arr.dim().0,
+        &covariate_path,
+        pheno_arr.len(),
+        &pheno_path,
+    )
+    .unwrap_or_exit(None::<String>);
 
     println!("\n=> normalizing the phenotypes");
     normalize_vector_inplace(&mut pheno_arr, 0);
@@ -0,0 +1,330 @@
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use biofile::plink_bed::geno_to_lowest_two_bits;
+use clap::{clap_app, Arg};
+use ndarray::{s, Array, Ix1, Ix2};
+use ndarray_rand::RandomExt;
+use program_flow::{
+    argparse::{extract_numeric_arg, extract_optional_str_arg},
+    OrExit,
+};
+use rand::distributions::Normal;
+
+use saber::{
+    estimator_builder::HeritabilityEstimatorBuilder,
+    exact::TraceMode,
+    gxg_pair_exclusion::GxgPairExclusion,
+    heritability_estimator::estimate_g_gxg_heritability,
+    partitioned_jackknife_estimates::{
+        Estimate, PartitionedJackknifeEstimates,
+    },
+    progress::StdoutProgress,
+    resampling::Resampling,
+    simulation::{
+        sim_geno::generate_g_matrix,
+        sim_pheno::{
+            generate_g_contribution, generate_gxg_contribution_from_gxg_basis,
+        },
+    },
+    util::{get_bed_bim_from_prefix_and_partition, matrix_util::ProbeType},
+};
+
+const G_VARIANCE: f64 = 0.5;
+const G_AND_GXG_G_VARIANCE: f64 = 0.3;
+const G_AND_GXG_GXG_VARIANCE: f64 = 0.2;
+const NUM_RANDOM_VECS: usize = 100;
+const NUM_JACKKNIFE_BLOCKS: usize = 20;
+/// The randomized trace estimates are the ones under test, which the exact
+/// path would otherwise replace for a small simulated cohort
+const TRACE_MODE: TraceMode = TraceMode::Randomized;
+/// An estimate passes if it is within this many standard errors of the
+/// simulated variance, or within `MIN_TOLERANCE` for tiny standard errors.
+const NUM_SE_TOLERANCE: f64 = 4.;
+const MIN_TOLERANCE: f64 = 0.05;
+
+fn main() {
+    let mut app = clap_app!(saber_selftest =>
+        (version: "0.1")
+    );
+    app = app
+        .about(
+            "Simulates a small dataset with known G and GxG variances, runs the\n\
+            estimators on it and checks the estimates. Exits with a non-zero\n\
+            status if any of the checks fails."
+        )
+        .arg(
+            Arg::with_name("num_people")
+                .long("num-people").takes_value(true).default_value("2000")
+                .help("The number of simulated people")
+        )
+        .arg(
+            Arg::with_name("num_snps")
+                .long("num-snps").takes_value(true).default_value("1000")
+                .help("The number of simulated SNPs for the G component")
+        )
+        .arg(
+            Arg::with_name("num_gxg_basis")
+                .long("num-gxg-basis").takes_value(true).default_value("20")
+                .help(
+                    "The number of simulated SNPs whose pairwise products form\n\
+                    the GxG component"
+                )
+        )
+        .arg(
+            Arg::with_name("out_dir")
+                .long("out-dir").takes_value(true)
+                .help(
+                    "The directory for the simulated files, a temporary\n\
+                    directory by default"
+                )
+        );
+    let matches = app.get_matches();
+    let num_people = extract_numeric_arg::<usize>(&matches, "num_people")
+        .unwrap_or_exit(Some("failed to extract num_people"));
+    let num_snps = extract_numeric_arg::<usize>(&matches, "num_snps")
+        .unwrap_or_exit(Some("failed to extract num_snps"));
+    let num_gxg_basis = extract_numeric_arg::<usize>(&matches, "num_gxg_basis")
+        .unwrap_or_exit(Some("failed to extract num_gxg_basis"));
+    let out_dir = extract_optional_str_arg(&matches, "out_dir").unwrap_or(
+        std::env::temp_dir()
+            .join(format!("saber_selftest_{}", std::process::id()))
+            .to_string_lossy()
+            .to_string(),
+    );
+    println!(
+        "num_people: {}\nnum_snps: {}\nnum_gxg_basis: {}\nout_dir: {}",
+        num_people, num_snps, num_gxg_basis, out_dir
+    );
+    create_dir_all(&out_dir).unwrap_or_exit(Some(format!(
+        "failed to create the directory {}",
+        out_dir
+    )));
+
+    println!("\n=> simulating the genotypes");
+    let geno = generate_g_matrix(num_people, num_snps, 0.25, 0.25)
+        .unwrap_or_exit(None::<String>);
+    let gxg_basis = generate_g_matrix(num_people, num_gxg_basis, 0.25, 0.25)
+        .unwrap_or_exit(None::<String>);
+    let g_prefix = path_in(&out_dir, "geno");
+    let gxg_prefix = path_in(&out_dir, "gxg_basis");
+    write_plink_files(&g_prefix, &geno).unwrap_or_exit(None::<String>);
+    write_plink_files(&gxg_prefix, &gxg_basis).unwrap_or_exit(None::<String>);
+
+    println!("\n=> simulating the phenotypes");
+    let g_effects =
+        generate_g_contribution(geno.mapv(|x| x as f32), G_VARIANCE);
+    let g_pheno_path = path_in(&out_dir, "g.pheno");
+    write_pheno(
+        &g_pheno_path,
+        &(g_effects + &generate_noise(num_people, 1. - G_VARIANCE)),
+    )
+    .unwrap_or_exit(None::<String>);
+
+    let g_and_gxg_effects =
+        generate_g_contribution(geno.mapv(|x| x as f32), G_AND_GXG_G_VARIANCE)
+            + &generate_gxg_contribution_from_gxg_basis(
+                gxg_basis.mapv(|x| x as f32),
+                G_AND_GXG_GXG_VARIANCE,
+                &GxgPairExclusion::none(num_gxg_basis),
+            );
+    let g_and_gxg_pheno_path = path_in(&out_dir, "g_and_gxg.pheno");
+    write_pheno(
+        &g_and_gxg_pheno_path,
+        &(g_and_gxg_effects
+            + &generate_noise(
+                num_people,
+                1. - G_AND_GXG_G_VARIANCE - G_AND_GXG_GXG_VARIANCE,
+            )),
+    )
+    .unwrap_or_exit(None::<String>);
+
+    let resampling = Resampling::BlockJackknife {
+        num_blocks: NUM_JACKKNIFE_BLOCKS,
+    };
+    let mut checks: Vec<(String, bool)> = Vec::new();
+
+    println!("\n=> estimating the G heritability");
+    let (bed, bim) = get_bed_bim_from_prefix_and_partition(
+        &vec![g_prefix.clone()],
+        &None,
+        &None,
+    )
+    .unwrap_or_exit(None::<String>);
+    let g_est = HeritabilityEstimatorBuilder::new()
+        .plink(bed, bim)
+        .phenotype(g_pheno_path.clone())
+        .num_random_vecs(NUM_RANDOM_VECS)
+        .probe_type(ProbeType::Rademacher)
+        .trace_mode(TRACE_MODE)
+        .resampling(resampling)
+        .run()
+        .unwrap_or_exit(None::<String>)
+        .into_hash_map();
+    checks.push(check_estimate(
+        "G heritability",
+        g_est[&g_pheno_path].partition_estimates[0],
+        G_VARIANCE,
+    ));
+
+    println!("\n=> estimating the G and GxG heritability");
+    let (g_bed, g_bim) =
+        get_bed_bim_from_prefix_and_partition(&vec![g_prefix], &None, &None)
+            .unwrap_or_exit(None::<String>);
+    let (gxg_bed, gxg_bim) =
+        get_bed_bim_from_prefix_and_partition(&vec![gxg_prefix], &None, &None)
+            .unwrap_or_exit(None::<String>);
+    let g_and_gxg_est = estimate_g_gxg_heritability(
+        g_bed,
+        g_bim,
+        gxg_bed,
+        gxg_bim,
+        vec![g_and_gxg_pheno_path.clone()],
+        NUM_RANDOM_VECS,
+        NUM_RANDOM_VECS,
+        ProbeType::Rademacher,
+        TRACE_MODE,
+        resampling,
+        None,
+        None,
+        &StdoutProgress,
+    )
+    .unwrap_or_exit(None::<String>);
+    let est = &g_and_gxg_est[&g_and_gxg_pheno_path];
+    checks.push(check_estimate(
+        "G heritability with GxG",
+        get_subset_estimate(est, "G").unwrap_or_exit(None::<String>),
+        G_AND_GXG_G_VARIANCE,
+    ));
+    checks.push(check_estimate(
+        "GxG heritability",
+        get_subset_estimate(est, "intra-GxG-partition")
+            .unwrap_or_exit(None::<String>),
+        G_AND_GXG_GXG_VARIANCE,
+    ));
+
+    let num_failed = checks.iter().filter(|(_, passed)| !passed).count();
+    println!("\n=> selftest summary");
+    for (line, _) in checks.iter() {
+        println!("{}", line);
+    }
+    if num_failed > 0 {
+        println!("\n{} of {} checks FAILED", num_failed, checks.len());
+        std::process::exit(1);
+    }
+    println!("\nall {} checks PASSED", checks.len());
+}
+
+fn path_in(dir: &str, file_name: &str) -> String {
+    Path::new(dir).join(file_name).to_string_lossy().to_string()
+}
+
+fn generate_noise(num_people: usize, variance: f64) -> Array<f32, Ix1> {
+    Array::random(num_people, Normal::new(0., variance.sqrt()))
+        .mapv(|e| e as f32)
+}
+
+fn get_subset_estimate(
+    est: &PartitionedJackknifeEstimates,
+    key: &str,
+) -> Result<Estimate<f64>, String> {
+    est.subset_sum_estimates
+        .iter()
+        .flatten()
+        .find(|(k, _)| k == key)
+        .map(|(_, e)| *e)
+        .ok_or_else(|| format!("no estimate for {}", key))
+}
+
+/// Returns the PASS or FAIL line and whether the check passed
+fn check_estimate(
+    name: &str,
+    est: Estimate<f64>,
+    expected: f64,
+) -> (String, bool) {
+    let tolerance = (NUM_SE_TOLERANCE * est.standard_error).max(MIN_TOLERANCE);
+    let diff = (est.bias_corrected_estimate - expected).abs();
+    let passed = diff <= tolerance;
+    (
+        format!(
+            "{} {}: estimate {:.5} (SE {:.5}), simulated {}, tolerance {:.5}",
+            if passed { "PASS" } else { "FAIL" },
+            name,
+            est.bias_corrected_estimate,
+            est.standard_error,
+            expected,
+            tolerance
+        ),
+        passed,
+    )
+}
+
+/// Writes `geno` of shape `num_people x num_snps` to `prefix`.bed, .bim and
+/// .fam, with every SNP on chromosome 1.
+fn write_plink_files(
+    prefix: &str,
+    geno: &Array<u8, Ix2>,
+) -> Result<(), String> {
+    let (num_people, num_snps) = geno.dim();
+    let create = |path: String| {
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&path)
+            .map(BufWriter::new)
+            .map_err(|why| format!("failed to create {}: {}", path, why))
+    };
+    let write_err = |why: std::io::Error| {
+        format!("failed to write the PLINK files {}: {}", prefix, why)
+    };
+
+    let mut bed = create(format!("{}.bed", prefix))?;
+    bed.write_all(&[0x6c, 0x1b, 0x1]).map_err(write_err)?;
+    for j in 0..num_snps {
+        let col = geno.slice(s![.., j]);
+        for start in (0..num_people).step_by(4) {
+            let mut byte = 0u8;
+            for k in start..std::cmp::min(start + 4, num_people) {
+                byte |= geno_to_lowest_two_bits(col[k]) << ((k - start) * 2);
+            }
+            bed.write_all(&[byte]).map_err(write_err)?;
+        }
+    }
+    bed.flush().map_err(write_err)?;
+
+    let mut bim = create(format!("{}.bim", prefix))?;
+    for j in 0..num_snps {
+        writeln!(bim, "1\trs{}\t0\t{}\tA\tG", j + 1, j + 1)
+            .map_err(write_err)?;
+    }
+    bim.flush().map_err(write_err)?;
+
+    let mut fam = create(format!("{}.fam", prefix))?;
+    for i in 0..num_people {
+        writeln!(fam, "{} {} 0 0 0 -9", i + 1, i + 1).map_err(write_err)?;
+    }
+    fam.flush().map_err(write_err)
+}
+
+fn write_pheno(path: &str, pheno: &Array<f32, Ix1>) -> Result<(), String> {
+    let write_err = |why: std::io::Error| {
+        format!("failed to write the phenotypes to {}: {}", path, why)
+    };
+    let mut buf = BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)
+            .map_err(write_err)?,
+    );
+    writeln!(buf, "FID IID pheno").map_err(write_err)?;
+    for (i, y) in pheno.iter().enumerate() {
+        writeln!(buf, "{} {} {}", i + 1, i + 1, y).map_err(write_err)?;
+    }
+    buf.flush().map_err(write_err)
+}
@@ -187,7 +187,7 @@ fn main() {
 
             let mut err_tracker = ValueTracker::new();
             for _ in 0..num_iter {
-                let gxg_dot_y_norm_sq_est = estimate_gxg_dot_y_norm_sq(&gxg_basis, &y, num_random_vecs);
+                let gxg_dot_y_norm_sq_est = estimate_gxg_dot_y_norm_sq(&gxg_basis, &y, None, None, num_random_vecs);
                 update_tracker_and_print(gxg_dot_y_norm_sq, gxg_dot_y_norm_sq_est, &mut err_tracker, "gxg_dot_y_norm_sq_est", percent_sig_fig);
             }
             println!("\ngxg_dot_y_norm_sq error ratio stats:\n{}", err_tracker.to_percent_string(percent_sig_fig));
@@ -205,7 +205,7 @@ fn main() {
 
             let mut err_tracker = ValueTracker::new();
             for _ in 0..num_iter {
-                let tr_k_gxg_k_est = estimate_tr_k_gxg_k(&rand_geno, &gxg_basis, num_random_vecs, None);
+                let tr_k_gxg_k_est = estimate_tr_k_gxg_k(&rand_geno, &gxg_basis, None, None, None, num_random_vecs, None);
                 update_tracker_and_print(tr_k_gxg_k_true, tr_k_gxg_k_est, &mut err_tracker, "tr_k_gxg_k_est", percent_sig_fig);
             }
             println!("\ntr_k_gxg_k error ratio stats:\n{}", err_tracker.to_percent_string(percent_sig_fig));
@@ -222,7 +222,7 @@ fn main() {
             let mut err_tracker = ValueTracker::new();
             println!("\n=> estimating the trace of GxG.dot(GxG.T)");
             for _ in 0..num_iter {
-                let tr_k_est = estimate_gxg_gram_trace(&gxg_basis, num_random_vecs).unwrap_or_exit(None::<String>);
+                let tr_k_est = estimate_gxg_gram_trace(&gxg_basis, None, None, num_random_vecs).unwrap_or_exit(None::<String>);
                 update_tracker_and_print(tr_k_true, tr_k_est, &mut err_tracker, "tr_k_est", percent_sig_fig);
             }
             println!("\ntr(K) estimate error ratio stats:\n{}", err_tracker.to_percent_string(percent_sig_fig));
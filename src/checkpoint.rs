@@ -0,0 +1,344 @@
+//! Checkpoints of the partitioned heritability estimation, so that a long run
+//! that is interrupted can resume without streaming the genotypes again.
+//!
+//! The estimation streams the genotypes once per jackknife block to
+//! accumulate the `X X^T Z` terms of the traces in the normal equations
+//! `A x = b`, and once more for the `|X^T y|^2` terms of `b`. The checkpoint
+//! holds the terms of the blocks streamed so far, together with the probes
+//! `Z`, the SNP blocks and the resampling replicates. These are everything
+//! the run draws at random, so a resumed run continues with the same random
+//! draws as the interrupted one, and the estimates on the replicates that
+//! are already solved are kept as well.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Ix1, Ix2};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    error::Error, heritability_estimator::CompactSnpPartition,
+    integer_set::index::SnpIndex, resampling::Replicate,
+};
+
+const STREAMED_CHECKPOINT_FILENAME: &str = "streamed.checkpoint";
+const REPLICATE_CHECKPOINT_FILENAME: &str = "replicates.checkpoint";
+
+/// Where the checkpoints are saved, and whether to resume from the
+/// checkpoint already there. Without `resume`, an existing checkpoint is
+/// overwritten.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CheckpointConfig {
+    pub dir: PathBuf,
+    pub resume: bool,
+}
+
+impl CheckpointConfig {
+    pub fn new<P: AsRef<Path>>(dir: P, resume: bool) -> CheckpointConfig {
+        CheckpointConfig {
+            dir: dir.as_ref().to_path_buf(),
+            resume,
+        }
+    }
+}
+
+/// The streamed terms are saved apart from the progress on the replicates,
+/// which is small and saved much more often.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EstimationCheckpoint {
+    num_people: usize,
+    pheno_names: Vec<String>,
+    partition_sizes: Vec<usize>,
+    /// The intervals of the SNPs in each resampling block
+    blocks: Vec<Vec<[SnpIndex; 2]>>,
+    /// The trace probes `Z`
+    probes: Array<f32, Ix2>,
+    /// `ggz[b][i]` is `X X^T Z` over the SNPs of partition `i` in block `b`,
+    /// for the blocks streamed so far
+    ggz: Vec<Vec<Array<f32, Ix2>>>,
+    /// `ygy[i][b]` holds `|X^T y|^2` over the SNPs of partition `i` in block
+    /// `b` for each phenotype, once they are streamed
+    ygy: Option<Vec<Vec<Array<f64, Ix1>>>>,
+    #[serde(skip)]
+    replicate_progress: ReplicateProgress,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+struct ReplicateProgress {
+    replicates: Option<Vec<Replicate>>,
+    /// The variance estimates of each phenotype on each of the first
+    /// `estimates.len()` replicates
+    estimates: Vec<Vec<Vec<f64>>>,
+}
+
+impl EstimationCheckpoint {
+    pub fn new(
+        num_people: usize,
+        pheno_names: Vec<String>,
+        partition_sizes: Vec<usize>,
+        blocks: &[CompactSnpPartition],
+        probes: Array<f32, Ix2>,
+    ) -> EstimationCheckpoint {
+        EstimationCheckpoint {
+            num_people,
+            pheno_names,
+            partition_sizes,
+            blocks: blocks
+                .iter()
+                .map(|block| {
+                    block
+                        .get_intervals_by_ref()
+                        .iter()
+                        .map(|i| [i.get_start(), i.get_end()])
+                        .collect()
+                })
+                .collect(),
+            probes,
+            ggz: Vec::new(),
+            ygy: None,
+            replicate_progress: ReplicateProgress::default(),
+        }
+    }
+
+    /// Whether `dir` holds a checkpoint
+    pub fn exists<P: AsRef<Path>>(dir: P) -> bool {
+        dir.as_ref().join(STREAMED_CHECKPOINT_FILENAME).is_file()
+    }
+
+    /// Saves the checkpoint in `dir`, which is created if missing
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|io_error| Error::IO {
+            why: format!("failed to create {}", dir.display()),
+            io_error,
+        })?;
+        write_bincode(&dir.join(STREAMED_CHECKPOINT_FILENAME), self)?;
+        self.save_replicate_progress(dir)
+    }
+
+    /// Saves only the progress on the replicates, leaving the streamed terms
+    /// saved earlier in `dir` as they are
+    pub fn save_replicate_progress<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<(), Error> {
+        write_bincode(
+            &dir.as_ref().join(REPLICATE_CHECKPOINT_FILENAME),
+            &self.replicate_progress,
+        )
+    }
+
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<EstimationCheckpoint, Error> {
+        let dir = dir.as_ref();
+        let mut checkpoint: EstimationCheckpoint =
+            read_bincode(&dir.join(STREAMED_CHECKPOINT_FILENAME))?;
+        let replicate_path = dir.join(REPLICATE_CHECKPOINT_FILENAME);
+        if replicate_path.is_file() {
+            checkpoint.replicate_progress = read_bincode(&replicate_path)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Checks that the checkpoint was saved by a run on the same people,
+    /// phenotypes and partitions with the same number of probes
+    pub fn check_compatible_with(
+        &self,
+        num_people: usize,
+        pheno_names: &[String],
+        partition_sizes: &[usize],
+        num_random_vecs: usize,
+    ) -> Result<(), String> {
+        if self.num_people != num_people {
+            return Err(format!(
+                "the checkpoint is for {} people, but the run has {}",
+                self.num_people, num_people
+            ));
+        }
+        if self.pheno_names.as_slice() != pheno_names {
+            return Err(format!(
+                "the checkpoint is for the phenotypes {:?}, but the run is \
+                for {:?}",
+                self.pheno_names, pheno_names
+            ));
+        }
+        if self.partition_sizes.as_slice() != partition_sizes {
+            return Err(format!(
+                "the checkpoint is for partitions of sizes {:?}, but the run \
+                has partitions of sizes {:?}",
+                self.partition_sizes, partition_sizes
+            ));
+        }
+        if self.probes.dim().1 != num_random_vecs {
+            return Err(format!(
+                "the checkpoint has {} random vectors, but the run uses {}",
+                self.probes.dim().1,
+                num_random_vecs
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn blocks(&self) -> Vec<CompactSnpPartition> {
+        self.blocks
+            .iter()
+            .map(|intervals| OrderedIntegerSet::from_slice(intervals))
+            .collect()
+    }
+
+    pub fn probes(&self) -> &Array<f32, Ix2> {
+        &self.probes
+    }
+
+    pub fn num_streamed_blocks(&self) -> usize {
+        self.ggz.len()
+    }
+
+    /// Appends `X X^T Z` of every partition on the next block
+    pub fn push_block_ggz(&mut self, ggz: Vec<Array<f32, Ix2>>) {
+        self.ggz.push(ggz);
+    }
+
+    /// Moves out `ggz[i][b]`, with the partitions in the outer dimension.
+    /// The streamed terms must not be saved afterwards, as they would be
+    /// saved without the `ggz`, but the replicate progress can.
+    pub fn take_partitioned_ggz(&mut self) -> Vec<Vec<Array<f32, Ix2>>> {
+        let num_partitions = self.partition_sizes.len();
+        let mut partitioned = vec![Vec::new(); num_partitions];
+        for block_ggz in self.ggz.drain(..) {
+            for (i, ggz) in block_ggz.into_iter().enumerate() {
+                partitioned[i].push(ggz);
+            }
+        }
+        partitioned
+    }
+
+    pub fn ygy(&self) -> Option<&Vec<Vec<Array<f64, Ix1>>>> {
+        self.ygy.as_ref()
+    }
+
+    pub fn set_ygy(&mut self, ygy: Vec<Vec<Array<f64, Ix1>>>) {
+        self.ygy = Some(ygy);
+    }
+
+    pub fn replicates(&self) -> Option<&Vec<Replicate>> {
+        self.replicate_progress.replicates.as_ref()
+    }
+
+    pub fn set_replicates(&mut self, replicates: Vec<Replicate>) {
+        self.replicate_progress.replicates = Some(replicates);
+    }
+
+    pub fn replicate_estimates(&self) -> &Vec<Vec<Vec<f64>>> {
+        &self.replicate_progress.estimates
+    }
+
+    pub fn push_replicate_estimates(&mut self, estimates: Vec<Vec<f64>>) {
+        self.replicate_progress.estimates.push(estimates);
+    }
+}
+
+/// Writes to a temporary file first and then renames it, so that an
+/// interruption while writing leaves the previous file intact
+fn write_bincode<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    {
+        let buf_writer = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(&tmp_path)
+                .map_err(|io_error| Error::IO {
+                    why: format!("failed to create {}", tmp_path.display()),
+                    io_error,
+                })?,
+        );
+        bincode::serialize_into(buf_writer, value)?;
+    }
+    fs::rename(&tmp_path, path).map_err(|io_error| Error::IO {
+        why: format!(
+            "failed to move {} to {}",
+            tmp_path.display(),
+            path.display()
+        ),
+        io_error,
+    })
+}
+
+fn read_bincode<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let buf_reader =
+        BufReader::new(OpenOptions::new().read(true).open(path).map_err(
+            |io_error| Error::IO {
+                why: format!("failed to open {}", path.display()),
+                io_error,
+            },
+        )?);
+    Ok(bincode::deserialize_from(buf_reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array};
+    use tempfile::tempdir;
+
+    use super::EstimationCheckpoint;
+    use crate::resampling::Replicate;
+
+    #[test]
+    fn test_checkpoint_save_and_load() {
+        let blocks = vec![
+            OrderedIntegerSet::from_slice(&[[0, 4], [10, 12]]),
+            OrderedIntegerSet::from_slice(&[[5, 9]]),
+        ];
+        let names = vec!["a".to_string(), "b".to_string()];
+        let mut checkpoint = EstimationCheckpoint::new(
+            3,
+            names.clone(),
+            vec![13],
+            &blocks,
+            Array::ones((3, 2)),
+        );
+        checkpoint.push_block_ggz(vec![Array::zeros((3, 2))]);
+        checkpoint.set_replicates(vec![
+            Replicate::LeaveOneOut(0),
+            Replicate::LeaveOneOut(1),
+        ]);
+        checkpoint.push_replicate_estimates(vec![vec![0.5], vec![0.2]]);
+
+        let dir = tempdir().unwrap();
+        assert!(!EstimationCheckpoint::exists(dir.path()));
+        checkpoint.save(dir.path()).unwrap();
+        assert!(EstimationCheckpoint::exists(dir.path()));
+        let loaded = EstimationCheckpoint::load(dir.path()).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.blocks(), blocks);
+        assert_eq!(loaded.num_streamed_blocks(), 1);
+
+        assert!(loaded.check_compatible_with(3, &names, &[13], 2).is_ok());
+        assert!(loaded.check_compatible_with(4, &names, &[13], 2).is_err());
+        assert!(loaded
+            .check_compatible_with(3, &names[..1], &[13], 2)
+            .is_err());
+        assert!(loaded.check_compatible_with(3, &names, &[12], 2).is_err());
+        assert!(loaded.check_compatible_with(3, &names, &[13], 3).is_err());
+
+        checkpoint.set_ygy(vec![vec![array![1., 2.], array![3., 4.]]]);
+        checkpoint.save(dir.path()).unwrap();
+        assert_eq!(EstimationCheckpoint::load(dir.path()).unwrap(), checkpoint);
+
+        // the replicate progress is saved apart from the streamed terms
+        let ggz = checkpoint.take_partitioned_ggz();
+        assert_eq!(ggz.len(), 1);
+        assert_eq!(ggz[0].len(), 1);
+        checkpoint.push_replicate_estimates(vec![vec![0.4], vec![0.1]]);
+        checkpoint.save_replicate_progress(dir.path()).unwrap();
+        let loaded = EstimationCheckpoint::load(dir.path()).unwrap();
+        assert_eq!(loaded.num_streamed_blocks(), 1);
+        assert_eq!(loaded.replicate_estimates().len(), 2);
+    }
+}
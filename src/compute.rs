@@ -0,0 +1,238 @@
+//! The threads and the memory the estimators may use.
+//!
+//! The genotypes are streamed in chunks of SNPs that are folded in parallel,
+//! so at any time every thread holds a standardized chunk of
+//! `num_people x chunk_size` entries and an accumulator of
+//! `num_people x num_columns` entries, where the columns are e.g. the trace
+//! probes. The chunk size is derived from the memory budget under that
+//! model. The estimates themselves, such as the products with the probes of
+//! every jackknife block, are not counted against the budget.
+//!
+//! With `spill_to_disk`, the probe matrices and their products with the
+//! kernels that would take more than half of the budget are held in
+//! memory-mapped files instead, see `workspace`, and are processed in blocks
+//! of columns that fit in that half.
+
+use std::mem::size_of;
+
+use rayon::ThreadPoolBuilder;
+
+use crate::{
+    error::Error,
+    gpu::{set_backend, ComputeBackend},
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+};
+
+pub const BYTES_PER_MB: usize = 1 << 20;
+
+/// Larger chunks than this only reduce the parallelism over the chunks
+pub const MAX_NUM_SNPS_PER_CHUNK: usize = 1024;
+
+/// Without any of the settings, the estimators run on the global rayon pool
+/// with `DEFAULT_NUM_SNPS_PER_CHUNK` SNPs per chunk.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct ComputeConfig {
+    /// The number of threads of a dedicated rayon pool
+    pub num_threads: Option<usize>,
+    /// The memory budget of the streamed chunks and their accumulators
+    pub max_memory_bytes: Option<usize>,
+    /// The number of SNPs per streamed chunk, in place of the one derived
+    /// from `max_memory_bytes`
+    pub chunk_size: Option<usize>,
+    /// The backend of the products of the streamed chunks, which falls back
+    /// on the CPU if the GPU cannot be used
+    pub backend: ComputeBackend,
+    /// Whether the probe workspaces exceeding the memory budget are spilled
+    /// to memory-mapped temporary files
+    pub spill_to_disk: bool,
+}
+
+impl ComputeConfig {
+    pub fn new(
+        num_threads: Option<usize>,
+        max_memory_bytes: Option<usize>,
+        chunk_size: Option<usize>,
+    ) -> ComputeConfig {
+        ComputeConfig {
+            num_threads,
+            max_memory_bytes,
+            chunk_size,
+            backend: ComputeBackend::default(),
+            spill_to_disk: false,
+        }
+    }
+
+    pub fn with_backend(mut self, backend: ComputeBackend) -> ComputeConfig {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_spill_to_disk(mut self, spill_to_disk: bool) -> ComputeConfig {
+        self.spill_to_disk = spill_to_disk;
+        self
+    }
+
+    /// The number of threads the estimators run on
+    pub fn num_threads(&self) -> usize {
+        self.num_threads.unwrap_or_else(rayon::current_num_threads)
+    }
+
+    /// The number of SNPs per streamed chunk when the accumulators have
+    /// `num_columns` columns
+    pub fn get_num_snps_per_chunk(
+        &self,
+        num_people: usize,
+        num_columns: usize,
+    ) -> Result<usize, Error> {
+        if let Some(chunk_size) = self.chunk_size {
+            if chunk_size == 0 {
+                return Err(Error::Generic(
+                    "the chunk size has to be positive".to_string(),
+                ));
+            }
+            return Ok(chunk_size);
+        }
+        let max_memory_bytes = match self.max_memory_bytes {
+            Some(max_memory_bytes) => max_memory_bytes,
+            None => return Ok(DEFAULT_NUM_SNPS_PER_CHUNK),
+        };
+        let num_entries_per_thread =
+            max_memory_bytes / self.num_threads() / size_of::<f32>();
+        let num_accumulator_entries = num_people * (num_columns + 1);
+        if num_entries_per_thread < num_accumulator_entries {
+            return Err(Error::Generic(format!(
+                "a memory budget of {} bytes is too small for {} people and \
+                {} columns on {} threads, which need at least {} bytes",
+                max_memory_bytes,
+                num_people,
+                num_columns,
+                self.num_threads(),
+                num_accumulator_entries * size_of::<f32>() * self.num_threads()
+            )));
+        }
+        Ok(std::cmp::min(
+            (num_entries_per_thread - num_people * num_columns) / num_people,
+            MAX_NUM_SNPS_PER_CHUNK,
+        ))
+    }
+
+    /// Whether the `num_matrices` workspaces of `num_people x num_columns`
+    /// entries, e.g. the probes and their products with every kernel, are
+    /// spilled to disk, which they are with `spill_to_disk` if they take more
+    /// than half of the memory budget
+    pub fn should_spill(
+        &self,
+        num_people: usize,
+        num_columns: usize,
+        num_matrices: usize,
+    ) -> bool {
+        match self.max_memory_bytes {
+            Some(max_memory_bytes) if self.spill_to_disk => {
+                num_matrices * num_people * num_columns * size_of::<f32>()
+                    > max_memory_bytes / 2
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of columns of the `num_matrices` workspaces processed at
+    /// once, i.e. all the `num_columns` unless the workspaces are spilled,
+    /// in which case a block of every workspace fits in half of the memory
+    /// budget
+    pub fn get_num_columns_per_block(
+        &self,
+        num_people: usize,
+        num_columns: usize,
+        num_matrices: usize,
+    ) -> usize {
+        match self.max_memory_bytes {
+            Some(max_memory_bytes)
+                if self.should_spill(num_people, num_columns, num_matrices) =>
+            {
+                let bytes_per_column =
+                    num_matrices * num_people * size_of::<f32>();
+                (max_memory_bytes / 2 / bytes_per_column).max(1)
+            }
+            _ => num_columns,
+        }
+    }
+
+    /// Runs `op` on a dedicated pool of `num_threads` threads, or on the
+    /// current pool if the number of threads is not set. The `backend` is
+    /// selected for the whole process, as the chunk products have no
+    /// reference to the config.
+    pub fn install<R, OP>(&self, op: OP) -> Result<R, Error>
+    where
+        R: Send,
+        OP: FnOnce() -> R + Send, {
+        set_backend(self.backend);
+        match self.num_threads {
+            None => Ok(op()),
+            Some(0) => Err(Error::Generic(
+                "the number of threads has to be positive".to_string(),
+            )),
+            Some(num_threads) => Ok(ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .map_err(|why| {
+                    Error::Generic(format!(
+                        "failed to build a pool of {} threads: {}",
+                        num_threads, why
+                    ))
+                })?
+                .install(op)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComputeConfig, MAX_NUM_SNPS_PER_CHUNK};
+    use crate::matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK;
+
+    #[test]
+    fn test_compute_config() {
+        let default = ComputeConfig::default();
+        assert_eq!(
+            default.get_num_snps_per_chunk(1000, 100).unwrap(),
+            DEFAULT_NUM_SNPS_PER_CHUNK
+        );
+        assert_eq!(default.install(|| 7).unwrap(), 7);
+
+        // 2 threads with 4 * 1000 * 150 bytes each hold the 1000 x 100
+        // accumulator and a chunk of 50 SNPs
+        let config = ComputeConfig::new(Some(2), Some(1_200_000), None);
+        assert_eq!(config.get_num_snps_per_chunk(1000, 100).unwrap(), 50);
+        assert_eq!(config.install(rayon::current_num_threads).unwrap(), 2);
+        assert!(config.get_num_snps_per_chunk(1000, 200).is_err());
+
+        let config = ComputeConfig::new(Some(1), Some(1 << 40), None);
+        assert_eq!(
+            config.get_num_snps_per_chunk(1000, 100).unwrap(),
+            MAX_NUM_SNPS_PER_CHUNK
+        );
+        let config = ComputeConfig::new(Some(1), Some(1_000), Some(30));
+        assert_eq!(config.get_num_snps_per_chunk(1000, 100).unwrap(), 30);
+
+        assert!(ComputeConfig::new(Some(0), None, None)
+            .install(|| 7)
+            .is_err());
+        assert!(ComputeConfig::new(None, None, Some(0))
+            .get_num_snps_per_chunk(10, 1)
+            .is_err());
+
+        // 3 workspaces of 100 x 50 entries take 60000 bytes, more than half
+        // of the budget, and blocks of 16 columns take 19200 bytes
+        let config = ComputeConfig::new(Some(1), Some(40_000), None);
+        assert!(!config.should_spill(100, 50, 3));
+        assert_eq!(config.get_num_columns_per_block(100, 50, 3), 50);
+        let config = config.with_spill_to_disk(true);
+        assert!(config.should_spill(100, 50, 3));
+        assert!(!config.should_spill(100, 10, 3));
+        assert_eq!(config.get_num_columns_per_block(100, 50, 3), 16);
+        assert_eq!(config.get_num_columns_per_block(100, 10, 3), 10);
+        assert!(!ComputeConfig::default()
+            .with_spill_to_disk(true)
+            .should_spill(100, 50, 3));
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// The two-sided confidence level of the bootstrap intervals
+pub const BOOTSTRAP_CI_LEVEL: f64 = 0.95;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct ConfidenceInterval {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl ConfidenceInterval {
+    pub fn new(lower: f64, upper: f64) -> ConfidenceInterval {
+        ConfidenceInterval {
+            lower,
+            upper,
+        }
+    }
+
+    fn nan() -> ConfidenceInterval {
+        ConfidenceInterval::new(std::f64::NAN, std::f64::NAN)
+    }
+}
+
+impl fmt::Display for ConfidenceInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match f.precision() {
+            Some(p) => {
+                write!(f, "[{:.*}, {:.*}]", p, self.lower, p, self.upper)
+            }
+            None => write!(f, "[{}, {}]", self.lower, self.upper),
+        }
+    }
+}
+
+/// The standard normal cumulative distribution function, accurate to about
+/// 1e-7 (Abramowitz and Stegun 7.1.26).
+pub fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1. / (1. + 0.327_591_1 * z);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741
+                    + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1. - poly * (-z * z).exp();
+    if x >= 0. {
+        0.5 * (1. + erf)
+    } else {
+        0.5 * (1. - erf)
+    }
+}
+
+/// The inverse of the standard normal cumulative distribution function,
+/// accurate to about 1e-9 (Acklam's rational approximation). Returns
+/// negative and positive infinity at 0 and 1, and NaN outside of [0, 1].
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+
+    if p.is_nan() || p < 0. || p > 1. {
+        return std::f64::NAN;
+    }
+    if p == 0. {
+        return std::f64::NEG_INFINITY;
+    }
+    if p == 1. {
+        return std::f64::INFINITY;
+    }
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.)
+    };
+    if p < P_LOW {
+        tail((-2. * p.ln()).sqrt())
+    } else if p > 1. - P_LOW {
+        -tail((-2. * (1. - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5])
+            * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r
+                + 1.)
+    }
+}
+
+/// The `p`-th quantile of the `sorted_values`, linearly interpolated between
+/// the order statistics.
+pub fn empirical_quantile(sorted_values: &[f64], p: f64) -> f64 {
+    if sorted_values.is_empty() || p.is_nan() {
+        return std::f64::NAN;
+    }
+    let h = (sorted_values.len() - 1) as f64 * p.max(0.).min(1.);
+    let lo = h.floor() as usize;
+    let hi = std::cmp::min(lo + 1, sorted_values.len() - 1);
+    sorted_values[lo]
+        + (h - lo as f64) * (sorted_values[hi] - sorted_values[lo])
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    sorted
+}
+
+/// The interval between the `(1 - level) / 2` and `(1 + level) / 2`
+/// quantiles of the bootstrap replicate estimates.
+pub fn percentile_interval(
+    replicate_estimates: &[f64],
+    level: f64,
+) -> ConfidenceInterval {
+    let sorted = sorted(replicate_estimates);
+    ConfidenceInterval::new(
+        empirical_quantile(&sorted, (1. - level) / 2.),
+        empirical_quantile(&sorted, (1. + level) / 2.),
+    )
+}
+
+/// The acceleration of the BCa interval from the leave-one-out jackknife
+/// estimates `t_i`, i.e. `sum d_i^3 / (6 (sum d_i^2)^(3/2))` with
+/// `d_i = mean(t) - t_i`.
+pub fn jackknife_acceleration(leave_one_out_estimates: &[f64]) -> f64 {
+    let n = leave_one_out_estimates.len() as f64;
+    let mean = leave_one_out_estimates.iter().sum::<f64>() / n;
+    let (sum_sq, sum_cube) = leave_one_out_estimates.iter().fold(
+        (0f64, 0f64),
+        |(sum_sq, sum_cube), t| {
+            let d = mean - t;
+            (sum_sq + d * d, sum_cube + d * d * d)
+        },
+    );
+    if sum_sq == 0. {
+        0.
+    } else {
+        sum_cube / (6. * sum_sq.powf(1.5))
+    }
+}
+
+/// The bias-corrected and accelerated (BCa) bootstrap interval. The bias
+/// correction is estimated from the fraction of the replicate estimates below
+/// the `point_estimate`, and the acceleration from the
+/// `leave_one_out_estimates`, which is taken to be zero if there are none.
+/// Both bounds are NaN if all the replicates fall on one side of the point
+/// estimate, as the bias correction is then unbounded.
+pub fn bca_interval(
+    point_estimate: f64,
+    replicate_estimates: &[f64],
+    leave_one_out_estimates: Option<&[f64]>,
+    level: f64,
+) -> ConfidenceInterval {
+    let sorted = sorted(replicate_estimates);
+    let num_below =
+        sorted.iter().filter(|&&t| t < point_estimate).count() as f64;
+    let num_ties =
+        sorted.iter().filter(|&&t| t == point_estimate).count() as f64;
+    let z0 =
+        normal_quantile((num_below + 0.5 * num_ties) / sorted.len() as f64);
+    if !z0.is_finite() {
+        return ConfidenceInterval::nan();
+    }
+    let a = match leave_one_out_estimates {
+        Some(estimates) if estimates.len() > 1 => {
+            jackknife_acceleration(estimates)
+        }
+        _ => 0.,
+    };
+    let adjusted_quantile = |p: f64| {
+        let z = z0 + normal_quantile(p);
+        normal_cdf(z0 + z / (1. - a * z))
+    };
+    ConfidenceInterval::new(
+        empirical_quantile(&sorted, adjusted_quantile((1. - level) / 2.)),
+        empirical_quantile(&sorted, adjusted_quantile((1. + level) / 2.)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bca_interval, empirical_quantile, jackknife_acceleration, normal_cdf,
+        normal_quantile, percentile_interval,
+    };
+
+    #[test]
+    fn test_normal_cdf_and_quantile() {
+        assert!((normal_cdf(0.) - 0.5).abs() < 1e-7);
+        assert!((normal_cdf(1.959_963_985) - 0.975).abs() < 1e-7);
+        assert!((normal_cdf(-1.) - 0.158_655_254).abs() < 1e-7);
+        assert!((normal_quantile(0.975) - 1.959_963_985).abs() < 1e-8);
+        assert!((normal_quantile(0.001) + 3.090_232_306).abs() < 1e-8);
+        assert_eq!(normal_quantile(0.5), 0.);
+        assert_eq!(normal_quantile(1.), std::f64::INFINITY);
+        assert!(normal_quantile(1.5).is_nan());
+    }
+
+    #[test]
+    fn test_percentile_interval() {
+        assert_eq!(empirical_quantile(&[1., 2., 4.], 0.75), 3.);
+        let values: Vec<f64> = (0..=100).rev().map(|x| x as f64).collect();
+        let ci = percentile_interval(&values, 0.9);
+        assert!((ci.lower - 5.).abs() < 1e-12);
+        assert!((ci.upper - 95.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_bca_interval() {
+        let values: Vec<f64> = (0..=100).map(|x| x as f64).collect();
+        // no bias and no acceleration reduces to the percentile interval
+        let ci = bca_interval(50., &values, None, 0.9);
+        assert!((ci.lower - 5.).abs() < 1e-4);
+        assert!((ci.upper - 95.).abs() < 1e-4);
+
+        // a point estimate below the bootstrap median shifts the interval down
+        let ci = bca_interval(40., &values, None, 0.9);
+        assert!(ci.lower < 5. && ci.upper < 95.);
+
+        assert_eq!(jackknife_acceleration(&[1., 2., 3.]), 0.);
+        let skewed = [1., 1., 1., 5.];
+        assert!(jackknife_acceleration(&skewed) < 0.);
+        let ci = bca_interval(50., &values, Some(&skewed), 0.9);
+        assert!(ci.lower < 5. && ci.upper < 95.);
+
+        assert!(bca_interval(200., &values, None, 0.9).lower.is_nan());
+    }
+}
@@ -0,0 +1,198 @@
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{s, stack, Array, Axis, Ix1, Ix2};
+use ndarray_linalg::Inverse;
+
+use crate::{
+    error::Error,
+    integer_set::iter::Iter,
+    util::{check_num_people, get_plink_covariate_arr},
+};
+
+/// The projection `M = I - C (C^T C)^{-1} C^T` onto the orthogonal
+/// complement of the column space of the fixed-effect covariates `C`, which
+/// always include an intercept column.
+///
+/// Projecting the phenotypes and the genotypes by `M` removes the covariate
+/// effects before the variance components are estimated. The kernels `K_i`
+/// then enter the normal equations as `M K_i M`, and the residual noise
+/// lives in a space of dimension `tr(M) = num_people - num_covariates`.
+pub struct CovariateProjection {
+    /// `C` of shape (num_people, num_covariates)
+    covariates: Array<f32, Ix2>,
+    /// `(C^T C)^{-1}`
+    gram_inv: Array<f32, Ix2>,
+}
+
+impl CovariateProjection {
+    /// `covariates` has one covariate per column, without the intercept,
+    /// which is prepended here.
+    pub fn new(
+        covariates: &Array<f32, Ix2>,
+    ) -> Result<CovariateProjection, String> {
+        let num_people = covariates.dim().0;
+        let covariates = stack(Axis(1), &[
+            Array::<f32, Ix2>::ones((num_people, 1)).view(),
+            covariates.view(),
+        ])
+        .map_err(|why| format!("failed to add the intercept: {}", why))?;
+        let num_covariates = covariates.dim().1;
+        if num_covariates >= num_people {
+            return Err(format!(
+                "{} covariates including the intercept leave no degrees of \
+                freedom for {} people",
+                num_covariates, num_people
+            ));
+        }
+        let gram = covariates.t().dot(&covariates).mapv(|x| x as f64);
+        let gram_inv = gram.inv().map_err(|why| {
+            format!(
+                "failed to invert the covariate Gram matrix, the covariates \
+                may be collinear: {:?}",
+                why
+            )
+        })?;
+        Ok(CovariateProjection {
+            covariates,
+            gram_inv: gram_inv.mapv(|x| x as f32),
+        })
+    }
+
+    /// Reads the covariates from a plink-style covariate file with the header
+    /// `FID IID COV_1 COV_2 ...`
+    pub fn from_file(
+        covariate_path: &str,
+    ) -> Result<CovariateProjection, String> {
+        CovariateProjection::new(
+            &get_plink_covariate_arr(covariate_path)
+                .map_err(|why| why.to_string())?,
+        )
+    }
+
+    /// Reads the covariates of only the `people`, by their row indices in
+    /// the covariate file, e.g. the people on a keep list
+    pub fn from_file_of_people(
+        covariate_path: &str,
+        people: &OrderedIntegerSet<usize>,
+    ) -> Result<CovariateProjection, String> {
+        let covariates = get_plink_covariate_arr(covariate_path)
+            .map_err(|why| why.to_string())?;
+        let people: Vec<usize> = people.iter().collect();
+        if let Some(&last) = people.last() {
+            if last >= covariates.dim().0 {
+                return Err(format!(
+                    "{} has {} people, but person {} is to be kept",
+                    covariate_path,
+                    covariates.dim().0,
+                    last
+                ));
+            }
+        }
+        CovariateProjection::new(&covariates.select(Axis(0), &people))
+    }
+
+    /// The projection for the covariates of only the people in the `rows`,
+    /// e.g. after some are dropped for their missing genotypes
+    pub fn select_rows(
+        &self,
+        rows: &[usize],
+    ) -> Result<CovariateProjection, String> {
+        CovariateProjection::new(
+            &self.covariates.slice(s![.., 1..]).select(Axis(0), rows),
+        )
+    }
+
+    pub fn num_people(&self) -> usize {
+        self.covariates.dim().0
+    }
+
+    /// The number of covariates including the intercept
+    pub fn num_covariates(&self) -> usize {
+        self.covariates.dim().1
+    }
+
+    /// `tr(M)`, the dimension of the space the covariates are projected out
+    /// of
+    pub fn trace(&self) -> f64 {
+        (self.num_people() - self.num_covariates()) as f64
+    }
+
+    pub fn check_num_people(
+        &self,
+        num_people: usize,
+        name: &str,
+    ) -> Result<(), Error> {
+        check_num_people(self.num_people(), "the covariates", num_people, name)
+    }
+
+    /// The least squares coefficients `(C^T C)^{-1} C^T v` of the covariates
+    /// for every column `v` of `matrix`, where the first row is the intercept
+    pub fn get_coefficients(
+        &self,
+        matrix: &Array<f32, Ix2>,
+    ) -> Array<f32, Ix2> {
+        self.gram_inv.dot(&self.covariates.t().dot(matrix))
+    }
+
+    /// Replaces every column `v` of `matrix` with `M v`
+    pub fn project_matrix_inplace(&self, matrix: &mut Array<f32, Ix2>) {
+        let coefficients = self.get_coefficients(matrix);
+        *matrix -= &self.covariates.dot(&coefficients);
+    }
+
+    pub fn project_vector_inplace(&self, vector: &mut Array<f32, Ix1>) {
+        let coefficients = self.gram_inv.dot(&self.covariates.t().dot(vector));
+        *vector -= &self.covariates.dot(&coefficients);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Uniform;
+
+    use super::CovariateProjection;
+
+    #[test]
+    fn test_covariate_projection() {
+        let covariates: Array<f32, Ix2> =
+            Array::random((30, 2), Uniform::new(-1., 1.));
+        let projection = CovariateProjection::new(&covariates).unwrap();
+        assert_eq!(projection.num_people(), 30);
+        assert_eq!(projection.num_covariates(), 3);
+        assert_eq!(projection.trace(), 27.);
+        let rows: Vec<usize> = (0..30).step_by(2).collect();
+        let subset = projection.select_rows(&rows).unwrap();
+        assert_eq!(subset.num_people(), 15);
+        assert_eq!(subset.num_covariates(), 3);
+
+        let mut matrix: Array<f32, Ix2> =
+            Array::random((30, 4), Uniform::new(-1., 1.));
+        projection.project_matrix_inplace(&mut matrix);
+        // the projected columns are orthogonal to the covariates and the
+        // intercept
+        for col in matrix.gencolumns() {
+            assert!(col.sum().abs() < 1e-4);
+            for cov in covariates.gencolumns() {
+                assert!(col.dot(&cov).abs() < 1e-4);
+            }
+        }
+        // M is idempotent
+        let projected = matrix.clone();
+        projection.project_matrix_inplace(&mut matrix);
+        for (a, b) in matrix.iter().zip(projected.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        let mut vector = matrix.column(0).to_owned();
+        projection.project_vector_inplace(&mut vector);
+        for (a, b) in vector.iter().zip(projected.column(0).iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+
+        assert!(CovariateProjection::new(&array![[1f32], [2.]]).is_err());
+        let collinear: Array<f32, Ix2> =
+            Array::from_shape_fn((10, 2), |(i, _)| i as f32);
+        assert!(CovariateProjection::new(&collinear).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! An environment variable measured on every person, for the
+//! gene-by-environment (GxE) variance component with the kernel
+//! `K ⊙ E E^T`.
+//!
+//! A discrete environment is coded by one indicator column per level, so
+//! that `K ⊙ E E^T` keeps the kernel entries of the pairs of people in the
+//! same environment. A continuous environment is a single standardized
+//! column. In both cases, `(K ⊙ E E^T) v = sum_l diag(e_l) K diag(e_l) v`
+//! for the columns `e_l` of `E`.
+
+use std::collections::BTreeMap;
+
+use ndarray::{s, stack, Array, Axis, Ix1, Ix2};
+
+use crate::util::{
+    get_plink_str_column, matrix_util::normalize_vector_inplace,
+};
+
+pub struct Environment {
+    /// `E` of shape (num_people, num_columns)
+    design: Array<f32, Ix2>,
+    /// The level of each indicator column of a discrete environment
+    level_names: Option<Vec<String>>,
+}
+
+impl Environment {
+    /// `labels[i]` is the environment of the i-th person
+    pub fn discrete(labels: &[String]) -> Result<Environment, String> {
+        let mut level_indices = BTreeMap::new();
+        for label in labels.iter() {
+            let next_index = level_indices.len();
+            level_indices.entry(label.as_str()).or_insert(next_index);
+        }
+        if level_indices.len() < 2 {
+            return Err(format!(
+                "a discrete environment needs at least two levels, found {}",
+                level_indices.len()
+            ));
+        }
+        let mut level_names = vec![String::new(); level_indices.len()];
+        for (name, &index) in level_indices.iter() {
+            level_names[index] = name.to_string();
+        }
+        let mut design =
+            Array::<f32, Ix2>::zeros((labels.len(), level_names.len()));
+        for (i, label) in labels.iter().enumerate() {
+            design[[i, level_indices[label.as_str()]]] = 1.;
+        }
+        Ok(Environment {
+            design,
+            level_names: Some(level_names),
+        })
+    }
+
+    /// The values are standardized to zero mean and unit variance
+    pub fn continuous(values: &Array<f32, Ix1>) -> Result<Environment, String> {
+        let mut values = values.clone();
+        let mean = values.sum() / values.len() as f32;
+        if values.iter().all(|&v| v == mean) {
+            return Err("the continuous environment is constant".to_string());
+        }
+        normalize_vector_inplace(&mut values, 0);
+        let num_people = values.len();
+        Ok(Environment {
+            design: values.into_shape((num_people, 1)).unwrap(),
+            level_names: None,
+        })
+    }
+
+    /// Reads the environment from a file with the header `FID IID ENV` and
+    /// one person per line, in the same format as the phenotype files
+    pub fn from_file(
+        path: &str,
+        is_discrete: bool,
+    ) -> Result<Environment, String> {
+        let tokens = get_plink_str_column(path)?;
+        if is_discrete {
+            Environment::discrete(&tokens)
+        } else {
+            let values = tokens
+                .iter()
+                .map(|t| {
+                    t.parse::<f32>().map_err(|why| {
+                        format!(
+                            "failed to parse the environment value {} in {}: \
+                            {}",
+                            t, path, why
+                        )
+                    })
+                })
+                .collect::<Result<Vec<f32>, String>>()?;
+            Environment::continuous(&Array::from_vec(values))
+        }
+    }
+
+    pub fn num_people(&self) -> usize {
+        self.design.dim().0
+    }
+
+    pub fn is_discrete(&self) -> bool {
+        self.level_names.is_some()
+    }
+
+    pub fn level_names(&self) -> Option<&Vec<String>> {
+        self.level_names.as_ref()
+    }
+
+    /// The main effects of the environment, which have to be projected out
+    /// as fixed-effect covariates: the indicators of all the levels but the
+    /// first, which the intercept accounts for, or the continuous values.
+    pub fn fixed_effects(&self) -> Array<f32, Ix2> {
+        if self.is_discrete() {
+            self.design.slice(s![.., 1..]).to_owned()
+        } else {
+            self.design.clone()
+        }
+    }
+
+    /// `(K ⊙ E E^T) rhs` given the product `k_dot` with `K`, which is called
+    /// once with the columns of `rhs` scaled by every column of `E`.
+    pub fn interaction_dot<F>(
+        &self,
+        k_dot: F,
+        rhs: &Array<f32, Ix2>,
+    ) -> Array<f32, Ix2>
+    where
+        F: Fn(&Array<f32, Ix2>) -> Array<f32, Ix2>, {
+        let num_people = self.num_people();
+        let num_cols = rhs.dim().1;
+        let env_cols: Vec<Array<f32, Ix2>> = self
+            .design
+            .gencolumns()
+            .into_iter()
+            .map(|e| e.to_owned().into_shape((num_people, 1)).unwrap())
+            .collect();
+        let scaled: Vec<Array<f32, Ix2>> =
+            env_cols.iter().map(|e| rhs * e).collect();
+        let products = k_dot(
+            &stack(
+                Axis(1),
+                &scaled.iter().map(|m| m.view()).collect::<Vec<_>>(),
+            )
+            .unwrap(),
+        );
+        let mut acc = Array::<f32, Ix2>::zeros(rhs.dim());
+        for (l, e) in env_cols.iter().enumerate() {
+            acc += &(&products.slice(s![.., l * num_cols..(l + 1) * num_cols])
+                * e);
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Uniform;
+
+    use super::Environment;
+
+    fn assert_interaction_dot(env: &Environment, e_et: &Array<f32, Ix2>) {
+        let n = env.num_people();
+        let x: Array<f32, Ix2> = Array::random((n, 7), Uniform::new(-1., 1.));
+        let k = x.dot(&x.t());
+        let rhs: Array<f32, Ix2> = Array::random((n, 3), Uniform::new(-1., 1.));
+        let expected = (&k * e_et).dot(&rhs);
+        let product = env.interaction_dot(|v| k.dot(v), &rhs);
+        for (a, b) in product.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_discrete_environment() {
+        let labels: Vec<String> = ["a", "b", "a", "c", "b", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let env = Environment::discrete(&labels).unwrap();
+        assert!(env.is_discrete());
+        assert_eq!(env.level_names().unwrap(), &vec!["a", "b", "c"]);
+        assert_eq!(env.fixed_effects().dim(), (6, 2));
+        let e_et = Array::from_shape_fn((6, 6), |(i, j)| {
+            (labels[i] == labels[j]) as i32 as f32
+        });
+        assert_interaction_dot(&env, &e_et);
+
+        assert!(Environment::discrete(&vec!["a".to_string(); 4]).is_err());
+    }
+
+    #[test]
+    fn test_continuous_environment() {
+        let values = Array::random(10, Uniform::new(0., 5.));
+        let env = Environment::continuous(&values).unwrap();
+        assert!(!env.is_discrete());
+        let e = env.fixed_effects();
+        assert_eq!(e.dim(), (10, 1));
+        assert!(e.sum().abs() < 1e-4);
+        assert_interaction_dot(&env, &e.dot(&e.t()));
+
+        assert!(Environment::continuous(&Array::ones(5)).is_err());
+    }
+}
@@ -1,29 +1,44 @@
 use biofile::error::Error as BiofileError;
-use std::{fmt, io};
+use ndarray::ShapeError;
+use std::io;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    IO { why: String, io_error: io::Error },
+    #[error("IO error {why}: {io_error}")]
+    IO {
+        why: String,
+        #[source]
+        io_error: io::Error,
+    },
+    /// A malformed or inconsistent PLINK bed, bim or fam file
+    #[error("PLINK file error: {0}")]
+    Plink(String),
+    /// The matrix of the normal equations `A x = b` cannot be inverted, e.g.
+    /// because two kernels are collinear
+    #[error("failed to solve the singular normal equations: {0}")]
+    SingularNormalEquations(String),
+    /// Two inputs that have to agree on a dimension do not
+    #[error("dimension mismatch: {0}")]
+    DimensionMismatch(String),
+    /// The phenotype does not cover the same people as the genotypes
+    #[error(
+        "{pheno} has {num_pheno_people} people, but the genotypes have \
+        {num_geno_people} people"
+    )]
+    PhenotypeSampleMismatch {
+        pheno: String,
+        num_pheno_people: usize,
+        num_geno_people: usize,
+    },
+    #[error("Generic Error: {0}")]
     Generic(String),
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::IO {
-                why,
-                io_error,
-            } => write!(f, "IO error {}: {}", why, io_error),
-            Error::Generic(why) => write!(f, "Generic Error: {}", why),
-        }
-    }
-}
-
 impl From<BiofileError> for Error {
     fn from(err: BiofileError) -> Error {
         match err {
-            BiofileError::BadFormat(why) => Error::Generic(why),
-            BiofileError::Generic(why) => Error::Generic(why),
+            BiofileError::BadFormat(why) => Error::Plink(why),
+            BiofileError::Generic(why) => Error::Plink(why),
             BiofileError::IO {
                 why,
                 io_error,
@@ -50,8 +65,37 @@ impl From<String> for Error {
     }
 }
 
+impl From<ShapeError> for Error {
+    fn from(err: ShapeError) -> Error {
+        Error::DimensionMismatch(err.to_string())
+    }
+}
+
 impl From<bincode::Error> for Error {
     fn from(err: bincode::Error) -> Error {
         Error::Generic(format!("bincode::error: {}", *err))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use biofile::error::Error as BiofileError;
+
+    use super::Error;
+
+    #[test]
+    fn test_error() {
+        match Error::from(BiofileError::BadFormat("bad bim".to_string())) {
+            Error::Plink(why) => assert_eq!(why, "bad bim"),
+            err => panic!("unexpected error {:?}", err),
+        }
+        let why = Error::PhenotypeSampleMismatch {
+            pheno: "height".to_string(),
+            num_pheno_people: 9,
+            num_geno_people: 10,
+        }
+        .to_string();
+        assert!(why.contains("height has 9 people"));
+        assert!(why.contains("have 10 people"));
+    }
+}
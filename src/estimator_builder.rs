@@ -0,0 +1,638 @@
+//! A builder for the partitioned heritability estimation, in place of the
+//! long positional argument lists of `estimate_heritability`.
+//!
+//! ```ignore
+//! let table = HeritabilityEstimatorBuilder::new()
+//!     .plink(bed, bim)
+//!     .phenotype("height.pheno")
+//!     .num_random_vecs(100)
+//!     .jackknife(JackknifeConfig::blocks(20))
+//!     .seed(42)
+//!     .run()?;
+//! ```
+
+use std::collections::HashMap;
+
+use biofile::{
+    plink_bed::PlinkBed,
+    plink_bim::{FilelinePartitions, PlinkBim},
+};
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{Array, Axis, Ix1, Ix2};
+
+use crate::{
+    checkpoint::CheckpointConfig,
+    compute::ComputeConfig,
+    covariate::CovariateProjection,
+    error::Error,
+    exact::TraceMode,
+    heritability_estimator::{
+        estimate_heritability_loco, estimate_heritability_multi_pheno,
+        Coordinate,
+    },
+    integer_set::iter::Iter,
+    missing_genotype::MissingGenotypePolicy,
+    partitioned_jackknife_estimates::{LocoEstimates, PhenoEstimateTable},
+    progress::{ProgressSink, StdoutProgress},
+    resampling::{JackknifeConfig, Resampling},
+    snp_weights::SnpWeights,
+    util::{
+        check_pheno_num_people, get_fam_aligned_pheno_arr, get_pheno_arr,
+        matrix_util::ProbeType, trace_metadata::Standardization,
+    },
+};
+
+pub const DEFAULT_NUM_JACKKNIFE_BLOCKS: usize = 20;
+
+/// A phenotype to estimate the heritability of, either read from a file with
+/// the header `FID IID PHENO` or given as an array over the people in the
+/// bed.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PhenotypeInput {
+    Path(String),
+    Array {
+        name: String,
+        values: Array<f32, Ix1>,
+    },
+}
+
+impl PhenotypeInput {
+    /// The name of the phenotype in the estimates, which is the path for the
+    /// phenotypes read from a file
+    pub fn name(&self) -> &str {
+        match self {
+            PhenotypeInput::Path(path) => path,
+            PhenotypeInput::Array {
+                name, ..
+            } => name,
+        }
+    }
+
+    /// The phenotypes read from a file are aligned to the `fam_path` if given
+    fn get_values(
+        &self,
+        fam_path: Option<&str>,
+    ) -> Result<Array<f32, Ix1>, Error> {
+        match self {
+            PhenotypeInput::Path(path) => match fam_path {
+                Some(fam_path) => {
+                    Ok(get_fam_aligned_pheno_arr(fam_path, path)?)
+                }
+                None => Ok(get_pheno_arr(path)?),
+            },
+            PhenotypeInput::Array {
+                values, ..
+            } => Ok(values.clone()),
+        }
+    }
+}
+
+impl From<&str> for PhenotypeInput {
+    fn from(path: &str) -> PhenotypeInput {
+        PhenotypeInput::Path(path.to_string())
+    }
+}
+
+impl From<String> for PhenotypeInput {
+    fn from(path: String) -> PhenotypeInput {
+        PhenotypeInput::Path(path)
+    }
+}
+
+impl From<(&str, Array<f32, Ix1>)> for PhenotypeInput {
+    fn from((name, values): (&str, Array<f32, Ix1>)) -> PhenotypeInput {
+        PhenotypeInput::Array {
+            name: name.to_string(),
+            values,
+        }
+    }
+}
+
+/// Configures and runs the partitioned heritability estimation. Only the
+/// genotypes, at least one phenotype and the number of random vectors are
+/// required. The other settings default to those of the
+/// `estimate_heritability` binary: a block jackknife over
+/// `DEFAULT_NUM_JACKKNIFE_BLOCKS` blocks, dense probes, no covariates and
+/// the progress printed to stdout.
+pub struct HeritabilityEstimatorBuilder {
+    plink: Option<(PlinkBed, PlinkBim<Coordinate>)>,
+    fam_path: Option<String>,
+    phenotypes: Vec<PhenotypeInput>,
+    pheno_matrix: Option<(Array<f32, Ix2>, Vec<String>)>,
+    covariates: Option<CovariateProjection>,
+    people: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<SnpWeights>,
+    standardization: Standardization,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    num_random_vecs: Option<usize>,
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    max_num_random_vecs: Option<usize>,
+    trace_tolerance: Option<f64>,
+    resampling: Resampling,
+    components: Option<HashMap<String, OrderedIntegerSet<Coordinate>>>,
+    analytical_se: bool,
+    num_parametric_bootstrap_reps: Option<usize>,
+    keep_resampling_blocks: bool,
+    checkpoint: Option<CheckpointConfig>,
+    compute: Option<ComputeConfig>,
+    seed: Option<u64>,
+    progress: Box<dyn ProgressSink>,
+}
+
+impl Default for HeritabilityEstimatorBuilder {
+    fn default() -> HeritabilityEstimatorBuilder {
+        HeritabilityEstimatorBuilder::new()
+    }
+}
+
+impl HeritabilityEstimatorBuilder {
+    pub fn new() -> HeritabilityEstimatorBuilder {
+        HeritabilityEstimatorBuilder {
+            plink: None,
+            fam_path: None,
+            phenotypes: Vec::new(),
+            pheno_matrix: None,
+            covariates: None,
+            people: None,
+            snp_weights: None,
+            standardization: Standardization::UnitVariance,
+            missing_genotypes: MissingGenotypePolicy::default(),
+            nonnegative: false,
+            num_random_vecs: None,
+            probe_type: ProbeType::default(),
+            probe_sparsity: None,
+            trace_mode: TraceMode::default(),
+            max_num_random_vecs: None,
+            trace_tolerance: None,
+            resampling: Resampling::BlockJackknife {
+                num_blocks: DEFAULT_NUM_JACKKNIFE_BLOCKS,
+            },
+            components: None,
+            analytical_se: false,
+            num_parametric_bootstrap_reps: None,
+            keep_resampling_blocks: false,
+            checkpoint: None,
+            compute: None,
+            seed: None,
+            progress: Box::new(StdoutProgress),
+        }
+    }
+
+    pub fn plink(
+        mut self,
+        bed: PlinkBed,
+        bim: PlinkBim<Coordinate>,
+    ) -> HeritabilityEstimatorBuilder {
+        self.plink = Some((bed, bim));
+        self
+    }
+
+    /// The fam file of the genotypes, to which the phenotypes read from a
+    /// file are joined by (FID, IID). Otherwise the phenotype files have to
+    /// list the people in the order of the bed.
+    pub fn fam(mut self, fam_path: &str) -> HeritabilityEstimatorBuilder {
+        self.fam_path = Some(fam_path.to_string());
+        self
+    }
+
+    /// Adds a phenotype, given as a path or as a `(name, values)` pair. The
+    /// phenotypes share the trace estimates and are estimated together.
+    pub fn phenotype<P: Into<PhenotypeInput>>(
+        mut self,
+        phenotype: P,
+    ) -> HeritabilityEstimatorBuilder {
+        self.phenotypes.push(phenotype.into());
+        self
+    }
+
+    /// Sets the phenotypes to the columns of `pheno_matrix`, named by
+    /// `pheno_names`, with one row for each of the people in the estimation,
+    /// i.e. only of those kept with `keep` if it is set. This is for the
+    /// phenotypes that are already aligned and adjusted, e.g. with the fixed
+    /// SNP effects regressed out, and cannot be combined with `phenotype`.
+    pub fn phenotype_matrix(
+        mut self,
+        pheno_matrix: Array<f32, Ix2>,
+        pheno_names: Vec<String>,
+    ) -> HeritabilityEstimatorBuilder {
+        self.pheno_matrix = Some((pheno_matrix, pheno_names));
+        self
+    }
+
+    /// The covariates of the people in the estimation, i.e. only of those
+    /// kept with `keep` if it is set
+    pub fn covariates(
+        mut self,
+        covariates: CovariateProjection,
+    ) -> HeritabilityEstimatorBuilder {
+        self.covariates = Some(covariates);
+        self
+    }
+
+    /// Restricts the estimation to the `people`, by their indices in the bed,
+    /// e.g. from `get_keep_indices`. The phenotypes still have a value for
+    /// everyone in the bed.
+    pub fn keep(
+        mut self,
+        people: OrderedIntegerSet<usize>,
+    ) -> HeritabilityEstimatorBuilder {
+        self.people = Some(people);
+        self
+    }
+
+    /// Weights the SNPs in the kernels, e.g. by the LDAK weights
+    pub fn snp_weights(
+        mut self,
+        snp_weights: SnpWeights,
+    ) -> HeritabilityEstimatorBuilder {
+        self.snp_weights = Some(snp_weights);
+        self
+    }
+
+    /// Scales the centered genotypes by `[2p(1-p)]^(alpha / 2)` for their
+    /// allele frequencies `p`, e.g. with `alpha = -0.25` as in LDAK, instead
+    /// of to unit variance. The allele frequencies are computed from the bed.
+    pub fn alpha(mut self, alpha: f64) -> HeritabilityEstimatorBuilder {
+        self.standardization = Standardization::from_alpha(alpha);
+        self
+    }
+
+    /// How the missing genotypes are handled, mean imputation by default
+    pub fn missing_genotypes(
+        mut self,
+        policy: MissingGenotypePolicy,
+    ) -> HeritabilityEstimatorBuilder {
+        self.missing_genotypes = policy;
+        self
+    }
+
+    pub fn num_random_vecs(
+        mut self,
+        num_random_vecs: usize,
+    ) -> HeritabilityEstimatorBuilder {
+        self.num_random_vecs = Some(num_random_vecs);
+        self
+    }
+
+    /// Draws the random probes from this distribution, Rademacher by default
+    pub fn probe_type(
+        mut self,
+        probe_type: ProbeType,
+    ) -> HeritabilityEstimatorBuilder {
+        self.probe_type = probe_type;
+        self
+    }
+
+    /// Uses sparse sign probes with this fraction of nonzero entries, which
+    /// requires the Rademacher probes
+    pub fn probe_sparsity(
+        mut self,
+        probe_sparsity: f64,
+    ) -> HeritabilityEstimatorBuilder {
+        self.probe_sparsity = Some(probe_sparsity);
+        self
+    }
+
+    /// Whether the traces are computed exactly, with one probe per person, or
+    /// estimated with the random probes. By default they are exact below
+    /// `DEFAULT_EXACT_MAX_NUM_PEOPLE` people.
+    pub fn trace_mode(
+        mut self,
+        trace_mode: TraceMode,
+    ) -> HeritabilityEstimatorBuilder {
+        self.trace_mode = trace_mode;
+        self
+    }
+
+    /// Adds probes up to this many for the partitions dominated by the Monte
+    /// Carlo error of the trace estimates
+    pub fn max_num_random_vecs(
+        mut self,
+        max_num_random_vecs: usize,
+    ) -> HeritabilityEstimatorBuilder {
+        self.max_num_random_vecs = Some(max_num_random_vecs);
+        self
+    }
+
+    /// Adds batches of probes until the relative Monte Carlo standard error
+    /// of `tr(K K)` is at most `tolerance` for every partition, up to
+    /// `max_num_random_vecs` probes if it is set
+    pub fn trace_tolerance(
+        mut self,
+        tolerance: f64,
+    ) -> HeritabilityEstimatorBuilder {
+        self.trace_tolerance = Some(tolerance);
+        self
+    }
+
+    pub fn resampling(
+        mut self,
+        resampling: Resampling,
+    ) -> HeritabilityEstimatorBuilder {
+        self.resampling = resampling;
+        self
+    }
+
+    /// The standard errors from a jackknife over the SNPs
+    pub fn jackknife(
+        self,
+        config: JackknifeConfig,
+    ) -> HeritabilityEstimatorBuilder {
+        self.resampling(config.into())
+    }
+
+    /// The SNPs of each variance component, by the line numbers in the bim,
+    /// replacing the partitions the bim may already have
+    pub fn components(
+        mut self,
+        components: HashMap<String, OrderedIntegerSet<Coordinate>>,
+    ) -> HeritabilityEstimatorBuilder {
+        self.components = Some(components);
+        self
+    }
+
+    /// Constrains the variance components to be nonnegative
+    pub fn nonnegative(
+        mut self,
+        nonnegative: bool,
+    ) -> HeritabilityEstimatorBuilder {
+        self.nonnegative = nonnegative;
+        self
+    }
+
+    pub fn analytical_se(
+        mut self,
+        analytical_se: bool,
+    ) -> HeritabilityEstimatorBuilder {
+        self.analytical_se = analytical_se;
+        self
+    }
+
+    /// Also computes the percentile intervals from `num_reps` parametric
+    /// bootstrap draws of the normal equations, which requires a resampling
+    /// with at least two replicates
+    pub fn parametric_bootstrap(
+        mut self,
+        num_reps: usize,
+    ) -> HeritabilityEstimatorBuilder {
+        self.num_parametric_bootstrap_reps = Some(num_reps);
+        self
+    }
+
+    /// Keeps the SNP blocks of the resampling replicates in the estimates,
+    /// next to the estimates of every replicate
+    pub fn keep_resampling_blocks(
+        mut self,
+        keep_resampling_blocks: bool,
+    ) -> HeritabilityEstimatorBuilder {
+        self.keep_resampling_blocks = keep_resampling_blocks;
+        self
+    }
+
+    pub fn checkpoint(
+        mut self,
+        checkpoint: CheckpointConfig,
+    ) -> HeritabilityEstimatorBuilder {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// The threads and the memory budget of the estimation
+    pub fn compute(
+        mut self,
+        compute: ComputeConfig,
+    ) -> HeritabilityEstimatorBuilder {
+        self.compute = Some(compute);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> HeritabilityEstimatorBuilder {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Reports the progress to the `progress` sink instead of stdout, e.g.
+    /// `SilentProgress` to report nothing
+    pub fn progress<P: ProgressSink + 'static>(
+        mut self,
+        progress: P,
+    ) -> HeritabilityEstimatorBuilder {
+        self.progress = Box::new(progress);
+        self
+    }
+
+    /// Estimates the heritability of every phenotype, with the standard
+    /// errors from the `resampling`
+    pub fn run(mut self) -> Result<PhenoEstimateTable, Error> {
+        let inputs = self.take_inputs()?;
+        estimate_heritability_multi_pheno(
+            inputs.bed,
+            inputs.bim,
+            inputs.pheno_matrix,
+            inputs.pheno_names,
+            inputs.num_random_vecs,
+            self.probe_type,
+            self.probe_sparsity,
+            self.trace_mode,
+            self.max_num_random_vecs,
+            self.trace_tolerance,
+            self.resampling,
+            self.covariates.as_ref(),
+            self.people.as_ref(),
+            inputs.snp_weights.as_ref(),
+            self.missing_genotypes,
+            self.nonnegative,
+            self.analytical_se,
+            self.num_parametric_bootstrap_reps,
+            self.keep_resampling_blocks,
+            self.checkpoint.as_ref(),
+            self.compute.as_ref(),
+            self.seed,
+            self.progress.as_ref(),
+        )
+    }
+
+    /// Estimates the heritability of every phenotype with each chromosome of
+    /// the bim left out in turn, with the standard errors from the jackknife
+    /// over the chromosomes. The `resampling`, the adaptive probes, the
+    /// analytical standard errors, the parametric bootstrap and the
+    /// checkpoint do not apply.
+    pub fn run_loco(mut self) -> Result<Vec<LocoEstimates>, Error> {
+        let inputs = self.take_inputs()?;
+        estimate_heritability_loco(
+            inputs.bed,
+            inputs.bim,
+            inputs.pheno_matrix,
+            inputs.pheno_names,
+            inputs.num_random_vecs,
+            self.probe_type,
+            self.probe_sparsity,
+            self.trace_mode,
+            self.covariates.as_ref(),
+            self.people.as_ref(),
+            inputs.snp_weights.as_ref(),
+            self.missing_genotypes,
+            self.nonnegative,
+            self.compute.as_ref(),
+            self.seed,
+            self.progress.as_ref(),
+        )
+    }
+
+    /// Checks the settings and takes the genotypes, the phenotype matrix of
+    /// the people in the estimation and the SNP weights with the
+    /// standardization applied
+    fn take_inputs(&mut self) -> Result<EstimationInputs, Error> {
+        let (bed, mut bim) = self
+            .plink
+            .take()
+            .ok_or_else(|| "the genotypes are not set".to_string())?;
+        if self.phenotypes.is_empty() && self.pheno_matrix.is_none() {
+            return Err(Error::Generic("no phenotypes are set".to_string()));
+        }
+        if !self.phenotypes.is_empty() && self.pheno_matrix.is_some() {
+            return Err(Error::Generic(
+                "the phenotype matrix cannot be combined with other phenotypes"
+                    .to_string(),
+            ));
+        }
+        let num_random_vecs = self.num_random_vecs.ok_or_else(|| {
+            "the number of random vectors is not set".to_string()
+        })?;
+        if self.probe_sparsity.is_some()
+            && self.probe_type != ProbeType::Rademacher
+        {
+            return Err(Error::Generic(format!(
+                "the probe sparsity requires the rademacher probes, not the {} \
+                probes",
+                self.probe_type
+            )));
+        }
+        if let Some(components) = self.components.take() {
+            bim.set_fileline_partitions(Some(FilelinePartitions::new(
+                components,
+            )));
+        }
+        let (pheno_matrix, pheno_names) = match self.pheno_matrix.take() {
+            Some((pheno_matrix, pheno_names)) => {
+                if pheno_matrix.dim().1 != pheno_names.len() {
+                    return Err(Error::DimensionMismatch(format!(
+                        "{} names for the {} columns of the phenotype matrix",
+                        pheno_names.len(),
+                        pheno_matrix.dim().1
+                    )));
+                }
+                check_pheno_num_people(
+                    pheno_matrix.dim().0,
+                    &pheno_names.join(", "),
+                    self.people
+                        .as_ref()
+                        .map_or(bed.num_people, |people| people.size()),
+                )?;
+                (pheno_matrix, pheno_names)
+            }
+            None => self.get_pheno_matrix(bed.num_people)?,
+        };
+        let snp_weights = match self.standardization {
+            Standardization::UnitVariance => self.snp_weights.take(),
+            Standardization::Alpha(alpha) => {
+                let alpha_weights = SnpWeights::from_alpha(
+                    alpha,
+                    &bed.get_minor_allele_frequencies(None),
+                )?;
+                Some(match &self.snp_weights {
+                    Some(w) => w.product(&alpha_weights)?,
+                    None => alpha_weights,
+                })
+            }
+        };
+        Ok(EstimationInputs {
+            bed,
+            bim,
+            pheno_matrix,
+            pheno_names,
+            num_random_vecs,
+            snp_weights,
+        })
+    }
+
+    /// The matrix of the phenotypes added with `phenotype`, restricted to the
+    /// people in the estimation
+    fn get_pheno_matrix(
+        &self,
+        num_people: usize,
+    ) -> Result<(Array<f32, Ix2>, Vec<String>), Error> {
+        let pheno_names: Vec<String> = self
+            .phenotypes
+            .iter()
+            .map(|p| p.name().to_string())
+            .collect();
+        let mut pheno_matrix =
+            Array::<f32, Ix2>::zeros((num_people, self.phenotypes.len()));
+        for (i, phenotype) in self.phenotypes.iter().enumerate() {
+            let values = phenotype.get_values(self.fam_path.as_deref())?;
+            check_pheno_num_people(values.len(), phenotype.name(), num_people)?;
+            pheno_matrix.column_mut(i).assign(&values);
+        }
+        if let Some(people) = &self.people {
+            pheno_matrix = pheno_matrix
+                .select(Axis(0), &people.iter().collect::<Vec<usize>>());
+        }
+        Ok((pheno_matrix, pheno_names))
+    }
+}
+
+/// The inputs of the estimation taken out of the builder by `take_inputs`
+struct EstimationInputs {
+    bed: PlinkBed,
+    bim: PlinkBim<Coordinate>,
+    pheno_matrix: Array<f32, Ix2>,
+    pheno_names: Vec<String>,
+    num_random_vecs: usize,
+    snp_weights: Option<SnpWeights>,
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{HeritabilityEstimatorBuilder, PhenotypeInput};
+    use crate::{
+        progress::SilentProgress,
+        resampling::{JackknifeConfig, Resampling},
+    };
+
+    #[test]
+    fn test_heritability_estimator_builder() {
+        let path = PhenotypeInput::from("height.pheno");
+        assert_eq!(path.name(), "height.pheno");
+        let arr = PhenotypeInput::from(("bmi", array![1f32, 2., 3.]));
+        assert_eq!(arr.name(), "bmi");
+        assert_eq!(arr.get_values(None).unwrap(), array![1f32, 2., 3.]);
+
+        let builder = HeritabilityEstimatorBuilder::new()
+            .phenotype("height.pheno")
+            .phenotype(("bmi", array![1f32, 2., 3.]))
+            .jackknife(JackknifeConfig::blocks(10))
+            .progress(SilentProgress);
+        assert_eq!(builder.phenotypes, vec![path, arr]);
+        assert_eq!(builder.resampling, Resampling::BlockJackknife {
+            num_blocks: 10
+        });
+        assert!(builder.run().is_err());
+
+        let builder = HeritabilityEstimatorBuilder::new()
+            .phenotype_matrix(array![[1f32], [2.], [3.]], vec![
+                "bmi".to_string()
+            ])
+            .progress(SilentProgress);
+        assert_eq!(
+            builder.pheno_matrix,
+            Some((array![[1f32], [2.], [3.]], vec!["bmi".to_string()]))
+        );
+        assert!(builder.run_loco().is_err());
+    }
+}
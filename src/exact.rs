@@ -0,0 +1,328 @@
+//! The exact path for small cohorts, in which the kernels are formed
+//! explicitly in memory and the traces of the normal equations are computed
+//! exactly instead of being estimated with random probes. The estimates
+//! then have no Monte Carlo error, which also makes them the reference for
+//! validating the randomized estimates.
+//!
+//! Every kernel takes `num_people^2` floats, so the exact path is only
+//! selected automatically below `DEFAULT_EXACT_MAX_NUM_PEOPLE` people.
+
+use std::{fmt, str::FromStr};
+
+use math::{
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+    stats::n_choose_2,
+};
+use ndarray::{Array, Ix1, Ix2, Zip};
+
+use crate::{
+    stochastic::GenotypeSource,
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+pub const DEFAULT_EXACT_MAX_NUM_PEOPLE: usize = 2000;
+
+/// How the traces of the normal equations are computed
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TraceMode {
+    /// the traces are estimated with random probe vectors
+    Randomized,
+    /// the traces are computed exactly from the kernels
+    Exact,
+    /// exact for fewer than `max_num_people` people, randomized otherwise
+    Auto { max_num_people: usize },
+}
+
+impl TraceMode {
+    pub fn is_exact(&self, num_people: usize) -> bool {
+        match self {
+            TraceMode::Randomized => false,
+            TraceMode::Exact => true,
+            TraceMode::Auto {
+                max_num_people,
+            } => num_people < *max_num_people,
+        }
+    }
+}
+
+impl Default for TraceMode {
+    fn default() -> TraceMode {
+        TraceMode::Auto {
+            max_num_people: DEFAULT_EXACT_MAX_NUM_PEOPLE,
+        }
+    }
+}
+
+impl FromStr for TraceMode {
+    type Err = String;
+
+    /// "auto" selects the exact path below `DEFAULT_EXACT_MAX_NUM_PEOPLE`
+    /// people
+    fn from_str(s: &str) -> Result<TraceMode, String> {
+        match s {
+            "randomized" => Ok(TraceMode::Randomized),
+            "exact" => Ok(TraceMode::Exact),
+            "auto" => Ok(TraceMode::default()),
+            _ => Err(format!(
+                "unrecognized trace mode {}, expected one of randomized, \
+                exact and auto",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TraceMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceMode::Randomized => write!(f, "randomized"),
+            TraceMode::Exact => write!(f, "exact"),
+            TraceMode::Auto {
+                max_num_people,
+            } => write!(f, "auto (exact below {} people)", max_num_people),
+        }
+    }
+}
+
+fn get_num_snps<G: GenotypeSource>(
+    geno: &G,
+    snp_range: &Option<OrderedIntegerSet<usize>>,
+) -> Result<usize, String> {
+    let num_snps = match snp_range {
+        Some(range) => range.size(),
+        None => geno.num_snps(),
+    };
+    if num_snps == 0 {
+        return Err("expected at least one SNP for the kernel".to_string());
+    }
+    Ok(num_snps)
+}
+
+/// The additive kernel `K = X X^T / m` of the standardized genotypes `X` of
+/// the `m` SNPs in `snp_range`, or all the SNPs if `None`
+pub fn get_g_kernel<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    num_snps_per_chunk: usize,
+) -> Result<Array<f64, Ix2>, String> {
+    let num_snps = get_num_snps(geno, &snp_range)?;
+    let num_people = geno.num_people();
+    let xxt = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk,
+        || Array::<f64, Ix2>::zeros((num_people, num_people)),
+        |acc, _, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            acc + &snp_chunk.dot(&snp_chunk.t()).mapv(|x| x as f64)
+        },
+        |a, b| a + &b,
+    );
+    Ok(xxt / num_snps as f64)
+}
+
+/// The Gram matrices `S = X X^T` and `H = (X * X) (X * X)^T` of the
+/// standardized genotypes `X` of a SNP range, where `*` is the element-wise
+/// product, from which the GxG kernels of the range are formed
+pub struct GxgGramMatrices {
+    s: Array<f64, Ix2>,
+    h: Array<f64, Ix2>,
+    num_snps: usize,
+}
+
+impl GxgGramMatrices {
+    pub fn new<G: GenotypeSource>(
+        geno: &G,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        num_snps_per_chunk: usize,
+    ) -> Result<GxgGramMatrices, String> {
+        let num_snps = get_num_snps(geno, &snp_range)?;
+        let num_people = geno.num_people();
+        let (s, h) = geno.fold_snp_chunks(
+            snp_range,
+            num_snps_per_chunk,
+            || {
+                (
+                    Array::<f64, Ix2>::zeros((num_people, num_people)),
+                    Array::<f64, Ix2>::zeros((num_people, num_people)),
+                )
+            },
+            |(s, h), _, mut snp_chunk| {
+                normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+                let sq_chunk = &snp_chunk * &snp_chunk;
+                (
+                    s + &snp_chunk.dot(&snp_chunk.t()).mapv(|x| x as f64),
+                    h + &sq_chunk.dot(&sq_chunk.t()).mapv(|x| x as f64),
+                )
+            },
+            |(s_a, h_a), (s_b, h_b)| (s_a + &s_b, h_a + &h_b),
+        );
+        Ok(GxgGramMatrices {
+            s,
+            h,
+            num_snps,
+        })
+    }
+
+    pub fn num_snps(&self) -> usize {
+        self.num_snps
+    }
+
+    /// `K_gxg = sum_{i < j} (x_i * x_j) (x_i * x_j)^T / (m choose 2)`, which
+    /// is `(S * S - H) / 2 / (m choose 2)`
+    pub fn intra_kernel(&self) -> Result<Array<f64, Ix2>, String> {
+        if self.num_snps < 2 {
+            return Err(format!(
+                "expected at least 2 SNPs for the GxG kernel, received {}",
+                self.num_snps
+            ));
+        }
+        let denom = 2. * n_choose_2(self.num_snps) as f64;
+        let mut kernel = &self.s * &self.s;
+        Zip::from(&mut kernel)
+            .and(&self.h)
+            .apply(|k, &h| *k = (*k - h) / denom);
+        Ok(kernel)
+    }
+
+    /// The kernel of the products of one SNP from each of the two ranges,
+    /// `S_a * S_b / (m_a m_b)`
+    pub fn inter_kernel(&self, other: &GxgGramMatrices) -> Array<f64, Ix2> {
+        &self.s * &other.s / (self.num_snps * other.num_snps) as f64
+    }
+}
+
+/// The normal equations `A x = b` of the `kernels` and the noise for the
+/// phenotype `y`, with `A_ij = tr(K_i K_j)`, `A_i,noise = tr(K_i)`,
+/// `A_noise,noise = n`, `b_i = y^T K_i y` and `b_noise = y^T y`, all
+/// computed exactly.
+pub fn get_exact_normal_equations(
+    kernels: &[Array<f64, Ix2>],
+    y: &Array<f32, Ix1>,
+) -> (Array<f64, Ix2>, Array<f64, Ix1>) {
+    let num_kernels = kernels.len();
+    let y = y.mapv(|x| x as f64);
+    let mut a = Array::zeros((num_kernels + 1, num_kernels + 1));
+    let mut b = Array::zeros(num_kernels + 1);
+    for i in 0..num_kernels {
+        for j in i..num_kernels {
+            // both kernels are symmetric
+            let tr_ki_kj = (&kernels[i] * &kernels[j]).sum();
+            a[[i, j]] = tr_ki_kj;
+            a[[j, i]] = tr_ki_kj;
+        }
+        let tr_k = kernels[i].diag().sum();
+        a[[i, num_kernels]] = tr_k;
+        a[[num_kernels, i]] = tr_k;
+        b[i] = y.dot(&kernels[i].dot(&y));
+    }
+    a[[num_kernels, num_kernels]] = y.len() as f64;
+    b[num_kernels] = y.dot(&y);
+    (a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, s, Array, Ix2};
+
+    use super::{
+        get_exact_normal_equations, get_g_kernel, GxgGramMatrices, TraceMode,
+    };
+    use crate::{
+        stochastic::{tr_gxg_k, tr_k, tr_kk, y_k_y},
+        util::matrix_util::normalize_matrix_columns_inplace,
+    };
+
+    #[test]
+    fn test_exact_kernels() {
+        let geno = array![
+            [0f32, 1., 2., 0.],
+            [1., 0., 1., 2.],
+            [2., 2., 0., 1.],
+            [0., 1., 1., 1.],
+            [1., 0., 2., 2.],
+            [2., 2., 1., 0.],
+            [1., 1., 0., 2.]
+        ];
+        let k = get_g_kernel(&geno, None, 3).unwrap();
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let expected = x.dot(&x.t()).mapv(|v| v as f64) / 4.;
+        assert!(k
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+        assert!(
+            (k.diag().sum()
+                - tr_k(&geno, None, None)
+                    .unwrap()
+                    .point_estimate_without_jackknife)
+                .abs()
+                < 1e-4
+        );
+        // the randomized estimate of tr(K K) is within its Monte Carlo error
+        let tr_kk_est = tr_kk(&geno, None, 2000, None, None).unwrap();
+        let tr_kk_exact = (&k * &k).sum();
+        assert!(
+            (tr_kk_est.point_estimate_without_jackknife - tr_kk_exact).abs()
+                < 0.1 * tr_kk_exact
+        );
+
+        // the GxG kernel from the explicit pairwise products
+        let range = Some(OrderedIntegerSet::from_slice(&[[0, 2]]));
+        let grams = GxgGramMatrices::new(&geno, range.clone(), 2).unwrap();
+        assert_eq!(grams.num_snps(), 3);
+        let mut products = Array::<f32, Ix2>::zeros((7, 3));
+        for (c, (i, j)) in [(0, 1), (0, 2), (1, 2)].iter().enumerate() {
+            products
+                .column_mut(c)
+                .assign(&(&x.column(*i) * &x.column(*j)));
+        }
+        let expected = products.dot(&products.t()).mapv(|v| v as f64) / 3.;
+        let gxg_k = grams.intra_kernel().unwrap();
+        assert!(gxg_k
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-4));
+        let tr_gxg_k_est = tr_gxg_k(&geno, range, 2000, None, None).unwrap();
+        assert!(
+            (tr_gxg_k_est.point_estimate_without_jackknife
+                - gxg_k.diag().sum())
+            .abs()
+                < 0.1 * gxg_k.diag().sum()
+        );
+        let single = GxgGramMatrices::new(
+            &geno,
+            Some(OrderedIntegerSet::from_slice(&[[3, 3]])),
+            2,
+        )
+        .unwrap();
+        assert!(single.intra_kernel().is_err());
+        let inter = grams.inter_kernel(&single);
+        let s_a = x.slice(s![0, 0..3]).dot(&x.slice(s![1, 0..3]));
+        let s_b = x[[0, 3]] * x[[1, 3]];
+        assert!((inter[[0, 1]] - (s_a * s_b) as f64 / 3.).abs() < 1e-4);
+
+        let y = array![0.5f32, -1., 1.5, 0., -0.5, 1., -1.5];
+        let (a, b) = get_exact_normal_equations(&[k.clone()], &y);
+        assert_eq!(a.dim(), (2, 2));
+        assert!((a[[0, 0]] - tr_kk_exact).abs() < 1e-6);
+        assert_eq!(a[[1, 1]], 7.);
+        assert!(
+            (b[0]
+                - y_k_y(&geno, None, &y, None)
+                    .unwrap()
+                    .point_estimate_without_jackknife)
+                .abs()
+                < 1e-3
+        );
+        assert!((b[1] - 7.).abs() < 1e-6);
+
+        assert!(TraceMode::default().is_exact(100));
+        assert!(!TraceMode::default().is_exact(1_000_000));
+        assert!(TraceMode::Exact.is_exact(1_000_000));
+        assert!(!TraceMode::Randomized.is_exact(1));
+        assert_eq!("exact".parse::<TraceMode>(), Ok(TraceMode::Exact));
+        assert!("fast".parse::<TraceMode>().is_err());
+    }
+}
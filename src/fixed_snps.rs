@@ -0,0 +1,223 @@
+//! The first stage of a two-stage estimation, in which a few loci of large
+//! effect, e.g. the hits of a preliminary GWAS, are fit as fixed effects and
+//! regressed out of the phenotypes. The variance components are then
+//! estimated on the residuals with the fixed SNPs left out of the kernels, so
+//! that a large QTL does not inflate the heritability of the polygenic
+//! background.
+
+use std::collections::{HashMap, HashSet};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{s, Array, Ix2};
+
+use crate::{
+    covariate::CovariateProjection, error::Error,
+    integer_set::collect::SortedCollecting,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK, stochastic::GenotypeSource,
+    util::get_file_line_tokens,
+};
+
+/// Reads the SNP IDs in `path`, one per line, without the duplicates
+pub fn read_snp_id_list(path: &str) -> Result<Vec<String>, Error> {
+    let mut seen = HashSet::new();
+    let mut ids = Vec::new();
+    for toks in get_file_line_tokens(path, 1)?.into_iter() {
+        if seen.insert(toks[0].clone()) {
+            ids.push(toks[0].clone());
+        }
+    }
+    Ok(ids)
+}
+
+/// The fixed-effect SNPs by their indices in the bed file formed from the
+/// `bim_paths` in order, sorted by the index
+#[derive(Clone, PartialEq, Debug)]
+pub struct FixedSnps {
+    snp_ids: Vec<String>,
+    snp_indices: Vec<usize>,
+}
+
+impl FixedSnps {
+    pub fn from_bim_files(
+        snp_ids: &[String],
+        bim_paths: &[String],
+    ) -> Result<FixedSnps, Error> {
+        let mut id_to_index = HashMap::new();
+        let mut offset = 0;
+        for path in bim_paths.iter() {
+            let lines = get_file_line_tokens(path, 6)?;
+            for (i, toks) in lines.iter().enumerate() {
+                id_to_index.entry(toks[1].clone()).or_insert(offset + i);
+            }
+            offset += lines.len();
+        }
+        let mut snps = snp_ids
+            .iter()
+            .map(|id| match id_to_index.get(id) {
+                Some(&i) => Ok((i, id.clone())),
+                None => Err(Error::Generic(format!(
+                    "the fixed-effect SNP {} is not in the bim files {:?}",
+                    id, bim_paths
+                ))),
+            })
+            .collect::<Result<Vec<(usize, String)>, Error>>()?;
+        if snps.is_empty() {
+            return Err(Error::Generic(
+                "expected at least one fixed-effect SNP".to_string(),
+            ));
+        }
+        snps.sort();
+        snps.dedup();
+        let (snp_indices, snp_ids) = snps.into_iter().unzip();
+        Ok(FixedSnps {
+            snp_ids,
+            snp_indices,
+        })
+    }
+
+    pub fn num_snps(&self) -> usize {
+        self.snp_indices.len()
+    }
+
+    pub fn snp_ids(&self) -> &[String] {
+        &self.snp_ids
+    }
+
+    /// The SNP indices as a set, e.g. to leave the SNPs out of the kernels
+    pub fn to_set(&self) -> OrderedIntegerSet<usize> {
+        OrderedIntegerSet::collect_from_sorted_iter(
+            self.snp_indices.iter().cloned(),
+        )
+    }
+
+    /// Streams the raw genotypes of the fixed SNPs from `geno` into a matrix
+    /// with one column per SNP
+    pub fn get_genotypes<G: GenotypeSource>(
+        &self,
+        geno: &G,
+    ) -> Result<Array<f32, Ix2>, Error> {
+        if let Some(&last) = self.snp_indices.last() {
+            if last >= geno.num_snps() {
+                return Err(Error::DimensionMismatch(format!(
+                    "fixed-effect SNP index {} out of range for {} SNPs",
+                    last,
+                    geno.num_snps()
+                )));
+            }
+        }
+        let num_people = geno.num_people();
+        let num_snps = self.num_snps();
+        Ok(geno.fold_snp_chunks(
+            Some(self.to_set()),
+            DEFAULT_NUM_SNPS_PER_CHUNK,
+            || Array::<f32, Ix2>::zeros((num_people, num_snps)),
+            |mut acc, rank, snp_chunk| {
+                let len = snp_chunk.dim().1;
+                acc.slice_mut(s![.., rank..rank + len]).assign(&snp_chunk);
+                acc
+            },
+            |a, b| a + &b,
+        ))
+    }
+
+    /// Regresses the genotypes of the fixed SNPs and an intercept out of
+    /// every column of `pheno_matrix` in place, returning the fixed effects
+    /// of the SNPs on the phenotypes
+    pub fn regress_out_inplace<G: GenotypeSource>(
+        &self,
+        geno: &G,
+        pheno_matrix: &mut Array<f32, Ix2>,
+    ) -> Result<FixedSnpEffects, Error> {
+        if pheno_matrix.dim().0 != geno.num_people() {
+            return Err(Error::DimensionMismatch(format!(
+                "a phenotype matrix of {} people for genotypes of {} people",
+                pheno_matrix.dim().0,
+                geno.num_people()
+            )));
+        }
+        let projection = CovariateProjection::new(&self.get_genotypes(geno)?)
+            .map_err(Error::Generic)?;
+        let coefficients = projection.get_coefficients(pheno_matrix);
+        projection.project_matrix_inplace(pheno_matrix);
+        Ok(FixedSnpEffects {
+            snp_ids: self.snp_ids.clone(),
+            effects: coefficients.slice(s![1.., ..]).to_owned(),
+        })
+    }
+}
+
+/// The effects of the fixed SNPs estimated in the first stage
+#[derive(Clone, PartialEq, Debug)]
+pub struct FixedSnpEffects {
+    pub snp_ids: Vec<String>,
+    /// `effects[[i, p]]` is the effect of the `i`-th SNP on phenotype `p`
+    /// per copy of the counted allele
+    pub effects: Array<f32, Ix2>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array, Axis, Ix2};
+    use tempfile::NamedTempFile;
+
+    use super::{read_snp_id_list, FixedSnps};
+
+    #[test]
+    fn test_fixed_snps() {
+        let mut bim_1 = NamedTempFile::new().unwrap();
+        writeln!(bim_1, "1 rs1 0 100 A G\n1 rs2 0 200 C T").unwrap();
+        let mut bim_2 = NamedTempFile::new().unwrap();
+        writeln!(bim_2, "2 rs3 0 100 A G\n2 rs4 0 200 C T").unwrap();
+        let bim_paths = vec![
+            bim_1.path().to_str().unwrap().to_string(),
+            bim_2.path().to_str().unwrap().to_string(),
+        ];
+        let mut id_file = NamedTempFile::new().unwrap();
+        writeln!(id_file, "rs4\nrs2\nrs4").unwrap();
+        let ids = read_snp_id_list(id_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(ids, vec!["rs4".to_string(), "rs2".to_string()]);
+
+        let fixed = FixedSnps::from_bim_files(&ids, &bim_paths).unwrap();
+        assert_eq!(fixed.snp_ids(), &["rs2".to_string(), "rs4".to_string()]);
+        assert_eq!(
+            fixed.to_set(),
+            OrderedIntegerSet::from_slice(&[[1, 1], [3, 3]])
+        );
+        assert!(FixedSnps::from_bim_files(&["rs5".to_string()], &bim_paths)
+            .is_err());
+
+        let geno = array![
+            [0f32, 1., 2., 0.],
+            [1., 0., 1., 2.],
+            [2., 2., 0., 1.],
+            [0., 1., 1., 1.],
+            [1., 0., 2., 2.],
+            [2., 2., 1., 0.],
+            [1., 1., 0., 2.]
+        ];
+        assert_eq!(
+            fixed.get_genotypes(&geno).unwrap(),
+            geno.select(Axis(1), &[1, 3])
+        );
+        // the phenotype is exactly linear in the fixed SNPs plus an offset
+        // in the first column
+        let noise = array![0.3f32, -0.2, 0.1, -0.4, 0.2, 0., 0.5];
+        let mut pheno = Array::<f32, Ix2>::zeros((7, 2));
+        for i in 0..7 {
+            pheno[[i, 0]] = 1. + 2. * geno[[i, 1]] - 0.5 * geno[[i, 3]];
+            pheno[[i, 1]] = noise[i];
+        }
+        let effects = fixed.regress_out_inplace(&geno, &mut pheno).unwrap();
+        assert_eq!(effects.effects.dim(), (2, 2));
+        assert!((effects.effects[[0, 0]] - 2.).abs() < 1e-4);
+        assert!((effects.effects[[1, 0]] + 0.5).abs() < 1e-4);
+        assert!(pheno.column(0).iter().all(|r| r.abs() < 1e-4));
+        // the residuals are orthogonal to the fixed SNPs
+        for j in [1, 3].iter() {
+            assert!(pheno.column(1).dot(&geno.column(*j)).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! The bivariate extension of the Haseman-Elston normal equations to the
+//! genetic covariance of two traits measured on the same people.
+//!
+//! For the normalized phenotypes `y_s` and `y_t` and the kernel
+//! `K = X X^T / m`, the covariance components solve
+//!
+//! ```text
+//! [tr(KK)  n] [sigma_g_st]   [y_s^T K y_t]
+//! [n       n] [sigma_e_st] = [y_s^T y_t  ]
+//! ```
+//!
+//! which reduces to the univariate normal equations for `s = t`. The genetic
+//! correlation is `sigma_g_12 / sqrt(sigma_g_11 sigma_g_22)`. The SNPs are
+//! split into contiguous jackknife blocks, and every quantity is re-estimated
+//! with each block left out.
+
+use std::fmt;
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Ix1, Ix2};
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    gpu::gram_dot,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    partitioned_jackknife_estimates::Estimate,
+    progress::ProgressSink,
+    resampling::Resampling,
+    stochastic::GenotypeSource,
+    util::{
+        check_num_people,
+        matrix_util::{
+            generate_sign_matrix, normalize_matrix_columns_inplace,
+            normalize_vector_inplace,
+        },
+    },
+};
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct GeneticCorrelationEstimates {
+    pub heritability_1: Estimate<f64>,
+    pub heritability_2: Estimate<f64>,
+    pub genetic_covariance: Estimate<f64>,
+    pub genetic_correlation: Estimate<f64>,
+    /// The correlation of the two phenotypes, which the genetic and the
+    /// noise covariances add up to
+    pub phenotypic_correlation: f64,
+}
+
+impl fmt::Display for GeneticCorrelationEstimates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indent: usize = 4;
+        writeln!(
+            f,
+            "\nheritability of trait 1\n{:indent$}",
+            self.heritability_1,
+            indent = indent
+        )?;
+        writeln!(
+            f,
+            "\nheritability of trait 2\n{:indent$}",
+            self.heritability_2,
+            indent = indent
+        )?;
+        writeln!(
+            f,
+            "\ngenetic covariance\n{:indent$}",
+            self.genetic_covariance,
+            indent = indent
+        )?;
+        writeln!(
+            f,
+            "\ngenetic correlation\n{:indent$}",
+            self.genetic_correlation,
+            indent = indent
+        )?;
+        writeln!(
+            f,
+            "\nphenotypic correlation: {}",
+            self.phenotypic_correlation
+        )
+    }
+}
+
+/// The statistics of a jackknife block of SNPs
+struct BlockStats {
+    num_snps: usize,
+    /// `X_b X_b^T Z`
+    ggz: Array<f32, Ix2>,
+    /// `y_1^T X_b X_b^T y_1`, `y_2^T X_b X_b^T y_2` and
+    /// `y_1^T X_b X_b^T y_2`
+    yky: [f64; 3],
+}
+
+/// `pheno_1` and `pheno_2` are normalized here and have to list the same
+/// people in the same order as `geno`. The probes are reproducible if `seed`
+/// is `Some`. The streamed jackknife blocks are reported to the `progress`.
+pub fn estimate_genetic_correlation<G: GenotypeSource>(
+    geno: &G,
+    pheno_1: &Array<f32, Ix1>,
+    pheno_2: &Array<f32, Ix1>,
+    num_random_vecs: usize,
+    num_jackknife_blocks: usize,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<GeneticCorrelationEstimates, Error> {
+    let num_people = geno.num_people();
+    let num_snps = geno.num_snps();
+    check_num_people(pheno_1.len(), "trait 1", num_people, "the genotypes")?;
+    check_num_people(pheno_2.len(), "trait 2", num_people, "the genotypes")?;
+    if num_jackknife_blocks < 2 || num_jackknife_blocks > num_snps {
+        return Err(Error::Generic(format!(
+            "the number of jackknife blocks has to be between 2 and the \
+            number of SNPs {}, received {}",
+            num_snps, num_jackknife_blocks
+        )));
+    }
+    let mut y1 = pheno_1.clone();
+    let mut y2 = pheno_2.clone();
+    normalize_vector_inplace(&mut y1, 0);
+    normalize_vector_inplace(&mut y2, 0);
+    let y1y2 = y1.dot(&y2) as f64;
+
+    let probes = generate_sign_matrix(num_people, num_random_vecs, None, seed);
+    let blocks: Vec<BlockStats> = (0..num_jackknife_blocks)
+        .map(|b| {
+            progress.progress(
+                "streaming jackknife block",
+                b + 1,
+                num_jackknife_blocks,
+            );
+            let range = OrderedIntegerSet::from_slice(&[[
+                b * num_snps / num_jackknife_blocks,
+                (b + 1) * num_snps / num_jackknife_blocks - 1,
+            ]]);
+            get_block_stats(geno, range, &probes, &y1, &y2)
+        })
+        .collect();
+
+    let get_estimates = |block_indices: &[usize]| -> [f64; 4] {
+        let m = block_indices
+            .iter()
+            .map(|&b| blocks[b].num_snps)
+            .sum::<usize>() as f64;
+        let mut ggz = Array::<f32, Ix2>::zeros((num_people, num_random_vecs));
+        let mut yky = [0f64; 3];
+        for &b in block_indices.iter() {
+            ggz += &blocks[b].ggz;
+            for (acc, x) in yky.iter_mut().zip(blocks[b].yky.iter()) {
+                *acc += x;
+            }
+        }
+        let n = num_people as f64;
+        let tr_kk = ggz.iter().map(|&x| (x * x) as f64).sum::<f64>()
+            / (m * m * num_random_vecs as f64);
+        // the normalized phenotypes have |y|^2 = n
+        let h2_1 = (yky[0] / m - n) / (tr_kk - n);
+        let h2_2 = (yky[1] / m - n) / (tr_kk - n);
+        let cov = (yky[2] / m - y1y2) / (tr_kk - n);
+        [h2_1, h2_2, cov, cov / (h2_1 * h2_2).sqrt()]
+    };
+
+    let all: Vec<usize> = (0..num_jackknife_blocks).collect();
+    let point = get_estimates(&all);
+    let knife: Vec<[f64; 4]> = (0..num_jackknife_blocks)
+        .map(|k| {
+            let rest: Vec<usize> =
+                all.iter().cloned().filter(|&b| b != k).collect();
+            get_estimates(&rest)
+        })
+        .collect();
+    let resampling = Resampling::BlockJackknife {
+        num_blocks: num_jackknife_blocks,
+    };
+    let get_estimate = |i: usize| {
+        resampling.get_estimate(
+            point[i],
+            &knife.iter().map(|e| e[i]).collect::<Vec<f64>>(),
+        )
+    };
+    Ok(GeneticCorrelationEstimates {
+        heritability_1: get_estimate(0),
+        heritability_2: get_estimate(1),
+        genetic_covariance: get_estimate(2),
+        genetic_correlation: get_estimate(3),
+        phenotypic_correlation: y1y2 / num_people as f64,
+    })
+}
+
+fn get_block_stats<G: GenotypeSource>(
+    geno: &G,
+    range: OrderedIntegerSet<usize>,
+    probes: &Array<f32, Ix2>,
+    y1: &Array<f32, Ix1>,
+    y2: &Array<f32, Ix1>,
+) -> BlockStats {
+    let shape = probes.dim();
+    let (ggz, yky, num_snps) = geno.fold_snp_chunks(
+        Some(range),
+        DEFAULT_NUM_SNPS_PER_CHUNK,
+        || (Array::<f32, Ix2>::zeros(shape), [0f64; 3], 0usize),
+        |(ggz, mut yky, num_snps), _, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            let xty1 = y1.dot(&snp_chunk);
+            let xty2 = y2.dot(&snp_chunk);
+            yky[0] += xty1.dot(&xty1) as f64;
+            yky[1] += xty2.dot(&xty2) as f64;
+            yky[2] += xty1.dot(&xty2) as f64;
+            (
+                ggz + &gram_dot(&snp_chunk, probes),
+                yky,
+                num_snps + snp_chunk.dim().1,
+            )
+        },
+        |(ggz_a, mut yky_a, num_snps_a), (ggz_b, yky_b, num_snps_b)| {
+            for (a, b) in yky_a.iter_mut().zip(yky_b.iter()) {
+                *a += b;
+            }
+            (ggz_a + &ggz_b, yky_a, num_snps_a + num_snps_b)
+        },
+    );
+    BlockStats {
+        num_snps,
+        ggz,
+        yky,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array, Ix1, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Normal;
+
+    use super::estimate_genetic_correlation;
+    use crate::{
+        progress::SilentProgress,
+        util::matrix_util::generate_plus_minus_one_bernoulli_matrix,
+    };
+
+    #[test]
+    fn test_genetic_correlation() {
+        let (num_people, num_snps) = (800, 200);
+        let geno: Array<f32, Ix2> =
+            generate_plus_minus_one_bernoulli_matrix(num_people, num_snps)
+                .mapv(|x| x + 1.);
+        let effects: Array<f32, Ix1> =
+            Array::random(num_snps, Normal::new(0., 1.)).mapv(|x| x as f32);
+        let g = geno.dot(&effects);
+        let g_std = {
+            let mean = g.sum() / num_people as f32;
+            (g.mapv(|x| (x - mean) * (x - mean)).sum() / num_people as f32)
+                .sqrt() as f64
+        };
+        let noise = || {
+            Array::random(num_people, Normal::new(0., g_std)).mapv(|x| x as f32)
+        };
+        // both traits share the genetic component, so rg is 1
+        let y1 = &g + &noise();
+        let y2 = &g * 2. + &noise();
+        let est = estimate_genetic_correlation(
+            &geno,
+            &y1,
+            &y2,
+            50,
+            10,
+            None,
+            &SilentProgress,
+        )
+        .unwrap();
+        assert!(
+            (est.genetic_correlation.point_estimate_without_jackknife - 1.)
+                .abs()
+                < 0.3
+        );
+        assert!(est.genetic_covariance.point_estimate_without_jackknife > 0.);
+        assert!(est.genetic_correlation.standard_error.is_finite());
+
+        // the traits are symmetric
+        let swapped = estimate_genetic_correlation(
+            &geno,
+            &y2,
+            &y1,
+            50,
+            10,
+            None,
+            &SilentProgress,
+        )
+        .unwrap();
+        assert!(
+            (swapped.phenotypic_correlation - est.phenotypic_correlation).abs()
+                < 1e-5
+        );
+        assert!(estimate_genetic_correlation(
+            &geno,
+            &y1,
+            &y2,
+            50,
+            1,
+            None,
+            &SilentProgress
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{Array, Axis, Ix2};
+
+use crate::{
+    error::Error,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    snp_weights::{weight_snp_chunk_inplace, SnpWeights},
+    stochastic::GenotypeSource,
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+/// The products of the standardized genotypes `X` of a SNP range with a set
+/// of probe vectors `Z` and with the phenotypes `Y`, computed in a single pass
+/// over the bed file and shared by all the trace estimators of a run.
+///
+/// The sketch keeps `X X^T Z`, the per-SNP products `X^T Y` and the squared
+/// norm of `X^T Z`, from which `tr(K)`, `tr(K K)` and `y^T K y` follow without
+/// reading the genotypes again. A trace `tr(K K_2)` with any other kernel
+/// `K_2` only needs the product `K_2 Z`, see `tr_k_dot`, which is how the
+/// traces of the G kernel with the GxG kernels avoid their own passes.
+pub struct GenotypeSketch {
+    probes: Array<f32, Ix2>,
+    /// the sum of the SNP weights, or the number of SNPs without weights
+    num_snps: f64,
+    /// `X X^T Z`, unnormalized
+    xxz: Array<f32, Ix2>,
+    /// `|X^T Z|^2`
+    xz_ssq: f64,
+    /// `xty[[i, p]]` is the product of the `i`-th SNP with phenotype `p`
+    xty: Array<f32, Ix2>,
+}
+
+impl GenotypeSketch {
+    /// Streams the SNPs in `snp_range`, or all the SNPs if `None`, in chunks
+    /// of `num_snps_per_chunk` SNPs. `probes` and `pheno_matrix` have one
+    /// column per probe vector and per normalized phenotype respectively.
+    /// With `snp_weights`, the kernel is `X W X^T / sum_i w_i`.
+    pub fn new<G: GenotypeSource>(
+        geno: &G,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        snp_weights: Option<&SnpWeights>,
+        probes: Array<f32, Ix2>,
+        pheno_matrix: &Array<f32, Ix2>,
+        num_snps_per_chunk: Option<usize>,
+    ) -> Result<GenotypeSketch, Error> {
+        let num_people = geno.num_people();
+        if probes.dim().0 != num_people || pheno_matrix.dim().0 != num_people {
+            return Err(Error::DimensionMismatch(format!(
+                "probes of shape {:?} and phenotypes of shape {:?} for {} \
+                people",
+                probes.dim(),
+                pheno_matrix.dim(),
+                num_people
+            )));
+        }
+        if let Some(w) = snp_weights {
+            w.check_num_snps(geno.num_snps())?;
+        }
+        let num_snps = match (&snp_range, snp_weights) {
+            (Some(range), Some(w)) => w.sum(range),
+            (None, Some(w)) => w.sum(&OrderedIntegerSet::from_slice(&[[
+                0,
+                geno.num_snps() - 1,
+            ]])),
+            (Some(range), None) => range.size() as f64,
+            (None, None) => geno.num_snps() as f64,
+        };
+        let num_range_snps = match &snp_range {
+            Some(range) => range.size(),
+            None => geno.num_snps(),
+        };
+        let sqrt_weights =
+            snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+        let sqrt_weights = sqrt_weights.as_deref();
+        let num_phenos = pheno_matrix.dim().1;
+        let chunk_size =
+            num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+        let zeros = || {
+            (
+                Array::<f32, Ix2>::zeros(probes.dim()),
+                0f64,
+                Vec::<(usize, Array<f32, Ix2>)>::new(),
+            )
+        };
+        let (xxz, xz_ssq, xty_chunks) = geno.fold_snp_chunks(
+            snp_range,
+            chunk_size,
+            zeros,
+            |(mut xxz, xz_ssq, mut xty_chunks), rank, mut snp_chunk| {
+                normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+                weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, rank);
+                let xz = snp_chunk.t().dot(&probes);
+                let ssq = xz.iter().map(|&x| x as f64 * x as f64).sum::<f64>();
+                xxz += &snp_chunk.dot(&xz);
+                xty_chunks.push((rank, snp_chunk.t().dot(pheno_matrix)));
+                (xxz, xz_ssq + ssq, xty_chunks)
+            },
+            |(xxz_a, ssq_a, mut chunks_a), (xxz_b, ssq_b, mut chunks_b)| {
+                chunks_a.append(&mut chunks_b);
+                (xxz_a + &xxz_b, ssq_a + ssq_b, chunks_a)
+            },
+        );
+        let mut xty = Array::<f32, Ix2>::zeros((num_range_snps, num_phenos));
+        for (rank, products) in xty_chunks.into_iter() {
+            for (i, row) in products.axis_iter(Axis(0)).enumerate() {
+                xty.row_mut(rank + i).assign(&row);
+            }
+        }
+        Ok(GenotypeSketch {
+            probes,
+            num_snps,
+            xxz,
+            xz_ssq,
+            xty,
+        })
+    }
+
+    pub fn num_snps(&self) -> f64 {
+        self.num_snps
+    }
+
+    pub fn num_probes(&self) -> usize {
+        self.probes.dim().1
+    }
+
+    pub fn probes(&self) -> &Array<f32, Ix2> {
+        &self.probes
+    }
+
+    /// `K Z` for the normalized kernel `K`
+    pub fn kernel_dot_probes(&self) -> Array<f32, Ix2> {
+        &self.xxz / self.num_snps as f32
+    }
+
+    /// The per-SNP products `X^T Y` in the order in which the SNPs are
+    /// streamed
+    pub fn xty(&self) -> &Array<f32, Ix2> {
+        &self.xty
+    }
+
+    /// `tr(K)`, which needs at least one probe
+    pub fn tr_k(&self) -> f64 {
+        self.xz_ssq / (self.num_snps * self.num_probes() as f64)
+    }
+
+    /// `tr(K K)`, which needs at least one probe
+    pub fn tr_kk(&self) -> f64 {
+        let ssq = self.xxz.iter().map(|&x| x as f64 * x as f64).sum::<f64>();
+        ssq / (self.num_snps * self.num_snps * self.num_probes() as f64)
+    }
+
+    /// `tr(K K_2)` from the product `K_2 Z` of another kernel `K_2` with the
+    /// probes. `K_2 Z` may itself be a sketch, as long as it is independent
+    /// of the probes given `Z`, e.g. a random sketch of a GxG kernel.
+    pub fn tr_k_dot(&self, k2_dot_probes: &Array<f32, Ix2>) -> f64 {
+        assert_eq!(
+            k2_dot_probes.dim(),
+            self.probes.dim(),
+            "the product with the probes has the wrong shape"
+        );
+        let dot = self
+            .xxz
+            .iter()
+            .zip(k2_dot_probes.iter())
+            .map(|(&a, &b)| a as f64 * b as f64)
+            .sum::<f64>();
+        dot / (self.num_snps * self.num_probes() as f64)
+    }
+
+    /// `y^T K y` for each of the phenotypes
+    pub fn yky(&self) -> Vec<f64> {
+        self.xty
+            .axis_iter(Axis(1))
+            .map(|col| {
+                col.iter().map(|&x| x as f64 * x as f64).sum::<f64>()
+                    / self.num_snps
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array, Axis, Ix2};
+
+    use super::GenotypeSketch;
+    use crate::{
+        snp_weights::SnpWeights,
+        util::matrix_util::normalize_matrix_columns_inplace,
+    };
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-3 * b.abs().max(1.), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_genotype_sketch() {
+        let geno = array![
+            [0f32, 1., 2., 1., 0.],
+            [1., 1., 0., 2., 1.],
+            [2., 0., 1., 1., 1.],
+            [0., 2., 1., 0., 2.],
+            [1., 0., 2., 2., 0.]
+        ];
+        let pheno =
+            array![[1f32, 0.5], [-1., 1.], [0.5, -2.], [0., 0.], [-0.5, 0.5]];
+        let range = OrderedIntegerSet::from_slice(&[[0, 1], [3, 4]]);
+        let mut x = geno.select(Axis(1), &[0, 1, 3, 4]);
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k: Array<f32, Ix2> = x.dot(&x.t()) / 4.;
+
+        // the identity probes make the traces exact
+        let sketch = GenotypeSketch::new(
+            &geno,
+            Some(range.clone()),
+            None,
+            Array::eye(5),
+            &pheno,
+            Some(3),
+        )
+        .unwrap();
+        assert_eq!(sketch.num_snps(), 4.);
+        assert_close(sketch.tr_k(), k.diag().sum() as f64 / 5.);
+        let tr_kk = k.dot(&k).diag().sum() as f64 / 5.;
+        assert_close(sketch.tr_kk(), tr_kk);
+        assert_close(sketch.tr_k_dot(&k), tr_kk);
+        for (p, yky) in sketch.yky().into_iter().enumerate() {
+            let y = pheno.column(p);
+            assert_close(yky, y.dot(&k.dot(&y)) as f64);
+        }
+        assert_eq!(sketch.xty().dim(), (4, 2));
+
+        // the weights scale the SNPs by their square roots
+        let weights = SnpWeights::new(vec![1., 4., 1., 0., 1.]).unwrap();
+        let weighted = GenotypeSketch::new(
+            &geno,
+            None,
+            Some(&weights),
+            Array::eye(5),
+            &pheno,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(weighted.num_snps(), 7.);
+        let mut wx = geno.select(Axis(1), &[0, 1, 2, 4]);
+        normalize_matrix_columns_inplace(&mut wx, 0);
+        wx.column_mut(1).mapv_inplace(|v| v * 2.);
+        let k: Array<f32, Ix2> = wx.dot(&wx.t()) / 7.;
+        assert_close(weighted.tr_kk(), k.dot(&k).diag().sum() as f64 / 5.);
+
+        assert!(GenotypeSketch::new(
+            &geno,
+            None,
+            None,
+            Array::eye(4),
+            &pheno,
+            None
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! The backend of the dense products of the standardized SNP chunks, which
+//! dominate the cost of the estimation. With the `cuda` feature, the
+//! products `X X^T Z` of every chunk can be offloaded to the GPU through
+//! cuBLAS. The backend is selected at runtime, by `ComputeConfig::install`
+//! for the estimators, and falls back on the CPU with a warning if the CUDA
+//! backend is not compiled in or no device can be used.
+//!
+//! The GPU products are computed in f32 like the CPU ones, but in a
+//! different order of summation, so the two agree to the f32 precision
+//! rather than exactly.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Once,
+    },
+};
+
+use log::warn;
+use ndarray::{Array, Ix2};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ComputeBackend {
+    Cpu,
+    Cuda,
+}
+
+impl Default for ComputeBackend {
+    fn default() -> ComputeBackend {
+        ComputeBackend::Cpu
+    }
+}
+
+impl FromStr for ComputeBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ComputeBackend, String> {
+        match s {
+            "cpu" => Ok(ComputeBackend::Cpu),
+            "cuda" => Ok(ComputeBackend::Cuda),
+            _ => Err(format!(
+                "unrecognized compute backend {}, expected cpu or cuda",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ComputeBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComputeBackend::Cpu => write!(f, "cpu"),
+            ComputeBackend::Cuda => write!(f, "cuda"),
+        }
+    }
+}
+
+static BACKEND: AtomicU8 = AtomicU8::new(0);
+static FALLBACK_WARNING: Once = Once::new();
+
+/// Selects the backend of the chunk products for the whole process
+pub fn set_backend(backend: ComputeBackend) {
+    BACKEND.store(
+        match backend {
+            ComputeBackend::Cpu => 0,
+            ComputeBackend::Cuda => 1,
+        },
+        Ordering::SeqCst,
+    );
+}
+
+pub fn get_backend() -> ComputeBackend {
+    match BACKEND.load(Ordering::SeqCst) {
+        0 => ComputeBackend::Cpu,
+        _ => ComputeBackend::Cuda,
+    }
+}
+
+/// Whether the CUDA backend is compiled in and sees at least one device
+pub fn is_cuda_available() -> bool {
+    #[cfg(feature = "cuda")]
+    {
+        cuda::get_device_count() > 0
+    }
+    #[cfg(not(feature = "cuda"))]
+    {
+        false
+    }
+}
+
+/// `X X^T Z` for a standardized SNP chunk `X` on the backend of
+/// `set_backend`
+pub fn gram_dot(x: &Array<f32, Ix2>, z: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+    gram_dot_with_backend(get_backend(), x, z)
+}
+
+fn gram_dot_with_backend(
+    backend: ComputeBackend,
+    x: &Array<f32, Ix2>,
+    z: &Array<f32, Ix2>,
+) -> Array<f32, Ix2> {
+    if backend == ComputeBackend::Cuda {
+        #[cfg(feature = "cuda")]
+        let result = cuda::gram_dot(x, z);
+        #[cfg(not(feature = "cuda"))]
+        let result: Result<Array<f32, Ix2>, String> =
+            Err("saber is built without the cuda feature".to_string());
+        match result {
+            Ok(product) => return product,
+            Err(why) => FALLBACK_WARNING.call_once(|| {
+                warn!(
+                    "the CUDA backend is unavailable, falling back on the \
+                    CPU: {}",
+                    why
+                )
+            }),
+        }
+    }
+    x.dot(&x.t().dot(z))
+}
+
+#[cfg(feature = "cuda")]
+mod cuda {
+    use std::{cell::RefCell, ffi::c_void, mem::size_of, os::raw::c_int, ptr};
+
+    use ndarray::{Array, Ix2};
+
+    type CublasHandle = *mut c_void;
+
+    const CUDA_SUCCESS: c_int = 0;
+    const CUBLAS_STATUS_SUCCESS: c_int = 0;
+    const CUDA_MEMCPY_HOST_TO_DEVICE: c_int = 1;
+    const CUDA_MEMCPY_DEVICE_TO_HOST: c_int = 2;
+    const CUBLAS_OP_N: c_int = 0;
+    const CUBLAS_OP_T: c_int = 1;
+
+    #[link(name = "cudart")]
+    extern "C" {
+        fn cudaGetDeviceCount(count: *mut c_int) -> c_int;
+        fn cudaMalloc(dev_ptr: *mut *mut c_void, size: usize) -> c_int;
+        fn cudaFree(dev_ptr: *mut c_void) -> c_int;
+        fn cudaMemcpy(
+            dst: *mut c_void,
+            src: *const c_void,
+            count: usize,
+            kind: c_int,
+        ) -> c_int;
+    }
+
+    #[link(name = "cublas")]
+    extern "C" {
+        fn cublasCreate_v2(handle: *mut CublasHandle) -> c_int;
+        fn cublasDestroy_v2(handle: CublasHandle) -> c_int;
+        fn cublasSgemm_v2(
+            handle: CublasHandle,
+            transa: c_int,
+            transb: c_int,
+            m: c_int,
+            n: c_int,
+            k: c_int,
+            alpha: *const f32,
+            a: *const f32,
+            lda: c_int,
+            b: *const f32,
+            ldb: c_int,
+            beta: *const f32,
+            c: *mut f32,
+            ldc: c_int,
+        ) -> c_int;
+    }
+
+    pub fn get_device_count() -> usize {
+        let mut count: c_int = 0;
+        if unsafe { cudaGetDeviceCount(&mut count) } == CUDA_SUCCESS {
+            count as usize
+        } else {
+            0
+        }
+    }
+
+    /// A cuBLAS handle for each of the threads folding the SNP chunks
+    struct Handle(CublasHandle);
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe {
+                cublasDestroy_v2(self.0);
+            }
+        }
+    }
+
+    thread_local! {
+        static HANDLE: RefCell<Option<Handle>> = RefCell::new(None);
+    }
+
+    struct DeviceBuffer {
+        ptr: *mut f32,
+        len: usize,
+    }
+
+    impl DeviceBuffer {
+        fn new(len: usize) -> Result<DeviceBuffer, String> {
+            let mut ptr: *mut c_void = ptr::null_mut();
+            let status =
+                unsafe { cudaMalloc(&mut ptr, len * size_of::<f32>()) };
+            if status != CUDA_SUCCESS {
+                return Err(format!(
+                    "failed to allocate {} floats on the device, CUDA error {}",
+                    len, status
+                ));
+            }
+            Ok(DeviceBuffer {
+                ptr: ptr as *mut f32,
+                len,
+            })
+        }
+
+        fn from_host(data: &[f32]) -> Result<DeviceBuffer, String> {
+            let buffer = DeviceBuffer::new(data.len())?;
+            let status = unsafe {
+                cudaMemcpy(
+                    buffer.ptr as *mut c_void,
+                    data.as_ptr() as *const c_void,
+                    data.len() * size_of::<f32>(),
+                    CUDA_MEMCPY_HOST_TO_DEVICE,
+                )
+            };
+            if status != CUDA_SUCCESS {
+                return Err(format!(
+                    "failed to copy to the device, CUDA error {}",
+                    status
+                ));
+            }
+            Ok(buffer)
+        }
+
+        fn to_host(&self) -> Result<Vec<f32>, String> {
+            let mut data = vec![0f32; self.len];
+            let status = unsafe {
+                cudaMemcpy(
+                    data.as_mut_ptr() as *mut c_void,
+                    self.ptr as *const c_void,
+                    self.len * size_of::<f32>(),
+                    CUDA_MEMCPY_DEVICE_TO_HOST,
+                )
+            };
+            if status != CUDA_SUCCESS {
+                return Err(format!(
+                    "failed to copy from the device, CUDA error {}",
+                    status
+                ));
+            }
+            Ok(data)
+        }
+    }
+
+    impl Drop for DeviceBuffer {
+        fn drop(&mut self) {
+            unsafe {
+                cudaFree(self.ptr as *mut c_void);
+            }
+        }
+    }
+
+    fn with_handle<T, F>(f: F) -> Result<T, String>
+    where
+        F: FnOnce(CublasHandle) -> Result<T, String>, {
+        HANDLE.with(|cell| {
+            let mut handle = cell.borrow_mut();
+            if handle.is_none() {
+                let mut h: CublasHandle = ptr::null_mut();
+                let status = unsafe { cublasCreate_v2(&mut h) };
+                if status != CUBLAS_STATUS_SUCCESS {
+                    return Err(format!(
+                        "failed to create a cuBLAS handle, status {}",
+                        status
+                    ));
+                }
+                *handle = Some(Handle(h));
+            }
+            f(handle.as_ref().unwrap().0)
+        })
+    }
+
+    /// The row-major `n x m` chunk `X` is the column-major `m x n` matrix
+    /// `X^T`, so `C = X^T Z` is computed as the column-major `C^T = Z^T X`
+    /// and `X C` as the column-major `C^T X^T`, without any transposition on
+    /// the host.
+    pub fn gram_dot(
+        x: &Array<f32, Ix2>,
+        z: &Array<f32, Ix2>,
+    ) -> Result<Array<f32, Ix2>, String> {
+        if get_device_count() == 0 {
+            return Err("no CUDA device is available".to_string());
+        }
+        let (n, m) = x.dim();
+        let k = z.dim().1;
+        let x_host: Vec<f32> = x.iter().cloned().collect();
+        let z_host: Vec<f32> = z.iter().cloned().collect();
+        let x_dev = DeviceBuffer::from_host(&x_host)?;
+        let z_dev = DeviceBuffer::from_host(&z_host)?;
+        let c_dev = DeviceBuffer::new(m * k)?;
+        let product_dev = DeviceBuffer::new(n * k)?;
+        let (alpha, beta) = (1f32, 0f32);
+        with_handle(|handle| {
+            let status = unsafe {
+                cublasSgemm_v2(
+                    handle,
+                    CUBLAS_OP_N,
+                    CUBLAS_OP_T,
+                    k as c_int,
+                    m as c_int,
+                    n as c_int,
+                    &alpha,
+                    z_dev.ptr,
+                    k as c_int,
+                    x_dev.ptr,
+                    m as c_int,
+                    &beta,
+                    c_dev.ptr,
+                    k as c_int,
+                )
+            };
+            if status != CUBLAS_STATUS_SUCCESS {
+                return Err(format!(
+                    "cublasSgemm failed with status {}",
+                    status
+                ));
+            }
+            let status = unsafe {
+                cublasSgemm_v2(
+                    handle,
+                    CUBLAS_OP_N,
+                    CUBLAS_OP_N,
+                    k as c_int,
+                    n as c_int,
+                    m as c_int,
+                    &alpha,
+                    c_dev.ptr,
+                    k as c_int,
+                    x_dev.ptr,
+                    m as c_int,
+                    &beta,
+                    product_dev.ptr,
+                    k as c_int,
+                )
+            };
+            if status != CUBLAS_STATUS_SUCCESS {
+                return Err(format!(
+                    "cublasSgemm failed with status {}",
+                    status
+                ));
+            }
+            Ok(())
+        })?;
+        Array::from_shape_vec((n, k), product_dev.to_host()?)
+            .map_err(|why| format!("{:?}", why))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array;
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Uniform;
+
+    use super::{gram_dot_with_backend, is_cuda_available, ComputeBackend};
+
+    #[test]
+    fn test_gram_dot() {
+        let x = Array::random((50, 20), Uniform::new(-2f32, 2.));
+        let z = Array::random((50, 4), Uniform::new(-1f32, 1.));
+        let expected = x.dot(&x.t().dot(&z));
+        assert_eq!(
+            gram_dot_with_backend(ComputeBackend::Cpu, &x, &z),
+            expected
+        );
+        // the GPU products, or the CPU fallback without a device, agree to
+        // the f32 precision
+        let product = gram_dot_with_backend(ComputeBackend::Cuda, &x, &z);
+        let scale = expected.iter().fold(0f32, |m, v| m.max(v.abs()));
+        assert!(product
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| (a - b).abs() <= 1e-4 * scale));
+        if !is_cuda_available() {
+            assert_eq!(product, expected);
+        }
+
+        assert_eq!("cuda".parse::<ComputeBackend>(), Ok(ComputeBackend::Cuda));
+        assert!("gpu".parse::<ComputeBackend>().is_err());
+        assert_eq!(ComputeBackend::default().to_string(), "cpu");
+    }
+}
@@ -0,0 +1,412 @@
+//! Computing the GRM `X X^T / m` of the standardized genotypes out of core,
+//! and exporting it in the binary GRM format of GCTA, i.e.
+//!
+//! - `PREFIX.grm.bin`: the lower triangle including the diagonal, row by
+//!   row, in little-endian `f32`
+//! - `PREFIX.grm.N.bin`: the number of SNPs behind every entry, in the same
+//!   layout
+//! - `PREFIX.grm.id`: the FID and IID of every person, tab separated
+//!
+//! The GRM is computed one block of `num_people_per_block` rows at a time
+//! from a pass over the genotypes, and every block is written out before the
+//! next one is computed, so the `n x n` GRM is never held in memory. A GRM
+//! read back with `Grm::from_gcta_files` can stand in for the streamed kernel
+//! of a component in the REML estimates.
+
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{s, Array, Axis, Ix2};
+use ndarray_parallel::prelude::*;
+use rayon::prelude::*;
+
+use crate::{
+    error::Error,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    progress::ProgressSink,
+    snp_chunk_filter::SnpChunkFilter,
+    snp_weights::{weight_snp_chunk_inplace, SnpWeights},
+    stochastic::GenotypeSource,
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+/// The blocks of the GRM held in memory have this many rows by default
+pub const DEFAULT_GRM_NUM_PEOPLE_PER_BLOCK: usize = 512;
+
+/// The `.grm.bin`, `.grm.N.bin` and `.grm.id` paths of the GRM with the
+/// `prefix`
+pub fn get_gcta_grm_paths(prefix: &str) -> (String, String, String) {
+    (
+        format!("{}.grm.bin", prefix),
+        format!("{}.grm.N.bin", prefix),
+        format!("{}.grm.id", prefix),
+    )
+}
+
+fn create_file(path: &str) -> Result<BufWriter<std::fs::File>, Error> {
+    Ok(BufWriter::new(
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)
+            .map_err(|io_error| Error::IO {
+                why: format!("failed to create {}", path),
+                io_error,
+            })?,
+    ))
+}
+
+/// The rows `[start, end)` of the unnormalized GRM `X W X^T` restricted to
+/// the columns `[0, end)`, which covers the part of the rows in the lower
+/// triangle, together with the number of SNPs in it, i.e. the sum of the
+/// weights of the SNPs passing the `snp_filter`
+fn get_grm_lower_block<G: GenotypeSource>(
+    geno: &G,
+    snp_range: &Option<OrderedIntegerSet<usize>>,
+    snp_filter: Option<&SnpChunkFilter>,
+    sqrt_weights: Option<&[f32]>,
+    start: usize,
+    end: usize,
+    num_snps_per_chunk: usize,
+) -> (Array<f32, Ix2>, f64) {
+    geno.fold_snp_chunks(
+        snp_range.clone(),
+        num_snps_per_chunk,
+        || (Array::<f32, Ix2>::zeros((end - start, end)), 0f64),
+        |(acc, num_snps), rank, snp_chunk| {
+            let kept: Vec<usize> = match snp_filter {
+                Some(filter) => filter.get_kept_columns(&snp_chunk),
+                None => (0..snp_chunk.dim().1).collect(),
+            };
+            let mut snp_chunk = if kept.len() == snp_chunk.dim().1 {
+                snp_chunk
+            } else {
+                snp_chunk.select(Axis(1), &kept)
+            };
+            let chunk_sqrt_weights: Option<Vec<f32>> = sqrt_weights
+                .map(|w| kept.iter().map(|&j| w[rank + j]).collect());
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(
+                &mut snp_chunk,
+                chunk_sqrt_weights.as_deref(),
+                0,
+            );
+            let chunk_num_snps = match &chunk_sqrt_weights {
+                Some(w) => w.iter().map(|&s| (s * s) as f64).sum(),
+                None => kept.len() as f64,
+            };
+            (
+                acc + &snp_chunk
+                    .slice(s![start..end, ..])
+                    .dot(&snp_chunk.slice(s![..end, ..]).t()),
+                num_snps + chunk_num_snps,
+            )
+        },
+        |(a, num_snps_a), (b, num_snps_b)| (a + &b, num_snps_a + num_snps_b),
+    )
+}
+
+/// Writes the GRM of the SNPs in `snp_range`, or of all the SNPs if `None`,
+/// to the GCTA files with the `out_prefix`. With `snp_weights`, the GRM is
+/// `X W X^T / sum_i w_i` and the sum of the weights takes the place of the
+/// number of SNPs in the `.grm.N.bin` file. `fid_iid_list` lists the people
+/// in the order of the genotypes.
+/// The SNPs failing the `snp_filter` are dropped from every SNP chunk as it
+/// is streamed, and hence from the number of SNPs, without a separate QC
+/// pass over the genotypes. Every block of rows computed is reported to the
+/// `progress`.
+pub fn write_gcta_grm<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_filter: Option<&SnpChunkFilter>,
+    snp_weights: Option<&SnpWeights>,
+    fid_iid_list: &[(String, String)],
+    out_prefix: &str,
+    num_people_per_block: Option<usize>,
+    num_snps_per_chunk: Option<usize>,
+    progress: &dyn ProgressSink,
+) -> Result<(), Error> {
+    let num_people = geno.num_people();
+    if fid_iid_list.len() != num_people {
+        return Err(Error::DimensionMismatch(format!(
+            "{} people IDs for the genotypes of {} people",
+            fid_iid_list.len(),
+            num_people
+        )));
+    }
+    if let Some(w) = snp_weights {
+        w.check_num_snps(geno.num_snps())?;
+    }
+    let num_people_per_block =
+        num_people_per_block.unwrap_or(DEFAULT_GRM_NUM_PEOPLE_PER_BLOCK);
+    if num_people_per_block == 0 {
+        return Err(Error::Generic(
+            "num_people_per_block has to be positive".to_string(),
+        ));
+    }
+    let num_snps_per_chunk =
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+
+    let (bin_path, n_bin_path, id_path) = get_gcta_grm_paths(out_prefix);
+    let mut id_writer = create_file(&id_path)?;
+    for (fid, iid) in fid_iid_list.iter() {
+        writeln!(id_writer, "{}\t{}", fid, iid)?;
+    }
+    let mut bin_writer = create_file(&bin_path)?;
+    let mut n_bin_writer = create_file(&n_bin_path)?;
+    for start in (0..num_people).step_by(num_people_per_block) {
+        let end = std::cmp::min(start + num_people_per_block, num_people);
+        progress.phase_start(&format!(
+            "computing the GRM rows [{}, {}) of {}",
+            start, end, num_people
+        ));
+        // every block streams the same SNPs, so they pass the filter alike
+        let (block, num_snps) = get_grm_lower_block(
+            geno,
+            &snp_range,
+            snp_filter,
+            sqrt_weights.as_deref(),
+            start,
+            end,
+            num_snps_per_chunk,
+        );
+        if num_snps <= 0. {
+            return Err(Error::Generic("the GRM has no SNPs".to_string()));
+        }
+        let n_bytes = (num_snps as f32).to_le_bytes();
+        for i in start..end {
+            for &v in block.slice(s![i - start, ..=i]).iter() {
+                bin_writer.write_all(&(v / num_snps as f32).to_le_bytes())?;
+                n_bin_writer.write_all(&n_bytes)?;
+            }
+        }
+    }
+    bin_writer.flush()?;
+    n_bin_writer.flush()?;
+    Ok(())
+}
+
+/// A GRM held in memory as its lower triangle including the diagonal, in
+/// the layout of the GCTA `.grm.bin` file
+#[derive(Clone, PartialEq, Debug)]
+pub struct Grm {
+    num_people: usize,
+    lower_triangle: Vec<f32>,
+}
+
+impl Grm {
+    pub fn new(
+        num_people: usize,
+        lower_triangle: Vec<f32>,
+    ) -> Result<Grm, Error> {
+        if lower_triangle.len() != num_people * (num_people + 1) / 2 {
+            return Err(Error::DimensionMismatch(format!(
+                "{} entries for the lower triangle of the GRM of {} people",
+                lower_triangle.len(),
+                num_people
+            )));
+        }
+        Ok(Grm {
+            num_people,
+            lower_triangle,
+        })
+    }
+
+    /// Reads the `.grm.bin` and `.grm.id` files with the `prefix`, where the
+    /// number of people is the number of lines in the `.grm.id` file
+    pub fn from_gcta_files(prefix: &str) -> Result<Grm, Error> {
+        let (bin_path, _, id_path) = get_gcta_grm_paths(prefix);
+        let num_people = BufReader::new(
+            OpenOptions::new().read(true).open(&id_path).map_err(
+                |io_error| Error::IO {
+                    why: format!("failed to open {}", id_path),
+                    io_error,
+                },
+            )?,
+        )
+        .lines()
+        .count();
+        let mut bytes = Vec::new();
+        OpenOptions::new()
+            .read(true)
+            .open(&bin_path)
+            .map_err(|io_error| Error::IO {
+                why: format!("failed to open {}", bin_path),
+                io_error,
+            })?
+            .read_to_end(&mut bytes)?;
+        if bytes.len() % 4 != 0 {
+            return Err(Error::Generic(format!(
+                "{} has {} bytes, which is not a whole number of f32",
+                bin_path,
+                bytes.len()
+            )));
+        }
+        let lower_triangle = bytes
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Grm::new(num_people, lower_triangle)
+    }
+
+    pub fn num_people(&self) -> usize {
+        self.num_people
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> f32 {
+        let (i, j) = if i >= j { (i, j) } else { (j, i) };
+        self.lower_triangle[i * (i + 1) / 2 + j]
+    }
+
+    /// `tr(K)`, exactly
+    pub fn trace(&self) -> f64 {
+        (0..self.num_people).map(|i| self.get(i, i) as f64).sum()
+    }
+
+    /// `tr(K K)`, i.e. the sum of squares of all the entries, exactly
+    pub fn trace_of_square(&self) -> f64 {
+        let lower_ssq: f64 = self
+            .lower_triangle
+            .iter()
+            .map(|&v| v as f64 * v as f64)
+            .sum();
+        let diag_ssq: f64 = (0..self.num_people)
+            .map(|i| self.get(i, i) as f64 * self.get(i, i) as f64)
+            .sum();
+        2. * lower_ssq - diag_ssq
+    }
+
+    /// `K M` for the GRM `K`, computed a row of `K` at a time
+    pub fn dot(&self, matrix: &Array<f64, Ix2>) -> Array<f64, Ix2> {
+        let mut product = Array::<f64, Ix2>::zeros(matrix.dim());
+        product
+            .axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, mut row)| {
+                for (j, m_row) in matrix.axis_iter(Axis(0)).enumerate() {
+                    row.scaled_add(self.get(i, j) as f64, &m_row);
+                }
+            });
+        product
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, stack, Array, Axis, Ix2};
+    use tempfile::TempDir;
+
+    use super::{get_gcta_grm_paths, write_gcta_grm, Grm};
+    use crate::{
+        progress::SilentProgress, snp_chunk_filter::SnpChunkFilter,
+        snp_weights::SnpWeights, util::get_fid_iid_list,
+        util::matrix_util::normalize_matrix_columns_inplace,
+    };
+
+    #[test]
+    fn test_write_gcta_grm() {
+        let geno = array![
+            [0f32, 1., 2., 1., 0.],
+            [1., 1., 0., 2., 1.],
+            [2., 0., 1., 1., 1.],
+            [0., 2., 1., 0., 2.],
+            [1., 0., 2., 2., 0.]
+        ];
+        let ids: Vec<(String, String)> = (0..5)
+            .map(|i| (format!("f{}", i), format!("i{}", i)))
+            .collect();
+        let dir = TempDir::new().unwrap();
+        let prefix = dir.path().join("g").to_str().unwrap().to_string();
+        let weights = SnpWeights::new(vec![1., 4., 1., 0., 1.]).unwrap();
+        write_gcta_grm(
+            &geno,
+            Some(OrderedIntegerSet::from_slice(&[[0, 2], [4, 4]])),
+            None,
+            Some(&weights),
+            &ids,
+            &prefix,
+            Some(2),
+            Some(3),
+            &SilentProgress,
+        )
+        .unwrap();
+
+        let mut wx = geno.select(Axis(1), &[0, 1, 2, 4]);
+        normalize_matrix_columns_inplace(&mut wx, 0);
+        wx.column_mut(1).mapv_inplace(|v| v * 2.);
+        let expected: Array<f32, Ix2> = wx.dot(&wx.t()) / 7.;
+        let grm = Grm::from_gcta_files(&prefix).unwrap();
+        assert_eq!(grm.num_people(), 5);
+        for i in 0..5 {
+            for j in 0..5 {
+                assert!((grm.get(i, j) - expected[[i, j]]).abs() < 1e-5);
+            }
+        }
+        let (_, n_bin_path, id_path) = get_gcta_grm_paths(&prefix);
+        assert_eq!(std::fs::read(n_bin_path).unwrap().len(), 15 * 4);
+        assert_eq!(get_fid_iid_list(&id_path).unwrap(), ids);
+
+        let expected_64 = expected.mapv(|x| x as f64);
+        assert!((grm.trace() - expected_64.diag().sum()).abs() < 1e-4);
+        assert!(
+            (grm.trace_of_square()
+                - expected_64.dot(&expected_64).diag().sum())
+            .abs()
+                < 1e-4
+        );
+
+        let m = array![[1., 0.], [0., 2.], [1., 1.], [-1., 0.], [0., 3.]];
+        let product = grm.dot(&m);
+        let expected_product = expected_64.dot(&m);
+        for (a, b) in product.iter().zip(expected_product.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        // the monomorphic SNP is dropped from its chunk as it is streamed
+        let with_monomorphic =
+            stack(Axis(1), &[geno.view(), Array::ones((5, 1)).view()]).unwrap();
+        write_gcta_grm(
+            &with_monomorphic,
+            None,
+            Some(&SnpChunkFilter::default().dropping_monomorphic()),
+            None,
+            &ids,
+            &prefix,
+            Some(3),
+            Some(4),
+            &SilentProgress,
+        )
+        .unwrap();
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let expected: Array<f32, Ix2> = x.dot(&x.t()) / 5.;
+        let grm = Grm::from_gcta_files(&prefix).unwrap();
+        for i in 0..5 {
+            for j in 0..5 {
+                assert!((grm.get(i, j) - expected[[i, j]]).abs() < 1e-5);
+            }
+        }
+
+        assert!(Grm::new(3, vec![1.; 5]).is_err());
+        assert!(write_gcta_grm(
+            &geno,
+            None,
+            None,
+            None,
+            &ids[..4],
+            &prefix,
+            None,
+            None,
+            &SilentProgress
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,721 @@
+use biofile::plink_bed::PlinkBed;
+use math::stats::{n_choose_2, sum_of_squares, sum_of_squares_f32};
+use ndarray::{Array, Ix1, Ix2};
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::{
+    error::Error,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    trace_estimator::{
+        estimate_gxg_dot_y_norm_sq, estimate_gxg_kernel_dot_matrix,
+        estimate_tr_gxg_ki_gxg_kj, estimate_tr_k_gxg_k, get_gxg_gram_trace,
+        get_gxg_kk_trace,
+    },
+    util::{
+        get_file_line_tokens,
+        matrix_util::{
+            derive_seed, normalize_matrix_columns_inplace, ProbeType,
+        },
+    },
+};
+
+/// The chromosome and the base pair coordinate of a SNP
+pub type SnpPosition = (String, u64);
+
+/// Pairs of GxG basis SNPs that are within `max_bp_distance` base pairs of
+/// each other on the same chromosome, or whose squared correlation is at least
+/// `min_r_sq`, are excluded from the GxG kernel, as they may still be in
+/// residual LD.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct GxgPairExclusionCriteria {
+    max_bp_distance: Option<u64>,
+    min_r_sq: Option<f64>,
+}
+
+impl GxgPairExclusionCriteria {
+    pub fn new(
+        max_bp_distance: Option<u64>,
+        min_r_sq: Option<f64>,
+    ) -> GxgPairExclusionCriteria {
+        GxgPairExclusionCriteria {
+            max_bp_distance,
+            min_r_sq,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_bp_distance.is_none() && self.min_r_sq.is_none()
+    }
+}
+
+/// The pairs of basis SNPs, as column indices `(i, j)` with `i < j`, that are
+/// left out of a GxG component. The GxG kernel of the component is then
+/// `sum_{included (i, j)} (g_i * g_j)(g_i * g_j)^T / num_included_pairs`.
+///
+/// If the number of pairs is capped, only `sampled_pairs`, drawn uniformly
+/// from the pairs that are not excluded, are included. Since the kernel is an
+/// average over its pairs, the kernel of the sampled pairs is an unbiased
+/// estimate of the kernel of all the remaining pairs.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GxgPairExclusion {
+    num_basis_snps: usize,
+    excluded_pairs: Vec<(usize, usize)>,
+    sampled_pairs: Option<Vec<(usize, usize)>>,
+}
+
+impl GxgPairExclusion {
+    /// Excludes nothing, i.e. every pair of basis SNPs is in the kernel
+    pub fn none(num_basis_snps: usize) -> GxgPairExclusion {
+        GxgPairExclusion {
+            num_basis_snps,
+            excluded_pairs: Vec::new(),
+            sampled_pairs: None,
+        }
+    }
+
+    pub fn from_pairs(
+        num_basis_snps: usize,
+        mut pairs: Vec<(usize, usize)>,
+    ) -> Result<GxgPairExclusion, String> {
+        for &(i, j) in pairs.iter() {
+            if i >= j || j >= num_basis_snps {
+                return Err(format!(
+                    "invalid GxG pair ({}, {}) for {} basis SNPs",
+                    i, j, num_basis_snps
+                ));
+            }
+        }
+        pairs.sort();
+        pairs.dedup();
+        Ok(GxgPairExclusion {
+            num_basis_snps,
+            excluded_pairs: pairs,
+            sampled_pairs: None,
+        })
+    }
+
+    /// `basis` is the matrix of GxG basis SNPs of shape (num_people,
+    /// num_basis_snps) and need not be normalized. `positions[i]` is the
+    /// position of the `i`-th basis SNP, and is required only if the criteria
+    /// include a maximum base pair distance.
+    pub fn new(
+        criteria: &GxgPairExclusionCriteria,
+        basis: &Array<f32, Ix2>,
+        positions: Option<&[SnpPosition]>,
+    ) -> Result<GxgPairExclusion, String> {
+        let (num_people, num_basis_snps) = basis.dim();
+        if let Some(positions) = positions {
+            if positions.len() != num_basis_snps {
+                return Err(format!(
+                    "{} positions provided for {} GxG basis SNPs",
+                    positions.len(),
+                    num_basis_snps
+                ));
+            }
+        }
+        let close_positions = match (criteria.max_bp_distance, positions) {
+            (None, _) => None,
+            (Some(d), Some(positions)) => Some((d, positions)),
+            (Some(_), None) => {
+                return Err(
+                    "SNP positions are required to exclude GxG pairs by \
+                    base pair distance"
+                        .to_string(),
+                )
+            }
+        };
+        let r_sq = criteria.min_r_sq.map(|min_r_sq| {
+            let mut normalized = basis.clone();
+            normalize_matrix_columns_inplace(&mut normalized, 0);
+            let corr = normalized.t().dot(&normalized) / num_people as f32;
+            (min_r_sq, corr)
+        });
+
+        let mut excluded_pairs = Vec::new();
+        for i in 0..num_basis_snps {
+            for j in i + 1..num_basis_snps {
+                let is_close = match close_positions {
+                    Some((d, positions)) => {
+                        let (chrom_i, bp_i) = &positions[i];
+                        let (chrom_j, bp_j) = &positions[j];
+                        chrom_i == chrom_j
+                            && (*bp_i as i64 - *bp_j as i64).abs() as u64 <= d
+                    }
+                    None => false,
+                };
+                let is_correlated = match &r_sq {
+                    Some((min_r_sq, corr)) => {
+                        let r = corr[[i, j]] as f64;
+                        r * r >= *min_r_sq
+                    }
+                    None => false,
+                };
+                if is_close || is_correlated {
+                    excluded_pairs.push((i, j));
+                }
+            }
+        }
+        Ok(GxgPairExclusion {
+            num_basis_snps,
+            excluded_pairs,
+            sampled_pairs: None,
+        })
+    }
+
+    #[inline]
+    pub fn num_basis_snps(&self) -> usize {
+        self.num_basis_snps
+    }
+
+    #[inline]
+    pub fn excluded_pairs(&self) -> &[(usize, usize)] {
+        &self.excluded_pairs
+    }
+
+    #[inline]
+    pub fn num_excluded_pairs(&self) -> usize {
+        self.excluded_pairs.len()
+    }
+
+    /// The number of pairs that are not excluded, regardless of the cap
+    #[inline]
+    pub fn num_uncapped_pairs(&self) -> usize {
+        n_choose_2(self.num_basis_snps) - self.excluded_pairs.len()
+    }
+
+    /// The number of pairs in the GxG kernel, i.e. the `mm` the kernel is
+    /// normalized by
+    #[inline]
+    pub fn num_included_pairs(&self) -> usize {
+        match &self.sampled_pairs {
+            Some(sampled_pairs) => sampled_pairs.len(),
+            None => self.num_uncapped_pairs(),
+        }
+    }
+
+    #[inline]
+    pub fn is_capped(&self) -> bool {
+        self.sampled_pairs.is_some()
+    }
+
+    #[inline]
+    pub fn is_excluded(&self, i: usize, j: usize) -> bool {
+        let pair = if i < j { (i, j) } else { (j, i) };
+        self.excluded_pairs.binary_search(&pair).is_ok()
+    }
+
+    /// Whether the pair is in the GxG kernel, taking the cap into account
+    #[inline]
+    pub fn is_included(&self, i: usize, j: usize) -> bool {
+        if i == j {
+            return false;
+        }
+        let pair = if i < j { (i, j) } else { (j, i) };
+        match &self.sampled_pairs {
+            Some(sampled_pairs) => sampled_pairs.binary_search(&pair).is_ok(),
+            None => self.excluded_pairs.binary_search(&pair).is_err(),
+        }
+    }
+
+    /// If more than `max_pairs` pairs are not excluded, keeps only
+    /// `max_pairs` of them drawn uniformly at random without replacement.
+    pub fn cap_num_pairs<R: Rng>(
+        mut self,
+        max_pairs: usize,
+        rng: &mut R,
+    ) -> GxgPairExclusion {
+        let num_uncapped_pairs = self.num_uncapped_pairs();
+        if num_uncapped_pairs <= max_pairs {
+            self.sampled_pairs = None;
+            return self;
+        }
+        let excluded_indices: Vec<usize> = self
+            .excluded_pairs
+            .iter()
+            .map(|&(i, j)| pair_to_linear_index(i, j, self.num_basis_snps))
+            .collect();
+        let mut linear_indices: Vec<usize> =
+            rand::seq::index::sample(rng, num_uncapped_pairs, max_pairs)
+                .into_vec()
+                .into_iter()
+                .map(|k| {
+                    // the k-th pair that is not excluded is at linear index
+                    // k + p, where p is the number of excluded indices e_q
+                    // with e_q - q <= k
+                    let mut lo = 0;
+                    let mut hi = excluded_indices.len();
+                    while lo < hi {
+                        let mid = (lo + hi) / 2;
+                        if excluded_indices[mid] - mid <= k {
+                            lo = mid + 1;
+                        } else {
+                            hi = mid;
+                        }
+                    }
+                    k + lo
+                })
+                .collect();
+        linear_indices.sort();
+
+        let m = self.num_basis_snps;
+        let mut sampled_pairs = Vec::with_capacity(max_pairs);
+        let mut row = 0;
+        let mut row_start = 0;
+        for l in linear_indices.into_iter() {
+            while l >= row_start + (m - 1 - row) {
+                row_start += m - 1 - row;
+                row += 1;
+            }
+            sampled_pairs.push((row, row + 1 + l - row_start));
+        }
+        self.sampled_pairs = Some(sampled_pairs);
+        self
+    }
+
+    /// `normalized_basis` should be the same basis the kernel is computed
+    /// from.
+    pub fn pair_products(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+    ) -> GxgPairProducts {
+        match &self.sampled_pairs {
+            Some(sampled_pairs) => GxgPairProducts::Only(
+                get_pair_product_matrix(sampled_pairs, normalized_basis),
+            ),
+            None => GxgPairProducts::AllExcept(get_pair_product_matrix(
+                &self.excluded_pairs,
+                normalized_basis,
+            )),
+        }
+    }
+}
+
+#[inline]
+fn pair_to_linear_index(i: usize, j: usize, num_basis_snps: usize) -> usize {
+    i * num_basis_snps - i * (i + 1) / 2 + (j - i - 1)
+}
+
+/// Returns the matrix whose columns are the element-wise products `g_i * g_j`
+/// of the `pairs`, of shape (num_people, pairs.len()).
+fn get_pair_product_matrix(
+    pairs: &[(usize, usize)],
+    normalized_basis: &Array<f32, Ix2>,
+) -> Array<f32, Ix2> {
+    let num_people = normalized_basis.dim().0;
+    let mut products = Array::zeros((num_people, pairs.len()));
+    for (k, &(i, j)) in pairs.iter().enumerate() {
+        products.column_mut(k).assign(
+            &(&normalized_basis.column(i) * &normalized_basis.column(j)),
+        );
+    }
+    products
+}
+
+/// The columns are the element-wise products `g_i * g_j` of pairs of
+/// normalized basis SNPs. Let `A` be the sum of `(g_i * g_j)(g_i * g_j)^T`
+/// over all the pairs and `W` be the matrix held by the variant. The
+/// unnormalized GxG kernel is `A - W W^T` for `AllExcept` and `W W^T` for
+/// `Only`.
+///
+/// The trace methods below are unnormalized, i.e. the caller divides them by
+/// the number of included pairs of each kernel involved. Their randomized
+/// estimates are reproducible if `seed` is `Some`.
+pub enum GxgPairProducts {
+    AllExcept(Array<f32, Ix2>),
+    Only(Array<f32, Ix2>),
+}
+
+impl GxgPairProducts {
+    /// `tr(K)`
+    pub fn gram_trace(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+        num_random_vecs: usize,
+        probe_type: ProbeType,
+        seed: Option<u64>,
+    ) -> Result<f64, Error> {
+        match self {
+            GxgPairProducts::AllExcept(w) => Ok(get_gxg_gram_trace(
+                normalized_basis,
+                None,
+                None,
+                num_random_vecs,
+                probe_type,
+                seed,
+            )? - sum_of_squares(w.iter())),
+            GxgPairProducts::Only(w) => Ok(sum_of_squares(w.iter())),
+        }
+    }
+
+    /// `tr(K K)`
+    pub fn kk_trace(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+        num_random_vecs: usize,
+        probe_type: ProbeType,
+        seed: Option<u64>,
+    ) -> Result<f64, Error> {
+        match self {
+            GxgPairProducts::AllExcept(w) => {
+                let full_mm = n_choose_2(normalized_basis.dim().1) as f64;
+                Ok(get_gxg_kk_trace(
+                    normalized_basis,
+                    None,
+                    None,
+                    num_random_vecs,
+                    probe_type,
+                    seed.map(|s| derive_seed(s, 0)),
+                )? * full_mm
+                    * full_mm
+                    - 2. * estimate_gxg_pair_product_quadratic_forms(
+                        normalized_basis,
+                        w,
+                        num_random_vecs,
+                        seed.map(|s| derive_seed(s, 1)),
+                    )
+                    + pair_products_gram_norm_sq(w, w))
+            }
+            GxgPairProducts::Only(w) => Ok(pair_products_gram_norm_sq(w, w)),
+        }
+    }
+
+    /// `y^T K y`
+    pub fn dot_y_norm_sq(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+        y: &Array<f32, Ix1>,
+        num_random_vecs: usize,
+        seed: Option<u64>,
+    ) -> f64 {
+        match self {
+            GxgPairProducts::AllExcept(w) => {
+                estimate_gxg_dot_y_norm_sq(
+                    normalized_basis,
+                    y,
+                    None,
+                    None,
+                    num_random_vecs,
+                    seed,
+                ) - pair_products_dot_y_norm_sq(w, y)
+            }
+            GxgPairProducts::Only(w) => pair_products_dot_y_norm_sq(w, y),
+        }
+    }
+
+    /// `K V` for the columns `V` of `rhs`, estimated with `num_random_vecs`
+    /// random sketches of all the pairs for `AllExcept` and exact for
+    /// `Only`. Each call draws new sketches, unless it is given the `seed`
+    /// of an earlier call.
+    pub fn dot_matrix(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+        rhs: &Array<f32, Ix2>,
+        num_random_vecs: usize,
+        seed: Option<u64>,
+    ) -> Array<f32, Ix2> {
+        match self {
+            GxgPairProducts::AllExcept(w) => {
+                estimate_gxg_kernel_dot_matrix(
+                    normalized_basis,
+                    rhs,
+                    None,
+                    None,
+                    num_random_vecs,
+                    seed,
+                ) - &w.dot(&w.t().dot(rhs))
+            }
+            GxgPairProducts::Only(w) => w.dot(&w.t().dot(rhs)),
+        }
+    }
+
+    /// `tr(K_g K)`, where `K_g` is the GRM of the genotypes in `geno_bed`
+    pub fn tr_k_gxg_k(
+        &self,
+        geno_bed: &mut PlinkBed,
+        normalized_basis: &Array<f32, Ix2>,
+        num_random_vecs: usize,
+        seed: Option<u64>,
+    ) -> f64 {
+        match self {
+            GxgPairProducts::AllExcept(w) => {
+                let full_tr = estimate_tr_k_gxg_k(
+                    geno_bed,
+                    normalized_basis,
+                    None,
+                    None,
+                    None,
+                    num_random_vecs,
+                    None,
+                    seed,
+                ) * n_choose_2(normalized_basis.dim().1) as f64;
+                if w.dim().1 == 0 {
+                    full_tr
+                } else {
+                    full_tr - get_tr_k_pair_products_gram(geno_bed, w, None)
+                }
+            }
+            GxgPairProducts::Only(w) => {
+                get_tr_k_pair_products_gram(geno_bed, w, None)
+            }
+        }
+    }
+
+    /// `tr(K_1 K_2)` for the kernels of two GxG components
+    pub fn tr_gxg_k1_gxg_k2(
+        &self,
+        normalized_basis: &Array<f32, Ix2>,
+        other: &GxgPairProducts,
+        other_normalized_basis: &Array<f32, Ix2>,
+        num_random_vecs: usize,
+        seed: Option<u64>,
+    ) -> f64 {
+        match (self, other) {
+            (
+                GxgPairProducts::AllExcept(w1),
+                GxgPairProducts::AllExcept(w2),
+            ) => {
+                estimate_tr_gxg_ki_gxg_kj(
+                    normalized_basis,
+                    other_normalized_basis,
+                    None,
+                    None,
+                    None,
+                    num_random_vecs,
+                    seed.map(|s| derive_seed(s, 0)),
+                ) * n_choose_2(normalized_basis.dim().1) as f64
+                    * n_choose_2(other_normalized_basis.dim().1) as f64
+                    - estimate_gxg_pair_product_quadratic_forms(
+                        normalized_basis,
+                        w2,
+                        num_random_vecs,
+                        seed.map(|s| derive_seed(s, 1)),
+                    )
+                    - estimate_gxg_pair_product_quadratic_forms(
+                        other_normalized_basis,
+                        w1,
+                        num_random_vecs,
+                        seed.map(|s| derive_seed(s, 2)),
+                    )
+                    + pair_products_gram_norm_sq(w1, w2)
+            }
+            (GxgPairProducts::Only(w1), GxgPairProducts::AllExcept(w2)) => {
+                estimate_gxg_pair_product_quadratic_forms(
+                    other_normalized_basis,
+                    w1,
+                    num_random_vecs,
+                    seed,
+                ) - pair_products_gram_norm_sq(w1, w2)
+            }
+            (GxgPairProducts::AllExcept(_), GxgPairProducts::Only(_)) => other
+                .tr_gxg_k1_gxg_k2(
+                    other_normalized_basis,
+                    self,
+                    normalized_basis,
+                    num_random_vecs,
+                    seed,
+                ),
+            (GxgPairProducts::Only(w1), GxgPairProducts::Only(w2)) => {
+                pair_products_gram_norm_sq(w1, w2)
+            }
+        }
+    }
+}
+
+/// Returns `sum_e w_e^T A w_e` over the columns `w_e` of `pair_products`,
+/// where `A = sum_{all i < j} (g_i * g_j)(g_i * g_j)^T` is the unnormalized
+/// GxG kernel of `normalized_basis` including every pair. If `seed` is
+/// `Some`, the probes of the `e`-th column are drawn from the stream
+/// `derive_seed(seed, e)`.
+pub fn estimate_gxg_pair_product_quadratic_forms(
+    normalized_basis: &Array<f32, Ix2>,
+    pair_products: &Array<f32, Ix2>,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+) -> f64 {
+    pair_products
+        .gencolumns()
+        .into_iter()
+        .enumerate()
+        .map(|(e, w)| {
+            estimate_gxg_dot_y_norm_sq(
+                normalized_basis,
+                &w.to_owned(),
+                None,
+                None,
+                num_random_vecs,
+                seed.map(|s| derive_seed(s, e as u64)),
+            )
+        })
+        .sum()
+}
+
+/// Returns `||W_1^T W_2||_F^2`, i.e. `tr(W_1 W_1^T W_2 W_2^T)`
+pub fn pair_products_gram_norm_sq(
+    pair_products_1: &Array<f32, Ix2>,
+    pair_products_2: &Array<f32, Ix2>,
+) -> f64 {
+    sum_of_squares(pair_products_1.t().dot(pair_products_2).iter())
+}
+
+/// Returns `||W^T y||^2`
+pub fn pair_products_dot_y_norm_sq(
+    pair_products: &Array<f32, Ix2>,
+    y: &Array<f32, Ix1>,
+) -> f64 {
+    sum_of_squares(pair_products.t().dot(y).iter())
+}
+
+/// Returns `tr(K W W^T)` where `K` is the GRM of the normalized genotypes in
+/// `geno_bed` and `W` is `pair_products`
+pub fn get_tr_k_pair_products_gram(
+    geno_bed: &PlinkBed,
+    pair_products: &Array<f32, Ix2>,
+    num_snps_per_chunk: Option<usize>,
+) -> f64 {
+    let chunk_size = num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let ssq = geno_bed
+        .col_chunk_iter(chunk_size, None)
+        .into_par_iter()
+        .fold_with(0f32, |mut acc, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            acc += sum_of_squares_f32(snp_chunk.t().dot(pair_products).iter());
+            acc
+        })
+        .reduce(|| 0f32, |a, b| a + b);
+    ssq as f64 / geno_bed.total_num_snps() as f64
+}
+
+/// Reads the chromosome and base pair coordinate of every SNP in the bim file
+pub fn get_bim_snp_positions(
+    bim_path: &str,
+) -> Result<Vec<SnpPosition>, Error> {
+    get_file_line_tokens(bim_path, 6)?
+        .into_iter()
+        .map(|toks| {
+            let bp = toks[3].parse::<u64>().map_err(|why| {
+                Error::Generic(format!(
+                    "failed to parse the base pair coordinate {} in {}: {}",
+                    toks[3], bim_path, why
+                ))
+            })?;
+            Ok((toks[0].to_owned(), bp))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array};
+    use rand::thread_rng;
+
+    use super::{
+        pair_products_dot_y_norm_sq, GxgPairExclusion,
+        GxgPairExclusionCriteria, GxgPairProducts,
+    };
+
+    #[test]
+    fn test_gxg_pair_exclusion() {
+        let basis = array![
+            [0., 1., 0., 2.],
+            [1., 0., 1., 1.],
+            [2., 1., 2., 0.],
+            [1., 2., 1., 1.],
+            [0., 0., 0., 2.]
+        ];
+        let positions = vec![
+            ("1".to_string(), 100),
+            ("1".to_string(), 150),
+            ("1".to_string(), 10_000),
+            ("2".to_string(), 120),
+        ];
+
+        let by_distance = GxgPairExclusion::new(
+            &GxgPairExclusionCriteria::new(Some(50), None),
+            &basis,
+            Some(positions.as_slice()),
+        )
+        .unwrap();
+        assert_eq!(by_distance.excluded_pairs(), &[(0, 1)]);
+        assert_eq!(by_distance.num_included_pairs(), 5);
+        assert!(by_distance.is_excluded(1, 0));
+        assert!(!by_distance.is_excluded(0, 3));
+
+        // columns 0 and 2 are identical and column 3 is perfectly
+        // anti-correlated with them
+        let by_r_sq = GxgPairExclusion::new(
+            &GxgPairExclusionCriteria::new(None, Some(0.99)),
+            &basis,
+            None,
+        )
+        .unwrap();
+        assert_eq!(by_r_sq.excluded_pairs(), &[(0, 2), (0, 3), (2, 3)]);
+
+        assert!(GxgPairExclusion::new(
+            &GxgPairExclusionCriteria::new(Some(50), None),
+            &basis,
+            None,
+        )
+        .is_err());
+        assert!(GxgPairExclusion::from_pairs(4, vec![(2, 1)]).is_err());
+        assert_eq!(
+            GxgPairExclusion::none(4),
+            GxgPairExclusion::from_pairs(4, Vec::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pair_products() {
+        let basis = array![[1., 2., 3.], [4., 5., 6.]];
+        let exclusion =
+            GxgPairExclusion::from_pairs(3, vec![(1, 2), (0, 2)]).unwrap();
+        match exclusion.pair_products(&basis) {
+            GxgPairProducts::AllExcept(products) => {
+                assert_eq!(products, array![[3., 6.], [24., 30.]]);
+                assert_eq!(
+                    pair_products_dot_y_norm_sq(
+                        &products,
+                        &Array::from_vec(vec![1., 1.])
+                    ),
+                    (27. * 27. + 36. * 36.) as f64
+                );
+            }
+            GxgPairProducts::Only(_) => panic!("the exclusion is not capped"),
+        }
+    }
+
+    #[test]
+    fn test_cap_num_pairs() {
+        let num_basis_snps = 30;
+        let excluded: Vec<(usize, usize)> =
+            (1..num_basis_snps).map(|j| (0, j)).collect();
+        let exclusion =
+            GxgPairExclusion::from_pairs(num_basis_snps, excluded).unwrap();
+        assert_eq!(exclusion.num_uncapped_pairs(), 406);
+
+        let uncapped = exclusion.clone().cap_num_pairs(1000, &mut thread_rng());
+        assert!(!uncapped.is_capped());
+        assert_eq!(uncapped.num_included_pairs(), 406);
+
+        let capped = exclusion.cap_num_pairs(400, &mut thread_rng());
+        assert!(capped.is_capped());
+        assert_eq!(capped.num_included_pairs(), 400);
+        let mut num_included = 0;
+        for i in 0..num_basis_snps {
+            for j in i + 1..num_basis_snps {
+                if capped.is_included(i, j) {
+                    assert!(!capped.is_excluded(i, j));
+                    assert!(capped.is_included(j, i));
+                    num_included += 1;
+                }
+            }
+        }
+        assert_eq!(num_included, 400);
+        match capped.pair_products(&Array::zeros((5, num_basis_snps))) {
+            GxgPairProducts::Only(products) => {
+                assert_eq!(products.dim(), (5, 400))
+            }
+            GxgPairProducts::AllExcept(_) => panic!("the exclusion is capped"),
+        }
+    }
+}
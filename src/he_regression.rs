@@ -0,0 +1,262 @@
+//! Haseman-Elston (HE) regression estimates of the variance components, as a
+//! cross-check on the trace-based method of moments estimates in
+//! `heritability_estimator`.
+//!
+//! The cross-products `y_i y_j` of the standardized phenotype over the pairs
+//! of people `i < j` are regressed on the GRM entries `K_k[i, j]` of the
+//! components without an intercept, i.e. the variances solve `A s = b` with
+//!
+//! - `A_kl = sum_{i < j} K_k[i, j] K_l[i, j]`
+//! - `b_k = sum_{i < j} K_k[i, j] y_i y_j`
+//!
+//! The GRM entries are computed exactly, one strip of
+//! `num_people_per_strip` rows at a time from a pass over the genotypes, so
+//! the `n x n` GRM is never formed.
+
+use std::fmt;
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{s, Array, Ix1, Ix2};
+use ndarray_linalg::Solve;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    reml::NOISE_COMPONENT_NAME,
+    stochastic::GenotypeSource,
+    util::matrix_util::{
+        normalize_matrix_columns_inplace, normalize_vector_inplace,
+    },
+};
+
+/// The strips of the GRM held in memory have this many rows by default
+pub const DEFAULT_HE_NUM_PEOPLE_PER_STRIP: usize = 512;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct HeRegressionEstimates {
+    /// The names of the components, with the noise component last
+    pub component_names: Vec<String>,
+    /// The variances in units of the phenotypic variance, with the noise
+    /// variance last as the remainder
+    pub variances: Vec<f64>,
+    pub total_heritability: f64,
+    pub num_pairs: usize,
+}
+
+impl fmt::Display for HeRegressionEstimates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "HE regression over {} pairs of people", self.num_pairs)?;
+        for (name, variance) in
+            self.component_names.iter().zip(self.variances.iter())
+        {
+            writeln!(f, "{}: variance {:.5}", name, variance)?;
+        }
+        write!(f, "total h2: {:.5}", self.total_heritability)
+    }
+}
+
+/// The rows `[start, end)` of `X X^T / num_snps` for the standardized
+/// genotypes `X` of the SNPs in `snp_range`
+fn get_grm_strip<G: GenotypeSource>(
+    geno: &G,
+    snp_range: &OrderedIntegerSet<usize>,
+    start: usize,
+    end: usize,
+    num_snps_per_chunk: usize,
+) -> Array<f32, Ix2> {
+    let num_people = geno.num_people();
+    let strip = geno.fold_snp_chunks(
+        Some(snp_range.clone()),
+        num_snps_per_chunk,
+        || Array::<f32, Ix2>::zeros((end - start, num_people)),
+        |acc, _, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            acc + &snp_chunk.slice(s![start..end, ..]).dot(&snp_chunk.t())
+        },
+        |a, b| a + &b,
+    );
+    strip / snp_range.size() as f32
+}
+
+/// `components` are the names and SNP ranges of the genetic components, to
+/// which the noise component is added. The phenotype is standardized first,
+/// so the variances are in units of the phenotypic variance.
+pub fn estimate_heritability_he<G: GenotypeSource>(
+    geno: &G,
+    components: Vec<(String, OrderedIntegerSet<usize>)>,
+    pheno: &Array<f32, Ix1>,
+    num_people_per_strip: Option<usize>,
+    num_snps_per_chunk: Option<usize>,
+) -> Result<HeRegressionEstimates, Error> {
+    let num_people = geno.num_people();
+    if pheno.len() != num_people {
+        return Err(Error::DimensionMismatch(format!(
+            "expected a phenotype of length {}, received {}",
+            num_people,
+            pheno.len()
+        )));
+    }
+    if num_people < 2 {
+        return Err(Error::Generic(
+            "HE regression needs at least two people".to_string(),
+        ));
+    }
+    if components.is_empty() {
+        return Err(Error::Generic(
+            "expected at least one genetic component".to_string(),
+        ));
+    }
+    if let Some((name, _)) = components.iter().find(|(_, r)| r.size() == 0) {
+        return Err(Error::Generic(format!("component {} has no SNPs", name)));
+    }
+    let num_people_per_strip =
+        num_people_per_strip.unwrap_or(DEFAULT_HE_NUM_PEOPLE_PER_STRIP);
+    if num_people_per_strip == 0 {
+        return Err(Error::Generic(
+            "num_people_per_strip has to be positive".to_string(),
+        ));
+    }
+    let num_snps_per_chunk =
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let num_components = components.len();
+
+    let mut y = pheno.mapv(|x| x as f64);
+    normalize_vector_inplace(&mut y, 0);
+    let mut a = Array::<f64, Ix2>::zeros((num_components, num_components));
+    let mut b = Array::<f64, Ix1>::zeros(num_components);
+    for start in (0..num_people).step_by(num_people_per_strip) {
+        let end = std::cmp::min(start + num_people_per_strip, num_people);
+        let strips: Vec<Array<f32, Ix2>> = components
+            .iter()
+            .map(|(_, range)| {
+                get_grm_strip(geno, range, start, end, num_snps_per_chunk)
+            })
+            .collect();
+        for i in start..end {
+            // only the pairs above the diagonal
+            for j in i + 1..num_people {
+                let yy = y[i] * y[j];
+                for k in 0..num_components {
+                    let k_ij = strips[k][[i - start, j]] as f64;
+                    b[k] += k_ij * yy;
+                    for l in k..num_components {
+                        a[[k, l]] += k_ij * strips[l][[i - start, j]] as f64;
+                    }
+                }
+            }
+        }
+    }
+    for k in 0..num_components {
+        for l in 0..k {
+            a[[k, l]] = a[[l, k]];
+        }
+    }
+    let sig_sq = a.solve_into(b).map_err(|why| {
+        Error::SingularNormalEquations(format!(
+            "the HE regression components may be collinear: {:?}",
+            why
+        ))
+    })?;
+
+    let mut component_names: Vec<String> =
+        components.into_iter().map(|(name, _)| name).collect();
+    component_names.push(NOISE_COMPONENT_NAME.to_string());
+    let total_heritability = sig_sq.sum();
+    let mut variances = sig_sq.to_vec();
+    variances.push(1. - total_heritability);
+    Ok(HeRegressionEstimates {
+        component_names,
+        variances,
+        total_heritability,
+        num_pairs: num_people * (num_people - 1) / 2,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{s, Array, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::StandardNormal;
+
+    use super::{estimate_heritability_he, get_grm_strip};
+    use crate::{
+        error::Error, util::matrix_util::normalize_matrix_columns_inplace,
+    };
+
+    #[test]
+    fn test_estimate_heritability_he() {
+        let (num_people, num_snps) = (1000, 400);
+        let geno: Array<f32, Ix2> =
+            Array::random((num_people, num_snps), StandardNormal).mapv(|x| {
+                if x > 0.5 {
+                    1.
+                } else if x > -0.5 {
+                    0.
+                } else {
+                    2.
+                }
+            });
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let all_snps = OrderedIntegerSet::from_slice(&[[0, num_snps - 1]]);
+
+        let grm = x.dot(&x.t()) / num_snps as f32;
+        let strip = get_grm_strip(&geno, &all_snps, 100, 130, 7);
+        for (a, b) in strip.iter().zip(grm.slice(s![100..130, ..]).iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        let effects = Array::random(num_snps, StandardNormal)
+            .mapv(|b| (b * (0.6 / num_snps as f64).sqrt()) as f32);
+        let noise = Array::random(num_people, StandardNormal)
+            .mapv(|e| (e * 0.4f64.sqrt()) as f32);
+        let pheno = x.dot(&effects) + &noise;
+        let est = estimate_heritability_he(
+            &geno,
+            vec![("G".to_string(), all_snps.clone())],
+            &pheno,
+            Some(300),
+            None,
+        )
+        .unwrap();
+        assert_eq!(est.component_names, vec!["G", "noise"]);
+        assert_eq!(est.num_pairs, num_people * (num_people - 1) / 2);
+        assert!((est.total_heritability - 0.6).abs() < 0.25, "{}", est);
+        assert!((est.variances.iter().sum::<f64>() - 1.).abs() < 1e-10);
+
+        assert!(estimate_heritability_he(
+            &geno,
+            Vec::new(),
+            &pheno,
+            None,
+            None
+        )
+        .is_err());
+        match estimate_heritability_he(
+            &geno,
+            vec![("G".to_string(), all_snps.clone())],
+            &pheno.slice(s![1..]).to_owned(),
+            None,
+            None,
+        ) {
+            Err(Error::DimensionMismatch(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        match estimate_heritability_he(
+            &geno,
+            vec![
+                ("G1".to_string(), all_snps.clone()),
+                ("G2".to_string(), all_snps),
+            ],
+            &pheno,
+            Some(300),
+            // a single chunk keeps the two strips bitwise identical
+            Some(num_snps),
+        ) {
+            Err(Error::SingularNormalEquations(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! The analytical sampling covariance of the variance components solved
+//! from the Haseman-Elston normal equations `A x = b`.
+//!
+//! The traces in `A` are taken as fixed, so the sampling variation of the
+//! solution `x = A^{-1} b` comes from the quadratic forms `b_i = y^T K_i y`
+//! alone, and its covariance is the sandwich `A^{-1} Cov(b) A^{-1}`. For
+//! Gaussian phenotypes with the covariance `V = sum_k x_k K_k`,
+//! `Cov(b_i, b_j) = 2 tr(K_i V K_j V)`.
+
+use std::fmt;
+
+use ndarray::{Array, Ix1, Ix2};
+use ndarray_linalg::Inverse;
+use serde::Serialize;
+
+const NUM_DISPLAY_DECIMALS: usize = 5;
+
+/// The variance components with the noise last, and their analytical
+/// sampling covariance.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct HeritabilityEstimate {
+    pub component_names: Vec<String>,
+    pub variances: Vec<f64>,
+    /// The sandwich covariance `A^{-1} Cov(b) A^{-1}` of the `variances`
+    pub covariance: Array<f64, Ix2>,
+}
+
+impl HeritabilityEstimate {
+    /// `a` is the matrix of the normal equations, and `b_covariance` the
+    /// covariance of their right-hand side.
+    pub fn new(
+        component_names: Vec<String>,
+        variances: Vec<f64>,
+        a: &Array<f64, Ix2>,
+        b_covariance: &Array<f64, Ix2>,
+    ) -> Result<HeritabilityEstimate, String> {
+        let num_components = variances.len();
+        if component_names.len() != num_components
+            || a.dim() != (num_components, num_components)
+            || b_covariance.dim() != (num_components, num_components)
+        {
+            return Err(format!(
+                "{} component names, {} variances, a normal equation matrix \
+                of shape {:?} and a covariance of shape {:?} do not match",
+                component_names.len(),
+                num_components,
+                a.dim(),
+                b_covariance.dim()
+            ));
+        }
+        Ok(HeritabilityEstimate {
+            component_names,
+            variances,
+            covariance: get_sandwich_covariance(a, b_covariance)?,
+        })
+    }
+
+    pub fn num_components(&self) -> usize {
+        self.variances.len()
+    }
+
+    pub fn standard_errors(&self) -> Vec<f64> {
+        (0..self.num_components())
+            .map(|i| self.covariance[[i, i]].max(0.).sqrt())
+            .collect()
+    }
+
+    /// The sum of the variances of all the components but the noise, and its
+    /// standard error
+    pub fn total_genetic_variance(&self) -> (f64, f64) {
+        let num_genetic = self.num_components().saturating_sub(1);
+        let total = self.variances[..num_genetic].iter().sum();
+        let mut variance = 0.;
+        for i in 0..num_genetic {
+            for j in 0..num_genetic {
+                variance += self.covariance[[i, j]];
+            }
+        }
+        (total, variance.max(0.).sqrt())
+    }
+
+    /// The share of the total variance due to all the components but the
+    /// noise, and its standard error by the delta method
+    pub fn total_heritability(&self) -> (f64, f64) {
+        let num_components = self.num_components();
+        let total: f64 = self.variances.iter().sum();
+        let (genetic, _) = self.total_genetic_variance();
+        let noise = total - genetic;
+        // the gradient of genetic / total
+        let gradient: Vec<f64> = (0..num_components)
+            .map(|i| {
+                if i + 1 < num_components {
+                    noise / (total * total)
+                } else {
+                    -genetic / (total * total)
+                }
+            })
+            .collect();
+        let mut variance = 0.;
+        for i in 0..num_components {
+            for j in 0..num_components {
+                variance += gradient[i] * self.covariance[[i, j]] * gradient[j];
+            }
+        }
+        (genetic / total, variance.max(0.).sqrt())
+    }
+}
+
+/// The estimates of the G and GxG variance components from the normal
+/// equations `A x = b`. The phenotype is normalized, so the variances are the
+/// fractions of the phenotypic variance due to each component.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct GxgHeritabilityResult {
+    pub normal_eq_a: Array<f64, Ix2>,
+    pub normal_eq_b: Array<f64, Ix1>,
+    /// The components are G, the GxG components, any precomputed GRMs and the
+    /// noise, in that order
+    pub component_names: Vec<String>,
+    pub var_components: Vec<f64>,
+    /// The analytical standard errors of the `var_components`
+    pub standard_errors: Vec<f64>,
+    /// The analytical sandwich covariance of the `var_components`
+    pub covariance: Array<f64, Ix2>,
+    /// The share of the total variance due to G and all the GxG components,
+    /// with the standard error by the delta method
+    pub h2_total: f64,
+    pub h2_total_standard_error: f64,
+    /// The normalized GxG basis SNPs, which can be passed on to the
+    /// estimation for the next phenotype
+    #[serde(skip_serializing)]
+    pub normalized_le_snps_arr: Vec<Array<f32, Ix2>>,
+    /// The normalized phenotype, with the covariates projected out if any
+    #[serde(skip_serializing)]
+    pub normalized_pheno_arr: Array<f32, Ix1>,
+}
+
+impl GxgHeritabilityResult {
+    pub fn new(
+        normal_eq_a: Array<f64, Ix2>,
+        normal_eq_b: Array<f64, Ix1>,
+        estimate: HeritabilityEstimate,
+        normalized_le_snps_arr: Vec<Array<f32, Ix2>>,
+        normalized_pheno_arr: Array<f32, Ix1>,
+    ) -> GxgHeritabilityResult {
+        let standard_errors = estimate.standard_errors();
+        let (h2_total, h2_total_standard_error) = estimate.total_heritability();
+        GxgHeritabilityResult {
+            normal_eq_a,
+            normal_eq_b,
+            component_names: estimate.component_names,
+            var_components: estimate.variances,
+            standard_errors,
+            covariance: estimate.covariance,
+            h2_total,
+            h2_total_standard_error,
+            normalized_le_snps_arr,
+            normalized_pheno_arr,
+        }
+    }
+
+    pub fn g_variance(&self) -> f64 {
+        self.var_components[0]
+    }
+
+    /// The variances of the GxG components
+    pub fn gxg_variances(&self) -> &[f64] {
+        &self.var_components[1..self.var_components.len() - 1]
+    }
+
+    pub fn noise_variance(&self) -> f64 {
+        self.var_components[self.var_components.len() - 1]
+    }
+}
+
+impl fmt::Display for GxgHeritabilityResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ((name, variance), se) in self
+            .component_names
+            .iter()
+            .zip(self.var_components.iter())
+            .zip(self.standard_errors.iter())
+        {
+            writeln!(
+                f,
+                "{}: variance {:.*} (SE {:.*})",
+                name, NUM_DISPLAY_DECIMALS, variance, NUM_DISPLAY_DECIMALS, se
+            )?;
+        }
+        writeln!(
+            f,
+            "total GxG variance: {:.*}",
+            NUM_DISPLAY_DECIMALS,
+            self.gxg_variances().iter().sum::<f64>()
+        )?;
+        write!(
+            f,
+            "total h2: {:.*} (SE {:.*})",
+            NUM_DISPLAY_DECIMALS,
+            self.h2_total,
+            NUM_DISPLAY_DECIMALS,
+            self.h2_total_standard_error
+        )
+    }
+}
+
+/// `A^{-1} b_covariance A^{-1}` for the symmetric `A`
+pub fn get_sandwich_covariance(
+    a: &Array<f64, Ix2>,
+    b_covariance: &Array<f64, Ix2>,
+) -> Result<Array<f64, Ix2>, String> {
+    let a_inv = a.inv().map_err(|why| {
+        format!(
+            "failed to invert the normal equation matrix for the sandwich \
+            covariance: {:?}",
+            why
+        )
+    })?;
+    Ok(a_inv.dot(b_covariance).dot(&a_inv.t()))
+}
+
+impl fmt::Display for HeritabilityEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "component\tvariance\tanalytical_se")?;
+        for ((name, variance), se) in self
+            .component_names
+            .iter()
+            .zip(self.variances.iter())
+            .zip(self.standard_errors().iter())
+        {
+            writeln!(
+                f,
+                "{}\t{:.*}\t{:.*}",
+                name, NUM_DISPLAY_DECIMALS, variance, NUM_DISPLAY_DECIMALS, se
+            )?;
+        }
+        let (total, se) = self.total_genetic_variance();
+        writeln!(
+            f,
+            "total_genetic\t{:.*}\t{:.*}",
+            NUM_DISPLAY_DECIMALS, total, NUM_DISPLAY_DECIMALS, se
+        )?;
+        write!(
+            f,
+            "\nanalytical covariance of the variance estimates\n{:.*}",
+            NUM_DISPLAY_DECIMALS, self.covariance
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{get_sandwich_covariance, HeritabilityEstimate};
+
+    #[test]
+    fn test_heritability_estimate() {
+        let a = array![[2., 1.], [1., 2.]];
+        let b_covariance = array![[4., 1.], [1., 2.]];
+        // A^{-1} = [[2, -1], [-1, 2]] / 3
+        let covariance = get_sandwich_covariance(&a, &b_covariance).unwrap();
+        let expected = array![[14., -7.], [-7., 8.]] / 9.;
+        for (x, y) in covariance.iter().zip(expected.iter()) {
+            assert!((x - y).abs() < 1e-10);
+        }
+
+        let est = HeritabilityEstimate::new(
+            vec!["g".to_string(), "noise".to_string()],
+            vec![0.3, 0.7],
+            &a,
+            &b_covariance,
+        )
+        .unwrap();
+        let se = est.standard_errors();
+        assert!((se[0] - (14f64 / 9.).sqrt()).abs() < 1e-10);
+        assert!((se[1] - (8f64 / 9.).sqrt()).abs() < 1e-10);
+        let (total, total_se) = est.total_genetic_variance();
+        assert_eq!(total, 0.3);
+        assert!((total_se - se[0]).abs() < 1e-10);
+        // h2 = g / (g + e) has the gradient (e, -g) / (g + e)^2 = (0.7, -0.3)
+        let (h2, h2_se) = est.total_heritability();
+        assert!((h2 - 0.3).abs() < 1e-10);
+        let expected_h2_var = (0.49 * 14. + 2. * 0.21 * 7. + 0.09 * 8.) / 9.;
+        assert!((h2_se - expected_h2_var.sqrt()).abs() < 1e-10);
+
+        assert!(HeritabilityEstimate::new(
+            vec!["g".to_string()],
+            vec![0.3, 0.7],
+            &a,
+            &b_covariance,
+        )
+        .is_err());
+        assert!(get_sandwich_covariance(
+            &array![[1., 1.], [1., 1.]],
+            &b_covariance
+        )
+        .is_err());
+    }
+}
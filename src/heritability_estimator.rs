@@ -1,220 +1,1327 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Debug, iter::Sum, path::Path};
 
 use biofile::{plink_bed::PlinkBed, plink_bim::PlinkBim};
+use log::debug;
 use math::{
-    partition::integer_partitions::Partition,
+    partition::integer_partitions::{IntegerPartitions, Partition},
     set::{
         ordered_integer_set::OrderedIntegerSet,
         traits::{Finite, Intersect},
     },
     stats::{n_choose_2, sum_of_squares, sum_of_squares_f32},
 };
-use ndarray::{array, Array, Ix1, Ix2};
+use ndarray::{array, s, stack, Array, Axis, Ix1, Ix2};
 use ndarray_linalg::Solve;
 use ndarray_parallel::prelude::*;
-use program_flow::OrExit;
+use num::{FromPrimitive, Integer, NumCast, ToPrimitive};
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use rayon::prelude::*;
 
 use crate::{
+    checkpoint::{CheckpointConfig, EstimationCheckpoint},
+    compute::ComputeConfig,
+    confidence_interval::BOOTSTRAP_CI_LEVEL,
+    covariate::CovariateProjection,
+    environment::Environment,
     error::Error,
-    jackknife::{AdditiveJackknife, Jackknife, JackknifePartitions},
+    exact::{
+        get_exact_normal_equations, get_g_kernel, GxgGramMatrices, TraceMode,
+    },
+    genotype_sketch::GenotypeSketch,
+    grm::Grm,
+    gxg_pair_exclusion::{GxgPairExclusion, GxgPairProducts},
+    heritability_estimate::{GxgHeritabilityResult, HeritabilityEstimate},
+    integer_set::{
+        chromosomal::ChromosomalIntegerSet,
+        collect::SortedCollecting,
+        index::{narrow_index_set, widen_index_set, SnpIndex},
+        iter::Iter,
+        ops::{MergeDifference, MergeIntersect, Union},
+    },
+    jackknife::{AdditiveJackknife, JackknifePartitions},
     matrix_ops::{
         column_normalized_row_ssq, get_column_mean_and_std,
-        get_gxg_dot_semi_kronecker_z_from_gz_and_ssq, normalized_g_dot_matrix,
-        normalized_g_transpose_dot_matrix, pheno_g_pheno_from_pheno_matrix,
-        pheno_k_pheno, sum_of_column_wise_inner_product,
+        get_gxg_dot_semi_kronecker_z_from_gz_and_ssq,
+        normalized_g_transpose_dot_matrix, sum_of_column_wise_inner_product,
         DEFAULT_NUM_SNPS_PER_CHUNK,
     },
-    partitioned_jackknife_estimates::PartitionedJackknifeEstimates,
+    missing_genotype::{
+        resolve_missing_genotypes, MeanImputed, MissingGenotypePolicy,
+    },
+    nnls::solve_nonnegative_normal_equations,
+    parametric_bootstrap::{
+        get_parametric_bootstrap_intervals, ParametricBootstrapInterval,
+    },
+    partitioned_jackknife_estimates::{
+        LocoEstimates, PartitionedJackknifeEstimates, PhenoEstimateTable,
+        TraceEstimate,
+    },
+    progress::ProgressSink,
+    resampling::{Replicate, Resampling, ResamplingBlocks},
+    sex_chrom::{XChromDosage, XDosageCompensated},
+    snp_weights::SnpWeights,
+    stochastic::{g_dot, g_gt_dot, gxg_k_dot, GenotypeSource, PeopleSubset},
     trace_estimator::{
-        estimate_gxg_dot_y_norm_sq, estimate_gxg_gram_trace,
-        estimate_gxg_kk_trace, estimate_tr_gxg_ki_gxg_kj, estimate_tr_k_gxg_k,
-        estimate_tr_kk, get_gxg_dot_y_norm_sq_from_basis_bed,
+        check_num_random_vecs, compute_yky, estimate_gxg_dot_y_norm_sq,
+        estimate_tr_k_gxg_k, estimate_tr_kk_hutchpp,
+        get_gxg_dot_y_norm_sq_from_basis_bed, get_gxg_gram_trace,
+        get_gxg_kk_trace,
     },
     util::{
-        get_pheno_matrix, get_pheno_path_to_arr,
+        check_num_people, check_pheno_num_people, get_pheno_matrix,
+        get_pheno_path_to_arr,
         matrix_util::{
-            generate_plus_minus_one_bernoulli_matrix,
-            normalize_matrix_columns_inplace, normalize_vector_inplace,
+            derive_seed, generate_plus_minus_one_bernoulli_matrix,
+            generate_probe_matrix, generate_sign_matrix,
+            generate_trace_probe_matrix, normalize_matrix_columns_inplace,
+            normalize_vector_inplace, ProbeType,
         },
     },
+    warning::{Warning, WarningCode},
+    xty_cache::XtyCache,
 };
 
 pub const DEFAULT_PARTITION_NAME: &str = "default_partition";
+/// The name of the component of all the dominance SNPs when the SNPs are not
+/// assigned to the components by a model
+pub const DOMINANCE_PARTITION_NAME: &str = "dominance";
 
 pub type Coordinate = usize;
 pub type SnpPartition = Partition<Coordinate>;
+/// A SNP partition stored with the compact `SnpIndex`, used where many
+/// partitions and their jackknife complements are kept in memory.
+pub type CompactSnpPartition = Partition<SnpIndex>;
+
+/// How often the progress on the resampling replicates is checkpointed
+pub const NUM_REPLICATES_PER_CHECKPOINT: usize = 50;
+
+const YGY_PHASE: &str = "generating ygy_pheno_matrix_jackknife";
+const GGZ_PHASE: &str = "generating ggz_jackknife";
+const PARAMETRIC_BOOTSTRAP_PHASE: &str =
+    "drawing the parametric bootstrap replicates of the normal equations";
+
+// The independent random streams of a seeded estimation, each derived from
+// the seed with `derive_seed`
+const PROBE_SEED_STREAM: u64 = 0;
+const BLOCK_SEED_STREAM: u64 = 1;
+const REPLICATE_SEED_STREAM: u64 = 2;
+const PARAMETRIC_BOOTSTRAP_SEED_STREAM: u64 = 3;
+const GXG_TRACE_SEED_STREAM: u64 = 4;
+const GXG_YKY_SEED_STREAM: u64 = 5;
+const ANALYTICAL_COVARIANCE_SEED_STREAM: u64 = 6;
+
+/// The replicates of the `resampling`, drawn from the replicate stream of
+/// the `seed` if it is `Some`
+fn get_seeded_replicates(
+    resampling: &Resampling,
+    seed: Option<u64>,
+) -> Vec<Replicate> {
+    match seed {
+        Some(seed) => resampling.get_replicates(&mut StdRng::seed_from_u64(
+            derive_seed(seed, REPLICATE_SEED_STREAM),
+        )),
+        None => resampling.get_replicates(&mut thread_rng()),
+    }
+}
 
+/// If `covariates` is `Some`, the phenotypes, the trace probes and the
+/// kernels are projected onto the orthogonal complement of the covariates
+/// before the normal equations are formed.
+/// If `people` is `Some`, only those rows of the bed are in the estimation,
+/// e.g. the people on a keep list, and the covariates have one row for each
+/// of them in order.
+/// If `snp_weights` is `Some`, the kernels are weighted by them, e.g. by the
+/// LDAK weights, and the SNPs are counted by the sum of their weights.
+/// The missing genotypes in the SNPs of the partitions are handled by the
+/// `missing_genotypes` policy, the same way for every trace and `y^T K y`.
+/// The random probes are drawn from the `probe_type`, and are sparse sign
+/// vectors if `probe_sparsity` is given.
+/// If the `trace_mode` selects the exact path for the number of people, the
+/// traces are computed exactly with one probe per person in place of the
+/// `num_random_vecs` random probes.
+/// If `trace_tolerance` is `Some`, batches of `num_random_vecs` probes are
+/// added until the relative Monte Carlo standard error of `tr(K_i K_i)` is at
+/// most the tolerance for every partition, up to `max_num_random_vecs`
+/// probes, or one probe per person if that is `None`. The traces on the full
+/// data and their Monte Carlo standard errors are in the `trace_estimates` of
+/// every estimate.
+/// If `nonnegative` is true, the normal equations are solved under the
+/// constraint that the variance components, including the noise, are
+/// nonnegative, on the full data and in every replicate alike.
+/// If `analytical_se` is true, every estimate also carries the analytical
+/// sandwich covariance of the variance components on the full data.
+/// If `num_parametric_bootstrap_reps` is `Some`, every estimate also carries
+/// the percentile intervals from that many draws of the normal equations,
+/// whose covariance is estimated from the resampling replicates.
+/// If `compute` is `Some`, it sets the threads and the memory budget of the
+/// estimation.
+/// If `keep_resampling_blocks` is true, every estimate also carries the SNP
+/// blocks of the resampling replicates.
+/// If `checkpoint` is `Some`, the progress is saved to its directory after
+/// every streamed block of SNPs and every `NUM_REPLICATES_PER_CHECKPOINT`
+/// replicates, and a run with `resume` continues from the checkpoint there.
+/// If `seed` is `Some`, the probes, the random SNP blocks and the bootstrap
+/// replicates are all derived from it, so that the estimates can be
+/// reproduced regardless of the number of threads.
+/// The phases, the replicates and the traces on the full data are reported
+/// to the `progress`.
 pub fn estimate_heritability(
     geno_bed: PlinkBed,
     geno_bim: PlinkBim<Coordinate>,
     pheno_path_vec: Vec<String>,
     num_random_vecs: usize,
-    num_jackknife_partitions: usize,
-) -> Result<HashMap<String, PartitionedJackknifeEstimates>, String> {
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    max_num_random_vecs: Option<usize>,
+    trace_tolerance: Option<f64>,
+    resampling: Resampling,
+    covariates: Option<&CovariateProjection>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    analytical_se: bool,
+    num_parametric_bootstrap_reps: Option<usize>,
+    keep_resampling_blocks: bool,
+    checkpoint: Option<&CheckpointConfig>,
+    compute: Option<&ComputeConfig>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<HashMap<String, PartitionedJackknifeEstimates>, Error> {
+    // the phenotype files list everyone in the bed
+    let mut pheno_matrix = get_pheno_matrix(&pheno_path_vec)?;
+    if let Some(people) = people {
+        check_pheno_num_people(
+            pheno_matrix.dim().0,
+            &pheno_path_vec.join(", "),
+            geno_bed.num_people,
+        )?;
+        pheno_matrix = pheno_matrix
+            .select(Axis(0), &people.iter().collect::<Vec<usize>>());
+    }
+    Ok(estimate_heritability_multi_pheno(
+        geno_bed,
+        geno_bim,
+        pheno_matrix,
+        pheno_path_vec,
+        num_random_vecs,
+        probe_type,
+        probe_sparsity,
+        trace_mode,
+        max_num_random_vecs,
+        trace_tolerance,
+        resampling,
+        covariates,
+        people,
+        snp_weights,
+        missing_genotypes,
+        nonnegative,
+        analytical_se,
+        num_parametric_bootstrap_reps,
+        keep_resampling_blocks,
+        checkpoint,
+        compute,
+        seed,
+        progress,
+    )?
+    .into_hash_map())
+}
+
+/// Estimates the heritability of every column of `pheno_matrix`, named by
+/// `pheno_names`. The traces `tr(K_i K_j)` do not depend on the phenotypes,
+/// so they are estimated once for all the columns, and only the normal
+/// equations are solved per phenotype in each replicate. If `people` is
+/// `Some`, `pheno_matrix` has one row for each of them in order.
+pub fn estimate_heritability_multi_pheno(
+    geno_bed: PlinkBed,
+    geno_bim: PlinkBim<Coordinate>,
+    pheno_matrix: Array<f32, Ix2>,
+    pheno_names: Vec<String>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    max_num_random_vecs: Option<usize>,
+    trace_tolerance: Option<f64>,
+    resampling: Resampling,
+    covariates: Option<&CovariateProjection>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    analytical_se: bool,
+    num_parametric_bootstrap_reps: Option<usize>,
+    keep_resampling_blocks: bool,
+    checkpoint: Option<&CheckpointConfig>,
+    compute: Option<&ComputeConfig>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<PhenoEstimateTable, Error> {
+    estimate_heritability_on_blocks(
+        geno_bed,
+        geno_bim,
+        pheno_matrix,
+        pheno_names,
+        num_random_vecs,
+        probe_type,
+        probe_sparsity,
+        trace_mode,
+        max_num_random_vecs,
+        trace_tolerance,
+        resampling,
+        None,
+        covariates,
+        people,
+        snp_weights,
+        missing_genotypes,
+        nonnegative,
+        analytical_se,
+        num_parametric_bootstrap_reps,
+        keep_resampling_blocks,
+        checkpoint,
+        compute,
+        seed,
+        progress,
+    )
+}
+
+/// Estimates the heritability of every column of `pheno_matrix` with each
+/// chromosome of `geno_bim` left out in turn. The per-chromosome estimates
+/// are the drops of the total heritability when the chromosome is left out,
+/// and the standard errors are from the jackknife over the chromosomes.
+pub fn estimate_heritability_loco(
+    geno_bed: PlinkBed,
+    mut geno_bim: PlinkBim<Coordinate>,
+    pheno_matrix: Array<f32, Ix2>,
+    pheno_names: Vec<String>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    covariates: Option<&CovariateProjection>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    compute: Option<&ComputeConfig>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<Vec<LocoEstimates>, Error> {
+    let chrom_snps = ChromosomalIntegerSet::from(
+        geno_bim.get_chrom_to_fileline_positions().map_err(|why| {
+            format!("failed to get the chromosomes from the bim: {:?}", why)
+        })?,
+    );
+    if chrom_snps.num_chroms() < 2 {
+        return Err(Error::Generic(format!(
+            "leaving out one chromosome at a time requires at least two \
+            chromosomes, found {}",
+            chrom_snps.num_chroms()
+        )));
+    }
+    let chroms: Vec<String> = chrom_snps.chroms().cloned().collect();
+    progress.message(&format!(
+        "\n=> leaving out each of the chromosomes {:?}",
+        chroms
+    ));
+    let chrom_blocks: Vec<OrderedIntegerSet<usize>> =
+        chrom_snps.iter().map(|(_, snps)| snps.clone()).collect();
+    let table = estimate_heritability_on_blocks(
+        geno_bed,
+        geno_bim,
+        pheno_matrix,
+        pheno_names,
+        num_random_vecs,
+        probe_type,
+        probe_sparsity,
+        trace_mode,
+        None,
+        None,
+        Resampling::BlockJackknife {
+            num_blocks: chroms.len(),
+        },
+        Some(chrom_blocks),
+        covariates,
+        people,
+        snp_weights,
+        missing_genotypes,
+        nonnegative,
+        false,
+        None,
+        false,
+        None,
+        compute,
+        seed,
+        progress,
+    )?;
+    Ok(table
+        .iter()
+        .map(|(name, est)| {
+            LocoEstimates::new(name.clone(), chroms.clone(), est.clone())
+        })
+        .collect::<Result<Vec<LocoEstimates>, String>>()?)
+}
+
+/// `blocks`, if `Some`, are the SNP blocks of the `resampling` in place of
+/// the ones it divides the partitions into. The estimation runs on the pool
+/// of the `compute` config if it sets the number of threads.
+fn estimate_heritability_on_blocks(
+    geno_bed: PlinkBed,
+    geno_bim: PlinkBim<Coordinate>,
+    pheno_matrix: Array<f32, Ix2>,
+    pheno_names: Vec<String>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    max_num_random_vecs: Option<usize>,
+    trace_tolerance: Option<f64>,
+    resampling: Resampling,
+    blocks: Option<Vec<OrderedIntegerSet<usize>>>,
+    covariates: Option<&CovariateProjection>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    analytical_se: bool,
+    num_parametric_bootstrap_reps: Option<usize>,
+    keep_resampling_blocks: bool,
+    checkpoint_config: Option<&CheckpointConfig>,
+    compute: Option<&ComputeConfig>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<PhenoEstimateTable, Error> {
+    let compute = compute.cloned().unwrap_or_default();
+    compute.install(|| {
+        estimate_heritability_in_pool(
+            geno_bed,
+            geno_bim,
+            pheno_matrix,
+            pheno_names,
+            num_random_vecs,
+            probe_type,
+            probe_sparsity,
+            trace_mode,
+            max_num_random_vecs,
+            trace_tolerance,
+            resampling,
+            blocks,
+            covariates,
+            people,
+            snp_weights,
+            missing_genotypes,
+            nonnegative,
+            analytical_se,
+            num_parametric_bootstrap_reps,
+            keep_resampling_blocks,
+            checkpoint_config,
+            &compute,
+            seed,
+            progress,
+        )
+    })?
+}
+
+fn estimate_heritability_in_pool(
+    geno_bed: PlinkBed,
+    geno_bim: PlinkBim<Coordinate>,
+    mut pheno_matrix: Array<f32, Ix2>,
+    pheno_names: Vec<String>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    probe_sparsity: Option<f64>,
+    trace_mode: TraceMode,
+    max_num_random_vecs: Option<usize>,
+    trace_tolerance: Option<f64>,
+    resampling: Resampling,
+    blocks: Option<Vec<OrderedIntegerSet<usize>>>,
+    covariates: Option<&CovariateProjection>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    missing_genotypes: MissingGenotypePolicy,
+    nonnegative: bool,
+    analytical_se: bool,
+    num_parametric_bootstrap_reps: Option<usize>,
+    keep_resampling_blocks: bool,
+    checkpoint_config: Option<&CheckpointConfig>,
+    compute: &ComputeConfig,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<PhenoEstimateTable, Error> {
+    if pheno_names.len() != pheno_matrix.dim().1 {
+        return Err(Error::DimensionMismatch(format!(
+            "{} phenotype names for a phenotype matrix of {} columns",
+            pheno_names.len(),
+            pheno_matrix.dim().1
+        )));
+    }
     let partitions = geno_bim.get_fileline_partitions_or(
         DEFAULT_PARTITION_NAME,
         OrderedIntegerSet::from_slice(&[[0, geno_bed.total_num_snps() - 1]]),
     );
-    let partition_array: Vec<SnpPartition> =
+    // the wide partitions index the genotypes, while the jackknife blocks and
+    // the checkpoints hold the compact ones
+    let wide_partition_array: Vec<SnpPartition> =
         partitions.ordered_partition_array();
+    let partition_array: Vec<CompactSnpPartition> = wide_partition_array
+        .iter()
+        .map(narrow_index_set)
+        .collect::<Result<Vec<CompactSnpPartition>, String>>()?;
     let partition_sizes: Vec<usize> =
         partition_array.iter().map(|p| p.size()).collect();
+    if let Some(snp_weights) = snp_weights {
+        snp_weights.check_num_snps(geno_bed.total_num_snps())?;
+    }
 
-    let jackknife_partitions = JackknifePartitions::from_integer_set(
-        partition_array.clone(),
-        num_jackknife_partitions,
-        false,
-    );
-
+    if checkpoint_config.is_some()
+        && (max_num_random_vecs.is_some() || trace_tolerance.is_some())
+    {
+        return Err(Error::Generic(
+            "the probes added adaptively for max_num_random_vecs or \
+            trace_tolerance are not checkpointed"
+                .to_string(),
+        ));
+    }
+    let mut jackknife_partitions = match blocks {
+        Some(blocks) => {
+            if blocks.len() != resampling.num_blocks() {
+                return Err(Error::DimensionMismatch(format!(
+                    "{} SNP blocks given for {} blocks of the resampling",
+                    blocks.len(),
+                    resampling.num_blocks()
+                )));
+            }
+            JackknifePartitions::from_partitions(IntegerPartitions::new(
+                blocks
+                    .iter()
+                    .map(narrow_index_set)
+                    .collect::<Result<Vec<CompactSnpPartition>, String>>()?,
+            ))
+        }
+        None => resampling.get_blocks(
+            partition_array.clone(),
+            seed.map(|seed| derive_seed(seed, BLOCK_SEED_STREAM)),
+        ),
+    };
     let num_partitions = partition_array.len();
-    let num_people = geno_bed.num_people;
-    let num_pheno_paths = pheno_path_vec.len();
+    // the people dropped for their missing genotypes are left out of the
+    // phenotypes and the covariates as well
+    let kept_rows = resolve_missing_genotypes(
+        &PeopleSubset::new(&geno_bed, people)?,
+        Some(
+            wide_partition_array
+                .iter()
+                .fold(OrderedIntegerSet::new(), |acc, p| acc.union(p)),
+        ),
+        missing_genotypes,
+        DEFAULT_NUM_SNPS_PER_CHUNK,
+    )?;
+    let kept_people;
+    let kept_covariates;
+    let (people, covariates) = match &kept_rows {
+        None => (people, covariates),
+        Some(rows) => {
+            let current_people: Vec<usize> = match people {
+                Some(people) => people.iter().collect(),
+                None => (0..geno_bed.num_people).collect(),
+            };
+            check_pheno_num_people(
+                pheno_matrix.dim().0,
+                &pheno_names.join(", "),
+                current_people.len(),
+            )?;
+            progress.message(&format!(
+                "\n=> dropping {} people with missing genotypes",
+                current_people.len() - rows.len()
+            ));
+            kept_people = OrderedIntegerSet::collect_from_sorted_iter(
+                rows.iter().map(|&r| current_people[r]),
+            );
+            pheno_matrix = pheno_matrix.select(Axis(0), rows);
+            kept_covariates = match covariates {
+                Some(covariates) => Some(covariates.select_rows(rows)?),
+                None => None,
+            };
+            (Some(&kept_people), kept_covariates.as_ref())
+        }
+    };
+    let people_geno = PeopleSubset::new(&geno_bed, people)?;
+    let geno = MeanImputed::new(&people_geno);
+    let num_people = geno.num_people();
+    let num_phenos = pheno_names.len();
 
-    println!(
+    progress.message(&format!(
         "num_people: {}\n\
         total_num_snps: {}\n",
         num_people,
         partition_sizes.iter().fold(0, |acc, size| acc + *size)
-    );
+    ));
     partitions
         .ordered_partition_keys()
         .iter()
         .enumerate()
         .for_each(|(i, k)| {
-            println!("partition named {} has {} SNPs", k, partition_sizes[i]);
+            progress.message(&format!(
+                "partition named {} has {} SNPs",
+                k, partition_sizes[i]
+            ));
+            if let Some(snp_weights) = snp_weights {
+                progress.message(&format!(
+                    "partition named {} has a total SNP weight of {}",
+                    k,
+                    snp_weights.sum(&wide_partition_array[i])
+                ));
+            }
         });
 
-    let mut pheno_matrix = get_pheno_matrix(&pheno_path_vec)?;
+    check_pheno_num_people(
+        pheno_matrix.dim().0,
+        &pheno_names.join(", "),
+        num_people,
+    )?;
     normalize_matrix_columns_inplace(&mut pheno_matrix, 0);
+    if let Some(covariates) = covariates {
+        covariates.check_num_people(num_people, "the genotype bed")?;
+        progress.message(&format!(
+            "\n=> projecting out {} covariates including the intercept",
+            covariates.num_covariates()
+        ));
+        covariates.project_matrix_inplace(&mut pheno_matrix);
+    }
 
-    let yy = num_people as f64;
-    println!("\n=> yy: {}", yy);
-
-    println!("=> generating ggz_jackknife");
-    let random_vecs =
-        generate_plus_minus_one_bernoulli_matrix(num_people, num_random_vecs);
-    let ggz_jackknife = get_partitioned_ggz_jackknife(
-        &geno_bed,
-        &partition_array,
-        &jackknife_partitions,
-        &random_vecs,
+    // |y|^2 is num_people for the normalized phenotypes, and |M y|^2 after
+    // the covariates are projected out
+    let yy: Vec<f64> = match covariates {
+        Some(_) => pheno_matrix
+            .gencolumns()
+            .into_iter()
+            .map(|col| sum_of_squares(col.iter()))
+            .collect(),
+        None => vec![num_people as f64; num_phenos],
+    };
+    progress.message(&format!("\n=> yy: {:?}", yy));
+
+    // the probes of the exact path are the columns of a scaled identity, for
+    // which the traces are exact, see `generate_trace_probe_matrix`
+    let num_random_vecs = if trace_mode.is_exact(num_people) {
+        progress.message(&format!(
+            "\n=> computing the traces exactly with {} probe vectors",
+            num_people
+        ));
+        num_people
+    } else {
+        check_num_random_vecs(
+            num_random_vecs,
+            num_people,
+            partition_sizes.iter().sum(),
+            num_partitions,
+            progress,
+        )?
+    };
+    let num_snps_per_chunk = compute.get_num_snps_per_chunk(
+        num_people,
+        std::cmp::max(num_random_vecs, num_phenos),
+    )?;
+    progress.message(&format!(
+        "num_threads: {}\nnum_snps_per_chunk: {}",
+        compute.num_threads(),
+        num_snps_per_chunk
+    ));
+    let probe_seed = seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM));
+    let mut random_vecs = generate_trace_probe_matrix(
+        num_people,
+        num_random_vecs,
+        probe_type,
+        probe_sparsity,
+        probe_seed,
     );
+    if let Some(covariates) = covariates {
+        covariates.project_matrix_inplace(&mut random_vecs);
+    }
+    // the blocks and the probes of a resumed run are the checkpointed ones
+    let mut checkpoint = match checkpoint_config {
+        Some(config) => Some(
+            if config.resume && EstimationCheckpoint::exists(&config.dir) {
+                progress.message(&format!(
+                    "\n=> resuming from the checkpoint in {}",
+                    config.dir.display()
+                ));
+                let checkpoint = EstimationCheckpoint::load(&config.dir)?;
+                checkpoint.check_compatible_with(
+                    num_people,
+                    &pheno_names,
+                    &partition_sizes,
+                    num_random_vecs,
+                )?;
+                let checkpointed_blocks = checkpoint.blocks();
+                if checkpointed_blocks.len()
+                    != jackknife_partitions.num_partitions()
+                {
+                    return Err(Error::Generic(format!(
+                        "the checkpoint has {} SNP blocks, but the resampling \
+                        has {}",
+                        checkpointed_blocks.len(),
+                        jackknife_partitions.num_partitions()
+                    )));
+                }
+                jackknife_partitions = JackknifePartitions::from_partitions(
+                    IntegerPartitions::new(checkpointed_blocks),
+                );
+                random_vecs = checkpoint.probes().clone();
+                checkpoint
+            } else {
+                EstimationCheckpoint::new(
+                    num_people,
+                    pheno_names.clone(),
+                    partition_sizes.clone(),
+                    &jackknife_partitions.iter().collect::<Vec<_>>(),
+                    random_vecs.clone(),
+                )
+            },
+        ),
+        None => None,
+    };
+    let wide_jackknife_partitions =
+        widen_jackknife_partitions(&jackknife_partitions);
+    // block_sizes[i][b] is the number of SNPs of partition i in block b, or
+    // the sum of their weights
+    let block_sizes: Vec<Vec<f64>> = wide_partition_array
+        .iter()
+        .map(|p| {
+            wide_jackknife_partitions
+                .iter()
+                .map(|block| {
+                    let snps = p.merge_intersect(&block);
+                    match snp_weights {
+                        Some(w) => w.sum(&snps),
+                        None => snps.size() as f64,
+                    }
+                })
+                .collect()
+        })
+        .collect();
 
-    println!("=> generating ygy_pheno_matrix_jackknife");
-    let ygy_pheno_matrix_jackknife = get_partitioned_ygy_pheno_matrix_jackknife(
-        &geno_bed,
-        &partition_array,
-        &jackknife_partitions,
-        &pheno_matrix,
-    );
+    if let (Some(checkpoint), Some(config)) =
+        (checkpoint.as_mut(), checkpoint_config)
+    {
+        stream_checkpointed_ggz(
+            &geno,
+            &partition_array,
+            &jackknife_partitions,
+            snp_weights,
+            &random_vecs,
+            num_snps_per_chunk,
+            checkpoint,
+            &config.dir,
+            progress,
+        )?;
+    }
+
+    progress.phase_start(YGY_PHASE);
+    let checkpointed_ygy = checkpoint
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.ygy().cloned());
+    let ygy_pheno_matrix_jackknife: Vec<AdditiveJackknife<Array<f64, Ix1>>> =
+        match checkpointed_ygy {
+            Some(ygy) => ygy
+                .into_iter()
+                .map(AdditiveJackknife::from_components)
+                .collect(),
+            None => get_partitioned_ygy_pheno_matrix_jackknife(
+                &geno,
+                &partition_array,
+                &jackknife_partitions,
+                snp_weights,
+                &pheno_matrix,
+                num_snps_per_chunk,
+            )?,
+        };
+    progress.phase_end(YGY_PHASE);
+    if let (Some(checkpoint), Some(config)) =
+        (checkpoint.as_mut(), checkpoint_config)
+    {
+        if checkpoint.ygy().is_none() {
+            checkpoint.set_ygy(
+                ygy_pheno_matrix_jackknife
+                    .iter()
+                    .map(|ygy| ygy.additive_components.clone())
+                    .collect(),
+            );
+            checkpoint.save(&config.dir)?;
+        }
+    }
+
+    let mut ggz_jackknife: Vec<AdditiveJackknife<Array<f32, Ix2>>> =
+        match checkpoint.as_mut() {
+            Some(checkpoint) => checkpoint
+                .take_partitioned_ggz()
+                .into_iter()
+                .map(AdditiveJackknife::from_components)
+                .collect(),
+            None => {
+                progress.phase_start(GGZ_PHASE);
+                let ggz_jackknife = get_partitioned_ggz_jackknife(
+                    &geno,
+                    &partition_array,
+                    &jackknife_partitions,
+                    snp_weights,
+                    &random_vecs,
+                    num_snps_per_chunk,
+                );
+                progress.phase_end(GGZ_PHASE);
+                ggz_jackknife
+            }
+        };
 
     type PartitionedEstimates = Vec<f64>;
 
-    let get_heritability_point_estimate = |k: Option<usize>,
-                                           jackknife_partition: Option<
-        &SnpPartition,
-    >|
-     -> Vec<PartitionedEstimates> {
-        let mut a = get_normal_eqn_lhs_matrix(num_partitions, num_people);
-        let mut b_list: Vec<Array<f64, Ix1>> = (0..num_pheno_paths)
-            .collect::<Vec<usize>>()
-            .into_iter()
-            .map(|_| {
-                let mut b = Array::zeros(num_partitions + 1);
-                b[num_partitions] = yy;
-                b
+    let get_num_snps = |replicate: &Replicate| {
+        block_sizes
+            .iter()
+            .map(|sizes| replicate.weighted_sum(sizes))
+            .collect::<Vec<f64>>()
+    };
+
+    let get_b_list =
+        |replicate: &Replicate, num_snps: &[f64]| -> Vec<Array<f64, Ix1>> {
+            let mut b_list: Vec<Array<f64, Ix1>> = (0..num_phenos)
+                .collect::<Vec<usize>>()
+                .into_iter()
+                .map(|p| {
+                    let mut b = Array::zeros(num_partitions + 1);
+                    b[num_partitions] = yy[p];
+                    b
+                })
+                .collect();
+            for i in 0..num_partitions {
+                ygy_pheno_matrix_jackknife[i]
+                    .replicate_sum(replicate, |c, w| c * w)
+                    .unwrap()
+                    .iter()
+                    .enumerate()
+                    .for_each(|(p, ygy)| {
+                        b_list[p][i] = ygy / num_snps[i];
+                    });
+            }
+            b_list
+        };
+
+    // the normal equations `A x = b` of the replicate, with one `b` for
+    // each phenotype
+    let get_normal_equations = |ggz_jackknife: &[AdditiveJackknife<
+        Array<f32, Ix2>,
+    >],
+                                probe_counts: &[usize],
+                                random_vecs: &Array<f32, Ix2>,
+                                replicate: &Replicate|
+     -> (Array<f64, Ix2>, Vec<Array<f64, Ix1>>) {
+        let num_snps = get_num_snps(replicate);
+        let ggz_list: Vec<Array<f32, Ix2>> = ggz_jackknife
+            .iter()
+            .map(|ggz| {
+                project_covariates(
+                    ggz.replicate_sum(replicate, |c, w| c * w as f32).unwrap(),
+                    covariates,
+                )
             })
             .collect();
-        for i in 0..num_partitions {
-            let num_snps_i =
-                partition_minus_knife(&partition_array[i], jackknife_partition)
-                    .size() as f64;
-            let ggz_i = ggz_jackknife[i].sum_minus_component_or_sum(k).unwrap();
-
-            ygy_pheno_matrix_jackknife[i]
-                .sum_minus_component_or_sum(k)
-                .unwrap()
-                .iter()
-                .enumerate()
-                .for_each(|(p, ygy)| {
-                    b_list[p][i] = ygy / num_snps_i;
-                    //                    println!("pheno {} yk{}y: {}",
-                    // pheno_path_vec[p], i, b_list[p][i]);
-                });
+        let a = get_trace_matrix(
+            &ggz_list,
+            &num_snps,
+            probe_counts,
+            num_people,
+            get_projected_probes(random_vecs, covariates).as_ref(),
+            None,
+        );
+        (a, get_b_list(replicate, &num_snps))
+    };
 
-            a[[i, i]] = sum_of_squares_f32(ggz_i.iter()) as f64
-                / num_snps_i
-                / num_snps_i
-                / num_random_vecs as f64;
-            //            println!("tr(k_{}_k_{})_est: {} num_snps_i: {}", i, i,
-            // a[[i, i]], num_snps_i);
-            for j in i + 1..num_partitions {
-                let num_snps_j = match jackknife_partition {
-                    Some(jackknife_partition) => {
-                        (partition_array[j].clone() - jackknife_partition)
-                            .size() as f64
+    let get_heritability_point_estimate =
+        |ggz_jackknife: &[AdditiveJackknife<Array<f32, Ix2>>],
+         probe_counts: &[usize],
+         random_vecs: &Array<f32, Ix2>,
+         replicate: &Replicate|
+         -> Result<Vec<PartitionedEstimates>, Error> {
+            let (a, b_list) = get_normal_equations(
+                ggz_jackknife,
+                probe_counts,
+                random_vecs,
+                replicate,
+            );
+            if *replicate == Replicate::Full {
+                for i in 0..num_partitions {
+                    for j in i..num_partitions {
+                        progress.trace_value(
+                            &format!("tr(K_{} K_{})", i, j),
+                            a[[i, j]],
+                        );
                     }
-                    None => partition_sizes[j] as f64,
+                }
+            }
+            b_list
+                .into_iter()
+                .map(|b| {
+                    get_variance_components(&a, &b, nonnegative).map(|sig_sq| {
+                        let mut sig_sq = sig_sq.to_vec();
+                        sig_sq.truncate(num_partitions);
+                        sig_sq
+                    })
+                })
+                .collect()
+        };
+
+    // The number of probes used for the trace entries in the row and column
+    // of each partition. The entry (i, j) uses the first
+    // max(probe_counts[i], probe_counts[j]) probes.
+    let mut probe_counts = vec![num_random_vecs; num_partitions];
+    let partition_keys = partitions.ordered_partition_keys();
+    // the partitions whose tr(K_i K_i) is left above the trace tolerance
+    let mut unconverged_partitions = Vec::new();
+    let boost_noise_dominated = max_num_random_vecs.is_some();
+    // with only a trace tolerance, the probes are added up to one per person,
+    // at which the traces could have been computed exactly
+    let adaptive_max_num_random_vecs =
+        max_num_random_vecs.or(trace_tolerance.map(|_| num_people));
+    if let Some(max_num_random_vecs) = adaptive_max_num_random_vecs {
+        if num_random_vecs < num_people {
+            let num_snps = get_num_snps(&Replicate::Full);
+            let b_list = get_b_list(&Replicate::Full, &num_snps);
+            loop {
+                let ggz_list: Vec<Array<f32, Ix2>> = ggz_jackknife
+                    .iter()
+                    .map(|ggz| {
+                        project_covariates(
+                            ggz.sum_minus_component_or_sum(None).unwrap(),
+                            covariates,
+                        )
+                    })
+                    .collect();
+                let noise_dominated = if boost_noise_dominated {
+                    get_noise_dominated_partitions(
+                        &ggz_list,
+                        &num_snps,
+                        &probe_counts,
+                        num_people,
+                        get_projected_probes(&random_vecs, covariates).as_ref(),
+                        &b_list,
+                    )?
+                } else {
+                    Vec::new()
                 };
-                let ggz_j =
-                    ggz_jackknife[j].sum_minus_component_or_sum(k).unwrap();
-                let tr_ki_kj_est =
-                    sum_of_column_wise_inner_product(&ggz_i, &ggz_j) as f64
-                        / num_snps_i
-                        / num_snps_j
-                        / num_random_vecs as f64;
-                //                println!("tr(k_{}_k_{})_est: {}", i, j,
-                // tr_ki_kj_est);
-                a[[i, j]] = tr_ki_kj_est;
-                a[[j, i]] = tr_ki_kj_est;
+                unconverged_partitions = match trace_tolerance {
+                    Some(tolerance) => get_unconverged_partitions(
+                        &ggz_list,
+                        &num_snps,
+                        &probe_counts,
+                        tolerance,
+                    ),
+                    None => Vec::new(),
+                };
+                let num_probes = *probe_counts.iter().max().unwrap();
+                let to_boost: Vec<usize> = (0..num_partitions)
+                    .filter(|i| {
+                        (noise_dominated.contains(i)
+                            || unconverged_partitions.contains(i))
+                            && probe_counts[*i] < max_num_random_vecs
+                    })
+                    .collect();
+                if to_boost.is_empty() {
+                    if !noise_dominated.is_empty() {
+                        progress.message(&format!(
+                            "\nWARNING: the Monte Carlo error from the trace \
+                            estimates dominates the estimates for partitions \
+                            {:?} even with {} random vectors",
+                            noise_dominated
+                                .iter()
+                                .map(|&i| partition_keys[i].as_str())
+                                .collect::<Vec<&str>>(),
+                            max_num_random_vecs
+                        ));
+                    }
+                    if !unconverged_partitions.is_empty() {
+                        progress.message(&format!(
+                            "\nWARNING: the relative Monte Carlo standard \
+                            error of tr(K K) exceeds the trace tolerance for \
+                            partitions {:?} even with {} random vectors",
+                            unconverged_partitions
+                                .iter()
+                                .map(|&i| partition_keys[i].as_str())
+                                .collect::<Vec<&str>>(),
+                            max_num_random_vecs
+                        ));
+                    }
+                    break;
+                }
+                let new_num_probes = if num_probes < max_num_random_vecs {
+                    let num_extra_probes = std::cmp::min(
+                        num_random_vecs,
+                        max_num_random_vecs - num_probes,
+                    );
+                    // every round of extra probes has a seeded stream of its
+                    // own
+                    let mut extra_random_vecs = generate_trace_probe_matrix(
+                        num_people,
+                        num_extra_probes,
+                        probe_type,
+                        probe_sparsity,
+                        probe_seed
+                            .map(|seed| derive_seed(seed, num_probes as u64)),
+                    );
+                    if let Some(covariates) = covariates {
+                        covariates
+                            .project_matrix_inplace(&mut extra_random_vecs);
+                    }
+                    let extra_ggz_jackknife = get_partitioned_ggz_jackknife(
+                        &geno,
+                        &partition_array,
+                        &jackknife_partitions,
+                        snp_weights,
+                        &extra_random_vecs,
+                        num_snps_per_chunk,
+                    );
+                    ggz_jackknife = ggz_jackknife
+                        .iter()
+                        .zip(extra_ggz_jackknife.iter())
+                        .map(|(ggz, extra)| {
+                            ggz.combine(extra, |a, b| {
+                                stack(Axis(1), &[a.view(), b.view()]).unwrap()
+                            })
+                        })
+                        .collect();
+                    random_vecs = stack(Axis(1), &[
+                        random_vecs.view(),
+                        extra_random_vecs.view(),
+                    ])?;
+                    num_probes + num_extra_probes
+                } else {
+                    num_probes
+                };
+                progress.message(&format!(
+                    "\n=> the Monte Carlo error of the traces is too large \
+                    for partitions {:?}, re-estimating their traces with {} \
+                    random vectors",
+                    to_boost
+                        .iter()
+                        .map(|&i| partition_keys[i].as_str())
+                        .collect::<Vec<&str>>(),
+                    new_num_probes
+                ));
+                for &i in to_boost.iter() {
+                    probe_counts[i] = new_num_probes;
+                }
             }
         }
-        b_list
-            .into_iter()
-            .map(|b| {
-                //                println!("solving ax=b\na = {:?}\nb = {:?}",
-                // a, b);
-                let mut sig_sq =
-                    a.solve_into(b).unwrap().as_slice().unwrap().to_owned();
-                sig_sq.truncate(num_partitions);
-                sig_sq
+    }
+
+    let trace_estimates = get_trace_estimates(
+        &ggz_jackknife
+            .iter()
+            .map(|ggz| {
+                project_covariates(
+                    ggz.sum_minus_component_or_sum(None).unwrap(),
+                    covariates,
+                )
             })
-            .collect()
-    };
+            .collect::<Vec<Array<f32, Ix2>>>(),
+        &get_num_snps(&Replicate::Full),
+        &probe_counts,
+        partition_keys,
+        num_random_vecs >= num_people,
+    );
+    let trace_warnings: Vec<Warning> = unconverged_partitions
+        .iter()
+        .map(|&i| {
+            Warning::new(
+                WarningCode::NotConverged,
+                format!(
+                    "the relative Monte Carlo standard error of tr(K K) for \
+                    partition {} exceeds the trace tolerance",
+                    partition_keys[i]
+                ),
+            )
+        })
+        .collect();
 
+    progress.message(&format!("\n=> resampling: {}", resampling));
+    let checkpointed_replicates = checkpoint
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.replicates().cloned());
+    let replicates = match checkpointed_replicates {
+        Some(replicates) => replicates,
+        None => {
+            let replicates = get_seeded_replicates(&resampling, seed);
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                checkpoint.set_replicates(replicates.clone());
+            }
+            replicates
+        }
+    };
     let mut pheno_knife_estimates: Vec<Vec<PartitionedEstimates>> =
-        vec![Vec::new(); num_pheno_paths];
-    jackknife_partitions.iter().enumerate().for_each(|(k, p)| {
-        println!("\n=> leaving out jackknife partition with index {}", k);
-        get_heritability_point_estimate(Some(k), Some(&p))
+        vec![Vec::new(); num_phenos];
+    let num_completed_replicates = match checkpoint.as_ref() {
+        Some(checkpoint) => {
+            for estimates in checkpoint.replicate_estimates().iter() {
+                for (i, est) in estimates.iter().enumerate() {
+                    pheno_knife_estimates[i].push(est.clone());
+                }
+            }
+            checkpoint.replicate_estimates().len()
+        }
+        None => 0,
+    };
+    if num_completed_replicates > 0 {
+        progress.message(&format!(
+            "\n=> {} of the {} replicates are completed in the checkpoint",
+            num_completed_replicates,
+            replicates.len()
+        ));
+    }
+    for (r, replicate) in
+        replicates.iter().enumerate().skip(num_completed_replicates)
+    {
+        progress.progress("estimating on replicate", r + 1, replicates.len());
+        let estimates = get_heritability_point_estimate(
+            &ggz_jackknife,
+            &probe_counts,
+            &random_vecs,
+            replicate,
+        )?;
+        if let (Some(checkpoint), Some(config)) =
+            (checkpoint.as_mut(), checkpoint_config)
+        {
+            checkpoint.push_replicate_estimates(estimates.clone());
+            if (r + 1) % NUM_REPLICATES_PER_CHECKPOINT == 0
+                || r + 1 == replicates.len()
+            {
+                checkpoint.save_replicate_progress(&config.dir)?;
+            }
+        }
+        estimates
             .into_iter()
             .enumerate()
             .for_each(|(i, estimates)| {
                 pheno_knife_estimates[i].push(estimates)
             });
-    });
+    }
 
-    let est_without_jackknife = get_heritability_point_estimate(None, None);
+    // the BCa acceleration of the bootstrap intervals is estimated from the
+    // leave-one-block-out estimates
+    let mut pheno_loo_estimates: Vec<Vec<PartitionedEstimates>> =
+        vec![Vec::new(); num_phenos];
+    if resampling.is_bootstrap() {
+        let num_blocks = resampling.num_blocks();
+        for k in 0..num_blocks {
+            progress.progress(
+                "estimating the BCa acceleration, leaving out block",
+                k + 1,
+                num_blocks,
+            );
+            get_heritability_point_estimate(
+                &ggz_jackknife,
+                &probe_counts,
+                &random_vecs,
+                &Replicate::LeaveOneOut(k),
+            )?
+            .into_iter()
+            .enumerate()
+            .for_each(|(i, estimates)| pheno_loo_estimates[i].push(estimates));
+        }
+    }
 
-    let path_to_est: HashMap<String, PartitionedJackknifeEstimates> = pheno_path_vec
-        .iter()
+    let est_without_jackknife = get_heritability_point_estimate(
+        &ggz_jackknife,
+        &probe_counts,
+        &random_vecs,
+        &Replicate::Full,
+    )?;
+
+    let mut analytical_estimates: Vec<Option<HeritabilityEstimate>> =
+        vec![None; num_phenos];
+    if analytical_se {
+        let num_snps = get_num_snps(&Replicate::Full);
+        let ggz_list: Vec<Array<f32, Ix2>> = ggz_jackknife
+            .iter()
+            .map(|ggz| {
+                project_covariates(
+                    ggz.sum_minus_component_or_sum(None).unwrap(),
+                    covariates,
+                )
+            })
+            .collect();
+        let a = get_trace_matrix(
+            &ggz_list,
+            &num_snps,
+            &probe_counts,
+            num_people,
+            get_projected_probes(&random_vecs, covariates).as_ref(),
+            None,
+        );
+        let geno = &geno;
+        let kernels: Vec<KernelProduct> = wide_partition_array
+            .iter()
+            .map(|partition| {
+                let range = partition.clone();
+                let num_snps = match snp_weights {
+                    Some(w) => w.sum(&range),
+                    None => partition.size() as f64,
+                };
+                KernelProduct {
+                    dot: Box::new(move |rhs: &Array<f32, Ix2>, _, _| {
+                        g_gt_dot(
+                            geno,
+                            Some(range.clone()),
+                            snp_weights,
+                            rhs,
+                            Some(num_snps_per_chunk),
+                        )
+                    }),
+                    is_exact: true,
+                    num_snps,
+                }
+            })
+            .collect();
+        let mut component_names = partition_keys.clone();
+        component_names.push("noise".to_string());
+        for (p, b) in get_b_list(&Replicate::Full, &num_snps)
+            .into_iter()
+            .enumerate()
+        {
+            progress.message(&format!("\n=> phenotype {}", pheno_names[p]));
+            let sig_sq = get_variance_components(&a, &b, nonnegative)?.to_vec();
+            analytical_estimates[p] = Some(get_analytical_estimate(
+                &kernels,
+                component_names.clone(),
+                &a,
+                sig_sq,
+                covariates,
+                num_people,
+                num_random_vecs,
+                seed.map(|seed| {
+                    derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)
+                }),
+                progress,
+            )?);
+        }
+    }
+
+    let mut parametric_intervals: Vec<
+        Option<Vec<ParametricBootstrapInterval>>,
+    > = vec![None; num_phenos];
+    if let Some(num_reps) = num_parametric_bootstrap_reps {
+        let covariance_scale = resampling
+            .covariance_scale(replicates.len())
+            .ok_or_else(|| {
+                format!(
+                    "the parametric bootstrap requires the normal equations \
+                    on at least two replicates of the {}",
+                    resampling
+                )
+            })?;
+        progress.phase_start(PARAMETRIC_BOOTSTRAP_PHASE);
+        let (a, b_list) = get_normal_equations(
+            &ggz_jackknife,
+            &probe_counts,
+            &random_vecs,
+            &Replicate::Full,
+        );
+        let replicate_normal_eqns: Vec<_> = replicates
+            .iter()
+            .map(|replicate| {
+                get_normal_equations(
+                    &ggz_jackknife,
+                    &probe_counts,
+                    &random_vecs,
+                    replicate,
+                )
+            })
+            .collect();
+        let mut component_names = partition_keys.clone();
+        component_names.push("noise".to_string());
+        for (p, b) in b_list.iter().enumerate() {
+            let pheno_normal_eqns: Vec<(Array<f64, Ix2>, Array<f64, Ix1>)> =
+                replicate_normal_eqns
+                    .iter()
+                    .map(|(a_r, b_list_r)| (a_r.clone(), b_list_r[p].clone()))
+                    .collect();
+            // every phenotype draws from a seeded stream of its own
+            let mut rng = StdRng::seed_from_u64(match seed {
+                Some(seed) => derive_seed(
+                    derive_seed(seed, PARAMETRIC_BOOTSTRAP_SEED_STREAM),
+                    p as u64,
+                ),
+                None => thread_rng().gen(),
+            });
+            parametric_intervals[p] = Some(get_parametric_bootstrap_intervals(
+                &component_names,
+                &a,
+                b,
+                &pheno_normal_eqns,
+                covariance_scale,
+                num_reps,
+                BOOTSTRAP_CI_LEVEL,
+                nonnegative,
+                &mut rng,
+            )?);
+        }
+        progress.phase_end(PARAMETRIC_BOOTSTRAP_PHASE);
+    }
+
+    let resampling_blocks = if keep_resampling_blocks {
+        Some(ResamplingBlocks::new(
+            &wide_jackknife_partitions
+                .iter()
+                .collect::<Vec<OrderedIntegerSet<usize>>>(),
+            replicates.clone(),
+        ))
+    } else {
+        None
+    };
+    let estimates: Vec<PartitionedJackknifeEstimates> = analytical_estimates
+        .into_iter()
+        .zip(parametric_intervals.into_iter())
         .enumerate()
-        .map(|(i, path)| {
-//            println!("\n=> {}", path);
-            Ok((
-                path.to_string(),
-                PartitionedJackknifeEstimates::from_jackknife_estimates(
+        .map(|(i, (analytical_estimate, parametric_intervals))| {
+            let mut est =
+                PartitionedJackknifeEstimates::from_replicate_estimates(
                     &est_without_jackknife[i],
                     &pheno_knife_estimates[i],
                     Some(partitions.ordered_partition_keys().clone()),
-                    None)?
-            ))
+                    None,
+                    &resampling,
+                )?;
+            if resampling.is_bootstrap() {
+                est.bootstrap_intervals = Some(est.get_bootstrap_intervals(
+                    BOOTSTRAP_CI_LEVEL,
+                    Some(&pheno_loo_estimates[i]),
+                )?);
+            }
+            est.analytical_estimate = analytical_estimate;
+            est.parametric_bootstrap_intervals = parametric_intervals;
+            est.resampling_blocks = resampling_blocks.clone();
+            est.trace_estimates = trace_estimates.clone();
+            est.warnings.extend(trace_warnings.iter().cloned());
+            Ok(est)
         })
-        .collect::<Result<HashMap<String, PartitionedJackknifeEstimates>, String>>()?;
-    Ok(path_to_est)
+        .collect::<Result<Vec<PartitionedJackknifeEstimates>, Error>>()?;
+    Ok(PhenoEstimateTable::new(
+        pheno_names,
+        estimates,
+        partition_sizes,
+    ))
 }
 
+/// If `x_dosage` is `Some`, the male genotypes of the X SNPs in `g_bed` are
+/// recoded under its dosage compensation model before they are standardized,
+/// where the X SNPs are expected to form a G partition of their own.
+/// The random vectors of the G traces are drawn from the `probe_type`, while
+/// the GxG sketches stay +-1 vectors, as their correction
+/// `((G u)^2 - rowssq(G)) / 2` relies on `u_i^2 = 1`.
+/// If the `trace_mode` selects the exact path for the number of people, the
+/// G and GxG kernels are formed explicitly in every replicate and the traces
+/// are computed exactly, without the random vectors.
+/// If `seed` is `Some`, the random vectors and the random jackknife subsets
+/// are drawn from it, so that the estimates are reproducible.
 pub fn estimate_g_gxg_heritability(
     g_bed: PlinkBed,
     g_bim: PlinkBim<Coordinate>,
@@ -223,8 +1330,23 @@ pub fn estimate_g_gxg_heritability(
     pheno_path_vec: Vec<String>,
     num_rand_vecs_g: usize,
     num_rand_vecs_gxg: usize,
-    num_jackknife_partitions: usize,
+    probe_type: ProbeType,
+    trace_mode: TraceMode,
+    resampling: Resampling,
+    x_dosage: Option<&XChromDosage>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
 ) -> Result<HashMap<String, PartitionedJackknifeEstimates>, Error> {
+    if let Resampling::BlockBootstrap {
+        ..
+    } = resampling
+    {
+        return Err(Error::Generic(
+            "the block bootstrap is not supported for the G and GxG \
+            estimates, as the GxG terms are not additive over the SNP blocks"
+                .to_string(),
+        ));
+    }
     let g_partitions = g_bim.get_fileline_partitions_or(
         DEFAULT_PARTITION_NAME,
         OrderedIntegerSet::from_slice(&[[0, g_bed.total_num_snps() - 1]]),
@@ -242,17 +1364,12 @@ pub fn estimate_g_gxg_heritability(
     let gxg_partition_array: Vec<SnpPartition> =
         gxg_partitions.ordered_partition_array();
 
-    let g_jackknife_partitions = JackknifePartitions::from_integer_set(
-        g_partition_array.clone(),
-        num_jackknife_partitions,
-        false,
-    );
+    let block_seed = seed.map(|seed| derive_seed(seed, BLOCK_SEED_STREAM));
+    let g_jackknife_partitions =
+        resampling.get_blocks(g_partition_array.clone(), block_seed);
 
-    let gxg_basis_jackknife_partitions = JackknifePartitions::from_integer_set(
-        gxg_partition_array.clone(),
-        num_jackknife_partitions,
-        false,
-    );
+    let gxg_basis_jackknife_partitions =
+        resampling.get_blocks(gxg_partition_array.clone(), block_seed);
 
     let num_g_partitions = g_partition_array.len();
     let num_gxg_partitions = gxg_partition_array.len();
@@ -270,70 +1387,177 @@ pub fn estimate_g_gxg_heritability(
         num_inter_gxg_partitions,
         g_partitions.ordered_partition_keys(),
         gxg_partitions.ordered_partition_keys(),
+        progress,
     )?;
 
     let mut pheno_path_to_arr = get_pheno_path_to_arr(&pheno_path_vec)?;
+    for path in pheno_path_vec.iter() {
+        check_pheno_num_people(
+            pheno_path_to_arr[path].len(),
+            path,
+            num_people,
+        )?;
+    }
     pheno_path_to_arr
         .iter_mut()
         .for_each(|(_path, mut pheno_arr)| {
             normalize_vector_inplace(&mut pheno_arr, 0)
         });
-    println!("\n=> normalized the phenotype vectors");
+    progress.message("\n=> normalized the phenotype vectors");
 
     let yy = num_people as f64;
 
-    println!("=> generating ggz_jackknife");
-    let g_random_vecs =
-        generate_plus_minus_one_bernoulli_matrix(num_people, num_rand_vecs_g);
+    let g_geno = XDosageCompensated::new(&g_bed, x_dosage)?;
+
+    if trace_mode.is_exact(num_people) {
+        progress.message(&format!(
+            "\n=> computing the traces exactly from the kernels of the {} \
+            people",
+            num_people
+        ));
+        let get_heritability_point_estimate =
+            |g_jackknife_range: Option<&SnpPartition>,
+             gxg_jackknife_range: Option<&SnpPartition>|
+             -> Result<HashMap<String, Vec<f64>>, Error> {
+                let mut kernels = g_partition_array
+                    .iter()
+                    .map(|p| {
+                        get_g_kernel(
+                            &g_geno,
+                            Some(partition_minus_knife(p, g_jackknife_range)),
+                            DEFAULT_NUM_SNPS_PER_CHUNK,
+                        )
+                    })
+                    .collect::<Result<Vec<Array<f64, Ix2>>, String>>()?;
+                let gxg_grams = gxg_partition_array
+                    .iter()
+                    .map(|p| {
+                        GxgGramMatrices::new(
+                            &gxg_basis_bed,
+                            Some(partition_minus_knife(p, gxg_jackknife_range)),
+                            DEFAULT_NUM_SNPS_PER_CHUNK,
+                        )
+                    })
+                    .collect::<Result<Vec<GxgGramMatrices>, String>>()?;
+                for grams in gxg_grams.iter() {
+                    kernels.push(grams.intra_kernel()?);
+                }
+                for i in 0..num_gxg_partitions {
+                    for j in i + 1..num_gxg_partitions {
+                        kernels.push(gxg_grams[i].inter_kernel(&gxg_grams[j]));
+                    }
+                }
+                pheno_path_to_arr
+                    .iter()
+                    .map(|(path, pheno_arr)| {
+                        let (a, b) =
+                            get_exact_normal_equations(&kernels, pheno_arr);
+                        solve_normal_equations(&a, &b).map(|sig_sq| {
+                            let mut sig_sq = sig_sq.to_vec();
+                            sig_sq.truncate(total_num_partitions);
+                            (path.clone(), sig_sq)
+                        })
+                    })
+                    .collect()
+            };
+
+        progress.message(&format!("\n=> resampling: {}", resampling));
+        let num_replicates = get_seeded_replicates(&resampling, seed).len();
+        let heritability_estimates = g_jackknife_partitions
+            .iter()
+            .zip(gxg_basis_jackknife_partitions.iter())
+            .take(num_replicates)
+            .enumerate()
+            .map(|(k, (g_jackknife_range, gxg_jackknife_range))| {
+                progress.message(&format!(
+                    "\n=> leaving out jackknife partition with index {}",
+                    k
+                ));
+                get_heritability_point_estimate(
+                    Some(&g_jackknife_range),
+                    Some(&gxg_jackknife_range),
+                )
+            })
+            .collect::<Result<Vec<HashMap<String, Vec<f64>>>, Error>>()?;
+        let est_without_knife = get_heritability_point_estimate(None, None)?;
+        return Ok(get_g_gxg_partitioned_estimates(
+            &pheno_path_vec,
+            &est_without_knife,
+            &heritability_estimates,
+            g_partitions.ordered_partition_keys(),
+            gxg_partitions.ordered_partition_keys(),
+            &resampling,
+        )?);
+    }
+
+    progress.message("=> generating ggz_jackknife");
+    let g_random_vecs = generate_probe_matrix(
+        num_people,
+        num_rand_vecs_g,
+        probe_type,
+        None,
+        seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM)),
+    );
+    // the probes on the SNPs of the G and of the two GxG sketches
+    let snp_probe_seed = |task: u64| {
+        seed.map(|seed| {
+            derive_seed(derive_seed(seed, GXG_TRACE_SEED_STREAM), task)
+        })
+    };
     let ggz_jackknife = get_partitioned_ggz_jackknife(
-        &g_bed,
+        &g_geno,
         &g_partition_array,
         &g_jackknife_partitions,
+        None,
         &g_random_vecs,
+        DEFAULT_NUM_SNPS_PER_CHUNK,
     );
 
-    println!("=> generating gz_jackknife");
+    progress.message("=> generating gz_jackknife");
     let gz_jackknife = get_partitioned_gz_jackknife(
-        &g_bed,
+        &g_geno,
         &g_partition_array,
         &g_jackknife_partitions,
         num_rand_vecs_g,
+        snp_probe_seed(0),
     );
 
-    println!("=> generating ygy_jackknives");
+    progress.message("=> generating ygy_jackknives");
     let ygy_jackknives: HashMap<String, Vec<AdditiveJackknife<f64>>> =
         pheno_path_to_arr
             .iter()
             .map(|(path, pheno_arr)| {
-                (
+                Ok((
                     path.clone(),
                     get_partitioned_ygy_jackknife(
-                        &g_bed,
+                        &g_geno,
                         &g_partition_array,
                         &g_jackknife_partitions,
                         &pheno_arr,
-                    ),
-                )
+                    )?,
+                ))
             })
-            .collect();
+            .collect::<Result<_, String>>()?;
 
-    println!("=> generating gxg_gz_jackknife");
+    progress.message("=> generating gxg_gz_jackknife");
     let gxg_gz_jackknife = get_partitioned_gz_jackknife(
         &gxg_basis_bed,
         &gxg_partition_array,
         &gxg_basis_jackknife_partitions,
         num_rand_vecs_gxg,
+        snp_probe_seed(1),
     );
 
-    println!("=> generating gxg_gu_jackknife");
+    progress.message("=> generating gxg_gu_jackknife");
     let gxg_gu_jackknife = get_partitioned_gz_jackknife(
         &gxg_basis_bed,
         &gxg_partition_array,
         &gxg_basis_jackknife_partitions,
         num_rand_vecs_gxg,
+        snp_probe_seed(2),
     );
 
-    println!("=> generating gxg_ssq_jackknife");
+    progress.message("=> generating gxg_ssq_jackknife");
     let gxg_ssq_jackknife: Vec<AdditiveJackknife<Array<f32, Ix1>>> =
         gxg_partition_array
             .par_iter()
@@ -351,86 +1575,93 @@ pub fn estimate_g_gxg_heritability(
             })
             .collect();
 
-    let get_heritability_point_estimate = |leave_out_index: Option<usize>,
-                                           g_jackknife_range: Option<
-        &SnpPartition,
-    >,
-                                           gxg_jackknife_range: Option<
-        &SnpPartition,
-    >|
-     -> HashMap<String, Vec<f64>> {
-        let JackknifeSelectorOutput {
-            gz_array,
-            ggz_array,
-            pheno_path_to_ygy_array,
-            gxg_gz_array,
-            gxg_gu_array,
-            gxg_ssq_array,
-            inter_chrom_gxg_zz_array,
-            inter_chrom_gxg_uu_array,
-            gxg_range_array,
-            g_range_sizes_array,
-            gxg_range_sizes_array,
-        } = leave_out_jackknife(
-            leave_out_index,
-            g_jackknife_range,
-            gxg_jackknife_range,
-            &gz_jackknife,
-            &ggz_jackknife,
-            &ygy_jackknives,
-            &gxg_gz_jackknife,
-            &gxg_gu_jackknife,
-            &gxg_ssq_jackknife,
-            &g_partition_array,
-            &gxg_partition_array,
-        );
-        let a = get_lhs_matrix_for_heritability_point_estimate(
-            &gz_array,
-            &ggz_array,
-            &gxg_gz_array,
-            &gxg_gu_array,
-            &gxg_ssq_array,
-            &inter_chrom_gxg_zz_array,
-            &inter_chrom_gxg_uu_array,
-            &g_range_sizes_array,
-            &gxg_range_sizes_array,
-            num_people,
-            num_rand_vecs_g,
-            num_rand_vecs_gxg,
-        );
-        let pheno_to_heritability_est: HashMap<String, Vec<f64>> =
-            pheno_path_to_arr
-                .iter()
-                .map(|(path, pheno_arr)| {
-                    let b = get_rhs_vec_for_heritability_point_estimate(
-                        &gxg_basis_bed,
-                        &pheno_arr,
-                        &pheno_path_to_ygy_array[path],
-                        yy,
-                        &gxg_range_array,
-                        &g_range_sizes_array,
-                        &gxg_range_sizes_array,
-                    );
-                    println!("=> Solving Ax=B for phenotype at {}", path);
-                    let mut sig_sq =
-                        a.solve_into(b).unwrap().as_slice().unwrap().to_owned();
-                    sig_sq.truncate(total_num_partitions);
-                    (path.clone(), sig_sq)
-                })
-                .collect();
+    let get_heritability_point_estimate =
+        |leave_out_index: Option<usize>,
+         g_jackknife_range: Option<&SnpPartition>,
+         gxg_jackknife_range: Option<&SnpPartition>|
+         -> Result<HashMap<String, Vec<f64>>, Error> {
+            let JackknifeSelectorOutput {
+                gz_array,
+                ggz_array,
+                pheno_path_to_ygy_array,
+                gxg_gz_array,
+                gxg_gu_array,
+                gxg_ssq_array,
+                inter_chrom_gxg_zz_array,
+                inter_chrom_gxg_uu_array,
+                gxg_range_array,
+                g_range_sizes_array,
+                gxg_range_sizes_array,
+            } = leave_out_jackknife(
+                leave_out_index,
+                g_jackknife_range,
+                gxg_jackknife_range,
+                &gz_jackknife,
+                &ggz_jackknife,
+                &ygy_jackknives,
+                &gxg_gz_jackknife,
+                &gxg_gu_jackknife,
+                &gxg_ssq_jackknife,
+                &g_partition_array,
+                &gxg_partition_array,
+                progress,
+            );
+            let a = get_lhs_matrix_for_heritability_point_estimate(
+                &gz_array,
+                &ggz_array,
+                &gxg_gz_array,
+                &gxg_gu_array,
+                &gxg_ssq_array,
+                &inter_chrom_gxg_zz_array,
+                &inter_chrom_gxg_uu_array,
+                &g_range_sizes_array,
+                &gxg_range_sizes_array,
+                num_people,
+                num_rand_vecs_g,
+                num_rand_vecs_gxg,
+                progress,
+            );
+            let pheno_to_heritability_est: HashMap<String, Vec<f64>> =
+                pheno_path_to_arr
+                    .iter()
+                    .map(|(path, pheno_arr)| {
+                        let b = get_rhs_vec_for_heritability_point_estimate(
+                            &gxg_basis_bed,
+                            &pheno_arr,
+                            &pheno_path_to_ygy_array[path],
+                            yy,
+                            &gxg_range_array,
+                            &g_range_sizes_array,
+                            &gxg_range_sizes_array,
+                            progress,
+                        )?;
+                        progress.message(&format!(
+                            "=> Solving Ax=B for phenotype at {}",
+                            path
+                        ));
+                        solve_normal_equations(&a, &b).map(|sig_sq| {
+                            let mut sig_sq = sig_sq.to_vec();
+                            sig_sq.truncate(total_num_partitions);
+                            (path.clone(), sig_sq)
+                        })
+                    })
+                    .collect::<Result<HashMap<String, Vec<f64>>, Error>>()?;
 
-        pheno_to_heritability_est.iter().for_each(|(path, est)| {
-            println!("\npheno {} sig_sq: {:?}", path, est);
-        });
+            pheno_to_heritability_est.iter().for_each(|(path, est)| {
+                progress
+                    .message(&format!("\npheno {} sig_sq: {:?}", path, est));
+            });
 
-        pheno_to_heritability_est
-    };
+            Ok(pheno_to_heritability_est)
+        };
 
+    progress.message(&format!("\n=> resampling: {}", resampling));
+    let num_replicates = get_seeded_replicates(&resampling, seed).len();
     let zipped_jackknife_partitions: Vec<(SnpPartition, SnpPartition)> =
         g_jackknife_partitions
             .iter()
             .zip(gxg_basis_jackknife_partitions.iter())
-            .map(|(a, b)| (a, b))
+            .take(num_replicates)
             .collect();
 
     let heritability_estimates: Vec<HashMap<String, Vec<f64>>> =
@@ -438,41 +1669,62 @@ pub fn estimate_g_gxg_heritability(
             .into_iter()
             .enumerate()
             .map(|(k, (g_jackknife_range, gxg_jackknife_range))| {
-                println!(
+                progress.message(&format!(
                     "\n=> leaving out jackknife partition with index {}",
                     k
-                );
+                ));
                 get_heritability_point_estimate(
                     Some(k),
                     Some(&g_jackknife_range),
                     Some(&gxg_jackknife_range),
                 )
             })
-            .collect();
+            .collect::<Result<Vec<HashMap<String, Vec<f64>>>, Error>>()?;
 
-    println!("\n=> Computing heritability without Jackknife");
-    let est_without_knife = get_heritability_point_estimate(None, None, None);
+    progress.message("\n=> Computing heritability without Jackknife");
+    let est_without_knife = get_heritability_point_estimate(None, None, None)?;
 
-    let mut total_partition_keys: Vec<String> = g_partitions
-        .ordered_partition_keys()
+    Ok(get_g_gxg_partitioned_estimates(
+        &pheno_path_vec,
+        &est_without_knife,
+        &heritability_estimates,
+        g_partitions.ordered_partition_keys(),
+        gxg_partitions.ordered_partition_keys(),
+        &resampling,
+    )?)
+}
+
+/// The estimates of every phenotype in `pheno_path_vec` from the estimates of
+/// the G and GxG components on the full data and in the replicates, with the
+/// G, the intra- and the inter-GxG-partition components grouped
+fn get_g_gxg_partitioned_estimates(
+    pheno_path_vec: &[String],
+    est_without_knife: &HashMap<String, Vec<f64>>,
+    heritability_estimates: &[HashMap<String, Vec<f64>>],
+    g_partition_keys: &[String],
+    gxg_partition_keys: &[String],
+    resampling: &Resampling,
+) -> Result<HashMap<String, PartitionedJackknifeEstimates>, String> {
+    let num_g_partitions = g_partition_keys.len();
+    let num_gxg_partitions = gxg_partition_keys.len();
+    let total_num_partitions =
+        num_g_partitions + num_gxg_partitions + n_choose_2(num_gxg_partitions);
+    let mut total_partition_keys: Vec<String> = g_partition_keys
         .iter()
         .map(|k| format!("G {}", k))
         .collect();
     total_partition_keys.append(
-        &mut gxg_partitions
-            .ordered_partition_keys()
+        &mut gxg_partition_keys
             .iter()
             .map(|k| format!("GxG {}", k))
             .collect::<Vec<String>>(),
     );
     total_partition_keys.append(
-        &mut gxg_partitions
-            .ordered_partition_keys()
+        &mut gxg_partition_keys
             .iter()
             .enumerate()
             .flat_map(|(i, k1)| {
-                gxg_partitions
-                    .ordered_partition_keys()
+                gxg_partition_keys
                     .iter()
                     .skip(i + 1)
                     .map(|k2| format!("G({})xG({})", k1, k2))
@@ -492,14 +1744,11 @@ pub fn estimate_g_gxg_heritability(
         })
         .collect();
 
-    let path_to_partitioned_estimates: HashMap<
-        String,
-        PartitionedJackknifeEstimates,
-    > = path_to_estimates
+    path_to_estimates
         .into_iter()
         .map(|(path, estimates)| {
             let partitioned_estimates =
-                PartitionedJackknifeEstimates::from_jackknife_estimates(
+                PartitionedJackknifeEstimates::from_replicate_estimates(
                     &est_without_knife[&path],
                     &estimates,
                     Some(total_partition_keys.clone()),
@@ -533,16 +1782,18 @@ pub fn estimate_g_gxg_heritability(
                             ]]),
                         ),
                     ]),
+                    resampling,
                 )
-                .unwrap_or_exit(Some(format!(
-                    "failed to get partitioned jackknife estimates for {}",
-                    path
-                )));
-            (path, partitioned_estimates)
+                .map_err(|why| {
+                    format!(
+                        "failed to get partitioned jackknife estimates for \
+                        {}: {}",
+                        path, why
+                    )
+                })?;
+            Ok((path, partitioned_estimates))
         })
-        .collect();
-
-    Ok(path_to_partitioned_estimates)
+        .collect()
 }
 
 fn get_lhs_matrix_for_heritability_point_estimate(
@@ -558,6 +1809,7 @@ fn get_lhs_matrix_for_heritability_point_estimate(
     num_people: usize,
     num_rand_vecs_g: usize,
     num_rand_vecs_gxg: usize,
+    progress: &dyn ProgressSink,
 ) -> Array<f64, Ix2> {
     let num_g_partitions = gz_array.len();
     let num_gxg_partitions = gxg_gz_array.len();
@@ -570,7 +1822,7 @@ fn get_lhs_matrix_for_heritability_point_estimate(
     let nrv_g = num_rand_vecs_g as f64;
     let nrv_gxg = num_rand_vecs_gxg as f64;
 
-    println!("=> computing g_pairwise_est");
+    progress.message("=> computing g_pairwise_est");
     // g_pairwise_est contains Vec<(
     // tr_kk_est,
     // tr_gk_i_gk_j_est_list,
@@ -670,13 +1922,17 @@ fn get_lhs_matrix_for_heritability_point_estimate(
         for (j, tr_ki_kj_est) in tr_gk_i_gk_j_est_list.into_iter().enumerate() {
             a[[i, i + 1 + j]] = tr_ki_kj_est;
             a[[i + 1 + j, i]] = tr_ki_kj_est;
-            println!("tr_gk{}_gk{}_est: {}", i, j, tr_ki_kj_est);
+            progress
+                .trace_value(&format!("tr_gk{}_gk{}_est", i, j), tr_ki_kj_est);
         }
         for (gxg_i, tr_g_gxg_est) in tr_g_gxg_est_list.into_iter().enumerate() {
             let global_gxg_i = num_g_partitions + gxg_i;
             a[[global_gxg_i, i]] = tr_g_gxg_est;
             a[[i, global_gxg_i]] = tr_g_gxg_est;
-            println!("tr_g_k{}_gxg_k{}_est: {}", i, gxg_i, tr_g_gxg_est);
+            progress.trace_value(
+                &format!("tr_g_k{}_gxg_k{}_est", i, gxg_i),
+                tr_g_gxg_est,
+            );
         }
         for (inter_gxg_ij, tr_g_inter_gxg_est) in
             tr_g_inter_gxg_est_list.into_iter().enumerate()
@@ -685,14 +1941,14 @@ fn get_lhs_matrix_for_heritability_point_estimate(
                 num_g_partitions + num_gxg_partitions + inter_gxg_ij;
             a[[global_inter_gxg_ij, i]] = tr_g_inter_gxg_est;
             a[[i, global_inter_gxg_ij]] = tr_g_inter_gxg_est;
-            println!(
-                "tr_g_k{}_inter_gxg_k{}_est: {}",
-                i, inter_gxg_ij, tr_g_inter_gxg_est
+            progress.trace_value(
+                &format!("tr_g_k{}_inter_gxg_k{}_est", i, inter_gxg_ij),
+                tr_g_inter_gxg_est,
             );
         }
     }
 
-    println!("=> computing gxg_pairwise_est");
+    progress.message("=> computing gxg_pairwise_est");
     // gxg_pairwise_est contains Vec<(
     // tr_gxg_ki_est,
     // tr_gxg_kki_est,
@@ -787,19 +2043,17 @@ fn get_lhs_matrix_for_heritability_point_estimate(
         a[[global_i, total_num_partitions]] = tr_gxg_ki_est;
         a[[total_num_partitions, global_i]] = tr_gxg_ki_est;
         a[[global_i, global_i]] = tr_gxg_kki_est;
-        println!("tr_gxg_k{}_est: {}", i, tr_gxg_ki_est);
-        println!("tr_gxg_kk{}_est: {}", i, tr_gxg_kki_est);
+        progress.trace_value(&format!("tr_gxg_k{}_est", i), tr_gxg_ki_est);
+        progress.trace_value(&format!("tr_gxg_kk{}_est", i), tr_gxg_kki_est);
         for (j, tr_gxg_i_gxg_j_est) in
             tr_gxg_ki_kj_est_list.into_iter().enumerate()
         {
             let global_j = num_g_partitions + i + 1 + j;
             a[[global_i, global_j]] = tr_gxg_i_gxg_j_est;
             a[[global_j, global_i]] = tr_gxg_i_gxg_j_est;
-            println!(
-                "tr_gxg_k{}_gxg_k{}: {}",
-                i,
-                i + 1 + j,
-                tr_gxg_i_gxg_j_est
+            progress.trace_value(
+                &format!("tr_gxg_k{}_gxg_k{}", i, i + 1 + j),
+                tr_gxg_i_gxg_j_est,
             );
         }
         for (inter_gxg_ij, tr_gxg_ki_inter_gxg_kij_est) in
@@ -809,14 +2063,14 @@ fn get_lhs_matrix_for_heritability_point_estimate(
                 num_g_partitions + num_gxg_partitions + inter_gxg_ij;
             a[[global_i, global_inter_gxg_ij]] = tr_gxg_ki_inter_gxg_kij_est;
             a[[global_inter_gxg_ij, global_i]] = tr_gxg_ki_inter_gxg_kij_est;
-            println!(
-                "tr_gxg_k{}_inter_gxg_k{}: {}",
-                i, inter_gxg_ij, tr_gxg_ki_inter_gxg_kij_est
+            progress.trace_value(
+                &format!("tr_gxg_k{}_inter_gxg_k{}", i, inter_gxg_ij),
+                tr_gxg_ki_inter_gxg_kij_est,
             );
         }
     }
 
-    println!("=> computing inter_gxg_pairwise_est");
+    progress.message("=> computing inter_gxg_pairwise_est");
     // inter_gxg_pairwise_est contains Vec<(
     // tr_inter_k_ij_est,
     // tr_inter_kk_ij_est,
@@ -895,14 +2149,23 @@ fn get_lhs_matrix_for_heritability_point_estimate(
         a[[global_ij, global_ij]] = tr_inter_kk_ij_est;
         a[[global_ij, total_num_partitions]] = tr_inter_k_ij_est;
         a[[total_num_partitions, global_ij]] = tr_inter_k_ij_est;
-        println!("tr_inter_gg_k{}_est: {}", i1j1, tr_inter_k_ij_est);
-        println!("tr_inter_gg_kk{}_est: {}", i1j1, tr_inter_kk_ij_est);
+        progress.trace_value(
+            &format!("tr_inter_gg_k{}_est", i1j1),
+            tr_inter_k_ij_est,
+        );
+        progress.trace_value(
+            &format!("tr_inter_gg_kk{}_est", i1j1),
+            tr_inter_kk_ij_est,
+        );
         for (i2j2, est) in tr_inter_i1j1_i2j2_list.into_iter().enumerate() {
             let global_i2j2 =
                 num_g_partitions + num_gxg_partitions + i1j1 + 1 + i2j2;
             a[[global_ij, global_i2j2]] = est;
             a[[global_i2j2, global_ij]] = est;
-            println!("tr_inter_gg_k{}_k{}_est: {}", i1j1, i2j2, est);
+            progress.trace_value(
+                &format!("tr_inter_gg_k{}_k{}_est", i1j1, i2j2),
+                est,
+            );
         }
     }
     a
@@ -916,7 +2179,8 @@ fn get_rhs_vec_for_heritability_point_estimate(
     gxg_range_array: &Vec<SnpPartition>,
     g_range_sizes_array: &Vec<usize>,
     gxg_range_sizes_array: &Vec<usize>,
-) -> Array<f64, Ix1> {
+    progress: &dyn ProgressSink,
+) -> Result<Array<f64, Ix1>, Error> {
     let num_g_partitions = g_range_sizes_array.len();
     let num_gxg_partitions = gxg_range_sizes_array.len();
     let num_inter_gxg_partitions = n_choose_2(num_gxg_partitions);
@@ -941,19 +2205,20 @@ fn get_rhs_vec_for_heritability_point_estimate(
                 DEFAULT_NUM_SNPS_PER_CHUNK,
             );
             // y_gxg_k_y
-            get_gxg_dot_y_norm_sq_from_basis_bed(
+            Ok(get_gxg_dot_y_norm_sq_from_basis_bed(
                 &gxg_basis_bed,
                 Some(range_i.clone()),
+                None,
                 &snp_mean_i,
                 &snp_std_i,
                 pheno_arr,
-            ) / num_gxg_snps_i
+            )? / num_gxg_snps_i)
         })
-        .collect();
+        .collect::<Result<Vec<f64>, Error>>()?;
     for (i, y_gxg_k_y) in y_gxg_k_y_list.into_iter().enumerate() {
         let global_i = num_g_partitions + i;
         b[global_i] = y_gxg_k_y;
-        println!("tr_y_gxg_k{}_y_est: {}", i, y_gxg_k_y);
+        progress.trace_value(&format!("tr_y_gxg_k{}_y_est", i), y_gxg_k_y);
     }
 
     let y_inter_gxg_k_y_list: Vec<f64> = (0..num_gxg_partitions)
@@ -976,10 +2241,9 @@ fn get_rhs_vec_for_heritability_point_estimate(
                         as f64;
 
                     let mut rhs_matrix = gxg_basis_bed
-                        .get_genotype_matrix(Some(range_j.clone()))
-                        .unwrap();
+                        .get_genotype_matrix(Some(range_j.clone()))?;
                     normalize_matrix_columns_inplace(&mut rhs_matrix, 0);
-                    sum_of_squares_f32(
+                    Ok(sum_of_squares_f32(
                         normalized_g_transpose_dot_matrix(
                             &gxg_basis_bed,
                             Some(range_i.clone()),
@@ -991,17 +2255,17 @@ fn get_rhs_vec_for_heritability_point_estimate(
                         )
                         .iter(),
                     ) as f64
-                        / num_gxg_snps_i1j1
+                        / num_gxg_snps_i1j1)
                 })
-                .collect::<Vec<f64>>()
+                .collect::<Vec<Result<f64, Error>>>()
         })
-        .collect();
+        .collect::<Result<Vec<f64>, Error>>()?;
     for (ij, y_inter_gxg_k_y) in y_inter_gxg_k_y_list.into_iter().enumerate() {
         let global_ij = num_g_partitions + num_gxg_partitions + ij;
         b[global_ij] = y_inter_gxg_k_y;
-        println!("y_inter_gg_k{}_y: {}", ij, y_inter_gxg_k_y);
+        progress.trace_value(&format!("y_inter_gg_k{}_y", ij), y_inter_gxg_k_y);
     }
-    b
+    Ok(b)
 }
 
 struct JackknifeSelectorOutput {
@@ -1030,6 +2294,7 @@ fn leave_out_jackknife(
     gxg_ssq_jackknife: &Vec<AdditiveJackknife<Array<f32, Ix1>>>,
     g_partition_array: &Vec<SnpPartition>,
     gxg_partition_array: &Vec<SnpPartition>,
+    progress: &dyn ProgressSink,
 ) -> JackknifeSelectorOutput {
     let num_gxg_partitions = gxg_partition_array.len();
 
@@ -1093,7 +2358,7 @@ fn leave_out_jackknife(
         })
         .collect();
 
-    println!("=> generating inter_chrom_gxg_zz_array");
+    progress.message("=> generating inter_chrom_gxg_zz_array");
     let inter_chrom_gxg_zz_array: Vec<Array<f32, Ix2>> = (0
         ..num_gxg_partitions)
         .collect::<Vec<usize>>()
@@ -1113,7 +2378,7 @@ fn leave_out_jackknife(
         })
         .collect();
 
-    println!("=> generating inter_chrom_gxg_uu_array");
+    progress.message("=> generating inter_chrom_gxg_uu_array");
     let inter_chrom_gxg_uu_array: Vec<Array<f32, Ix2>> = (0
         ..num_gxg_partitions)
         .collect::<Vec<usize>>()
@@ -1163,6 +2428,25 @@ fn leave_out_jackknife(
     }
 }
 
+/// Checks that the phenotype array and every GxG basis array have one row per
+/// person in the genotype bed.
+fn check_multi_gxg_num_people(
+    num_people: usize,
+    le_snps_arr: &[Array<f32, Ix2>],
+    pheno_arr: &Array<f32, Ix1>,
+) -> Result<(), Error> {
+    check_pheno_num_people(pheno_arr.len(), "the phenotype array", num_people)?;
+    for (i, arr) in le_snps_arr.iter().enumerate() {
+        check_num_people(
+            arr.dim().0,
+            &format!("the basis of GxG component {}", i + 1),
+            num_people,
+            "the genotype bed",
+        )?;
+    }
+    Ok(())
+}
+
 fn check_and_print_g_and_gxg_partition_info(
     g_bed: &PlinkBed,
     gxg_basis_bed: &PlinkBed,
@@ -1171,6 +2455,7 @@ fn check_and_print_g_and_gxg_partition_info(
     num_inter_gxg_partitions: usize,
     g_partition_names: &Vec<String>,
     gxg_partition_names: &Vec<String>,
+    progress: &dyn ProgressSink,
 ) -> Result<(), Error> {
     if g_bed.num_people != gxg_basis_bed.num_people {
         return Err(Error::Generic(format!(
@@ -1182,7 +2467,7 @@ fn check_and_print_g_and_gxg_partition_info(
         g_partition_array.iter().map(|p| p.size()).collect();
     let gxg_partition_sizes: Vec<usize> =
         gxg_partition_array.iter().map(|p| p.size()).collect();
-    println!(
+    progress.message(&format!(
         "num_people: {}\n\
         total_num_g_snps: {}\n\
         total_num_gxg_basis_snps: {}\n\
@@ -1191,15 +2476,18 @@ fn check_and_print_g_and_gxg_partition_info(
         g_partition_sizes.iter().fold(0, |acc, size| acc + *size),
         gxg_partition_sizes.iter().fold(0, |acc, size| acc + *size),
         num_inter_gxg_partitions
-    );
+    ));
     g_partition_names.iter().enumerate().for_each(|(i, k)| {
-        println!("G partition named {} has {} SNPs", k, g_partition_sizes[i]);
+        progress.message(&format!(
+            "G partition named {} has {} SNPs",
+            k, g_partition_sizes[i]
+        ));
     });
     gxg_partition_names.iter().enumerate().for_each(|(i, k)| {
-        println!(
+        progress.message(&format!(
             "GxG partition named {} has {} SNPs",
             k, gxg_partition_sizes[i]
-        );
+        ));
     });
     Ok(())
 }
@@ -1224,6 +2512,260 @@ fn get_rhs_normal_eqn_vec(num_partitions: usize, yy: f64) -> Array<f64, Ix1> {
     b
 }
 
+/// The number of batches the probes are split into to estimate the Monte Carlo
+/// standard error of the estimates due to the trace estimation
+const NUM_PROBE_BATCHES: usize = 10;
+
+/// The probes `M Z` and `tr(M)` for the projection `M` onto the orthogonal
+/// complement of the covariates. The noise entries of the normal equations
+/// are then `tr(M K_i)` and `tr(M)` instead of `num_people`.
+struct ProjectedProbes<'a> {
+    probes: &'a Array<f32, Ix2>,
+    trace: f64,
+}
+
+fn get_projected_probes<'a>(
+    probes: &'a Array<f32, Ix2>,
+    covariates: Option<&CovariateProjection>,
+) -> Option<ProjectedProbes<'a>> {
+    covariates.map(|covariates| ProjectedProbes {
+        probes,
+        trace: covariates.trace(),
+    })
+}
+
+fn project_covariates(
+    mut matrix: Array<f32, Ix2>,
+    covariates: Option<&CovariateProjection>,
+) -> Array<f32, Ix2> {
+    if let Some(covariates) = covariates {
+        covariates.project_matrix_inplace(&mut matrix);
+    }
+    matrix
+}
+
+/// `ggz_list[i]` is `G_i G_i^T Z` for the probes `Z` and the i-th partition,
+/// or `M G_i G_i^T M Z` if the covariates are projected out by `M`, in which
+/// case `projected_probes` holds `M Z`.
+/// The trace entry (i, j) is estimated with the first
+/// `max(probe_counts[i], probe_counts[j])` probes, or only those among them
+/// in `batch` if it is `Some((batch_index, num_batches))`, where the probes
+/// are assigned to the batches in a round-robin fashion.
+fn get_trace_matrix(
+    ggz_list: &[Array<f32, Ix2>],
+    num_snps: &[f64],
+    probe_counts: &[usize],
+    num_people: usize,
+    projected_probes: Option<&ProjectedProbes>,
+    batch: Option<(usize, usize)>,
+) -> Array<f64, Ix2> {
+    let num_partitions = ggz_list.len();
+    let mut a = get_normal_eqn_lhs_matrix(num_partitions, num_people);
+    let (offset, step) = batch.unwrap_or((0, 1));
+    for i in 0..num_partitions {
+        for j in i..num_partitions {
+            let c = std::cmp::max(probe_counts[i], probe_counts[j]);
+            let ggz_i = ggz_list[i].slice(s![.., offset..c;step as isize]);
+            let ggz_j = ggz_list[j].slice(s![.., offset..c;step as isize]);
+            let tr_ki_kj_est = sum_of_column_wise_inner_product(&ggz_i, &ggz_j)
+                as f64
+                / num_snps[i]
+                / num_snps[j]
+                / ggz_i.dim().1 as f64;
+            a[[i, j]] = tr_ki_kj_est;
+            a[[j, i]] = tr_ki_kj_est;
+        }
+    }
+    if let Some(projected) = projected_probes {
+        for i in 0..num_partitions {
+            let c = probe_counts[i];
+            let ggz_i = ggz_list[i].slice(s![.., offset..c;step as isize]);
+            let z = projected.probes.slice(s![.., offset..c;step as isize]);
+            let tr_mk_est = sum_of_column_wise_inner_product(&z, &ggz_i) as f64
+                / num_snps[i]
+                / ggz_i.dim().1 as f64;
+            a[[i, num_partitions]] = tr_mk_est;
+            a[[num_partitions, i]] = tr_mk_est;
+        }
+        a[[num_partitions, num_partitions]] = projected.trace;
+    }
+    a
+}
+
+/// Solves the normal equations `a x = b` for the variance components,
+/// failing instead of panicking when `a` is singular, e.g. when two kernels
+/// are collinear.
+pub fn solve_normal_equations(
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+) -> Result<Array<f64, Ix1>, Error> {
+    a.solve(b)
+        .map_err(|why| Error::SingularNormalEquations(format!("{:?}", why)))
+}
+
+/// Solves the normal equations, with `solve_nonnegative_normal_equations` if
+/// the variance components are constrained to be `nonnegative`
+fn get_variance_components(
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+    nonnegative: bool,
+) -> Result<Array<f64, Ix1>, Error> {
+    if nonnegative {
+        solve_nonnegative_normal_equations(a, b)
+    } else {
+        solve_normal_equations(a, b)
+    }
+}
+
+/// Returns the indices of the partitions for which the Monte Carlo standard
+/// error of the estimate due to the trace estimation exceeds the magnitude of
+/// the estimate itself for any of the phenotypes in `b_list`.
+///
+/// The standard error is estimated from the spread of the estimates obtained
+/// from disjoint batches of the probes.
+fn get_noise_dominated_partitions(
+    ggz_list: &[Array<f32, Ix2>],
+    num_snps: &[f64],
+    probe_counts: &[usize],
+    num_people: usize,
+    projected_probes: Option<&ProjectedProbes>,
+    b_list: &[Array<f64, Ix1>],
+) -> Result<Vec<usize>, Error> {
+    let num_partitions = ggz_list.len();
+    let num_batches = std::cmp::min(
+        NUM_PROBE_BATCHES,
+        *probe_counts.iter().min().unwrap_or(&0),
+    );
+    if num_batches < 2 {
+        return Ok(Vec::new());
+    }
+    let a = get_trace_matrix(
+        ggz_list,
+        num_snps,
+        probe_counts,
+        num_people,
+        projected_probes,
+        None,
+    );
+    let batch_a_list: Vec<Array<f64, Ix2>> = (0..num_batches)
+        .map(|batch_index| {
+            get_trace_matrix(
+                ggz_list,
+                num_snps,
+                probe_counts,
+                num_people,
+                projected_probes,
+                Some((batch_index, num_batches)),
+            )
+        })
+        .collect();
+    let mut is_noise_dominated = vec![false; num_partitions];
+    for b in b_list.iter() {
+        let estimates = solve_normal_equations(&a, b)?;
+        let batch_estimates = batch_a_list
+            .iter()
+            .map(|batch_a| solve_normal_equations(batch_a, b))
+            .collect::<Result<Vec<Array<f64, Ix1>>, Error>>()?;
+        for i in 0..num_partitions {
+            let batch_mean = batch_estimates.iter().map(|e| e[i]).sum::<f64>()
+                / num_batches as f64;
+            let batch_var = batch_estimates
+                .iter()
+                .map(|e| (e[i] - batch_mean) * (e[i] - batch_mean))
+                .sum::<f64>()
+                / (num_batches - 1) as f64;
+            let mc_standard_error = (batch_var / num_batches as f64).sqrt();
+            if mc_standard_error > estimates[i].abs() {
+                is_noise_dominated[i] = true;
+            }
+        }
+    }
+    Ok((0..num_partitions)
+        .filter(|&i| is_noise_dominated[i])
+        .collect())
+}
+
+/// The per-probe values of `tr(K_i K_j)` over the first `num_probes`
+/// columns of `ggz_i` and `ggz_j`, whose mean is the trace estimate of
+/// `get_trace_matrix`
+fn get_trace_probe_values(
+    ggz_i: &Array<f32, Ix2>,
+    ggz_j: &Array<f32, Ix2>,
+    num_snps_i: f64,
+    num_snps_j: f64,
+    num_probes: usize,
+) -> Vec<f64> {
+    (0..num_probes)
+        .map(|p| {
+            ggz_i.column(p).dot(&ggz_j.column(p)) as f64
+                / num_snps_i
+                / num_snps_j
+        })
+        .collect()
+}
+
+/// The estimates of `tr(K_i K_j)` for every pair of partitions with their
+/// Monte Carlo standard errors, which are zero if the probes are `exact`
+fn get_trace_estimates(
+    ggz_list: &[Array<f32, Ix2>],
+    num_snps: &[f64],
+    probe_counts: &[usize],
+    partition_keys: &[String],
+    exact: bool,
+) -> Vec<TraceEstimate> {
+    let num_partitions = ggz_list.len();
+    let mut trace_estimates = Vec::new();
+    for i in 0..num_partitions {
+        for j in i..num_partitions {
+            let name =
+                format!("tr(K_{} K_{})", partition_keys[i], partition_keys[j]);
+            let num_probes = std::cmp::max(probe_counts[i], probe_counts[j]);
+            let values = get_trace_probe_values(
+                &ggz_list[i],
+                &ggz_list[j],
+                num_snps[i],
+                num_snps[j],
+                num_probes,
+            );
+            trace_estimates.push(if exact {
+                TraceEstimate::exact(
+                    name,
+                    values.iter().sum::<f64>() / num_probes as f64,
+                    num_probes,
+                )
+            } else {
+                TraceEstimate::from_probe_values(name, &values)
+            });
+        }
+    }
+    trace_estimates
+}
+
+/// The partitions whose estimate of `tr(K_i K_i)` has a relative Monte Carlo
+/// standard error above the `tolerance`
+fn get_unconverged_partitions(
+    ggz_list: &[Array<f32, Ix2>],
+    num_snps: &[f64],
+    probe_counts: &[usize],
+    tolerance: f64,
+) -> Vec<usize> {
+    (0..ggz_list.len())
+        .filter(|&i| {
+            !TraceEstimate::from_probe_values(
+                String::new(),
+                &get_trace_probe_values(
+                    &ggz_list[i],
+                    &ggz_list[i],
+                    num_snps[i],
+                    num_snps[i],
+                    probe_counts[i],
+                ),
+            )
+            .is_within_tolerance(tolerance)
+        })
+        .collect()
+}
+
 fn get_normal_eqn_lhs_matrix(
     num_partitions: usize,
     num_people: usize,
@@ -1238,12 +2780,14 @@ fn get_normal_eqn_lhs_matrix(
     a
 }
 
-fn partition_minus_knife(
-    partition_range: &SnpPartition,
-    knife: Option<&SnpPartition>,
-) -> SnpPartition {
+fn partition_minus_knife<I>(
+    partition_range: &Partition<I>,
+    knife: Option<&Partition<I>>,
+) -> Partition<I>
+where
+    I: Copy + Integer + ToPrimitive, {
     match knife {
-        Some(r) => partition_range.clone() - r,
+        Some(r) => partition_range.merge_difference(r),
         None => partition_range.clone(),
     }
 }
@@ -1296,35 +2840,38 @@ fn get_mean_ssq_of_z1g1g2z2(
     sum_of_squares_f32(g1z1.t().dot(g2z2).iter()) as f64 / b1 / b2
 }
 
-fn get_partitioned_gz_jackknife(
-    bed: &PlinkBed,
+/// `G Z` for random +-1 vectors `Z` on the SNPs of every partition on every
+/// jackknife block. If `seed` is `Some`, the vectors of the `p`-th partition
+/// on the `k`-th block are drawn from the stream
+/// `derive_seed(derive_seed(seed, p), k)`.
+fn get_partitioned_gz_jackknife<G: GenotypeSource + Sync>(
+    geno: &G,
     snp_partition_array: &Vec<SnpPartition>,
     jackknife_partitions: &JackknifePartitions<Coordinate>,
     num_rand_vecs: usize,
+    seed: Option<u64>,
 ) -> Vec<AdditiveJackknife<Array<f32, Ix2>>> {
     snp_partition_array
         .par_iter()
-        .map(|partition| {
+        .enumerate()
+        .map(|(p, partition)| {
+            let partition_seed = seed.map(|seed| derive_seed(seed, p as u64));
             AdditiveJackknife::from_op_over_jackknife_partitions(
                 jackknife_partitions,
-                |_, knife| {
+                |k, knife| {
                     let range_intersect = knife.intersect(partition);
                     let range_size = range_intersect.size();
-                    let (snp_mean, snp_std) = get_column_mean_and_std(
-                        bed,
-                        &range_intersect,
-                        DEFAULT_NUM_SNPS_PER_CHUNK,
-                    );
-                    normalized_g_dot_matrix(
-                        bed,
+                    g_dot(
+                        geno,
                         Some(range_intersect),
-                        &snp_mean,
-                        &snp_std,
-                        &generate_plus_minus_one_bernoulli_matrix(
+                        None,
+                        &generate_sign_matrix(
                             range_size,
                             num_rand_vecs,
+                            None,
+                            partition_seed
+                                .map(|seed| derive_seed(seed, k as u64)),
                         ),
-                        None,
                         Some(2048),
                     )
                 },
@@ -1333,41 +2880,57 @@ fn get_partitioned_gz_jackknife(
         .collect::<Vec<AdditiveJackknife<Array<f32, Ix2>>>>()
 }
 
-fn get_partitioned_ggz_jackknife(
-    bed: &PlinkBed,
-    snp_partition_array: &Vec<SnpPartition>,
-    jackknife_partitions: &JackknifePartitions<Coordinate>,
+/// The `jackknife_partitions` with their SNP indices widened to `usize` once,
+/// rather than on every block of every partition
+fn widen_jackknife_partitions<I>(
+    jackknife_partitions: &JackknifePartitions<I>,
+) -> JackknifePartitions<usize>
+where
+    I: Copy + Debug + FromPrimitive + Integer + Sum + ToPrimitive, {
+    JackknifePartitions::from_partitions(IntegerPartitions::new(
+        jackknife_partitions
+            .iter()
+            .map(|block| widen_index_set(&block))
+            .collect(),
+    ))
+}
+
+/// `X X^T Z` for the standardized genotypes `X` of every partition on every
+/// jackknife block, where `geno` may hold a subset of the people in the bed
+fn get_partitioned_ggz_jackknife<G, I>(
+    geno: &G,
+    snp_partition_array: &Vec<Partition<I>>,
+    jackknife_partitions: &JackknifePartitions<I>,
+    snp_weights: Option<&SnpWeights>,
     rand_vecs: &Array<f32, Ix2>,
-) -> Vec<AdditiveJackknife<Array<f32, Ix2>>> {
+    num_snps_per_chunk: usize,
+) -> Vec<AdditiveJackknife<Array<f32, Ix2>>>
+where
+    G: GenotypeSource + Sync,
+    I: Copy
+        + Debug
+        + FromPrimitive
+        + Integer
+        + NumCast
+        + Send
+        + Sum
+        + Sync
+        + ToPrimitive, {
+    let jackknife_partitions = widen_jackknife_partitions(jackknife_partitions);
     snp_partition_array
         .par_iter()
         .map(|partition| {
+            let partition = widen_index_set(partition);
             AdditiveJackknife::from_op_over_jackknife_partitions(
                 &jackknife_partitions,
                 |_, knife| {
-                    let range_intersect = knife.intersect(partition);
-                    let (snp_mean, snp_std) = get_column_mean_and_std(
-                        &bed,
-                        &range_intersect,
-                        DEFAULT_NUM_SNPS_PER_CHUNK,
-                    );
-                    let gtz = normalized_g_transpose_dot_matrix(
-                        &bed,
-                        Some(range_intersect.clone()),
-                        &snp_mean,
-                        &snp_std,
-                        &rand_vecs,
-                        None,
-                        None,
-                    );
-                    normalized_g_dot_matrix(
-                        &bed,
-                        Some(range_intersect),
-                        &snp_mean,
-                        &snp_std,
-                        &gtz,
-                        None,
-                        Some(2048),
+                    let range = knife.intersect(&partition);
+                    g_gt_dot(
+                        geno,
+                        Some(range),
+                        snp_weights,
+                        rand_vecs,
+                        Some(num_snps_per_chunk),
                     )
                 },
             )
@@ -1375,82 +2938,114 @@ fn get_partitioned_ggz_jackknife(
         .collect()
 }
 
-fn get_partitioned_ygy_jackknife(
-    bed: &PlinkBed,
+/// Streams the blocks of `jackknife_partitions` that are not yet in the
+/// `checkpoint` one at a time, adding `X X^T Z` of every partition on the
+/// block to the checkpoint and saving it to `checkpoint_dir` after each.
+fn stream_checkpointed_ggz<G: GenotypeSource + Sync>(
+    geno: &G,
+    snp_partition_array: &[CompactSnpPartition],
+    jackknife_partitions: &JackknifePartitions<SnpIndex>,
+    snp_weights: Option<&SnpWeights>,
+    rand_vecs: &Array<f32, Ix2>,
+    num_snps_per_chunk: usize,
+    checkpoint: &mut EstimationCheckpoint,
+    checkpoint_dir: &Path,
+    progress: &dyn ProgressSink,
+) -> Result<(), String> {
+    let num_blocks = jackknife_partitions.num_partitions();
+    let snp_partition_array: Vec<OrderedIntegerSet<usize>> =
+        snp_partition_array.iter().map(widen_index_set).collect();
+    for b in checkpoint.num_streamed_blocks()..num_blocks {
+        progress.progress(
+            "generating ggz with checkpointing on block",
+            b + 1,
+            num_blocks,
+        );
+        let block = widen_index_set(&jackknife_partitions[b]);
+        let block_ggz: Vec<Array<f32, Ix2>> = snp_partition_array
+            .par_iter()
+            .map(|partition| {
+                let range = block.intersect(partition);
+                g_gt_dot(
+                    geno,
+                    Some(range),
+                    snp_weights,
+                    rand_vecs,
+                    Some(num_snps_per_chunk),
+                )
+            })
+            .collect();
+        checkpoint.push_block_ggz(block_ggz);
+        checkpoint
+            .save(checkpoint_dir)
+            .map_err(|why| why.to_string())?;
+    }
+    Ok(())
+}
+
+fn get_partitioned_ygy_jackknife<G: GenotypeSource>(
+    geno: &G,
     snp_partition_array: &Vec<SnpPartition>,
     jackknife_partitions: &JackknifePartitions<Coordinate>,
     pheno_arr: &Array<f32, Ix1>,
-) -> Vec<AdditiveJackknife<f64>> {
-    snp_partition_array
+) -> Result<Vec<AdditiveJackknife<f64>>, String> {
+    let pheno_matrix = pheno_arr.to_owned().insert_axis(Axis(1));
+    let mut xty_cache = XtyCache::new(DEFAULT_NUM_SNPS_PER_CHUNK, 1);
+    for partition in snp_partition_array.iter() {
+        xty_cache.extend(geno, partition, None, &pheno_matrix)?;
+    }
+    Ok(snp_partition_array
         .par_iter()
         .map(|partition| {
-            let means_and_stds_jackknife =
-                Jackknife::from_op_over_jackknife_partitions(
-                    jackknife_partitions,
-                    |knife| {
-                        get_column_mean_and_std(
-                            bed,
-                            &knife.intersect(partition),
-                            DEFAULT_NUM_SNPS_PER_CHUNK,
-                        )
-                    },
-                );
             AdditiveJackknife::from_op_over_jackknife_partitions(
                 jackknife_partitions,
-                |k, knife| {
-                    let range = knife.intersect(partition);
-                    let num_snps_in_range = range.size() as f64;
-                    pheno_k_pheno(
-                        pheno_arr,
-                        &range,
-                        bed,
-                        &means_and_stds_jackknife.components[k].0,
-                        &means_and_stds_jackknife.components[k].1,
-                        DEFAULT_NUM_SNPS_PER_CHUNK,
-                    ) * num_snps_in_range
+                |_, knife| {
+                    xty_cache
+                        .sum_of_squares(&knife.intersect(partition))
+                        .unwrap()[0]
                 },
             )
         })
-        .collect()
+        .collect())
 }
 
-fn get_partitioned_ygy_pheno_matrix_jackknife(
-    bed: &PlinkBed,
-    snp_partition_array: &Vec<SnpPartition>,
-    jackknife_partitions: &JackknifePartitions<Coordinate>,
+fn get_partitioned_ygy_pheno_matrix_jackknife<G: GenotypeSource, I>(
+    geno: &G,
+    snp_partition_array: &Vec<Partition<I>>,
+    jackknife_partitions: &JackknifePartitions<I>,
+    snp_weights: Option<&SnpWeights>,
     pheno_matrix: &Array<f32, Ix2>,
-) -> Vec<AdditiveJackknife<Array<f64, Ix1>>> {
-    snp_partition_array
+    num_snps_per_chunk: usize,
+) -> Result<Vec<AdditiveJackknife<Array<f64, Ix1>>>, String>
+where
+    I: Copy
+        + Debug
+        + FromPrimitive
+        + Integer
+        + NumCast
+        + Send
+        + Sum
+        + Sync
+        + ToPrimitive, {
+    let snp_partition_array: Vec<OrderedIntegerSet<usize>> =
+        snp_partition_array.iter().map(widen_index_set).collect();
+    let jackknife_partitions = widen_jackknife_partitions(jackknife_partitions);
+    let mut xty_cache = XtyCache::new(num_snps_per_chunk, pheno_matrix.dim().1);
+    for partition in snp_partition_array.iter() {
+        xty_cache.extend(geno, partition, snp_weights, pheno_matrix)?;
+    }
+    Ok(snp_partition_array
         .par_iter()
         .map(|partition| {
-            let means_and_stds_jackknife =
-                Jackknife::from_op_over_jackknife_partitions(
-                    jackknife_partitions,
-                    |knife| {
-                        get_column_mean_and_std(
-                            bed,
-                            &knife.intersect(partition),
-                            DEFAULT_NUM_SNPS_PER_CHUNK,
-                        )
-                    },
-                );
             AdditiveJackknife::from_op_over_jackknife_partitions(
-                jackknife_partitions,
-                |k, knife| {
+                &jackknife_partitions,
+                |_, knife| {
                     let range = knife.intersect(partition);
-                    let ygy_list = pheno_g_pheno_from_pheno_matrix(
-                        pheno_matrix,
-                        &range,
-                        bed,
-                        &means_and_stds_jackknife.components[k].0,
-                        &means_and_stds_jackknife.components[k].1,
-                        None,
-                    );
-                    Array::from_vec(ygy_list)
+                    Array::from_vec(xty_cache.sum_of_squares(&range).unwrap())
                 },
             )
         })
-        .collect()
+        .collect())
 }
 
 fn i_j_to_index(i: usize, j: usize, num_partitions: usize) -> usize {
@@ -1459,115 +3054,217 @@ fn i_j_to_index(i: usize, j: usize, num_partitions: usize) -> usize {
 
 /// `geno_arr` is the genotype matrix for the G component
 /// Each array in `le_snps_arr` contains the gxg basis SNPs for the
-/// corresponding gxg component Returns (a, b, var_estimates,
-/// normalized_geno_arr, normalized_le_snps_arr, normalized_pheno_arr),
-/// where `a` and `b` are the matrix A and vector b in Ax = b that is solved for
-/// the heritability estimates. `var_estimates` is a vector of the variance
-/// estimates due to G, the GxG components, and noise, in that order.
-/// The phenotypes are normalized to have unit variance so the `var_estimates`
-/// are the fractions of the total phenotypic variance due to the various
-/// components.
+/// corresponding gxg component, and `gxg_pair_exclusions[i]` lists the pairs
+/// of basis SNPs left out of the i-th gxg kernel.
+/// The result holds the matrix A and vector b in Ax = b that is solved for
+/// the variance estimates due to G, the GxG components, and noise, in that
+/// order, with their analytical standard errors, and the normalized
+/// `le_snps_arr` and phenotype.
+/// If `covariates` is `Some`, the returned phenotypes have the covariates
+/// projected out, and the kernels enter the normal equations projected on
+/// both sides.
+/// The probes of the G sketch and of the Hutch++ estimates of the GxG traces
+/// are drawn from the `probe_type`.
+/// If `seed` is `Some`, all the probes and sketches are drawn from it, so
+/// that the estimates are reproducible.
 pub fn estimate_g_and_multi_gxg_heritability(
     geno_arr: &mut PlinkBed,
     mut le_snps_arr: Vec<Array<f32, Ix2>>,
+    gxg_pair_exclusions: &[GxgPairExclusion],
     mut pheno_arr: Array<f32, Ix1>,
     num_random_vecs: usize,
-) -> Result<
-    (
-        Array<f64, Ix2>,
-        Array<f64, Ix1>,
-        Vec<f64>,
-        Vec<Array<f32, Ix2>>,
-        Array<f32, Ix1>,
-    ),
-    Error,
-> {
+    probe_type: ProbeType,
+    covariates: Option<&CovariateProjection>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<GxgHeritabilityResult, Error> {
     let (num_people, num_snps) =
         (geno_arr.num_people, geno_arr.total_num_snps());
     let num_gxg_components = le_snps_arr.len();
-    println!(
+    progress.message(&format!(
         "\n\
     => estimating heritability due to G and GxG\n\
     num_people: {}\n\
     num_snps: {}\n\
     number of GxG components: {}",
         num_people, num_snps, num_gxg_components
-    );
-    for (i, arr) in le_snps_arr.iter().enumerate() {
-        println!(
-            "GxG component [{}/{}]: {} LE SNPs",
+    ));
+    check_multi_gxg_num_people(num_people, &le_snps_arr, &pheno_arr)?;
+    check_gxg_pair_exclusions(&le_snps_arr, gxg_pair_exclusions)?;
+    for (i, (arr, exclusion)) in le_snps_arr
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+        .enumerate()
+    {
+        progress.message(&format!(
+            "GxG component [{}/{}]: {} LE SNPs, {} pairs excluded",
             i + 1,
             num_gxg_components,
-            arr.dim().1
-        );
+            arr.dim().1,
+            exclusion.num_excluded_pairs()
+        ));
     }
 
     for (i, arr) in le_snps_arr.iter_mut().enumerate() {
-        println!(
+        progress.message(&format!(
             "=> normalizing GxG component [{}/{}]",
             i + 1,
             num_gxg_components
-        );
+        ));
         normalize_matrix_columns_inplace(arr, 0);
     }
 
-    println!("\n=> normalizing the phenotype vector");
+    progress.message("\n=> normalizing the phenotype vector");
     normalize_vector_inplace(&mut pheno_arr, 0);
 
+    if let Some(covariates) = covariates {
+        covariates.check_num_people(num_people, "the genotype bed")?;
+        progress.message(&format!(
+            "\n=> projecting out {} covariates including the intercept",
+            covariates.num_covariates()
+        ));
+        covariates.project_vector_inplace(&mut pheno_arr);
+        let (a, b, estimate) = {
+            let pair_products: Vec<GxgPairProducts> = le_snps_arr
+                .iter()
+                .zip(gxg_pair_exclusions.iter())
+                .map(|(arr, exclusion)| exclusion.pair_products(arr))
+                .collect();
+            let kernels = get_g_and_gxg_kernels(
+                geno_arr,
+                &le_snps_arr,
+                &pair_products,
+                gxg_pair_exclusions,
+            );
+            let (a, b) = get_covariate_adjusted_normal_eqn(
+                &kernels,
+                &pheno_arr,
+                Some(covariates),
+                num_random_vecs,
+                seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM)),
+                progress,
+            );
+            debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+            let sig_sq = solve_normal_equations(&a, &b)?;
+            progress.message(&format!("variance estimates: {:?}", sig_sq));
+            let estimate = get_analytical_estimate(
+                &kernels,
+                get_multi_gxg_component_names(num_gxg_components),
+                &a,
+                sig_sq.to_vec(),
+                Some(covariates),
+                num_people,
+                num_random_vecs,
+                seed.map(|seed| {
+                    derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)
+                }),
+                progress,
+            )?;
+            (a, b, estimate)
+        };
+        return Ok(GxgHeritabilityResult::new(
+            a,
+            b,
+            estimate,
+            le_snps_arr,
+            pheno_arr,
+        ));
+    }
+
     let mut a = Array::<f64, Ix2>::zeros((
         num_gxg_components + 2,
         num_gxg_components + 2,
     ));
 
-    println!("\n=> estimating traces related to the G matrix");
+    progress.message("\n=> sketching the G matrix");
     let num_rand_z = 100usize;
-    let tr_kk_est = estimate_tr_kk(geno_arr, None, num_rand_z, None);
+    let sketch = get_g_sketch(
+        geno_arr,
+        &pheno_arr,
+        generate_probe_matrix(
+            num_people,
+            num_rand_z,
+            probe_type,
+            None,
+            seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM)),
+        ),
+    )?;
+    let tr_kk_est = sketch.tr_kk();
     a[[0, 0]] = tr_kk_est;
-    println!("tr_kk_est: {}", tr_kk_est);
+    progress.trace_value("tr_kk_est", tr_kk_est);
+
+    let pair_products: Vec<GxgPairProducts> = le_snps_arr
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+        .map(|(arr, exclusion)| exclusion.pair_products(arr))
+        .collect();
+    let mm: Vec<f64> = gxg_pair_exclusions
+        .iter()
+        .map(|exclusion| exclusion.num_included_pairs() as f64)
+        .collect();
+    // the seed of the `task`-th trace estimate of the `i`-th GxG component,
+    // where the tasks 0, 1 and 2 are tr(K K), tr(K) and tr(G K), and the task
+    // 3 + j is tr(K K_j) with the j-th component
+    let trace_seed = |i: usize, task: usize| {
+        seed.map(|seed| {
+            derive_seed(
+                derive_seed(derive_seed(seed, GXG_TRACE_SEED_STREAM), i as u64),
+                task as u64,
+            )
+        })
+    };
 
-    println!("\n=> estimating traces related to the GxG component pairs");
+    progress
+        .message("\n=> estimating traces related to the GxG component pairs");
     for i in 0..num_gxg_components {
         for j in i + 1..num_gxg_components {
-            a[[1 + i, 1 + j]] = estimate_tr_gxg_ki_gxg_kj(
+            a[[1 + i, 1 + j]] = pair_products[i].tr_gxg_k1_gxg_k2(
                 &le_snps_arr[i],
+                &pair_products[j],
                 &le_snps_arr[j],
                 num_random_vecs,
-            );
+                trace_seed(i, 3 + j),
+            ) / (mm[i] * mm[j]);
             a[[1 + j, 1 + i]] = a[[1 + i, 1 + j]];
-            println!(
-                "tr(gxg_k{} gxg_k{}) est: {}",
-                i + 1,
-                j + 1,
-                a[[1 + i, 1 + j]]
+            progress.trace_value(
+                &format!("tr(gxg_k{} gxg_k{}) est", i + 1, j + 1),
+                a[[1 + i, 1 + j]],
             );
         }
     }
 
-    println!("\n=> estimating traces related to the GxG components");
+    progress.message("\n=> estimating traces related to the GxG components");
     for i in 0..num_gxg_components {
-        println!("\nGXG component {}", i + 1);
-        let mm = n_choose_2(le_snps_arr[i].dim().1) as f64;
+        progress.message(&format!("\nGXG component {}", i + 1));
 
-        let gxg_tr_kk_est =
-            estimate_gxg_kk_trace(&le_snps_arr[i], num_random_vecs)?;
+        let gxg_tr_kk_est = pair_products[i].kk_trace(
+            &le_snps_arr[i],
+            num_random_vecs,
+            probe_type,
+            trace_seed(i, 0),
+        )? / (mm[i] * mm[i]);
         a[[1 + i, 1 + i]] = gxg_tr_kk_est;
-        println!("gxg_tr_kk{}_est: {}", i + 1, gxg_tr_kk_est);
+        progress.trace_value(&format!("gxg_tr_kk{}_est", i + 1), gxg_tr_kk_est);
 
-        let gxg_tr_k_est =
-            estimate_gxg_gram_trace(&le_snps_arr[i], num_random_vecs)? / mm;
+        let gxg_tr_k_est = pair_products[i].gram_trace(
+            &le_snps_arr[i],
+            num_random_vecs,
+            probe_type,
+            trace_seed(i, 1),
+        )? / mm[i];
         a[[num_gxg_components + 1, 1 + i]] = gxg_tr_k_est;
         a[[1 + i, num_gxg_components + 1]] = gxg_tr_k_est;
-        println!("gxg_tr_k{}_est: {}", i + 1, gxg_tr_k_est);
+        progress.trace_value(&format!("gxg_tr_k{}_est", i + 1), gxg_tr_k_est);
 
-        let tr_gk_est = estimate_tr_k_gxg_k(
-            geno_arr,
+        let tr_gk_est = sketch.tr_k_dot(&pair_products[i].dot_matrix(
             &le_snps_arr[i],
+            sketch.probes(),
             num_random_vecs,
-            None,
-        );
+            trace_seed(i, 2),
+        )) / mm[i];
         a[[0, 1 + i]] = tr_gk_est;
         a[[1 + i, 0]] = tr_gk_est;
-        println!("tr_gk{}_est: {}", i + 1, tr_gk_est);
+        progress.trace_value(&format!("tr_gk{}_est", i + 1), tr_gk_est);
     }
 
     let n = num_people as f64;
@@ -1575,192 +3272,921 @@ pub fn estimate_g_and_multi_gxg_heritability(
     a[[0, num_gxg_components + 1]] = n;
     a[[num_gxg_components + 1, num_gxg_components + 1]] = n;
     let b = get_yky_gxg_yky_and_yy(
-        geno_arr,
+        sketch.yky()[0],
         &pheno_arr,
         &le_snps_arr,
+        gxg_pair_exclusions,
         num_random_vecs,
+        seed.map(|seed| derive_seed(seed, GXG_YKY_SEED_STREAM)),
+        progress,
     );
-    println!("solving ax=b\na = {:?}\nb = {:?}", a, b);
-    let sig_sq = a.solve_into(b.clone()).unwrap();
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
 
-    println!("variance estimates: {:?}", sig_sq);
-    let mut var_estimates = Vec::new();
-    for i in 0..num_gxg_components + 2 {
-        var_estimates.push(sig_sq[i]);
-    }
-    Ok((a, b, var_estimates, le_snps_arr, pheno_arr))
+    progress.message(&format!("variance estimates: {:?}", sig_sq));
+    let estimate = get_multi_gxg_analytical_estimate(
+        geno_arr,
+        &le_snps_arr,
+        &pair_products,
+        gxg_pair_exclusions,
+        &a,
+        sig_sq.to_vec(),
+        None,
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)),
+        progress,
+    )?;
+    Ok(GxgHeritabilityResult::new(
+        a,
+        b,
+        estimate,
+        le_snps_arr,
+        pheno_arr,
+    ))
 }
 
 /// `saved_traces` is the matrix A in the normal equation Ax = y for
-/// heritability estimation
+/// heritability estimation, which has to be estimated with the same
+/// `covariates`. The estimates of the remaining random quantities are
+/// reproducible if `seed` is `Some`.
 pub fn estimate_g_and_multi_gxg_heritability_from_saved_traces(
     geno_bed: &mut PlinkBed,
     mut le_snps_arr: Vec<Array<f32, Ix2>>,
+    gxg_pair_exclusions: &[GxgPairExclusion],
     mut pheno_arr: Array<f32, Ix1>,
     num_random_vecs: usize,
     saved_traces: Array<f64, Ix2>,
-) -> Result<
-    (
-        Array<f64, Ix2>,
-        Array<f64, Ix1>,
-        Vec<f64>,
-        Vec<Array<f32, Ix2>>,
-        Array<f32, Ix1>,
-    ),
-    Error,
-> {
+    covariates: Option<&CovariateProjection>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<GxgHeritabilityResult, Error> {
     let (num_people, num_snps) =
         (geno_bed.num_people, geno_bed.total_num_snps());
     let num_gxg_components = le_snps_arr.len();
-    println!(
+    progress.message(&format!(
         "\n\
     => estimating heritability due to G and GxG\n\
     num_people: {}\n\
     num_snps: {}\n\
     number of GxG components: {}",
         num_people, num_snps, num_gxg_components
-    );
-    for (i, arr) in le_snps_arr.iter().enumerate() {
-        println!(
-            "GxG component [{}/{}]: {} LE SNPs",
+    ));
+    check_multi_gxg_num_people(num_people, &le_snps_arr, &pheno_arr)?;
+    check_gxg_pair_exclusions(&le_snps_arr, gxg_pair_exclusions)?;
+    for (i, (arr, exclusion)) in le_snps_arr
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+        .enumerate()
+    {
+        progress.message(&format!(
+            "GxG component [{}/{}]: {} LE SNPs, {} pairs excluded",
             i + 1,
             num_gxg_components,
-            arr.dim().1
-        );
+            arr.dim().1,
+            exclusion.num_excluded_pairs()
+        ));
     }
 
     for (i, arr) in le_snps_arr.iter_mut().enumerate() {
-        println!(
+        progress.message(&format!(
             "=> normalizing GxG component [{}/{}]",
             i + 1,
             num_gxg_components
-        );
+        ));
         normalize_matrix_columns_inplace(arr, 0);
     }
 
-    println!("\n=> normalizing the phenotype vector");
+    progress.message("\n=> normalizing the phenotype vector");
     normalize_vector_inplace(&mut pheno_arr, 0);
+    if let Some(covariates) = covariates {
+        covariates.check_num_people(num_people, "the genotype bed")?;
+        covariates.project_vector_inplace(&mut pheno_arr);
+    }
 
-    println!("\n=> computing yy yky and estimating gxg_yky");
+    // with the covariates projected out of y, these are y^T M K M y and
+    // |M y|^2 as the adjusted normal equations require
+    progress.message("\n=> computing yy yky and estimating gxg_yky");
+    let yky = compute_yky(geno_bed, &pheno_arr, None, None, None)?;
+    debug!("yky of the SNP chunks: {:?}", yky.chunk_contributions);
     let b = get_yky_gxg_yky_and_yy(
-        geno_bed,
+        yky.yky,
         &pheno_arr,
         &le_snps_arr,
+        gxg_pair_exclusions,
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, GXG_YKY_SEED_STREAM)),
+        progress,
+    );
+
+    debug!("solving ax=b\na = {:?}\nb = {:?}", saved_traces, b);
+    let sig_sq = solve_normal_equations(&saved_traces, &b)?;
+
+    progress.message(&format!("variance estimates: {:?}", sig_sq));
+    let pair_products: Vec<GxgPairProducts> = le_snps_arr
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+        .map(|(arr, exclusion)| exclusion.pair_products(arr))
+        .collect();
+    let estimate = get_multi_gxg_analytical_estimate(
+        geno_bed,
+        &le_snps_arr,
+        &pair_products,
+        gxg_pair_exclusions,
+        &saved_traces,
+        sig_sq.to_vec(),
+        covariates,
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)),
+        progress,
+    )?;
+    Ok(GxgHeritabilityResult::new(
+        saved_traces,
+        b,
+        estimate,
+        le_snps_arr,
+        pheno_arr,
+    ))
+}
+
+/// Estimates the same G and GxG variance components as
+/// `estimate_g_and_multi_gxg_heritability` without materializing the GxG
+/// basis SNPs. The i-th GxG component is over all the pairs of the SNPs of
+/// `gxg_basis_bed` in `gxg_snp_ranges[i]`, which are streamed in normalized
+/// chunks like the genotypes of the G component, so that the memory is
+/// linear in the number of people rather than in the number of basis SNPs.
+///
+/// The GxG kernels are multiplied through random sketches of the pairwise
+/// products, which take a pass over the basis SNPs for every batch of
+/// sketches. The returned `normalized_le_snps_arr` is empty.
+///
+/// `snp_weights` weight the SNPs of the G kernel and `gxg_basis_snp_weights`
+/// the basis SNPs of the GxG kernels, where a pair is weighted by the product
+/// of the weights of its SNPs.
+///
+/// Every named GRM in `precomputed_grms`, e.g. of a functional category read
+/// with `Grm::from_gcta_files`, is fit as a further component after the GxG
+/// components. Its traces with the other kernels are estimated from the
+/// probes, while `tr(K)` and `tr(K K)` are computed exactly from the loaded
+/// GRM unless there are `covariates`.
+///
+/// If `seed` is `Some`, all the probes and sketches are drawn from it, so
+/// that the estimates are reproducible.
+pub fn estimate_g_and_multi_gxg_heritability_from_beds(
+    geno_bed: &PlinkBed,
+    gxg_basis_bed: &PlinkBed,
+    gxg_snp_ranges: &[OrderedIntegerSet<usize>],
+    snp_weights: Option<&SnpWeights>,
+    gxg_basis_snp_weights: Option<&SnpWeights>,
+    precomputed_grms: &[(String, Grm)],
+    mut pheno_arr: Array<f32, Ix1>,
+    num_random_vecs: usize,
+    covariates: Option<&CovariateProjection>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<GxgHeritabilityResult, Error> {
+    let (num_people, num_snps) =
+        (geno_bed.num_people, geno_bed.total_num_snps());
+    let num_gxg_components = gxg_snp_ranges.len();
+    progress.message(&format!(
+        "\n\
+    => estimating heritability due to G and GxG from the streamed GxG basis\n\
+    num_people: {}\n\
+    num_snps: {}\n\
+    number of GxG components: {}",
+        num_people, num_snps, num_gxg_components
+    ));
+    check_num_people(
+        gxg_basis_bed.num_people,
+        "the GxG basis bed",
+        num_people,
+        "the genotype bed",
+    )?;
+    check_pheno_num_people(pheno_arr.len(), "the phenotype", num_people)?;
+    if let Some(w) = snp_weights {
+        w.check_num_snps(num_snps)?;
+    }
+    if let Some(w) = gxg_basis_snp_weights {
+        w.check_num_snps(gxg_basis_bed.total_num_snps())?;
+    }
+    for (i, range) in gxg_snp_ranges.iter().enumerate() {
+        if range.size() < 2 {
+            return Err(Error::Generic(format!(
+                "GxG component {} needs at least 2 basis SNPs, received {}",
+                i + 1,
+                range.size()
+            )));
+        }
+        if let Some(last) =
+            range.get_intervals_by_ref().last().map(|i| i.get_end())
+        {
+            if last >= gxg_basis_bed.total_num_snps() {
+                return Err(Error::Generic(format!(
+                    "GxG component {} refers to the basis SNP {} of a bed \
+                    with {} SNPs",
+                    i + 1,
+                    last,
+                    gxg_basis_bed.total_num_snps()
+                )));
+            }
+        }
+        progress.message(&format!(
+            "GxG component [{}/{}]: {} LE SNPs",
+            i + 1,
+            num_gxg_components,
+            range.size()
+        ));
+    }
+    for (name, grm) in precomputed_grms.iter() {
+        check_num_people(
+            grm.num_people(),
+            &format!("the GRM {}", name),
+            num_people,
+            "the genotype bed",
+        )?;
+        progress.message(&format!("precomputed GRM component: {}", name));
+    }
+
+    progress.message("\n=> normalizing the phenotype vector");
+    normalize_vector_inplace(&mut pheno_arr, 0);
+    if let Some(covariates) = covariates {
+        covariates.check_num_people(num_people, "the genotype bed")?;
+        progress.message(&format!(
+            "\n=> projecting out {} covariates including the intercept",
+            covariates.num_covariates()
+        ));
+        covariates.project_vector_inplace(&mut pheno_arr);
+    }
+
+    let mut kernels = vec![KernelProduct {
+        dot: Box::new(move |rhs: &Array<f32, Ix2>, _, _| {
+            g_gt_dot(geno_bed, None, snp_weights, rhs, None)
+        }),
+        is_exact: true,
+        num_snps: match snp_weights {
+            Some(w) => {
+                w.sum(&OrderedIntegerSet::from_slice(&[[0, num_snps - 1]]))
+            }
+            None => num_snps as f64,
+        },
+    }];
+    for range in gxg_snp_ranges.iter() {
+        kernels.push(KernelProduct {
+            dot: Box::new(
+                move |rhs: &Array<f32, Ix2>, num_sketches, sketch_seed| {
+                    gxg_k_dot(
+                        gxg_basis_bed,
+                        Some(range.clone()),
+                        gxg_basis_snp_weights,
+                        rhs,
+                        num_sketches,
+                        None,
+                        sketch_seed,
+                    )
+                },
+            ),
+            is_exact: false,
+            num_snps: match gxg_basis_snp_weights {
+                Some(w) => w.sum_of_pair_products(range),
+                None => n_choose_2(range.size()) as f64,
+            },
+        });
+    }
+    for (_, grm) in precomputed_grms.iter() {
+        kernels.push(KernelProduct {
+            dot: Box::new(move |rhs: &Array<f32, Ix2>, _, _| {
+                grm.dot(&rhs.mapv(|x| x as f64)).mapv(|x| x as f32)
+            }),
+            is_exact: true,
+            num_snps: 1.,
+        });
+    }
+    let (mut a, b) = get_covariate_adjusted_normal_eqn(
+        &kernels,
+        &pheno_arr,
+        covariates,
         num_random_vecs,
+        seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM)),
+        progress,
+    );
+    if covariates.is_none() {
+        let num_kernels = kernels.len();
+        for (i, (_, grm)) in precomputed_grms.iter().enumerate() {
+            let k = num_gxg_components + 1 + i;
+            a[[k, k]] = grm.trace_of_square();
+            a[[k, num_kernels]] = grm.trace();
+            a[[num_kernels, k]] = a[[k, num_kernels]];
+        }
+    }
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
+    progress.message(&format!("variance estimates: {:?}", sig_sq));
+    let mut component_names = get_multi_gxg_component_names(num_gxg_components);
+    component_names.splice(
+        num_gxg_components + 1..num_gxg_components + 1,
+        precomputed_grms.iter().map(|(name, _)| name.clone()),
     );
+    let estimate = get_analytical_estimate(
+        &kernels,
+        component_names,
+        &a,
+        sig_sq.to_vec(),
+        covariates,
+        num_people,
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)),
+        progress,
+    )?;
+    Ok(GxgHeritabilityResult::new(
+        a,
+        b,
+        estimate,
+        Vec::new(),
+        pheno_arr,
+    ))
+}
+
+/// `G`, `GxG_1`, ..., `GxG_k` and `noise`
+fn get_multi_gxg_component_names(num_gxg_components: usize) -> Vec<String> {
+    let mut names = vec!["G".to_string()];
+    for i in 1..=num_gxg_components {
+        names.push(format!("GxG_{}", i));
+    }
+    names.push("noise".to_string());
+    names
+}
+
+/// The products of the G kernel and of every GxG kernel, whose basis SNPs
+/// in `le_snps_arr` are normalized
+fn get_g_and_gxg_kernels<'a>(
+    geno_bed: &'a PlinkBed,
+    le_snps_arr: &'a [Array<f32, Ix2>],
+    pair_products: &'a [GxgPairProducts],
+    gxg_pair_exclusions: &[GxgPairExclusion],
+) -> Vec<KernelProduct<'a>> {
+    let mut kernels = vec![KernelProduct {
+        dot: Box::new(move |rhs: &Array<f32, Ix2>, _, _| {
+            g_gt_dot(geno_bed, None, None, rhs, None)
+        }),
+        is_exact: true,
+        num_snps: geno_bed.total_num_snps() as f64,
+    }];
+    for (i, products) in pair_products.iter().enumerate() {
+        let basis = &le_snps_arr[i];
+        kernels.push(KernelProduct {
+            dot: Box::new(
+                move |rhs: &Array<f32, Ix2>, num_sketches, sketch_seed| {
+                    products.dot_matrix(basis, rhs, num_sketches, sketch_seed)
+                },
+            ),
+            is_exact: false,
+            num_snps: gxg_pair_exclusions[i].num_included_pairs() as f64,
+        });
+    }
+    kernels
+}
 
-    println!("solving ax=b\na = {:?}\nb = {:?}", saved_traces, b);
-    let sig_sq = saved_traces.solve_into(b.clone()).unwrap();
+/// The G and GxG variance components `sig_sq` solved from the normal
+/// equations with the matrix `a`, with their analytical covariance, which is
+/// reproducible if `seed` is `Some`
+fn get_multi_gxg_analytical_estimate(
+    geno_bed: &PlinkBed,
+    le_snps_arr: &[Array<f32, Ix2>],
+    pair_products: &[GxgPairProducts],
+    gxg_pair_exclusions: &[GxgPairExclusion],
+    a: &Array<f64, Ix2>,
+    sig_sq: Vec<f64>,
+    covariates: Option<&CovariateProjection>,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<HeritabilityEstimate, Error> {
+    get_analytical_estimate(
+        &get_g_and_gxg_kernels(
+            geno_bed,
+            le_snps_arr,
+            pair_products,
+            gxg_pair_exclusions,
+        ),
+        get_multi_gxg_component_names(le_snps_arr.len()),
+        a,
+        sig_sq,
+        covariates,
+        geno_bed.num_people,
+        num_random_vecs,
+        seed,
+        progress,
+    )
+}
 
-    println!("variance estimates: {:?}", sig_sq);
-    let mut var_estimates = Vec::new();
-    for i in 0..num_gxg_components + 2 {
-        var_estimates.push(sig_sq[i]);
+fn check_gxg_pair_exclusions(
+    le_snps_arr: &[Array<f32, Ix2>],
+    gxg_pair_exclusions: &[GxgPairExclusion],
+) -> Result<(), Error> {
+    if le_snps_arr.len() != gxg_pair_exclusions.len() {
+        return Err(Error::Generic(format!(
+            "{} GxG pair exclusions provided for {} GxG components",
+            gxg_pair_exclusions.len(),
+            le_snps_arr.len()
+        )));
+    }
+    for (i, (arr, exclusion)) in le_snps_arr
+        .iter()
+        .zip(gxg_pair_exclusions.iter())
+        .enumerate()
+    {
+        if arr.dim().1 != exclusion.num_basis_snps() {
+            return Err(Error::Generic(format!(
+                "GxG component {} has {} basis SNPs but its pair exclusion \
+                is for {} basis SNPs",
+                i + 1,
+                arr.dim().1,
+                exclusion.num_basis_snps()
+            )));
+        }
     }
-    Ok((saved_traces, b, var_estimates, le_snps_arr, pheno_arr))
+    Ok(())
+}
+
+/// The sketch of all the SNPs of the G component with the `probes` and the
+/// normalized phenotype, which takes the only pass over `geno_bed` that the
+/// traces and `y^T K y` of the G kernel need
+fn get_g_sketch(
+    geno_bed: &PlinkBed,
+    normalized_pheno_arr: &Array<f32, Ix1>,
+    probes: Array<f32, Ix2>,
+) -> Result<GenotypeSketch, Error> {
+    GenotypeSketch::new(
+        geno_bed,
+        None,
+        None,
+        probes,
+        &normalized_pheno_arr.view().insert_axis(Axis(1)).to_owned(),
+        None,
+    )
 }
 
+/// `yky` is the `y^T K y` of the G kernel, e.g. from the `GenotypeSketch`
+/// of the trace estimation or from `compute_yky`.
+/// The estimate of the i-th GxG component is drawn from
+/// `derive_seed(seed, i)` if `seed` is `Some`.
 fn get_yky_gxg_yky_and_yy(
-    geno_arr: &mut PlinkBed,
+    yky: f64,
     normalized_pheno_arr: &Array<f32, Ix1>,
     normalized_le_snps_arr: &Vec<Array<f32, Ix2>>,
+    gxg_pair_exclusions: &[GxgPairExclusion],
     num_random_vecs: usize,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
 ) -> Array<f64, Ix1> {
-    let num_snps = geno_arr.total_num_snps();
     let num_gxg_components = normalized_le_snps_arr.len();
 
     let mut b = Array::<f64, Ix1>::zeros(num_gxg_components + 2);
 
-    let yky = geno_arr
-        .col_chunk_iter(1000, None)
-        .into_par_iter()
-        .fold(
-            || 0f32,
-            |mut acc, mut snp_chunk| {
-                normalize_matrix_columns_inplace(&mut snp_chunk, 0);
-                let arr = snp_chunk
-                    .t()
-                    .dot(normalized_pheno_arr)
-                    .as_slice()
-                    .unwrap()
-                    .to_owned();
-                acc += sum_of_squares_f32(arr.iter());
-                acc
-            },
-        )
-        .reduce(|| 0f32, |a, b| a + b)
-        / num_snps as f32;
     let yy = sum_of_squares(normalized_pheno_arr.iter());
-    b[0] = yky as f64;
+    b[0] = yky;
     b[num_gxg_components + 1] = yy;
-    println!("yky: {}\nyy: {}", yky, yy);
+    progress.message(&format!("yky: {}\nyy: {}", yky, yy));
 
-    println!("\n=> estimating traces related to y and the GxG components");
+    progress
+        .message("\n=> estimating traces related to y and the GxG components");
     for i in 0..num_gxg_components {
-        println!("\nGXG component {}", i + 1);
-        let mm = n_choose_2(normalized_le_snps_arr[i].dim().1) as f64;
-        println!(
+        progress.message(&format!("\nGXG component {}", i + 1));
+        let mm = gxg_pair_exclusions[i].num_included_pairs() as f64;
+        progress.message(&format!(
             "estimate_gxg_dot_y_norm_sq using {} random vectors",
             num_random_vecs * 50
-        );
-        let gxg_yky = estimate_gxg_dot_y_norm_sq(
-            &normalized_le_snps_arr[i],
-            &normalized_pheno_arr,
-            num_random_vecs * 50,
-        ) / mm;
+        ));
+        let gxg_yky = gxg_pair_exclusions[i]
+            .pair_products(&normalized_le_snps_arr[i])
+            .dot_y_norm_sq(
+                &normalized_le_snps_arr[i],
+                normalized_pheno_arr,
+                num_random_vecs * 50,
+                seed.map(|seed| derive_seed(seed, i as u64)),
+            )
+            / mm;
         b[1 + i] = gxg_yky;
-        println!("gxg{}_yky_est: {}", i + 1, gxg_yky);
+        progress.trace_value(&format!("gxg{}_yky_est", i + 1), gxg_yky);
     }
     b
 }
 
+/// The product of a kernel with the columns of a matrix, with the number of
+/// random sketches and the seed of the sketches for kernels that are not
+/// multiplied exactly. The product is unnormalized, i.e. the kernel times
+/// `num_snps`.
+struct KernelProduct<'a> {
+    dot: Box<
+        dyn Fn(&Array<f32, Ix2>, usize, Option<u64>) -> Array<f32, Ix2> + 'a,
+    >,
+    is_exact: bool,
+    num_snps: f64,
+}
+
+/// The normal equations of the variance components after the covariates are
+/// projected out by `M`, with `tr(M K_i M K_j)`, `tr(M K_i)` and `tr(M)` in
+/// the matrix and `y^T M K_i M y` and `|M y|^2` in the vector.
+/// `projected_pheno` is `M y`. Without `covariates`, `M` is the identity.
+///
+/// The traces are estimated with the probes `M Z`. Kernels that are only
+/// estimated from random sketches are multiplied with the probes twice with
+/// independent sketches, and the squared traces take the inner products
+/// across the two, so that the sketching error does not bias them.
+/// The probes and the sketches are reproducible if `seed` is `Some`.
+fn get_covariate_adjusted_normal_eqn(
+    kernels: &[KernelProduct],
+    projected_pheno: &Array<f32, Ix1>,
+    covariates: Option<&CovariateProjection>,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> (Array<f64, Ix2>, Array<f64, Ix1>) {
+    let num_kernels = kernels.len();
+    let num_people = projected_pheno.dim();
+    let z = project_covariates(
+        generate_sign_matrix(
+            num_people,
+            num_random_vecs,
+            None,
+            seed.map(|seed| derive_seed(seed, 0)),
+        ),
+        covariates,
+    );
+    // the seed of the `product`-th sketched product with the `i`-th kernel
+    let sketch_seed = |i: usize, product: usize| {
+        seed.map(|seed| derive_seed(seed, (1 + 3 * i + product) as u64))
+    };
+
+    let y = projected_pheno.to_owned().insert_axis(Axis(1));
+
+    let mut a = Array::<f64, Ix2>::zeros((num_kernels + 1, num_kernels + 1));
+    let mut b = Array::<f64, Ix1>::zeros(num_kernels + 1);
+    // the two independent products M K_i M Z of each kernel
+    let mut mkmz_list = Vec::new();
+    for (i, kernel) in kernels.iter().enumerate() {
+        progress.message(&format!(
+            "\n=> estimating the covariate adjusted traces of kernel [{}/{}]",
+            i + 1,
+            num_kernels
+        ));
+        let kz = (kernel.dot)(&z, num_random_vecs, sketch_seed(i, 0));
+        a[[i, num_kernels]] = sum_of_column_wise_inner_product(&z, &kz) as f64
+            / kernel.num_snps
+            / num_random_vecs as f64;
+        a[[num_kernels, i]] = a[[i, num_kernels]];
+        let other_kz = if kernel.is_exact {
+            None
+        } else {
+            Some(project_covariates(
+                (kernel.dot)(&z, num_random_vecs, sketch_seed(i, 1)),
+                covariates,
+            ))
+        };
+        mkmz_list.push((project_covariates(kz, covariates), other_kz));
+
+        let ky = (kernel.dot)(&y, num_random_vecs * 50, sketch_seed(i, 2));
+        b[i] = y.t().dot(&ky)[[0, 0]] as f64 / kernel.num_snps;
+    }
+    for i in 0..num_kernels {
+        for j in i..num_kernels {
+            let (mkmz_i, other_i) = &mkmz_list[i];
+            let (mkmz_j, other_j) = &mkmz_list[j];
+            let other_i = other_i.as_ref().unwrap_or(mkmz_i);
+            let other_j = other_j.as_ref().unwrap_or(mkmz_j);
+            let tr_ki_kj_est =
+                (sum_of_column_wise_inner_product(mkmz_i, other_j)
+                    + sum_of_column_wise_inner_product(other_i, mkmz_j))
+                    as f64
+                    / 2.
+                    / kernels[i].num_snps
+                    / kernels[j].num_snps
+                    / num_random_vecs as f64;
+            a[[i, j]] = tr_ki_kj_est;
+            a[[j, i]] = tr_ki_kj_est;
+        }
+    }
+    a[[num_kernels, num_kernels]] = match covariates {
+        Some(covariates) => covariates.trace(),
+        None => num_people as f64,
+    };
+    b[num_kernels] = sum_of_squares(projected_pheno.iter());
+    (a, b)
+}
+
+/// The Gaussian sampling covariance `Cov(b_i, b_j) = 2 tr(K_i V K_j V)` of
+/// the right-hand side of the normal equations, where the last component is
+/// the noise with the kernel `I`, and `V = sum_k sig_sq[k] K_k` is the
+/// phenotypic covariance implied by the estimates `sig_sq`. If `covariates`
+/// is `Some`, every kernel, including the noise, is replaced by `M K M`.
+///
+/// The traces are estimated as the mean of `(V K_i z)^T (K_j V z)` over the
+/// probes `z`, where `V K_i Z` is obtained for all the components at once by
+/// multiplying `V` with the stacked `K_i Z`.
+/// The probes and the sketches are reproducible if `seed` is `Some`.
+fn get_normal_eqn_rhs_covariance(
+    kernels: &[KernelProduct],
+    sig_sq: &[f64],
+    covariates: Option<&CovariateProjection>,
+    num_people: usize,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+) -> Result<Array<f64, Ix2>, Error> {
+    let num_components = kernels.len() + 1;
+    // `pass` indexes the three rounds of products with the components
+    let apply_component = |pass: usize, k: usize, rhs: &Array<f32, Ix2>| {
+        let rhs = project_covariates(rhs.clone(), covariates);
+        match kernels.get(k) {
+            Some(kernel) => {
+                let sketch_seed = seed.map(|seed| {
+                    derive_seed(seed, (1 + pass * num_components + k) as u64)
+                });
+                project_covariates(
+                    (kernel.dot)(&rhs, num_random_vecs, sketch_seed),
+                    covariates,
+                ) / kernel.num_snps as f32
+            }
+            None => rhs,
+        }
+    };
+    let apply_v = |kz_list: &[Array<f32, Ix2>]| {
+        kz_list.iter().zip(sig_sq.iter()).fold(
+            Array::<f32, Ix2>::zeros(kz_list[0].dim()),
+            |acc, (kz, &s)| acc + &(kz * s as f32),
+        )
+    };
+
+    let z = generate_sign_matrix(
+        num_people,
+        num_random_vecs,
+        None,
+        seed.map(|seed| derive_seed(seed, 0)),
+    );
+    let kz_list: Vec<Array<f32, Ix2>> = (0..num_components)
+        .map(|k| apply_component(0, k, &z))
+        .collect();
+    let vz = apply_v(&kz_list);
+    let kvz_list: Vec<Array<f32, Ix2>> = (0..num_components)
+        .map(|k| apply_component(1, k, &vz))
+        .collect();
+    let stacked_kz = stack(
+        Axis(1),
+        &kz_list.iter().map(|kz| kz.view()).collect::<Vec<_>>(),
+    )?;
+    let vkz = apply_v(
+        &(0..num_components)
+            .map(|k| apply_component(2, k, &stacked_kz))
+            .collect::<Vec<Array<f32, Ix2>>>(),
+    );
+
+    let mut covariance =
+        Array::<f64, Ix2>::zeros((num_components, num_components));
+    for i in 0..num_components {
+        let vkz_i =
+            vkz.slice(s![.., i * num_random_vecs..(i + 1) * num_random_vecs]);
+        for j in 0..num_components {
+            let tr_est = sum_of_column_wise_inner_product(&vkz_i, &kvz_list[j])
+                as f64
+                / num_random_vecs as f64;
+            // adding the estimate to both (i, j) and (j, i) symmetrizes it
+            // and supplies the factor of 2
+            covariance[[i, j]] += tr_est;
+            covariance[[j, i]] += tr_est;
+        }
+    }
+    Ok(covariance)
+}
+
+/// The variance components `sig_sq` solved from the normal equations with
+/// the matrix `a`, with their sandwich covariance. The covariance is
+/// reproducible if `seed` is `Some`.
+fn get_analytical_estimate(
+    kernels: &[KernelProduct],
+    component_names: Vec<String>,
+    a: &Array<f64, Ix2>,
+    sig_sq: Vec<f64>,
+    covariates: Option<&CovariateProjection>,
+    num_people: usize,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<HeritabilityEstimate, Error> {
+    progress
+        .phase_start("estimating the analytical covariance of the estimates");
+    let b_covariance = get_normal_eqn_rhs_covariance(
+        kernels,
+        &sig_sq,
+        covariates,
+        num_people,
+        num_random_vecs,
+        seed,
+    )?;
+    Ok(HeritabilityEstimate::new(
+        component_names,
+        sig_sq,
+        a,
+        &b_covariance,
+    )?)
+}
+
+/// Estimates the variance components of G, of GxE with the kernel
+/// `K ⊙ E E^T` for the `environment` `E`, and of the noise. The main effects
+/// of the environment are projected out as fixed-effect covariates together
+/// with the `covariates`, which do not include the intercept. The estimates
+/// are reproducible if `seed` is `Some`.
+pub fn estimate_g_and_gxe_heritability(
+    geno_bed: &PlinkBed,
+    environment: &Environment,
+    mut pheno_arr: Array<f32, Ix1>,
+    num_random_vecs: usize,
+    covariates: Option<&Array<f32, Ix2>>,
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<HeritabilityEstimate, Error> {
+    let (num_people, num_snps) =
+        (geno_bed.num_people, geno_bed.total_num_snps());
+    progress.message(&format!(
+        "\n\
+    => estimating heritability due to G and GxE\n\
+    num_people: {}\n\
+    num_snps: {}\n\
+    environment: {}",
+        num_people,
+        num_snps,
+        match environment.level_names() {
+            Some(names) => format!("discrete with levels {:?}", names),
+            None => "continuous".to_string(),
+        }
+    ));
+    check_pheno_num_people(pheno_arr.len(), "the phenotype array", num_people)?;
+    check_num_people(
+        environment.num_people(),
+        "the environment",
+        num_people,
+        "the genotype bed",
+    )?;
+    let fixed_effects = match covariates {
+        Some(covariates) => {
+            check_num_people(
+                covariates.dim().0,
+                "the covariates",
+                num_people,
+                "the genotype bed",
+            )?;
+            stack(Axis(1), &[
+                environment.fixed_effects().view(),
+                covariates.view(),
+            ])
+            .map_err(|why| {
+                format!("failed to stack the environment effects: {}", why)
+            })?
+        }
+        None => environment.fixed_effects(),
+    };
+    let projection = CovariateProjection::new(&fixed_effects)?;
+    progress.message(&format!(
+        "\n=> projecting out {} covariates including the intercept and the \
+        environment",
+        projection.num_covariates()
+    ));
+
+    normalize_vector_inplace(&mut pheno_arr, 0);
+    projection.project_vector_inplace(&mut pheno_arr);
+
+    let g_dot =
+        |rhs: &Array<f32, Ix2>| g_gt_dot(geno_bed, None, None, rhs, None);
+    let kernels = vec![
+        KernelProduct {
+            dot: Box::new(|rhs: &Array<f32, Ix2>, _, _| g_dot(rhs)),
+            is_exact: true,
+            num_snps: num_snps as f64,
+        },
+        KernelProduct {
+            dot: Box::new(|rhs: &Array<f32, Ix2>, _, _| {
+                environment.interaction_dot(&g_dot, rhs)
+            }),
+            is_exact: true,
+            num_snps: num_snps as f64,
+        },
+    ];
+    let (a, b) = get_covariate_adjusted_normal_eqn(
+        &kernels,
+        &pheno_arr,
+        Some(&projection),
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, PROBE_SEED_STREAM)),
+        progress,
+    );
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
+    progress.message(&format!("variance estimates: {:?}", sig_sq));
+    get_analytical_estimate(
+        &kernels,
+        vec!["G".to_string(), "GxE".to_string(), "noise".to_string()],
+        &a,
+        sig_sq.to_vec(),
+        Some(&projection),
+        num_people,
+        num_random_vecs,
+        seed.map(|seed| derive_seed(seed, ANALYTICAL_COVARIANCE_SEED_STREAM)),
+        progress,
+    )
+}
+
 #[deprecated]
 pub fn estimate_gxg_heritability(
     gxg_basis_arr: Array<f32, Ix2>,
     mut pheno_arr: Array<f32, Ix1>,
     num_random_vecs: usize,
-) -> Result<f64, String> {
-    println!("\n=> estimate_gxg_heritability");
+    covariates: Option<&CovariateProjection>,
+    progress: &dyn ProgressSink,
+) -> Result<f64, Error> {
+    progress.message("\n=> estimate_gxg_heritability");
     let (num_people, num_basis_snps) = gxg_basis_arr.dim();
+    check_pheno_num_people(pheno_arr.len(), "the phenotype array", num_people)?;
     let mm = n_choose_2(num_basis_snps) as f64;
-    println!(
+    progress.message(&format!(
         "num_people: {}\nnum_basis_snps: {}\nnumber of equivalent GxG SNPs: {}",
         num_people,
         num_basis_snps,
         n_choose_2(num_basis_snps)
-    );
+    ));
 
-    println!("\n=> normalizing the phenotype vector");
+    progress.message("\n=> normalizing the phenotype vector");
     normalize_vector_inplace(&mut pheno_arr, 0);
 
-    let gxg_kk_trace_est =
-        estimate_gxg_kk_trace(&gxg_basis_arr, num_random_vecs)?;
-    let gxg_k_trace_est =
-        estimate_gxg_gram_trace(&gxg_basis_arr, num_random_vecs)? / mm;
+    if let Some(covariates) = covariates {
+        covariates.check_num_people(num_people, "the GxG basis")?;
+        covariates.project_vector_inplace(&mut pheno_arr);
+        let products = GxgPairExclusion::none(num_basis_snps)
+            .pair_products(&gxg_basis_arr);
+        let kernels = vec![KernelProduct {
+            dot: Box::new(|rhs: &Array<f32, Ix2>, num_sketches, _| {
+                products.dot_matrix(&gxg_basis_arr, rhs, num_sketches, None)
+            }),
+            is_exact: false,
+            num_snps: mm,
+        }];
+        let (a, b) = get_covariate_adjusted_normal_eqn(
+            &kernels,
+            &pheno_arr,
+            Some(covariates),
+            num_random_vecs,
+            None,
+            progress,
+        );
+        debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+        let sig_sq = solve_normal_equations(&a, &b)?;
+        progress.message(&format!("\nsig_sq: {} {}", sig_sq[0], sig_sq[1]));
+        let heritability = sig_sq[0] / (sig_sq[0] + sig_sq[1]);
+        progress.message(&format!("heritability: {}", heritability));
+        return Ok(heritability);
+    }
+
+    let gxg_kk_trace_est = get_gxg_kk_trace(
+        &gxg_basis_arr,
+        None,
+        None,
+        num_random_vecs,
+        ProbeType::Rademacher,
+        None,
+    )?;
+    let gxg_k_trace_est = get_gxg_gram_trace(
+        &gxg_basis_arr,
+        None,
+        None,
+        num_random_vecs,
+        ProbeType::Rademacher,
+        None,
+    )? / mm;
 
-    println!("gxg_k_trace_est: {}", gxg_k_trace_est);
-    println!("gxg_kk_trace_est: {}", gxg_kk_trace_est);
+    progress.trace_value("gxg_k_trace_est", gxg_k_trace_est);
+    progress.trace_value("gxg_kk_trace_est", gxg_kk_trace_est);
 
-    let yky =
-        estimate_gxg_dot_y_norm_sq(&gxg_basis_arr, &pheno_arr, num_random_vecs)
-            / mm;
+    let yky = estimate_gxg_dot_y_norm_sq(
+        &gxg_basis_arr,
+        &pheno_arr,
+        None,
+        None,
+        num_random_vecs,
+        None,
+    ) / mm;
     let yy = sum_of_squares(pheno_arr.iter());
-    println!("yky: {}", yky);
-    println!("yy: {}", yy);
+    progress.trace_value("yky", yky);
+    progress.trace_value("yy", yy);
 
     let a = array![[gxg_kk_trace_est, gxg_k_trace_est], [
         gxg_k_trace_est,
         num_people as f64
     ]];
     let b = array![yky, yy];
-    println!("solving ax=b\na = {:?}\nb = {:?}", a, b);
-    let sig_sq = a.solve_into(b).unwrap();
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
 
     let sig_sq_g = sig_sq[0] as f64;
     let sig_sq_e = sig_sq[1] as f64;
-    println!("\nsig_sq: {} {}", sig_sq_g, sig_sq_e);
+    progress.message(&format!("\nsig_sq: {} {}", sig_sq_g, sig_sq_e));
     let heritability = sig_sq_g / (sig_sq_g + sig_sq_e);
-    println!("heritability: {}", heritability);
+    progress.message(&format!("heritability: {}", heritability));
 
     Ok(heritability)
 }
@@ -1773,59 +4199,100 @@ pub fn estimate_g_and_single_gxg_heritability(
     mut le_snps_arr: Array<f32, Ix2>,
     mut pheno_arr: Array<f32, Ix1>,
     num_random_vecs: usize,
+    progress: &dyn ProgressSink,
 ) -> Result<(f64, f64, f64), Error> {
     let mut geno_arr: Array<f32, Ix2> =
         geno_arr_bed.get_genotype_matrix(None)?;
     let (num_people, num_snps) = geno_arr.dim();
     let num_independent_snps = le_snps_arr.dim().1;
-    println!(
+    check_pheno_num_people(pheno_arr.len(), "the phenotype array", num_people)?;
+    check_num_people(
+        le_snps_arr.dim().0,
+        "the GxG basis",
+        num_people,
+        "the genotype bed",
+    )?;
+    progress.message(&format!(
         "\n\
     => estimating heritability due to G and GxG\n\
     num_people: {}\n\
     num_snps: {}\n\
     num_independent_snps: {}",
         num_people, num_snps, num_independent_snps
-    );
+    ));
 
-    println!("\n=> normalizing the genotype matrices");
+    progress.message("\n=> normalizing the genotype matrices");
     normalize_matrix_columns_inplace(&mut geno_arr, 0);
     normalize_matrix_columns_inplace(&mut le_snps_arr, 0);
 
-    println!("\n=> normalizing the phenotype vector");
+    progress.message("\n=> normalizing the phenotype vector");
     normalize_vector_inplace(&mut pheno_arr, 0);
 
-    println!("\n=> estimating traces related to the G matrix");
+    progress.message("\n=> estimating traces related to the G matrix");
     let num_rand_z = 100usize;
-    let tr_kk_est = estimate_tr_kk(geno_arr_bed, None, num_rand_z, None);
-    println!("tr_kk_est: {}", tr_kk_est);
+    let tr_kk_est = estimate_tr_kk_hutchpp(
+        &*geno_arr_bed,
+        None,
+        None,
+        None,
+        num_rand_z,
+        ProbeType::Rademacher,
+        None,
+        None,
+    )?;
+    progress.trace_value("tr_kk_est", tr_kk_est);
     let xy = geno_arr.t().dot(&pheno_arr);
     let yky = sum_of_squares(xy.iter()) / num_snps as f64;
     let yy = sum_of_squares(pheno_arr.iter());
 
-    println!("\n=> estimating traces related to the GxG matrix");
+    progress.message("\n=> estimating traces related to the GxG matrix");
     let mm = n_choose_2(num_independent_snps) as f64;
 
-    let gxg_tr_kk_est = estimate_gxg_kk_trace(&le_snps_arr, num_random_vecs)?;
-    let gxg_tr_k_est =
-        estimate_gxg_gram_trace(&le_snps_arr, num_random_vecs)? / mm;
+    let gxg_tr_kk_est = get_gxg_kk_trace(
+        &le_snps_arr,
+        None,
+        None,
+        num_random_vecs,
+        ProbeType::Rademacher,
+        None,
+    )?;
+    let gxg_tr_k_est = get_gxg_gram_trace(
+        &le_snps_arr,
+        None,
+        None,
+        num_random_vecs,
+        ProbeType::Rademacher,
+        None,
+    )? / mm;
 
-    println!("gxg_tr_k_est: {}", gxg_tr_k_est);
-    println!("gxg_tr_kk_est: {}", gxg_tr_kk_est);
+    progress.trace_value("gxg_tr_k_est", gxg_tr_k_est);
+    progress.trace_value("gxg_tr_kk_est", gxg_tr_kk_est);
 
-    println!(
+    progress.message(&format!(
         "estimate_gxg_dot_y_norm_sq using {} random vectors",
         num_random_vecs * 50
-    );
+    ));
     let gxg_yky = estimate_gxg_dot_y_norm_sq(
         &le_snps_arr,
         &pheno_arr,
+        None,
+        None,
         num_random_vecs * 50,
+        None,
     ) / mm;
-    println!("gxg_yky: {}", gxg_yky);
+    progress.trace_value("gxg_yky", gxg_yky);
 
-    let tr_gk_est =
-        estimate_tr_k_gxg_k(geno_arr_bed, &le_snps_arr, num_random_vecs, None);
-    println!("tr_gk_est: {}", tr_gk_est);
+    let tr_gk_est = estimate_tr_k_gxg_k(
+        geno_arr_bed,
+        &le_snps_arr,
+        None,
+        None,
+        None,
+        num_random_vecs,
+        None,
+        None,
+    );
+    progress.trace_value("tr_gk_est", tr_gk_est);
 
     let n = num_people as f64;
     let a = array![
@@ -1834,10 +4301,10 @@ pub fn estimate_g_and_single_gxg_heritability(
         [n, gxg_tr_k_est, n]
     ];
     let b = array![yky, gxg_yky, yy];
-    println!("solving ax=b\na = {:?}\nb = {:?}", a, b);
-    let sig_sq = a.solve_into(b).unwrap();
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
 
-    println!("variance estimates: {:?}", sig_sq);
+    progress.message(&format!("variance estimates: {:?}", sig_sq));
     Ok((sig_sq[0], sig_sq[1], sig_sq[2]))
 }
 
@@ -1846,61 +4313,72 @@ pub fn estimate_heritability_directly(
     mut geno_arr: Array<f32, Ix2>,
     mut pheno_arr: Array<f32, Ix1>,
     num_random_vecs: usize,
-) -> Result<f64, String> {
+    progress: &dyn ProgressSink,
+) -> Result<f64, Error> {
     let (num_people, num_snps) = geno_arr.dim();
-    println!("num_people: {}\nnum_snps: {}", num_people, num_snps);
+    progress.message(&format!(
+        "num_people: {}\nnum_snps: {}",
+        num_people, num_snps
+    ));
+    check_pheno_num_people(pheno_arr.len(), "the phenotype array", num_people)?;
 
-    println!("\n=> normalizing the genotype matrix column-wise");
+    progress.message("\n=> normalizing the genotype matrix column-wise");
     normalize_matrix_columns_inplace(&mut geno_arr, 0);
 
-    println!("\n=> normalizing the phenotype vector");
+    progress.message("\n=> normalizing the phenotype vector");
     normalize_vector_inplace(&mut pheno_arr, 0);
 
-    println!("\n=> generating random estimators");
+    progress.message("\n=> generating random estimators");
     let rand_vecs =
         generate_plus_minus_one_bernoulli_matrix(num_people, num_random_vecs);
 
-    println!(
+    progress.message(&format!(
         "\n=> MatMul geno_arr{:?} with rand_mat{:?}",
         geno_arr.dim(),
         rand_vecs.dim()
-    );
+    ));
     let xz_arr = geno_arr.t().dot(&rand_vecs);
 
-    println!(
+    progress.message(&format!(
         "\n=> MatMul geno_arr{:?}.T with xz_arr{:?}",
         geno_arr.dim(),
         xz_arr.dim()
-    );
+    ));
     let xxz = geno_arr.dot(&xz_arr);
 
-    println!("\n=> calculating trace estimate through L2 squared");
+    progress.message("\n=> calculating trace estimate through L2 squared");
     let trace_kk_est = sum_of_squares(xxz.iter())
         / (num_snps * num_snps * num_random_vecs) as f64;
-    println!("trace_kk_est: {}", trace_kk_est);
+    progress.trace_value("trace_kk_est", trace_kk_est);
 
-    println!("\n=> calculating yKy and yy");
+    progress.message("\n=> calculating yKy and yy");
     let yky = sum_of_squares(pheno_arr.dot(&geno_arr).iter()) / num_snps as f64;
     let yy = sum_of_squares(pheno_arr.iter());
 
     let n = num_people as f64;
     let a = array![[trace_kk_est, n], [n, n]];
     let b = array![yky, yy];
-    println!("solving ax=b\na = {:?}\nb = {:?}", a, b);
-    let sig_sq = a.solve_into(b).unwrap();
-    println!("sig_sq: {:?}", sig_sq);
+    debug!("solving ax=b\na = {:?}\nb = {:?}", a, b);
+    let sig_sq = solve_normal_equations(&a, &b)?;
+    progress.message(&format!("sig_sq: {:?}", sig_sq));
 
     let g_var = sig_sq[0] as f64;
     let noise_var = sig_sq[1] as f64;
     let heritability = g_var / (g_var + noise_var);
-    println!("heritability: {}", heritability);
+    progress.message(&format!("heritability: {}", heritability));
 
     Ok(heritability)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::i_j_to_index;
+    use ndarray::array;
+
+    use super::{
+        get_trace_estimates, get_unconverged_partitions, i_j_to_index,
+        solve_normal_equations,
+    };
+    use crate::error::Error;
 
     #[test]
     fn test_i_j_to_index() {
@@ -1917,4 +4395,59 @@ mod tests {
             test(n);
         }
     }
+
+    #[test]
+    fn test_solve_normal_equations() {
+        let a = array![[2., 1.], [1., 1.]];
+        let sig_sq = solve_normal_equations(&a, &array![3., 2.]).unwrap();
+        assert!((sig_sq[0] - 1.).abs() < 1e-10);
+        assert!((sig_sq[1] - 1.).abs() < 1e-10);
+
+        let singular = array![[1., 1.], [1., 1.]];
+        match solve_normal_equations(&singular, &array![1., 2.]) {
+            Err(Error::SingularNormalEquations(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_trace_estimates() {
+        // two probes for partition a and four for partition b
+        let ggz_a = array![[1f32, 3., 0., 0.], [1., 1., 0., 0.]];
+        let ggz_b = array![[1f32, 1., 2., 2.], [0., 2., 0., 2.]];
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let estimates = get_trace_estimates(
+            &[ggz_a.clone(), ggz_b.clone()],
+            &[1., 2.],
+            &[2, 4],
+            &keys,
+            false,
+        );
+        assert_eq!(estimates.len(), 3);
+        assert_eq!(estimates[0].name, "tr(K_a K_a)");
+        // the per-probe values are 2 and 10
+        assert_eq!(estimates[0].value, 6.);
+        assert_eq!(estimates[0].mc_standard_error, 4.);
+        assert_eq!(estimates[1].name, "tr(K_a K_b)");
+        assert_eq!(estimates[1].num_random_vecs, 4);
+        let exact = get_trace_estimates(
+            &[ggz_a.clone(), ggz_b.clone()],
+            &[1., 2.],
+            &[2, 4],
+            &keys,
+            true,
+        );
+        assert_eq!(exact[0].value, 6.);
+        assert_eq!(exact[0].mc_standard_error, 0.);
+        // the relative standard errors of tr(K K) are 2 / 3 and about 0.32
+        assert_eq!(
+            get_unconverged_partitions(
+                &[ggz_a, ggz_b],
+                &[1., 2.],
+                &[2, 4],
+                0.5
+            ),
+            vec![0]
+        );
+    }
 }
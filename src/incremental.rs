@@ -0,0 +1,311 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Ix2};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error, gpu::gram_dot,
+    partitioned_jackknife_estimates::PartitionedJackknifeEstimates,
+    resampling::Resampling, stochastic::GenotypeSource,
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+const INCREMENTAL_COMPONENT_NAME: &str = "G";
+
+/// The sufficient statistics of the single component heritability estimates,
+/// accumulated one batch of SNPs at a time, so that a newly genotyped batch
+/// can be appended by streaming only the new SNPs.
+///
+/// With `X_b` the standardized genotypes of batch `b`, `tr(KK)` is estimated
+/// from `X X^T Z = sum_b X_b X_b^T Z` and `y^T X X^T y = sum_b |X_b^T y|^2`,
+/// both divided by the powers of the total number of SNPs. The probes `Z`
+/// are regenerated from `probe_seed`, so every batch uses the same probes.
+/// Each batch is also a jackknife block of the estimates.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IncrementalAccumulator {
+    num_people: usize,
+    num_random_vecs: usize,
+    probe_seed: u64,
+    num_phenos: usize,
+    batches: Vec<BatchAccumulator>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct BatchAccumulator {
+    num_snps: usize,
+    /// `X_b X_b^T Z`
+    ggz: Array<f32, Ix2>,
+    /// `|X_b^T y|^2` for each phenotype
+    ygy: Vec<f64>,
+}
+
+impl IncrementalAccumulator {
+    pub fn new(
+        num_people: usize,
+        num_random_vecs: usize,
+        probe_seed: u64,
+        num_phenos: usize,
+    ) -> IncrementalAccumulator {
+        IncrementalAccumulator {
+            num_people,
+            num_random_vecs,
+            probe_seed,
+            num_phenos,
+            batches: Vec::new(),
+        }
+    }
+
+    pub fn num_batches(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn num_snps(&self) -> usize {
+        self.batches.iter().map(|b| b.num_snps).sum()
+    }
+
+    /// The `num_people x num_random_vecs` +-1 probes seeded by `probe_seed`
+    fn get_probes(&self) -> Array<f32, Ix2> {
+        let mut rng = StdRng::seed_from_u64(self.probe_seed);
+        Array::from_shape_fn((self.num_people, self.num_random_vecs), |_| {
+            if rng.gen::<bool>() {
+                1.
+            } else {
+                -1.
+            }
+        })
+    }
+
+    /// Streams the SNPs in `snp_range`, or all the SNPs if `None`, and
+    /// appends them as a new batch. `pheno_matrix` has one normalized
+    /// phenotype per column, and has to be the same for every batch.
+    pub fn append_batch<G: GenotypeSource>(
+        &mut self,
+        geno: &G,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        pheno_matrix: &Array<f32, Ix2>,
+        num_snps_per_chunk: usize,
+    ) -> Result<(), Error> {
+        if geno.num_people() != self.num_people {
+            return Err(Error::DimensionMismatch(format!(
+                "the batch has {} people, but the earlier batches have {}",
+                geno.num_people(),
+                self.num_people
+            )));
+        }
+        if pheno_matrix.dim() != (self.num_people, self.num_phenos) {
+            return Err(Error::DimensionMismatch(format!(
+                "expected a phenotype matrix of shape ({}, {}), received {:?}",
+                self.num_people,
+                self.num_phenos,
+                pheno_matrix.dim()
+            )));
+        }
+        let probes = self.get_probes();
+        let (num_people, num_random_vecs, num_phenos) =
+            (self.num_people, self.num_random_vecs, self.num_phenos);
+        let (ggz, ygy, num_snps) = geno.fold_snp_chunks(
+            snp_range,
+            num_snps_per_chunk,
+            || {
+                (
+                    Array::<f32, Ix2>::zeros((num_people, num_random_vecs)),
+                    vec![0f64; num_phenos],
+                    0usize,
+                )
+            },
+            |(ggz, mut ygy, num_snps), _, mut snp_chunk| {
+                normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+                let xty = snp_chunk.t().dot(pheno_matrix);
+                for (acc, col) in ygy.iter_mut().zip(xty.gencolumns()) {
+                    *acc += col.iter().map(|&x| (x * x) as f64).sum::<f64>();
+                }
+                (
+                    ggz + &gram_dot(&snp_chunk, &probes),
+                    ygy,
+                    num_snps + snp_chunk.dim().1,
+                )
+            },
+            |(ggz_a, mut ygy_a, num_snps_a), (ggz_b, ygy_b, num_snps_b)| {
+                for (a, b) in ygy_a.iter_mut().zip(ygy_b.into_iter()) {
+                    *a += b;
+                }
+                (ggz_a + &ggz_b, ygy_a, num_snps_a + num_snps_b)
+            },
+        );
+        if num_snps == 0 {
+            return Err(Error::Generic("the batch has no SNPs".to_string()));
+        }
+        self.batches.push(BatchAccumulator {
+            num_snps,
+            ggz,
+            ygy,
+        });
+        Ok(())
+    }
+
+    /// The estimates over the batches in `batch_indices` for each phenotype.
+    fn get_point_estimates(&self, batch_indices: &[usize]) -> Vec<Vec<f64>> {
+        let num_snps: usize = batch_indices
+            .iter()
+            .map(|&b| self.batches[b].num_snps)
+            .sum();
+        let mut ggz =
+            Array::<f32, Ix2>::zeros((self.num_people, self.num_random_vecs));
+        let mut ygy = vec![0f64; self.num_phenos];
+        for &b in batch_indices.iter() {
+            ggz += &self.batches[b].ggz;
+            for (acc, x) in ygy.iter_mut().zip(self.batches[b].ygy.iter()) {
+                *acc += x;
+            }
+        }
+        let m = num_snps as f64;
+        let n = self.num_people as f64;
+        let tr_kk = ggz.iter().map(|&x| (x * x) as f64).sum::<f64>()
+            / (m * m * self.num_random_vecs as f64);
+        // the normal equations [tr(KK) n; n n] [g; e] = [yKy; n], as tr(K)
+        // and |y|^2 are both n for standardized genotypes and phenotypes
+        ygy.into_iter()
+            .map(|ygy| vec![(ygy / m - n) / (tr_kk - n)])
+            .collect()
+    }
+
+    /// The estimates for each phenotype, with the jackknife over the batches
+    /// if there are at least two of them.
+    pub fn estimate(
+        &self,
+    ) -> Result<Vec<PartitionedJackknifeEstimates>, Error> {
+        let num_batches = self.batches.len();
+        if num_batches == 0 {
+            return Err(Error::Generic(
+                "no SNP batches have been accumulated".to_string(),
+            ));
+        }
+        let all: Vec<usize> = (0..num_batches).collect();
+        let point_estimates = self.get_point_estimates(&all);
+        let mut knife_estimates = vec![Vec::new(); self.num_phenos];
+        let resampling = if num_batches > 1 {
+            for k in 0..num_batches {
+                let rest: Vec<usize> =
+                    all.iter().cloned().filter(|&b| b != k).collect();
+                for (p, est) in
+                    self.get_point_estimates(&rest).into_iter().enumerate()
+                {
+                    knife_estimates[p].push(est);
+                }
+            }
+            Resampling::BlockJackknife {
+                num_blocks: num_batches,
+            }
+        } else {
+            Resampling::None
+        };
+        point_estimates
+            .iter()
+            .zip(knife_estimates.iter())
+            .map(|(point_estimate, knife_estimates)| {
+                PartitionedJackknifeEstimates::from_replicate_estimates(
+                    point_estimate,
+                    knife_estimates,
+                    Some(vec![INCREMENTAL_COMPONENT_NAME.to_string()]),
+                    None,
+                    &resampling,
+                )
+            })
+            .collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let buf_writer = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)
+                .map_err(|io_error| Error::IO {
+                    why: format!("failed to create {}", path.display()),
+                    io_error,
+                })?,
+        );
+        bincode::serialize_into(buf_writer, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<IncrementalAccumulator, Error> {
+        let path = path.as_ref();
+        let buf_reader =
+            BufReader::new(OpenOptions::new().read(true).open(path).map_err(
+                |io_error| Error::IO {
+                    why: format!("failed to open {}", path.display()),
+                    io_error,
+                },
+            )?);
+        Ok(bincode::deserialize_from(buf_reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{s, Array, Ix2};
+    use tempfile::NamedTempFile;
+
+    use super::IncrementalAccumulator;
+    use crate::util::matrix_util::{
+        generate_plus_minus_one_bernoulli_matrix,
+        normalize_matrix_columns_inplace,
+    };
+
+    #[test]
+    fn test_appending_batches_matches_a_single_pass() {
+        let geno: Array<f32, Ix2> =
+            generate_plus_minus_one_bernoulli_matrix(50, 40).mapv(|x| x + 1.);
+        let mut pheno =
+            generate_plus_minus_one_bernoulli_matrix(50, 2).mapv(|x| x * 0.5);
+        normalize_matrix_columns_inplace(&mut pheno, 0);
+
+        let mut incremental = IncrementalAccumulator::new(50, 10, 42, 2);
+        for range in [[0, 14], [15, 29], [30, 39]].iter() {
+            incremental
+                .append_batch(
+                    &geno,
+                    Some(OrderedIntegerSet::from_slice(&[*range])),
+                    &pheno,
+                    4,
+                )
+                .unwrap();
+        }
+        let mut single_pass = IncrementalAccumulator::new(50, 10, 42, 2);
+        single_pass.append_batch(&geno, None, &pheno, 7).unwrap();
+        assert_eq!(incremental.num_batches(), 3);
+        assert_eq!(incremental.num_snps(), 40);
+
+        let all_batches = incremental.get_point_estimates(&[0, 1, 2]);
+        let single = single_pass.get_point_estimates(&[0]);
+        for (a, b) in all_batches.iter().zip(single.iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-3 * b[0].abs().max(1.));
+        }
+        let estimates = incremental.estimate().unwrap();
+        assert_eq!(estimates.len(), 2);
+        assert_eq!(estimates[0].jackknife_iteration_estimates.len(), 3);
+
+        let file = NamedTempFile::new().unwrap();
+        incremental.save(file.path()).unwrap();
+        assert_eq!(
+            IncrementalAccumulator::load(file.path()).unwrap(),
+            incremental
+        );
+
+        assert!(incremental
+            .append_batch(&geno, None, &pheno.slice_move(s![.., ..1]), 4)
+            .is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet, traits::Finite,
+};
+
+use crate::{
+    gxg_pair_exclusion::SnpPosition,
+    integer_set::{
+        collect::SortedCollecting,
+        ops::{IntervalList, MergeDifference, MergeIntersect, Union},
+    },
+};
+
+/// A set of `(chromosome, position)` pairs across the genome, stored as one
+/// `OrderedIntegerSet` of positions per chromosome. Chromosomes without any
+/// positions are never stored, so two sets are equal if and only if they
+/// contain the same pairs, and the chromosomes are visited in sorted order.
+///
+/// The positions can be base pair coordinates, or the bim file line indices
+/// returned by `PlinkBim::get_chrom_to_fileline_positions`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ChromosomalIntegerSet {
+    chrom_to_positions: BTreeMap<String, OrderedIntegerSet<usize>>,
+}
+
+impl ChromosomalIntegerSet {
+    pub fn new() -> ChromosomalIntegerSet {
+        ChromosomalIntegerSet::default()
+    }
+
+    /// Adds the `positions` on `chrom` to the set.
+    pub fn insert(
+        &mut self,
+        chrom: &str,
+        positions: &OrderedIntegerSet<usize>,
+    ) {
+        if positions.interval_list().is_empty() {
+            return;
+        }
+        let merged = match self.chrom_to_positions.get(chrom) {
+            Some(existing) => existing.union(positions),
+            None => positions.clone(),
+        };
+        self.chrom_to_positions.insert(chrom.to_string(), merged);
+    }
+
+    pub fn get(&self, chrom: &str) -> Option<&OrderedIntegerSet<usize>> {
+        self.chrom_to_positions.get(chrom)
+    }
+
+    pub fn contains(&self, chrom: &str, position: usize) -> bool {
+        match self.chrom_to_positions.get(chrom) {
+            None => false,
+            Some(set) => intervals_contain(set.interval_list(), position),
+        }
+    }
+
+    /// The chromosomes with at least one position, in sorted order
+    pub fn chroms(&self) -> impl Iterator<Item = &String> {
+        self.chrom_to_positions.keys()
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&String, &OrderedIntegerSet<usize>)> {
+        self.chrom_to_positions.iter()
+    }
+
+    pub fn num_chroms(&self) -> usize {
+        self.chrom_to_positions.len()
+    }
+
+    /// The total number of pairs across all the chromosomes
+    pub fn size(&self) -> usize {
+        self.chrom_to_positions.values().map(|set| set.size()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chrom_to_positions.is_empty()
+    }
+
+    pub fn union(
+        &self,
+        other: &ChromosomalIntegerSet,
+    ) -> ChromosomalIntegerSet {
+        let mut union = self.clone();
+        for (chrom, positions) in other.iter() {
+            union.insert(chrom, positions);
+        }
+        union
+    }
+
+    pub fn intersect(
+        &self,
+        other: &ChromosomalIntegerSet,
+    ) -> ChromosomalIntegerSet {
+        self.iter()
+            .filter_map(|(chrom, positions)| {
+                other
+                    .get(chrom)
+                    .map(|o| (chrom.to_string(), positions.merge_intersect(o)))
+            })
+            .collect()
+    }
+
+    pub fn difference(
+        &self,
+        other: &ChromosomalIntegerSet,
+    ) -> ChromosomalIntegerSet {
+        self.iter()
+            .map(|(chrom, positions)| {
+                let diff = match other.get(chrom) {
+                    Some(o) => positions.merge_difference(o),
+                    None => positions.clone(),
+                };
+                (chrom.to_string(), diff)
+            })
+            .collect()
+    }
+
+    /// The union of the positions on all the chromosomes. Meaningful when the
+    /// positions are already genome-wide indices, e.g. bim file line indices.
+    pub fn flatten(&self) -> OrderedIntegerSet<usize> {
+        self.chrom_to_positions
+            .values()
+            .fold(OrderedIntegerSet::new(), |acc, set| acc.union(set))
+    }
+
+    /// Builds the set of base pair coordinates of the SNPs at the bim file
+    /// line `indices`, where `positions[i]` is the coordinate of line `i`.
+    pub fn from_fileline_indices(
+        indices: &OrderedIntegerSet<usize>,
+        positions: &[SnpPosition],
+    ) -> Result<ChromosomalIntegerSet, String> {
+        let mut chrom_to_bps: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for interval in indices.interval_list().iter() {
+            if interval.get_end() >= positions.len() {
+                return Err(format!(
+                    "bim line index {} out of range for {} SNPs",
+                    interval.get_end(),
+                    positions.len()
+                ));
+            }
+            for i in interval.get_start()..=interval.get_end() {
+                let (chrom, bp) = &positions[i];
+                chrom_to_bps
+                    .entry(chrom.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(*bp as usize);
+            }
+        }
+        Ok(chrom_to_bps
+            .into_iter()
+            .map(|(chrom, mut bps)| {
+                bps.sort();
+                (chrom, OrderedIntegerSet::collect_from_sorted_iter(bps))
+            })
+            .collect())
+    }
+
+    /// The indices of the bim file lines whose base pair coordinate is in the
+    /// set, where `positions[i]` is the coordinate of line `i`.
+    pub fn to_fileline_indices(
+        &self,
+        positions: &[SnpPosition],
+    ) -> OrderedIntegerSet<usize> {
+        OrderedIntegerSet::collect_from_sorted_iter(
+            positions.iter().enumerate().filter_map(|(i, (chrom, bp))| {
+                if self.contains(chrom, *bp as usize) {
+                    Some(i)
+                } else {
+                    None
+                }
+            }),
+        )
+    }
+}
+
+impl std::iter::FromIterator<(String, OrderedIntegerSet<usize>)>
+    for ChromosomalIntegerSet
+{
+    fn from_iter<I: IntoIterator<Item = (String, OrderedIntegerSet<usize>)>>(
+        iter: I,
+    ) -> ChromosomalIntegerSet {
+        let mut set = ChromosomalIntegerSet::new();
+        for (chrom, positions) in iter {
+            set.insert(&chrom, &positions);
+        }
+        set
+    }
+}
+
+impl From<HashMap<String, OrderedIntegerSet<usize>>> for ChromosomalIntegerSet {
+    fn from(
+        chrom_to_positions: HashMap<String, OrderedIntegerSet<usize>>,
+    ) -> ChromosomalIntegerSet {
+        chrom_to_positions.into_iter().collect()
+    }
+}
+
+impl std::ops::Index<&str> for ChromosomalIntegerSet {
+    type Output = OrderedIntegerSet<usize>;
+
+    /// Panics if `chrom` has no positions in the set.
+    fn index(&self, chrom: &str) -> &OrderedIntegerSet<usize> {
+        &self.chrom_to_positions[chrom]
+    }
+}
+
+fn intervals_contain(
+    intervals: &[ContiguousIntegerSet<usize>],
+    position: usize,
+) -> bool {
+    intervals
+        .binary_search_by(|interval| {
+            if interval.get_end() < position {
+                std::cmp::Ordering::Less
+            } else if interval.get_start() > position {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+
+    use super::ChromosomalIntegerSet;
+
+    fn get_set(
+        chrom_to_intervals: &[(&str, &[[usize; 2]])],
+    ) -> ChromosomalIntegerSet {
+        chrom_to_intervals
+            .iter()
+            .map(|(chrom, intervals)| {
+                (chrom.to_string(), OrderedIntegerSet::from_slice(intervals))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_ops() {
+        let a = get_set(&[("1", &[[1, 10]]), ("2", &[[5, 8]])]);
+        let b = get_set(&[("1", &[[8, 20]]), ("3", &[[0, 1]])]);
+        assert_eq!(
+            a.union(&b),
+            get_set(&[("1", &[[1, 20]]), ("2", &[[5, 8]]), ("3", &[[0, 1]])])
+        );
+        assert_eq!(a.intersect(&b), get_set(&[("1", &[[8, 10]])]));
+        assert_eq!(
+            a.difference(&b),
+            get_set(&[("1", &[[1, 7]]), ("2", &[[5, 8]])])
+        );
+        assert_eq!(a.difference(&a), ChromosomalIntegerSet::new());
+        assert!(a.difference(&a).is_empty());
+        assert_eq!(a.size(), 14);
+        assert!(a.contains("2", 6));
+        assert!(!a.contains("2", 9));
+        assert!(!a.contains("3", 0));
+        assert_eq!(a.chroms().collect::<Vec<&String>>(), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_from_hash_map_and_flatten() {
+        let mut map = HashMap::new();
+        map.insert("2".to_string(), OrderedIntegerSet::from_slice(&[[3, 4]]));
+        map.insert("1".to_string(), OrderedIntegerSet::from_slice(&[[0, 2]]));
+        map.insert("X".to_string(), OrderedIntegerSet::new());
+        let set = ChromosomalIntegerSet::from(map);
+        assert_eq!(set.num_chroms(), 2);
+        assert_eq!(set["2"], OrderedIntegerSet::from_slice(&[[3, 4]]));
+        assert_eq!(set.flatten(), OrderedIntegerSet::from_slice(&[[0, 4]]));
+    }
+
+    #[test]
+    fn test_fileline_index_conversion() {
+        let positions = vec![
+            ("1".to_string(), 100),
+            ("1".to_string(), 101),
+            ("1".to_string(), 200),
+            ("2".to_string(), 100),
+        ];
+        let set = ChromosomalIntegerSet::from_fileline_indices(
+            &OrderedIntegerSet::from_slice(&[[1, 3]]),
+            &positions,
+        )
+        .unwrap();
+        assert_eq!(
+            set,
+            get_set(&[("1", &[[101, 101], [200, 200]]), ("2", &[[100, 100]])])
+        );
+        assert_eq!(
+            set.to_fileline_indices(&positions),
+            OrderedIntegerSet::from_slice(&[[1, 3]])
+        );
+        assert!(ChromosomalIntegerSet::from_fileline_indices(
+            &OrderedIntegerSet::from_slice(&[[3, 4]]),
+            &positions,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use math::set::contiguous_integer_set::ContiguousIntegerSet;
+use num::{FromPrimitive, Integer, ToPrimitive};
+use rayon::prelude::*;
+
+use crate::integer_set::ops::{checked_span, IntervalList};
+
+/// Blocks of at most `chunk_size` consecutive integers covering a set.
+/// A block never spans two of the intervals in the set, so the last block of
+/// each interval can be smaller than `chunk_size`.
+pub struct IntervalChunks<E> {
+    chunks: Vec<ContiguousIntegerSet<E>>,
+}
+
+impl<E> IntervalChunks<E> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    #[inline]
+    pub fn into_vec(self) -> Vec<ContiguousIntegerSet<E>> {
+        self.chunks
+    }
+}
+
+impl<E: Send> IntoParallelIterator for IntervalChunks<E> {
+    type Item = ContiguousIntegerSet<E>;
+    type Iter = rayon::vec::IntoIter<ContiguousIntegerSet<E>>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        self.chunks.into_par_iter()
+    }
+}
+
+pub trait ParChunks<E> {
+    /// Panics if `chunk_size` is zero.
+    fn par_chunks(&self, chunk_size: usize) -> IntervalChunks<E>;
+}
+
+impl<E, S> ParChunks<E> for S
+where
+    E: Copy + FromPrimitive + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn par_chunks(&self, chunk_size: usize) -> IntervalChunks<E> {
+        assert!(chunk_size > 0, "chunk_size has to be positive");
+        let offset = chunk_size - 1;
+        let mut chunks = Vec::new();
+        for interval in self.interval_list().iter() {
+            let end = interval.get_end();
+            let mut start = interval.get_start();
+            // end - start itself can overflow for signed intervals spanning
+            // more than E::max_value() integers
+            while checked_span(start, end).map_or(true, |span| span > offset) {
+                // start + offset < end, but the offset can still exceed
+                // E::max_value() for signed E, so it is added in two halves
+                let half = offset / 2;
+                let chunk_end = start
+                    + E::from_usize(half).unwrap()
+                    + E::from_usize(offset - half).unwrap();
+                chunks.push(ContiguousIntegerSet::new(start, chunk_end));
+                start = chunk_end + E::one();
+            }
+            chunks.push(ContiguousIntegerSet::new(start, end));
+        }
+        IntervalChunks {
+            chunks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::{
+        contiguous_integer_set::ContiguousIntegerSet,
+        ordered_integer_set::OrderedIntegerSet, traits::Finite,
+    };
+    use rayon::prelude::*;
+
+    use super::ParChunks;
+
+    #[test]
+    fn test_par_chunks() {
+        let set = OrderedIntegerSet::from_slice(&[[0usize, 9], [20, 22]]);
+        assert_eq!(set.par_chunks(4).into_vec(), vec![
+            ContiguousIntegerSet::new(0, 3),
+            ContiguousIntegerSet::new(4, 7),
+            ContiguousIntegerSet::new(8, 9),
+            ContiguousIntegerSet::new(20, 22),
+        ]);
+        assert_eq!(set.par_chunks(100).len(), 2);
+
+        let total: usize =
+            set.par_chunks(3).into_par_iter().map(|c| c.size()).sum();
+        assert_eq!(total, set.size());
+
+        let small = OrderedIntegerSet::from_slice(&[[0u8, 255]]);
+        assert_eq!(small.par_chunks(1000).into_vec(), vec![
+            ContiguousIntegerSet::new(0u8, 255)
+        ]);
+
+        let signed = ContiguousIntegerSet::new(std::i8::MIN, std::i8::MAX);
+        let chunks = signed.par_chunks(100).into_vec();
+        assert_eq!(chunks, vec![
+            ContiguousIntegerSet::new(-128i8, -29),
+            ContiguousIntegerSet::new(-28, 71),
+            ContiguousIntegerSet::new(72, 127),
+        ]);
+        assert_eq!(signed.par_chunks(200).into_vec(), vec![
+            ContiguousIntegerSet::new(-128i8, 71),
+            ContiguousIntegerSet::new(72, 127),
+        ]);
+    }
+}
@@ -0,0 +1,99 @@
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{Integer, ToPrimitive};
+
+use crate::integer_set::ops::union_intervals;
+
+/// Bulk construction from sorted elements in a single linear pass, as
+/// opposed to `Collecting::collect`, which searches for the insertion point
+/// of every element.
+pub trait SortedCollecting<E>: Sized {
+    /// The elements have to be in non-decreasing order, and duplicates are
+    /// ignored.
+    fn collect_from_sorted_iter<I: IntoIterator<Item = E>>(iter: I) -> Self;
+
+    /// The elements have to be in non-decreasing order, and may overlap with
+    /// the existing elements in the set.
+    fn extend_from_sorted_slice(&mut self, slice: &[E]);
+}
+
+impl<E: Copy + Integer + ToPrimitive> SortedCollecting<E>
+    for OrderedIntegerSet<E>
+{
+    fn collect_from_sorted_iter<I: IntoIterator<Item = E>>(iter: I) -> Self {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            sorted_to_intervals(iter),
+        )
+    }
+
+    fn extend_from_sorted_slice(&mut self, slice: &[E]) {
+        let intervals = union_intervals(
+            self.get_intervals_by_ref(),
+            &sorted_to_intervals(slice.iter().cloned()),
+        );
+        *self =
+            OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+                intervals,
+            );
+    }
+}
+
+/// Groups runs of consecutive elements from a non-decreasing sequence into
+/// sorted and coalesced intervals.
+pub fn sorted_to_intervals<E, I>(iter: I) -> Vec<ContiguousIntegerSet<E>>
+where
+    E: Copy + Integer,
+    I: IntoIterator<Item = E>, {
+    let mut intervals: Vec<ContiguousIntegerSet<E>> = Vec::new();
+    for x in iter.into_iter() {
+        match intervals.last_mut() {
+            Some(last) if x <= last.get_end() => {
+                debug_assert!(
+                    x >= last.get_start(),
+                    "the elements are not sorted"
+                );
+            }
+            // x - 1 cannot overflow once x > last.get_end()
+            Some(last) if x - E::one() == last.get_end() => {
+                *last = ContiguousIntegerSet::new(last.get_start(), x);
+            }
+            _ => intervals.push(ContiguousIntegerSet::new(x, x)),
+        }
+    }
+    intervals
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+
+    use super::SortedCollecting;
+
+    #[test]
+    fn test_collect_from_sorted_iter() {
+        assert_eq!(
+            OrderedIntegerSet::collect_from_sorted_iter(vec![
+                1, 2, 2, 3, 5, 7, 8, 9
+            ]),
+            OrderedIntegerSet::from_slice(&[[1, 3], [5, 5], [7, 9]])
+        );
+        assert_eq!(
+            OrderedIntegerSet::<usize>::collect_from_sorted_iter(Vec::new()),
+            OrderedIntegerSet::new()
+        );
+    }
+
+    #[test]
+    fn test_extend_from_sorted_slice() {
+        let mut set = OrderedIntegerSet::from_slice(&[[1, 3], [10, 12]]);
+        set.extend_from_sorted_slice(&[0, 4, 6, 11, 13, 20]);
+        assert_eq!(
+            set,
+            OrderedIntegerSet::from_slice(&[[0, 4], [6, 6], [10, 13], [
+                20, 20
+            ]])
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{Integer, ToPrimitive};
+
+use crate::integer_set::{
+    collect::sorted_to_intervals, iter::Iter, ops::IntervalList,
+};
+
+/// Derives a subset by a predicate without materializing the elements into a
+/// `Vec` and back.
+pub trait Retain<E: Copy + Integer + ToPrimitive> {
+    /// Returns the set of elements `e` for which `predicate(e)` is true
+    fn retain<F: Fn(E) -> bool>(&self, predicate: F) -> OrderedIntegerSet<E>;
+
+    /// Replaces each interval by the slice of it returned by `f`, or drops the
+    /// interval if `f` returns `None`. The returned slices are clipped to the
+    /// original intervals, so the result is always a subset.
+    fn filter_map_intervals<F>(&self, f: F) -> OrderedIntegerSet<E>
+    where
+        F: Fn(&ContiguousIntegerSet<E>) -> Option<ContiguousIntegerSet<E>>;
+}
+
+impl<E, S> Retain<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn retain<F: Fn(E) -> bool>(&self, predicate: F) -> OrderedIntegerSet<E> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            sorted_to_intervals(self.iter().filter(|&e| predicate(e))),
+        )
+    }
+
+    fn filter_map_intervals<F>(&self, f: F) -> OrderedIntegerSet<E>
+    where
+        F: Fn(&ContiguousIntegerSet<E>) -> Option<ContiguousIntegerSet<E>>,
+    {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            self.interval_list()
+                .iter()
+                .filter_map(|interval| {
+                    let slice = f(interval)?;
+                    let start =
+                        std::cmp::max(slice.get_start(), interval.get_start());
+                    let end =
+                        std::cmp::min(slice.get_end(), interval.get_end());
+                    if start <= end {
+                        Some(ContiguousIntegerSet::new(start, end))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::{
+        contiguous_integer_set::ContiguousIntegerSet,
+        ordered_integer_set::OrderedIntegerSet,
+    };
+
+    use super::Retain;
+
+    #[test]
+    fn test_retain() {
+        let set = OrderedIntegerSet::from_slice(&[[0, 5], [10, 13]]);
+        assert_eq!(
+            set.retain(|e| e % 2 == 0 || e > 10),
+            OrderedIntegerSet::from_slice(&[[0, 0], [2, 2], [4, 4], [10, 13]])
+        );
+        assert_eq!(set.retain(|_| false), OrderedIntegerSet::new());
+        assert_eq!(set.retain(|_| true), set);
+        assert_eq!(
+            ContiguousIntegerSet::new(3usize, 7).retain(|e| e != 5),
+            OrderedIntegerSet::from_slice(&[[3, 4], [6, 7]])
+        );
+        assert_eq!(
+            ContiguousIntegerSet::new(250u8, 255).retain(|e| e > 252),
+            OrderedIntegerSet::from_slice(&[[253, 255]])
+        );
+    }
+
+    #[test]
+    fn test_filter_map_intervals() {
+        let set = OrderedIntegerSet::from_slice(&[[0, 5], [10, 13], [20, 20]]);
+        assert_eq!(
+            set.filter_map_intervals(|i| if i.get_start() == 20 {
+                None
+            } else {
+                Some(ContiguousIntegerSet::new(
+                    i.get_start() + 1,
+                    i.get_end() + 10,
+                ))
+            }),
+            OrderedIntegerSet::from_slice(&[[1, 5], [11, 13]])
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+
+/// A closed interval `[start, end]` of reals, e.g. a genetic map range in cM
+/// or a window of p-values, mirroring `ContiguousIntegerSet` for the interval
+/// algebra in this module.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ContiguousFloatInterval {
+    start: f64,
+    end: f64,
+}
+
+impl ContiguousFloatInterval {
+    /// The interval is empty if `start > end`. Panics if either bound is NaN.
+    pub fn new(start: f64, end: f64) -> ContiguousFloatInterval {
+        assert!(
+            !start.is_nan() && !end.is_nan(),
+            "interval bounds cannot be NaN"
+        );
+        ContiguousFloatInterval {
+            start,
+            end,
+        }
+    }
+
+    #[inline]
+    pub fn get_start(&self) -> f64 {
+        self.start
+    }
+
+    #[inline]
+    pub fn get_end(&self) -> f64 {
+        self.end
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start > self.end
+    }
+
+    #[inline]
+    pub fn length(&self) -> f64 {
+        if self.is_empty() {
+            0.
+        } else {
+            self.end - self.start
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, x: f64) -> bool {
+        self.start <= x && x <= self.end
+    }
+
+    pub fn intersect(
+        &self,
+        other: &ContiguousFloatInterval,
+    ) -> Option<ContiguousFloatInterval> {
+        let i = ContiguousFloatInterval::new(
+            self.start.max(other.start),
+            self.end.min(other.end),
+        );
+        if i.is_empty() {
+            None
+        } else {
+            Some(i)
+        }
+    }
+}
+
+/// Coalescing where gaps of at most `epsilon` are considered to be touching,
+/// the counterpart of two integer intervals being adjacent.
+pub trait EpsilonCoalesce<T> {
+    fn coalesce_with(&self, other: &T, epsilon: f64) -> Option<T>;
+}
+
+impl EpsilonCoalesce<ContiguousFloatInterval> for ContiguousFloatInterval {
+    fn coalesce_with(
+        &self,
+        other: &ContiguousFloatInterval,
+        epsilon: f64,
+    ) -> Option<ContiguousFloatInterval> {
+        if self.is_empty() {
+            return Some(*other);
+        }
+        if other.is_empty() {
+            return Some(*self);
+        }
+        if self.start > other.end + epsilon || other.start > self.end + epsilon
+        {
+            None
+        } else {
+            Some(ContiguousFloatInterval::new(
+                self.start.min(other.start),
+                self.end.max(other.end),
+            ))
+        }
+    }
+}
+
+/// Sorts the non-empty intervals and merges the ones within `epsilon` of
+/// each other.
+pub fn coalesce_float_intervals(
+    mut intervals: Vec<ContiguousFloatInterval>,
+    epsilon: f64,
+) -> Vec<ContiguousFloatInterval> {
+    intervals.retain(|i| !i.is_empty());
+    intervals.sort_by(|a, b| {
+        a.start.partial_cmp(&b.start).unwrap_or(Ordering::Equal)
+    });
+    let mut coalesced: Vec<ContiguousFloatInterval> =
+        Vec::with_capacity(intervals.len());
+    for interval in intervals.into_iter() {
+        match coalesced
+            .last()
+            .and_then(|last| last.coalesce_with(&interval, epsilon))
+        {
+            Some(merged) => *coalesced.last_mut().unwrap() = merged,
+            None => coalesced.push(interval),
+        }
+    }
+    coalesced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        coalesce_float_intervals, ContiguousFloatInterval, EpsilonCoalesce,
+    };
+
+    #[test]
+    fn test_float_interval() {
+        let a = ContiguousFloatInterval::new(0.5, 2.);
+        let b = ContiguousFloatInterval::new(1.5, 3.);
+        assert_eq!(a.length(), 1.5);
+        assert!(a.contains(2.) && !a.contains(2.1));
+        assert_eq!(
+            a.intersect(&b),
+            Some(ContiguousFloatInterval::new(1.5, 2.))
+        );
+        assert_eq!(a.intersect(&ContiguousFloatInterval::new(2.5, 3.)), None);
+        assert!(ContiguousFloatInterval::new(1., 0.).is_empty());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let a = ContiguousFloatInterval::new(0., 1.);
+        let b = ContiguousFloatInterval::new(1.0005, 2.);
+        assert_eq!(a.coalesce_with(&b, 0.), None);
+        assert_eq!(
+            a.coalesce_with(&b, 1e-3),
+            Some(ContiguousFloatInterval::new(0., 2.))
+        );
+        assert_eq!(
+            coalesce_float_intervals(
+                vec![
+                    ContiguousFloatInterval::new(5., 6.),
+                    b,
+                    ContiguousFloatInterval::new(3., 2.),
+                    a,
+                ],
+                1e-3
+            ),
+            vec![
+                ContiguousFloatInterval::new(0., 2.),
+                ContiguousFloatInterval::new(5., 6.)
+            ]
+        );
+    }
+}
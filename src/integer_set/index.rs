@@ -0,0 +1,81 @@
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{Integer, NumCast, ToPrimitive};
+use std::fmt::Debug;
+
+/// The compact index type used to store SNP index sets inside the
+/// estimators. Sets read from or passed to the PLINK files use `usize`, and
+/// are converted with `narrow_index_set` and `widen_index_set`.
+pub type SnpIndex = u32;
+
+/// Converts every interval endpoint of `set` to the type `T`. Returns an error
+/// if any endpoint cannot be represented by `T`.
+pub fn convert_index_set<S, T>(
+    set: &OrderedIntegerSet<S>,
+) -> Result<OrderedIntegerSet<T>, String>
+where
+    S: Copy + Debug + Integer + ToPrimitive,
+    T: Copy + Integer + NumCast + ToPrimitive, {
+    let convert = |i: S| {
+        T::from(i).ok_or_else(|| {
+            format!("index {:?} does not fit in the target index type", i)
+        })
+    };
+    // the conversion is monotone so the intervals stay sorted and coalesced
+    Ok(
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            set.get_intervals_by_ref()
+                .iter()
+                .map(|interval| {
+                    Ok(ContiguousIntegerSet::new(
+                        convert(interval.get_start())?,
+                        convert(interval.get_end())?,
+                    ))
+                })
+                .collect::<Result<Vec<ContiguousIntegerSet<T>>, String>>()?,
+        ),
+    )
+}
+
+/// Checked conversion of a `usize` index set read from the input files.
+#[inline]
+pub fn narrow_index_set(
+    set: &OrderedIntegerSet<usize>,
+) -> Result<OrderedIntegerSet<SnpIndex>, String> {
+    convert_index_set(set)
+}
+
+/// Converts an index set back to `usize` before handing it to the PLINK
+/// readers. Panics only if an index exceeds `usize::MAX`, which cannot
+/// happen for `SnpIndex`.
+#[inline]
+pub fn widen_index_set<I>(
+    set: &OrderedIntegerSet<I>,
+) -> OrderedIntegerSet<usize>
+where
+    I: Copy + Debug + Integer + ToPrimitive, {
+    convert_index_set(set).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+
+    use super::{convert_index_set, narrow_index_set, widen_index_set};
+
+    #[test]
+    fn test_index_set_conversion() {
+        let set = OrderedIntegerSet::from_slice(&[[0usize, 3], [10, 4000]]);
+        let narrowed = narrow_index_set(&set).unwrap();
+        assert_eq!(
+            narrowed,
+            OrderedIntegerSet::from_slice(&[[0u32, 3], [10, 4000]])
+        );
+        assert_eq!(widen_index_set(&narrowed), set);
+
+        let too_large = OrderedIntegerSet::from_slice(&[[0usize, 300]]);
+        assert!(convert_index_set::<usize, u8>(&too_large).is_err());
+    }
+}
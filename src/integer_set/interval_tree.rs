@@ -0,0 +1,156 @@
+use math::set::contiguous_integer_set::ContiguousIntegerSet;
+use num::{Integer, ToPrimitive};
+
+/// A static interval tree mapping integer intervals to payloads, e.g. SNP
+/// position ranges to annotation tracks.
+///
+/// The intervals are kept sorted by their start, and the tree is the implicit
+/// balanced binary search tree over that array, where every node also stores
+/// the largest end within its subtree. Both stabbing and overlap queries take
+/// `O(log(n) + k)` time for `k` matches.
+pub struct IntervalTree<E, T> {
+    entries: Vec<(ContiguousIntegerSet<E>, T)>,
+    subtree_max_end: Vec<E>,
+}
+
+impl<E: Copy + Integer + ToPrimitive, T> IntervalTree<E, T> {
+    /// Empty intervals are dropped. Intervals may overlap.
+    pub fn new(mut entries: Vec<(ContiguousIntegerSet<E>, T)>) -> Self {
+        entries
+            .retain(|(interval, _)| interval.get_start() <= interval.get_end());
+        entries.sort_by(|(a, _), (b, _)| {
+            a.get_start()
+                .cmp(&b.get_start())
+                .then(a.get_end().cmp(&b.get_end()))
+        });
+        let mut subtree_max_end: Vec<E> = entries
+            .iter()
+            .map(|(interval, _)| interval.get_end())
+            .collect();
+        fill_subtree_max_end(&mut subtree_max_end, 0, entries.len());
+        IntervalTree {
+            entries,
+            subtree_max_end,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entries whose interval contains `point`, ordered by the
+    /// interval start.
+    pub fn stab(&self, point: E) -> Vec<(&ContiguousIntegerSet<E>, &T)> {
+        self.overlapping(&ContiguousIntegerSet::new(point, point))
+    }
+
+    /// Returns the entries whose interval overlaps `query`, ordered by the
+    /// interval start.
+    pub fn overlapping(
+        &self,
+        query: &ContiguousIntegerSet<E>,
+    ) -> Vec<(&ContiguousIntegerSet<E>, &T)> {
+        let mut matches = Vec::new();
+        if query.get_start() <= query.get_end() {
+            self.collect_overlapping(
+                0,
+                self.entries.len(),
+                query.get_start(),
+                query.get_end(),
+                &mut matches,
+            );
+        }
+        matches
+    }
+
+    fn collect_overlapping<'a>(
+        &'a self,
+        lo: usize,
+        hi: usize,
+        start: E,
+        end: E,
+        matches: &mut Vec<(&'a ContiguousIntegerSet<E>, &'a T)>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        if self.subtree_max_end[mid] < start {
+            return;
+        }
+        self.collect_overlapping(lo, mid, start, end, matches);
+        let (interval, payload) = &self.entries[mid];
+        // every interval in the right subtree starts at or after this one
+        if interval.get_start() <= end {
+            if interval.get_end() >= start {
+                matches.push((interval, payload));
+            }
+            self.collect_overlapping(mid + 1, hi, start, end, matches);
+        }
+    }
+}
+
+/// On entry `max_end[i]` is the end of the i-th interval. On return, the
+/// root of every subtree over `[lo, hi)` holds the largest end in the subtree.
+fn fill_subtree_max_end<E: Copy + Ord>(
+    max_end: &mut [E],
+    lo: usize,
+    hi: usize,
+) -> Option<E> {
+    if lo >= hi {
+        return None;
+    }
+    let mid = lo + (hi - lo) / 2;
+    let left = fill_subtree_max_end(max_end, lo, mid);
+    let right = fill_subtree_max_end(max_end, mid + 1, hi);
+    let mut m = max_end[mid];
+    for e in left.into_iter().chain(right) {
+        m = std::cmp::max(m, e);
+    }
+    max_end[mid] = m;
+    Some(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::contiguous_integer_set::ContiguousIntegerSet;
+
+    use super::IntervalTree;
+
+    fn payloads<'a>(
+        matches: Vec<(&ContiguousIntegerSet<usize>, &'a &'static str)>,
+    ) -> Vec<&'a str> {
+        matches.into_iter().map(|(_, p)| *p).collect()
+    }
+
+    #[test]
+    fn test_interval_tree() {
+        let tree = IntervalTree::new(vec![
+            (ContiguousIntegerSet::new(10usize, 20), "gene_a"),
+            (ContiguousIntegerSet::new(0, 100), "enhancer"),
+            (ContiguousIntegerSet::new(15, 30), "gene_b"),
+            (ContiguousIntegerSet::new(40, 45), "gene_c"),
+            (ContiguousIntegerSet::new(5, 1), "empty"),
+        ]);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(payloads(tree.stab(17)), vec![
+            "enhancer", "gene_a", "gene_b"
+        ]);
+        assert_eq!(payloads(tree.stab(35)), vec!["enhancer"]);
+        assert!(tree.stab(101).is_empty());
+        assert_eq!(
+            payloads(tree.overlapping(&ContiguousIntegerSet::new(21, 40))),
+            vec!["enhancer", "gene_b", "gene_c"]
+        );
+        assert_eq!(
+            payloads(tree.overlapping(&ContiguousIntegerSet::new(46, 99))),
+            vec!["enhancer"]
+        );
+    }
+}
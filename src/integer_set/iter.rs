@@ -0,0 +1,144 @@
+use math::set::contiguous_integer_set::ContiguousIntegerSet;
+use num::{Integer, ToPrimitive};
+
+use crate::integer_set::ops::{checked_len, IntervalList};
+
+/// Iterates over the elements of a set in increasing order by walking the
+/// borrowed intervals, as opposed to `ToIterator` in the math crate, which
+/// clones the whole set first.
+pub struct ElementIter<'a, E> {
+    intervals: &'a [ContiguousIntegerSet<E>],
+    interval_index: usize,
+    next: Option<E>,
+}
+
+impl<'a, E: Copy + Integer> ElementIter<'a, E> {
+    pub fn new(intervals: &'a [ContiguousIntegerSet<E>]) -> ElementIter<'a, E> {
+        ElementIter {
+            intervals,
+            interval_index: 0,
+            next: intervals.first().map(|i| i.get_start()),
+        }
+    }
+}
+
+impl<'a, E: Copy + Integer + ToPrimitive> Iterator for ElementIter<'a, E> {
+    type Item = E;
+
+    fn next(&mut self) -> Option<E> {
+        let current = self.next?;
+        let end = self.intervals[self.interval_index].get_end();
+        // never increment past `end` so that sets ending at E::max_value()
+        // do not overflow
+        self.next = if current < end {
+            Some(current + E::one())
+        } else {
+            self.interval_index += 1;
+            self.intervals
+                .get(self.interval_index)
+                .map(|i| i.get_start())
+        };
+        Some(current)
+    }
+
+    /// The upper bound is `None` if the number of remaining elements does
+    /// not fit in a usize, e.g. for `[i64::MIN, i64::MAX]`.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match self.next {
+            None => Some(0),
+            Some(next) => {
+                self.intervals[self.interval_index + 1..].iter().fold(
+                    checked_len(
+                        next,
+                        self.intervals[self.interval_index].get_end(),
+                    ),
+                    |acc, i| {
+                        acc?.checked_add(checked_len(
+                            i.get_start(),
+                            i.get_end(),
+                        )?)
+                    },
+                )
+            }
+        };
+        match remaining {
+            Some(r) => (r, Some(r)),
+            None => (std::usize::MAX, None),
+        }
+    }
+}
+
+impl<'a, E: Copy + Integer + ToPrimitive> ExactSizeIterator
+    for ElementIter<'a, E>
+{
+}
+
+/// Borrowing iteration over the elements of a set.
+///
+/// `IntoIterator for &OrderedIntegerSet` would have to be implemented in the
+/// math crate, so `for i in set.iter()` is the way to loop over a set without
+/// cloning it.
+pub trait Iter<E> {
+    fn iter(&self) -> ElementIter<'_, E>;
+}
+
+impl<E, S> Iter<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    #[inline]
+    fn iter(&self) -> ElementIter<'_, E> {
+        ElementIter::new(self.interval_list())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::{
+        set::{
+            contiguous_integer_set::ContiguousIntegerSet,
+            ordered_integer_set::OrderedIntegerSet,
+        },
+        traits::ToIterator,
+    };
+
+    use super::Iter;
+
+    #[test]
+    fn test_iter() {
+        let set = OrderedIntegerSet::from_slice(&[[1, 3], [7, 7], [10, 12]]);
+        assert_eq!(set.iter().collect::<Vec<usize>>(), vec![
+            1, 2, 3, 7, 10, 11, 12
+        ]);
+        assert_eq!(
+            set.iter().collect::<Vec<usize>>(),
+            set.to_iter().collect::<Vec<usize>>()
+        );
+        let mut iter = set.iter();
+        assert_eq!(iter.len(), 7);
+        iter.next();
+        iter.next();
+        iter.next();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(OrderedIntegerSet::<usize>::new().iter().next(), None);
+        assert_eq!(ContiguousIntegerSet::new(5usize, 4).iter().next(), None);
+    }
+
+    #[test]
+    fn test_iter_at_type_bound() {
+        let set = OrderedIntegerSet::from_slice(&[[250u8, 255]]);
+        assert_eq!(set.iter().collect::<Vec<u8>>(), vec![
+            250, 251, 252, 253, 254, 255
+        ]);
+        let full = ContiguousIntegerSet::new(std::i32::MIN, std::i32::MAX);
+        assert_eq!(full.iter().len(), 1usize << 32);
+        assert_eq!(
+            ContiguousIntegerSet::new(std::i64::MIN, std::i64::MAX)
+                .iter()
+                .size_hint()
+                .1,
+            None
+        );
+    }
+}
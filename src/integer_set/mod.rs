@@ -0,0 +1,15 @@
+pub mod chromosomal;
+pub mod chunks;
+pub mod collect;
+pub mod filter;
+pub mod float_interval;
+pub mod index;
+pub mod interval_tree;
+pub mod iter;
+pub mod ops;
+pub mod rle;
+pub mod sample;
+pub mod slice;
+#[cfg(feature = "proptest-strategies")]
+pub mod strategy;
+pub mod validate;
@@ -0,0 +1,778 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{Integer, ToPrimitive};
+
+/// Exposes the sorted, coalesced intervals backing a set so that the set
+/// algebra below can be computed with a single linear merge.
+pub trait IntervalList<E: Copy + Integer + ToPrimitive> {
+    fn interval_list(&self) -> &[ContiguousIntegerSet<E>];
+}
+
+impl<E: Copy + Integer + ToPrimitive> IntervalList<E>
+    for ContiguousIntegerSet<E>
+{
+    #[inline]
+    fn interval_list(&self) -> &[ContiguousIntegerSet<E>] {
+        if self.get_start() > self.get_end() {
+            &[]
+        } else {
+            std::slice::from_ref(self)
+        }
+    }
+}
+
+impl<E: Copy + Integer + ToPrimitive> IntervalList<E> for OrderedIntegerSet<E> {
+    #[inline]
+    fn interval_list(&self) -> &[ContiguousIntegerSet<E>] {
+        self.get_intervals_by_ref()
+    }
+}
+
+pub trait Union<Rhs, Output> {
+    fn union(&self, other: Rhs) -> Output;
+}
+
+/// Intersection computed by a linear merge over the two interval lists, as
+/// opposed to the nested-loop `Intersect` in the math crate.
+pub trait MergeIntersect<Rhs, Output> {
+    fn merge_intersect(&self, other: Rhs) -> Output;
+}
+
+/// Set difference computed by a single two-pointer sweep over the two
+/// interval lists, as opposed to the `Sub` implementation in the math crate,
+/// which allocates an intermediate set per interval.
+pub trait MergeDifference<Rhs, Output> {
+    fn merge_difference(&self, other: Rhs) -> Output;
+}
+
+pub trait SymmetricDifference<Rhs, Output> {
+    fn symmetric_difference(&self, other: Rhs) -> Output;
+}
+
+/// Intersection of many sets with a single k-way merge of their interval
+/// lists in `O(n log k)` for `n` intervals in total, as opposed to folding
+/// `k - 1` pairwise intersections, e.g. for intersecting the SNP filters of
+/// several criteria.
+pub trait IntersectAll<E: Copy + Integer + ToPrimitive>: Sized {
+    /// The intersection of no sets is taken to be empty.
+    fn intersect_all(sets: &[Self]) -> OrderedIntegerSet<E>;
+}
+
+/// Relations between two sets decided by a linear merge over the interval
+/// lists, without iterating over the elements, e.g. to check that the SNP
+/// sets of two components do not overlap.
+pub trait SetRelations<E: Copy + Integer + ToPrimitive> {
+    fn is_subset_of<T: IntervalList<E>>(&self, other: &T) -> bool;
+
+    fn is_superset_of<T: IntervalList<E>>(&self, other: &T) -> bool;
+
+    fn is_disjoint_from<T: IntervalList<E>>(&self, other: &T) -> bool;
+
+    /// Whether the two sets share at least one element
+    fn overlaps<T: IntervalList<E>>(&self, other: &T) -> bool;
+}
+
+/// The elements of `universe` that are not in the set, computed with a single
+/// linear sweep. Elements of the set outside the universe are ignored.
+pub trait ComplementWithin<E: Copy + Integer + ToPrimitive> {
+    fn complement_within(
+        &self,
+        universe: &ContiguousIntegerSet<E>,
+    ) -> OrderedIntegerSet<E>;
+}
+
+impl<E, S, T> Union<&T, OrderedIntegerSet<E>> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+    T: IntervalList<E>,
+{
+    fn union(&self, other: &T) -> OrderedIntegerSet<E> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            union_intervals(self.interval_list(), other.interval_list()),
+        )
+    }
+}
+
+impl<E, S, T> MergeIntersect<&T, OrderedIntegerSet<E>> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+    T: IntervalList<E>,
+{
+    fn merge_intersect(&self, other: &T) -> OrderedIntegerSet<E> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            intersect_intervals(self.interval_list(), other.interval_list()),
+        )
+    }
+}
+
+impl<E, S, T> MergeDifference<&T, OrderedIntegerSet<E>> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+    T: IntervalList<E>,
+{
+    fn merge_difference(&self, other: &T) -> OrderedIntegerSet<E> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            difference_intervals(self.interval_list(), other.interval_list()),
+        )
+    }
+}
+
+impl<E, S, T> SymmetricDifference<&T, OrderedIntegerSet<E>> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+    T: IntervalList<E>,
+{
+    fn symmetric_difference(&self, other: &T) -> OrderedIntegerSet<E> {
+        let a = self.interval_list();
+        let b = other.interval_list();
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            difference_intervals(
+                &union_intervals(a, b),
+                &intersect_intervals(a, b),
+            ),
+        )
+    }
+}
+
+impl<E, S> SetRelations<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn is_subset_of<T: IntervalList<E>>(&self, other: &T) -> bool {
+        is_subset_intervals(self.interval_list(), other.interval_list())
+    }
+
+    fn is_superset_of<T: IntervalList<E>>(&self, other: &T) -> bool {
+        is_subset_intervals(other.interval_list(), self.interval_list())
+    }
+
+    fn is_disjoint_from<T: IntervalList<E>>(&self, other: &T) -> bool {
+        is_disjoint_intervals(self.interval_list(), other.interval_list())
+    }
+
+    #[inline]
+    fn overlaps<T: IntervalList<E>>(&self, other: &T) -> bool {
+        !self.is_disjoint_from(other)
+    }
+}
+
+impl<E, S> ComplementWithin<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn complement_within(
+        &self,
+        universe: &ContiguousIntegerSet<E>,
+    ) -> OrderedIntegerSet<E> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            difference_intervals(
+                universe.interval_list(),
+                self.interval_list(),
+            ),
+        )
+    }
+}
+
+impl<E, S> IntersectAll<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn intersect_all(sets: &[S]) -> OrderedIntegerSet<E> {
+        let lists: Vec<&[ContiguousIntegerSet<E>]> =
+            sets.iter().map(|s| s.interval_list()).collect();
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            intersect_all_intervals(&lists),
+        )
+    }
+}
+
+/// Whether `start` either falls inside or immediately follows an interval
+/// ending at `end`, written so that it cannot overflow at the type bounds.
+/// `start - end` would overflow for e.g. `end = i32::MIN, start = i32::MAX`,
+/// whereas `start - 1` cannot once `start > end`.
+#[inline]
+fn touches<E: Copy + Integer>(end: E, start: E) -> bool {
+    start <= end || start - E::one() == end
+}
+
+/// `end - start` for `start <= end`, computed without overflowing `E` even
+/// if the interval spans more than `E::max_value()` integers, e.g.
+/// `[i32::MIN, i32::MAX]`. `None` if the difference does not fit in a usize.
+pub fn checked_span<E: Copy + Integer + ToPrimitive>(
+    start: E,
+    end: E,
+) -> Option<usize> {
+    let zero = E::zero();
+    if start >= zero || end < zero {
+        (end - start).to_usize()
+    } else {
+        // start < 0 <= end, so split the difference at zero. Negating
+        // start + 1 instead of start cannot overflow.
+        let below_zero = (zero - (start + E::one())).to_usize()?;
+        end.to_usize()?.checked_add(below_zero)?.checked_add(1)
+    }
+}
+
+/// The number of integers in `[start, end]` for `start <= end`, `None` if it
+/// does not fit in a usize.
+#[inline]
+pub fn checked_len<E: Copy + Integer + ToPrimitive>(
+    start: E,
+    end: E,
+) -> Option<usize> {
+    checked_span(start, end)?.checked_add(1)
+}
+
+/// The number of elements in a set, computed without overflowing at the type
+/// bounds, as opposed to `Finite::size` in the math crate, which overflows
+/// for intervals such as `[i32::MIN, i32::MAX]`.
+pub trait CheckedSize<E> {
+    /// `None` if the size does not fit in a usize.
+    fn checked_size(&self) -> Option<usize>;
+}
+
+impl<E, S> CheckedSize<E> for S
+where
+    E: Copy + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn checked_size(&self) -> Option<usize> {
+        self.interval_list().iter().try_fold(0usize, |acc, i| {
+            acc.checked_add(checked_len(i.get_start(), i.get_end())?)
+        })
+    }
+}
+
+/// Both `a` and `b` have to be sorted, coalesced and free of empty intervals.
+/// Returns the sorted and coalesced intervals of the union.
+pub fn union_intervals<E: Copy + Integer>(
+    a: &[ContiguousIntegerSet<E>],
+    b: &[ContiguousIntegerSet<E>],
+) -> Vec<ContiguousIntegerSet<E>> {
+    let mut merged: Vec<ContiguousIntegerSet<E>> =
+        Vec::with_capacity(a.len() + b.len());
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() || j < b.len() {
+        let next = if j >= b.len()
+            || (i < a.len() && a[i].get_start() <= b[j].get_start())
+        {
+            i += 1;
+            a[i - 1]
+        } else {
+            j += 1;
+            b[j - 1]
+        };
+        match merged.last_mut() {
+            Some(last) if touches(last.get_end(), next.get_start()) => {
+                if next.get_end() > last.get_end() {
+                    *last = ContiguousIntegerSet::new(
+                        last.get_start(),
+                        next.get_end(),
+                    );
+                }
+            }
+            _ => merged.push(next),
+        }
+    }
+    merged
+}
+
+/// Both `a` and `b` have to be sorted, coalesced and free of empty intervals.
+/// Returns the sorted and coalesced intervals of the intersection.
+pub fn intersect_intervals<E: Copy + Integer>(
+    a: &[ContiguousIntegerSet<E>],
+    b: &[ContiguousIntegerSet<E>],
+) -> Vec<ContiguousIntegerSet<E>> {
+    let mut intersection = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() && j < b.len() {
+        let start = std::cmp::max(a[i].get_start(), b[j].get_start());
+        let end = std::cmp::min(a[i].get_end(), b[j].get_end());
+        if start <= end {
+            intersection.push(ContiguousIntegerSet::new(start, end));
+        }
+        if a[i].get_end() < b[j].get_end() {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    intersection
+}
+
+/// Both `a` and `b` have to be sorted, coalesced and free of empty intervals.
+/// As `b` is coalesced, every interval of a subset `a` has to lie within a
+/// single interval of `b`.
+pub fn is_subset_intervals<E: Copy + Integer>(
+    a: &[ContiguousIntegerSet<E>],
+    b: &[ContiguousIntegerSet<E>],
+) -> bool {
+    let mut j = 0;
+    for interval in a.iter() {
+        while j < b.len() && b[j].get_end() < interval.get_start() {
+            j += 1;
+        }
+        if j == b.len()
+            || b[j].get_start() > interval.get_start()
+            || b[j].get_end() < interval.get_end()
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Both `a` and `b` have to be sorted, coalesced and free of empty intervals.
+pub fn is_disjoint_intervals<E: Copy + Integer>(
+    a: &[ContiguousIntegerSet<E>],
+    b: &[ContiguousIntegerSet<E>],
+) -> bool {
+    let mut i = 0;
+    let mut j = 0;
+    while i < a.len() && j < b.len() {
+        if std::cmp::max(a[i].get_start(), b[j].get_start())
+            <= std::cmp::min(a[i].get_end(), b[j].get_end())
+        {
+            return false;
+        }
+        if a[i].get_end() < b[j].get_end() {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    true
+}
+
+/// Every list has to be sorted, coalesced and free of empty intervals.
+/// Returns the sorted and coalesced intervals of the intersection of all the
+/// lists, which is empty if there are no lists.
+///
+/// The intervals of all the lists are visited in increasing order of their
+/// starts through a heap holding the next interval of each list. As the
+/// intervals within a list are disjoint, each list has at most one interval
+/// covering the current start, and once all the lists have one, the
+/// intersection extends from the current start to the smallest of their
+/// ends. The coalesced inputs guarantee that the pieces are not adjacent.
+pub fn intersect_all_intervals<E: Copy + Integer>(
+    lists: &[&[ContiguousIntegerSet<E>]],
+) -> Vec<ContiguousIntegerSet<E>> {
+    let mut intersection = Vec::new();
+    if lists.is_empty() || lists.iter().any(|l| l.is_empty()) {
+        return intersection;
+    }
+    // (start, list index, interval index) of the next interval of each list
+    let mut next_starts: BinaryHeap<Reverse<(E, usize, usize)>> = lists
+        .iter()
+        .enumerate()
+        .map(|(k, l)| Reverse((l[0].get_start(), k, 0)))
+        .collect();
+    // the ends of the intervals covering the current start, one per list
+    let mut active_ends: BinaryHeap<Reverse<E>> = BinaryHeap::new();
+    while let Some(Reverse((start, k, i))) = next_starts.pop() {
+        while let Some(&Reverse(end)) = active_ends.peek() {
+            if end >= start {
+                break;
+            }
+            active_ends.pop();
+        }
+        active_ends.push(Reverse(lists[k][i].get_end()));
+        if active_ends.len() == lists.len() {
+            let Reverse(end) = *active_ends.peek().unwrap();
+            intersection.push(ContiguousIntegerSet::new(start, end));
+        }
+        if i + 1 < lists[k].len() {
+            next_starts.push(Reverse((lists[k][i + 1].get_start(), k, i + 1)));
+        }
+    }
+    intersection
+}
+
+/// Both `a` and `b` have to be sorted, coalesced and free of empty intervals.
+/// Returns the sorted and coalesced intervals of `a - b`. The neighbors of an
+/// interval of `b` are only computed when they fall inside an interval of
+/// `a`, so this cannot overflow at the type bounds either.
+pub fn difference_intervals<E: Copy + Integer>(
+    a: &[ContiguousIntegerSet<E>],
+    b: &[ContiguousIntegerSet<E>],
+) -> Vec<ContiguousIntegerSet<E>> {
+    let mut diff = Vec::with_capacity(a.len());
+    let mut j = 0;
+    for interval in a.iter() {
+        let end = interval.get_end();
+        let mut start = interval.get_start();
+        let mut exhausted = false;
+        while j < b.len() && b[j].get_end() < start {
+            j += 1;
+        }
+        let mut k = j;
+        while k < b.len() && b[k].get_start() <= end {
+            if b[k].get_start() > start {
+                diff.push(ContiguousIntegerSet::new(
+                    start,
+                    b[k].get_start() - E::one(),
+                ));
+            }
+            if b[k].get_end() >= end {
+                exhausted = true;
+                break;
+            }
+            start = b[k].get_end() + E::one();
+            k += 1;
+        }
+        if !exhausted {
+            diff.push(ContiguousIntegerSet::new(start, end));
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::{
+        contiguous_integer_set::ContiguousIntegerSet,
+        ordered_integer_set::OrderedIntegerSet,
+    };
+
+    use proptest::prelude::*;
+
+    use super::{
+        checked_len, checked_span, difference_intervals,
+        intersect_all_intervals, intersect_intervals, is_disjoint_intervals,
+        is_subset_intervals, union_intervals, CheckedSize, ComplementWithin,
+        IntersectAll, MergeDifference, MergeIntersect, SetRelations,
+        SymmetricDifference, Union,
+    };
+
+    #[test]
+    fn test_union() {
+        let a = OrderedIntegerSet::from_slice(&[[1, 3], [8, 10], [20, 25]]);
+        let b = OrderedIntegerSet::from_slice(&[[4, 5], [9, 12], [30, 31]]);
+        assert_eq!(
+            a.union(&b),
+            OrderedIntegerSet::from_slice(&[[1, 5], [8, 12], [20, 25], [
+                30, 31
+            ]])
+        );
+        assert_eq!(a.union(&OrderedIntegerSet::new()), a);
+        assert_eq!(
+            ContiguousIntegerSet::new(2, 4)
+                .union(&ContiguousIntegerSet::new(6, 7)),
+            OrderedIntegerSet::from_slice(&[[2, 4], [6, 7]])
+        );
+        assert_eq!(
+            a.union(&ContiguousIntegerSet::new(0, 21)),
+            OrderedIntegerSet::from_slice(&[[0, 25]])
+        );
+    }
+
+    #[test]
+    fn test_merge_intersect() {
+        let a = OrderedIntegerSet::from_slice(&[[1, 3], [8, 10], [20, 25]]);
+        let b = OrderedIntegerSet::from_slice(&[[3, 9], [22, 30]]);
+        assert_eq!(
+            a.merge_intersect(&b),
+            OrderedIntegerSet::from_slice(&[[3, 3], [8, 9], [22, 25]])
+        );
+        assert_eq!(
+            a.merge_intersect(&ContiguousIntegerSet::new(11, 19)),
+            OrderedIntegerSet::new()
+        );
+    }
+
+    #[test]
+    fn test_intersect_all() {
+        let sets = vec![
+            OrderedIntegerSet::from_slice(&[[1, 10], [15, 30], [40, 50]]),
+            OrderedIntegerSet::from_slice(&[[3, 20], [25, 45]]),
+            OrderedIntegerSet::from_slice(&[[0, 4], [8, 16], [28, 60]]),
+        ];
+        assert_eq!(
+            OrderedIntegerSet::intersect_all(&sets),
+            OrderedIntegerSet::from_slice(&[
+                [3, 4],
+                [8, 10],
+                [15, 16],
+                [28, 30],
+                [40, 45]
+            ])
+        );
+        assert_eq!(
+            OrderedIntegerSet::intersect_all(&sets),
+            sets[0].merge_intersect(&sets[1]).merge_intersect(&sets[2])
+        );
+        assert_eq!(OrderedIntegerSet::intersect_all(&sets[..1]), sets[0]);
+        assert_eq!(
+            OrderedIntegerSet::<i32>::intersect_all(&[]),
+            OrderedIntegerSet::new()
+        );
+        assert_eq!(
+            OrderedIntegerSet::intersect_all(&[
+                sets[0].clone(),
+                OrderedIntegerSet::new()
+            ]),
+            OrderedIntegerSet::new()
+        );
+        assert_eq!(
+            ContiguousIntegerSet::intersect_all(&[
+                ContiguousIntegerSet::new(0u8, 255),
+                ContiguousIntegerSet::new(200, 255),
+            ]),
+            OrderedIntegerSet::from_slice(&[[200, 255]])
+        );
+    }
+
+    #[test]
+    fn test_merge_difference() {
+        let a = OrderedIntegerSet::from_slice(&[[1, 10], [15, 20], [30, 40]]);
+        let b = OrderedIntegerSet::from_slice(&[[0, 2], [5, 6], [18, 32]]);
+        assert_eq!(
+            a.merge_difference(&b),
+            OrderedIntegerSet::from_slice(&[[3, 4], [7, 10], [15, 17], [
+                33, 40
+            ]])
+        );
+        assert_eq!(a.merge_difference(&b), a.clone() - &b);
+        assert_eq!(a.merge_difference(&a), OrderedIntegerSet::new());
+        assert_eq!(
+            a.merge_difference(&ContiguousIntegerSet::new(8, 35)),
+            OrderedIntegerSet::from_slice(&[[1, 7], [36, 40]])
+        );
+    }
+
+    #[test]
+    fn test_symmetric_difference() {
+        let a = OrderedIntegerSet::from_slice(&[[1, 5], [10, 15]]);
+        let b = OrderedIntegerSet::from_slice(&[[3, 12]]);
+        assert_eq!(
+            a.symmetric_difference(&b),
+            OrderedIntegerSet::from_slice(&[[1, 2], [6, 9], [13, 15]])
+        );
+        assert_eq!(a.symmetric_difference(&a), OrderedIntegerSet::new());
+    }
+
+    #[test]
+    fn test_set_relations() {
+        let a = OrderedIntegerSet::from_slice(&[[1, 5], [10, 15], [20, 20]]);
+        let b = OrderedIntegerSet::from_slice(&[[2, 4], [12, 15]]);
+        let c = OrderedIntegerSet::from_slice(&[[6, 9], [16, 19]]);
+        assert!(b.is_subset_of(&a));
+        assert!(a.is_superset_of(&b));
+        assert!(!a.is_subset_of(&b));
+        assert!(a.is_subset_of(&a));
+        assert!(OrderedIntegerSet::new().is_subset_of(&a));
+        assert!(!b.is_subset_of(&ContiguousIntegerSet::new(2, 14)));
+        assert!(b.is_subset_of(&ContiguousIntegerSet::new(2, 15)));
+        // 6 to 9 are not in a
+        assert!(!ContiguousIntegerSet::new(5, 10).is_subset_of(&a));
+
+        assert!(a.is_disjoint_from(&c));
+        assert!(!a.overlaps(&c));
+        assert!(a.overlaps(&b));
+        assert!(!b.is_disjoint_from(&a));
+        assert!(a.overlaps(&ContiguousIntegerSet::new(20, 30)));
+        assert!(OrderedIntegerSet::new().is_disjoint_from(&a));
+        assert!(ContiguousIntegerSet::new(0u8, 254)
+            .is_disjoint_from(&ContiguousIntegerSet::new(255, 255)));
+    }
+
+    #[test]
+    fn test_complement_within() {
+        let a = OrderedIntegerSet::from_slice(&[[0, 3], [8, 10], [20, 25]]);
+        assert_eq!(
+            a.complement_within(&ContiguousIntegerSet::new(2, 22)),
+            OrderedIntegerSet::from_slice(&[[4, 7], [11, 19]])
+        );
+        assert_eq!(
+            OrderedIntegerSet::new()
+                .complement_within(&ContiguousIntegerSet::new(5, 9)),
+            OrderedIntegerSet::from_slice(&[[5, 9]])
+        );
+        assert_eq!(
+            a.complement_within(&ContiguousIntegerSet::new(8, 10)),
+            OrderedIntegerSet::new()
+        );
+    }
+
+    #[test]
+    fn test_type_bounds() {
+        let a = OrderedIntegerSet::from_slice(&[[0u8, 0]]);
+        let b = OrderedIntegerSet::from_slice(&[[255u8, 255]]);
+        assert_eq!(
+            a.union(&b),
+            OrderedIntegerSet::from_slice(&[[0, 0], [255, 255]])
+        );
+        assert_eq!(
+            ContiguousIntegerSet::new(0u8, 254)
+                .union(&ContiguousIntegerSet::new(255, 255)),
+            OrderedIntegerSet::from_slice(&[[0, 255]])
+        );
+        assert_eq!(
+            ContiguousIntegerSet::new(0u8, 255).merge_difference(&b),
+            OrderedIntegerSet::from_slice(&[[0, 254]])
+        );
+        assert_eq!(
+            a.complement_within(&ContiguousIntegerSet::new(0, 255)),
+            OrderedIntegerSet::from_slice(&[[1, 255]])
+        );
+        let min =
+            OrderedIntegerSet::from_slice(&[[std::i32::MIN, std::i32::MIN]]);
+        let max =
+            OrderedIntegerSet::from_slice(&[[std::i32::MAX, std::i32::MAX]]);
+        assert_eq!(
+            min.union(&max),
+            OrderedIntegerSet::from_slice(&[[std::i32::MIN, std::i32::MIN], [
+                std::i32::MAX,
+                std::i32::MAX
+            ]])
+        );
+        assert_eq!(
+            ContiguousIntegerSet::new(std::i32::MIN, std::i32::MAX)
+                .checked_size(),
+            Some(1 << 32)
+        );
+        assert_eq!(
+            ContiguousIntegerSet::new(std::i64::MIN, std::i64::MAX)
+                .checked_size(),
+            None
+        );
+        assert_eq!(checked_span(std::i8::MIN, std::i8::MAX), Some(255));
+        assert_eq!(checked_len(-3i8, -3), Some(1));
+        assert_eq!(checked_len(std::u64::MAX - 1, std::u64::MAX), Some(2));
+    }
+
+    /// Interval endpoints biased towards the type bounds and zero
+    fn endpoint() -> impl Strategy<Value = i32> {
+        prop_oneof![
+            Just(std::i32::MIN),
+            Just(std::i32::MIN + 1),
+            Just(-1),
+            Just(0),
+            Just(std::i32::MAX - 1),
+            Just(std::i32::MAX),
+            any::<i32>(),
+        ]
+    }
+
+    fn interval_set() -> impl Strategy<Value = Vec<ContiguousIntegerSet<i32>>> {
+        prop::collection::vec((endpoint(), endpoint()), 0..6).prop_map(
+            |pairs| {
+                pairs.into_iter().fold(Vec::new(), |acc, (x, y)| {
+                    let interval = ContiguousIntegerSet::new(
+                        std::cmp::min(x, y),
+                        std::cmp::max(x, y),
+                    );
+                    union_intervals(&acc, &[interval])
+                })
+            },
+        )
+    }
+
+    fn contains(intervals: &[ContiguousIntegerSet<i32>], x: i32) -> bool {
+        intervals
+            .iter()
+            .any(|i| i.get_start() <= x && x <= i.get_end())
+    }
+
+    /// The endpoints of all the intervals and their neighbors, which is where
+    /// the results of the set operations can change
+    fn probes(
+        a: &[ContiguousIntegerSet<i32>],
+        b: &[ContiguousIntegerSet<i32>],
+    ) -> Vec<i32> {
+        let mut probes = vec![std::i32::MIN, 0, std::i32::MAX];
+        for i in a.iter().chain(b.iter()) {
+            for &x in [i.get_start(), i.get_end()].iter() {
+                probes.push(x);
+                probes.extend(x.checked_sub(1));
+                probes.extend(x.checked_add(1));
+            }
+        }
+        probes
+    }
+
+    fn is_coalesced(intervals: &[ContiguousIntegerSet<i32>]) -> bool {
+        intervals.iter().all(|i| i.get_start() <= i.get_end())
+            && intervals.windows(2).all(|w| {
+                w[0].get_end() < w[1].get_start()
+                    && w[1].get_start() - 1 > w[0].get_end()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_set_operations_match_membership(
+            a in interval_set(),
+            b in interval_set()
+        ) {
+            let union = union_intervals(&a, &b);
+            let intersection = intersect_intervals(&a, &b);
+            let difference = difference_intervals(&a, &b);
+            prop_assert!(is_coalesced(&union));
+            prop_assert!(is_coalesced(&intersection));
+            prop_assert!(is_coalesced(&difference));
+            for x in probes(&a, &b) {
+                let (in_a, in_b) = (contains(&a, x), contains(&b, x));
+                prop_assert_eq!(contains(&union, x), in_a || in_b);
+                prop_assert_eq!(contains(&intersection, x), in_a && in_b);
+                prop_assert_eq!(contains(&difference, x), in_a && !in_b);
+            }
+        }
+
+        #[test]
+        fn prop_set_relations_match_intersection(
+            a in interval_set(),
+            b in interval_set()
+        ) {
+            let intersection = intersect_intervals(&a, &b);
+            prop_assert_eq!(is_subset_intervals(&a, &b), intersection == a);
+            prop_assert_eq!(
+                is_disjoint_intervals(&a, &b),
+                intersection.is_empty()
+            );
+        }
+
+        #[test]
+        fn prop_intersect_all_matches_membership(
+            sets in prop::collection::vec(interval_set(), 1..6)
+        ) {
+            let lists: Vec<&[ContiguousIntegerSet<i32>]> =
+                sets.iter().map(|s| s.as_slice()).collect();
+            let intersection = intersect_all_intervals(&lists);
+            prop_assert!(is_coalesced(&intersection));
+            let folded = sets[1..].iter().fold(sets[0].clone(), |acc, s| {
+                intersect_intervals(&acc, s)
+            });
+            prop_assert_eq!(&intersection, &folded);
+            for x in sets.iter().flat_map(|s| probes(s, &[])) {
+                prop_assert_eq!(
+                    contains(&intersection, x),
+                    sets.iter().all(|s| contains(s, x))
+                );
+            }
+        }
+
+        #[test]
+        fn prop_checked_len_matches_wide_arithmetic(
+            x in endpoint(),
+            y in endpoint()
+        ) {
+            let (start, end) = (std::cmp::min(x, y), std::cmp::max(x, y));
+            let expected = end as i64 - start as i64 + 1;
+            prop_assert_eq!(checked_len(start, end), Some(expected as usize));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{FromPrimitive, Integer, ToPrimitive};
+
+use crate::{error::Error, integer_set::ops::IntervalList};
+
+const RLE_MAGIC: &[u8; 4] = b"SRLE";
+const RLE_VERSION: u8 = 1;
+
+/// A compact binary format for caching SNP index sets on disk, e.g. the
+/// jackknife blocks or the partitions, between runs and across processes.
+///
+/// The file starts with the magic bytes `SRLE`, a version byte and the number
+/// of runs. Each run is then stored as two LEB128 varints: the gap from the
+/// end of the previous run (the first run stores its start), and the run
+/// length minus one. A set of dense SNP ranges therefore takes a few bytes
+/// per range regardless of the number of SNPs.
+pub trait RunLengthEncoding: Sized {
+    fn encode_runs<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
+
+    fn decode_runs<R: Read>(reader: &mut R) -> Result<Self, Error>;
+
+    fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut writer = BufWriter::new(
+            OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .open(path)
+                .map_err(|io_error| Error::IO {
+                    why: format!("failed to create {}", path.display()),
+                    io_error,
+                })?,
+        );
+        self.encode_runs(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut reader =
+            BufReader::new(OpenOptions::new().read(true).open(path).map_err(
+                |io_error| Error::IO {
+                    why: format!("failed to open {}", path.display()),
+                    io_error,
+                },
+            )?);
+        Self::decode_runs(&mut reader)
+    }
+}
+
+impl<E> RunLengthEncoding for OrderedIntegerSet<E>
+where
+    E: Copy + FromPrimitive + Integer + ToPrimitive,
+{
+    fn encode_runs<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let intervals = self.interval_list();
+        writer.write_all(RLE_MAGIC)?;
+        writer.write_all(&[RLE_VERSION])?;
+        write_varint(writer, intervals.len() as u64)?;
+        let mut prev_end: Option<u64> = None;
+        for interval in intervals.iter() {
+            let start = to_u64(interval.get_start())?;
+            let end = to_u64(interval.get_end())?;
+            let gap = match prev_end {
+                None => start,
+                Some(prev_end) => start - prev_end - 1,
+            };
+            write_varint(writer, gap)?;
+            write_varint(writer, end - start)?;
+            prev_end = Some(end);
+        }
+        Ok(())
+    }
+
+    fn decode_runs<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != RLE_MAGIC {
+            return Err(Error::Generic(
+                "not a run-length encoded integer set".to_string(),
+            ));
+        }
+        let mut version = [0u8; 1];
+        reader.read_exact(&mut version)?;
+        if version[0] != RLE_VERSION {
+            return Err(Error::Generic(format!(
+                "unsupported run-length encoding version {}, expected {}",
+                version[0], RLE_VERSION
+            )));
+        }
+        let num_runs = read_varint(reader)?;
+        let mut intervals = Vec::new();
+        let mut prev_end: Option<u64> = None;
+        for _ in 0..num_runs {
+            let gap = read_varint(reader)?;
+            let len_minus_one = read_varint(reader)?;
+            let start = match prev_end {
+                None => Some(gap),
+                // a zero gap would make the run adjacent to the previous one
+                Some(_) if gap == 0 => None,
+                Some(prev_end) => {
+                    prev_end.checked_add(gap).and_then(|s| s.checked_add(1))
+                }
+            };
+            let (start, end) = match start {
+                Some(start) => match start.checked_add(len_minus_one) {
+                    Some(end) => (start, end),
+                    None => return Err(corrupt_runs_error()),
+                },
+                None => return Err(corrupt_runs_error()),
+            };
+            intervals.push(ContiguousIntegerSet::new(
+                from_u64(start)?,
+                from_u64(end)?,
+            ));
+            prev_end = Some(end);
+        }
+        Ok(
+            OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+                intervals,
+            ),
+        )
+    }
+}
+
+fn corrupt_runs_error() -> Error {
+    Error::Generic("corrupt run-length encoded integer set".to_string())
+}
+
+fn to_u64<E: ToPrimitive>(element: E) -> Result<u64, Error> {
+    element.to_u64().ok_or_else(|| {
+        Error::Generic(
+            "only non-negative elements can be run-length encoded".to_string(),
+        )
+    })
+}
+
+fn from_u64<E: FromPrimitive>(value: u64) -> Result<E, Error> {
+    E::from_u64(value).ok_or_else(|| {
+        Error::Generic(format!(
+            "run-length encoded element {} does not fit in the element type",
+            value
+        ))
+    })
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), Error> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(());
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    let mut byte = [0u8; 1];
+    for shift in (0..64).step_by(7) {
+        reader.read_exact(&mut byte)?;
+        let bits = (byte[0] & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return Err(corrupt_runs_error());
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(corrupt_runs_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use tempfile::NamedTempFile;
+
+    use super::RunLengthEncoding;
+
+    #[test]
+    fn test_encode_decode_runs() {
+        let set =
+            OrderedIntegerSet::from_slice(&[[3, 9], [11, 11], [300, 100_000]]);
+        let mut bytes = Vec::new();
+        set.encode_runs(&mut bytes).unwrap();
+        // magic, version, number of runs and two one-byte varints for each of
+        // the first two runs, two-byte gap and three-byte length for the last
+        assert_eq!(bytes.len(), 4 + 1 + 1 + 4 + 5);
+        assert_eq!(
+            OrderedIntegerSet::<usize>::decode_runs(&mut bytes.as_slice())
+                .unwrap(),
+            set
+        );
+
+        let empty = OrderedIntegerSet::<usize>::new();
+        let mut bytes = Vec::new();
+        empty.encode_runs(&mut bytes).unwrap();
+        assert_eq!(
+            OrderedIntegerSet::<usize>::decode_runs(&mut bytes.as_slice())
+                .unwrap(),
+            empty
+        );
+
+        let mut bytes = Vec::new();
+        OrderedIntegerSet::from_slice(&[[0u64, 300]])
+            .encode_runs(&mut bytes)
+            .unwrap();
+        assert!(OrderedIntegerSet::<u8>::decode_runs(&mut bytes.as_slice())
+            .is_err());
+        assert!(
+            OrderedIntegerSet::<usize>::decode_runs(&mut &b"SRLE"[..]).is_err()
+        );
+        assert!(OrderedIntegerSet::<usize>::decode_runs(
+            &mut &b"SRLE\x01\x02\x00\x00\x00\x00"[..]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_write_read_file() {
+        let file = NamedTempFile::new().unwrap();
+        let set = OrderedIntegerSet::from_slice(&[[0usize, 0], [2, 5], [
+            1 << 40,
+            (1 << 40) + 7,
+        ]]);
+        set.write_to(file.path()).unwrap();
+        assert_eq!(OrderedIntegerSet::read_from(file.path()).unwrap(), set);
+    }
+}
@@ -0,0 +1,437 @@
+use std::{fmt::Debug, iter::Sum};
+
+use math::{
+    sample::Sample,
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+};
+use num::{FromPrimitive, Integer, ToPrimitive};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    integer_set::{
+        collect::SortedCollecting, iter::Iter, ops::MergeDifference,
+        slice::SplitInto,
+    },
+    util::matrix_util::derive_seed,
+};
+
+/// How many elements to draw from each stratum
+#[derive(Clone, PartialEq, Debug)]
+pub enum StratumSampleSizes {
+    /// Draws this many elements in total, allocated proportionally to the
+    /// stratum sizes. Rounding remainders go to the strata with the largest
+    /// fractional allocations, so the counts add up to the total exactly.
+    Proportional(usize),
+    /// Draws the i-th count from the i-th stratum
+    Counts(Vec<usize>),
+}
+
+impl StratumSampleSizes {
+    pub fn counts(
+        &self,
+        stratum_sizes: &[usize],
+    ) -> Result<Vec<usize>, String> {
+        let counts = match self {
+            StratumSampleSizes::Proportional(total) => {
+                proportional_counts(*total, stratum_sizes)?
+            }
+            StratumSampleSizes::Counts(counts) => {
+                if counts.len() != stratum_sizes.len() {
+                    return Err(format!(
+                        "{} sample counts for {} strata",
+                        counts.len(),
+                        stratum_sizes.len()
+                    ));
+                }
+                counts.clone()
+            }
+        };
+        for (i, (&count, &size)) in
+            counts.iter().zip(stratum_sizes.iter()).enumerate()
+        {
+            if count > size {
+                return Err(format!(
+                    "cannot sample {} elements from stratum {} of size {}",
+                    count, i, size
+                ));
+            }
+        }
+        Ok(counts)
+    }
+}
+
+fn proportional_counts(
+    total: usize,
+    stratum_sizes: &[usize],
+) -> Result<Vec<usize>, String> {
+    let population: usize = stratum_sizes.iter().sum();
+    if total > population {
+        return Err(format!(
+            "cannot sample {} elements from {} elements in total",
+            total, population
+        ));
+    }
+    if population == 0 {
+        return Ok(vec![0; stratum_sizes.len()]);
+    }
+    // (count, remainder) of total * size / population for every stratum
+    let mut allocations: Vec<(usize, u128)> = stratum_sizes
+        .iter()
+        .map(|&size| {
+            let numerator = total as u128 * size as u128;
+            (
+                (numerator / population as u128) as usize,
+                numerator % population as u128,
+            )
+        })
+        .collect();
+    let allocated: usize = allocations.iter().map(|(count, _)| count).sum();
+    let mut order: Vec<usize> = (0..stratum_sizes.len()).collect();
+    order.sort_by(|&a, &b| allocations[b].1.cmp(&allocations[a].1));
+    for &i in order.iter().take(total - allocated) {
+        allocations[i].0 += 1;
+    }
+    Ok(allocations.into_iter().map(|(count, _)| count).collect())
+}
+
+/// Samples without replacement from each of the `strata` independently and
+/// returns the sampled subset of every stratum in the same order. If `seed`
+/// is `Some`, the `i`-th stratum is sampled from the stream
+/// `derive_seed(seed, i)`, so that the samples are reproducible.
+pub fn stratified_sample_without_replacement<E>(
+    strata: &[OrderedIntegerSet<E>],
+    sizes: &StratumSampleSizes,
+    seed: Option<u64>,
+) -> Result<Vec<OrderedIntegerSet<E>>, String>
+where
+    E: Copy + Debug + FromPrimitive + Integer + Sum + ToPrimitive, {
+    let stratum_sizes: Vec<usize> = strata.iter().map(|s| s.size()).collect();
+    let counts = sizes.counts(&stratum_sizes)?;
+    Ok(strata
+        .iter()
+        .zip(counts.into_iter())
+        .enumerate()
+        .map(|(i, (stratum, count))| match seed {
+            Some(seed) => reservoir_sample_with_rng(
+                stratum.iter(),
+                count,
+                &mut StdRng::seed_from_u64(derive_seed(seed, i as u64)),
+            ),
+            None => stratum.sample_subset_without_replacement(count).unwrap(),
+        })
+        .collect())
+}
+
+/// Samples `sample_size` elements uniformly without replacement from a stream
+/// of distinct integers in a single pass, keeping only the sample in memory
+/// (reservoir sampling). Returns every element if the stream is shorter.
+/// The sample is reproducible if `seed` is `Some`.
+pub fn reservoir_sample<E, I>(
+    iter: I,
+    sample_size: usize,
+    seed: Option<u64>,
+) -> OrderedIntegerSet<E>
+where
+    E: Copy + Integer + ToPrimitive,
+    I: IntoIterator<Item = E>, {
+    match seed {
+        Some(seed) => reservoir_sample_with_rng(
+            iter,
+            sample_size,
+            &mut StdRng::seed_from_u64(seed),
+        ),
+        None => reservoir_sample_with_rng(
+            iter,
+            sample_size,
+            &mut rand::thread_rng(),
+        ),
+    }
+}
+
+pub fn reservoir_sample_with_rng<E, I, R>(
+    iter: I,
+    sample_size: usize,
+    rng: &mut R,
+) -> OrderedIntegerSet<E>
+where
+    E: Copy + Integer + ToPrimitive,
+    I: IntoIterator<Item = E>,
+    R: Rng, {
+    let mut reservoir: Vec<E> = Vec::with_capacity(sample_size);
+    for (i, x) in iter.into_iter().enumerate() {
+        if i < sample_size {
+            reservoir.push(x);
+        } else {
+            let j = rng.gen_range(0, i + 1);
+            if j < sample_size {
+                reservoir[j] = x;
+            }
+        }
+    }
+    reservoir.sort();
+    OrderedIntegerSet::collect_from_sorted_iter(reservoir)
+}
+
+/// Yields the `(train, test)` pairs of k-fold cross-validation over a set.
+/// The test sets of the `k` folds partition the set and their sizes differ
+/// by at most one, and each train set is the rest of the set.
+pub struct KFold<E> {
+    set: OrderedIntegerSet<E>,
+    test_sets: Vec<OrderedIntegerSet<E>>,
+    next_fold: usize,
+}
+
+impl<E: Copy + FromPrimitive + Integer + ToPrimitive> KFold<E> {
+    /// Folds of consecutive elements, e.g. contiguous SNP blocks.
+    pub fn new(
+        set: OrderedIntegerSet<E>,
+        k: usize,
+    ) -> Result<KFold<E>, String> {
+        check_num_folds(&set, k)?;
+        let test_sets = set.split_into(k);
+        Ok(KFold {
+            set,
+            test_sets,
+            next_fold: 0,
+        })
+    }
+
+    /// Folds of elements assigned by a random permutation seeded with `seed`,
+    /// so that the folds are reproducible across runs.
+    pub fn shuffled(
+        set: OrderedIntegerSet<E>,
+        k: usize,
+        seed: u64,
+    ) -> Result<KFold<E>, String> {
+        check_num_folds(&set, k)?;
+        let mut elements: Vec<E> = set.iter().collect();
+        elements.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut fold_elements: Vec<Vec<E>> = vec![Vec::new(); k];
+        for (i, x) in elements.into_iter().enumerate() {
+            fold_elements[i % k].push(x);
+        }
+        let test_sets = fold_elements
+            .into_iter()
+            .map(|mut fold| {
+                fold.sort();
+                OrderedIntegerSet::collect_from_sorted_iter(fold)
+            })
+            .collect();
+        Ok(KFold {
+            set,
+            test_sets,
+            next_fold: 0,
+        })
+    }
+
+    pub fn num_folds(&self) -> usize {
+        self.test_sets.len()
+    }
+}
+
+fn check_num_folds<E>(
+    set: &OrderedIntegerSet<E>,
+    k: usize,
+) -> Result<(), String>
+where
+    E: Copy + Integer + ToPrimitive, {
+    let size = set.size();
+    if k < 2 || k > size {
+        Err(format!(
+            "the number of folds has to be between 2 and the set size {}, \
+            received {}",
+            size, k
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl<E: Copy + Integer + ToPrimitive> Iterator for KFold<E> {
+    type Item = (OrderedIntegerSet<E>, OrderedIntegerSet<E>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let test = self.test_sets.get(self.next_fold)?.clone();
+        self.next_fold += 1;
+        Some((self.set.merge_difference(&test), test))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.test_sets.len() - self.next_fold;
+        (remaining, Some(remaining))
+    }
+}
+
+/// Iterates over the leave-one-block-out subsets of a set partitioned into
+/// contiguous blocks of nearly equal sizes. Contiguous blocks of SNPs, as
+/// opposed to random subsets, keep the SNPs in LD with each other in the
+/// same block, so that the jackknife replicates are nearly independent.
+pub struct BlockJackknife<E> {
+    folds: KFold<E>,
+}
+
+impl<E: Copy + FromPrimitive + Integer + ToPrimitive> BlockJackknife<E> {
+    pub fn new(
+        set: OrderedIntegerSet<E>,
+        num_blocks: usize,
+    ) -> Result<BlockJackknife<E>, String> {
+        Ok(BlockJackknife {
+            folds: KFold::new(set, num_blocks)?,
+        })
+    }
+
+    /// The blocks in increasing order, the `i`-th of which is left out of the
+    /// `i`-th subset.
+    pub fn blocks(&self) -> &[OrderedIntegerSet<E>] {
+        &self.folds.test_sets
+    }
+}
+
+impl<E: Copy + Integer + ToPrimitive> Iterator for BlockJackknife<E> {
+    type Item = OrderedIntegerSet<E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.folds.next().map(|(rest, _)| rest)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.folds.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::{
+        set::{
+            ordered_integer_set::OrderedIntegerSet,
+            traits::{Finite, Intersect},
+        },
+        traits::ToIterator,
+    };
+
+    use super::{
+        reservoir_sample, stratified_sample_without_replacement,
+        BlockJackknife, KFold, StratumSampleSizes,
+    };
+    use crate::integer_set::ops::Union;
+
+    #[test]
+    fn test_counts() {
+        assert_eq!(
+            StratumSampleSizes::Proportional(10)
+                .counts(&[50, 30, 20])
+                .unwrap(),
+            vec![5, 3, 2]
+        );
+        assert_eq!(
+            StratumSampleSizes::Proportional(2)
+                .counts(&[1, 1, 1])
+                .unwrap()
+                .iter()
+                .sum::<usize>(),
+            2
+        );
+        assert!(StratumSampleSizes::Proportional(4).counts(&[1, 2]).is_err());
+        assert!(StratumSampleSizes::Counts(vec![1]).counts(&[1, 2]).is_err());
+        assert!(StratumSampleSizes::Counts(vec![2, 1])
+            .counts(&[1, 2])
+            .is_err());
+    }
+
+    #[test]
+    fn test_stratified_sample_without_replacement() {
+        let strata = vec![
+            OrderedIntegerSet::from_slice(&[[0usize, 99]]),
+            OrderedIntegerSet::from_slice(&[[200, 219], [300, 329]]),
+        ];
+        let sizes = StratumSampleSizes::Counts(vec![10, 25]);
+        let samples =
+            stratified_sample_without_replacement(&strata, &sizes, None)
+                .unwrap();
+        assert_eq!(samples[0].size(), 10);
+        assert_eq!(samples[1].size(), 25);
+        for (sample, stratum) in samples.iter().zip(strata.iter()) {
+            assert_eq!(sample.intersect(stratum).size(), sample.size());
+        }
+
+        let seeded = |seed| {
+            stratified_sample_without_replacement(&strata, &sizes, Some(seed))
+                .unwrap()
+        };
+        assert_eq!(seeded(3), seeded(3));
+        assert_eq!(seeded(3)[1].size(), 25);
+        assert_ne!(seeded(3), seeded(4));
+    }
+
+    #[test]
+    fn test_reservoir_sample() {
+        let sample =
+            reservoir_sample((0..1000usize).filter(|i| i % 3 == 0), 50, None);
+        assert_eq!(sample.size(), 50);
+        assert!(sample.to_iter().all(|i| i % 3 == 0 && i < 1000));
+        assert_eq!(
+            reservoir_sample(0..1000usize, 50, Some(1)),
+            reservoir_sample(0..1000usize, 50, Some(1))
+        );
+
+        assert_eq!(
+            reservoir_sample(vec![7usize, 3, 4, 5], 10, None),
+            OrderedIntegerSet::from_slice(&[[3, 5], [7, 7]])
+        );
+    }
+
+    #[test]
+    fn test_k_fold() {
+        let set = OrderedIntegerSet::from_slice(&[[1usize, 4], [10, 15]]);
+        let folds: Vec<_> = KFold::new(set.clone(), 3).unwrap().collect();
+        assert_eq!(folds.len(), 3);
+        assert_eq!(folds[0].1, OrderedIntegerSet::from_slice(&[[1, 4]]));
+        assert_eq!(folds[1].1, OrderedIntegerSet::from_slice(&[[10, 12]]));
+        assert_eq!(folds[2].1, OrderedIntegerSet::from_slice(&[[13, 15]]));
+        assert_eq!(
+            folds[1].0,
+            OrderedIntegerSet::from_slice(&[[1, 4], [13, 15]])
+        );
+
+        let shuffled: Vec<_> =
+            KFold::shuffled(set.clone(), 4, 7).unwrap().collect();
+        let union = shuffled
+            .iter()
+            .fold(OrderedIntegerSet::new(), |acc, (_, test)| acc.union(test));
+        assert_eq!(union, set);
+        for (train, test) in shuffled.iter() {
+            assert!(test.size() == 2 || test.size() == 3);
+            assert_eq!(train.size() + test.size(), set.size());
+            assert_eq!(train.intersect(test).size(), 0);
+        }
+        let repeated: Vec<_> =
+            KFold::shuffled(set.clone(), 4, 7).unwrap().collect();
+        assert_eq!(shuffled, repeated);
+
+        assert!(KFold::new(set.clone(), 1).is_err());
+        assert!(KFold::new(set, 11).is_err());
+    }
+
+    #[test]
+    fn test_block_jackknife() {
+        let set = OrderedIntegerSet::from_slice(&[[0usize, 9], [20, 29]]);
+        let jackknife = BlockJackknife::new(set.clone(), 4).unwrap();
+        assert_eq!(jackknife.blocks(), &[
+            OrderedIntegerSet::from_slice(&[[0, 4]]),
+            OrderedIntegerSet::from_slice(&[[5, 9]]),
+            OrderedIntegerSet::from_slice(&[[20, 24]]),
+            OrderedIntegerSet::from_slice(&[[25, 29]]),
+        ]);
+        let blocks = jackknife.blocks().to_vec();
+        let subsets: Vec<_> = jackknife.collect();
+        assert_eq!(subsets.len(), 4);
+        for (subset, block) in subsets.iter().zip(blocks.iter()) {
+            assert_eq!(subset.size(), 15);
+            assert_eq!(subset.union(block), set);
+        }
+        assert_eq!(
+            subsets[1],
+            OrderedIntegerSet::from_slice(&[[0, 4], [20, 29]])
+        );
+    }
+}
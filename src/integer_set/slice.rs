@@ -0,0 +1,233 @@
+use std::ops::{Bound, RangeBounds};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{FromPrimitive, Integer, ToPrimitive};
+
+use crate::integer_set::ops::{checked_len, CheckedSize, IntervalList};
+
+/// Slicing a set by the ranks of its elements in increasing order, with any
+/// of the std range types, e.g. `set.slice_range(..n)`, `set.slice_range(n..)`
+/// or `set.slice_range(a..=b)`.
+///
+/// The inherent `slice` in the math crate only accepts `Range<usize>`, and
+/// its `Slicing` trait cannot be implemented for the other range types
+/// outside of the math crate, hence the separate method name.
+pub trait RangeSlicing<E> {
+    /// Panics if the range starts after it ends or ends past the size of the
+    /// set, like slicing a std slice.
+    fn slice_range<R: RangeBounds<usize>>(
+        &self,
+        range: R,
+    ) -> OrderedIntegerSet<E>;
+}
+
+impl<E, S> RangeSlicing<E> for S
+where
+    E: Copy + FromPrimitive + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn slice_range<R: RangeBounds<usize>>(
+        &self,
+        range: R,
+    ) -> OrderedIntegerSet<E> {
+        let intervals = self.interval_list();
+        let interval_len = |interval: &ContiguousIntegerSet<E>| {
+            checked_len(interval.get_start(), interval.get_end())
+                .expect("the set is too large to be indexed by a usize")
+        };
+        let size: usize = intervals.iter().map(interval_len).sum();
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e + 1,
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => size,
+        };
+        assert!(
+            start <= end,
+            "slice index starts at {} but ends at {}",
+            start,
+            end
+        );
+        assert!(
+            end <= size,
+            "range end index {} out of range for a set of size {}",
+            end,
+            size
+        );
+        let mut sliced = Vec::new();
+        if start == end {
+            return OrderedIntegerSet::new();
+        }
+        // the rank of the first element of the current interval
+        let mut offset = 0;
+        for interval in intervals.iter() {
+            if offset >= end {
+                break;
+            }
+            let len = interval_len(interval);
+            if offset + len > start {
+                let first = start.saturating_sub(offset);
+                let last = std::cmp::min(end - offset, len) - 1;
+                sliced.push(ContiguousIntegerSet::new(
+                    interval.get_start() + E::from_usize(first).unwrap(),
+                    interval.get_start() + E::from_usize(last).unwrap(),
+                ));
+            }
+            offset += len;
+        }
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            sliced,
+        )
+    }
+}
+
+/// Splitting a set into consecutive parts of nearly equal sizes, e.g. SNP
+/// blocks for the block jackknife or ranges of SNPs for worker threads.
+pub trait SplitInto<E> {
+    /// Returns `k` sets in increasing order whose union is the set and whose
+    /// sizes differ by at most one, with the larger parts first. Some parts
+    /// are empty if the set has fewer than `k` elements. Panics if `k` is 0.
+    fn split_into(&self, k: usize) -> Vec<OrderedIntegerSet<E>>;
+}
+
+impl<E, S> SplitInto<E> for S
+where
+    E: Copy + FromPrimitive + Integer + ToPrimitive,
+    S: IntervalList<E>,
+{
+    fn split_into(&self, k: usize) -> Vec<OrderedIntegerSet<E>> {
+        assert!(k > 0, "cannot split a set into 0 parts");
+        let mut intervals = self.interval_list().iter();
+        let size = self
+            .checked_size()
+            .expect("the set is too large to be indexed by a usize");
+        // the part of the current interval not yet assigned to a part
+        let mut current =
+            intervals.next().map(|i| (i.get_start(), i.get_end()));
+        (0..k)
+            .map(|part_index| {
+                let mut remaining =
+                    size / k + if part_index < size % k { 1 } else { 0 };
+                let mut part = Vec::new();
+                while remaining > 0 {
+                    let (start, end) = current.unwrap();
+                    let len = checked_len(start, end).unwrap();
+                    if len <= remaining {
+                        part.push(ContiguousIntegerSet::new(start, end));
+                        remaining -= len;
+                        current = intervals
+                            .next()
+                            .map(|i| (i.get_start(), i.get_end()));
+                    } else {
+                        let last = start + E::from_usize(remaining - 1).unwrap();
+                        part.push(ContiguousIntegerSet::new(start, last));
+                        current = Some((last + E::one(), end));
+                        remaining = 0;
+                    }
+                }
+                OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+                    part,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::{
+        contiguous_integer_set::ContiguousIntegerSet,
+        ordered_integer_set::OrderedIntegerSet, traits::Finite,
+    };
+
+    use super::{RangeSlicing, SplitInto};
+    use crate::integer_set::ops::Union;
+
+    #[test]
+    fn test_slice_range() {
+        // elements 1 2 3 7 8 20 21 22 23
+        let set = OrderedIntegerSet::from_slice(&[[1, 3], [7, 8], [20, 23]]);
+        assert_eq!(
+            set.slice_range(..4),
+            OrderedIntegerSet::from_slice(&[[1, 3], [7, 7]])
+        );
+        assert_eq!(
+            set.slice_range(4..),
+            OrderedIntegerSet::from_slice(&[[8, 8], [20, 23]])
+        );
+        assert_eq!(
+            set.slice_range(2..=5),
+            OrderedIntegerSet::from_slice(&[[3, 3], [7, 8], [20, 20]])
+        );
+        assert_eq!(set.slice_range(..), set);
+        assert_eq!(set.slice_range(3..3), OrderedIntegerSet::new());
+        assert_eq!(set.slice_range(1..1), OrderedIntegerSet::new());
+        assert_eq!(set.slice_range(..=8), set);
+        assert_eq!(set.slice_range(0..2), set.slice(0..2));
+
+        let contiguous = ContiguousIntegerSet::new(10, 19);
+        assert_eq!(
+            contiguous.slice_range(..3),
+            OrderedIntegerSet::from_slice(&[[10, 12]])
+        );
+        assert_eq!(
+            contiguous.slice_range(7..),
+            OrderedIntegerSet::from_slice(&[[17, 19]])
+        );
+        assert_eq!(
+            contiguous.slice_range(2..=2),
+            OrderedIntegerSet::from_slice(&[[12, 12]])
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_slice_range_out_of_bounds() {
+        OrderedIntegerSet::from_slice(&[[1, 3]]).slice_range(..=3);
+    }
+
+    #[test]
+    fn test_split_into() {
+        // elements 1 2 3 7 8 20 21 22 23
+        let set = OrderedIntegerSet::from_slice(&[[1, 3], [7, 8], [20, 23]]);
+        assert_eq!(set.split_into(4), vec![
+            OrderedIntegerSet::from_slice(&[[1, 3]]),
+            OrderedIntegerSet::from_slice(&[[7, 8]]),
+            OrderedIntegerSet::from_slice(&[[20, 21]]),
+            OrderedIntegerSet::from_slice(&[[22, 23]]),
+        ]);
+        assert_eq!(set.split_into(2), vec![
+            OrderedIntegerSet::from_slice(&[[1, 3], [7, 8]]),
+            OrderedIntegerSet::from_slice(&[[20, 23]]),
+        ]);
+        assert_eq!(set.split_into(1), vec![set.clone()]);
+        for k in 1..12 {
+            let parts = set.split_into(k);
+            assert_eq!(parts.len(), k);
+            let sizes: Vec<usize> = parts.iter().map(|p| p.size()).collect();
+            assert_eq!(sizes.iter().sum::<usize>(), 9);
+            assert!(
+                sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1
+            );
+            let union = parts
+                .iter()
+                .fold(OrderedIntegerSet::new(), |acc, p| acc.union(p));
+            assert_eq!(union, set);
+        }
+        assert_eq!(ContiguousIntegerSet::new(0, 4).split_into(2), vec![
+            OrderedIntegerSet::from_slice(&[[0, 2]]),
+            OrderedIntegerSet::from_slice(&[[3, 4]]),
+        ]);
+        assert_eq!(OrderedIntegerSet::<usize>::new().split_into(2), vec![
+            OrderedIntegerSet::new(),
+            OrderedIntegerSet::new()
+        ]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeSet, fmt::Debug};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use num::{Bounded, Integer, ToPrimitive};
+use proptest::{arbitrary::Arbitrary, collection, prelude::*};
+
+use crate::integer_set::{iter::Iter, ops::union_intervals};
+
+/// Elements biased towards zero and the bounds of `E`, where the arithmetic
+/// on the interval endpoints is most likely to overflow.
+pub fn element_near_bounds<E>() -> impl Strategy<Value = E>
+where
+    E: Arbitrary + Bounded + Copy + Debug + Integer + 'static, {
+    prop_oneof![
+        Just(E::min_value()),
+        Just(E::min_value() + E::one()),
+        Just(E::zero()),
+        Just(E::max_value() - E::one()),
+        Just(E::max_value()),
+        any::<E>(),
+    ]
+}
+
+/// Arbitrary non-empty intervals with endpoints drawn from `element`.
+pub fn contiguous_integer_set<E, S>(
+    element: S,
+) -> impl Strategy<Value = ContiguousIntegerSet<E>>
+where
+    E: Copy + Debug + Integer,
+    S: Strategy<Value = E> + Clone, {
+    (element.clone(), element).prop_map(|(x, y)| {
+        ContiguousIntegerSet::new(std::cmp::min(x, y), std::cmp::max(x, y))
+    })
+}
+
+/// Arbitrary sets made of the union of up to `max_num_intervals` intervals
+/// with endpoints drawn from `element`, so the intervals of the set satisfy
+/// `Validate` regardless of how they overlap.
+pub fn ordered_integer_set<E, S>(
+    element: S,
+    max_num_intervals: usize,
+) -> impl Strategy<Value = OrderedIntegerSet<E>>
+where
+    E: Copy + Debug + Integer + ToPrimitive,
+    S: Strategy<Value = E> + Clone, {
+    collection::vec(contiguous_integer_set(element), 0..=max_num_intervals)
+        .prop_map(|intervals| {
+            OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+                intervals.into_iter().fold(Vec::new(), |acc, interval| {
+                    union_intervals(&acc, &[interval])
+                }),
+            )
+        })
+}
+
+/// The naive model to check the set arithmetic against. Only suitable for
+/// small sets, as every element is stored separately.
+pub fn to_btree_set<E>(set: &OrderedIntegerSet<E>) -> BTreeSet<E>
+where
+    E: Copy + Integer + Ord + ToPrimitive, {
+    set.iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{element_near_bounds, ordered_integer_set, to_btree_set};
+    use crate::integer_set::{
+        ops::{MergeDifference, MergeIntersect, Union},
+        validate::Validate,
+    };
+
+    proptest! {
+        #[test]
+        fn prop_set_arithmetic_matches_btree_set(
+            a in ordered_integer_set(0..200i32, 8),
+            b in ordered_integer_set(0..200i32, 8)
+        ) {
+            let (model_a, model_b) = (to_btree_set(&a), to_btree_set(&b));
+            let union = a.union(&b);
+            let intersection = a.merge_intersect(&b);
+            let difference = a.merge_difference(&b);
+            prop_assert!(union.validate().is_ok());
+            prop_assert!(intersection.validate().is_ok());
+            prop_assert!(difference.validate().is_ok());
+            prop_assert_eq!(
+                to_btree_set(&union),
+                model_a.union(&model_b).cloned().collect()
+            );
+            prop_assert_eq!(
+                to_btree_set(&intersection),
+                model_a.intersection(&model_b).cloned().collect()
+            );
+            prop_assert_eq!(
+                to_btree_set(&difference),
+                model_a.difference(&model_b).cloned().collect()
+            );
+        }
+
+        #[test]
+        fn prop_sets_near_bounds_are_valid(
+            a in ordered_integer_set(element_near_bounds::<i8>(), 6),
+            b in ordered_integer_set(element_near_bounds::<u8>(), 6)
+        ) {
+            prop_assert!(a.validate().is_ok());
+            prop_assert!(b.validate().is_ok());
+            prop_assert!(a.union(&a).validate().is_ok());
+            prop_assert!(b.merge_difference(&b).validate().is_ok());
+        }
+    }
+}
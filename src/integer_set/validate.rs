@@ -0,0 +1,89 @@
+use std::fmt::Debug;
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use num::{Integer, ToPrimitive};
+
+use crate::integer_set::ops::IntervalList;
+
+/// Checks the invariants that the set algebra in this crate relies on, which
+/// `from_ordered_coalesced_contiguous_integer_sets` in the math crate takes
+/// for granted: every interval is non-empty, and the intervals are sorted and
+/// neither overlap nor touch each other.
+pub trait Validate {
+    /// Describes the first violated invariant, if any.
+    fn validate(&self) -> Result<(), String>;
+}
+
+impl<E> Validate for OrderedIntegerSet<E>
+where
+    E: Copy + Debug + Integer + ToPrimitive,
+{
+    fn validate(&self) -> Result<(), String> {
+        let intervals = self.interval_list();
+        for (i, interval) in intervals.iter().enumerate() {
+            if interval.get_start() > interval.get_end() {
+                return Err(format!("interval {} {:?} is empty", i, interval));
+            }
+        }
+        for (i, pair) in intervals.windows(2).enumerate() {
+            let (prev, next) = (&pair[0], &pair[1]);
+            if next.get_start() <= prev.get_end() {
+                return Err(format!(
+                    "interval {} {:?} does not start after the end of \
+                    interval {} {:?}",
+                    i + 1,
+                    next,
+                    i,
+                    prev
+                ));
+            }
+            // next.get_start() - 1 cannot overflow once it exceeds
+            // prev.get_end()
+            if next.get_start() - E::one() == prev.get_end() {
+                return Err(format!(
+                    "interval {} {:?} is adjacent to interval {} {:?} and \
+                    should have been coalesced",
+                    i + 1,
+                    next,
+                    i,
+                    prev
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::{
+        contiguous_integer_set::ContiguousIntegerSet,
+        ordered_integer_set::OrderedIntegerSet,
+    };
+
+    use super::Validate;
+
+    fn from_intervals(intervals: &[[i32; 2]]) -> OrderedIntegerSet<i32> {
+        OrderedIntegerSet::from_ordered_coalesced_contiguous_integer_sets(
+            intervals
+                .iter()
+                .map(|&[start, end]| ContiguousIntegerSet::new(start, end))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(from_intervals(&[]).validate().is_ok());
+        assert!(from_intervals(&[[1, 3], [5, 5], [8, 20]])
+            .validate()
+            .is_ok());
+        assert!(from_intervals(&[[std::i32::MIN, 0], [2, std::i32::MAX]])
+            .validate()
+            .is_ok());
+        assert!(from_intervals(&[[1, 3], [5, 4]]).validate().is_err());
+        assert!(from_intervals(&[[5, 8], [1, 3]]).validate().is_err());
+        assert!(from_intervals(&[[1, 5], [3, 8]]).validate().is_err());
+        assert!(from_intervals(&[[1, 3], [4, 8]]).validate().is_err());
+    }
+}
@@ -16,9 +16,17 @@ use math::{
     },
 };
 use num::{FromPrimitive, Integer, ToPrimitive};
+use rand::Rng;
 use rayon::prelude::*;
 
-use crate::error::Error;
+use crate::{
+    error::Error,
+    integer_set::{
+        iter::Iter, ops::MergeDifference, sample::reservoir_sample_with_rng,
+        slice::RangeSlicing,
+    },
+    resampling::Replicate,
+};
 use std::{fmt::Debug, iter::Sum};
 
 pub struct Jackknife<C> {
@@ -61,12 +69,23 @@ impl<C: Send> AdditiveJackknife<C> {
     where
         F: Fn(usize, &Partition<T>) -> C + Send + Sync,
         C: for<'a> Add<&'a C, Output = C> + Clone, {
-        let additive_components: Vec<C> = jackknife_partitions
-            .iter()
-            .into_par_iter()
-            .enumerate()
-            .map(|(i, p)| op(i, &p))
-            .collect();
+        AdditiveJackknife::from_components(
+            jackknife_partitions
+                .iter()
+                .into_par_iter()
+                .enumerate()
+                .map(|(i, p)| op(i, &p))
+                .collect(),
+        )
+    }
+
+    /// `additive_components[k]` is the component of the k-th jackknife
+    /// partition
+    pub fn from_components(
+        additive_components: Vec<C>,
+    ) -> AdditiveJackknife<C>
+    where
+        C: for<'a> Add<&'a C, Output = C> + Clone, {
         let sum = match additive_components.first() {
             Some(first) => Some(
                 additive_components
@@ -121,6 +140,75 @@ impl<C: Send> AdditiveJackknife<C> {
         }
     }
 
+    /// The sum of the components on the `replicate`. `scale(c, w)` has to
+    /// return the component `c` multiplied by the block weight `w`.
+    pub fn replicate_sum<'a, F>(
+        &'a self,
+        replicate: &Replicate,
+        scale: F,
+    ) -> Result<C, String>
+    where
+        &'a C: Sub<Output = C> + Deref,
+        C: for<'b> Add<&'b C, Output = C> + Clone,
+        F: Fn(&C, f64) -> C, {
+        match replicate {
+            Replicate::Full => self.sum_minus_component_or_sum(None),
+            Replicate::LeaveOneOut(k) => {
+                self.sum_minus_component_or_sum(Some(*k))
+            }
+            Replicate::Weighted(weights) => {
+                if weights.len() != self.additive_components.len() {
+                    return Err(format!(
+                        "{} replicate weights for {} components",
+                        weights.len(),
+                        self.additive_components.len()
+                    ));
+                }
+                weights
+                    .iter()
+                    .zip(self.additive_components.iter())
+                    .filter(|(&w, _)| w != 0.)
+                    .map(|(&w, c)| scale(c, w))
+                    .fold(None, |acc: Option<C>, x| match acc {
+                        None => Some(x),
+                        Some(acc) => Some(acc + &x),
+                    })
+                    .ok_or_else(|| {
+                        "all the replicate weights are zero".to_string()
+                    })
+            }
+        }
+    }
+
+    /// Combines the corresponding components of the two jackknives with `op`,
+    /// which has to distribute over the addition of the components, e.g.
+    /// concatenating matrices along an axis.
+    pub fn combine<F>(
+        &self,
+        other: &AdditiveJackknife<C>,
+        op: F,
+    ) -> AdditiveJackknife<C>
+    where
+        F: Fn(&C, &C) -> C, {
+        assert_eq!(
+            self.additive_components.len(),
+            other.additive_components.len(),
+            "cannot combine jackknives with different numbers of components"
+        );
+        AdditiveJackknife {
+            additive_components: self
+                .additive_components
+                .iter()
+                .zip(other.additive_components.iter())
+                .map(|(a, b)| op(a, b))
+                .collect(),
+            sum: match (&self.sum, &other.sum) {
+                (Some(a), Some(b)) => Some(op(a, b)),
+                _ => None,
+            },
+        }
+    }
+
     fn get_sum_minus_component_filepath(
         file_prefix: &str,
         component_index: usize,
@@ -194,10 +282,47 @@ impl<T: Copy + Debug + FromPrimitive + Integer + Sum + ToPrimitive>
     /// partitions and combines the i-th partition from each set into a
     /// single Jackknife partition, for all i
     pub fn from_integer_set(
-        mut integer_sets: Vec<OrderedIntegerSet<T>>,
+        integer_sets: Vec<OrderedIntegerSet<T>>,
         num_partitions: usize,
         randomize: bool,
     ) -> JackknifePartitions<T> {
+        if randomize {
+            JackknifePartitions::partition_with(
+                integer_sets,
+                num_partitions,
+                |s, size| s.sample_subset_without_replacement(size).unwrap(),
+            )
+        } else {
+            JackknifePartitions::partition_with(
+                integer_sets,
+                num_partitions,
+                |s, size| s.slice_range(..size),
+            )
+        }
+    }
+
+    /// The random partitions of `from_integer_set` with `randomize`, drawn
+    /// with the `rng` so that they can be reproduced from a seed
+    pub fn from_integer_set_with_rng<R: Rng>(
+        integer_sets: Vec<OrderedIntegerSet<T>>,
+        num_partitions: usize,
+        rng: &mut R,
+    ) -> JackknifePartitions<T> {
+        JackknifePartitions::partition_with(
+            integer_sets,
+            num_partitions,
+            |s, size| reservoir_sample_with_rng(s.iter(), size, rng),
+        )
+    }
+
+    /// `take(s, size)` takes a subset of `size` elements from the set `s`
+    fn partition_with<F>(
+        mut integer_sets: Vec<OrderedIntegerSet<T>>,
+        num_partitions: usize,
+        mut take: F,
+    ) -> JackknifePartitions<T>
+    where
+        F: FnMut(&OrderedIntegerSet<T>, usize) -> OrderedIntegerSet<T>, {
         let partition_size: Vec<usize> = integer_sets
             .iter()
             .map(|s| s.size() / num_partitions)
@@ -206,15 +331,8 @@ impl<T: Copy + Debug + FromPrimitive + Integer + Sum + ToPrimitive>
         for _ in 0..num_partitions - 1 {
             let mut merged_partition = Vec::new();
             for (i, s) in integer_sets.iter_mut().enumerate() {
-                let p;
-                if randomize {
-                    p = s
-                        .sample_subset_without_replacement(partition_size[i])
-                        .unwrap();
-                } else {
-                    p = s.slice(0..partition_size[i]);
-                }
-                *s -= &p;
+                let p = take(s, partition_size[i]);
+                *s = s.merge_difference(&p);
                 merged_partition.append(&mut p.into_intervals());
             }
             partitions.push(OrderedIntegerSet::from(merged_partition));
@@ -260,9 +378,10 @@ impl<T: Copy + Debug + FromPrimitive + Integer + Sum + ToPrimitive> Index<usize>
 #[cfg(test)]
 mod tests {
     use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
-    use ndarray::{Array, Ix2};
+    use ndarray::{Array, Axis, Ix2};
+    use rand::{rngs::StdRng, SeedableRng};
 
-    use crate::jackknife::AdditiveJackknife;
+    use crate::{jackknife::AdditiveJackknife, resampling::Replicate};
 
     use super::JackknifePartitions;
     use math::traits::ToIterator;
@@ -288,7 +407,7 @@ mod tests {
             }
         }
         let config = JackknifePartitions::from_integer_set(
-            vec![integer_set],
+            vec![integer_set.clone()],
             num_partitions,
             false,
         );
@@ -299,6 +418,22 @@ mod tests {
                 assert_eq!(p.size(), size / num_partitions);
             }
         }
+
+        let seeded = |seed: u64| {
+            JackknifePartitions::from_integer_set_with_rng(
+                vec![integer_set.clone()],
+                num_partitions,
+                &mut StdRng::seed_from_u64(seed),
+            )
+        };
+        let (config, same_seed) = (seeded(3), seeded(3));
+        assert_eq!(config.union().size(), size);
+        for i in 0..num_partitions {
+            if i < num_partitions - 1 {
+                assert_eq!(config[i].size(), size / num_partitions);
+            }
+            assert_eq!(config[i], same_seed[i]);
+        }
     }
 
     #[test]
@@ -330,4 +465,44 @@ mod tests {
             assert_eq!(decoded, jackknife.sum_minus_component(i));
         }
     }
+
+    #[test]
+    fn test_combine_jackknife() {
+        let config = JackknifePartitions::from_integer_set(
+            vec![OrderedIntegerSet::from_slice(&[[0, 9]])],
+            5,
+            false,
+        );
+        let get_jackknife = |cols: usize| {
+            AdditiveJackknife::from_op_over_jackknife_partitions(
+                &config,
+                |k, _| Array::<f32, Ix2>::ones((2, cols)) * k as f32,
+            )
+        };
+        let combined = get_jackknife(1).combine(&get_jackknife(3), |a, b| {
+            ndarray::stack(Axis(1), &[a.view(), b.view()]).unwrap()
+        });
+        assert_eq!(combined.additive_components.len(), 5);
+        let scale = |c: &Array<f32, Ix2>, w: f64| c * w as f32;
+        assert_eq!(
+            combined.replicate_sum(&Replicate::Full, scale).unwrap(),
+            Array::<f32, Ix2>::ones((2, 4)) * 10.
+        );
+        assert_eq!(
+            combined
+                .replicate_sum(
+                    &Replicate::Weighted(vec![0., 0., 1., 0., 2.]),
+                    scale
+                )
+                .unwrap(),
+            Array::<f32, Ix2>::ones((2, 4)) * 10.
+        );
+        assert!(combined
+            .replicate_sum(&Replicate::Weighted(vec![0.; 5]), scale)
+            .is_err());
+        assert_eq!(
+            combined.sum_minus_component(2),
+            Array::<f32, Ix2>::ones((2, 4)) * 8.
+        );
+    }
 }
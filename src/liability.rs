@@ -0,0 +1,207 @@
+//! Case-control phenotypes and the transformation of their heritability from
+//! the observed 0/1 scale to the liability scale.
+//!
+//! Following Lee et al. (2011), the heritability `h2_o` estimated on the
+//! observed scale of a sample with the case fraction `P` is on the liability
+//! scale
+//!
+//! `h2_l = h2_o * K^2 (1 - K)^2 / (P (1 - P) z^2)`
+//!
+//! for the population prevalence `K`, where `z` is the standard normal
+//! density at the liability threshold `t = Phi^{-1}(1 - K)`.
+
+use std::fmt;
+
+use ndarray::{ArrayBase, Data, Ix1};
+use serde::Serialize;
+
+use crate::{
+    confidence_interval::normal_quantile,
+    error::Error,
+    partitioned_jackknife_estimates::{
+        Estimate, PartitionedJackknifeEstimates,
+    },
+};
+
+const NUM_DISPLAY_DECIMALS: usize = 5;
+
+/// Whether every value of the `pheno` is 0 or 1 and both occur
+pub fn is_case_control<S: Data<Elem = f32>>(pheno: &ArrayBase<S, Ix1>) -> bool {
+    pheno.iter().all(|&y| y == 0. || y == 1.)
+        && pheno.iter().any(|&y| y == 1.)
+        && pheno.iter().any(|&y| y == 0.)
+}
+
+/// The fraction of cases in a case-control `pheno`
+pub fn get_case_fraction<S: Data<Elem = f32>>(
+    pheno: &ArrayBase<S, Ix1>,
+) -> Result<f64, Error> {
+    if !is_case_control(pheno) {
+        return Err(Error::Generic(
+            "a case-control phenotype has to be coded 0 for the controls \
+            and 1 for the cases, with at least one of each"
+                .to_string(),
+        ));
+    }
+    Ok(pheno.iter().filter(|&&y| y == 1.).count() as f64 / pheno.len() as f64)
+}
+
+/// The factor that converts a heritability on the observed scale of a sample
+/// with the `case_fraction` to the liability scale, for the population
+/// `prevalence`
+pub fn get_liability_scale_factor(
+    prevalence: f64,
+    case_fraction: f64,
+) -> Result<f64, Error> {
+    if !(prevalence > 0. && prevalence < 1.) {
+        return Err(Error::Generic(format!(
+            "the prevalence has to be in (0, 1), received {}",
+            prevalence
+        )));
+    }
+    if !(case_fraction > 0. && case_fraction < 1.) {
+        return Err(Error::Generic(format!(
+            "the case fraction has to be in (0, 1), received {}",
+            case_fraction
+        )));
+    }
+    let t = normal_quantile(1. - prevalence);
+    let z = (-t * t / 2.).exp() / (2. * std::f64::consts::PI).sqrt();
+    let k = prevalence * (1. - prevalence);
+    Ok(k * k / (case_fraction * (1. - case_fraction) * z * z))
+}
+
+fn scale_estimate(estimate: &Estimate<f64>, factor: f64) -> Estimate<f64> {
+    Estimate::new(
+        estimate.bias_corrected_estimate * factor,
+        estimate.jackknife_mean * factor,
+        estimate.point_estimate_without_jackknife * factor,
+        estimate.standard_error * factor,
+    )
+}
+
+/// The heritability of a case-control phenotype on both the observed and the
+/// liability scale. The conversion is a constant factor, so the standard
+/// errors are scaled by it as well.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct LiabilityScaleEstimates {
+    pub prevalence: f64,
+    pub case_fraction: f64,
+    pub scale_factor: f64,
+    /// The total heritability on the observed scale
+    pub observed_h2: Estimate<f64>,
+    /// The total heritability on the liability scale
+    pub liability_h2: Estimate<f64>,
+    /// The estimate of each partition on the liability scale
+    pub liability_partition_estimates: Vec<Estimate<f64>>,
+}
+
+impl LiabilityScaleEstimates {
+    /// `estimates` are on the observed scale of a sample with the
+    /// `case_fraction`
+    pub fn new(
+        estimates: &PartitionedJackknifeEstimates,
+        prevalence: f64,
+        case_fraction: f64,
+    ) -> Result<LiabilityScaleEstimates, Error> {
+        let scale_factor =
+            get_liability_scale_factor(prevalence, case_fraction)?;
+        let observed_h2 = match estimates.sum_estimate {
+            Some(sum_estimate) => sum_estimate,
+            None => estimates.partition_estimates.first().cloned().ok_or_else(
+                || {
+                    Error::Generic(
+                        "no estimates to convert to the liability scale"
+                            .to_string(),
+                    )
+                },
+            )?,
+        };
+        Ok(LiabilityScaleEstimates {
+            prevalence,
+            case_fraction,
+            scale_factor,
+            observed_h2,
+            liability_h2: scale_estimate(&observed_h2, scale_factor),
+            liability_partition_estimates: estimates
+                .partition_estimates
+                .iter()
+                .map(|e| scale_estimate(e, scale_factor))
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for LiabilityScaleEstimates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "prevalence: {}\n\
+            case fraction: {:.*}\n\
+            observed scale h2: {:.*} (SE {:.*})\n\
+            liability scale h2: {:.*} (SE {:.*})",
+            self.prevalence,
+            NUM_DISPLAY_DECIMALS,
+            self.case_fraction,
+            NUM_DISPLAY_DECIMALS,
+            self.observed_h2.point_estimate_without_jackknife,
+            NUM_DISPLAY_DECIMALS,
+            self.observed_h2.standard_error,
+            NUM_DISPLAY_DECIMALS,
+            self.liability_h2.point_estimate_without_jackknife,
+            NUM_DISPLAY_DECIMALS,
+            self.liability_h2.standard_error
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::{
+        get_case_fraction, get_liability_scale_factor, is_case_control,
+        LiabilityScaleEstimates,
+    };
+    use crate::partitioned_jackknife_estimates::PartitionedJackknifeEstimates;
+
+    #[test]
+    fn test_liability_scale() {
+        assert!(is_case_control(&array![0f32, 1., 1., 0.]));
+        assert!(!is_case_control(&array![0f32, 0., 0.]));
+        assert!(!is_case_control(&array![1f32, 2., 1.]));
+        assert_eq!(get_case_fraction(&array![0f32, 1., 1., 1.]).unwrap(), 0.75);
+        assert!(get_case_fraction(&array![0.5f32, 1.]).is_err());
+
+        // K = 0.5 puts the threshold at 0 with the density 1 / sqrt(2 pi)
+        let factor = get_liability_scale_factor(0.5, 0.5).unwrap();
+        assert!((factor - std::f64::consts::PI / 2.).abs() < 1e-8);
+        // rarer diseases ascertained at half cases shrink the estimates
+        assert!(get_liability_scale_factor(0.01, 0.5).unwrap() < 1.);
+        assert!(get_liability_scale_factor(0., 0.5).is_err());
+        assert!(get_liability_scale_factor(0.1, 1.).is_err());
+
+        let est = PartitionedJackknifeEstimates::from_jackknife_estimates(
+            &vec![0.1, 0.2],
+            &vec![vec![0.1, 0.2], vec![0.12, 0.18], vec![0.08, 0.22]],
+            Some(vec!["a".to_string(), "b".to_string()]),
+            None,
+        )
+        .unwrap();
+        let liability = LiabilityScaleEstimates::new(&est, 0.5, 0.5).unwrap();
+        let h2_o = liability.observed_h2.point_estimate_without_jackknife;
+        assert!((h2_o - 0.3).abs() < 1e-12);
+        assert!(
+            (liability.liability_h2.point_estimate_without_jackknife
+                - h2_o * factor)
+                .abs()
+                < 1e-12
+        );
+        assert!(
+            (liability.liability_partition_estimates[1].standard_error
+                - est.partition_estimates[1].standard_error * factor)
+                .abs()
+                < 1e-12
+        );
+    }
+}
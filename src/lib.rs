@@ -1,8 +1,46 @@
+pub mod accumulation;
+pub mod checkpoint;
+pub mod compute;
+pub mod confidence_interval;
+pub mod covariate;
+pub mod environment;
 pub mod error;
+pub mod estimator_builder;
+pub mod exact;
+pub mod fixed_snps;
+pub mod genetic_correlation;
+pub mod genotype_sketch;
+pub mod gpu;
+pub mod grm;
+pub mod gxg_pair_exclusion;
+pub mod he_regression;
+pub mod heritability_estimate;
 pub mod heritability_estimator;
+pub mod incremental;
+pub mod integer_set;
 pub mod jackknife;
+pub mod liability;
+pub mod linear_operator;
+pub mod maf_bins;
 pub mod matrix_ops;
+pub mod missing_genotype;
+pub mod model_spec;
+pub mod nnls;
+pub mod parametric_bootstrap;
 pub mod partitioned_jackknife_estimates;
+pub mod progress;
+pub mod reml;
+pub mod resampling;
+pub mod sex_chrom;
 pub mod simulation;
+pub mod snp_chunk_filter;
+pub mod snp_overlap;
+pub mod snp_qc;
+pub mod snp_weights;
+pub mod spectral;
+pub mod stochastic;
 pub mod trace_estimator;
 pub mod util;
+pub mod warning;
+pub mod workspace;
+pub mod xty_cache;
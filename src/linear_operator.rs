@@ -0,0 +1,341 @@
+//! The kernels as matrix-free linear operators on the people. A kernel is
+//! only ever applied to a batch of columns at a time, so that a streamed
+//! kernel passes over the genotypes once per batch, and the trace estimators
+//! and the conjugate gradient solves of REML work on any kernel, or any sum
+//! of kernels, through the same `LinearOperator::apply`.
+
+use math::{
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+    stats::n_choose_2,
+};
+use ndarray::{Array, Ix2};
+
+use crate::{
+    grm::Grm,
+    snp_weights::SnpWeights,
+    stochastic::{g_gt_dot, GenotypeSource},
+    trace_estimator::gxg_kernel_dot_matrix,
+};
+
+/// A symmetric `dim x dim` matrix available only through its products
+pub trait LinearOperator {
+    fn dim(&self) -> usize;
+
+    /// The product with every column of `rhs`, which has `dim` rows
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2>;
+}
+
+impl<'a, A: LinearOperator + ?Sized> LinearOperator for &'a A {
+    fn dim(&self) -> usize {
+        (**self).dim()
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        (**self).apply(rhs)
+    }
+}
+
+/// A dense symmetric matrix, e.g. a kernel formed explicitly for a small
+/// cohort
+impl LinearOperator for Array<f32, Ix2> {
+    fn dim(&self) -> usize {
+        self.shape()[0]
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        self.dot(rhs)
+    }
+}
+
+impl LinearOperator for Grm {
+    fn dim(&self) -> usize {
+        self.num_people()
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        self.dot(&rhs.mapv(|x| x as f64)).mapv(|x| x as f32)
+    }
+}
+
+/// The GRM `X X^T / m` of the standardized genotypes `X` of the SNPs in
+/// `snp_range`, or `X W X^T / sum_i w_i` with `snp_weights`, streamed from
+/// the genotypes on every product
+pub struct StreamedGrm<'a, G: GenotypeSource> {
+    geno: &'a G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&'a SnpWeights>,
+    num_snps_per_chunk: Option<usize>,
+    num_snps: f64,
+}
+
+impl<'a, G: GenotypeSource> StreamedGrm<'a, G> {
+    pub fn new(
+        geno: &'a G,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        snp_weights: Option<&'a SnpWeights>,
+        num_snps_per_chunk: Option<usize>,
+    ) -> StreamedGrm<'a, G> {
+        let num_snps = match snp_weights {
+            Some(w) => w
+                .get_sqrt_weights(snp_range.as_ref())
+                .iter()
+                .map(|&s| (s * s) as f64)
+                .sum(),
+            None => match &snp_range {
+                Some(range) => range.size() as f64,
+                None => geno.num_snps() as f64,
+            },
+        };
+        StreamedGrm {
+            geno,
+            snp_range,
+            snp_weights,
+            num_snps_per_chunk,
+            num_snps,
+        }
+    }
+
+    /// The normalizer of the kernel, i.e. the number of SNPs, or the sum of
+    /// the weights with `snp_weights`
+    pub fn num_snps(&self) -> f64 {
+        self.num_snps
+    }
+}
+
+impl<'a, G: GenotypeSource> LinearOperator for StreamedGrm<'a, G> {
+    fn dim(&self) -> usize {
+        self.geno.num_people()
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        g_gt_dot(
+            self.geno,
+            self.snp_range.clone(),
+            self.snp_weights,
+            rhs,
+            self.num_snps_per_chunk,
+        ) / self.num_snps as f32
+    }
+}
+
+/// The dominance GRM over the dominance SNPs of a bed, which follow the
+/// additive SNPs as given by `get_additive_and_dominance_snps`. The
+/// dominance genotypes are standardized like the additive ones, so the
+/// kernel is the GRM of the dominance SNPs.
+pub struct DominanceKernel<'a, G: GenotypeSource> {
+    grm: StreamedGrm<'a, G>,
+}
+
+impl<'a, G: GenotypeSource> DominanceKernel<'a, G> {
+    pub fn new(
+        geno: &'a G,
+        dominance_snps: OrderedIntegerSet<usize>,
+        num_snps_per_chunk: Option<usize>,
+    ) -> Result<DominanceKernel<'a, G>, String> {
+        if dominance_snps.size() == 0 {
+            return Err("the dominance kernel has no SNPs".to_string());
+        }
+        Ok(DominanceKernel {
+            grm: StreamedGrm::new(
+                geno,
+                Some(dominance_snps),
+                None,
+                num_snps_per_chunk,
+            ),
+        })
+    }
+}
+
+impl<'a, G: GenotypeSource> LinearOperator for DominanceKernel<'a, G> {
+    fn dim(&self) -> usize {
+        self.grm.dim()
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        self.grm.apply(rhs)
+    }
+}
+
+/// The GxG kernel of the pairwise products of the standardized columns of
+/// `gxg_basis`, normalized by the number of pairs, applied exactly without
+/// forming the pairs
+pub struct GxgKernel<'a> {
+    gxg_basis: &'a Array<f32, Ix2>,
+    num_pairs: f64,
+}
+
+impl<'a> GxgKernel<'a> {
+    pub fn new(gxg_basis: &'a Array<f32, Ix2>) -> GxgKernel<'a> {
+        GxgKernel {
+            gxg_basis,
+            num_pairs: n_choose_2(gxg_basis.dim().1) as f64,
+        }
+    }
+
+    pub fn num_pairs(&self) -> f64 {
+        self.num_pairs
+    }
+}
+
+impl<'a> LinearOperator for GxgKernel<'a> {
+    fn dim(&self) -> usize {
+        self.gxg_basis.dim().0
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        gxg_kernel_dot_matrix(self.gxg_basis, rhs, None, None)
+            / self.num_pairs as f32
+    }
+}
+
+/// A user-defined symmetric `dim x dim` kernel given by a closure computing
+/// its product with a batch of columns, e.g. an epistatic kernel over
+/// annotations, so that any trace estimator on a `LinearOperator` applies to
+/// it without a bespoke implementation in saber
+pub struct MatvecOperator<F: Fn(&Array<f32, Ix2>) -> Array<f32, Ix2>> {
+    matvec: F,
+    dim: usize,
+}
+
+impl<F: Fn(&Array<f32, Ix2>) -> Array<f32, Ix2>> MatvecOperator<F> {
+    pub fn new(matvec: F, dim: usize) -> MatvecOperator<F> {
+        MatvecOperator {
+            matvec,
+            dim,
+        }
+    }
+}
+
+impl<F: Fn(&Array<f32, Ix2>) -> Array<f32, Ix2>> LinearOperator
+    for MatvecOperator<F>
+{
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        (self.matvec)(rhs)
+    }
+}
+
+/// `sum_k w_k K_k + w_0 I` for the kernels `K_k`, e.g. the covariance
+/// `V = sum_k sigma_k^2 K_k + sigma_e^2 I` of the REML iterations, in which
+/// each kernel is applied to the whole batch once
+pub struct KernelSum<'a> {
+    terms: Vec<(f32, &'a dyn LinearOperator)>,
+    identity_weight: f32,
+}
+
+impl<'a> KernelSum<'a> {
+    pub fn new(
+        terms: Vec<(f32, &'a dyn LinearOperator)>,
+        identity_weight: f32,
+    ) -> Result<KernelSum<'a>, String> {
+        if let Some((_, first)) = terms.first() {
+            if let Some((_, k)) =
+                terms.iter().find(|(_, k)| k.dim() != first.dim())
+            {
+                return Err(format!(
+                    "cannot sum kernels of dimensions {} and {}",
+                    first.dim(),
+                    k.dim()
+                ));
+            }
+        }
+        Ok(KernelSum {
+            terms,
+            identity_weight,
+        })
+    }
+}
+
+impl<'a> LinearOperator for KernelSum<'a> {
+    fn dim(&self) -> usize {
+        match self.terms.first() {
+            Some((_, k)) => k.dim(),
+            None => 0,
+        }
+    }
+
+    fn apply(&self, rhs: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+        let mut product = rhs * self.identity_weight;
+        for (w, k) in self.terms.iter() {
+            product.scaled_add(*w, &k.apply(rhs));
+        }
+        product
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{Array, Axis, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Uniform;
+
+    use super::{
+        DominanceKernel, GxgKernel, KernelSum, LinearOperator, MatvecOperator,
+        StreamedGrm,
+    };
+    use crate::util::matrix_util::normalize_matrix_columns_inplace;
+
+    #[test]
+    fn test_linear_operators() {
+        let geno =
+            Array::random((20, 6), Uniform::from(0..3)).mapv(|g: u8| g as f32);
+        let rhs = Array::random((20, 3), Uniform::new(-1f32, 1.));
+        let snp_range = OrderedIntegerSet::from_slice(&[[0, 3]]);
+        let mut x = geno.select(Axis(1), &[0, 1, 2, 3]);
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k: Array<f32, Ix2> = x.dot(&x.t()) / 4.;
+
+        let grm = StreamedGrm::new(&geno, Some(snp_range), None, Some(3));
+        assert_eq!(grm.dim(), 20);
+        assert_eq!(grm.num_snps(), 4.);
+        let diff = grm.apply(&rhs) - k.apply(&rhs);
+        assert!(diff.iter().all(|d| d.abs() < 1e-4));
+
+        let dominance = DominanceKernel::new(
+            &geno,
+            OrderedIntegerSet::from_slice(&[[4, 5]]),
+            None,
+        )
+        .unwrap();
+        let mut d = geno.select(Axis(1), &[4, 5]);
+        normalize_matrix_columns_inplace(&mut d, 0);
+        let diff = dominance.apply(&rhs) - d.dot(&d.t().dot(&rhs)) / 2.;
+        assert!(diff.iter().all(|d| d.abs() < 1e-4));
+        assert!(DominanceKernel::new(&geno, OrderedIntegerSet::new(), None)
+            .is_err());
+
+        let gxg = GxgKernel::new(&x);
+        assert_eq!(gxg.num_pairs(), 6.);
+
+        let matvec =
+            MatvecOperator::new(|rhs: &Array<f32, Ix2>| k.dot(rhs), 20);
+        assert_eq!(matvec.dim(), 20);
+        assert_eq!(matvec.apply(&rhs), k.apply(&rhs));
+
+        let sum = KernelSum::new(
+            vec![
+                (2., &grm as &dyn LinearOperator),
+                (0.5, &gxg as &dyn LinearOperator),
+            ],
+            3.,
+        )
+        .unwrap();
+        assert_eq!(sum.dim(), 20);
+        let diff = sum.apply(&rhs)
+            - (k.apply(&rhs) * 2. + gxg.apply(&rhs) * 0.5 + &rhs * 3.);
+        assert!(diff.iter().all(|d| d.abs() < 1e-4));
+        let small = Array::<f32, Ix2>::eye(3);
+        assert!(KernelSum::new(
+            vec![
+                (1., &grm as &dyn LinearOperator),
+                (1., &small as &dyn LinearOperator),
+            ],
+            0.
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Partitioning the SNPs into bins of their minor allele frequency (MAF), so
+//! that every bin is fit as a variance component of its own as in GREML-MS.
+
+use std::{fmt, str::FromStr};
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+
+use crate::integer_set::filter::Retain;
+
+/// The largest possible minor allele frequency, closing the last bin
+pub const MAX_MAF: f32 = 0.5;
+
+/// Consecutive MAF bins `[0, b_1), [b_1, b_2), ..., [b_k, 0.5]` given by their
+/// interior boundaries `b_1 < ... < b_k`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MafBins {
+    boundaries: Vec<f32>,
+}
+
+impl MafBins {
+    pub fn new(boundaries: Vec<f32>) -> Result<MafBins, String> {
+        if boundaries.is_empty() {
+            return Err("expected at least one MAF bin boundary".to_string());
+        }
+        if let Some(b) = boundaries.iter().find(|&&b| !(b > 0. && b < MAX_MAF))
+        {
+            return Err(format!(
+                "the MAF bin boundaries have to be in (0, {}), received {}",
+                MAX_MAF, b
+            ));
+        }
+        if boundaries.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(format!(
+                "the MAF bin boundaries have to be increasing, received {:?}",
+                boundaries
+            ));
+        }
+        Ok(MafBins {
+            boundaries,
+        })
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.boundaries.len() + 1
+    }
+
+    /// The lower and upper bound of the `i`-th bin, where only the last bin
+    /// includes its upper bound
+    pub fn get_bounds(&self, i: usize) -> (f32, f32) {
+        let lower = if i == 0 { 0. } else { self.boundaries[i - 1] };
+        let upper = self.boundaries.get(i).cloned().unwrap_or(MAX_MAF);
+        (lower, upper)
+    }
+
+    /// The name of the `i`-th bin, e.g. `maf[0.01,0.05)`
+    pub fn get_bin_name(&self, i: usize) -> String {
+        let (lower, upper) = self.get_bounds(i);
+        let closing = if i + 1 == self.num_bins() { ']' } else { ')' };
+        format!("maf[{},{}{}", lower, upper, closing)
+    }
+
+    /// Splits `snps` into the bins by the minor allele frequency `mafs[i]` of
+    /// every SNP `i`, leaving out the empty bins. Each bin is named by
+    /// `get_bin_name`.
+    pub fn partition(
+        &self,
+        snps: &OrderedIntegerSet<usize>,
+        mafs: &[f32],
+    ) -> Vec<(String, OrderedIntegerSet<usize>)> {
+        (0..self.num_bins())
+            .map(|b| {
+                let (lower, upper) = self.get_bounds(b);
+                let is_last = b + 1 == self.num_bins();
+                let bin = snps.retain(|i| {
+                    mafs[i] >= lower
+                        && (mafs[i] < upper || is_last && mafs[i] <= upper)
+                });
+                (self.get_bin_name(b), bin)
+            })
+            .filter(|(_, bin)| bin.size() > 0)
+            .collect()
+    }
+}
+
+impl FromStr for MafBins {
+    type Err = String;
+
+    /// Parses comma-separated boundaries such as `0.01,0.05,0.1`
+    fn from_str(s: &str) -> Result<MafBins, String> {
+        MafBins::new(
+            s.split(',')
+                .map(|b| {
+                    b.trim().parse::<f32>().map_err(|why| {
+                        format!("invalid MAF bin boundary {}: {}", b, why)
+                    })
+                })
+                .collect::<Result<Vec<f32>, String>>()?,
+        )
+    }
+}
+
+impl fmt::Display for MafBins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> =
+            (0..self.num_bins()).map(|i| self.get_bin_name(i)).collect();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+
+    use super::MafBins;
+
+    #[test]
+    fn test_maf_bins() {
+        let bins: MafBins = "0.01, 0.05,0.2".parse().unwrap();
+        assert_eq!(bins.num_bins(), 4);
+        assert_eq!(bins.get_bounds(0), (0., 0.01));
+        assert_eq!(bins.get_bounds(3), (0.2, 0.5));
+        assert_eq!(bins.get_bin_name(1), "maf[0.01,0.05)");
+        assert_eq!(bins.get_bin_name(3), "maf[0.2,0.5]");
+
+        let mafs = [0.005, 0.3, 0.01, 0.5, 0.02, 0.049, 0.25, 0.001];
+        let snps = OrderedIntegerSet::from_slice(&[[0, 6]]);
+        assert_eq!(bins.partition(&snps, &mafs), vec![
+            (
+                "maf[0,0.01)".to_string(),
+                OrderedIntegerSet::from_slice(&[[0, 0]])
+            ),
+            (
+                "maf[0.01,0.05)".to_string(),
+                OrderedIntegerSet::from_slice(&[[2, 2], [4, 5]])
+            ),
+            (
+                "maf[0.2,0.5]".to_string(),
+                OrderedIntegerSet::from_slice(&[[1, 1], [3, 3], [6, 6]])
+            ),
+        ]);
+
+        assert!("".parse::<MafBins>().is_err());
+        assert!("0.05,0.01".parse::<MafBins>().is_err());
+        assert!("0.01,0.5".parse::<MafBins>().is_err());
+        assert!("0.01,x".parse::<MafBins>().is_err());
+    }
+}
@@ -8,7 +8,7 @@ use math::{
         sum_of_squares, sum_of_squares_f32,
     },
 };
-use ndarray::{iter, s, Array, Axis, Dim, Ix1, Ix2};
+use ndarray::{iter, s, Array, ArrayBase, Axis, Data, Dim, Ix1, Ix2};
 use ndarray_parallel::prelude::*;
 use rayon::prelude::*;
 
@@ -360,10 +360,13 @@ pub fn pheno_g_pheno_from_pheno_matrix(
         .collect()
 }
 
-pub fn sum_of_column_wise_inner_product(
-    arr1: &Array<f32, Ix2>,
-    arr2: &Array<f32, Ix2>,
-) -> f32 {
+pub fn sum_of_column_wise_inner_product<S1, S2>(
+    arr1: &ArrayBase<S1, Ix2>,
+    arr2: &ArrayBase<S2, Ix2>,
+) -> f32
+where
+    S1: Data<Elem = f32>,
+    S2: Data<Elem = f32> + Sync, {
     arr1.axis_iter(Axis(1))
         .into_par_iter()
         .enumerate()
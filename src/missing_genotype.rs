@@ -0,0 +1,313 @@
+//! The handling of the missing genotypes, i.e. the values other than 0, 1
+//! and 2, when the SNP chunks are standardized. The policy is applied to
+//! every chunk streamed from the genotypes, so that the traces and the
+//! `y^T K y` terms of an estimation all see the same genotypes.
+
+use std::{fmt, str::FromStr};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Axis, Ix2};
+
+use crate::{
+    integer_set::{collect::SortedCollecting, iter::Iter},
+    stochastic::GenotypeSource,
+};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MissingGenotypePolicy {
+    /// a missing genotype is replaced by the mean of the called genotypes of
+    /// its SNP, so that it is zero after the centering
+    MeanImpute,
+    /// the people with a missing genotype in any of the SNPs of the
+    /// estimation are left out of it
+    DropPeople,
+    /// the estimation fails if any genotype is missing
+    Fail,
+}
+
+impl Default for MissingGenotypePolicy {
+    fn default() -> MissingGenotypePolicy {
+        MissingGenotypePolicy::MeanImpute
+    }
+}
+
+impl FromStr for MissingGenotypePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MissingGenotypePolicy, String> {
+        match s {
+            "mean-impute" => Ok(MissingGenotypePolicy::MeanImpute),
+            "drop-people" => Ok(MissingGenotypePolicy::DropPeople),
+            "fail" => Ok(MissingGenotypePolicy::Fail),
+            _ => Err(format!(
+                "unrecognized missing genotype policy {}, expected one of \
+                mean-impute, drop-people and fail",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MissingGenotypePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MissingGenotypePolicy::MeanImpute => write!(f, "mean-impute"),
+            MissingGenotypePolicy::DropPeople => write!(f, "drop-people"),
+            MissingGenotypePolicy::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+#[inline]
+pub fn is_missing_genotype(g: f32) -> bool {
+    !(g == 0. || g == 1. || g == 2.)
+}
+
+/// Replaces the missing genotypes of every SNP column by the mean of its
+/// called genotypes. A column without a called genotype is set to zero.
+pub fn impute_missing_with_mean_inplace(snp_chunk: &mut Array<f32, Ix2>) {
+    for mut col in snp_chunk.axis_iter_mut(Axis(1)) {
+        let (sum, num_called) = col
+            .iter()
+            .filter(|&&g| !is_missing_genotype(g))
+            .fold((0f64, 0usize), |(sum, n), &g| (sum + g as f64, n + 1));
+        if num_called == col.len() {
+            continue;
+        }
+        let mean = if num_called == 0 {
+            0.
+        } else {
+            (sum / num_called as f64) as f32
+        };
+        col.iter_mut()
+            .filter(|g| is_missing_genotype(**g))
+            .for_each(|g| *g = mean);
+    }
+}
+
+/// The row indices of the people with a missing genotype in any of the SNPs
+/// in `snp_range`, or all the SNPs if `None`
+pub fn get_people_with_missing_genotypes<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    num_snps_per_chunk: usize,
+) -> OrderedIntegerSet<usize> {
+    let num_people = geno.num_people();
+    let has_missing = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk,
+        || vec![false; num_people],
+        |mut acc, _, snp_chunk| {
+            for (i, row) in snp_chunk.axis_iter(Axis(0)).enumerate() {
+                if !acc[i] {
+                    acc[i] = row.iter().any(|&g| is_missing_genotype(g));
+                }
+            }
+            acc
+        },
+        |mut a, b| {
+            a.iter_mut().zip(b.iter()).for_each(|(x, y)| *x |= y);
+            a
+        },
+    );
+    OrderedIntegerSet::collect_from_sorted_iter(
+        has_missing
+            .into_iter()
+            .enumerate()
+            .filter(|(_, missing)| *missing)
+            .map(|(i, _)| i),
+    )
+}
+
+/// Applies the `policy` to the people of `geno` over the SNPs in
+/// `snp_range`. Returns the row indices of the people to keep if the policy
+/// drops anyone, and an error under `MissingGenotypePolicy::Fail` if any
+/// genotype is missing.
+pub fn resolve_missing_genotypes<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    policy: MissingGenotypePolicy,
+    num_snps_per_chunk: usize,
+) -> Result<Option<Vec<usize>>, String> {
+    if policy == MissingGenotypePolicy::MeanImpute {
+        return Ok(None);
+    }
+    let missing: Vec<usize> =
+        get_people_with_missing_genotypes(geno, snp_range, num_snps_per_chunk)
+            .iter()
+            .collect();
+    if missing.is_empty() {
+        return Ok(None);
+    }
+    match policy {
+        MissingGenotypePolicy::Fail => Err(format!(
+            "{} of the {} people have missing genotypes, e.g. the person in \
+            row {}",
+            missing.len(),
+            geno.num_people(),
+            missing[0]
+        )),
+        _ => {
+            if missing.len() == geno.num_people() {
+                return Err(format!(
+                    "all the {} people have missing genotypes",
+                    missing.len()
+                ));
+            }
+            let mut missing = missing.into_iter().peekable();
+            Ok(Some(
+                (0..geno.num_people())
+                    .filter(|&i| {
+                        if missing.peek() == Some(&i) {
+                            missing.next();
+                            false
+                        } else {
+                            true
+                        }
+                    })
+                    .collect(),
+            ))
+        }
+    }
+}
+
+/// The genotypes of `geno` with the missing genotypes of every streamed
+/// chunk imputed by the SNP means, before the chunk reaches the
+/// standardization. The chunks of genotypes without missing values are
+/// passed through as they are.
+pub struct MeanImputed<'a, G: GenotypeSource> {
+    geno: &'a G,
+}
+
+impl<'a, G: GenotypeSource> MeanImputed<'a, G> {
+    pub fn new(geno: &'a G) -> MeanImputed<'a, G> {
+        MeanImputed {
+            geno,
+        }
+    }
+}
+
+impl<'a, G: GenotypeSource> GenotypeSource for MeanImputed<'a, G> {
+    fn num_people(&self) -> usize {
+        self.geno.num_people()
+    }
+
+    fn num_snps(&self) -> usize {
+        self.geno.num_snps()
+    }
+
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync, {
+        self.geno.fold_snp_chunks(
+            snp_range,
+            chunk_size,
+            identity,
+            |acc, rank, mut snp_chunk| {
+                impute_missing_with_mean_inplace(&mut snp_chunk);
+                fold(acc, rank, snp_chunk)
+            },
+            reduce,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::array;
+
+    use super::{
+        get_people_with_missing_genotypes, impute_missing_with_mean_inplace,
+        resolve_missing_genotypes, MeanImputed, MissingGenotypePolicy,
+    };
+    use crate::stochastic::{tr_k, GenotypeSource};
+
+    #[test]
+    fn test_missing_genotypes() {
+        let geno =
+            array![[0f32, 2., 9.], [1., 9., 9.], [2., 0., 9.], [1., 1., 9.]];
+        let mut imputed = geno.clone();
+        impute_missing_with_mean_inplace(&mut imputed);
+        assert_eq!(imputed, array![
+            [0f32, 2., 0.],
+            [1., 1., 0.],
+            [2., 0., 0.],
+            [1., 1., 0.]
+        ]);
+        assert_eq!(
+            MeanImputed::new(&geno).fold_snp_chunks(
+                None,
+                2,
+                || 0f32,
+                |acc, _, chunk| acc + chunk.sum(),
+                |a, b| a + b
+            ),
+            imputed.sum()
+        );
+        // the imputed genotypes are zero after the standardization
+        assert!(tr_k(&MeanImputed::new(&geno), None, Some(2))
+            .unwrap()
+            .point_estimate_without_jackknife
+            .is_finite());
+
+        assert_eq!(
+            get_people_with_missing_genotypes(&geno, None, 2),
+            OrderedIntegerSet::from_slice(&[[0, 3]])
+        );
+        let snp_range = Some(OrderedIntegerSet::from_slice(&[[0, 1]]));
+        assert_eq!(
+            get_people_with_missing_genotypes(&geno, snp_range.clone(), 1),
+            OrderedIntegerSet::from_slice(&[[1, 1]])
+        );
+        assert_eq!(
+            resolve_missing_genotypes(
+                &geno,
+                snp_range.clone(),
+                MissingGenotypePolicy::DropPeople,
+                1
+            ),
+            Ok(Some(vec![0, 2, 3]))
+        );
+        assert_eq!(
+            resolve_missing_genotypes(
+                &geno,
+                snp_range.clone(),
+                MissingGenotypePolicy::MeanImpute,
+                1
+            ),
+            Ok(None)
+        );
+        assert!(resolve_missing_genotypes(
+            &geno,
+            snp_range,
+            MissingGenotypePolicy::Fail,
+            1
+        )
+        .is_err());
+        assert_eq!(
+            resolve_missing_genotypes(
+                &geno,
+                Some(OrderedIntegerSet::from_slice(&[[0, 0]])),
+                MissingGenotypePolicy::Fail,
+                1
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            "drop-people".parse::<MissingGenotypePolicy>(),
+            Ok(MissingGenotypePolicy::DropPeople)
+        );
+        assert!("drop".parse::<MissingGenotypePolicy>().is_err());
+    }
+}
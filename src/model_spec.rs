@@ -0,0 +1,625 @@
+use std::{collections::HashMap, fmt, fs::read_to_string, str::FromStr};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+
+use crate::integer_set::{
+    filter::Retain,
+    ops::{IntervalList, MergeIntersect},
+};
+
+pub const DEFAULT_RESPONSE_NAME: &str = "y";
+
+/// The kinds of variance components, written as `G`, `D`, `GxG` and `E` in a
+/// model specification.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ComponentKind {
+    Additive,
+    Dominance,
+    Gxg,
+    Noise,
+}
+
+impl ComponentKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComponentKind::Additive => "G",
+            ComponentKind::Dominance => "D",
+            ComponentKind::Gxg => "GxG",
+            ComponentKind::Noise => "E",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<ComponentKind, String> {
+        match name {
+            "G" => Ok(ComponentKind::Additive),
+            "D" => Ok(ComponentKind::Dominance),
+            "GxG" => Ok(ComponentKind::Gxg),
+            "E" => Ok(ComponentKind::Noise),
+            other => Err(format!(
+                "unknown component {}, expected one of G, D, GxG and E",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn holds(&self, lhs: f32, rhs: f32) -> bool {
+        match self {
+            Comparison::Less => lhs < rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
+
+/// The SNPs forming a component
+#[derive(Clone, PartialEq, Debug)]
+pub enum SnpSelector {
+    /// every SNP available to the component, written as `all`
+    All,
+    /// the SNPs whose minor allele frequency satisfies the bound, e.g.
+    /// `maf<0.05`
+    Maf {
+        comparison: Comparison,
+        threshold: f32,
+    },
+    /// a partition from the partition file, e.g. `partition=coding`
+    Partition(String),
+    /// a SNP set known to the estimator, e.g. `le` for the LE SNPs of GxG
+    Named(String),
+}
+
+impl SnpSelector {
+    /// The subset of `candidates` picked by the selector. `mafs` holds the
+    /// minor allele frequency of every SNP and is only read by the MAF
+    /// selectors. `partitions` are the named SNP partitions, e.g. from a
+    /// partition file. Named sets have to be resolved by the caller.
+    pub fn select(
+        &self,
+        candidates: &OrderedIntegerSet<usize>,
+        mafs: Option<&[f32]>,
+        partitions: &HashMap<String, OrderedIntegerSet<usize>>,
+    ) -> Result<OrderedIntegerSet<usize>, String> {
+        match self {
+            SnpSelector::All => Ok(candidates.clone()),
+            SnpSelector::Maf {
+                comparison,
+                threshold,
+            } => {
+                let mafs = mafs.ok_or_else(|| {
+                    format!("{} requires the minor allele frequencies", self)
+                })?;
+                Ok(candidates.retain(|i| comparison.holds(mafs[i], *threshold)))
+            }
+            SnpSelector::Partition(name) => match partitions.get(name) {
+                Some(partition) => Ok(candidates.merge_intersect(partition)),
+                None => Err(format!(
+                    "partition {} is not in the partition file",
+                    name
+                )),
+            },
+            SnpSelector::Named(name) => Err(format!(
+                "unknown SNP set {}, expected all, maf<THRESHOLD or \
+                partition=NAME",
+                name
+            )),
+        }
+    }
+
+    fn parse(arg: &str) -> Result<SnpSelector, String> {
+        if arg == "all" {
+            return Ok(SnpSelector::All);
+        }
+        if arg.starts_with("maf") {
+            let bound = arg["maf".len()..].trim();
+            let (comparison, threshold) = if bound.starts_with("<=") {
+                (Comparison::LessOrEqual, &bound[2..])
+            } else if bound.starts_with(">=") {
+                (Comparison::GreaterOrEqual, &bound[2..])
+            } else if bound.starts_with('<') {
+                (Comparison::Less, &bound[1..])
+            } else if bound.starts_with('>') {
+                (Comparison::Greater, &bound[1..])
+            } else {
+                return Err(format!(
+                    "expected a MAF bound of the form maf<0.05, received {}",
+                    arg
+                ));
+            };
+            let threshold = threshold.trim().parse::<f32>().map_err(|why| {
+                format!("invalid MAF threshold in {}: {}", arg, why)
+            })?;
+            if !(threshold >= 0. && threshold <= 0.5) {
+                return Err(format!(
+                    "the MAF threshold in {} has to be in [0, 0.5]",
+                    arg
+                ));
+            }
+            return Ok(SnpSelector::Maf {
+                comparison,
+                threshold,
+            });
+        }
+        match split_key_value(arg) {
+            Some(("partition", name)) => {
+                Ok(SnpSelector::Partition(parse_identifier(name)?))
+            }
+            Some((key, _)) => Err(format!("unknown option {} in {}", key, arg)),
+            None => Ok(SnpSelector::Named(parse_identifier(arg)?)),
+        }
+    }
+}
+
+impl fmt::Display for SnpSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnpSelector::All => write!(f, "all"),
+            SnpSelector::Maf {
+                comparison,
+                threshold,
+            } => write!(f, "maf{}{}", comparison.symbol(), threshold),
+            SnpSelector::Partition(name) => write!(f, "partition={}", name),
+            SnpSelector::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// How a GxG component is split into one component per group of SNPs
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Grouping {
+    Chrom,
+    Partition,
+}
+
+impl Grouping {
+    fn name(&self) -> &'static str {
+        match self {
+            Grouping::Chrom => "chrom",
+            Grouping::Partition => "partition",
+        }
+    }
+}
+
+/// A single term of a model specification such as `G(maf<0.05)` or
+/// `GxG(le, within=chrom)`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ComponentSpec {
+    pub kind: ComponentKind,
+    pub snps: SnpSelector,
+    pub within: Option<Grouping>,
+}
+
+impl fmt::Display for ComponentSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({}", self.kind.name(), self.snps)?;
+        if let Some(grouping) = self.within {
+            write!(f, ", within={}", grouping.name())?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A declarative variance component model, e.g.
+/// `y ~ G(all) + G(maf<0.05) + GxG(le, within=chrom) + E`.
+///
+/// `G` and `D` are additive and dominance components over the SNPs picked by
+/// their selector, `all` by default. `GxG` is a pairwise interaction
+/// component over an LE SNP set, optionally split `within=chrom` or
+/// `within=partition`. The noise component `E` is always fit and can be
+/// written for readability. The response name is informational, as the
+/// phenotypes come from the phenotype files.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ModelSpec {
+    pub response: String,
+    pub components: Vec<ComponentSpec>,
+}
+
+impl ModelSpec {
+    /// Reads a model from a file, ignoring the lines starting with `#`, so
+    /// that a long model can be split over several lines.
+    pub fn from_file(path: &str) -> Result<ModelSpec, String> {
+        let content = read_to_string(path)
+            .map_err(|why| format!("failed to read {}: {}", path, why))?;
+        content
+            .lines()
+            .filter(|l| !l.trim_start().starts_with('#'))
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .parse()
+            .map_err(|why| format!("invalid model in {}: {}", path, why))
+    }
+
+    pub fn components_of_kind(
+        &self,
+        kind: ComponentKind,
+    ) -> impl Iterator<Item = &ComponentSpec> {
+        self.components.iter().filter(move |c| c.kind == kind)
+    }
+
+    /// Checks that the model only has components of the `supported` kinds,
+    /// naming the `executable` in the error otherwise.
+    pub fn check_supported_kinds(
+        &self,
+        supported: &[ComponentKind],
+        executable: &str,
+    ) -> Result<(), String> {
+        match self
+            .components
+            .iter()
+            .find(|c| !supported.contains(&c.kind))
+        {
+            Some(c) => Err(format!(
+                "{} components such as {} are not supported by {}",
+                c.kind.name(),
+                c,
+                executable
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The SNPs of each component of the given `kind`, keyed by the component
+    /// as written in the canonical model string. `candidates` are the SNPs
+    /// available to that kind, see `SnpSelector::select` for the rest.
+    pub fn get_component_snps(
+        &self,
+        kind: ComponentKind,
+        candidates: &OrderedIntegerSet<usize>,
+        mafs: Option<&[f32]>,
+        partitions: &HashMap<String, OrderedIntegerSet<usize>>,
+    ) -> Result<Vec<(String, OrderedIntegerSet<usize>)>, String> {
+        self.components_of_kind(kind)
+            .map(|c| {
+                let snps = c.snps.select(candidates, mafs, partitions)?;
+                if snps.interval_list().is_empty() {
+                    return Err(format!("{} has no SNPs", c));
+                }
+                Ok((c.to_string(), snps))
+            })
+            .collect()
+    }
+
+    /// Whether resolving the components requires the minor allele
+    /// frequencies
+    pub fn needs_maf(&self) -> bool {
+        self.components.iter().any(|c| match c.snps {
+            SnpSelector::Maf {
+                ..
+            } => true,
+            _ => false,
+        })
+    }
+}
+
+impl FromStr for ModelSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ModelSpec, String> {
+        let (response, rhs) = match s.find('~') {
+            Some(i) => (parse_identifier(&s[..i])?, &s[i + 1..]),
+            None => (DEFAULT_RESPONSE_NAME.to_string(), s),
+        };
+        let mut components: Vec<ComponentSpec> = Vec::new();
+        let mut has_noise = false;
+        for term in split_top_level(rhs, '+')?.into_iter() {
+            let (name, args) = match term.find('(') {
+                Some(i) => {
+                    if !term.ends_with(')') {
+                        return Err(format!("missing ) in {}", term));
+                    }
+                    (
+                        term[..i].trim(),
+                        split_top_level(&term[i + 1..term.len() - 1], ',')?,
+                    )
+                }
+                None => (term, Vec::new()),
+            };
+            let kind = ComponentKind::from_name(name)?;
+            if kind == ComponentKind::Noise {
+                if !args.is_empty() {
+                    return Err(format!(
+                        "E takes no arguments, received {}",
+                        term
+                    ));
+                }
+                if has_noise {
+                    return Err("E appears more than once".to_string());
+                }
+                has_noise = true;
+                continue;
+            }
+            let mut snps = None;
+            let mut within = None;
+            for arg in args.into_iter() {
+                match split_key_value(arg) {
+                    Some(("within", grouping)) => {
+                        if kind != ComponentKind::Gxg {
+                            return Err(format!(
+                                "within= only applies to GxG, received {}",
+                                term
+                            ));
+                        }
+                        if within.is_some() {
+                            return Err(format!(
+                                "repeated within= in {}",
+                                term
+                            ));
+                        }
+                        within = Some(match grouping {
+                            "chrom" => Grouping::Chrom,
+                            "partition" => Grouping::Partition,
+                            other => {
+                                return Err(format!(
+                                    "expected within=chrom or \
+                                    within=partition, received within={}",
+                                    other
+                                ))
+                            }
+                        });
+                    }
+                    _ => {
+                        if snps.is_some() {
+                            return Err(format!(
+                                "more than one SNP selector in {}",
+                                term
+                            ));
+                        }
+                        snps = Some(SnpSelector::parse(arg)?);
+                    }
+                }
+            }
+            let component = ComponentSpec {
+                kind,
+                snps: snps.unwrap_or(SnpSelector::All),
+                within,
+            };
+            if components.contains(&component) {
+                return Err(format!("{} appears more than once", component));
+            }
+            components.push(component);
+        }
+        if components.is_empty() {
+            return Err(format!(
+                "the model {} has no genetic components",
+                s.trim()
+            ));
+        }
+        Ok(ModelSpec {
+            response,
+            components,
+        })
+    }
+}
+
+impl fmt::Display for ModelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ~ ", self.response)?;
+        for c in self.components.iter() {
+            write!(f, "{} + ", c)?;
+        }
+        write!(f, "E")
+    }
+}
+
+/// Splits `s` at the `separator`s outside of parentheses, trimming the parts
+fn split_top_level(s: &str, separator: char) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("unbalanced ) in {}", s.trim()))?
+            }
+            c if c == separator && depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("unbalanced ( in {}", s.trim()));
+    }
+    parts.push(s[start..].trim());
+    if parts.iter().any(|p| p.is_empty()) {
+        return Err(format!("empty term in {}", s.trim()));
+    }
+    Ok(parts)
+}
+
+fn split_key_value(arg: &str) -> Option<(&str, &str)> {
+    arg.find('=')
+        .map(|i| (arg[..i].trim(), arg[i + 1..].trim()))
+}
+
+fn parse_identifier(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.is_empty()
+        || !s
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(format!("invalid name {:?}", s));
+    }
+    Ok(s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+
+    use super::{
+        Comparison, ComponentKind, ComponentSpec, Grouping, ModelSpec,
+        SnpSelector,
+    };
+
+    #[test]
+    fn test_parse_model() {
+        let model: ModelSpec =
+            "y ~ G(all) + G(maf<0.05) + GxG(le_basis, within=chrom) + E"
+                .parse()
+                .unwrap();
+        assert_eq!(model.response, "y");
+        assert_eq!(model.components, vec![
+            ComponentSpec {
+                kind: ComponentKind::Additive,
+                snps: SnpSelector::All,
+                within: None,
+            },
+            ComponentSpec {
+                kind: ComponentKind::Additive,
+                snps: SnpSelector::Maf {
+                    comparison: Comparison::Less,
+                    threshold: 0.05,
+                },
+                within: None,
+            },
+            ComponentSpec {
+                kind: ComponentKind::Gxg,
+                snps: SnpSelector::Named("le_basis".to_string()),
+                within: Some(Grouping::Chrom),
+            },
+        ]);
+        assert_eq!(
+            model.to_string(),
+            "y ~ G(all) + G(maf<0.05) + GxG(le_basis, within=chrom) + E"
+        );
+        assert!(model.needs_maf());
+        assert_eq!(model.to_string().parse::<ModelSpec>().unwrap(), model);
+
+        let model: ModelSpec = "G + D(partition=coding)".parse().unwrap();
+        assert_eq!(model.to_string(), "y ~ G(all) + D(partition=coding) + E");
+        assert!(!model.needs_maf());
+        assert_eq!(
+            model.components_of_kind(ComponentKind::Dominance).count(),
+            1
+        );
+        assert!(model
+            .check_supported_kinds(
+                &[ComponentKind::Additive, ComponentKind::Dominance],
+                "estimate_heritability"
+            )
+            .is_ok());
+        assert!(model
+            .check_supported_kinds(
+                &[ComponentKind::Additive],
+                "estimate_g_gxg_heritability"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_models() {
+        for model in [
+            "y ~ E",
+            "y ~ G(all) + + E",
+            "y ~ G(all",
+            "y ~ G(all))",
+            "y ~ H(all)",
+            "y ~ G(all) + E(all)",
+            "y ~ G + E + E",
+            "y ~ G(all) + G",
+            "y ~ G(all, within=chrom)",
+            "y ~ GxG(le, within=gene)",
+            "y ~ G(maf=0.05)",
+            "y ~ G(maf<0.7)",
+            "y ~ G(all, maf<0.05)",
+            "y ~ G(annotation=coding)",
+        ]
+        .iter()
+        {
+            assert!(model.parse::<ModelSpec>().is_err(), "{}", model);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let candidates = OrderedIntegerSet::from_slice(&[[0, 5]]);
+        let mafs = [0.01, 0.2, 0.04, 0.3, 0.05, 0.5];
+        let mut partitions = HashMap::new();
+        partitions.insert(
+            "coding".to_string(),
+            OrderedIntegerSet::from_slice(&[[4, 9]]),
+        );
+        let select = |arg: &str| {
+            SnpSelector::parse(arg).unwrap().select(
+                &candidates,
+                Some(&mafs[..]),
+                &partitions,
+            )
+        };
+        assert_eq!(select("all").unwrap(), candidates);
+        assert_eq!(
+            select("maf<0.05").unwrap(),
+            OrderedIntegerSet::from_slice(&[[0, 0], [2, 2]])
+        );
+        assert_eq!(
+            select("maf>=0.05").unwrap(),
+            OrderedIntegerSet::from_slice(&[[1, 1], [3, 5]])
+        );
+        assert_eq!(
+            select("partition=coding").unwrap(),
+            OrderedIntegerSet::from_slice(&[[4, 5]])
+        );
+        assert!(select("partition=noncoding").is_err());
+        assert!(select("le").is_err());
+
+        let model: ModelSpec = "y ~ G + G(maf<0.05) + G(partition=coding) + D"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            model
+                .get_component_snps(
+                    ComponentKind::Additive,
+                    &candidates,
+                    Some(&mafs[..]),
+                    &partitions
+                )
+                .unwrap(),
+            vec![
+                ("G(all)".to_string(), candidates.clone()),
+                (
+                    "G(maf<0.05)".to_string(),
+                    OrderedIntegerSet::from_slice(&[[0, 0], [2, 2]])
+                ),
+                (
+                    "G(partition=coding)".to_string(),
+                    OrderedIntegerSet::from_slice(&[[4, 5]])
+                ),
+            ]
+        );
+        assert!(model
+            .get_component_snps(
+                ComponentKind::Dominance,
+                &OrderedIntegerSet::new(),
+                Some(&mafs[..]),
+                &partitions
+            )
+            .is_err());
+    }
+}
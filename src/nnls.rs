@@ -0,0 +1,136 @@
+//! Solving the normal equations `A x = b` of the variance components under
+//! the constraint `x >= 0`.
+//!
+//! The normal equations are those of the least squares fit of `y y^T` by the
+//! kernels, with `A` the Gram matrix `tr(K_i K_j)` of the kernels and `b` the
+//! products `y^T K_i y`. The constrained estimates therefore minimize
+//! `x^T A x / 2 - b^T x` over `x >= 0`, which is solved by the active set
+//! method of Lawson and Hanson on `A` and `b` directly. Where the
+//! unconstrained solution is nonnegative, the two coincide.
+
+use ndarray::{Array, Axis, Ix1, Ix2};
+use ndarray_linalg::Solve;
+
+use crate::error::Error;
+
+/// The active set method adds a component per outer iteration, so it
+/// converges in far fewer iterations than this for any number of components
+const MAX_NUM_ITERATIONS_PER_COMPONENT: usize = 30;
+
+const TOLERANCE: f64 = 1e-12;
+
+fn solve_passive_set(
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+    passive: &[usize],
+) -> Result<Array<f64, Ix1>, Error> {
+    let mut z = Array::<f64, Ix1>::zeros(b.len());
+    let a_pp = a.select(Axis(0), passive).select(Axis(1), passive);
+    let z_p = a_pp
+        .solve(&b.select(Axis(0), passive))
+        .map_err(|why| Error::SingularNormalEquations(format!("{:?}", why)))?;
+    for (&i, &v) in passive.iter().zip(z_p.iter()) {
+        z[i] = v;
+    }
+    Ok(z)
+}
+
+/// The variance components `x >= 0` minimizing `x^T A x / 2 - b^T x` for the
+/// symmetric positive definite `a`
+pub fn solve_nonnegative_normal_equations(
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+) -> Result<Array<f64, Ix1>, Error> {
+    let k = b.len();
+    if a.dim() != (k, k) {
+        return Err(Error::DimensionMismatch(format!(
+            "a {:?} matrix for {} normal equations",
+            a.dim(),
+            k
+        )));
+    }
+    let scale = a.iter().fold(0f64, |m, v| m.max(v.abs())).max(1.);
+    let mut x = Array::<f64, Ix1>::zeros(k);
+    let mut is_passive = vec![false; k];
+    for _ in 0..MAX_NUM_ITERATIONS_PER_COMPONENT * k {
+        let gradient = b - &a.dot(&x);
+        let next = (0..k)
+            .filter(|&i| !is_passive[i])
+            .max_by(|&i, &j| gradient[i].partial_cmp(&gradient[j]).unwrap());
+        match next {
+            Some(j) if gradient[j] > TOLERANCE * scale => is_passive[j] = true,
+            _ => return Ok(x),
+        }
+        loop {
+            let passive: Vec<usize> =
+                (0..k).filter(|&i| is_passive[i]).collect();
+            let z = solve_passive_set(a, b, &passive)?;
+            if passive.iter().all(|&i| z[i] > 0.) {
+                x = z;
+                break;
+            }
+            // step from x towards z until the first component hits zero
+            let step = passive
+                .iter()
+                .filter(|&&i| z[i] <= 0.)
+                .map(|&i| x[i] / (x[i] - z[i]))
+                .fold(1f64, f64::min);
+            x = &x + &((&z - &x) * step);
+            for &i in passive.iter() {
+                if x[i] <= TOLERANCE * scale {
+                    x[i] = 0.;
+                    is_passive[i] = false;
+                }
+            }
+        }
+    }
+    Err(Error::Generic(format!(
+        "the nonnegative solve of the normal equations did not converge in \
+        {} iterations",
+        MAX_NUM_ITERATIONS_PER_COMPONENT * k
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::solve_nonnegative_normal_equations;
+    use crate::heritability_estimator::solve_normal_equations;
+
+    #[test]
+    fn test_solve_nonnegative_normal_equations() {
+        let a = array![[2., 1., 0.5], [1., 3., 1.], [0.5, 1., 4.]];
+
+        // a nonnegative unconstrained solution is kept
+        let b = array![3.5, 5., 5.5];
+        let x = solve_nonnegative_normal_equations(&a, &b).unwrap();
+        let expected = solve_normal_equations(&a, &b).unwrap();
+        assert!((&x - &expected).iter().all(|d| d.abs() < 1e-10));
+
+        // the unconstrained solution has a negative second component
+        let b = array![2., -1., 4.];
+        assert!(solve_normal_equations(&a, &b).unwrap()[1] < 0.);
+        let x = solve_nonnegative_normal_equations(&a, &b).unwrap();
+        assert_eq!(x[1], 0.);
+        assert!(x.iter().all(|&v| v >= 0.));
+        // the KKT conditions: the gradient vanishes on the positive
+        // components and points out of the feasible set on the zero ones
+        let gradient = &b - &a.dot(&x);
+        for i in 0..3 {
+            if x[i] > 0. {
+                assert!(gradient[i].abs() < 1e-10);
+            } else {
+                assert!(gradient[i] <= 1e-10);
+            }
+        }
+
+        let x = solve_nonnegative_normal_equations(&a, &array![-1., -2., -3.])
+            .unwrap();
+        assert_eq!(x, array![0., 0., 0.]);
+
+        assert!(
+            solve_nonnegative_normal_equations(&a, &array![1., 2.]).is_err()
+        );
+    }
+}
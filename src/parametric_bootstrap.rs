@@ -0,0 +1,313 @@
+//! Parametric bootstrap confidence intervals of the variance components.
+//!
+//! The entries of the normal equations `A x = b`, i.e. the upper triangle of
+//! `A` and all of `b`, are taken to be jointly Gaussian around their values on
+//! the full data, with the covariance estimated from their values on the
+//! resampling replicates. Each bootstrap replicate draws `(A, b)` from that
+//! distribution and solves the normal equations, so that the percentile
+//! intervals carry the nonlinearity of `A^{-1} b` that the standard errors
+//! leave out.
+
+use ndarray::{Array, Ix1, Ix2};
+use ndarray_linalg::{Eigh, UPLO};
+use rand::{distributions::StandardNormal, Rng};
+use serde::Serialize;
+
+use crate::{
+    confidence_interval::{percentile_interval, ConfidenceInterval},
+    error::Error,
+    heritability_estimator::solve_normal_equations,
+    nnls::solve_nonnegative_normal_equations,
+};
+
+pub const DEFAULT_NUM_PARAMETRIC_BOOTSTRAP_REPS: usize = 1000;
+
+/// The percentile interval of a component at the two-sided `level`, from
+/// `num_reps` parametric bootstrap replicates
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct ParametricBootstrapInterval {
+    pub component: String,
+    pub level: f64,
+    pub num_reps: usize,
+    pub percentile: ConfidenceInterval,
+}
+
+/// The upper triangle of `a` row by row, followed by `b`
+fn vectorize_normal_equations(
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+) -> Array<f64, Ix1> {
+    let k = b.len();
+    let mut x = Vec::with_capacity(k * (k + 1) / 2 + k);
+    for i in 0..k {
+        for j in i..k {
+            x.push(a[[i, j]]);
+        }
+    }
+    x.extend(b.iter());
+    Array::from_vec(x)
+}
+
+/// The inverse of `vectorize_normal_equations` for `k` components
+fn unvectorize_normal_equations(
+    x: &Array<f64, Ix1>,
+    k: usize,
+) -> (Array<f64, Ix2>, Array<f64, Ix1>) {
+    let mut a = Array::zeros((k, k));
+    let mut offset = 0;
+    for i in 0..k {
+        for j in i..k {
+            a[[i, j]] = x[offset];
+            a[[j, i]] = x[offset];
+            offset += 1;
+        }
+    }
+    let b = (0..k).map(|i| x[offset + i]).collect();
+    (a, b)
+}
+
+/// `component_names` name the `k` variance components of the normal
+/// equations `a x = b` on the full data, with the noise last, and
+/// `replicate_normal_eqns` are the normal equations on each resampling
+/// replicate. Their covariance is the sum of the outer products of the
+/// centered replicates multiplied by `covariance_scale`, e.g. from
+/// `Resampling::covariance_scale`.
+///
+/// Returns the intervals of every component followed by the `total` of all
+/// the components but the noise. The draws whose normal equations are
+/// singular are left out. If `nonnegative` is true, every draw is solved
+/// under the constraint that the variance components are nonnegative, as
+/// the estimates themselves.
+pub fn get_parametric_bootstrap_intervals<R: Rng>(
+    component_names: &[String],
+    a: &Array<f64, Ix2>,
+    b: &Array<f64, Ix1>,
+    replicate_normal_eqns: &[(Array<f64, Ix2>, Array<f64, Ix1>)],
+    covariance_scale: f64,
+    num_reps: usize,
+    level: f64,
+    nonnegative: bool,
+    rng: &mut R,
+) -> Result<Vec<ParametricBootstrapInterval>, Error> {
+    let k = b.len();
+    if component_names.len() != k || a.dim() != (k, k) {
+        return Err(Error::DimensionMismatch(format!(
+            "{} component names for normal equations with a matrix of shape \
+            {:?} and a right-hand side of length {}",
+            component_names.len(),
+            a.dim(),
+            k
+        )));
+    }
+    if let Some((a_r, b_r)) = replicate_normal_eqns
+        .iter()
+        .find(|(a_r, b_r)| a_r.dim() != (k, k) || b_r.len() != k)
+    {
+        return Err(Error::DimensionMismatch(format!(
+            "a replicate has normal equations of shapes {:?} and {}, \
+            expected {:?} and {}",
+            a_r.dim(),
+            b_r.len(),
+            (k, k),
+            k
+        )));
+    }
+    if replicate_normal_eqns.len() < 2 {
+        return Err(Error::Generic(format!(
+            "the covariance of the normal equations requires at least two \
+            replicates, received {}",
+            replicate_normal_eqns.len()
+        )));
+    }
+    if !(level > 0. && level < 1.) {
+        return Err(Error::Generic(format!(
+            "the confidence level has to be in (0, 1), received {}",
+            level
+        )));
+    }
+    if num_reps == 0 {
+        return Err(Error::Generic(
+            "the parametric bootstrap requires at least one replicate"
+                .to_string(),
+        ));
+    }
+
+    let replicates: Vec<Array<f64, Ix1>> = replicate_normal_eqns
+        .iter()
+        .map(|(a_r, b_r)| vectorize_normal_equations(a_r, b_r))
+        .collect();
+    let m = replicates[0].len();
+    let mut mean = Array::<f64, Ix1>::zeros(m);
+    for x in replicates.iter() {
+        mean = mean + x;
+    }
+    mean /= replicates.len() as f64;
+    let mut covariance = Array::<f64, Ix2>::zeros((m, m));
+    for x in replicates.iter() {
+        let d = (x - &mean).into_shape((m, 1)).unwrap();
+        covariance = covariance + &d.dot(&d.t());
+    }
+    covariance *= covariance_scale;
+
+    // the covariance is only positive semidefinite when there are fewer
+    // replicates than entries, so it is factored by its eigendecomposition
+    let (eigenvalues, eigenvectors) =
+        covariance.eigh(UPLO::Upper).map_err(|why| {
+            Error::Generic(format!(
+                "failed to factor the covariance of the normal equations: \
+                {:?}",
+                why
+            ))
+        })?;
+    let root = eigenvectors * &eigenvalues.mapv(|l| l.max(0.).sqrt());
+    let center = vectorize_normal_equations(a, b);
+
+    let mut component_draws: Vec<Vec<f64>> = vec![Vec::new(); k + 1];
+    for _ in 0..num_reps {
+        let z: Array<f64, Ix1> =
+            (0..m).map(|_| rng.sample(StandardNormal)).collect();
+        let (a_star, b_star) =
+            unvectorize_normal_equations(&(&center + &root.dot(&z)), k);
+        let solution = if nonnegative {
+            solve_nonnegative_normal_equations(&a_star, &b_star)
+        } else {
+            solve_normal_equations(&a_star, &b_star)
+        };
+        if let Ok(sig_sq) = solution {
+            for (c, draws) in component_draws.iter_mut().take(k).enumerate() {
+                draws.push(sig_sq[c]);
+            }
+            component_draws[k].push(sig_sq.iter().take(k - 1).sum());
+        }
+    }
+    let num_solved = component_draws[k].len();
+    if num_solved == 0 {
+        return Err(Error::SingularNormalEquations(
+            "the normal equations of every parametric bootstrap replicate are \
+            singular"
+                .to_string(),
+        ));
+    }
+    Ok(component_names
+        .iter()
+        .cloned()
+        .chain(std::iter::once("total".to_string()))
+        .zip(component_draws.iter())
+        .map(|(component, draws)| ParametricBootstrapInterval {
+            component,
+            level,
+            num_reps: num_solved,
+            percentile: percentile_interval(draws, level),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array, Ix1, Ix2};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{
+        get_parametric_bootstrap_intervals, unvectorize_normal_equations,
+        vectorize_normal_equations,
+    };
+
+    #[test]
+    fn test_parametric_bootstrap_intervals() {
+        let a = array![[4., 1.], [1., 2.]];
+        let b = array![1.625, 1.5];
+        let x = vectorize_normal_equations(&a, &b);
+        assert_eq!(x, array![4., 1., 2., 1.625, 1.5]);
+        assert_eq!(unvectorize_normal_equations(&x, 2), (a.clone(), b.clone()));
+
+        let names = vec!["g".to_string(), "noise".to_string()];
+        let mut rng = StdRng::seed_from_u64(7);
+        // identical replicates have no covariance, so every draw solves to
+        // the point estimate (0.25, 0.625)
+        let replicates = vec![(a.clone(), b.clone()); 5];
+        let intervals = get_parametric_bootstrap_intervals(
+            &names,
+            &a,
+            &b,
+            &replicates,
+            0.8,
+            50,
+            0.95,
+            false,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(intervals.len(), 3);
+        assert_eq!(intervals[2].component, "total");
+        for (interval, expected) in
+            intervals.iter().zip([0.25, 0.625, 0.25].iter())
+        {
+            assert_eq!(interval.num_reps, 50);
+            assert!((interval.percentile.lower - expected).abs() < 1e-10);
+            assert!((interval.percentile.upper - expected).abs() < 1e-10);
+        }
+
+        // replicates that perturb b widen the intervals around the estimate
+        let replicates: Vec<(Array<f64, Ix2>, Array<f64, Ix1>)> = (0..10)
+            .map(|r| (a.clone(), &b + (r as f64 - 4.5) * 0.1))
+            .collect();
+        let intervals = get_parametric_bootstrap_intervals(
+            &names,
+            &a,
+            &b,
+            &replicates,
+            0.9,
+            500,
+            0.9,
+            false,
+            &mut rng,
+        )
+        .unwrap();
+        for (interval, expected) in
+            intervals.iter().zip([0.25, 0.625, 0.25].iter())
+        {
+            assert!(interval.percentile.lower < *expected);
+            assert!(interval.percentile.upper > *expected);
+        }
+
+        // the nonnegative draws never cross zero
+        let intervals = get_parametric_bootstrap_intervals(
+            &names,
+            &a,
+            &b,
+            &replicates,
+            0.9,
+            500,
+            0.9,
+            true,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(intervals.iter().all(|i| i.percentile.lower >= 0.));
+
+        assert!(get_parametric_bootstrap_intervals(
+            &names[..1],
+            &a,
+            &b,
+            &replicates,
+            0.9,
+            10,
+            0.9,
+            false,
+            &mut rng,
+        )
+        .is_err());
+        assert!(get_parametric_bootstrap_intervals(
+            &names,
+            &a,
+            &b,
+            &replicates[..1],
+            0.9,
+            10,
+            0.9,
+            false,
+            &mut rng,
+        )
+        .is_err());
+    }
+}
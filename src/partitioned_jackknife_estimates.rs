@@ -1,11 +1,25 @@
-use std::{collections::HashSet, fmt};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Ix2};
+use serde::Serialize;
 
-use math::{
-    set::ordered_integer_set::OrderedIntegerSet, stats::standard_deviation,
-    traits::ToIterator,
+use crate::{
+    confidence_interval::{
+        bca_interval, percentile_interval, ConfidenceInterval,
+    },
+    heritability_estimate::HeritabilityEstimate,
+    integer_set::iter::Iter,
+    liability::LiabilityScaleEstimates,
+    parametric_bootstrap::ParametricBootstrapInterval,
+    resampling::{Resampling, ResamplingBlocks},
+    warning::{Warning, WarningCode},
 };
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
 pub struct Estimate<T> {
     pub bias_corrected_estimate: T,
     pub jackknife_mean: T,
@@ -29,40 +43,253 @@ impl<T> Estimate<T> {
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize)]
 pub struct PartitionedJackknifeEstimates {
     pub partition_names: Option<Vec<String>>,
     pub partition_estimates: Vec<Estimate<f64>>,
     pub sum_estimate: Option<Estimate<f64>>,
     pub subset_sum_estimates: Option<Vec<(String, Estimate<f64>)>>,
+    /// The jackknife covariance matrix of the partition estimates, whose
+    /// diagonal is the square of the partition standard errors.
+    /// `None` if there are fewer than two jackknife iterations.
+    pub covariance: Option<Array<f64, Ix2>>,
+    /// `jackknife_iteration_estimates[k][p]` is the estimate for partition `p`
+    /// with the `k`-th jackknife partition left out.
+    pub jackknife_iteration_estimates: Vec<Vec<f64>>,
+    /// The SNP blocks of the replicates behind the
+    /// `jackknife_iteration_estimates`, set when the estimator is asked to
+    /// keep them.
+    pub resampling_blocks: Option<ResamplingBlocks>,
+    /// The bootstrap confidence intervals, set when the replicates are drawn
+    /// by the bootstrap.
+    pub bootstrap_intervals: Option<Vec<BootstrapInterval>>,
+    /// The percentile intervals from the parametric bootstrap of the normal
+    /// equations, set when the estimator is asked for them.
+    pub parametric_bootstrap_intervals:
+        Option<Vec<ParametricBootstrapInterval>>,
+    /// The variances with their analytical sandwich covariance, set when the
+    /// estimator is asked for analytical standard errors.
+    pub analytical_estimate: Option<HeritabilityEstimate>,
+    /// The estimates on the liability scale, set for the case-control
+    /// phenotypes of a known prevalence.
+    pub liability_scale: Option<LiabilityScaleEstimates>,
+    /// The traces of the normal equations on the full data with their Monte
+    /// Carlo standard errors, set by the estimators that report them.
+    pub trace_estimates: Vec<TraceEstimate>,
+    pub warnings: Vec<Warning>,
+}
+
+/// A trace of the normal equations with the Monte Carlo standard error of its
+/// estimate, i.e. the standard error of the mean of the per-probe values,
+/// which is zero for the traces computed exactly
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct TraceEstimate {
+    pub name: String,
+    pub value: f64,
+    pub mc_standard_error: f64,
+    pub num_random_vecs: usize,
+    /// The fraction of the Monte Carlo variance of the per-probe values
+    /// removed by a control variate, set for the traces estimated with one
+    pub variance_reduction: Option<f64>,
 }
 
-fn get_jackknife_mean_and_std(
-    point_estimate_without_jackknife: f64,
-    estimates: &Vec<f64>,
-) -> Estimate<f64> {
-    let n = estimates.len() as f64;
+impl TraceEstimate {
+    /// The mean of the per-probe `values` and its standard error, which is
+    /// NaN for a single probe
+    pub fn from_probe_values(name: String, values: &[f64]) -> TraceEstimate {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>()
+                / (n - 1.);
+        TraceEstimate {
+            name,
+            value: mean,
+            mc_standard_error: (variance / n).sqrt(),
+            num_random_vecs: values.len(),
+            variance_reduction: None,
+        }
+    }
+
+    /// The control variate estimate from the per-probe `values` and the
+    /// per-probe `control_values`, whose expectation `control_mean` is known
+    /// exactly. Every value `v` is adjusted to `v - beta (c - control_mean)`
+    /// with the `beta` minimizing the variance of the adjusted values, which
+    /// is fit on the same probes and biases the estimate by `O(1 / n)` for
+    /// `n` probes.
+    pub fn from_control_variate(
+        name: String,
+        values: &[f64],
+        control_values: &[f64],
+        control_mean: f64,
+    ) -> TraceEstimate {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let mean_control = control_values.iter().sum::<f64>() / n;
+        let (covariance, control_variance) = values
+            .iter()
+            .zip(control_values.iter())
+            .fold((0f64, 0f64), |(cov, var), (v, c)| {
+                (
+                    cov + (v - mean) * (c - mean_control),
+                    var + (c - mean_control) * (c - mean_control),
+                )
+            });
+        let beta = if control_variance > 0. {
+            covariance / control_variance
+        } else {
+            0.
+        };
+        let adjusted: Vec<f64> = values
+            .iter()
+            .zip(control_values.iter())
+            .map(|(v, c)| v - beta * (c - control_mean))
+            .collect();
+        let plain_standard_error =
+            TraceEstimate::from_probe_values(name.clone(), values)
+                .mc_standard_error;
+        let mut est = TraceEstimate::from_probe_values(name, &adjusted);
+        est.variance_reduction = Some(if plain_standard_error > 0. {
+            1. - (est.mc_standard_error / plain_standard_error).powi(2)
+        } else {
+            0.
+        });
+        est
+    }
+
+    pub fn exact(
+        name: String,
+        value: f64,
+        num_random_vecs: usize,
+    ) -> TraceEstimate {
+        TraceEstimate {
+            name,
+            value,
+            mc_standard_error: 0.,
+            num_random_vecs,
+            variance_reduction: None,
+        }
+    }
+
+    /// The Monte Carlo standard error relative to the magnitude of the trace
+    pub fn relative_standard_error(&self) -> f64 {
+        self.mc_standard_error / self.value.abs()
+    }
+
+    /// Whether the relative standard error is at most `tolerance`, which it
+    /// is not if it is undefined, e.g. for a single probe
+    pub fn is_within_tolerance(&self, tolerance: f64) -> bool {
+        self.relative_standard_error() <= tolerance
+    }
+}
 
-    let jackknife_mean = estimates.iter().sum::<f64>() / n;
-    let standard_error =
-        standard_deviation(estimates.iter(), 0) * (n - 1.).sqrt();
-    let bias_corrected_estimate =
-        n * point_estimate_without_jackknife - (n - 1.) * jackknife_mean;
+/// The percentile and BCa bootstrap confidence intervals of a component at
+/// the given two-sided `level`.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct BootstrapInterval {
+    pub component: String,
+    pub level: f64,
+    pub percentile: ConfidenceInterval,
+    pub bca: ConfidenceInterval,
+}
 
-    Estimate {
-        bias_corrected_estimate,
-        jackknife_mean,
-        point_estimate_without_jackknife,
-        standard_error,
+/// `jackknife_iteration_estimates[k][p]` is the estimate for partition `p`
+/// on the `k`-th replicate. The covariance is scaled as given by
+/// `Resampling::covariance_scale`, e.g. by `(n - 1) / n` for `n` jackknife
+/// iterations.
+fn get_jackknife_covariance(
+    jackknife_iteration_estimates: &Vec<Vec<f64>>,
+    num_partitions: usize,
+    resampling: &Resampling,
+) -> Option<Array<f64, Ix2>> {
+    let n = jackknife_iteration_estimates.len();
+    let scale = resampling.covariance_scale(n)?;
+    let means: Vec<f64> = (0..num_partitions)
+        .map(|p| {
+            jackknife_iteration_estimates
+                .iter()
+                .map(|estimates| estimates[p])
+                .sum::<f64>()
+                / n as f64
+        })
+        .collect();
+    let mut covariance = Array::zeros((num_partitions, num_partitions));
+    for estimates in jackknife_iteration_estimates.iter() {
+        for i in 0..num_partitions {
+            for j in i..num_partitions {
+                covariance[[i, j]] +=
+                    (estimates[i] - means[i]) * (estimates[j] - means[j]);
+            }
+        }
     }
+    for i in 0..num_partitions {
+        for j in i..num_partitions {
+            covariance[[i, j]] *= scale;
+            covariance[[j, i]] = covariance[[i, j]];
+        }
+    }
+    Some(covariance)
+}
+
+/// A `NegativeVariance` warning for every partition, and the total, whose
+/// bias corrected estimate is negative.
+fn get_negative_variance_warnings(
+    partition_names: &Option<Vec<String>>,
+    partition_estimates: &[Estimate<f64>],
+    sum_estimate: &Option<Estimate<f64>>,
+) -> Vec<Warning> {
+    let named_estimates = partition_estimates
+        .iter()
+        .enumerate()
+        .map(|(i, est)| {
+            let name = match partition_names {
+                Some(names) => names[i].clone(),
+                None => format!("partition {}", i),
+            };
+            (name, est)
+        })
+        .chain(sum_estimate.iter().map(|est| ("total".to_string(), est)));
+    named_estimates
+        .filter(|(_, est)| est.bias_corrected_estimate < 0.)
+        .map(|(name, est)| {
+            Warning::new(
+                WarningCode::NegativeVariance,
+                format!(
+                    "the variance estimate of {} is negative: {}",
+                    name, est.bias_corrected_estimate
+                ),
+            )
+        })
+        .collect()
 }
 
 impl PartitionedJackknifeEstimates {
+    /// Summarizes the estimates of the leave-one-out jackknife iterations.
     pub fn from_jackknife_estimates(
         point_estimate_without_jackknife: &Vec<f64>,
         jackknife_iteration_estimates: &Vec<Vec<f64>>,
         partition_names: Option<Vec<String>>,
         subset_sum_indices: Option<Vec<(String, OrderedIntegerSet<usize>)>>,
+    ) -> Result<PartitionedJackknifeEstimates, String> {
+        PartitionedJackknifeEstimates::from_replicate_estimates(
+            point_estimate_without_jackknife,
+            jackknife_iteration_estimates,
+            partition_names,
+            subset_sum_indices,
+            &Resampling::BlockJackknife {
+                num_blocks: jackknife_iteration_estimates.len(),
+            },
+        )
+    }
+
+    /// Summarizes the estimates on the replicates drawn by the `resampling`,
+    /// with the standard error formula of the `resampling`.
+    pub fn from_replicate_estimates(
+        point_estimate_without_jackknife: &Vec<f64>,
+        jackknife_iteration_estimates: &Vec<Vec<f64>>,
+        partition_names: Option<Vec<String>>,
+        subset_sum_indices: Option<Vec<(String, OrderedIntegerSet<usize>)>>,
+        resampling: &Resampling,
     ) -> Result<PartitionedJackknifeEstimates, String> {
         if jackknife_iteration_estimates
             .iter()
@@ -73,12 +300,23 @@ impl PartitionedJackknifeEstimates {
         {
             return Err(format!("inconsistent number of partitioned estimates across Jackknife iterations"));
         }
-        if jackknife_iteration_estimates.len() == 0 {
+        if jackknife_iteration_estimates.len() == 0
+            && *resampling != Resampling::None
+        {
             return Ok(PartitionedJackknifeEstimates {
                 partition_names: None,
                 partition_estimates: Vec::new(),
                 sum_estimate: None,
                 subset_sum_estimates: None,
+                covariance: None,
+                jackknife_iteration_estimates: Vec::new(),
+                resampling_blocks: None,
+                bootstrap_intervals: None,
+                parametric_bootstrap_intervals: None,
+                analytical_estimate: None,
+                liability_scale: None,
+                trace_estimates: Vec::new(),
+                warnings: Vec::new(),
             });
         }
         let num_partitions = point_estimate_without_jackknife.len();
@@ -112,7 +350,7 @@ impl PartitionedJackknifeEstimates {
             .iter()
             .zip(partition_raw_estimates.iter())
             .map(|(&point_estimate, estimates)| {
-                get_jackknife_mean_and_std(point_estimate, estimates)
+                resampling.get_estimate(point_estimate, estimates)
             })
             .collect();
 
@@ -122,8 +360,10 @@ impl PartitionedJackknifeEstimates {
             .collect();
 
         let sum_estimate = {
-            if total_variance_estimates.len() > 1 {
-                Some(get_jackknife_mean_and_std(
+            if total_variance_estimates.len() > 1
+                || *resampling == Resampling::None
+            {
+                Some(resampling.get_estimate(
                     point_estimate_without_jackknife.iter().sum(),
                     &total_variance_estimates,
                 ))
@@ -140,19 +380,15 @@ impl PartitionedJackknifeEstimates {
                     .map(|(subset_key, subset_indices)| {
                         (
                             subset_key.to_string(),
-                            get_jackknife_mean_and_std(
-                                subset_indices.to_iter().fold(
-                                    0f64,
-                                    |acc, i| {
-                                        acc + point_estimate_without_jackknife
-                                            [i]
-                                    },
-                                ),
+                            resampling.get_estimate(
+                                subset_indices.iter().fold(0f64, |acc, i| {
+                                    acc + point_estimate_without_jackknife[i]
+                                }),
                                 &jackknife_iteration_estimates
                                     .iter()
                                     .map(|point_estimate| {
                                         subset_indices
-                                            .to_iter()
+                                            .iter()
                                             .fold(0f64, |acc, i| {
                                                 acc + point_estimate[i]
                                             })
@@ -165,24 +401,368 @@ impl PartitionedJackknifeEstimates {
             ),
         };
 
+        let covariance = get_jackknife_covariance(
+            jackknife_iteration_estimates,
+            num_partitions,
+            resampling,
+        );
+
+        let warnings = get_negative_variance_warnings(
+            &partition_names,
+            &partition_estimates,
+            &sum_estimate,
+        );
         Ok(PartitionedJackknifeEstimates {
             partition_names,
             partition_estimates,
             sum_estimate,
             subset_sum_estimates,
+            covariance,
+            jackknife_iteration_estimates: jackknife_iteration_estimates
+                .clone(),
+            resampling_blocks: None,
+            bootstrap_intervals: None,
+            parametric_bootstrap_intervals: None,
+            analytical_estimate: None,
+            liability_scale: None,
+            trace_estimates: Vec::new(),
+            warnings,
         })
     }
 
+    /// Computes the bootstrap confidence intervals of every partition and the
+    /// total, treating the replicate estimates as bootstrap replicates.
+    /// `leave_one_out_estimates[k][p]` is the estimate for partition `p` with
+    /// the `k`-th block left out, used for the BCa acceleration. Without
+    /// them, the acceleration is zero and the BCa interval is only bias
+    /// corrected.
+    pub fn get_bootstrap_intervals(
+        &self,
+        level: f64,
+        leave_one_out_estimates: Option<&Vec<Vec<f64>>>,
+    ) -> Result<Vec<BootstrapInterval>, String> {
+        if !(level > 0. && level < 1.) {
+            return Err(format!(
+                "the confidence level has to be in (0, 1), received {}",
+                level
+            ));
+        }
+        if self.jackknife_iteration_estimates.is_empty() {
+            return Err(
+                "no bootstrap replicates to compute the intervals from"
+                    .to_string(),
+            );
+        }
+        let num_partitions = self.partition_estimates.len();
+        if let Some(estimates) = leave_one_out_estimates {
+            if estimates.iter().any(|e| e.len() != num_partitions) {
+                return Err(format!(
+                    "the leave-one-out estimates do not all have {} partitions",
+                    num_partitions
+                ));
+            }
+        }
+        let get_interval =
+            |component: String,
+             point_estimate: f64,
+             get_value: &dyn Fn(&Vec<f64>) -> f64| {
+                let replicates: Vec<f64> = self
+                    .jackknife_iteration_estimates
+                    .iter()
+                    .map(get_value)
+                    .collect();
+                let leave_one_out: Option<Vec<f64>> = leave_one_out_estimates
+                    .map(|estimates| estimates.iter().map(get_value).collect());
+                BootstrapInterval {
+                    component,
+                    level,
+                    percentile: percentile_interval(&replicates, level),
+                    bca: bca_interval(
+                        point_estimate,
+                        &replicates,
+                        leave_one_out.as_deref(),
+                        level,
+                    ),
+                }
+            };
+        let mut intervals: Vec<BootstrapInterval> = (0..num_partitions)
+            .map(|p| {
+                get_interval(
+                    self.get_component_name(p),
+                    self.partition_estimates[p]
+                        .point_estimate_without_jackknife,
+                    &|e| e[p],
+                )
+            })
+            .collect();
+        intervals.push(get_interval(
+            "total".to_string(),
+            self.partition_estimates
+                .iter()
+                .map(|e| e.point_estimate_without_jackknife)
+                .sum(),
+            &|e| e.iter().sum(),
+        ));
+        Ok(intervals)
+    }
+
     pub fn get_partition_names(&self) -> Option<&Vec<String>> {
         match &self.partition_names {
             Some(names) => Some(names),
             None => None,
         }
     }
+
+    fn get_component_name(&self, partition_index: usize) -> String {
+        match &self.partition_names {
+            Some(names) => names[partition_index].to_string(),
+            None => format!("partition {}", partition_index),
+        }
+    }
+
+    /// Long-format `(replicate, component, estimate)` rows of the estimates
+    /// from every jackknife iteration, with a `total` component summing the
+    /// partitions of each iteration.
+    pub fn jackknife_replicate_rows(&self) -> Vec<(usize, String, f64)> {
+        let mut rows = Vec::new();
+        for (k, estimates) in
+            self.jackknife_iteration_estimates.iter().enumerate()
+        {
+            for (p, &estimate) in estimates.iter().enumerate() {
+                rows.push((k, self.get_component_name(p), estimate));
+            }
+            rows.push((k, "total".to_string(), estimates.iter().sum()));
+        }
+        rows
+    }
+
+    /// One line per component and the total, each with a one-line ASCII
+    /// histogram of the jackknife replicate estimates between their minimum
+    /// and maximum.
+    pub fn format_jackknife_histograms(&self, num_bins: usize) -> Vec<String> {
+        let num_partitions = self.partition_estimates.len();
+        if self.jackknife_iteration_estimates.is_empty() {
+            return Vec::new();
+        }
+        let mut components: Vec<(String, Vec<f64>)> = (0..num_partitions)
+            .map(|p| {
+                (
+                    self.get_component_name(p),
+                    self.jackknife_iteration_estimates
+                        .iter()
+                        .map(|estimates| estimates[p])
+                        .collect(),
+                )
+            })
+            .collect();
+        components.push((
+            "total".to_string(),
+            self.jackknife_iteration_estimates
+                .iter()
+                .map(|estimates| estimates.iter().sum())
+                .collect(),
+        ));
+        let name_width = components
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        components
+            .iter()
+            .map(|(name, values)| {
+                let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max =
+                    values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                format!(
+                    "{:<w$}  {:>.*} |{}| {:.*}",
+                    name,
+                    NUM_DISPLAY_DECIMALS,
+                    min,
+                    format_ascii_histogram(values, num_bins),
+                    NUM_DISPLAY_DECIMALS,
+                    max,
+                    w = name_width
+                )
+            })
+            .collect()
+    }
+
+    /// One row per partition, followed by the subset sums and the total.
+    /// The share of each row is relative to the total point estimate, and is
+    /// `None` if the total is zero.
+    pub fn summary_rows(&self) -> Vec<SummaryRow> {
+        let total: f64 = self
+            .partition_estimates
+            .iter()
+            .map(|e| e.point_estimate_without_jackknife)
+            .sum();
+        let mut rows: Vec<SummaryRow> = self
+            .partition_estimates
+            .iter()
+            .enumerate()
+            .map(|(i, estimate)| {
+                SummaryRow::new(
+                    self.get_component_name(i),
+                    estimate,
+                    total,
+                    false,
+                )
+            })
+            .collect();
+        if let Some(subset_sum_estimates) = &self.subset_sum_estimates {
+            for (key, estimate) in subset_sum_estimates.iter() {
+                rows.push(SummaryRow::new(
+                    format!("subset {}", key),
+                    estimate,
+                    total,
+                    false,
+                ));
+            }
+        }
+        if let Some(sum_estimate) = &self.sum_estimate {
+            rows.push(SummaryRow::new(
+                "total".to_string(),
+                sum_estimate,
+                total,
+                true,
+            ));
+        }
+        rows
+    }
+}
+
+/// z-score of the two-sided 95% normal confidence interval
+const Z_95: f64 = 1.959_963_984_540_054;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SummaryRow {
+    pub component: String,
+    pub estimate: f64,
+    pub standard_error: f64,
+    pub ci_95: (f64, f64),
+    pub share: Option<f64>,
+    pub is_total: bool,
+}
+
+impl SummaryRow {
+    fn new(
+        component: String,
+        estimate: &Estimate<f64>,
+        total: f64,
+        is_total: bool,
+    ) -> SummaryRow {
+        let point = estimate.point_estimate_without_jackknife;
+        let se = estimate.standard_error;
+        SummaryRow {
+            component,
+            estimate: point,
+            standard_error: se,
+            ci_95: (point - Z_95 * se, point + Z_95 * se),
+            share: if total == 0. {
+                None
+            } else {
+                Some(point / total)
+            },
+            is_total,
+        }
+    }
+}
+
+pub const SUMMARY_TABLE_HEADER: [&str; 5] =
+    ["component", "estimate", "SE", "95% CI", "share"];
+
+/// Formats the rows into aligned columns. The header is the first returned
+/// line, followed by one line per row.
+pub fn format_summary_table(rows: &[SummaryRow]) -> Vec<String> {
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|r| {
+            [
+                r.component.to_string(),
+                format!("{:.*}", NUM_DISPLAY_DECIMALS, r.estimate),
+                format!("{:.*}", NUM_DISPLAY_DECIMALS, r.standard_error),
+                format!(
+                    "[{:.*}, {:.*}]",
+                    NUM_DISPLAY_DECIMALS,
+                    r.ci_95.0,
+                    NUM_DISPLAY_DECIMALS,
+                    r.ci_95.1
+                ),
+                match r.share {
+                    Some(share) => format!("{:.1}%", share * 100.),
+                    None => "NA".to_string(),
+                },
+            ]
+        })
+        .collect();
+    let mut widths: Vec<usize> =
+        SUMMARY_TABLE_HEADER.iter().map(|h| h.len()).collect();
+    for row in cells.iter() {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = std::cmp::max(*w, cell.len());
+        }
+    }
+    let format_line = |row: &[String]| {
+        row.iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(i, (cell, &w))| {
+                if i == 0 {
+                    format!("{:<w$}", cell, w = w)
+                } else {
+                    format!("{:>w$}", cell, w = w)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("  ")
+    };
+    let header: Vec<String> =
+        SUMMARY_TABLE_HEADER.iter().map(|h| h.to_string()).collect();
+    let mut lines = vec![format_line(&header)];
+    for row in cells.iter() {
+        lines.push(format_line(row));
+    }
+    lines
 }
 
 const NUM_DISPLAY_DECIMALS: usize = 5;
 
+/// Characters of increasing density for the ASCII histogram bars
+const HISTOGRAM_LEVELS: &[u8] = b" .:-=+*#%@";
+
+/// Bins the `values` into `num_bins` equal-width bins between their minimum
+/// and maximum, and draws each bin as a single character whose density is
+/// proportional to the bin count relative to the largest bin.
+pub fn format_ascii_histogram(values: &[f64], num_bins: usize) -> String {
+    if values.is_empty() || num_bins == 0 {
+        return String::new();
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let mut counts = vec![0usize; num_bins];
+    for &v in values.iter() {
+        let bin = if max > min {
+            (((v - min) / (max - min)) * num_bins as f64) as usize
+        } else {
+            num_bins / 2
+        };
+        counts[std::cmp::min(bin, num_bins - 1)] += 1;
+    }
+    let max_count = *counts.iter().max().unwrap();
+    let top_level = HISTOGRAM_LEVELS.len() - 1;
+    counts
+        .iter()
+        .map(|&c| {
+            let level = if c == 0 {
+                0
+            } else {
+                std::cmp::max(1, c * top_level / max_count)
+            };
+            HISTOGRAM_LEVELS[level] as char
+        })
+        .collect()
+}
+
 impl<T: fmt::Display> fmt::Display for Estimate<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let indent = f.width().unwrap_or(0);
@@ -253,6 +833,612 @@ impl fmt::Display for PartitionedJackknifeEstimates {
                 indent = indent
             )?;
         }
+        if let Some(intervals) = &self.bootstrap_intervals {
+            for interval in intervals.iter() {
+                writeln!(
+                    f,
+                    "\n{:.0}% bootstrap confidence intervals for {}\n\
+                    {:indent$}percentile: {:.*}\n\
+                    {:indent$}BCa: {:.*}",
+                    interval.level * 100.,
+                    interval.component,
+                    "",
+                    NUM_DISPLAY_DECIMALS,
+                    interval.percentile,
+                    "",
+                    NUM_DISPLAY_DECIMALS,
+                    interval.bca,
+                    indent = indent
+                )?;
+            }
+        }
+        if let Some(intervals) = &self.parametric_bootstrap_intervals {
+            for interval in intervals.iter() {
+                writeln!(
+                    f,
+                    "\n{:.0}% parametric bootstrap interval for {} from {} \
+                    replicates\n{:indent$}percentile: {:.*}",
+                    interval.level * 100.,
+                    interval.component,
+                    interval.num_reps,
+                    "",
+                    NUM_DISPLAY_DECIMALS,
+                    interval.percentile,
+                    indent = indent
+                )?;
+            }
+        }
+        if let Some(covariance) = &self.covariance {
+            writeln!(
+                f,
+                "\njackknife covariance of the partition estimates\n{:.*}",
+                NUM_DISPLAY_DECIMALS, covariance
+            )?;
+        }
+        if let Some(analytical) = &self.analytical_estimate {
+            writeln!(f, "\nanalytical estimates\n{}", analytical)?;
+        }
+        if let Some(liability) = &self.liability_scale {
+            writeln!(f, "\ncase-control estimates\n{}", liability)?;
+        }
+        for warning in self.warnings.iter() {
+            writeln!(f, "\nWARNING {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// The variance of a partition of the SNPs, and its share of the total
+/// variance relative to its share of the SNPs.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct PartitionHeritability {
+    pub variance: f64,
+    pub standard_error: f64,
+    pub prop_of_h2: f64,
+    /// `prop_of_h2` divided by the fraction of the SNPs in the partition
+    pub enrichment: f64,
+}
+
+/// The per-partition heritability of a functional annotation, keyed by the
+/// partition names of the bim file.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct PartitionedHeritabilityEstimate {
+    /// The partition names in the order of the estimates
+    pub partition_names: Vec<String>,
+    pub partitions: HashMap<String, PartitionHeritability>,
+}
+
+impl PartitionedHeritabilityEstimate {
+    /// `partition_sizes[i]` is the number of SNPs in the i-th partition of
+    /// `estimates`.
+    pub fn new(
+        estimates: &PartitionedJackknifeEstimates,
+        partition_sizes: &[usize],
+    ) -> Result<PartitionedHeritabilityEstimate, String> {
+        let num_partitions = estimates.partition_estimates.len();
+        if partition_sizes.len() != num_partitions {
+            return Err(format!(
+                "{} partition sizes for {} partition estimates",
+                partition_sizes.len(),
+                num_partitions
+            ));
+        }
+        let partition_names: Vec<String> = (0..num_partitions)
+            .map(|i| estimates.get_component_name(i))
+            .collect();
+        let total_variance: f64 = estimates
+            .partition_estimates
+            .iter()
+            .map(|e| e.point_estimate_without_jackknife)
+            .sum();
+        let total_num_snps = partition_sizes.iter().sum::<usize>() as f64;
+        let partitions = partition_names
+            .iter()
+            .zip(estimates.partition_estimates.iter())
+            .zip(partition_sizes.iter())
+            .map(|((name, estimate), &size)| {
+                let variance = estimate.point_estimate_without_jackknife;
+                let prop_of_h2 = variance / total_variance;
+                (name.clone(), PartitionHeritability {
+                    variance,
+                    standard_error: estimate.standard_error,
+                    prop_of_h2,
+                    enrichment: prop_of_h2 / (size as f64 / total_num_snps),
+                })
+            })
+            .collect();
+        Ok(PartitionedHeritabilityEstimate {
+            partition_names,
+            partitions,
+        })
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&String, &PartitionHeritability)> {
+        self.partition_names
+            .iter()
+            .map(move |name| (name, &self.partitions[name]))
+    }
+}
+
+impl fmt::Display for PartitionedHeritabilityEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "partition\tvariance\tSE\tprop_of_h2\tenrichment")?;
+        for (name, p) in self.iter() {
+            writeln!(
+                f,
+                "{}\t{:.*}\t{:.*}\t{:.*}\t{:.*}",
+                name,
+                NUM_DISPLAY_DECIMALS,
+                p.variance,
+                NUM_DISPLAY_DECIMALS,
+                p.standard_error,
+                NUM_DISPLAY_DECIMALS,
+                p.prop_of_h2,
+                NUM_DISPLAY_DECIMALS,
+                p.enrichment
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The estimates of several phenotypes on the same genotypes, in the order of
+/// the phenotype columns they were estimated from. `partition_sizes[i]` is
+/// the number of SNPs in the i-th partition of every estimate.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct PhenoEstimateTable {
+    pub pheno_names: Vec<String>,
+    pub estimates: Vec<PartitionedJackknifeEstimates>,
+    pub partition_sizes: Vec<usize>,
+}
+
+impl PhenoEstimateTable {
+    pub fn new(
+        pheno_names: Vec<String>,
+        estimates: Vec<PartitionedJackknifeEstimates>,
+        partition_sizes: Vec<usize>,
+    ) -> PhenoEstimateTable {
+        assert_eq!(
+            pheno_names.len(),
+            estimates.len(),
+            "the number of phenotype names does not match the number of \
+            estimates"
+        );
+        PhenoEstimateTable {
+            pheno_names,
+            estimates,
+            partition_sizes,
+        }
+    }
+
+    pub fn get_partitioned_heritability(
+        &self,
+        pheno_name: &str,
+    ) -> Option<Result<PartitionedHeritabilityEstimate, String>> {
+        self.get(pheno_name).map(|est| {
+            PartitionedHeritabilityEstimate::new(est, &self.partition_sizes)
+        })
+    }
+
+    pub fn get(
+        &self,
+        pheno_name: &str,
+    ) -> Option<&PartitionedJackknifeEstimates> {
+        self.pheno_names
+            .iter()
+            .position(|name| name == pheno_name)
+            .map(|i| &self.estimates[i])
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (&String, &PartitionedJackknifeEstimates)> {
+        self.pheno_names.iter().zip(self.estimates.iter())
+    }
+
+    pub fn into_hash_map(
+        self,
+    ) -> HashMap<String, PartitionedJackknifeEstimates> {
+        self.pheno_names.into_iter().zip(self.estimates).collect()
+    }
+
+    /// One `phenotype<TAB>component<TAB>estimate<TAB>SE` row per component
+    /// of every phenotype, following the summary rows of each phenotype.
+    pub fn tsv_rows(&self) -> Vec<String> {
+        let mut rows = vec!["phenotype\tcomponent\testimate\tSE".to_string()];
+        for (name, est) in self.iter() {
+            for row in est.summary_rows() {
+                rows.push(format!(
+                    "{}\t{}\t{}\t{}",
+                    name, row.component, row.estimate, row.standard_error
+                ));
+            }
+        }
+        rows
+    }
+}
+
+impl fmt::Display for PhenoEstimateTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.tsv_rows() {
+            writeln!(f, "{}", row)?;
+        }
         Ok(())
     }
 }
+
+/// The leave-one-chromosome-out (LOCO) estimates of the total heritability
+/// of a phenotype, whose jackknife replicates each leave out a chromosome.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct LocoEstimates {
+    pub pheno_name: String,
+    pub chroms: Vec<String>,
+    /// The total heritability on the full data
+    pub total_estimate: f64,
+    /// `loco_estimates[c]` is the total heritability with chromosome `c`
+    /// left out
+    pub loco_estimates: Vec<f64>,
+    /// `chrom_estimates[c]` is the heritability attributed to chromosome
+    /// `c`, i.e. the drop of the total when it is left out
+    pub chrom_estimates: Vec<f64>,
+    /// The sum of the `chrom_estimates`
+    pub summed_estimate: f64,
+    /// The estimates with the standard errors of the jackknife over the
+    /// chromosomes
+    pub estimates: PartitionedJackknifeEstimates,
+}
+
+impl LocoEstimates {
+    pub fn new(
+        pheno_name: String,
+        chroms: Vec<String>,
+        estimates: PartitionedJackknifeEstimates,
+    ) -> Result<LocoEstimates, String> {
+        if estimates.jackknife_iteration_estimates.len() != chroms.len() {
+            return Err(format!(
+                "{} leave-one-chromosome-out estimates for {} chromosomes",
+                estimates.jackknife_iteration_estimates.len(),
+                chroms.len()
+            ));
+        }
+        let total_estimate: f64 = estimates
+            .partition_estimates
+            .iter()
+            .map(|e| e.point_estimate_without_jackknife)
+            .sum();
+        let loco_estimates: Vec<f64> = estimates
+            .jackknife_iteration_estimates
+            .iter()
+            .map(|e| e.iter().sum())
+            .collect();
+        let chrom_estimates: Vec<f64> =
+            loco_estimates.iter().map(|e| total_estimate - e).collect();
+        Ok(LocoEstimates {
+            pheno_name,
+            chroms,
+            total_estimate,
+            summed_estimate: chrom_estimates.iter().sum(),
+            loco_estimates,
+            chrom_estimates,
+            estimates,
+        })
+    }
+}
+
+/// A TSV table with one row per chromosome, followed by the summed and the
+/// total estimates
+impl fmt::Display for LocoEstimates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "chrom	h2_loco	h2_chrom")?;
+        for ((chrom, loco), h2) in self
+            .chroms
+            .iter()
+            .zip(self.loco_estimates.iter())
+            .zip(self.chrom_estimates.iter())
+        {
+            writeln!(
+                f,
+                "{}\t{:.*}\t{:.*}",
+                chrom, NUM_DISPLAY_DECIMALS, loco, NUM_DISPLAY_DECIMALS, h2
+            )?;
+        }
+        writeln!(
+            f,
+            "sum\t\t{:.*}",
+            NUM_DISPLAY_DECIMALS, self.summed_estimate
+        )?;
+        write!(
+            f,
+            "total\t\t{:.*}",
+            NUM_DISPLAY_DECIMALS, self.total_estimate
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_ascii_histogram, format_summary_table, LocoEstimates,
+        PartitionedJackknifeEstimates, PhenoEstimateTable, TraceEstimate,
+    };
+    use crate::{resampling::Resampling, warning::WarningCode};
+
+    #[test]
+    fn test_estimates_without_resampling() {
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![1., 2.],
+            &vec![],
+            None,
+            None,
+            &Resampling::None,
+        )
+        .unwrap();
+        assert_eq!(est.partition_estimates.len(), 2);
+        assert_eq!(est.sum_estimate.unwrap().bias_corrected_estimate, 3.);
+        assert!(est.partition_estimates[0].standard_error.is_nan());
+        assert_eq!(est.covariance, None);
+    }
+
+    #[test]
+    fn test_summary_rows() {
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![1., 3.],
+            &vec![],
+            None,
+            None,
+            &Resampling::None,
+        )
+        .unwrap();
+        let rows = est.summary_rows();
+        assert_eq!(rows[0].share, Some(0.25));
+        assert!(format_summary_table(&rows)[1].ends_with("25.0%"));
+
+        // the shares of a zero total are not available
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![1., -1.],
+            &vec![],
+            None,
+            None,
+            &Resampling::None,
+        )
+        .unwrap();
+        let rows = est.summary_rows();
+        assert!(rows.iter().all(|r| r.share.is_none()));
+        assert!(format_summary_table(&rows)
+            .iter()
+            .skip(1)
+            .all(|line| line.ends_with("NA")));
+    }
+
+    #[test]
+    fn test_trace_estimate() {
+        let est = TraceEstimate::from_probe_values("tr(K K)".to_string(), &[
+            9., 11., 8., 12.,
+        ]);
+        assert_eq!(est.value, 10.);
+        assert_eq!(est.num_random_vecs, 4);
+        // the sample variance is 10 / 3
+        assert!((est.mc_standard_error - (10f64 / 12.).sqrt()).abs() < 1e-12);
+        assert!(est.is_within_tolerance(0.1));
+        assert!(!est.is_within_tolerance(0.05));
+        let single =
+            TraceEstimate::from_probe_values("tr(K K)".to_string(), &[9.]);
+        assert!(!single.is_within_tolerance(1.));
+        let exact = TraceEstimate::exact("tr(K K)".to_string(), 10., 5);
+        assert!(exact.is_within_tolerance(0.));
+        // the values are linear in the controls, so the control variate
+        // removes all of their variance
+        let est = TraceEstimate::from_control_variate(
+            "tr(K K)".to_string(),
+            &[1., 3., 5.],
+            &[0., 1., 2.],
+            1.,
+        );
+        assert_eq!(est.value, 3.);
+        assert_eq!(est.mc_standard_error, 0.);
+        assert_eq!(est.variance_reduction, Some(1.));
+        assert_eq!(est.num_random_vecs, 3);
+    }
+
+    #[test]
+    fn test_negative_variance_warnings() {
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![-1., 0.5],
+            &vec![],
+            Some(vec!["a".to_string(), "b".to_string()]),
+            None,
+            &Resampling::None,
+        )
+        .unwrap();
+        assert_eq!(est.warnings.len(), 2);
+        assert_eq!(est.warnings[0].code, WarningCode::NegativeVariance);
+        assert!(est.warnings[0].message.contains("of a is negative"));
+        assert!(est.warnings[1].message.contains("of total is negative"));
+
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![1., 2.],
+            &vec![],
+            None,
+            None,
+            &Resampling::None,
+        )
+        .unwrap();
+        assert!(est.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_format_ascii_histogram() {
+        assert_eq!(
+            format_ascii_histogram(&[0., 0.1, 0.15, 0.9, 1.], 5),
+            "@   *"
+        );
+        assert_eq!(format_ascii_histogram(&[2., 2.], 3), " @ ");
+        assert_eq!(format_ascii_histogram(&[], 3), "");
+    }
+
+    #[test]
+    fn test_jackknife_replicate_rows() {
+        let est = PartitionedJackknifeEstimates::from_jackknife_estimates(
+            &vec![1., 2.],
+            &vec![vec![1., 2.5], vec![0.5, 2.]],
+            Some(vec!["a".to_string(), "b".to_string()]),
+            None,
+        )
+        .unwrap();
+        assert_eq!(est.jackknife_replicate_rows(), vec![
+            (0, "a".to_string(), 1.),
+            (0, "b".to_string(), 2.5),
+            (0, "total".to_string(), 3.5),
+            (1, "a".to_string(), 0.5),
+            (1, "b".to_string(), 2.),
+            (1, "total".to_string(), 2.5),
+        ]);
+        assert_eq!(est.format_jackknife_histograms(4).len(), 3);
+    }
+
+    #[test]
+    fn test_jackknife_covariance() {
+        let jackknife_estimates = vec![
+            vec![1., 2., 0.5],
+            vec![1.5, 1.8, 0.7],
+            vec![0.8, 2.4, 0.4],
+            vec![1.2, 2.1, 0.6],
+        ];
+        let est = PartitionedJackknifeEstimates::from_jackknife_estimates(
+            &vec![1.1, 2.0, 0.55],
+            &jackknife_estimates,
+            None,
+            None,
+        )
+        .unwrap();
+        let covariance = est.covariance.unwrap();
+        assert_eq!(covariance.dim(), (3, 3));
+        for i in 0..3 {
+            let se = est.partition_estimates[i].standard_error;
+            assert!((covariance[[i, i]] - se * se).abs() < 1e-9);
+            for j in 0..3 {
+                assert_eq!(covariance[[i, j]], covariance[[j, i]]);
+            }
+        }
+        // the first two partitions move in opposite directions
+        assert!(covariance[[0, 1]] < 0.);
+    }
+
+    #[test]
+    fn test_bootstrap_intervals() {
+        let replicates: Vec<Vec<f64>> =
+            (0..=100).map(|x| vec![x as f64, 1.]).collect();
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![50., 1.],
+            &replicates,
+            Some(vec!["a".to_string(), "b".to_string()]),
+            None,
+            &Resampling::BlockBootstrap {
+                num_blocks: 10,
+                num_reps: 101,
+            },
+        )
+        .unwrap();
+        let intervals = est.get_bootstrap_intervals(0.9, None).unwrap();
+        assert_eq!(
+            intervals
+                .iter()
+                .map(|i| i.component.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b", "total"]
+        );
+        assert!((intervals[0].percentile.lower - 5.).abs() < 1e-12);
+        assert!((intervals[0].bca.upper - 95.).abs() < 1e-4);
+        assert!((intervals[2].percentile.lower - 6.).abs() < 1e-12);
+        assert!(est.get_bootstrap_intervals(1.5, None).is_err());
+        assert!(est
+            .get_bootstrap_intervals(0.9, Some(&vec![vec![1.]]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_pheno_estimate_table() {
+        let get_est = |estimates: &[f64]| {
+            PartitionedJackknifeEstimates::from_replicate_estimates(
+                &estimates.to_vec(),
+                &vec![],
+                Some(vec!["a".to_string(), "b".to_string()]),
+                None,
+                &Resampling::None,
+            )
+            .unwrap()
+        };
+        let table = PhenoEstimateTable::new(
+            vec!["y1".to_string(), "y2".to_string()],
+            vec![get_est(&[0.1, 0.2]), get_est(&[0.3, 0.4])],
+            vec![30, 10],
+        );
+        assert_eq!(
+            table.get("y2").unwrap().partition_estimates[0]
+                .point_estimate_without_jackknife,
+            0.3
+        );
+        assert!(table.get("y3").is_none());
+
+        // a has 75% of the SNPs and 3/7 of the variance of y2
+        let partitioned =
+            table.get_partitioned_heritability("y2").unwrap().unwrap();
+        assert_eq!(partitioned.partition_names, vec!["a", "b"]);
+        let a = partitioned.partitions["a"];
+        assert!((a.prop_of_h2 - 3. / 7.).abs() < 1e-9);
+        assert!((a.enrichment - 4. / 7.).abs() < 1e-9);
+        assert!(
+            (partitioned.partitions["b"].enrichment - 16. / 7.).abs() < 1e-9
+        );
+        let rows = table.tsv_rows();
+        // the header and a, b and the total for each phenotype
+        assert_eq!(rows.len(), 7);
+        assert!(rows[1].starts_with("y1\ta\t0.1\t"));
+        assert!(rows[6].starts_with("y2\ttotal\t"));
+        let map = table.into_hash_map();
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map["y1"].partition_estimates[1].point_estimate_without_jackknife,
+            0.2
+        );
+    }
+
+    #[test]
+    fn test_loco_estimates() {
+        let est = PartitionedJackknifeEstimates::from_replicate_estimates(
+            &vec![0.2, 0.3],
+            &vec![vec![0.15, 0.25], vec![0.2, 0.2], vec![0.1, 0.3]],
+            None,
+            None,
+            &Resampling::BlockJackknife {
+                num_blocks: 3,
+            },
+        )
+        .unwrap();
+        let chroms: Vec<String> =
+            ["1", "2", "3"].iter().map(|c| c.to_string()).collect();
+        let loco =
+            LocoEstimates::new("y".to_string(), chroms.clone(), est.clone())
+                .unwrap();
+        assert!((loco.total_estimate - 0.5).abs() < 1e-10);
+        let expected = [0.1, 0.1, 0.1];
+        for ((l, h2), e) in loco
+            .loco_estimates
+            .iter()
+            .zip(loco.chrom_estimates.iter())
+            .zip(expected.iter())
+        {
+            assert!((l - 0.4).abs() < 1e-10);
+            assert!((h2 - e).abs() < 1e-10);
+        }
+        assert!((loco.summed_estimate - 0.3).abs() < 1e-10);
+        assert_eq!(format!("{}", loco).lines().count(), 6);
+
+        assert!(
+            LocoEstimates::new("y".to_string(), chroms[..2].to_vec(), est)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Reporting the progress of the estimators, which print to stdout through
+//! `StdoutProgress` in the binaries and can be silenced with `SilentProgress`
+//! when saber is embedded as a library.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_NUM_PROGRESS_LINES: usize = 100;
+
+/// The callbacks of the estimators. Every callback does nothing by default,
+/// so that an implementation only overrides the ones it is interested in.
+/// The callbacks can be made from the rayon worker threads.
+pub trait ProgressSink: Sync {
+    /// A phase of the estimation starts, e.g. streaming the genotypes for
+    /// the products with the trace probes
+    fn phase_start(&self, _phase: &str) {}
+
+    fn phase_end(&self, _phase: &str) {}
+
+    /// `completed` of the `total` steps of the `phase` are done, e.g. the
+    /// SNP chunks streamed or the resampling replicates solved
+    fn progress(&self, _phase: &str, _completed: usize, _total: usize) {}
+
+    /// An intermediate trace estimate, e.g. `tr(K_i K_j)`
+    fn trace_value(&self, _name: &str, _value: f64) {}
+
+    /// Any other information on the estimation, e.g. the problem dimensions
+    /// or a warning
+    fn message(&self, _message: &str) {}
+}
+
+/// Prints the progress to stdout in the format of the binaries. Phases of
+/// many steps, such as the SNP chunks, are printed at most
+/// `MAX_NUM_PROGRESS_LINES` times.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct StdoutProgress;
+
+impl ProgressSink for StdoutProgress {
+    fn phase_start(&self, phase: &str) {
+        println!("=> {}", phase);
+    }
+
+    fn phase_end(&self, phase: &str) {
+        println!("=> done {}", phase);
+    }
+
+    fn progress(&self, phase: &str, completed: usize, total: usize) {
+        let step = std::cmp::max(1, total / MAX_NUM_PROGRESS_LINES);
+        if completed % step == 0 || completed == total {
+            println!("=> {} [{}/{}]", phase, completed, total);
+        }
+    }
+
+    fn trace_value(&self, name: &str, value: f64) {
+        println!("{}: {}", name, value);
+    }
+
+    fn message(&self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+/// Reports nothing
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct SilentProgress;
+
+impl ProgressSink for SilentProgress {}
+
+/// Counts the steps of a phase that are completed in parallel, and reports
+/// the count to the `sink` after each step.
+pub struct ProgressCounter<'a> {
+    sink: &'a dyn ProgressSink,
+    phase: &'a str,
+    total: usize,
+    completed: AtomicUsize,
+}
+
+impl<'a> ProgressCounter<'a> {
+    pub fn new(
+        sink: &'a dyn ProgressSink,
+        phase: &'a str,
+        total: usize,
+    ) -> ProgressCounter<'a> {
+        ProgressCounter {
+            sink,
+            phase,
+            total,
+            completed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn increment(&self) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        self.sink.progress(self.phase, completed, self.total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rayon::prelude::*;
+
+    use super::{ProgressCounter, ProgressSink, SilentProgress};
+
+    #[derive(Default)]
+    struct RecordingProgress {
+        events: Mutex<Vec<(usize, usize)>>,
+    }
+
+    impl ProgressSink for RecordingProgress {
+        fn progress(&self, _phase: &str, completed: usize, total: usize) {
+            self.events.lock().unwrap().push((completed, total));
+        }
+    }
+
+    #[test]
+    fn test_progress_counter() {
+        let sink = RecordingProgress::default();
+        let counter = ProgressCounter::new(&sink, "streaming", 20);
+        (0..20).into_par_iter().for_each(|_| counter.increment());
+        let mut events = sink.events.lock().unwrap().clone();
+        events.sort();
+        assert_eq!(events, (1..=20).map(|i| (i, 20)).collect::<Vec<_>>());
+
+        // the default callbacks do nothing
+        let silent = SilentProgress;
+        silent.phase_start("streaming");
+        ProgressCounter::new(&silent, "streaming", 1).increment();
+    }
+}
@@ -0,0 +1,679 @@
+//! Stochastic average information REML (AI-REML) estimates of the variance
+//! components, as an alternative to the method of moments estimates in
+//! `heritability_estimator`.
+//!
+//! The model is `y ~ N(1 mu, V)` with `V = sum_k s_k K_k + s_e I`, where
+//! `K_k = X_k X_k^T / m_k` for the standardized genotypes `X_k` of the `m_k`
+//! SNPs of component `k`. `V` is never formed. Its products stream the
+//! genotypes from a `GenotypeSource`, and the solves against `V` use
+//! conjugate gradients. With the projection
+//! `P = V^-1 - V^-1 1 (1^T V^-1 1)^-1 1^T V^-1`, every iteration updates the
+//! variances by the average information step `s += AI^-1 score`, where
+//!
+//! - `score_k = (y^T P K_k P y - tr(P K_k)) / 2`, with the trace estimated
+//!   from `num_random_vecs` Rademacher probes `z` as the mean of
+//!   `(P z)^T (K_k z)`
+//! - `AI_kl = (K_k P y)^T P (K_l P y) / 2`
+//!
+//! The standard errors are the square roots of the diagonal of `AI^-1`.
+//!
+//! `estimate_heritability_reml_from_grms` fits the same model with the
+//! kernels given as precomputed GRMs, e.g. exported by `grm::write_gcta_grm`
+//! or by GCTA, which are multiplied in memory instead of streamed.
+
+use std::fmt;
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{s, Array, Axis, Ix1, Ix2};
+use ndarray_linalg::Inverse;
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    grm::Grm,
+    linear_operator::{KernelSum, LinearOperator, StreamedGrm},
+    stochastic::GenotypeSource,
+    util::matrix_util::{generate_sign_matrix, normalize_vector_inplace},
+    warning::{Warning, WarningCode},
+};
+
+pub const NOISE_COMPONENT_NAME: &str = "noise";
+pub const DEFAULT_REML_MAX_ITERATIONS: usize = 20;
+/// The iterations stop once no variance changes by more than this fraction
+/// of the total variance.
+pub const DEFAULT_REML_TOLERANCE: f64 = 1e-4;
+pub const DEFAULT_CG_TOLERANCE: f64 = 1e-5;
+pub const DEFAULT_CG_MAX_ITERATIONS: usize = 500;
+/// The variances are kept at or above this fraction of the phenotypic
+/// variance, as the REML likelihood is only defined for a positive definite
+/// `V`.
+const MIN_VARIANCE: f64 = 1e-6;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct RemlConfig {
+    pub num_random_vecs: usize,
+    pub max_iterations: usize,
+    pub tolerance: f64,
+    pub cg_tolerance: f64,
+    pub cg_max_iterations: usize,
+    pub num_snps_per_chunk: Option<usize>,
+    /// Seeds the probes of the traces in the REML scores, so that the
+    /// estimates are reproducible regardless of the number of threads
+    pub seed: Option<u64>,
+}
+
+impl RemlConfig {
+    pub fn new(num_random_vecs: usize) -> RemlConfig {
+        RemlConfig {
+            num_random_vecs,
+            max_iterations: DEFAULT_REML_MAX_ITERATIONS,
+            tolerance: DEFAULT_REML_TOLERANCE,
+            cg_tolerance: DEFAULT_CG_TOLERANCE,
+            cg_max_iterations: DEFAULT_CG_MAX_ITERATIONS,
+            num_snps_per_chunk: None,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct CgDiagnostics {
+    pub num_iterations: usize,
+    /// The largest residual norm over the right hand sides, relative to the
+    /// norm of the right hand side
+    pub max_relative_residual: f64,
+}
+
+/// The state after an AI-REML iteration, for diagnosing the convergence
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RemlIteration {
+    pub variances: Vec<f64>,
+    pub score: Vec<f64>,
+    /// The largest change of a variance relative to the total variance
+    pub max_relative_change: f64,
+    pub cg: CgDiagnostics,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RemlEstimates {
+    /// The names of the components, with the noise component last
+    pub component_names: Vec<String>,
+    pub variances: Vec<f64>,
+    pub variance_standard_errors: Vec<f64>,
+    /// The share of the total variance of each component other than the
+    /// noise, with the standard errors by the delta method
+    pub heritabilities: Vec<f64>,
+    pub heritability_standard_errors: Vec<f64>,
+    pub total_heritability: f64,
+    pub total_heritability_standard_error: f64,
+    pub converged: bool,
+    pub iterations: Vec<RemlIteration>,
+    pub warnings: Vec<Warning>,
+}
+
+impl fmt::Display for RemlEstimates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.converged {
+            writeln!(
+                f,
+                "REML converged after {} iterations",
+                self.iterations.len()
+            )?;
+        } else {
+            writeln!(
+                f,
+                "REML did not converge in {} iterations",
+                self.iterations.len()
+            )?;
+        }
+        for (i, name) in self.component_names.iter().enumerate() {
+            write!(
+                f,
+                "{}: variance {:.5} (SE {:.5})",
+                name, self.variances[i], self.variance_standard_errors[i]
+            )?;
+            if i < self.heritabilities.len() {
+                write!(
+                    f,
+                    ", h2 {:.5} (SE {:.5})",
+                    self.heritabilities[i],
+                    self.heritability_standard_errors[i]
+                )?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "total h2: {:.5} (SE {:.5})",
+            self.total_heritability, self.total_heritability_standard_error
+        )?;
+        for warning in self.warnings.iter() {
+            write!(f, "\nWARNING {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Solves `A X = B` for a symmetric positive definite `A` given by its
+/// product `apply_a`. The columns of `B` are solved for together, so that
+/// every iteration takes a single product with `A`, e.g. a single pass over
+/// the genotypes. A column stops being updated once its residual falls below
+/// `tolerance` relative to its right hand side.
+pub fn conjugate_gradient<F>(
+    apply_a: F,
+    b: &Array<f64, Ix2>,
+    tolerance: f64,
+    max_iterations: usize,
+) -> (Array<f64, Ix2>, CgDiagnostics)
+where
+    F: Fn(&Array<f64, Ix2>) -> Array<f64, Ix2>, {
+    let num_cols = b.dim().1;
+    let mut x = Array::<f64, Ix2>::zeros(b.dim());
+    let mut residual = b.clone();
+    let mut direction = b.clone();
+    let b_norms: Vec<f64> = b
+        .gencolumns()
+        .into_iter()
+        .map(|col| {
+            let norm = col.dot(&col).sqrt();
+            if norm > 0. {
+                norm
+            } else {
+                1.
+            }
+        })
+        .collect();
+    let mut residual_ssq: Vec<f64> = residual
+        .gencolumns()
+        .into_iter()
+        .map(|col| col.dot(&col))
+        .collect();
+    let relative_residual = |ssq: &[f64], j: usize| ssq[j].sqrt() / b_norms[j];
+    let max_relative_residual = |ssq: &[f64]| {
+        (0..num_cols)
+            .map(|j| relative_residual(ssq, j))
+            .fold(0., f64::max)
+    };
+    let mut num_iterations = 0;
+    while num_iterations < max_iterations
+        && max_relative_residual(&residual_ssq) > tolerance
+    {
+        let a_direction = apply_a(&direction);
+        for j in 0..num_cols {
+            if relative_residual(&residual_ssq, j) <= tolerance {
+                continue;
+            }
+            let alpha = residual_ssq[j]
+                / direction.column(j).dot(&a_direction.column(j));
+            x.column_mut(j).scaled_add(alpha, &direction.column(j));
+            residual
+                .column_mut(j)
+                .scaled_add(-alpha, &a_direction.column(j));
+            let ssq = residual.column(j).dot(&residual.column(j));
+            let beta = ssq / residual_ssq[j];
+            let mut d = direction.column_mut(j);
+            d *= beta;
+            d += &residual.column(j);
+            residual_ssq[j] = ssq;
+        }
+        num_iterations += 1;
+    }
+    (x, CgDiagnostics {
+        num_iterations,
+        max_relative_residual: max_relative_residual(&residual_ssq),
+    })
+}
+
+/// The products with the normalized component kernels and with `V`
+struct Kernels<'a> {
+    kernels: Vec<Box<dyn LinearOperator + 'a>>,
+}
+
+impl<'a> Kernels<'a> {
+    fn num_components(&self) -> usize {
+        self.kernels.len()
+    }
+
+    fn k_dot(&self, k: usize, matrix: &Array<f64, Ix2>) -> Array<f64, Ix2> {
+        self.kernels[k]
+            .apply(&matrix.mapv(|x| x as f32))
+            .mapv(|x| x as f64)
+    }
+
+    /// `variances` has the noise variance last. `V` is applied as a
+    /// `KernelSum`, i.e. with a single product per kernel for all the
+    /// columns.
+    fn v_dot(
+        &self,
+        variances: &[f64],
+        matrix: &Array<f64, Ix2>,
+    ) -> Array<f64, Ix2> {
+        let v = KernelSum::new(
+            self.kernels
+                .iter()
+                .zip(variances.iter())
+                .map(|(k, &var)| (var as f32, k.as_ref()))
+                .collect(),
+            variances[self.num_components()] as f32,
+        )
+        .unwrap();
+        v.apply(&matrix.mapv(|x| x as f32)).mapv(|x| x as f64)
+    }
+}
+
+/// Applies `P` to the columns of `B` given `V^-1 B` and `V^-1 1`, using
+/// `1^T V^-1 b = sum(V^-1 b)` for the symmetric `V`.
+fn project(
+    v_inv_b: &Array<f64, Ix2>,
+    v_inv_one: &Array<f64, Ix1>,
+) -> Array<f64, Ix2> {
+    let one_v_inv_one = v_inv_one.sum();
+    let weights = v_inv_b.sum_axis(Axis(0)) / one_v_inv_one;
+    let mut projected = v_inv_b.clone();
+    for (mut col, &w) in
+        projected.gencolumns_mut().into_iter().zip(weights.iter())
+    {
+        col.scaled_add(-w, v_inv_one);
+    }
+    projected
+}
+
+/// `components` are the names and SNP ranges of the genetic components, to
+/// which the noise component is added. The phenotype is standardized first,
+/// so the variances are in units of the phenotypic variance.
+pub fn estimate_heritability_reml<G: GenotypeSource>(
+    geno: &G,
+    components: Vec<(String, OrderedIntegerSet<usize>)>,
+    pheno: &Array<f32, Ix1>,
+    config: &RemlConfig,
+) -> Result<RemlEstimates, Error> {
+    let mut component_names = Vec::new();
+    let mut kernels: Vec<Box<dyn LinearOperator + '_>> = Vec::new();
+    for (name, range) in components.into_iter() {
+        if range.size() == 0 {
+            return Err(Error::Generic(format!(
+                "component {} has no SNPs",
+                name
+            )));
+        }
+        component_names.push(name);
+        kernels.push(Box::new(StreamedGrm::new(
+            geno,
+            Some(range),
+            None,
+            config.num_snps_per_chunk,
+        )));
+    }
+    estimate_reml_variances(
+        geno.num_people(),
+        component_names,
+        Kernels {
+            kernels,
+        },
+        pheno,
+        config,
+    )
+}
+
+/// The REML estimates of `estimate_heritability_reml` with the precomputed
+/// GRMs of the `components`, e.g. read from GCTA files with
+/// `Grm::from_gcta_files`, in place of the streamed genotypes
+pub fn estimate_heritability_reml_from_grms(
+    components: &[(String, Grm)],
+    pheno: &Array<f32, Ix1>,
+    config: &RemlConfig,
+) -> Result<RemlEstimates, Error> {
+    let num_people = match components.first() {
+        Some((_, grm)) => grm.num_people(),
+        None => {
+            return Err(Error::Generic(
+                "expected at least one genetic component".to_string(),
+            ))
+        }
+    };
+    let mut kernels: Vec<Box<dyn LinearOperator + '_>> = Vec::new();
+    for (name, grm) in components.iter() {
+        if grm.num_people() != num_people {
+            return Err(Error::DimensionMismatch(format!(
+                "the GRM of component {} has {} people, expected {}",
+                name,
+                grm.num_people(),
+                num_people
+            )));
+        }
+        kernels.push(Box::new(grm));
+    }
+    estimate_reml_variances(
+        num_people,
+        components.iter().map(|(name, _)| name.clone()).collect(),
+        Kernels {
+            kernels,
+        },
+        pheno,
+        config,
+    )
+}
+
+fn estimate_reml_variances(
+    num_people: usize,
+    mut component_names: Vec<String>,
+    kernels: Kernels<'_>,
+    pheno: &Array<f32, Ix1>,
+    config: &RemlConfig,
+) -> Result<RemlEstimates, Error> {
+    if pheno.len() != num_people {
+        return Err(Error::DimensionMismatch(format!(
+            "expected a phenotype of length {}, received {}",
+            num_people,
+            pheno.len()
+        )));
+    }
+    if component_names.is_empty() {
+        return Err(Error::Generic(
+            "expected at least one genetic component".to_string(),
+        ));
+    }
+    if config.num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    component_names.push(NOISE_COMPONENT_NAME.to_string());
+    let num_components = kernels.num_components();
+    let num_params = num_components + 1;
+    let num_random_vecs = config.num_random_vecs;
+
+    let mut y = pheno.mapv(|x| x as f64);
+    normalize_vector_inplace(&mut y, 0);
+    let probes =
+        generate_sign_matrix(num_people, num_random_vecs, None, config.seed)
+            .mapv(|x| x as f64);
+    // K_k Z stays the same across the iterations
+    let kz: Vec<Array<f64, Ix2>> = (0..num_components)
+        .map(|k| kernels.k_dot(k, &probes))
+        .collect();
+    // the columns 1, y and Z to solve for in every iteration
+    let mut rhs = Array::<f64, Ix2>::zeros((num_people, num_random_vecs + 2));
+    rhs.column_mut(0).fill(1.);
+    rhs.column_mut(1).assign(&y);
+    rhs.slice_mut(s![.., 2..]).assign(&probes);
+
+    let mut variances = vec![0.5 / num_components as f64; num_components];
+    variances.push(0.5);
+    let mut iterations: Vec<RemlIteration> = Vec::new();
+    let mut converged = false;
+    let mut ai_inv = Array::<f64, Ix2>::zeros((num_params, num_params));
+    while iterations.len() < config.max_iterations {
+        let (v_inv_rhs, cg_rhs) = conjugate_gradient(
+            |m| kernels.v_dot(&variances, m),
+            &rhs,
+            config.cg_tolerance,
+            config.cg_max_iterations,
+        );
+        let v_inv_one = v_inv_rhs.column(0).to_owned();
+        let p_rhs =
+            project(&v_inv_rhs.slice(s![.., 1..]).to_owned(), &v_inv_one);
+        let py = p_rhs.column(0).to_owned();
+        let pz = p_rhs.slice(s![.., 1..]);
+
+        // u_k = K_k P y, with u = P y for the noise component
+        let mut u = Array::<f64, Ix2>::zeros((num_people, num_params));
+        let py_matrix = py.clone().into_shape((num_people, 1)).unwrap();
+        for k in 0..num_components {
+            u.column_mut(k)
+                .assign(&kernels.k_dot(k, &py_matrix).column(0));
+        }
+        u.column_mut(num_components).assign(&py);
+        let score: Vec<f64> = (0..num_params)
+            .map(|k| {
+                let kz_k = if k < num_components { &kz[k] } else { &probes };
+                let trace = (&pz * kz_k).sum() / num_random_vecs as f64;
+                (py.dot(&u.column(k)) - trace) / 2.
+            })
+            .collect();
+
+        let (v_inv_u, cg_u) = conjugate_gradient(
+            |m| kernels.v_dot(&variances, m),
+            &u,
+            config.cg_tolerance,
+            config.cg_max_iterations,
+        );
+        let pu = project(&v_inv_u, &v_inv_one);
+        let ai = u.t().dot(&pu) / 2.;
+        let ai = (&ai + &ai.t()) / 2.;
+        ai_inv = ai.inv().map_err(|why| {
+            format!("the average information matrix is singular: {:?}", why)
+        })?;
+        let step = ai_inv.dot(&Array::from_vec(score.clone()));
+
+        let updated: Vec<f64> = variances
+            .iter()
+            .zip(step.iter())
+            .map(|(v, d)| (v + d).max(MIN_VARIANCE))
+            .collect();
+        let total: f64 = updated.iter().sum();
+        let max_relative_change = variances
+            .iter()
+            .zip(updated.iter())
+            .map(|(old, new)| (new - old).abs() / total)
+            .fold(0., f64::max);
+        variances = updated;
+        iterations.push(RemlIteration {
+            variances: variances.clone(),
+            score,
+            max_relative_change,
+            cg: CgDiagnostics {
+                num_iterations: cg_rhs.num_iterations + cg_u.num_iterations,
+                max_relative_residual: cg_rhs
+                    .max_relative_residual
+                    .max(cg_u.max_relative_residual),
+            },
+        });
+        if max_relative_change < config.tolerance {
+            converged = true;
+            break;
+        }
+    }
+
+    let variance_standard_errors: Vec<f64> =
+        (0..num_params).map(|k| ai_inv[[k, k]].sqrt()).collect();
+    let total: f64 = variances.iter().sum();
+    let genetic: f64 = variances[..num_components].iter().sum();
+    // the delta method with the gradient of a ratio of the variances
+    let delta_method_se =
+        |gradient: &Array<f64, Ix1>| gradient.dot(&ai_inv.dot(gradient)).sqrt();
+    let mut heritabilities = Vec::new();
+    let mut heritability_standard_errors = Vec::new();
+    for k in 0..num_components {
+        heritabilities.push(variances[k] / total);
+        let gradient = Array::from_shape_fn(num_params, |j| {
+            (if j == k { total } else { 0. } - variances[k]) / (total * total)
+        });
+        heritability_standard_errors.push(delta_method_se(&gradient));
+    }
+    let total_gradient = Array::from_shape_fn(num_params, |j| {
+        (if j < num_components { total } else { 0. } - genetic)
+            / (total * total)
+    });
+
+    let mut warnings = Vec::new();
+    if !converged {
+        warnings.push(Warning::new(
+            WarningCode::NotConverged,
+            format!(
+                "REML did not converge in {} iterations",
+                config.max_iterations
+            ),
+        ));
+    }
+    for (name, v) in component_names.iter().zip(variances.iter()) {
+        if *v <= MIN_VARIANCE {
+            warnings.push(Warning::new(
+                WarningCode::NegativeVariance,
+                format!(
+                    "the REML estimate of {} is at the lower bound {}, the \
+                    unconstrained estimate is likely negative",
+                    name, MIN_VARIANCE
+                ),
+            ));
+        }
+    }
+    Ok(RemlEstimates {
+        component_names,
+        variance_standard_errors,
+        heritabilities,
+        heritability_standard_errors,
+        total_heritability: genetic / total,
+        total_heritability_standard_error: delta_method_se(&total_gradient),
+        variances,
+        converged,
+        iterations,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::StandardNormal;
+
+    use super::{
+        conjugate_gradient, estimate_heritability_reml,
+        estimate_heritability_reml_from_grms, RemlConfig,
+    };
+    use crate::{
+        grm::Grm, util::matrix_util::normalize_matrix_columns_inplace,
+    };
+
+    #[test]
+    fn test_conjugate_gradient() {
+        let a = array![[4., 1., 0.], [1., 3., 1.], [0., 1., 2.]];
+        let x = array![[1., -2.], [2., 0.], [-1., 5.]];
+        let b = a.dot(&x);
+        let (solution, diagnostics) =
+            conjugate_gradient(|m| a.dot(m), &b, 1e-10, 100);
+        assert!(diagnostics.max_relative_residual <= 1e-10);
+        assert!(diagnostics.num_iterations <= 5);
+        for (s, e) in solution.iter().zip(x.iter()) {
+            assert!((s - e).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_estimate_heritability_reml() {
+        let (num_people, num_snps) = (1000, 400);
+        let geno: Array<f32, Ix2> =
+            Array::random((num_people, num_snps), StandardNormal).mapv(|x| {
+                if x > 0.5 {
+                    1.
+                } else if x > -0.5 {
+                    0.
+                } else {
+                    2.
+                }
+            });
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let effects = Array::random(num_snps, StandardNormal)
+            .mapv(|b| (b * (0.6 / num_snps as f64).sqrt()) as f32);
+        let noise = Array::random(num_people, StandardNormal)
+            .mapv(|e| (e * 0.4f64.sqrt()) as f32);
+        let pheno = x.dot(&effects) + &noise;
+
+        let est = estimate_heritability_reml(
+            &geno,
+            vec![(
+                "G".to_string(),
+                OrderedIntegerSet::from_slice(&[[0, num_snps - 1]]),
+            )],
+            &pheno,
+            &RemlConfig::new(20),
+        )
+        .unwrap();
+        assert_eq!(est.component_names, vec!["G", "noise"]);
+        assert!(est.converged, "{}", est);
+        assert!(est.heritability_standard_errors[0] > 0.);
+        assert!(
+            (est.total_heritability - 0.6).abs()
+                < 4. * est.total_heritability_standard_error + 0.05,
+            "{}",
+            est
+        );
+        assert!(est
+            .iterations
+            .iter()
+            .all(|i| i.cg.max_relative_residual <= 1e-5));
+    }
+
+    #[test]
+    fn test_estimate_heritability_reml_from_grms() {
+        let (num_people, num_snps) = (800, 300);
+        let mut x: Array<f32, Ix2> =
+            Array::random((num_people, num_snps), StandardNormal).mapv(|x| {
+                if x > 0. {
+                    1.
+                } else {
+                    0.
+                }
+            });
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let effects = Array::random(num_snps, StandardNormal)
+            .mapv(|b| (b * (0.5 / num_snps as f64).sqrt()) as f32);
+        let noise = Array::random(num_people, StandardNormal)
+            .mapv(|e| (e * 0.5f64.sqrt()) as f32);
+        let pheno = x.dot(&effects) + &noise;
+
+        let k = x.dot(&x.t()) / num_snps as f32;
+        let mut lower_triangle = Vec::new();
+        for i in 0..num_people {
+            for j in 0..=i {
+                lower_triangle.push(k[[i, j]]);
+            }
+        }
+        let grm = Grm::new(num_people, lower_triangle).unwrap();
+        let est = estimate_heritability_reml_from_grms(
+            &[("G".to_string(), grm.clone())],
+            &pheno,
+            &RemlConfig::new(20),
+        )
+        .unwrap();
+        assert_eq!(est.component_names, vec!["G", "noise"]);
+        assert!(est.converged, "{}", est);
+        assert!(
+            (est.total_heritability - 0.5).abs()
+                < 4. * est.total_heritability_standard_error + 0.05,
+            "{}",
+            est
+        );
+
+        // the seeded probes give the same estimates on every run
+        let mut config = RemlConfig::new(20);
+        config.seed = Some(3);
+        let seeded = || {
+            estimate_heritability_reml_from_grms(
+                &[("G".to_string(), grm.clone())],
+                &pheno,
+                &config,
+            )
+            .unwrap()
+            .total_heritability
+        };
+        assert_eq!(seeded(), seeded());
+
+        let small = Grm::new(2, vec![1., 0., 1.]).unwrap();
+        assert!(estimate_heritability_reml_from_grms(
+            &[("G".to_string(), small)],
+            &pheno,
+            &RemlConfig::new(20),
+        )
+        .is_err());
+        assert!(estimate_heritability_reml_from_grms(
+            &[],
+            &pheno,
+            &RemlConfig::new(20)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use std::{fmt, iter::Sum};
+
+use math::{
+    set::ordered_integer_set::OrderedIntegerSet, stats::standard_deviation,
+};
+use num::{FromPrimitive, Integer, ToPrimitive};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    integer_set::ops::Union, jackknife::JackknifePartitions,
+    partitioned_jackknife_estimates::Estimate,
+};
+
+pub const DEFAULT_NUM_BOOTSTRAP_REPS: usize = 100;
+
+/// How the SNPs are resampled to estimate the standard errors. The SNPs in
+/// each component are divided into blocks, and every replicate estimate is
+/// computed on a reweighting of the blocks.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Resampling {
+    /// Leaves out each of `num_partitions` random subsets of the SNPs in turn
+    RandomSubsetJackknife { num_partitions: usize },
+    /// Leaves out each of `num_blocks` contiguous SNP blocks in turn
+    BlockJackknife { num_blocks: usize },
+    /// Draws `num_blocks` out of the `num_blocks` contiguous SNP blocks with
+    /// replacement, `num_reps` times
+    BlockBootstrap { num_blocks: usize, num_reps: usize },
+    /// Point estimates only, the standard errors are NaN
+    None,
+}
+
+/// A jackknife over the SNPs, leaving out each of `num_blocks` contiguous
+/// SNP blocks or random subsets of the SNPs in turn
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct JackknifeConfig {
+    pub num_blocks: usize,
+    pub random_subsets: bool,
+}
+
+impl JackknifeConfig {
+    pub fn blocks(num_blocks: usize) -> JackknifeConfig {
+        JackknifeConfig {
+            num_blocks,
+            random_subsets: false,
+        }
+    }
+
+    pub fn random_subsets(num_partitions: usize) -> JackknifeConfig {
+        JackknifeConfig {
+            num_blocks: num_partitions,
+            random_subsets: true,
+        }
+    }
+}
+
+impl From<JackknifeConfig> for Resampling {
+    fn from(config: JackknifeConfig) -> Resampling {
+        if config.random_subsets {
+            Resampling::RandomSubsetJackknife {
+                num_partitions: config.num_blocks,
+            }
+        } else {
+            Resampling::BlockJackknife {
+                num_blocks: config.num_blocks,
+            }
+        }
+    }
+}
+
+/// A replicate of the data in terms of the SNP blocks
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Replicate {
+    /// All the blocks, i.e. the full data
+    Full,
+    /// All the blocks except the one with this index
+    LeaveOneOut(usize),
+    /// Every block weighted by the number of times it is drawn
+    Weighted(Vec<f64>),
+}
+
+impl Replicate {
+    /// `block_values[b]` is the value of an additive quantity on block `b`.
+    /// Returns the value of the quantity on the replicate.
+    pub fn weighted_sum(&self, block_values: &[f64]) -> f64 {
+        match self {
+            Replicate::Full => block_values.iter().sum(),
+            Replicate::LeaveOneOut(k) => {
+                block_values.iter().sum::<f64>() - block_values[*k]
+            }
+            Replicate::Weighted(weights) => weights
+                .iter()
+                .zip(block_values.iter())
+                .map(|(w, v)| w * v)
+                .sum(),
+        }
+    }
+}
+
+/// The SNP blocks of a resampling and its replicates, so that the estimate
+/// of every replicate can be traced back to the SNPs it is computed on.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ResamplingBlocks {
+    /// The intervals of the SNPs in each block, by their line in the bim
+    pub blocks: Vec<Vec<[usize; 2]>>,
+    /// The `k`-th replicate estimate is computed on `replicates[k]`
+    pub replicates: Vec<Replicate>,
+}
+
+impl ResamplingBlocks {
+    pub fn new(
+        blocks: &[OrderedIntegerSet<usize>],
+        replicates: Vec<Replicate>,
+    ) -> ResamplingBlocks {
+        ResamplingBlocks {
+            blocks: blocks
+                .iter()
+                .map(|block| {
+                    block
+                        .get_intervals_by_ref()
+                        .iter()
+                        .map(|i| [i.get_start(), i.get_end()])
+                        .collect()
+                })
+                .collect(),
+            replicates,
+        }
+    }
+
+    /// The SNPs in the blocks of positive weight in the `k`-th replicate, or
+    /// `None` if there is no such replicate
+    pub fn get_replicate_snps(
+        &self,
+        k: usize,
+    ) -> Option<OrderedIntegerSet<usize>> {
+        let replicate = self.replicates.get(k)?;
+        let block_in_replicate = |b: usize| match replicate {
+            Replicate::Full => true,
+            Replicate::LeaveOneOut(left_out) => b != *left_out,
+            Replicate::Weighted(weights) => weights[b] > 0.,
+        };
+        Some(
+            self.blocks
+                .iter()
+                .enumerate()
+                .filter(|(b, _)| block_in_replicate(*b))
+                .fold(OrderedIntegerSet::new(), |acc, (_, intervals)| {
+                    acc.union(&OrderedIntegerSet::from_slice(intervals))
+                }),
+        )
+    }
+}
+
+impl Resampling {
+    /// `name` is one of `random-subset`, `block-jackknife`, `block-bootstrap`
+    /// and `none`. `num_reps` is only used by the bootstrap.
+    pub fn from_str(
+        name: &str,
+        num_blocks: usize,
+        num_reps: usize,
+    ) -> Result<Resampling, String> {
+        let resampling = match name {
+            "random-subset" => Resampling::RandomSubsetJackknife {
+                num_partitions: num_blocks,
+            },
+            "block-jackknife" => Resampling::BlockJackknife {
+                num_blocks,
+            },
+            "block-bootstrap" => Resampling::BlockBootstrap {
+                num_blocks,
+                num_reps,
+            },
+            "none" => Resampling::None,
+            _ => {
+                return Err(format!(
+                    "unrecognized resampling {}, expected one of \
+                    random-subset, block-jackknife, block-bootstrap and none",
+                    name
+                ))
+            }
+        };
+        if resampling.num_blocks() < 1 {
+            return Err(format!("{} requires at least one block", name));
+        }
+        Ok(resampling)
+    }
+
+    /// The number of SNP blocks the estimators have to keep track of. Without
+    /// resampling, all the SNPs form a single block.
+    pub fn num_blocks(&self) -> usize {
+        match self {
+            Resampling::RandomSubsetJackknife {
+                num_partitions,
+            } => *num_partitions,
+            Resampling::BlockJackknife {
+                num_blocks,
+            } => *num_blocks,
+            Resampling::BlockBootstrap {
+                num_blocks, ..
+            } => *num_blocks,
+            Resampling::None => 1,
+        }
+    }
+
+    pub fn is_jackknife(&self) -> bool {
+        match self {
+            Resampling::RandomSubsetJackknife {
+                ..
+            }
+            | Resampling::BlockJackknife {
+                ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_bootstrap(&self) -> bool {
+        match self {
+            Resampling::BlockBootstrap {
+                ..
+            } => true,
+            _ => false,
+        }
+    }
+
+    /// Divides the `integer_sets` into the SNP blocks. The random subsets of
+    /// the `RandomSubsetJackknife` are reproducible if `seed` is `Some`.
+    pub fn get_blocks<
+        T: Copy + std::fmt::Debug + FromPrimitive + Integer + Sum + ToPrimitive,
+    >(
+        &self,
+        integer_sets: Vec<OrderedIntegerSet<T>>,
+        seed: Option<u64>,
+    ) -> JackknifePartitions<T> {
+        let randomize = match self {
+            Resampling::RandomSubsetJackknife {
+                ..
+            } => true,
+            _ => false,
+        };
+        match seed {
+            Some(seed) if randomize => {
+                JackknifePartitions::from_integer_set_with_rng(
+                    integer_sets,
+                    self.num_blocks(),
+                    &mut StdRng::seed_from_u64(seed),
+                )
+            }
+            _ => JackknifePartitions::from_integer_set(
+                integer_sets,
+                self.num_blocks(),
+                randomize,
+            ),
+        }
+    }
+
+    /// The replicates to estimate on in addition to the full data
+    pub fn get_replicates<R: Rng>(&self, rng: &mut R) -> Vec<Replicate> {
+        match self {
+            Resampling::RandomSubsetJackknife {
+                num_partitions: n,
+            }
+            | Resampling::BlockJackknife {
+                num_blocks: n,
+            } => (0..*n).map(Replicate::LeaveOneOut).collect(),
+            Resampling::BlockBootstrap {
+                num_blocks,
+                num_reps,
+            } => (0..*num_reps)
+                .map(|_| {
+                    let mut weights = vec![0f64; *num_blocks];
+                    for _ in 0..*num_blocks {
+                        weights[rng.gen_range(0, *num_blocks)] += 1.;
+                    }
+                    Replicate::Weighted(weights)
+                })
+                .collect(),
+            Resampling::None => Vec::new(),
+        }
+    }
+
+    /// Summarizes the `replicate_estimates` around the `point_estimate` on
+    /// the full data. For `n` jackknife replicates with mean `m`, the
+    /// standard error is `sqrt((n - 1) / n * sum (x - m)^2)` and the bias
+    /// corrected estimate is `n * point_estimate - (n - 1) * m`. For the
+    /// bootstrap, they are the sample standard deviation of the replicates
+    /// and `2 * point_estimate - m`.
+    pub fn get_estimate(
+        &self,
+        point_estimate: f64,
+        replicate_estimates: &[f64],
+    ) -> Estimate<f64> {
+        let n = replicate_estimates.len() as f64;
+        if *self == Resampling::None || replicate_estimates.is_empty() {
+            return Estimate::new(
+                point_estimate,
+                point_estimate,
+                point_estimate,
+                std::f64::NAN,
+            );
+        }
+        let mean = replicate_estimates.iter().sum::<f64>() / n;
+        if self.is_jackknife() {
+            Estimate::new(
+                n * point_estimate - (n - 1.) * mean,
+                mean,
+                point_estimate,
+                standard_deviation(replicate_estimates.iter(), 0)
+                    * (n - 1.).sqrt(),
+            )
+        } else {
+            Estimate::new(
+                2. * point_estimate - mean,
+                mean,
+                point_estimate,
+                standard_deviation(replicate_estimates.iter(), 1),
+            )
+        }
+    }
+
+    /// The factor to multiply the sum of the outer products of the centered
+    /// replicate estimates by to get their covariance. `None` if there are
+    /// too few replicates for a covariance.
+    pub fn covariance_scale(&self, num_replicates: usize) -> Option<f64> {
+        if *self == Resampling::None || num_replicates < 2 {
+            return None;
+        }
+        let n = num_replicates as f64;
+        if self.is_jackknife() {
+            Some((n - 1.) / n)
+        } else {
+            Some(1. / (n - 1.))
+        }
+    }
+}
+
+impl fmt::Display for Resampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resampling::RandomSubsetJackknife {
+                num_partitions,
+            } => write!(
+                f,
+                "random subset jackknife with {} partitions",
+                num_partitions
+            ),
+            Resampling::BlockJackknife {
+                num_blocks,
+            } => write!(f, "block jackknife with {} blocks", num_blocks),
+            Resampling::BlockBootstrap {
+                num_blocks,
+                num_reps,
+            } => write!(
+                f,
+                "block bootstrap with {} blocks and {} replicates",
+                num_blocks, num_reps
+            ),
+            Resampling::None => write!(f, "no resampling"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use rand::thread_rng;
+
+    use super::{JackknifeConfig, Replicate, Resampling, ResamplingBlocks};
+
+    #[test]
+    fn test_get_replicates() {
+        let mut rng = thread_rng();
+        let jackknife = Resampling::from_str("block-jackknife", 3, 0).unwrap();
+        assert_eq!(jackknife.get_replicates(&mut rng), vec![
+            Replicate::LeaveOneOut(0),
+            Replicate::LeaveOneOut(1),
+            Replicate::LeaveOneOut(2)
+        ]);
+        let bootstrap = Resampling::from_str("block-bootstrap", 4, 5).unwrap();
+        let replicates = bootstrap.get_replicates(&mut rng);
+        assert_eq!(replicates.len(), 5);
+        for r in replicates.iter() {
+            assert_eq!(r.weighted_sum(&[1., 1., 1., 1.]), 4.);
+        }
+        assert!(bootstrap.is_bootstrap() && !jackknife.is_bootstrap());
+        assert!(Resampling::None.get_replicates(&mut rng).is_empty());
+        assert_eq!(Resampling::None.num_blocks(), 1);
+        assert!(Resampling::from_str("delete-d", 4, 5).is_err());
+        assert!(Resampling::from_str("block-jackknife", 0, 5).is_err());
+        assert_eq!(Resampling::from(JackknifeConfig::blocks(3)), jackknife);
+        assert_eq!(
+            Resampling::from(JackknifeConfig::random_subsets(3)),
+            Resampling::RandomSubsetJackknife {
+                num_partitions: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_resampling_blocks() {
+        let blocks = vec![
+            OrderedIntegerSet::from_slice(&[[0, 9], [30, 39]]),
+            OrderedIntegerSet::from_slice(&[[10, 29]]),
+            OrderedIntegerSet::from_slice(&[[40, 49]]),
+        ];
+        let resampling_blocks = ResamplingBlocks::new(&blocks, vec![
+            Replicate::LeaveOneOut(1),
+            Replicate::Weighted(vec![0., 2., 1.]),
+        ]);
+        assert_eq!(resampling_blocks.blocks[0], vec![[0, 9], [30, 39]]);
+        assert_eq!(
+            resampling_blocks.get_replicate_snps(0),
+            Some(OrderedIntegerSet::from_slice(&[[0, 9], [30, 49]]))
+        );
+        assert_eq!(
+            resampling_blocks.get_replicate_snps(1),
+            Some(OrderedIntegerSet::from_slice(&[[10, 29], [40, 49]]))
+        );
+        assert_eq!(resampling_blocks.get_replicate_snps(2), None);
+    }
+
+    #[test]
+    fn test_weighted_sum() {
+        let values = [1., 2., 3.];
+        assert_eq!(Replicate::Full.weighted_sum(&values), 6.);
+        assert_eq!(Replicate::LeaveOneOut(1).weighted_sum(&values), 4.);
+        assert_eq!(
+            Replicate::Weighted(vec![2., 0., 1.]).weighted_sum(&values),
+            5.
+        );
+    }
+
+    #[test]
+    fn test_get_estimate() {
+        let replicates = [1., 2., 3.];
+        let jackknife = Resampling::BlockJackknife {
+            num_blocks: 3,
+        };
+        let est = jackknife.get_estimate(2.5, &replicates);
+        assert!((est.bias_corrected_estimate - 3.5).abs() < 1e-12);
+        assert!((est.standard_error - (4f64 / 3.).sqrt()).abs() < 1e-12);
+
+        let bootstrap = Resampling::BlockBootstrap {
+            num_blocks: 3,
+            num_reps: 3,
+        };
+        let est = bootstrap.get_estimate(2.5, &replicates);
+        assert!((est.bias_corrected_estimate - 3.).abs() < 1e-12);
+        assert!((est.standard_error - 1.).abs() < 1e-12);
+
+        let est = Resampling::None.get_estimate(2.5, &[]);
+        assert_eq!(est.bias_corrected_estimate, 2.5);
+        assert!(est.standard_error.is_nan());
+    }
+}
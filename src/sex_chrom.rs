@@ -0,0 +1,544 @@
+//! Handling of the sex chromosomes in the G components.
+//!
+//! The SNPs on the X chromosome form a G component of their own, as males
+//! carry a single copy of it and their genotypes are not comparable with the
+//! autosomal ones. PLINK stores the hemizygous male genotypes as homozygous,
+//! i.e. with the dosages 0 and 2, which is the coding under full dosage
+//! compensation, where the single active X of a male has the effect of the two
+//! X chromosomes of a female. The other `DosageCompensation` models recode
+//! the male dosages before the genotypes are standardized.
+//!
+//! The SNPs on the Y chromosome and the mitochondrial SNPs are left out by
+//! default, and form their own components when included. The pseudoautosomal
+//! region, coded XY or 25 by PLINK, is diploid in males and is treated as
+//! autosomal.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{Array, ArrayViewMut1, Ix2};
+
+use crate::{
+    error::Error,
+    integer_set::{
+        collect::SortedCollecting, iter::Iter, ops::MergeDifference,
+    },
+    stochastic::GenotypeSource,
+    util::get_file_line_tokens,
+};
+
+pub const X_PARTITION_NAME: &str = "X";
+pub const Y_PARTITION_NAME: &str = "Y";
+pub const MT_PARTITION_NAME: &str = "MT";
+
+/// The PLINK code of the sex of a male in the fifth column of a fam file
+const FAM_MALE_CODE: &str = "1";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChromKind {
+    Autosome,
+    X,
+    Y,
+    Mt,
+}
+
+impl ChromKind {
+    /// Classifies a chromosome code of a bim file, either numeric as in
+    /// PLINK, where 23, 24 and 26 are X, Y and MT, or by name with an
+    /// optional `chr` prefix
+    pub fn from_code(code: &str) -> ChromKind {
+        let code = code.to_uppercase();
+        let code = code.trim_start_matches("CHR");
+        match code {
+            "23" | "X" => ChromKind::X,
+            "24" | "Y" => ChromKind::Y,
+            "26" | "MT" | "M" => ChromKind::Mt,
+            _ => ChromKind::Autosome,
+        }
+    }
+}
+
+/// How the dosages of the male X genotypes relate to the female ones
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DosageCompensation {
+    /// The male genotypes are coded 0/2, as they are stored by PLINK
+    Full,
+    /// The male genotypes are coded 0/1, i.e. the single X of a male has
+    /// half the effect of the two X chromosomes of a female
+    None,
+    /// The male and the female genotypes are standardized separately, so
+    /// that the SNPs have the same variance in both sexes
+    EqualVariance,
+}
+
+impl Default for DosageCompensation {
+    fn default() -> DosageCompensation {
+        DosageCompensation::Full
+    }
+}
+
+impl FromStr for DosageCompensation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DosageCompensation, String> {
+        match s {
+            "full" => Ok(DosageCompensation::Full),
+            "none" => Ok(DosageCompensation::None),
+            "equal-variance" => Ok(DosageCompensation::EqualVariance),
+            _ => Err(format!(
+                "unrecognized dosage compensation {}, expected one of full, \
+                none and equal-variance",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DosageCompensation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DosageCompensation::Full => write!(f, "full"),
+            DosageCompensation::None => write!(f, "none"),
+            DosageCompensation::EqualVariance => write!(f, "equal-variance"),
+        }
+    }
+}
+
+/// Reads whether each person in the fam file is a male. The people of
+/// unknown sex are treated as females.
+pub fn get_fam_male_flags(fam_path: &str) -> Result<Vec<bool>, Error> {
+    Ok(get_file_line_tokens(fam_path, 6)?
+        .into_iter()
+        .map(|toks| toks[4] == FAM_MALE_CODE)
+        .collect())
+}
+
+/// The SNPs of a bim file on each of the sex chromosomes and the
+/// mitochondrial chromosome, by their line numbers
+#[derive(Clone, PartialEq, Debug)]
+pub struct SexChromSnps {
+    pub x: OrderedIntegerSet<usize>,
+    pub y: OrderedIntegerSet<usize>,
+    pub mt: OrderedIntegerSet<usize>,
+}
+
+impl SexChromSnps {
+    /// `chroms[i]` is the chromosome code of the `i`-th SNP
+    pub fn new(chroms: &[String]) -> SexChromSnps {
+        let kinds: Vec<ChromKind> =
+            chroms.iter().map(|c| ChromKind::from_code(c)).collect();
+        let snps_of = |kind: ChromKind| {
+            OrderedIntegerSet::collect_from_sorted_iter(
+                (0..kinds.len()).filter(|&i| kinds[i] == kind),
+            )
+        };
+        SexChromSnps {
+            x: snps_of(ChromKind::X),
+            y: snps_of(ChromKind::Y),
+            mt: snps_of(ChromKind::Mt),
+        }
+    }
+
+    pub fn from_bim_file(bim_path: &str) -> Result<SexChromSnps, Error> {
+        let chroms: Vec<String> = get_file_line_tokens(bim_path, 6)?
+            .into_iter()
+            .map(|mut toks| toks.swap_remove(0))
+            .collect();
+        Ok(SexChromSnps::new(&chroms))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() == 0 && self.y.size() == 0 && self.mt.size() == 0
+    }
+
+    /// Removes the X, Y and MT SNPs from every partition and adds the X SNPs
+    /// as a partition named `X_PARTITION_NAME`, followed by the Y and MT SNPs
+    /// as partitions of their own if they are included. The partitions left
+    /// empty are dropped. A partition of the input cannot be named after one
+    /// of the sex chromosome partitions unless it only has such SNPs.
+    pub fn split_partitions(
+        &self,
+        partitions: HashMap<String, OrderedIntegerSet<usize>>,
+        include_y: bool,
+        include_mt: bool,
+    ) -> Result<HashMap<String, OrderedIntegerSet<usize>>, Error> {
+        let mut split: HashMap<String, OrderedIntegerSet<usize>> = partitions
+            .into_iter()
+            .map(|(name, snps)| {
+                let snps = snps
+                    .merge_difference(&self.x)
+                    .merge_difference(&self.y)
+                    .merge_difference(&self.mt);
+                (name, snps)
+            })
+            .filter(|(_, snps)| snps.size() > 0)
+            .collect();
+        let sex_chrom_partitions = [
+            (X_PARTITION_NAME, &self.x, true),
+            (Y_PARTITION_NAME, &self.y, include_y),
+            (MT_PARTITION_NAME, &self.mt, include_mt),
+        ];
+        for &(name, snps, is_included) in sex_chrom_partitions.iter() {
+            if split.contains_key(name) {
+                return Err(Error::Generic(format!(
+                    "the partition named {} has SNPs outside of chromosome {}",
+                    name, name
+                )));
+            }
+            if is_included && snps.size() > 0 {
+                split.insert(name.to_string(), snps.clone());
+            }
+        }
+        Ok(split)
+    }
+}
+
+/// The X SNPs of the G bed and the sexes of the people, from which the male
+/// X genotypes are recoded under a `DosageCompensation` model
+#[derive(Clone, PartialEq, Debug)]
+pub struct XChromDosage {
+    x_snps: OrderedIntegerSet<usize>,
+    is_male: Vec<bool>,
+    dosage_compensation: DosageCompensation,
+}
+
+impl XChromDosage {
+    pub fn new(
+        x_snps: OrderedIntegerSet<usize>,
+        is_male: Vec<bool>,
+        dosage_compensation: DosageCompensation,
+    ) -> XChromDosage {
+        XChromDosage {
+            x_snps,
+            is_male,
+            dosage_compensation,
+        }
+    }
+
+    pub fn dosage_compensation(&self) -> DosageCompensation {
+        self.dosage_compensation
+    }
+
+    pub fn num_males(&self) -> usize {
+        self.is_male.iter().filter(|&&m| m).count()
+    }
+
+    /// Recodes the male genotypes in `col`, a raw X SNP of everyone
+    fn compensate_snp_inplace(&self, mut col: ArrayViewMut1<f32>) {
+        match self.dosage_compensation {
+            DosageCompensation::Full => {}
+            DosageCompensation::None => {
+                for (x, &is_male) in col.iter_mut().zip(self.is_male.iter()) {
+                    if is_male {
+                        *x /= 2.;
+                    }
+                }
+            }
+            DosageCompensation::EqualVariance => {
+                standardize_sex_inplace(&mut col, &self.is_male, true);
+                standardize_sex_inplace(&mut col, &self.is_male, false);
+            }
+        }
+    }
+}
+
+/// Standardizes the entries of `col` of the people with `is_male[i] == male`
+/// to a mean of zero and a variance of one over those people
+fn standardize_sex_inplace(
+    col: &mut ArrayViewMut1<f32>,
+    is_male: &[bool],
+    male: bool,
+) {
+    let (sum, ssq, count) = col
+        .iter()
+        .zip(is_male.iter())
+        .filter(|&(_, &m)| m == male)
+        .fold((0f64, 0f64, 0usize), |(sum, ssq, count), (&x, _)| {
+            (sum + x as f64, ssq + x as f64 * x as f64, count + 1)
+        });
+    if count == 0 {
+        return;
+    }
+    let mean = sum / count as f64;
+    let std = (ssq / count as f64 - mean * mean).max(0.).sqrt();
+    for (x, &m) in col.iter_mut().zip(is_male.iter()) {
+        if m == male {
+            *x -= mean as f32;
+            if std > 0. {
+                *x /= std as f32;
+            }
+        }
+    }
+}
+
+/// The genotypes in `geno` with the male genotypes of the X SNPs recoded
+/// under the `XChromDosage` as every chunk is streamed, or the genotypes in
+/// `geno` as they are if there is no `XChromDosage`.
+pub struct XDosageCompensated<'a, G: GenotypeSource> {
+    geno: &'a G,
+    x_dosage: Option<&'a XChromDosage>,
+}
+
+impl<'a, G: GenotypeSource> XDosageCompensated<'a, G> {
+    pub fn new(
+        geno: &'a G,
+        x_dosage: Option<&'a XChromDosage>,
+    ) -> Result<XDosageCompensated<'a, G>, Error> {
+        if let Some(x_dosage) = x_dosage {
+            if x_dosage.is_male.len() != geno.num_people() {
+                return Err(Error::DimensionMismatch(format!(
+                    "the sexes of {} people for genotypes of {} people",
+                    x_dosage.is_male.len(),
+                    geno.num_people()
+                )));
+            }
+            let last = x_dosage
+                .x_snps
+                .get_intervals_by_ref()
+                .last()
+                .map(|i| i.get_end());
+            if let Some(last) = last {
+                if last >= geno.num_snps() {
+                    return Err(Error::DimensionMismatch(format!(
+                        "X SNP index {} out of range for {} SNPs",
+                        last,
+                        geno.num_snps()
+                    )));
+                }
+            }
+        }
+        Ok(XDosageCompensated {
+            geno,
+            x_dosage,
+        })
+    }
+}
+
+impl<'a, G: GenotypeSource> GenotypeSource for XDosageCompensated<'a, G> {
+    fn num_people(&self) -> usize {
+        self.geno.num_people()
+    }
+
+    fn num_snps(&self) -> usize {
+        self.geno.num_snps()
+    }
+
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync, {
+        let x_dosage = match self.x_dosage {
+            Some(x_dosage)
+                if x_dosage.dosage_compensation != DosageCompensation::Full
+                    && x_dosage.x_snps.size() > 0 =>
+            {
+                x_dosage
+            }
+            _ => {
+                return self.geno.fold_snp_chunks(
+                    snp_range, chunk_size, identity, fold, reduce,
+                )
+            }
+        };
+        let mut is_x_snp = vec![false; self.geno.num_snps()];
+        for i in x_dosage.x_snps.iter() {
+            is_x_snp[i] = true;
+        }
+        // is_x[r] tells whether the SNP of rank r in the range is an X SNP
+        let is_x: Vec<bool> = match &snp_range {
+            Some(range) => range.iter().map(|i| is_x_snp[i]).collect(),
+            None => is_x_snp,
+        };
+        self.geno.fold_snp_chunks(
+            snp_range,
+            chunk_size,
+            identity,
+            |acc, rank, mut snp_chunk| {
+                for (j, col) in
+                    snp_chunk.gencolumns_mut().into_iter().enumerate()
+                {
+                    if is_x[rank + j] {
+                        x_dosage.compensate_snp_inplace(col);
+                    }
+                }
+                fold(acc, rank, snp_chunk)
+            },
+            reduce,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write};
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array, Ix2};
+    use tempfile::NamedTempFile;
+
+    use super::{
+        get_fam_male_flags, ChromKind, DosageCompensation, SexChromSnps,
+        XChromDosage, XDosageCompensated, MT_PARTITION_NAME, X_PARTITION_NAME,
+    };
+    use crate::stochastic::GenotypeSource;
+
+    fn collect_geno<G: GenotypeSource>(geno: &G) -> Array<f32, Ix2> {
+        let mut chunks = geno.fold_snp_chunks(
+            None,
+            2,
+            Vec::new,
+            |mut acc, rank, chunk| {
+                acc.push((rank, chunk));
+                acc
+            },
+            |mut a, mut b| {
+                a.append(&mut b);
+                a
+            },
+        );
+        chunks.sort_by_key(|(rank, _)| *rank);
+        let mut out = Array::zeros((geno.num_people(), geno.num_snps()));
+        for (rank, chunk) in chunks.into_iter() {
+            for (j, col) in chunk.gencolumns().into_iter().enumerate() {
+                out.column_mut(rank + j).assign(&col);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_chrom_kind() {
+        assert_eq!(ChromKind::from_code("23"), ChromKind::X);
+        assert_eq!(ChromKind::from_code("chrX"), ChromKind::X);
+        assert_eq!(ChromKind::from_code("24"), ChromKind::Y);
+        assert_eq!(ChromKind::from_code("MT"), ChromKind::Mt);
+        assert_eq!(ChromKind::from_code("26"), ChromKind::Mt);
+        assert_eq!(ChromKind::from_code("25"), ChromKind::Autosome);
+        assert_eq!(ChromKind::from_code("XY"), ChromKind::Autosome);
+        assert_eq!(ChromKind::from_code("1"), ChromKind::Autosome);
+        assert_eq!(
+            "equal-variance".parse::<DosageCompensation>().unwrap(),
+            DosageCompensation::EqualVariance
+        );
+        assert!("partial".parse::<DosageCompensation>().is_err());
+    }
+
+    #[test]
+    fn test_split_partitions() {
+        let chroms: Vec<String> = ["1", "1", "23", "X", "24", "2", "MT"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let sex_snps = SexChromSnps::new(&chroms);
+        assert_eq!(sex_snps.x, OrderedIntegerSet::from_slice(&[[2, 3]]));
+        assert_eq!(sex_snps.y, OrderedIntegerSet::from_slice(&[[4, 4]]));
+        assert_eq!(sex_snps.mt, OrderedIntegerSet::from_slice(&[[6, 6]]));
+
+        let mut partitions = HashMap::new();
+        partitions
+            .insert("a".to_string(), OrderedIntegerSet::from_slice(&[[0, 3]]));
+        partitions
+            .insert("b".to_string(), OrderedIntegerSet::from_slice(&[[4, 6]]));
+        let split = sex_snps
+            .split_partitions(partitions.clone(), false, true)
+            .unwrap();
+        assert_eq!(split.len(), 4);
+        assert_eq!(split["a"], OrderedIntegerSet::from_slice(&[[0, 1]]));
+        assert_eq!(split["b"], OrderedIntegerSet::from_slice(&[[5, 5]]));
+        assert_eq!(
+            split[X_PARTITION_NAME],
+            OrderedIntegerSet::from_slice(&[[2, 3]])
+        );
+        assert_eq!(
+            split[MT_PARTITION_NAME],
+            OrderedIntegerSet::from_slice(&[[6, 6]])
+        );
+
+        partitions.insert(
+            X_PARTITION_NAME.to_string(),
+            OrderedIntegerSet::from_slice(&[[0, 0]]),
+        );
+        assert!(sex_snps.split_partitions(partitions, false, false).is_err());
+    }
+
+    #[test]
+    fn test_x_dosage_compensated() {
+        let geno =
+            array![[0f32, 2., 1.], [2., 0., 1.], [1., 2., 2.], [0., 0., 0.], [
+                2., 2., 1.
+            ]];
+        let is_male = vec![true, true, false, false, true];
+        let x_snps = OrderedIntegerSet::from_slice(&[[1, 2]]);
+
+        let full = XChromDosage::new(
+            x_snps.clone(),
+            is_male.clone(),
+            DosageCompensation::Full,
+        );
+        let full_geno = XDosageCompensated::new(&geno, Some(&full)).unwrap();
+        assert_eq!(collect_geno(&full_geno), geno);
+
+        let none = XChromDosage::new(
+            x_snps.clone(),
+            is_male.clone(),
+            DosageCompensation::None,
+        );
+        assert_eq!(none.num_males(), 3);
+        let none_geno = XDosageCompensated::new(&geno, Some(&none)).unwrap();
+        let expected = array![
+            [0f32, 1., 0.5],
+            [2., 0., 0.5],
+            [1., 2., 2.],
+            [0., 0., 0.],
+            [2., 1., 0.5]
+        ];
+        assert_eq!(collect_geno(&none_geno), expected);
+
+        let equal = XChromDosage::new(
+            x_snps,
+            is_male.clone(),
+            DosageCompensation::EqualVariance,
+        );
+        let equal_geno = collect_geno(
+            &XDosageCompensated::new(&geno, Some(&equal)).unwrap(),
+        );
+        assert_eq!(equal_geno.column(0), geno.column(0));
+        for j in 1..3 {
+            for &male in [true, false].iter() {
+                let vals: Vec<f32> = (0..5)
+                    .filter(|&i| is_male[i] == male)
+                    .map(|i| equal_geno[[i, j]])
+                    .collect();
+                let mean = vals.iter().sum::<f32>() / vals.len() as f32;
+                assert!(mean.abs() < 1e-5);
+            }
+        }
+
+        assert!(XDosageCompensated::new(
+            &geno,
+            Some(&XChromDosage::new(
+                OrderedIntegerSet::from_slice(&[[3, 3]]),
+                is_male,
+                DosageCompensation::None,
+            ))
+        )
+        .is_err());
+
+        let mut fam = NamedTempFile::new().unwrap();
+        writeln!(fam, "f1 i1 0 0 1 -9\nf2 i2 0 0 2 -9\nf3 i3 0 0 0 -9")
+            .unwrap();
+        assert_eq!(
+            get_fam_male_flags(fam.path().to_str().unwrap()).unwrap(),
+            vec![true, false, false]
+        );
+    }
+}
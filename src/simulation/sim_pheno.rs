@@ -7,7 +7,7 @@ use std::{
 use biofile::{plink_bed::PlinkBed, plink_bim::PlinkBim};
 use math::{
     set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
-    stats::{mean, n_choose_2, variance},
+    stats::{mean, variance},
 };
 use ndarray::{s, Array, Axis, Ix1, Ix2, ShapeBuilder};
 use ndarray_parallel::prelude::*;
@@ -16,11 +16,12 @@ use rand::distributions::Normal;
 use rayon::prelude::*;
 
 use crate::{
+    gxg_pair_exclusion::GxgPairExclusion,
     heritability_estimator::{Coordinate, DEFAULT_PARTITION_NAME},
     util::matrix_util::normalize_matrix_columns_inplace,
 };
 
-/// 
+///
 /// * `geno_arr` is the 2D genotype array, of shape (num_individuals, num_snps)
 /// * `effect_variance` is the variance of the total effect sizes,
 /// i.e. each coefficient will have a variance of effect_variance /
@@ -199,12 +200,20 @@ pub fn generate_g_contribution_from_bed_bim(
     Ok(effects)
 }
 
+/// Pairs of basis SNPs left out by `gxg_pair_exclusion` have no effect,
+/// matching the GxG kernel used by the estimators.
 pub fn generate_gxg_contribution_from_gxg_basis(
     mut gxg_basis: Array<f32, Ix2>,
     gxg_variance: f64,
+    gxg_pair_exclusion: &GxgPairExclusion,
 ) -> Array<f32, Ix1> {
     let (num_people, num_basis) = gxg_basis.dim();
-    let num_gxg_pairs = n_choose_2(num_basis);
+    assert_eq!(
+        num_basis,
+        gxg_pair_exclusion.num_basis_snps(),
+        "the GxG pair exclusion does not match the number of basis SNPs"
+    );
+    let num_gxg_pairs = gxg_pair_exclusion.num_included_pairs();
     println!("\n=> generate_gxg_contribution_from_gxg_basis\nnum_people: {}\nnum_basis: {}\nequivalent # gxg pairs: {}\ngxg_variance: {}",
              num_people, num_basis, num_gxg_pairs, gxg_variance);
 
@@ -222,9 +231,14 @@ pub fn generate_gxg_contribution_from_gxg_basis(
             .for_each(|mut col| {
                 col *= &snp_i;
             });
-        let gxg_effect_sizes =
+        let mut gxg_effect_sizes =
             Array::random(gxg.dim().1, Normal::new(0f64, gxg_single_std_dev))
                 .mapv(|e| e as f32);
+        for j in i + 1..num_basis {
+            if !gxg_pair_exclusion.is_included(i, j) {
+                gxg_effect_sizes[j - i - 1] = 0.;
+            }
+        }
         gxg_effects += &gxg.dot(&gxg_effect_sizes);
     }
     gxg_effects
@@ -242,18 +256,122 @@ pub fn get_sim_output_path(
     }
 }
 
+/// Name of the effects column when the columns are not named after the
+/// components
+pub const DEFAULT_EFFECTS_COLUMN_NAME: &str = "pheno";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EffectsDelimiter {
+    Space,
+    Tab,
+}
+
+impl EffectsDelimiter {
+    pub fn from_str(s: &str) -> Result<EffectsDelimiter, String> {
+        match s {
+            "space" => Ok(EffectsDelimiter::Space),
+            "tab" => Ok(EffectsDelimiter::Tab),
+            _ => Err(format!(
+                "unrecognized delimiter {}, expected space or tab",
+                s
+            )),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EffectsDelimiter::Space => " ",
+            EffectsDelimiter::Tab => "\t",
+        }
+    }
+}
+
+/// Layout of the simulated effects files. The default is the space
+/// delimited `FID IID pheno` format with a header line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EffectsFileFormat {
+    pub delimiter: EffectsDelimiter,
+    pub include_header: bool,
+    /// Whether the header names the effects column after the component
+    /// instead of `DEFAULT_EFFECTS_COLUMN_NAME`
+    pub named_columns: bool,
+    /// If not `None`, the effects are written in scientific notation with
+    /// this many decimals
+    pub scientific_precision: Option<usize>,
+}
+
+impl Default for EffectsFileFormat {
+    fn default() -> EffectsFileFormat {
+        EffectsFileFormat {
+            delimiter: EffectsDelimiter::Space,
+            include_header: true,
+            named_columns: false,
+            scientific_precision: None,
+        }
+    }
+}
+
+impl EffectsFileFormat {
+    fn format_value(&self, val: f32) -> String {
+        match self.scientific_precision {
+            None => val.to_string(),
+            Some(precision) => format!("{:.*e}", precision, val),
+        }
+    }
+
+    fn format_header(&self, column_names: &[&str]) -> String {
+        let mut toks = vec!["FID", "IID"];
+        toks.extend(column_names.iter());
+        toks.join(self.delimiter.as_str())
+    }
+
+    fn format_line(&self, fid: &str, iid: &str, values: &[f32]) -> String {
+        let mut toks = vec![fid.to_string(), iid.to_string()];
+        toks.extend(values.iter().map(|&v| self.format_value(v)));
+        toks.join(self.delimiter.as_str())
+    }
+}
+
+/// Writes the effects of a single component, one individual per line.
+/// `column_name` is only used in the header if `format.named_columns`.
 pub fn write_effects_to_file(
     effects: &Array<f32, Ix1>,
     fid_iid_list: &Vec<(String, String)>,
     out_path: &str,
+    column_name: &str,
+    format: &EffectsFileFormat,
 ) -> Result<(), std::io::Error> {
-    assert_eq!(
-        effects.dim(),
-        fid_iid_list.len(),
-        "length of the phenotype array {} not equal to the length of the fid_iid_list {}",
-        effects.dim(),
-        fid_iid_list.len()
-    );
+    let column_name = if format.named_columns {
+        column_name
+    } else {
+        DEFAULT_EFFECTS_COLUMN_NAME
+    };
+    write_wide_effects_to_file(
+        &[(column_name, effects)],
+        fid_iid_list,
+        out_path,
+        format,
+    )
+}
+
+/// Writes the effects of all the `columns` to a single file, with one
+/// column per component named after it.
+pub fn write_wide_effects_to_file(
+    columns: &[(&str, &Array<f32, Ix1>)],
+    fid_iid_list: &Vec<(String, String)>,
+    out_path: &str,
+    format: &EffectsFileFormat,
+) -> Result<(), std::io::Error> {
+    for (name, effects) in columns.iter() {
+        assert_eq!(
+            effects.dim(),
+            fid_iid_list.len(),
+            "length of the {} effects array {} not equal to the length of the fid_iid_list {}",
+            name,
+            effects.dim(),
+            fid_iid_list.len()
+        );
+    }
     let mut buf = BufWriter::new(
         OpenOptions::new()
             .create(true)
@@ -261,9 +379,17 @@ pub fn write_effects_to_file(
             .write(true)
             .open(out_path)?,
     );
-    buf.write_fmt(format_args!("FID IID pheno\n"))?;
-    for (val, (fid, iid)) in effects.iter().zip(fid_iid_list.iter()) {
-        buf.write_fmt(format_args!("{} {} {}\n", fid, iid, val))?;
+    if format.include_header {
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        buf.write_fmt(format_args!("{}\n", format.format_header(&names)))?;
+    }
+    for (i, (fid, iid)) in fid_iid_list.iter().enumerate() {
+        let values: Vec<f32> =
+            columns.iter().map(|(_, effects)| effects[i]).collect();
+        buf.write_fmt(format_args!(
+            "{}\n",
+            format.format_line(fid, iid, &values)
+        ))?;
     }
     Ok(())
 }
@@ -283,7 +409,29 @@ mod tests {
 
     use super::{
         generate_g_contribution, generate_gxg_contribution_from_gxg_basis,
+        EffectsDelimiter, EffectsFileFormat,
     };
+    use crate::gxg_pair_exclusion::GxgPairExclusion;
+
+    #[test]
+    fn test_effects_file_format() {
+        let format = EffectsFileFormat::default();
+        assert_eq!(format.format_header(&["pheno"]), "FID IID pheno");
+        assert_eq!(format.format_line("f", "i", &[0.5]), "f i 0.5");
+
+        let format = EffectsFileFormat {
+            delimiter: EffectsDelimiter::Tab,
+            include_header: true,
+            named_columns: true,
+            scientific_precision: Some(2),
+        };
+        assert_eq!(format.format_header(&["G", "GxG1"]), "FID\tIID\tG\tGxG1");
+        assert_eq!(
+            format.format_line("f", "i", &[1234., -0.001]),
+            "f\ti\t1.23e3\t-1.00e-3"
+        );
+        assert!(EffectsDelimiter::from_str("comma").is_err());
+    }
 
     #[test]
     fn test_generate_gxg_contribution_from_gxg_basis() {
@@ -295,6 +443,28 @@ mod tests {
         let gxg_effects = generate_gxg_contribution_from_gxg_basis(
             gxg_basis,
             desired_variance,
+            &GxgPairExclusion::none(num_basis),
+        );
+        let actual_variance = variance(gxg_effects.iter(), 0);
+        assert!((actual_variance - desired_variance).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_generate_gxg_contribution_with_excluded_pairs() {
+        let (num_people, num_basis) = (10000, 100);
+        let gxg_basis =
+            Array::random((num_people, num_basis), Uniform::from(0..3))
+                .mapv(|e| e as f32);
+        let exclusion = GxgPairExclusion::from_pairs(
+            num_basis,
+            (1..num_basis).map(|j| (0, j)).collect(),
+        )
+        .unwrap();
+        let desired_variance = 0.05;
+        let gxg_effects = generate_gxg_contribution_from_gxg_basis(
+            gxg_basis,
+            desired_variance,
+            &exclusion,
         );
         let actual_variance = variance(gxg_effects.iter(), 0);
         assert!((actual_variance - desired_variance).abs() < 0.01);
@@ -0,0 +1,123 @@
+use ndarray::{Array, ArrayView1, Axis, Ix2};
+
+use crate::snp_qc::SnpQcStats;
+
+/// Quality control thresholds applied to every chunk of SNP columns as it is
+/// read from the bed file. SNPs failing the thresholds are dropped from the
+/// chunk, and hence from the number of SNPs the estimators normalize by.
+///
+/// A genotype is considered missing if it is not one of 0, 1 or 2.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct SnpChunkFilter {
+    min_maf: Option<f32>,
+    max_missing_rate: Option<f32>,
+    drop_monomorphic: bool,
+}
+
+impl SnpChunkFilter {
+    pub fn new(
+        min_maf: Option<f32>,
+        max_missing_rate: Option<f32>,
+    ) -> SnpChunkFilter {
+        SnpChunkFilter {
+            min_maf,
+            max_missing_rate,
+            drop_monomorphic: false,
+        }
+    }
+
+    /// Also drops the monomorphic SNPs, which the `SnpQc` pass always fails
+    pub fn dropping_monomorphic(mut self) -> SnpChunkFilter {
+        self.drop_monomorphic = true;
+        self
+    }
+
+    pub fn passes(&self, snp_col: ArrayView1<f32>) -> bool {
+        self.passes_stats(&SnpQcStats::from_column(snp_col))
+    }
+
+    pub fn passes_stats(&self, stats: &SnpQcStats) -> bool {
+        if self.drop_monomorphic && stats.is_monomorphic {
+            return false;
+        }
+        if let Some(max_missing_rate) = self.max_missing_rate {
+            if stats.missing_rate > max_missing_rate {
+                return false;
+            }
+        }
+        if let Some(min_maf) = self.min_maf {
+            if stats.missing_rate == 1. || stats.maf < min_maf {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The indices of the columns of the chunk that pass the filter
+    pub fn get_kept_columns(&self, snp_chunk: &Array<f32, Ix2>) -> Vec<usize> {
+        snp_chunk
+            .gencolumns()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, col)| self.passes(col.view()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the chunk with the failing columns removed
+    pub fn apply(&self, snp_chunk: Array<f32, Ix2>) -> Array<f32, Ix2> {
+        let kept = self.get_kept_columns(&snp_chunk);
+        if kept.len() == snp_chunk.dim().1 {
+            snp_chunk
+        } else {
+            snp_chunk.select(Axis(1), &kept)
+        }
+    }
+}
+
+/// Applies the filter if there is one
+#[inline]
+pub fn apply_snp_chunk_filter(
+    snp_chunk: Array<f32, Ix2>,
+    filter: Option<&SnpChunkFilter>,
+) -> Array<f32, Ix2> {
+    match filter {
+        Some(f) => f.apply(snp_chunk),
+        None => snp_chunk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+
+    use super::SnpChunkFilter;
+
+    #[test]
+    fn test_snp_chunk_filter() {
+        // columns: common, rare, monomorphic, half missing
+        let chunk =
+            array![[0f32, 0., 2., 1.], [1., 0., 2., 9.], [2., 0., 2., 9.], [
+                1., 1., 2., 0.
+            ]];
+        assert_eq!(
+            SnpChunkFilter::new(Some(0.2), None).apply(chunk.clone()),
+            array![[0f32, 1.], [1., 9.], [2., 9.], [1., 0.]]
+        );
+        assert_eq!(
+            SnpChunkFilter::new(None, Some(0.25)).apply(chunk.clone()),
+            array![[0f32, 0., 2.], [1., 0., 2.], [2., 0., 2.], [1., 1., 2.]]
+        );
+        assert_eq!(
+            SnpChunkFilter::new(Some(0.2), Some(0.25)).apply(chunk.clone()),
+            array![[0f32], [1.], [2.], [1.]]
+        );
+        assert_eq!(SnpChunkFilter::default().apply(chunk.clone()), chunk);
+        assert_eq!(
+            SnpChunkFilter::new(None, Some(0.25))
+                .dropping_monomorphic()
+                .apply(chunk.clone()),
+            array![[0f32, 0.], [1., 0.], [2., 0.], [1., 1.]]
+        );
+    }
+}
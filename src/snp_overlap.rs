@@ -0,0 +1,175 @@
+//! Detecting the SNPs that are both in the G bfile and among the LE SNPs of
+//! the GxG components. The variance of such a SNP is counted by both the G and
+//! the GxG kernels, so it is better removed from the G component.
+
+use std::collections::HashSet;
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+
+use crate::{
+    error::Error,
+    integer_set::{collect::SortedCollecting, ops::MergeDifference},
+    util::get_file_line_tokens,
+    warning::{Warning, WarningCode},
+};
+
+/// A SNP is identified by its chromosome, base pair coordinate and alleles,
+/// where the alleles are sorted so that a swap of the reference allele still
+/// matches.
+pub type SnpKey = (String, u64, String, String);
+
+/// Reads the `SnpKey` of every SNP in the bim file
+pub fn get_bim_snp_keys(bim_path: &str) -> Result<Vec<SnpKey>, Error> {
+    get_file_line_tokens(bim_path, 6)?
+        .into_iter()
+        .map(|toks| {
+            let bp = toks[3].parse::<u64>().map_err(|why| {
+                Error::Generic(format!(
+                    "failed to parse the base pair coordinate {} in {}: {}",
+                    toks[3], bim_path, why
+                ))
+            })?;
+            let (a1, a2) = if toks[4] <= toks[5] {
+                (toks[4].to_owned(), toks[5].to_owned())
+            } else {
+                (toks[5].to_owned(), toks[4].to_owned())
+            };
+            Ok((toks[0].to_owned(), bp, a1, a2))
+        })
+        .collect()
+}
+
+/// The SNPs in both bim files, by their line numbers in each
+#[derive(Clone, PartialEq, Debug)]
+pub struct SnpOverlap {
+    pub g_snps: OrderedIntegerSet<usize>,
+    pub le_snps: OrderedIntegerSet<usize>,
+}
+
+impl SnpOverlap {
+    pub fn new(g_keys: &[SnpKey], le_keys: &[SnpKey]) -> SnpOverlap {
+        let g_key_set: HashSet<&SnpKey> = g_keys.iter().collect();
+        let le_key_set: HashSet<&SnpKey> = le_keys.iter().collect();
+        SnpOverlap {
+            g_snps: OrderedIntegerSet::collect_from_sorted_iter(
+                (0..g_keys.len()).filter(|&i| le_key_set.contains(&g_keys[i])),
+            ),
+            le_snps: OrderedIntegerSet::collect_from_sorted_iter(
+                (0..le_keys.len()).filter(|&i| g_key_set.contains(&le_keys[i])),
+            ),
+        }
+    }
+
+    pub fn from_bim_files(
+        g_bim_path: &str,
+        le_snps_bim_path: &str,
+    ) -> Result<SnpOverlap, Error> {
+        Ok(SnpOverlap::new(
+            &get_bim_snp_keys(g_bim_path)?,
+            &get_bim_snp_keys(le_snps_bim_path)?,
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.g_snps.size() == 0
+    }
+
+    /// The number of G SNPs that are also LE SNPs
+    pub fn num_snps(&self) -> usize {
+        self.g_snps.size()
+    }
+
+    /// The SNPs of a G component without the overlapping SNPs
+    pub fn prune(
+        &self,
+        g_snps: &OrderedIntegerSet<usize>,
+    ) -> OrderedIntegerSet<usize> {
+        g_snps.merge_difference(&self.g_snps)
+    }
+
+    /// The warning about the overlap, or `None` if there is no overlap
+    pub fn get_warning(&self, is_pruned: bool) -> Option<Warning> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(Warning::new(
+            WarningCode::GxgSnpsOverlapG,
+            format!(
+                "{} SNPs of the G component are also LE SNPs of the GxG \
+                components, {}",
+                self.num_snps(),
+                if is_pruned {
+                    "they are removed from the G component"
+                } else {
+                    "so their variance is counted by both"
+                }
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use tempfile::NamedTempFile;
+
+    use super::{SnpKey, SnpOverlap};
+    use crate::warning::WarningCode;
+
+    fn key(chrom: &str, bp: u64, a1: &str, a2: &str) -> SnpKey {
+        (chrom.to_string(), bp, a1.to_string(), a2.to_string())
+    }
+
+    #[test]
+    fn test_snp_overlap() {
+        let g_keys = vec![
+            key("1", 100, "A", "G"),
+            key("1", 200, "C", "T"),
+            key("1", 300, "A", "C"),
+            key("2", 100, "A", "G"),
+            key("2", 200, "G", "T"),
+        ];
+        let le_keys = vec![
+            key("1", 200, "C", "T"),
+            key("1", 300, "A", "G"),
+            key("2", 100, "A", "G"),
+            key("3", 100, "A", "G"),
+        ];
+        let overlap = SnpOverlap::new(&g_keys, &le_keys);
+        assert_eq!(
+            overlap.g_snps,
+            OrderedIntegerSet::from_slice(&[[1, 1], [3, 3]])
+        );
+        assert_eq!(
+            overlap.le_snps,
+            OrderedIntegerSet::from_slice(&[[0, 0], [2, 2]])
+        );
+        assert_eq!(overlap.num_snps(), 2);
+        assert_eq!(
+            overlap.prune(&OrderedIntegerSet::from_slice(&[[0, 4]])),
+            OrderedIntegerSet::from_slice(&[[0, 0], [2, 2], [4, 4]])
+        );
+        assert_eq!(
+            overlap.get_warning(true).unwrap().code,
+            WarningCode::GxgSnpsOverlapG
+        );
+
+        let disjoint = SnpOverlap::new(&g_keys[..1], &le_keys);
+        assert!(disjoint.is_empty());
+        assert!(disjoint.get_warning(false).is_none());
+
+        // the alleles match regardless of their order
+        let mut g_bim = NamedTempFile::new().unwrap();
+        writeln!(g_bim, "1 rs1 0 100 A G\n1 rs2 0 200 C T").unwrap();
+        let mut le_bim = NamedTempFile::new().unwrap();
+        writeln!(le_bim, "1 rs9 0 200 T C").unwrap();
+        let overlap = SnpOverlap::from_bim_files(
+            g_bim.path().to_str().unwrap(),
+            le_bim.path().to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(overlap.g_snps, OrderedIntegerSet::from_slice(&[[1, 1]]));
+    }
+}
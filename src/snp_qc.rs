@@ -0,0 +1,235 @@
+//! A quality control pass over the genotypes before the estimation. The
+//! per-SNP minor allele frequency, missing rate and monomorphism are computed
+//! in a single streaming pass, and the SNPs that fail the thresholds are left
+//! out of the SNP ranges of every component.
+//!
+//! The monomorphic SNPs always fail: a column with no variance cannot be
+//! standardized, and the NaNs it would produce poison every trace it enters.
+
+use std::{collections::HashMap, fmt};
+
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{ArrayView1, Axis};
+
+use crate::{
+    integer_set::{collect::SortedCollecting, ops::MergeIntersect},
+    snp_chunk_filter::SnpChunkFilter,
+    stochastic::GenotypeSource,
+};
+
+/// A genotype is considered missing if it is not one of 0, 1 or 2.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SnpQcStats {
+    /// The frequency of the less common allele among the called genotypes,
+    /// or zero if no genotype is called
+    pub maf: f32,
+    pub missing_rate: f32,
+    /// Whether all the called genotypes are the same, including when no
+    /// genotype is called
+    pub is_monomorphic: bool,
+}
+
+impl SnpQcStats {
+    pub fn from_column(snp_col: ArrayView1<f32>) -> SnpQcStats {
+        let num_people = snp_col.len();
+        let mut num_missing = 0usize;
+        let mut allele_count = 0f64;
+        let mut first_called = None;
+        let mut is_monomorphic = true;
+        for &g in snp_col.iter() {
+            if g == 0. || g == 1. || g == 2. {
+                allele_count += g as f64;
+                match first_called {
+                    None => first_called = Some(g),
+                    Some(first) if first != g => is_monomorphic = false,
+                    _ => {}
+                }
+            } else {
+                num_missing += 1;
+            }
+        }
+        let num_called = num_people - num_missing;
+        let maf = if num_called == 0 {
+            0.
+        } else {
+            let freq = allele_count / (2 * num_called) as f64;
+            freq.min(1. - freq) as f32
+        };
+        SnpQcStats {
+            maf,
+            missing_rate: if num_people == 0 {
+                0.
+            } else {
+                num_missing as f32 / num_people as f32
+            },
+            is_monomorphic,
+        }
+    }
+}
+
+/// The QC statistics of every SNP in a genotype source
+#[derive(Clone, PartialEq, Debug)]
+pub struct SnpQc {
+    stats: Vec<SnpQcStats>,
+}
+
+impl SnpQc {
+    pub fn new<G: GenotypeSource>(
+        geno: &G,
+        num_snps_per_chunk: usize,
+    ) -> SnpQc {
+        let mut chunks = geno.fold_snp_chunks(
+            None,
+            num_snps_per_chunk,
+            Vec::new,
+            |mut acc, rank, snp_chunk| {
+                acc.push((
+                    rank,
+                    snp_chunk
+                        .axis_iter(Axis(1))
+                        .map(SnpQcStats::from_column)
+                        .collect::<Vec<SnpQcStats>>(),
+                ));
+                acc
+            },
+            |mut a, mut b| {
+                a.append(&mut b);
+                a
+            },
+        );
+        chunks.sort_by_key(|(rank, _)| *rank);
+        SnpQc {
+            stats: chunks.into_iter().flat_map(|(_, stats)| stats).collect(),
+        }
+    }
+
+    pub fn num_snps(&self) -> usize {
+        self.stats.len()
+    }
+
+    pub fn get_stats(&self, snp_index: usize) -> &SnpQcStats {
+        &self.stats[snp_index]
+    }
+
+    /// The SNPs that are not monomorphic and pass the thresholds of the
+    /// `filter`
+    pub fn get_passing_snps(
+        &self,
+        filter: &SnpChunkFilter,
+    ) -> OrderedIntegerSet<usize> {
+        OrderedIntegerSet::collect_from_sorted_iter(
+            self.stats
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| !s.is_monomorphic && filter.passes_stats(s))
+                .map(|(i, _)| i),
+        )
+    }
+
+    /// Keeps only the passing SNPs in every partition, dropping the
+    /// partitions left empty
+    pub fn filter_partitions(
+        &self,
+        partitions: HashMap<String, OrderedIntegerSet<usize>>,
+        filter: &SnpChunkFilter,
+    ) -> HashMap<String, OrderedIntegerSet<usize>> {
+        let passing = self.get_passing_snps(filter);
+        partitions
+            .into_iter()
+            .map(|(name, snps)| (name, snps.merge_intersect(&passing)))
+            .filter(|(_, snps)| snps.size() > 0)
+            .collect()
+    }
+
+    pub fn summarize(&self, filter: &SnpChunkFilter) -> SnpQcSummary {
+        SnpQcSummary {
+            num_snps: self.num_snps(),
+            num_monomorphic: self
+                .stats
+                .iter()
+                .filter(|s| s.is_monomorphic)
+                .count(),
+            num_passing: self.get_passing_snps(filter).size(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SnpQcSummary {
+    pub num_snps: usize,
+    pub num_monomorphic: usize,
+    pub num_passing: usize,
+}
+
+impl fmt::Display for SnpQcSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of the {} SNPs pass the QC, {} SNPs are monomorphic",
+            self.num_passing, self.num_snps, self.num_monomorphic
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::array;
+
+    use super::{SnpQc, SnpQcStats};
+    use crate::snp_chunk_filter::SnpChunkFilter;
+
+    #[test]
+    fn test_snp_qc() {
+        // columns: common, rare, monomorphic, half missing, all missing
+        let geno = array![
+            [0f32, 0., 2., 1., 9.],
+            [1., 0., 2., 9., 9.],
+            [2., 0., 2., 9., 9.],
+            [1., 1., 2., 0., 9.]
+        ];
+        let stats = SnpQcStats::from_column(geno.column(1));
+        assert_eq!(stats.maf, 0.125);
+        assert_eq!(stats.missing_rate, 0.);
+        assert!(!stats.is_monomorphic);
+        let stats = SnpQcStats::from_column(geno.column(3));
+        assert_eq!(stats.missing_rate, 0.5);
+        assert!(!stats.is_monomorphic);
+        assert!(SnpQcStats::from_column(geno.column(2)).is_monomorphic);
+        assert!(SnpQcStats::from_column(geno.column(4)).is_monomorphic);
+
+        let qc = SnpQc::new(&geno, 2);
+        assert_eq!(qc.num_snps(), 5);
+        assert_eq!(qc.get_stats(0).maf, 0.5);
+        assert_eq!(
+            qc.get_passing_snps(&SnpChunkFilter::default()),
+            OrderedIntegerSet::from_slice(&[[0, 1], [3, 3]])
+        );
+        let filter = SnpChunkFilter::new(Some(0.2), Some(0.25));
+        assert_eq!(
+            qc.get_passing_snps(&filter),
+            OrderedIntegerSet::from_slice(&[[0, 0]])
+        );
+        let summary = qc.summarize(&filter);
+        assert_eq!(summary.num_monomorphic, 2);
+        assert_eq!(summary.num_passing, 1);
+
+        let mut partitions = HashMap::new();
+        partitions
+            .insert("a".to_string(), OrderedIntegerSet::from_slice(&[[0, 1]]));
+        partitions
+            .insert("b".to_string(), OrderedIntegerSet::from_slice(&[[2, 4]]));
+        let filtered =
+            qc.filter_partitions(partitions, &SnpChunkFilter::default());
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered["b"], OrderedIntegerSet::from_slice(&[[3, 3]]));
+        let mut partitions = HashMap::new();
+        partitions
+            .insert("c".to_string(), OrderedIntegerSet::from_slice(&[[2, 2]]));
+        assert!(qc
+            .filter_partitions(partitions, &SnpChunkFilter::default())
+            .is_empty());
+    }
+}
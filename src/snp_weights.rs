@@ -0,0 +1,258 @@
+//! Per-SNP weights of the kernels, e.g. the LDAK weights that even out the
+//! contribution of the SNPs in regions of high linkage disequilibrium.
+//!
+//! With the weights `w` of the SNPs in a range, the additive kernel becomes
+//! `K = X W X^T / sum_i w_i` for the standardized genotypes `X`, and the GxG
+//! kernel becomes `sum_{i < j} w_i w_j (x_i * x_j) (x_i * x_j)^T` divided by
+//! `sum_{i < j} w_i w_j`. Both follow from scaling every standardized SNP
+//! `x_i` by `sqrt(w_i)`, which is how the weights are applied to the streamed
+//! chunks of SNPs. The alpha model of the SNP effects is the special case of
+//! the weights `(2 f_i (1 - f_i))^(1 + alpha)` for the allele frequencies
+//! `f_i`, see `SnpWeights::from_alpha`.
+
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{BufRead, BufReader},
+};
+
+use math::set::ordered_integer_set::OrderedIntegerSet;
+use ndarray::{Array, Axis, Ix2};
+
+use crate::{
+    error::Error, integer_set::iter::Iter,
+    util::matrix_util::get_alpha_model_weight,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SnpWeights {
+    /// `weights[i]` is the weight of the SNP on line `i` of the bim files
+    weights: Vec<f32>,
+}
+
+impl SnpWeights {
+    /// The weights have to be finite and nonnegative, and at least one of
+    /// them positive.
+    pub fn new(weights: Vec<f32>) -> Result<SnpWeights, Error> {
+        if let Some((i, w)) = weights
+            .iter()
+            .enumerate()
+            .find(|(_, w)| !(w.is_finite() && **w >= 0.))
+        {
+            return Err(Error::Generic(format!(
+                "the SNP weights have to be finite and nonnegative, SNP {} \
+                has the weight {}",
+                i, w
+            )));
+        }
+        if !weights.iter().any(|&w| w > 0.) {
+            return Err(Error::Generic(
+                "at least one SNP weight has to be positive".to_string(),
+            ));
+        }
+        Ok(SnpWeights {
+            weights,
+        })
+    }
+
+    /// Reads a file with a `SNP_ID WEIGHT` pair on every line, such as the
+    /// `weights.short` file of LDAK, for the SNPs in the `bim_paths` in the
+    /// order of the bed. The SNPs missing from the file get a weight of zero.
+    pub fn from_file(
+        weights_path: &str,
+        bim_paths: &[String],
+    ) -> Result<SnpWeights, Error> {
+        let mut id_to_weight = HashMap::<String, f32>::new();
+        let buf =
+            BufReader::new(OpenOptions::new().read(true).open(weights_path)?);
+        for (i, line) in buf.lines().enumerate() {
+            let line = line?;
+            let toks: Vec<&str> = line.split_whitespace().collect();
+            if toks.len() != 2 {
+                return Err(Error::Generic(format!(
+                    "expected 2 tokens on line {} of {}, found {}",
+                    i + 1,
+                    weights_path,
+                    toks.len()
+                )));
+            }
+            let weight = toks[1].parse::<f32>().map_err(|why| {
+                Error::Generic(format!(
+                    "invalid weight {} on line {} of {}: {}",
+                    toks[1],
+                    i + 1,
+                    weights_path,
+                    why
+                ))
+            })?;
+            id_to_weight.insert(toks[0].to_string(), weight);
+        }
+        let mut weights = Vec::new();
+        for bim_path in bim_paths.iter() {
+            let buf =
+                BufReader::new(OpenOptions::new().read(true).open(bim_path)?);
+            for (i, line) in buf.lines().enumerate() {
+                let line = line?;
+                let id = line.split_whitespace().nth(1).ok_or_else(|| {
+                    Error::Plink(format!(
+                        "line {} of {} has no SNP ID",
+                        i + 1,
+                        bim_path
+                    ))
+                })?;
+                weights.push(id_to_weight.get(id).cloned().unwrap_or(0.));
+            }
+        }
+        SnpWeights::new(weights)
+    }
+
+    /// The weights that standardize the SNPs with the minor allele
+    /// frequencies `mafs` as in the alpha model, e.g. with `alpha = -0.25` as
+    /// in LDAK, instead of to unit variance
+    pub fn from_alpha(alpha: f64, mafs: &[f32]) -> Result<SnpWeights, Error> {
+        SnpWeights::new(
+            mafs.iter()
+                .map(|&maf| get_alpha_model_weight(maf, alpha))
+                .collect(),
+        )
+    }
+
+    /// The products of the weights of every SNP, e.g. of the LDAK weights and
+    /// the alpha model weights
+    pub fn product(&self, other: &SnpWeights) -> Result<SnpWeights, Error> {
+        other.check_num_snps(self.num_snps())?;
+        SnpWeights::new(
+            self.weights
+                .iter()
+                .zip(other.weights.iter())
+                .map(|(a, b)| a * b)
+                .collect(),
+        )
+    }
+
+    pub fn num_snps(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn check_num_snps(&self, num_snps: usize) -> Result<(), Error> {
+        if self.weights.len() != num_snps {
+            return Err(Error::DimensionMismatch(format!(
+                "{} SNP weights for {} SNPs",
+                self.weights.len(),
+                num_snps
+            )));
+        }
+        Ok(())
+    }
+
+    /// `sum_i w_i` over the SNPs in `snp_range`, which takes the place of the
+    /// number of SNPs in the normalization of the additive kernel
+    pub fn sum(&self, snp_range: &OrderedIntegerSet<usize>) -> f64 {
+        snp_range.iter().map(|i| self.weights[i] as f64).sum()
+    }
+
+    /// `sum_{i < j} w_i w_j` over the SNPs in `snp_range`, which takes the
+    /// place of `m choose 2` in the normalization of the GxG kernel
+    pub fn sum_of_pair_products(
+        &self,
+        snp_range: &OrderedIntegerSet<usize>,
+    ) -> f64 {
+        let (sum, ssq) = snp_range.iter().fold((0f64, 0f64), |(s, q), i| {
+            let w = self.weights[i] as f64;
+            (s + w, q + w * w)
+        });
+        (sum * sum - ssq) / 2.
+    }
+
+    /// The square roots of the weights of the SNPs in `snp_range`, or of all
+    /// the SNPs if `None`, in the order in which they are streamed
+    pub fn get_sqrt_weights(
+        &self,
+        snp_range: Option<&OrderedIntegerSet<usize>>,
+    ) -> Vec<f32> {
+        match snp_range {
+            Some(range) => {
+                range.iter().map(|i| self.weights[i].sqrt()).collect()
+            }
+            None => self.weights.iter().map(|w| w.sqrt()).collect(),
+        }
+    }
+}
+
+/// Scales the standardized SNPs of a chunk by the square roots of their
+/// weights, where `offset` is the rank of the first SNP of the chunk among
+/// the `sqrt_weights` of the streamed SNPs
+pub fn weight_snp_chunk_inplace(
+    snp_chunk: &mut Array<f32, Ix2>,
+    sqrt_weights: Option<&[f32]>,
+    offset: usize,
+) {
+    if let Some(sqrt_weights) = sqrt_weights {
+        for (j, mut col) in snp_chunk.axis_iter_mut(Axis(1)).enumerate() {
+            col *= sqrt_weights[offset + j];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::array;
+    use tempfile::NamedTempFile;
+
+    use super::{weight_snp_chunk_inplace, SnpWeights};
+
+    #[test]
+    fn test_snp_weights() {
+        let weights = SnpWeights::new(vec![1., 0.25, 0., 4.]).unwrap();
+        let range = OrderedIntegerSet::from_slice(&[[0, 1], [3, 3]]);
+        assert_eq!(weights.sum(&range), 5.25);
+        // 1 * 0.25 + 1 * 4 + 0.25 * 4
+        assert_eq!(weights.sum_of_pair_products(&range), 5.25);
+        assert_eq!(weights.get_sqrt_weights(Some(&range)), vec![1., 0.5, 2.]);
+        assert!(weights.check_num_snps(4).is_ok());
+        assert!(weights.check_num_snps(5).is_err());
+
+        let mut chunk = array![[1f32, 1.], [-1., 2.]];
+        weight_snp_chunk_inplace(&mut chunk, Some(&[1., 0.5, 2.]), 1);
+        assert_eq!(chunk, array![[0.5f32, 2.], [-0.5, 4.]]);
+
+        let alpha = SnpWeights::from_alpha(-0.5, &[0.5, 0., 0.125]).unwrap();
+        for (w, expected) in alpha
+            .get_sqrt_weights(None)
+            .iter()
+            .zip([0.5f32.powf(0.25), 0., 0.21875f32.powf(0.25)].iter())
+        {
+            assert!((w - expected).abs() < 1e-6);
+        }
+        assert_eq!(
+            SnpWeights::from_alpha(-1., &[0.5, 0.1]).unwrap(),
+            SnpWeights::new(vec![1., 1.]).unwrap()
+        );
+        assert_eq!(
+            weights
+                .product(&SnpWeights::new(vec![2., 2., 1., 0.5]).unwrap())
+                .unwrap(),
+            SnpWeights::new(vec![2., 0.5, 0., 2.]).unwrap()
+        );
+        assert!(weights.product(&alpha).is_err());
+
+        assert!(SnpWeights::new(vec![0., 0.]).is_err());
+        assert!(SnpWeights::new(vec![1., -1.]).is_err());
+        assert!(SnpWeights::new(vec![1., std::f32::NAN]).is_err());
+
+        let mut bim = NamedTempFile::new().unwrap();
+        writeln!(bim, "1 rs1 0 100 A G\n1 rs2 0 200 C T\n2 rs3 0 300 A C")
+            .unwrap();
+        let mut weights_file = NamedTempFile::new().unwrap();
+        writeln!(weights_file, "rs3 0.5\nrs1 2").unwrap();
+        let weights =
+            SnpWeights::from_file(weights_file.path().to_str().unwrap(), &[
+                bim.path().to_str().unwrap().to_string(),
+            ])
+            .unwrap();
+        assert_eq!(weights, SnpWeights::new(vec![2., 0., 0.5]).unwrap());
+    }
+}
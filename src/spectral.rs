@@ -0,0 +1,420 @@
+//! Stochastic Lanczos quadrature (SLQ) estimates of the spectral sums
+//! `tr(f(K)) = sum_i f(lambda_i)` of a kernel, e.g. the log determinant, or
+//! the distribution of the eigenvalues of the GRM, which shows the
+//! population structure of a cohort before its heritability estimates are
+//! trusted.
+//!
+//! For every probe `z`, `num_lanczos_steps` Lanczos iterations started from
+//! `z / |z|` tridiagonalize `K` into `T`, and
+//! `z^T f(K) z ~ |z|^2 e_1^T f(T) e_1 = sum_k |z|^2 tau_k^2 f(theta_k)` is
+//! the Gauss quadrature of the spectral measure of `K` seen from `z`, with
+//! the eigenvalues `theta_k` of `T` as the nodes and the squared first
+//! entries `tau_k^2` of their eigenvectors as the weights. The rule is exact
+//! for the polynomials of degree below `2 num_lanczos_steps`, and the mean
+//! over the probes estimates `tr(f(K))` like the Hutchinson estimator. The
+//! quadrature rules are computed once, and any number of spectral sums are
+//! estimated from them.
+
+use ndarray::{Array, ArrayView, Ix1, Ix2};
+use ndarray_linalg::{Eigh, UPLO};
+
+use crate::{
+    error::Error,
+    linear_operator::LinearOperator,
+    partitioned_jackknife_estimates::TraceEstimate,
+    util::matrix_util::{generate_probe_matrix, ProbeType},
+};
+
+/// A Lanczos iteration stops early once the norm of the next vector falls
+/// below this fraction of the largest diagonal entry of `T`, i.e. once the
+/// Krylov space of the probe is exhausted, e.g. by a GRM of fewer SNPs than
+/// steps.
+const LANCZOS_BREAKDOWN_TOLERANCE: f64 = 1e-4;
+
+/// The Gauss quadrature rule of the Lanczos iterations from one probe `z`,
+/// whose weights sum to `|z|^2`
+#[derive(Clone, PartialEq, Debug)]
+struct ProbeQuadrature {
+    nodes: Vec<f64>,
+    weights: Vec<f64>,
+}
+
+impl ProbeQuadrature {
+    fn integrate<F: Fn(f64) -> f64>(&self, f: F) -> f64 {
+        self.nodes
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(&theta, &w)| w * f(theta))
+            .sum()
+    }
+}
+
+/// The quadrature rules of the probes of `stochastic_lanczos_quadrature`
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpectralQuadrature {
+    num_people: usize,
+    probe_rules: Vec<ProbeQuadrature>,
+}
+
+impl SpectralQuadrature {
+    pub fn num_people(&self) -> usize {
+        self.num_people
+    }
+
+    pub fn num_random_vecs(&self) -> usize {
+        self.probe_rules.len()
+    }
+
+    /// `tr(f(K))` with the Monte Carlo standard error over the probes
+    pub fn estimate_spectral_sum<F: Fn(f64) -> f64>(
+        &self,
+        name: &str,
+        f: F,
+    ) -> TraceEstimate {
+        let values: Vec<f64> = self
+            .probe_rules
+            .iter()
+            .map(|rule| rule.integrate(&f))
+            .collect();
+        TraceEstimate::from_probe_values(name.to_string(), &values)
+    }
+
+    /// `log det(K + shift I)`. The GRM of fewer SNPs than people is
+    /// singular, so a positive `shift` is needed to keep the logarithm
+    /// finite.
+    pub fn estimate_log_det(&self, shift: f64) -> Result<TraceEstimate, Error> {
+        let min_node = self.get_ritz_value_range().0;
+        if min_node + shift <= 0. {
+            return Err(Error::Generic(format!(
+                "the log determinant needs a positive definite K + shift I, \
+                but the smallest Ritz value {} of K is at most -shift {}",
+                min_node, -shift
+            )));
+        }
+        Ok(self.estimate_spectral_sum(
+            &format!("log det(K + {} I)", shift),
+            |theta| (theta + shift).ln(),
+        ))
+    }
+
+    /// The effective number of independent SNPs, i.e. the number of
+    /// independent SNPs whose GRM has the same variance of the off-diagonal
+    /// entries, `(tr K)^2 / (tr(K^2) - (tr K)^2 / n)` for `n` people, or
+    /// infinity if the off-diagonal entries are estimated to vanish.
+    pub fn estimate_effective_num_snps(&self) -> f64 {
+        let tr_k = self.estimate_spectral_sum("tr(K)", |theta| theta).value;
+        let tr_kk = self
+            .estimate_spectral_sum("tr(K^2)", |theta| theta * theta)
+            .value;
+        let off_diagonal = tr_kk - tr_k * tr_k / self.num_people as f64;
+        if off_diagonal > 0. {
+            tr_k * tr_k / off_diagonal
+        } else {
+            std::f64::INFINITY
+        }
+    }
+
+    /// The smallest and the largest Ritz values over all the probes, which
+    /// lie within the spectrum of `K`
+    pub fn get_ritz_value_range(&self) -> (f64, f64) {
+        self.probe_rules
+            .iter()
+            .flat_map(|rule| rule.nodes.iter())
+            .fold(
+                (std::f64::INFINITY, std::f64::NEG_INFINITY),
+                |(lo, hi), &x| (lo.min(x), hi.max(x)),
+            )
+    }
+
+    /// The estimated fraction of the eigenvalues of `K` in each of the bins
+    /// `[bin_edges[i], bin_edges[i + 1])`, where the last bin also includes
+    /// its upper edge. The fractions are unbiased, but may fall slightly
+    /// outside `[0, 1]` for few probes.
+    pub fn get_eigenvalue_histogram(
+        &self,
+        bin_edges: &[f64],
+    ) -> Result<Vec<f64>, Error> {
+        if bin_edges.len() < 2 {
+            return Err(Error::Generic(format!(
+                "expected at least 2 bin edges, received {}",
+                bin_edges.len()
+            )));
+        }
+        if bin_edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::Generic(format!(
+                "the bin edges have to be strictly increasing, received {:?}",
+                bin_edges
+            )));
+        }
+        let num_bins = bin_edges.len() - 1;
+        let last_edge = bin_edges[num_bins];
+        let scale = (self.num_people * self.num_random_vecs()) as f64;
+        let mut fractions = vec![0.; num_bins];
+        for rule in self.probe_rules.iter() {
+            for (&theta, &w) in rule.nodes.iter().zip(rule.weights.iter()) {
+                let bin = if theta == last_edge {
+                    Some(num_bins - 1)
+                } else {
+                    bin_edges
+                        .windows(2)
+                        .position(|pair| theta >= pair[0] && theta < pair[1])
+                };
+                if let Some(bin) = bin {
+                    fractions[bin] += w / scale;
+                }
+            }
+        }
+        Ok(fractions)
+    }
+}
+
+/// Runs `num_lanczos_steps` Lanczos iterations on the symmetric `kernel`
+/// from each of `num_random_vecs` probes of the `probe_type`, and returns
+/// the quadrature rules of the probes, from which the spectral sums of the
+/// kernel are estimated. The probes are iterated together, so that every
+/// step takes a single product with the kernel, e.g. a single pass over the
+/// genotypes of a `StreamedGrm`. The Lanczos vectors are kept to
+/// reorthogonalize every new vector against them, which takes
+/// `num_lanczos_steps` f32 matrices of the size of the probes. The number of
+/// steps is capped at the dimension of the kernel.
+pub fn stochastic_lanczos_quadrature<K: LinearOperator + ?Sized>(
+    kernel: &K,
+    num_random_vecs: usize,
+    num_lanczos_steps: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<SpectralQuadrature, Error> {
+    let num_people = kernel.dim();
+    if num_people == 0 {
+        return Err(Error::Generic("the kernel has no rows".to_string()));
+    }
+    if num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    if num_lanczos_steps == 0 {
+        return Err(Error::Generic(
+            "num_lanczos_steps has to be positive".to_string(),
+        ));
+    }
+    let num_steps = std::cmp::min(num_lanczos_steps, num_people);
+    let mut v = generate_probe_matrix(
+        num_people,
+        num_random_vecs,
+        probe_type,
+        None,
+        seed,
+    );
+    let mut probe_sq_norms = Vec::with_capacity(num_random_vecs);
+    for mut z in v.gencolumns_mut() {
+        let sq_norm = dot_f64(z.view(), z.view());
+        if sq_norm == 0. {
+            return Err(Error::Generic("received a zero probe".to_string()));
+        }
+        z /= sq_norm.sqrt() as f32;
+        probe_sq_norms.push(sq_norm);
+    }
+
+    let mut alphas = vec![Vec::new(); num_random_vecs];
+    let mut betas = vec![Vec::new(); num_random_vecs];
+    let mut active = vec![true; num_random_vecs];
+    let mut basis: Vec<Array<f32, Ix2>> = Vec::with_capacity(num_steps);
+    for step in 0..num_steps {
+        if !active.iter().any(|&a| a) {
+            break;
+        }
+        basis.push(v);
+        let current = &basis[step];
+        let mut w = kernel.apply(current);
+        if w.dim() != current.dim() {
+            return Err(Error::DimensionMismatch(format!(
+                "the kernel returned a product of shape {:?} for probes of \
+                shape {:?}",
+                w.dim(),
+                current.dim()
+            )));
+        }
+        for b in 0..num_random_vecs {
+            let mut w_b = w.column_mut(b);
+            if !active[b] {
+                w_b.fill(0.);
+                continue;
+            }
+            let alpha = dot_f64(current.column(b), w_b.view());
+            w_b.scaled_add(-alpha as f32, &current.column(b));
+            if step > 0 {
+                let beta = betas[b][step - 1];
+                w_b.scaled_add(-beta as f32, &basis[step - 1].column(b));
+            }
+            // the f32 Lanczos vectors lose their orthogonality within a few
+            // steps without the full reorthogonalization
+            for q in basis.iter() {
+                let c = dot_f64(q.column(b), w_b.view());
+                w_b.scaled_add(-c as f32, &q.column(b));
+            }
+            alphas[b].push(alpha);
+            let beta = dot_f64(w_b.view(), w_b.view()).sqrt();
+            let scale = alphas[b].iter().fold(0f64, |m, a| m.max(a.abs()));
+            if step + 1 == num_steps
+                || beta <= LANCZOS_BREAKDOWN_TOLERANCE * scale
+            {
+                active[b] = false;
+                w_b.fill(0.);
+            } else {
+                betas[b].push(beta);
+                w_b /= beta as f32;
+            }
+        }
+        v = w;
+    }
+
+    let probe_rules = alphas
+        .iter()
+        .zip(betas.iter())
+        .zip(probe_sq_norms.iter())
+        .map(|((alpha, beta), &sq_norm)| {
+            get_probe_quadrature(alpha, beta, sq_norm)
+        })
+        .collect::<Result<Vec<ProbeQuadrature>, Error>>()?;
+    Ok(SpectralQuadrature {
+        num_people,
+        probe_rules,
+    })
+}
+
+/// The quadrature rule of the tridiagonal `T` with the diagonal `alpha` and
+/// the off-diagonal `beta`, which is one entry shorter, scaled by the squared norm of the probe
+fn get_probe_quadrature(
+    alpha: &[f64],
+    beta: &[f64],
+    probe_sq_norm: f64,
+) -> Result<ProbeQuadrature, Error> {
+    let size = alpha.len();
+    let mut t = Array::<f64, Ix2>::zeros((size, size));
+    for (i, &a) in alpha.iter().enumerate() {
+        t[[i, i]] = a;
+    }
+    for (i, &b) in beta.iter().enumerate() {
+        t[[i, i + 1]] = b;
+        t[[i + 1, i]] = b;
+    }
+    let (nodes, eigenvectors) = t.eigh(UPLO::Lower).map_err(|why| {
+        format!(
+            "failed to diagonalize the Lanczos tridiagonal matrix: {:?}",
+            why
+        )
+    })?;
+    Ok(ProbeQuadrature {
+        nodes: nodes.to_vec(),
+        weights: eigenvectors
+            .row(0)
+            .iter()
+            .map(|tau| probe_sq_norm * tau * tau)
+            .collect(),
+    })
+}
+
+fn dot_f64(a: ArrayView<f32, Ix1>, b: ArrayView<f32, Ix1>) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(&x, &y)| x as f64 * y as f64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray_linalg::{Eigh, UPLO};
+
+    use super::stochastic_lanczos_quadrature;
+    use crate::util::{
+        brute_force::{assert_unbiased, grm, random_genotypes, trace},
+        matrix_util::ProbeType,
+    };
+
+    #[test]
+    fn test_stochastic_lanczos_quadrature() {
+        // 8 SNPs on 40 people, so that 32 of the eigenvalues of the GRM are
+        // zero and the Lanczos iterations break down after 9 steps
+        let geno = random_genotypes(40, 8);
+        let k = grm(&geno);
+        let k_f32 = k.mapv(|x| x as f32);
+        let (eigenvalues, _) = k.eigh(UPLO::Lower).unwrap();
+        let slq = |num_random_vecs: usize, seed: u64| {
+            stochastic_lanczos_quadrature(
+                &k_f32,
+                num_random_vecs,
+                15,
+                ProbeType::Rademacher,
+                Some(seed),
+            )
+            .unwrap()
+        };
+
+        let quadrature = slq(10, 1);
+        assert_eq!(quadrature.num_people(), 40);
+        assert_eq!(quadrature.num_random_vecs(), 10);
+        for rule in quadrature.probe_rules.iter() {
+            assert!(rule.nodes.len() <= 10);
+            let total_weight: f64 = rule.weights.iter().sum();
+            assert!((total_weight - 40.).abs() < 1e-3);
+        }
+        let (lo, hi) = quadrature.get_ritz_value_range();
+        let max_eigenvalue = eigenvalues.iter().cloned().fold(0., f64::max);
+        assert!(lo > -1e-3);
+        assert!(hi < max_eigenvalue * (1. + 1e-4));
+
+        assert_unbiased("SLQ tr(K)", trace(&k), 30, 1e-4, |r| {
+            slq(10, r as u64)
+                .estimate_spectral_sum("tr(K)", |theta| theta)
+                .value
+        });
+        let log_det: f64 = eigenvalues.iter().map(|l| (l + 1.).ln()).sum();
+        assert_unbiased("SLQ log det(K + I)", log_det, 30, 1e-4, |r| {
+            slq(10, r as u64).estimate_log_det(1.).unwrap().value
+        });
+        let num_zeros =
+            eigenvalues.iter().filter(|l| l.abs() < 0.01).count() as f64;
+        let bin_edges = [-0.01, 0.01, max_eigenvalue + 1.];
+        assert_unbiased(
+            "SLQ zero eigenvalues",
+            num_zeros / 40.,
+            30,
+            1e-4,
+            |r| {
+                slq(10, r as u64)
+                    .get_eigenvalue_histogram(&bin_edges)
+                    .unwrap()[0]
+            },
+        );
+
+        let tr_k = trace(&k);
+        let tr_kk: f64 = eigenvalues.iter().map(|l| l * l).sum();
+        let effective_num_snps = tr_k * tr_k / (tr_kk - tr_k * tr_k / 40.);
+        let estimate = slq(100, 2).estimate_effective_num_snps();
+        assert!(
+            estimate > effective_num_snps / 2.
+                && estimate < effective_num_snps * 2.
+        );
+
+        assert!(quadrature.estimate_log_det(-1.).is_err());
+        assert!(quadrature.get_eigenvalue_histogram(&[0.]).is_err());
+        assert!(quadrature.get_eigenvalue_histogram(&[1., 0.]).is_err());
+        assert!(stochastic_lanczos_quadrature(
+            &k_f32,
+            0,
+            15,
+            ProbeType::Rademacher,
+            None
+        )
+        .is_err());
+        assert!(stochastic_lanczos_quadrature(
+            &k_f32,
+            10,
+            0,
+            ProbeType::Rademacher,
+            None
+        )
+        .is_err());
+    }
+}
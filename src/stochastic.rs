@@ -0,0 +1,848 @@
+//! The trace and quadratic-form primitives behind the heritability
+//! estimators, exposed for building custom variance component models on the
+//! streaming genotype engine.
+//!
+//! The kernels are built from the standardized genotypes `X` of the `m` SNPs
+//! in a SNP range, i.e. `K = X X^T / m` for the additive component and
+//! `K_gxg = sum_{i < j} (x_i * x_j) (x_i * x_j)^T / (m choose 2)` for the
+//! pairwise GxG component, where `x_i * x_j` is the element-wise product of
+//! two SNPs. The genotypes are read one chunk of SNPs at a time from any
+//! `GenotypeSource`, so the kernels are never formed explicitly.
+//!
+//! Every primitive returns an `Estimate`. The randomized ones average over
+//! `num_random_vecs` Rademacher probe vectors and treat the probes as the
+//! jackknife units, for which the delete-one jackknife reduces to the mean of
+//! the probe values and its standard error. The exact ones have a standard
+//! error of zero. The probes of a randomized primitive are drawn from the
+//! `seed` if it is `Some`, so that the estimate is reproducible regardless
+//! of the number of threads.
+//!
+//! Unlike the estimators in `heritability_estimator` and `trace_estimator`,
+//! the signatures in this module are kept stable across releases.
+
+use biofile::plink_bed::PlinkBed;
+use math::{
+    set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
+    stats::n_choose_2,
+};
+use ndarray::{s, Array, Axis, Ix1, Ix2};
+use rayon::prelude::*;
+
+use crate::{
+    gpu::gram_dot,
+    integer_set::iter::Iter,
+    matrix_ops::DEFAULT_NUM_SNPS_PER_CHUNK,
+    partitioned_jackknife_estimates::Estimate,
+    snp_weights::{weight_snp_chunk_inplace, SnpWeights},
+    trace_estimator::compute_yky,
+    util::matrix_util::{
+        derive_seed, generate_sign_matrix, normalize_matrix_columns_inplace,
+    },
+};
+
+/// The maximum number of GxG sketch columns held in memory at once
+const MAX_GXG_SKETCH_BATCH_SIZE: usize = 100;
+
+/// A genotype matrix of shape `num_people x num_snps` that can be streamed
+/// in chunks of SNPs.
+pub trait GenotypeSource {
+    fn num_people(&self) -> usize;
+
+    fn num_snps(&self) -> usize;
+
+    /// Folds over the raw genotypes of consecutive chunks of `chunk_size`
+    /// SNPs in `snp_range`, or all the SNPs if `None`, in parallel. `fold`
+    /// receives the accumulator, the rank within `snp_range` of the first SNP
+    /// in the chunk and the `num_people x chunk_len` genotypes of the chunk.
+    /// The partial accumulators are combined with `reduce`.
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync;
+}
+
+impl GenotypeSource for PlinkBed {
+    fn num_people(&self) -> usize {
+        self.num_people
+    }
+
+    fn num_snps(&self) -> usize {
+        self.total_num_snps()
+    }
+
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync, {
+        self.col_chunk_iter(chunk_size, snp_range)
+            .into_par_iter()
+            .enumerate()
+            .fold(&identity, |acc, (chunk_index, snp_chunk)| {
+                fold(acc, chunk_index * chunk_size, snp_chunk)
+            })
+            .reduce(&identity, reduce)
+    }
+}
+
+/// An in-memory genotype matrix with one row per person.
+impl GenotypeSource for Array<f32, Ix2> {
+    fn num_people(&self) -> usize {
+        self.dim().0
+    }
+
+    fn num_snps(&self) -> usize {
+        self.dim().1
+    }
+
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync, {
+        let snp_indices: Vec<usize> = match snp_range {
+            Some(range) => range.iter().collect(),
+            None => (0..self.dim().1).collect(),
+        };
+        snp_indices
+            .par_chunks(chunk_size)
+            .enumerate()
+            .fold(&identity, |acc, (chunk_index, indices)| {
+                fold(
+                    acc,
+                    chunk_index * chunk_size,
+                    self.select(Axis(1), indices),
+                )
+            })
+            .reduce(&identity, reduce)
+    }
+}
+
+/// The genotypes of a subset of the people in `geno`, e.g. those on a keep
+/// list. The rows of the people are selected from every chunk as it is
+/// streamed, so the chunks are standardized over the kept people only and the
+/// full genotype matrix is never loaded.
+pub struct PeopleSubset<'a, G: GenotypeSource> {
+    geno: &'a G,
+    /// The row indices of the kept people, or `None` for all the people
+    people: Option<Vec<usize>>,
+}
+
+impl<'a, G: GenotypeSource> PeopleSubset<'a, G> {
+    /// `people` are the row indices of the people to keep, or `None` to keep
+    /// everyone
+    pub fn new(
+        geno: &'a G,
+        people: Option<&OrderedIntegerSet<usize>>,
+    ) -> Result<PeopleSubset<'a, G>, String> {
+        let people = match people {
+            None => None,
+            Some(people) => {
+                let people: Vec<usize> = people.iter().collect();
+                if people.is_empty() {
+                    return Err("no people are kept".to_string());
+                }
+                if let Some(&last) = people.last() {
+                    if last >= geno.num_people() {
+                        return Err(format!(
+                            "person index {} out of range for {} people",
+                            last,
+                            geno.num_people()
+                        ));
+                    }
+                }
+                Some(people)
+            }
+        };
+        Ok(PeopleSubset {
+            geno,
+            people,
+        })
+    }
+}
+
+impl<'a, G: GenotypeSource> GenotypeSource for PeopleSubset<'a, G> {
+    fn num_people(&self) -> usize {
+        match &self.people {
+            Some(people) => people.len(),
+            None => self.geno.num_people(),
+        }
+    }
+
+    fn num_snps(&self) -> usize {
+        self.geno.num_snps()
+    }
+
+    fn fold_snp_chunks<T, ID, F, R>(
+        &self,
+        snp_range: Option<OrderedIntegerSet<usize>>,
+        chunk_size: usize,
+        identity: ID,
+        fold: F,
+        reduce: R,
+    ) -> T
+    where
+        T: Send,
+        ID: Fn() -> T + Send + Sync,
+        F: Fn(T, usize, Array<f32, Ix2>) -> T + Send + Sync,
+        R: Fn(T, T) -> T + Send + Sync, {
+        let people = &self.people;
+        self.geno.fold_snp_chunks(
+            snp_range,
+            chunk_size,
+            identity,
+            |acc, rank, snp_chunk| match people {
+                Some(people) => {
+                    fold(acc, rank, snp_chunk.select(Axis(0), people))
+                }
+                None => fold(acc, rank, snp_chunk),
+            },
+            reduce,
+        )
+    }
+}
+
+/// tr(K), computed exactly as the sum of squares of the standardized
+/// genotypes divided by the number of SNPs.
+pub fn tr_k<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    num_snps_per_chunk: Option<usize>,
+) -> Result<Estimate<f64>, String> {
+    let num_snps = check_num_snps(geno, &snp_range, 1)?;
+    let ssq = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        || 0f64,
+        |acc, _, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            acc + snp_chunk.iter().map(|&x| (x * x) as f64).sum::<f64>()
+        },
+        |a, b| a + b,
+    );
+    Ok(exact_estimate(ssq / num_snps as f64))
+}
+
+/// tr(K K), estimated as the average of `|K z|^2` over the probes `z`.
+pub fn tr_kk<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Result<Estimate<f64>, String> {
+    let num_snps = check_num_snps(geno, &snp_range, 1)?;
+    check_num_random_vecs(num_random_vecs)?;
+    let probes =
+        generate_sign_matrix(geno.num_people(), num_random_vecs, None, seed);
+    let kz = g_gt_dot(geno, snp_range, None, &probes, num_snps_per_chunk);
+    let denom = (num_snps * num_snps) as f64;
+    Ok(estimate_from_probe_values(
+        kz.mapv(|x| x * x)
+            .sum_axis(Axis(0))
+            .iter()
+            .map(|&ssq| ssq as f64 / denom)
+            .collect(),
+    ))
+}
+
+/// tr(K_i K_j) for the kernels of two SNP ranges, estimated as the average
+/// of `(K_i z)^T (K_j z)` over the probes `z`.
+pub fn tr_ki_kj<G: GenotypeSource>(
+    geno: &G,
+    snp_range_i: Option<OrderedIntegerSet<usize>>,
+    snp_range_j: Option<OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Result<Estimate<f64>, String> {
+    let num_snps_i = check_num_snps(geno, &snp_range_i, 1)?;
+    let num_snps_j = check_num_snps(geno, &snp_range_j, 1)?;
+    check_num_random_vecs(num_random_vecs)?;
+    let probes =
+        generate_sign_matrix(geno.num_people(), num_random_vecs, None, seed);
+    let ki_z = g_gt_dot(geno, snp_range_i, None, &probes, num_snps_per_chunk);
+    let kj_z = g_gt_dot(geno, snp_range_j, None, &probes, num_snps_per_chunk);
+    let denom = (num_snps_i * num_snps_j) as f64;
+    Ok(estimate_from_probe_values(
+        (&ki_z * &kj_z)
+            .sum_axis(Axis(0))
+            .iter()
+            .map(|&dot| dot as f64 / denom)
+            .collect(),
+    ))
+}
+
+/// `y^T K y`, computed exactly as `|X^T y|^2` divided by the number of SNPs
+/// by `compute_yky`.
+pub fn y_k_y<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    y: &Array<f32, Ix1>,
+    num_snps_per_chunk: Option<usize>,
+) -> Result<Estimate<f64>, String> {
+    check_num_snps(geno, &snp_range, 1)?;
+    check_pheno_len(geno, y)?;
+    Ok(exact_estimate(
+        compute_yky(geno, y, snp_range, None, num_snps_per_chunk)
+            .map_err(|why| why.to_string())?
+            .yky,
+    ))
+}
+
+/// tr(K_gxg), estimated as the average of `|w|^2 / (m choose 2)` over the
+/// probes `u` on the SNPs, where `w = sum_{i < j} u_i u_j (x_i * x_j)`,
+/// which is `((X u)^2 - rowwise |x|^2) / 2` element-wise.
+pub fn tr_gxg_k<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Result<Estimate<f64>, String> {
+    let num_snps = check_num_snps(geno, &snp_range, 2)?;
+    check_num_random_vecs(num_random_vecs)?;
+    let w = gxg_sketch(
+        geno,
+        snp_range,
+        None,
+        &generate_sign_matrix(num_snps, num_random_vecs, None, seed),
+        num_snps_per_chunk,
+    );
+    let denom = n_choose_2(num_snps) as f64;
+    Ok(estimate_from_probe_values(
+        w.mapv(|x| x * x)
+            .sum_axis(Axis(0))
+            .iter()
+            .map(|&ssq| ssq as f64 / denom)
+            .collect(),
+    ))
+}
+
+/// `y^T K_gxg y`, which is `sum_{i < j} M_ij^2 / (m choose 2)` for
+/// `M = X^T diag(y) X`, estimated as the average of
+/// `(|M u|^2 - sum_i M_ii^2) / 2 / (m choose 2)` over the probes `u` on the
+/// SNPs. Takes two passes over the genotypes.
+pub fn y_gxg_k_y<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    y: &Array<f32, Ix1>,
+    num_random_vecs: usize,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Result<Estimate<f64>, String> {
+    let num_snps = check_num_snps(geno, &snp_range, 2)?;
+    check_num_random_vecs(num_random_vecs)?;
+    check_pheno_len(geno, y)?;
+    let chunk_size = num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let num_people = geno.num_people();
+    let probes = generate_sign_matrix(num_snps, num_random_vecs, None, seed);
+    let gu = geno.fold_snp_chunks(
+        snp_range.clone(),
+        chunk_size,
+        || Array::<f32, Ix2>::zeros((num_people, num_random_vecs)),
+        |gu, offset, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            let len = snp_chunk.dim().1;
+            gu + &snp_chunk.dot(&probes.slice(s![offset..offset + len, ..]))
+        },
+        |a, b| a + &b,
+    );
+    // M u = X^T (y * X u)
+    let y_gu = &gu * &y.view().insert_axis(Axis(1));
+    let (mu_ssq, diag_ssq) = geno.fold_snp_chunks(
+        snp_range,
+        chunk_size,
+        || (vec![0f64; num_random_vecs], 0f64),
+        |(mut mu_ssq, diag_ssq), _, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            let mu = snp_chunk.t().dot(&y_gu);
+            for (acc, col) in mu_ssq.iter_mut().zip(mu.gencolumns()) {
+                *acc += col.iter().map(|&x| (x * x) as f64).sum::<f64>();
+            }
+            let diag = y.dot(&(&snp_chunk * &snp_chunk));
+            (
+                mu_ssq,
+                diag_ssq + diag.iter().map(|&x| (x * x) as f64).sum::<f64>(),
+            )
+        },
+        |(mut mu_ssq_a, diag_ssq_a), (mu_ssq_b, diag_ssq_b)| {
+            for (a, b) in mu_ssq_a.iter_mut().zip(mu_ssq_b.into_iter()) {
+                *a += b;
+            }
+            (mu_ssq_a, diag_ssq_a + diag_ssq_b)
+        },
+    );
+    let denom = n_choose_2(num_snps) as f64;
+    Ok(estimate_from_probe_values(
+        mu_ssq
+            .into_iter()
+            .map(|ssq| (ssq - diag_ssq) / 2. / denom)
+            .collect(),
+    ))
+}
+
+/// Repeats a randomized primitive with batches of `batch_size` probes, e.g.
+/// `|n, seed| tr_gxg_k(&geno, None, n, None, seed)`, until the relative
+/// standard error of the pooled estimate is at most `tolerance` or the next
+/// batch would exceed `max_num_random_vecs` probes. The batch means are
+/// pooled as independent means of the same weight. If `seed` is `Some`, the
+/// `k`-th batch is seeded with `derive_seed(seed, k)`. Returns the pooled
+/// estimate and the number of probes used.
+pub fn estimate_to_tolerance<F>(
+    estimate: F,
+    batch_size: usize,
+    tolerance: f64,
+    max_num_random_vecs: usize,
+    seed: Option<u64>,
+) -> Result<(Estimate<f64>, usize), String>
+where
+    F: Fn(usize, Option<u64>) -> Result<Estimate<f64>, String>, {
+    if batch_size < 2 {
+        return Err(format!(
+            "a batch needs at least 2 probes for a standard error, received \
+            {}",
+            batch_size
+        ));
+    }
+    let mut batches: Vec<Estimate<f64>> = Vec::new();
+    loop {
+        let batch_seed =
+            seed.map(|seed| derive_seed(seed, batches.len() as u64));
+        batches.push(estimate(batch_size, batch_seed)?);
+        let num_batches = batches.len() as f64;
+        let mean = batches
+            .iter()
+            .map(|e| e.point_estimate_without_jackknife)
+            .sum::<f64>()
+            / num_batches;
+        let standard_error = batches
+            .iter()
+            .map(|e| e.standard_error * e.standard_error)
+            .sum::<f64>()
+            .sqrt()
+            / num_batches;
+        let num_used = batches.len() * batch_size;
+        if standard_error <= tolerance * mean.abs()
+            || num_used + batch_size > max_num_random_vecs
+        {
+            return Ok((
+                Estimate::new(mean, mean, mean, standard_error),
+                num_used,
+            ));
+        }
+    }
+}
+
+/// `X X^T probes` for the standardized genotypes `X` of the SNPs in
+/// `snp_range`, i.e. `K probes` times the number of SNPs. With
+/// `snp_weights`, it is `X W X^T probes`, i.e. `K probes` times the sum of
+/// the weights.
+pub(crate) fn g_gt_dot<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    probes: &Array<f32, Ix2>,
+    num_snps_per_chunk: Option<usize>,
+) -> Array<f32, Ix2> {
+    let shape = probes.dim();
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    let sqrt_weights = sqrt_weights.as_deref();
+    geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        || Array::<f32, Ix2>::zeros(shape),
+        |acc, offset, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, offset);
+            acc + &gram_dot(&snp_chunk, probes)
+        },
+        |a, b| a + &b,
+    )
+}
+
+/// `X rhs` for the standardized genotypes `X` of the SNPs in `snp_range`,
+/// where `rhs` has a row for every SNP in the range. With `snp_weights`,
+/// every standardized SNP `x_i` is scaled by `sqrt(w_i)` first.
+pub(crate) fn g_dot<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    rhs: &Array<f32, Ix2>,
+    num_snps_per_chunk: Option<usize>,
+) -> Array<f32, Ix2> {
+    let shape = (geno.num_people(), rhs.dim().1);
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    let sqrt_weights = sqrt_weights.as_deref();
+    geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        || Array::<f32, Ix2>::zeros(shape),
+        |acc, offset, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, offset);
+            let len = snp_chunk.dim().1;
+            acc + &snp_chunk.dot(&rhs.slice(s![offset..offset + len, ..]))
+        },
+        |a, b| a + &b,
+    )
+}
+
+/// The sketch `((X probes)^2 - rowwise |x|^2) / 2` of the pairwise products
+/// of the standardized genotypes `X` of the SNPs in `snp_range`, whose
+/// columns are `sum_{i < j} u_i u_j (x_i * x_j)` for the probes `u` on the
+/// SNPs, so that `E[w w^T]` is the unnormalized GxG kernel for every column
+/// `w`. With `snp_weights`, every standardized SNP `x_i` is scaled by
+/// `sqrt(w_i)` first.
+pub(crate) fn gxg_sketch<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    probes: &Array<f32, Ix2>,
+    num_snps_per_chunk: Option<usize>,
+) -> Array<f32, Ix2> {
+    let num_people = geno.num_people();
+    let num_probes = probes.dim().1;
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    let sqrt_weights = sqrt_weights.as_deref();
+    let (gu, row_ssq) = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        || {
+            (
+                Array::<f32, Ix2>::zeros((num_people, num_probes)),
+                Array::<f32, Ix1>::zeros(num_people),
+            )
+        },
+        |(gu, row_ssq), offset, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, offset);
+            let len = snp_chunk.dim().1;
+            (
+                gu + &snp_chunk
+                    .dot(&probes.slice(s![offset..offset + len, ..])),
+                row_ssq + &(&snp_chunk * &snp_chunk).sum_axis(Axis(1)),
+            )
+        },
+        |(gu_a, row_ssq_a), (gu_b, row_ssq_b)| {
+            (gu_a + &gu_b, row_ssq_a + &row_ssq_b)
+        },
+    );
+    (gu.mapv(|x| x * x) - &row_ssq.insert_axis(Axis(1))) / 2.
+}
+
+/// An unbiased estimate of `A rhs` for the unnormalized GxG kernel `A` of
+/// the SNPs in `snp_range`, i.e. `K_gxg rhs` times `m choose 2`, or times
+/// `sum_{i < j} w_i w_j` with `snp_weights`, from
+/// `num_sketches` columns of `gxg_sketch`. The sketches are drawn in batches
+/// of at most `MAX_GXG_SKETCH_BATCH_SIZE` columns, one pass over the
+/// genotypes each, so that only a batch is held in memory. If `seed` is
+/// `Some`, the batch of the sketches from the `k`-th on is drawn from the
+/// stream `derive_seed(seed, k)`.
+pub(crate) fn gxg_k_dot<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    rhs: &Array<f32, Ix2>,
+    num_sketches: usize,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let num_snps = match &snp_range {
+        Some(range) => range.size(),
+        None => geno.num_snps(),
+    };
+    let mut acc = Array::<f32, Ix2>::zeros(rhs.dim());
+    let mut num_drawn = 0;
+    while num_drawn < num_sketches {
+        let batch_size =
+            std::cmp::min(MAX_GXG_SKETCH_BATCH_SIZE, num_sketches - num_drawn);
+        let sketch = gxg_sketch(
+            geno,
+            snp_range.clone(),
+            snp_weights,
+            &generate_sign_matrix(
+                num_snps,
+                batch_size,
+                None,
+                seed.map(|s| derive_seed(s, num_drawn as u64)),
+            ),
+            num_snps_per_chunk,
+        );
+        acc += &gram_dot(&sketch, rhs);
+        num_drawn += batch_size;
+    }
+    acc / num_sketches as f32
+}
+
+fn check_num_snps<G: GenotypeSource>(
+    geno: &G,
+    snp_range: &Option<OrderedIntegerSet<usize>>,
+    min_num_snps: usize,
+) -> Result<usize, String> {
+    let num_snps = match snp_range {
+        Some(range) => range.size(),
+        None => geno.num_snps(),
+    };
+    if num_snps < min_num_snps {
+        return Err(format!(
+            "expected at least {} SNPs, received {}",
+            min_num_snps, num_snps
+        ));
+    }
+    Ok(num_snps)
+}
+
+fn check_num_random_vecs(num_random_vecs: usize) -> Result<(), String> {
+    if num_random_vecs == 0 {
+        Err("num_random_vecs has to be positive".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn check_pheno_len<G: GenotypeSource>(
+    geno: &G,
+    y: &Array<f32, Ix1>,
+) -> Result<(), String> {
+    if y.len() != geno.num_people() {
+        Err(format!(
+            "expected a phenotype of length {}, received {}",
+            geno.num_people(),
+            y.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn exact_estimate(value: f64) -> Estimate<f64> {
+    Estimate::new(value, value, value, 0.)
+}
+
+/// The mean of the per-probe values and its standard error, which is NaN for
+/// a single probe.
+fn estimate_from_probe_values(values: Vec<f64>) -> Estimate<f64> {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance =
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / (n - 1.);
+    Estimate::new(mean, mean, mean, (variance / n).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::{array, Array, Axis, Ix2};
+
+    use super::{
+        estimate_to_tolerance, g_gt_dot, gxg_k_dot, tr_gxg_k, tr_k, tr_ki_kj,
+        tr_kk, y_gxg_k_y, y_k_y, GenotypeSource, PeopleSubset,
+    };
+    use crate::util::matrix_util::normalize_matrix_columns_inplace;
+
+    fn get_geno() -> Array<f32, Ix2> {
+        array![
+            [0., 1., 2., 0., 1.],
+            [1., 1., 0., 2., 0.],
+            [2., 0., 1., 1., 1.],
+            [0., 2., 1., 0., 2.],
+            [1., 0., 2., 1., 0.],
+            [1., 1., 0., 2., 1.],
+        ]
+    }
+
+    fn assert_within_se(estimate: f64, se: f64, expected: f64) {
+        assert!(
+            (estimate - expected).abs() <= 5. * se + 1e-4 * expected.abs(),
+            "estimate {} with standard error {}, expected {}",
+            estimate,
+            se,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_additive_primitives() {
+        let geno = get_geno();
+        let y = array![1., -0.5, 0.3, 2., -1.2, 0.7];
+        let range = OrderedIntegerSet::from_slice(&[[0, 1], [3, 4]]);
+        let mut x = geno.select(Axis(1), &[0, 1, 3, 4]);
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k = x.dot(&x.t()) / 4.;
+
+        let est = tr_k(&geno, Some(range.clone()), Some(3)).unwrap();
+        assert!((est.point_estimate_without_jackknife - 6.).abs() < 1e-4);
+        assert_eq!(est.standard_error, 0.);
+
+        let est = y_k_y(&geno, Some(range.clone()), &y, Some(3)).unwrap();
+        let expected = y.dot(&k.dot(&y)) as f64;
+        assert!(
+            (est.point_estimate_without_jackknife - expected).abs()
+                < 1e-4 * expected.abs().max(1.)
+        );
+
+        let expected_tr_kk = (&k * &k).sum() as f64;
+        let est =
+            tr_kk(&geno, Some(range.clone()), 2000, Some(3), Some(1)).unwrap();
+        assert_within_se(
+            est.point_estimate_without_jackknife,
+            est.standard_error,
+            expected_tr_kk,
+        );
+        let est = tr_ki_kj(
+            &geno,
+            Some(range.clone()),
+            Some(range),
+            2000,
+            Some(3),
+            Some(1),
+        )
+        .unwrap();
+        assert_within_se(
+            est.point_estimate_without_jackknife,
+            est.standard_error,
+            expected_tr_kk,
+        );
+
+        assert!(y_k_y(&geno, None, &array![1., 2.], None).is_err());
+        // the seeded probes are reproducible
+        let seeded = |seed| {
+            tr_kk(&geno, None, 10, None, Some(seed))
+                .unwrap()
+                .point_estimate_without_jackknife
+        };
+        assert_eq!(seeded(4), seeded(4));
+        assert_ne!(seeded(4), seeded(5));
+        assert!(tr_kk(&geno, None, 0, None, None).is_err());
+    }
+
+    #[test]
+    fn test_gxg_primitives() {
+        let geno = get_geno();
+        let y = array![1., -0.5, 0.3, 2., -1.2, 0.7];
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let num_snps = x.dim().1;
+        let mut k = Array::<f32, Ix2>::zeros((6, 6));
+        for i in 0..num_snps {
+            for j in i + 1..num_snps {
+                let w = (&x.column(i) * &x.column(j)).insert_axis(Axis(1));
+                k = k + w.dot(&w.t());
+            }
+        }
+        k /= (num_snps * (num_snps - 1) / 2) as f32;
+
+        let est = tr_gxg_k(&geno, None, 2000, Some(2), None).unwrap();
+        assert_within_se(
+            est.point_estimate_without_jackknife,
+            est.standard_error,
+            k.diag().sum() as f64,
+        );
+        let est = y_gxg_k_y(&geno, None, &y, 2000, Some(2), None).unwrap();
+        assert_within_se(
+            est.point_estimate_without_jackknife,
+            est.standard_error,
+            y.dot(&k.dot(&y)) as f64,
+        );
+
+        // the sketched product is unnormalized, and drawn over several
+        // batches
+        let rhs = y.clone().insert_axis(Axis(1));
+        let expected = k.dot(&rhs) * 10.;
+        let product =
+            gxg_k_dot(&geno, None, None, &rhs, 20000, Some(2), Some(5));
+        let err = (&product - &expected).mapv(|x| x * x).sum().sqrt();
+        assert!(err < 0.1 * expected.mapv(|x| x * x).sum().sqrt());
+
+        let (est, num_used) = estimate_to_tolerance(
+            |n, seed| tr_gxg_k(&geno, None, n, Some(2), seed),
+            50,
+            0.02,
+            20000,
+            Some(7),
+        )
+        .unwrap();
+        assert_eq!(num_used % 50, 0);
+        assert!(
+            est.standard_error
+                <= 0.02 * est.point_estimate_without_jackknife.abs()
+                || num_used + 50 > 20000
+        );
+        assert_within_se(
+            est.point_estimate_without_jackknife,
+            est.standard_error,
+            k.diag().sum() as f64,
+        );
+        let (_, num_used) = estimate_to_tolerance(
+            |n, seed| tr_gxg_k(&geno, None, n, Some(2), seed),
+            50,
+            f64::INFINITY,
+            20000,
+            None,
+        )
+        .unwrap();
+        assert_eq!(num_used, 50);
+        assert!(estimate_to_tolerance(
+            |n, seed| tr_gxg_k(&geno, None, n, Some(2), seed),
+            1,
+            0.1,
+            100,
+            None
+        )
+        .is_err());
+
+        let single = OrderedIntegerSet::from_slice(&[[2, 2]]);
+        assert!(tr_gxg_k(&geno, Some(single), 10, None, None).is_err());
+    }
+
+    #[test]
+    fn test_people_subset() {
+        let geno = get_geno();
+        let people = OrderedIntegerSet::from_slice(&[[0, 1], [3, 5]]);
+        let subset = PeopleSubset::new(&geno, Some(&people)).unwrap();
+        assert_eq!(subset.num_people(), 5);
+        assert_eq!(subset.num_snps(), 5);
+
+        let kept = geno.select(Axis(0), &[0, 1, 3, 4, 5]);
+        let probes = Array::from_shape_fn((5, 3), |(i, j)| (i + j) as f32);
+        let product = g_gt_dot(&subset, None, None, &probes, Some(2));
+        let expected = g_gt_dot(&kept, None, None, &probes, Some(2));
+        for (a, b) in product.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        let all = PeopleSubset::new(&geno, None).unwrap();
+        assert_eq!(all.num_people(), 6);
+        let out_of_range = OrderedIntegerSet::from_slice(&[[4, 6]]);
+        assert!(PeopleSubset::new(&geno, Some(&out_of_range)).is_err());
+    }
+}
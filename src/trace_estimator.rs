@@ -1,76 +1,709 @@
+//! The randomized estimators of the traces and the `y^T K y` terms of the
+//! normal equations. Every estimator takes an optional SNP range, or a range
+//! for each kernel involved, and an optional set of `people`, the row
+//! indices of the people in the estimation, so that the jackknife blocks and
+//! the keep lists compose with every component. The probes, the phenotypes
+//! and the precomputed products passed in have one row per kept person, and
+//! the precomputed SNP means and standard deviations are expected to be over
+//! the kept people.
+
+use std::sync::Mutex;
+
 use biofile::plink_bed::PlinkBed;
 use math::{
     set::{ordered_integer_set::OrderedIntegerSet, traits::Finite},
     stats::{n_choose_2, sum_f32, sum_of_squares, sum_of_squares_f32},
 };
-use ndarray::{Array, Axis, Ix1, Ix2};
+use ndarray::{s, stack, Array, Axis, Ix1, Ix2};
+use ndarray_linalg::QR;
 use ndarray_parallel::prelude::*;
 use rayon::prelude::*;
 
 use crate::{
+    accumulation::{AccumulationPrecision, ArrayAccumulator},
+    compute::ComputeConfig,
+    error::Error,
+    gpu::gram_dot,
+    linear_operator::{GxgKernel, LinearOperator, StreamedGrm},
     matrix_ops::{
         normalized_g_dot_matrix, normalized_g_dot_rand,
         normalized_g_transpose_dot_matrix, DEFAULT_NUM_SNPS_PER_CHUNK,
     },
+    partitioned_jackknife_estimates::TraceEstimate,
+    progress::{ProgressCounter, ProgressSink},
+    snp_chunk_filter::{apply_snp_chunk_filter, SnpChunkFilter},
+    snp_weights::{weight_snp_chunk_inplace, SnpWeights},
+    stochastic::{GenotypeSource, PeopleSubset},
     util::matrix_util::{
-        generate_plus_minus_one_bernoulli_matrix,
-        normalize_matrix_columns_inplace,
+        derive_seed, generate_probe_matrix, generate_sign_matrix,
+        normalize_matrix_columns_inplace, ProbeType,
     },
+    workspace::{get_column_blocks, MatrixWorkspace},
 };
 
+/// Checks `num_random_vecs` against the dimensions of the problem and returns
+/// the number of probe vectors to use, or an error if it is zero.
+///
+/// With at least as many random vectors as people, the traces can be
+/// computed exactly with `num_people` probes (see
+/// `generate_trace_probe_matrix`), so `num_people` is returned instead. A
+/// warning is reported to the `progress` if the number exceeds the rank of
+/// the kernel, or if it is so small that the relative Monte Carlo standard
+/// error of a trace estimate, roughly `sqrt(2 / num_random_vecs)`, exceeds
+/// the expected share `1 / num_components` of each variance component.
+pub fn check_num_random_vecs(
+    num_random_vecs: usize,
+    num_people: usize,
+    num_snps: usize,
+    num_components: usize,
+    progress: &dyn ProgressSink,
+) -> Result<usize, String> {
+    if num_random_vecs == 0 {
+        return Err("num_random_vecs has to be positive".to_string());
+    }
+    if num_random_vecs >= num_people {
+        progress.message(&format!(
+            "\nWARNING: num_random_vecs {} >= num_people {}, \
+            computing the traces exactly with {} probe vectors instead",
+            num_random_vecs, num_people, num_people
+        ));
+        return Ok(num_people);
+    }
+    if num_random_vecs > num_snps {
+        progress.message(&format!(
+            "\nWARNING: num_random_vecs {} exceeds the kernel rank bound of {} \
+            SNPs, consider reducing it",
+            num_random_vecs, num_snps
+        ));
+    }
+    let relative_se = (2. / num_random_vecs as f64).sqrt();
+    let expected_share = 1. / num_components.max(1) as f64;
+    if relative_se > expected_share {
+        progress.message(&format!(
+            "\nWARNING: num_random_vecs {} is too small, the expected relative \
+            Monte Carlo standard error {:.3} exceeds the expected component \
+            share {:.3}, consider using at least {} random vectors",
+            num_random_vecs,
+            relative_se,
+            expected_share,
+            (2. / (expected_share * expected_share)).ceil() as usize
+        ));
+    }
+    Ok(num_random_vecs)
+}
+
 /// geno_bed has shape num_people x num_snps
+///
+/// SNPs failing the `snp_filter` are left out of the kernel. With
+/// `snp_weights`, the kernel is `X W X^T / sum_i w_i` over the SNPs kept.
+/// The probes are reproducible if `seed` is `Some`. The products of the SNP
+/// chunks and their sum of squares are accumulated in the `precision`. Every
+/// streamed SNP chunk and the estimate are reported to the `progress`.
 pub fn estimate_tr_kk(
     geno_bed: &mut PlinkBed,
     snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_filter: Option<&SnpChunkFilter>,
+    snp_weights: Option<&SnpWeights>,
     num_random_vecs: usize,
     num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+    precision: AccumulationPrecision,
+    progress: &dyn ProgressSink,
 ) -> f64 {
     let chunk_size = num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let counter = ProgressCounter::new(
+        progress,
+        "estimating tr(KK) on SNP chunk",
+        get_num_chunks(geno_bed, &snp_range, chunk_size),
+    );
 
-    let num_people = geno_bed.num_people;
-    let num_snps = match &snp_range {
-        Some(range) => range.size(),
-        None => geno_bed.total_num_snps(),
-    };
+    let people_rows = get_people_rows(people);
+    let num_people = people_rows
+        .as_ref()
+        .map_or(geno_bed.num_people, |rows| rows.len());
     let rand_mat =
-        generate_plus_minus_one_bernoulli_matrix(num_people, num_random_vecs);
-    let xxz_arr: Vec<f32> = geno_bed
+        generate_sign_matrix(num_people, num_random_vecs, None, seed);
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    // the number of SNPs is the sum of the weights of the SNPs kept
+    let (xxz_acc, num_snps): (ArrayAccumulator, f64) = geno_bed
         .col_chunk_iter(chunk_size, snp_range)
         .into_par_iter()
+        .enumerate()
         .fold(
-            || vec![0f32; num_people * num_random_vecs],
-            |mut acc, mut snp_chunk| {
+            || {
+                (
+                    ArrayAccumulator::zeros(
+                        precision,
+                        num_people * num_random_vecs,
+                    ),
+                    0f64,
+                )
+            },
+            |(mut acc, num_snps), (chunk_index, snp_chunk)| {
+                let offset = chunk_index * chunk_size;
+                let snp_chunk = select_people_rows(snp_chunk, &people_rows);
+                let chunk_sqrt_weights: Option<Vec<f32>> = sqrt_weights
+                    .as_ref()
+                    .map(|w| w[offset..offset + snp_chunk.dim().1].to_vec());
+                let (mut snp_chunk, chunk_sqrt_weights) = match snp_filter {
+                    Some(filter) => {
+                        let kept = filter.get_kept_columns(&snp_chunk);
+                        (
+                            snp_chunk.select(Axis(1), &kept),
+                            chunk_sqrt_weights.map(|w| {
+                                kept.iter().map(|&j| w[j]).collect::<Vec<f32>>()
+                            }),
+                        )
+                    }
+                    None => (snp_chunk, chunk_sqrt_weights),
+                };
                 normalize_matrix_columns_inplace(&mut snp_chunk, 0);
-                for (i, val) in snp_chunk
-                    .dot(&snp_chunk.t().dot(&rand_mat))
-                    .as_slice()
-                    .unwrap()
-                    .into_iter()
-                    .enumerate()
-                {
-                    acc[i] += val;
-                }
-                acc
+                weight_snp_chunk_inplace(
+                    &mut snp_chunk,
+                    chunk_sqrt_weights.as_deref(),
+                    0,
+                );
+                let chunk_num_snps = match &chunk_sqrt_weights {
+                    Some(w) => w.iter().map(|&s| (s * s) as f64).sum(),
+                    None => snp_chunk.dim().1 as f64,
+                };
+                acc.add(gram_dot(&snp_chunk, &rand_mat).iter());
+                counter.increment();
+                (acc, num_snps + chunk_num_snps)
             },
         )
         .reduce(
-            || vec![0f32; num_people * num_random_vecs],
-            |mut a, b| {
-                for (i, val) in b.iter().enumerate() {
-                    a[i] += val;
-                }
-                a
+            || {
+                (
+                    ArrayAccumulator::zeros(
+                        precision,
+                        num_people * num_random_vecs,
+                    ),
+                    0f64,
+                )
+            },
+            |(a, num_snps_a), (b, num_snps_b)| {
+                (a.merge(b), num_snps_a + num_snps_b)
             },
         );
 
-    sum_of_squares_f32(xxz_arr.iter()) as f64
-        / (num_snps * num_snps * num_random_vecs) as f64
+    let tr_kk = xxz_acc.sum_of_squares()
+        / (num_snps * num_snps * num_random_vecs as f64);
+    progress.trace_value("tr(KK)", tr_kk);
+    tr_kk
+}
+
+/// The exact `y^T K y` of the GRM `K` of a SNP range, together with the
+/// contribution of every streamed SNP chunk, e.g. to find the chunks that
+/// dominate the quadratic form
+#[derive(Clone, PartialEq, Debug)]
+pub struct YkyContributions {
+    pub yky: f64,
+    /// The contributions `|X_c^T y|^2 / num_snps` of the chunks `c` in the
+    /// order of their SNPs, which sum to `yky`
+    pub chunk_contributions: Vec<f64>,
+    /// The normalizer of the kernel, i.e. the number of SNPs, or the sum of
+    /// the weights with `snp_weights`
+    pub num_snps: f64,
+}
+
+/// `y^T K y = |X^T y|^2 / m` for the standardized genotypes `X` of the SNPs
+/// in `snp_range`, or `|W^(1/2) X^T y|^2 / sum_i w_i` with `snp_weights`,
+/// computed exactly in one pass over the genotypes in chunks of
+/// `num_snps_per_chunk` SNPs. The squares are summed in f64.
+pub fn compute_yky<G: GenotypeSource>(
+    geno: &G,
+    pheno: &Array<f32, Ix1>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    num_snps_per_chunk: Option<usize>,
+) -> Result<YkyContributions, Error> {
+    if pheno.len() != geno.num_people() {
+        return Err(Error::DimensionMismatch(format!(
+            "the phenotype has {} entries but the genotypes have {} people",
+            pheno.len(),
+            geno.num_people()
+        )));
+    }
+    if snp_range.as_ref().map_or(geno.num_snps(), |r| r.size()) == 0 {
+        return Err(Error::Generic(
+            "yKy requires at least one SNP".to_string(),
+        ));
+    }
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    // (the rank of the first SNP, |X_c^T y|^2, the number of SNPs) of the
+    // chunks
+    let mut chunks: Vec<(usize, f64, f64)> = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        Vec::new,
+        |mut acc, rank, mut snp_chunk| {
+            let chunk_sqrt_weights = sqrt_weights
+                .as_ref()
+                .map(|w| &w[rank..rank + snp_chunk.dim().1]);
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(&mut snp_chunk, chunk_sqrt_weights, 0);
+            let chunk_num_snps = match chunk_sqrt_weights {
+                Some(w) => w.iter().map(|&s| (s * s) as f64).sum(),
+                None => snp_chunk.dim().1 as f64,
+            };
+            let ssq = pheno
+                .dot(&snp_chunk)
+                .iter()
+                .map(|&x| x as f64 * x as f64)
+                .sum::<f64>();
+            acc.push((rank, ssq, chunk_num_snps));
+            acc
+        },
+        |mut a, mut b| {
+            a.append(&mut b);
+            a
+        },
+    );
+    chunks.sort_by_key(|&(rank, _, _)| rank);
+    let num_snps: f64 = chunks.iter().map(|&(_, _, m)| m).sum();
+    if num_snps <= 0. {
+        return Err(Error::Generic(
+            "the weights of the SNPs of yKy sum to zero".to_string(),
+        ));
+    }
+    let chunk_contributions: Vec<f64> =
+        chunks.iter().map(|&(_, ssq, _)| ssq / num_snps).collect();
+    Ok(YkyContributions {
+        yky: chunk_contributions.iter().sum(),
+        chunk_contributions,
+        num_snps,
+    })
+}
+
+/// The seed of the probes that continue a run of `num_previous_random_vecs`
+/// probes drawn from `seed`, whose streams are independent of the previous
+/// ones
+pub fn get_refinement_seed(
+    seed: Option<u64>,
+    num_previous_random_vecs: usize,
+) -> Option<u64> {
+    seed.map(|s| derive_seed(s, num_previous_random_vecs as u64))
+}
+
+/// Refines a `previous` trace estimate averaged over
+/// `num_previous_random_vecs` probes with `num_more_random_vecs` more, where
+/// `estimate(num_random_vecs, seed)` runs a randomized estimator, e.g.
+/// `estimate_tr_kk` or `estimate_gxg_kk_trace`, with new probes. The running
+/// sums of the two runs are combined, so the result is the average over all
+/// the probes, as if the estimator had been run once with
+/// `num_previous_random_vecs + num_more_random_vecs` probes.
+pub fn refine_trace_estimate<F>(
+    previous: f64,
+    num_previous_random_vecs: usize,
+    num_more_random_vecs: usize,
+    seed: Option<u64>,
+    estimate: F,
+) -> Result<f64, Error>
+where
+    F: FnOnce(usize, Option<u64>) -> Result<f64, Error>, {
+    if num_more_random_vecs == 0 {
+        return Ok(previous);
+    }
+    let more = estimate(
+        num_more_random_vecs,
+        get_refinement_seed(seed, num_previous_random_vecs),
+    )?;
+    Ok(combine_trace_estimates(
+        previous,
+        num_previous_random_vecs,
+        more,
+        num_more_random_vecs,
+    ))
+}
+
+/// The average over the probes of two runs averaged over `num_random_vecs_a`
+/// and `num_random_vecs_b` probes respectively
+pub fn combine_trace_estimates(
+    a: f64,
+    num_random_vecs_a: usize,
+    b: f64,
+    num_random_vecs_b: usize,
+) -> f64 {
+    (a * num_random_vecs_a as f64 + b * num_random_vecs_b as f64)
+        / (num_random_vecs_a + num_random_vecs_b) as f64
+}
+
+fn get_num_chunks(
+    geno_bed: &PlinkBed,
+    snp_range: &Option<OrderedIntegerSet<usize>>,
+    chunk_size: usize,
+) -> usize {
+    let num_snps = match snp_range {
+        Some(range) => range.size(),
+        None => geno_bed.total_num_snps(),
+    };
+    (num_snps + chunk_size - 1) / chunk_size
+}
+
+/// The row indices of the `people`, or `None` for everyone
+fn get_people_rows(
+    people: Option<&OrderedIntegerSet<usize>>,
+) -> Option<Vec<usize>> {
+    people.map(|people| people.iter().collect())
+}
+
+/// Keeps the rows of the people in `people_rows` of a streamed SNP chunk
+fn select_people_rows(
+    snp_chunk: Array<f32, Ix2>,
+    people_rows: &Option<Vec<usize>>,
+) -> Array<f32, Ix2> {
+    match people_rows {
+        Some(rows) => snp_chunk.select(Axis(0), rows),
+        None => snp_chunk,
+    }
+}
+
+/// Places the rows of `arr`, one for each of the `people_rows`, at those
+/// rows of a matrix over all the `num_people`, leaving zeros for the people
+/// left out, so that the products over the full bed only see the kept people
+fn scatter_people_rows(
+    arr: &Array<f32, Ix2>,
+    people_rows: &[usize],
+    num_people: usize,
+) -> Array<f32, Ix2> {
+    let mut scattered = Array::zeros((num_people, arr.dim().1));
+    for (row, &i) in arr.axis_iter(Axis(0)).zip(people_rows.iter()) {
+        scattered.row_mut(i).assign(&row);
+    }
+    scattered
+}
+
+/// `y` over all the `num_people` with zeros for the people left out, as in
+/// `scatter_people_rows`
+fn scatter_people_entries(
+    y: &Array<f32, Ix1>,
+    people_rows: &Option<Vec<usize>>,
+    num_people: usize,
+) -> Array<f32, Ix1> {
+    match people_rows {
+        Some(rows) => {
+            let mut scattered = Array::zeros(num_people);
+            for (&y_i, &i) in y.iter().zip(rows.iter()) {
+                scattered[i] = y_i;
+            }
+            scattered
+        }
+        None => y.to_owned(),
+    }
+}
+
+/// The rows of the `people` and the columns in `snp_range` of an in-memory
+/// genotype matrix, or `None` if both are `None` and `arr` is used as it is
+fn select_people_and_snps(
+    arr: &Array<f32, Ix2>,
+    snp_range: Option<&OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+) -> Option<Array<f32, Ix2>> {
+    let selected = match people {
+        Some(people) => {
+            Some(arr.select(Axis(0), &people.iter().collect::<Vec<usize>>()))
+        }
+        None => None,
+    };
+    match snp_range {
+        Some(range) => Some(
+            selected
+                .as_ref()
+                .unwrap_or(arr)
+                .select(Axis(1), &range.iter().collect::<Vec<usize>>()),
+        ),
+        None => selected,
+    }
+}
+
+/// The products of a Hutch++ estimate (Meyer et al. 2021) of a trace
+/// involving a symmetric matrix `A`, where `q` is an orthonormal basis of
+/// the low-rank sketch `A S` and `residual_probes` are the remaining probes
+/// projected onto the complement of `q`
+struct HutchppProducts {
+    q: Array<f32, Ix2>,
+    a_q: Array<f32, Ix2>,
+    residual_probes: Array<f32, Ix2>,
+    a_residual_probes: Array<f32, Ix2>,
+}
+
+/// Spends `num_random_vecs` products with the symmetric operator `A`. A
+/// third of the probes `S` find the basis `Q` of `A S`, which captures the
+/// top of the spectrum, and `A` is then applied to `Q` and to the other
+/// probes projected by `I - Q Q^T` in a single batch, i.e. two calls of
+/// `apply` in total.
+fn get_hutchpp_products<A: LinearOperator + ?Sized>(
+    a: &A,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<HutchppProducts, Error> {
+    let num_rows = a.dim();
+    if num_random_vecs < 3 {
+        return Err(Error::Generic(format!(
+            "Hutch++ requires at least 3 random vectors, received {}",
+            num_random_vecs
+        )));
+    }
+    let num_sketch_vecs = (num_random_vecs / 3).min(num_rows);
+    let num_residual_vecs = num_random_vecs - 2 * (num_random_vecs / 3);
+    let probes = generate_probe_matrix(
+        num_rows,
+        num_sketch_vecs + num_residual_vecs,
+        probe_type,
+        None,
+        seed,
+    );
+    let (q, _) = a
+        .apply(&probes.slice(s![.., ..num_sketch_vecs]).to_owned())
+        .qr()
+        .map_err(|why| {
+            format!("failed to orthonormalize the Hutch++ sketch: {:?}", why)
+        })?;
+    let g = probes.slice(s![.., num_sketch_vecs..]);
+    let residual_probes = &g - &q.dot(&q.t().dot(&g));
+    let a_products =
+        a.apply(&stack(Axis(1), &[q.view(), residual_probes.view()])?);
+    let rank = q.dim().1;
+    Ok(HutchppProducts {
+        a_q: a_products.slice(s![.., ..rank]).to_owned(),
+        a_residual_probes: a_products.slice(s![.., rank..]).to_owned(),
+        q,
+        residual_probes,
+    })
+}
+
+/// The Hutch++ estimate of `tr(A)`, i.e. `tr(Q^T A Q)` plus the Hutchinson
+/// estimate of the trace of the residual `(I - Q Q^T) A (I - Q Q^T)`. For
+/// the same number of products with `A`, the error shrinks as
+/// `1 / num_random_vecs` instead of `1 / sqrt(num_random_vecs)` for a
+/// positive semi-definite `A`.
+pub fn hutchpp_trace<A: LinearOperator + ?Sized>(
+    a: &A,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let p = get_hutchpp_products(a, num_random_vecs, probe_type, seed)?;
+    let num_residual_vecs = p.residual_probes.dim().1 as f64;
+    Ok(sum_f32((&p.q * &p.a_q).iter()) as f64
+        + sum_f32((&p.residual_probes * &p.a_residual_probes).iter()) as f64
+            / num_residual_vecs)
+}
+
+/// The Hutch++ estimate of `tr(A A) = ||A Q||_F^2 + ||A (I - Q Q^T)||_F^2`
+/// for a symmetric `A`, in which the residual term is estimated with the
+/// projected probes. Each of the two calls of `apply` is a single pass over
+/// the genotypes when `A` is a streamed kernel.
+pub fn hutchpp_trace_of_square<A: LinearOperator + ?Sized>(
+    a: &A,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let p = get_hutchpp_products(a, num_random_vecs, probe_type, seed)?;
+    let num_residual_vecs = p.residual_probes.dim().1 as f64;
+    Ok(sum_of_squares_f32(p.a_q.iter()) as f64
+        + sum_of_squares_f32(p.a_residual_probes.iter()) as f64
+            / num_residual_vecs)
+}
+
+/// The Hutchinson estimate of `tr(A)` of a user-defined symmetric
+/// `num_people x num_people` kernel `A` available only through `matvec`,
+/// which returns the product `A Z` with a batch of probes `Z` of
+/// `num_people` rows, i.e. the mean of `z^T A z` over the probes `z`. The
+/// kernel is applied to all the probes in a single call. Wrapped in a
+/// `MatvecOperator`, the same closure serves `hutchpp_trace` and the other
+/// estimators on a `LinearOperator`.
+pub fn estimate_trace<F>(
+    matvec: F,
+    num_people: usize,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error>
+where
+    F: Fn(&Array<f32, Ix2>) -> Array<f32, Ix2>, {
+    if num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    let probes = generate_probe_matrix(
+        num_people,
+        num_random_vecs,
+        probe_type,
+        None,
+        seed,
+    );
+    let product = matvec(&probes);
+    if product.dim() != probes.dim() {
+        return Err(Error::DimensionMismatch(format!(
+            "the matvec returned a product of dim {:?} for the probes of dim \
+            {:?}",
+            product.dim(),
+            probes.dim()
+        )));
+    }
+    Ok(sum_f32((&probes * &product).iter()) as f64 / num_random_vecs as f64)
+}
+
+/// The Hutchinson estimate of `tr(A B)` for two symmetric operators of the
+/// same dimension, i.e. the mean of `(A z)^T (B z)` over the probes `z`,
+/// which serves every pair of kernels, e.g. `tr(K_G K_GxG)`, with one batch
+/// of products per operator
+pub fn hutchinson_trace_of_product<A, B>(
+    a: &A,
+    b: &B,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error>
+where
+    A: LinearOperator + ?Sized,
+    B: LinearOperator + ?Sized, {
+    if a.dim() != b.dim() {
+        return Err(Error::DimensionMismatch(format!(
+            "cannot multiply operators of dimensions {} and {}",
+            a.dim(),
+            b.dim()
+        )));
+    }
+    if num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    let probes =
+        generate_probe_matrix(a.dim(), num_random_vecs, probe_type, None, seed);
+    Ok(
+        sum_f32((&a.apply(&probes) * &b.apply(&probes)).iter()) as f64
+            / num_random_vecs as f64,
+    )
+}
+
+/// The Hutch++ counterpart of `estimate_tr_kk`, which reaches the accuracy
+/// of the plain Hutchinson estimate with far fewer random vectors and hence
+/// far fewer products per pass over the genotypes. The kernel is
+/// `X W X^T / sum_i w_i` with `snp_weights`, and `X X^T / m` otherwise.
+pub fn estimate_tr_kk_hutchpp<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let geno = PeopleSubset::new(geno, people)?;
+    hutchpp_trace_of_square(
+        &StreamedGrm::new(&geno, snp_range, snp_weights, num_snps_per_chunk),
+        num_random_vecs,
+        probe_type,
+        seed,
+    )
+}
+
+/// The Hutchinson estimate of `tr(K K)` with the control variate
+/// `z^T D K z`, where `D` is the diagonal of `K`, whose expectation
+/// `tr(D K) = sum_i K_ii^2` is known exactly. The diagonal is accumulated
+/// from every SNP chunk in the same streaming pass as `K z`, so the control
+/// variate costs no extra pass over the genotypes, and the fraction of the
+/// Monte Carlo variance it removes is the `variance_reduction` of the
+/// estimate. The kernel is `X W X^T / sum_i w_i` with `snp_weights`, and
+/// `X X^T / m` otherwise. `K z`, the diagonal and the per-probe sums are
+/// accumulated in the `precision`.
+pub fn estimate_tr_kk_control_variate<G: GenotypeSource>(
+    geno: &G,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_weights: Option<&SnpWeights>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+    precision: AccumulationPrecision,
+) -> Result<TraceEstimate, Error> {
+    if num_random_vecs < 2 {
+        return Err(Error::Generic(format!(
+            "the control variate needs at least 2 random vectors, got {}",
+            num_random_vecs
+        )));
+    }
+    let geno = PeopleSubset::new(geno, people)?;
+    let num_people = geno.num_people();
+    let num_snps =
+        StreamedGrm::new(&geno, snp_range.clone(), snp_weights, None)
+            .num_snps();
+    let probes = generate_probe_matrix(
+        num_people,
+        num_random_vecs,
+        probe_type,
+        None,
+        seed,
+    );
+    let sqrt_weights =
+        snp_weights.map(|w| w.get_sqrt_weights(snp_range.as_ref()));
+    let sqrt_weights = sqrt_weights.as_deref();
+    let (ggz, diag) = geno.fold_snp_chunks(
+        snp_range,
+        num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK),
+        || {
+            (
+                ArrayAccumulator::zeros(
+                    precision,
+                    num_people * num_random_vecs,
+                ),
+                ArrayAccumulator::zeros(precision, num_people),
+            )
+        },
+        |(mut ggz, mut diag), offset, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, offset);
+            ggz.add(gram_dot(&snp_chunk, &probes).iter());
+            diag.add(snp_chunk.mapv(|x| x * x).sum_axis(Axis(1)).iter());
+            (ggz, diag)
+        },
+        |(ggz_a, diag_a), (ggz_b, diag_b)| {
+            (ggz_a.merge(ggz_b), diag_a.merge(diag_b))
+        },
+    );
+    let ggz =
+        Array::from_shape_vec((num_people, num_random_vecs), ggz.into_f64())?;
+    let diag = diag.into_f64();
+    let mm = num_snps * num_snps;
+    let values: Vec<f64> = ggz
+        .axis_iter(Axis(1))
+        .map(|col| precision.sum_of_squares(col.iter().cloned()) / mm)
+        .collect();
+    let control_values: Vec<f64> = ggz
+        .axis_iter(Axis(1))
+        .zip(probes.axis_iter(Axis(1)))
+        .map(|(col, z)| {
+            precision.dot(
+                diag.iter().zip(z.iter()).map(|(&d, &z)| d * z as f64),
+                col.iter().cloned(),
+            ) / mm
+        })
+        .collect();
+    Ok(TraceEstimate::from_control_variate(
+        "tr(K K)".to_string(),
+        &values,
+        &control_values,
+        precision.sum_of_squares(diag.iter().cloned()) / mm,
+    ))
 }
 
 pub fn estimate_tr_ki_kj(
     geno_bed: &mut PlinkBed,
     snp_range_i: Option<OrderedIntegerSet<usize>>,
     snp_range_j: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     snp_mean_i: &Array<f32, Ix1>,
     snp_std_i: &Array<f32, Ix1>,
     snp_mean_j: &Array<f32, Ix1>,
@@ -90,15 +723,19 @@ pub fn estimate_tr_ki_kj(
         None => geno_bed.total_num_snps(),
     };
 
+    let people_rows = get_people_rows(people);
     let gj_z = match precomputed_normalized_g_j_dot_rand {
         Some(arr) => arr.to_owned(),
-        None => normalized_g_dot_rand(
-            geno_bed,
-            snp_range_j,
-            snp_mean_j,
-            snp_std_j,
-            num_random_vecs,
-            Some(chunk_size),
+        None => select_people_rows(
+            normalized_g_dot_rand(
+                geno_bed,
+                snp_range_j,
+                snp_mean_j,
+                snp_std_j,
+                num_random_vecs,
+                Some(chunk_size),
+            ),
+            &people_rows,
         ),
     };
     let gj_z_col_sum = {
@@ -107,14 +744,16 @@ pub fn estimate_tr_ki_kj(
             .into_par_iter()
             .map(|col| sum_f32(col.iter()))
             .collect_into_vec(&mut col_sums);
-        Array::from_shape_vec(num_random_vecs, col_sums).unwrap()
+        Array::from_vec(col_sums)
     };
     let ssq = geno_bed
         .col_chunk_iter(chunk_size, snp_range_i)
         .into_par_iter()
         .enumerate()
         .fold_with(0f32, |mut acc, (chunk_index, snp_chunk)| {
-            let arr = snp_chunk.t().dot(&gj_z).as_slice().unwrap().to_owned();
+            let snp_chunk = select_people_rows(snp_chunk, &people_rows);
+            let arr: Vec<f32> =
+                snp_chunk.t().dot(&gj_z).iter().cloned().collect();
             for local_snp_index in 0..snp_chunk.dim().1 {
                 let offset = local_snp_index * num_random_vecs;
                 let m = snp_mean_i[chunk_index * chunk_size + local_snp_index];
@@ -130,72 +769,344 @@ pub fn estimate_tr_ki_kj(
     ssq as f64 / (num_snps_i * num_snps_j * num_random_vecs) as f64
 }
 
+/// The matrix of `tr(K_i K_j)` for the kernels `K_i = X_i X_i^T / m_i` of
+/// the `snp_ranges`, estimated from the products `K_i Z` of every kernel with
+/// the same Rademacher probes `Z`. The probes and the products are held in
+/// workspaces, which are spilled to memory-mapped files and processed in
+/// blocks of probe columns if they exceed the memory budget of `compute`.
+/// The `j`-th probe is drawn from the stream `derive_seed(seed, j)`, so the
+/// estimate does not depend on the blocks.
+pub fn estimate_tr_ki_kj_matrix<G: GenotypeSource>(
+    geno: &G,
+    snp_ranges: &[OrderedIntegerSet<usize>],
+    num_random_vecs: usize,
+    seed: Option<u64>,
+    compute: &ComputeConfig,
+) -> Result<Array<f64, Ix2>, Error> {
+    if num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    if snp_ranges.iter().any(|range| range.size() == 0) {
+        return Err(Error::Generic(
+            "expected at least one SNP in every kernel".to_string(),
+        ));
+    }
+    let num_people = geno.num_people();
+    let num_kernels = snp_ranges.len();
+    let spill =
+        compute.should_spill(num_people, num_random_vecs, num_kernels + 1);
+    let blocks = get_column_blocks(
+        num_random_vecs,
+        compute.get_num_columns_per_block(
+            num_people,
+            num_random_vecs,
+            num_kernels + 1,
+        ),
+    );
+    let num_snps_per_chunk = compute
+        .get_num_snps_per_chunk(num_people, blocks[0].1 - blocks[0].0)?;
+
+    let mut probes =
+        MatrixWorkspace::zeros(num_people, num_random_vecs, spill)?;
+    for &(start, end) in blocks.iter() {
+        probes.set_column_block(
+            start,
+            &generate_probe_columns(num_people, start, end, seed),
+        );
+    }
+    let mut products = Vec::with_capacity(num_kernels);
+    for range in snp_ranges.iter() {
+        let kernel = StreamedGrm::new(
+            geno,
+            Some(range.clone()),
+            None,
+            Some(num_snps_per_chunk),
+        );
+        let mut kz =
+            MatrixWorkspace::zeros(num_people, num_random_vecs, spill)?;
+        for &(start, end) in blocks.iter() {
+            kz.set_column_block(
+                start,
+                &kernel.apply(&probes.get_column_block(start, end)),
+            );
+        }
+        products.push(kz);
+    }
+    Ok(get_tr_ki_kj_matrix_from_products(&products, &blocks))
+}
+
+/// The matrix of `tr(K_i K_j)` for the disjoint `partitions` of the SNPs,
+/// like `estimate_tr_ki_kj_matrix`, but in a single pass over the genotypes
+/// instead of one pass per partition. Every SNP chunk is split by partition,
+/// and the product `X_i X_i^T Z` of each part is added to the product
+/// `K_i Z` of its partition, so that all the products are complete after
+/// the pass. The products are spilled to memory-mapped files if they exceed
+/// the memory budget of `compute`, while the probes stay in memory, as every
+/// chunk is multiplied with all of them. The probes are drawn as in
+/// `estimate_tr_ki_kj_matrix`, so the two agree up to the f32 rounding.
+pub fn estimate_partitioned_tr_ki_kj_matrix<G: GenotypeSource>(
+    geno: &G,
+    partitions: &[OrderedIntegerSet<usize>],
+    num_random_vecs: usize,
+    seed: Option<u64>,
+    compute: &ComputeConfig,
+) -> Result<Array<f64, Ix2>, Error> {
+    if num_random_vecs == 0 {
+        return Err(Error::Generic(
+            "num_random_vecs has to be positive".to_string(),
+        ));
+    }
+    let num_snps = geno.num_snps();
+    let num_partitions = partitions.len();
+    let mut snp_partitions: Vec<Option<usize>> = vec![None; num_snps];
+    for (p, partition) in partitions.iter().enumerate() {
+        if partition.size() == 0 {
+            return Err(Error::Generic(format!("partition {} has no SNPs", p)));
+        }
+        for snp in partition.iter() {
+            match snp_partitions.get_mut(snp) {
+                None => {
+                    return Err(Error::Generic(format!(
+                        "SNP {} of partition {} is out of range for {} SNPs",
+                        snp, p, num_snps
+                    )));
+                }
+                Some(Some(other)) => {
+                    return Err(Error::Generic(format!(
+                        "SNP {} is in both partitions {} and {}",
+                        snp, other, p
+                    )));
+                }
+                Some(entry) => *entry = Some(p),
+            }
+        }
+    }
+    // the partition of each SNP in the order of the union of the partitions,
+    // which is the order of the ranks of the streamed chunks
+    let mut union_intervals: Vec<[usize; 2]> = Vec::new();
+    let mut partition_by_rank = Vec::new();
+    for (snp, p) in snp_partitions.iter().enumerate() {
+        if let Some(p) = p {
+            match union_intervals.last_mut() {
+                Some(interval) if interval[1] + 1 == snp => interval[1] = snp,
+                _ => union_intervals.push([snp, snp]),
+            }
+            partition_by_rank.push(*p);
+        }
+    }
+
+    let num_people = geno.num_people();
+    let spill =
+        compute.should_spill(num_people, num_random_vecs, num_partitions + 1);
+    let blocks = get_column_blocks(
+        num_random_vecs,
+        compute.get_num_columns_per_block(
+            num_people,
+            num_random_vecs,
+            num_partitions + 1,
+        ),
+    );
+    let num_snps_per_chunk =
+        compute.get_num_snps_per_chunk(num_people, num_random_vecs)?;
+    let probes = generate_probe_columns(num_people, 0, num_random_vecs, seed);
+    let products = Mutex::new(
+        (0..num_partitions)
+            .map(|_| MatrixWorkspace::zeros(num_people, num_random_vecs, spill))
+            .collect::<Result<Vec<MatrixWorkspace>, String>>()?,
+    );
+    geno.fold_snp_chunks(
+        Some(OrderedIntegerSet::from_slice(&union_intervals)),
+        num_snps_per_chunk,
+        || (),
+        |_, rank, mut snp_chunk| {
+            normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+            let mut partition_columns = vec![Vec::new(); num_partitions];
+            let chunk_partitions =
+                &partition_by_rank[rank..rank + snp_chunk.dim().1];
+            for (k, &p) in chunk_partitions.iter().enumerate() {
+                partition_columns[p].push(k);
+            }
+            for (p, columns) in partition_columns.iter().enumerate() {
+                if columns.is_empty() {
+                    continue;
+                }
+                let part = snp_chunk.select(Axis(1), columns);
+                let product = gram_dot(&part, &probes);
+                let mut products = products.lock().unwrap();
+                let mut kz = products[p].view_mut();
+                kz += &product;
+            }
+        },
+        |_, _| (),
+    );
+
+    let mut products = products.into_inner().unwrap();
+    for (kz, partition) in products.iter_mut().zip(partitions.iter()) {
+        let num_snps = partition.size() as f32;
+        kz.view_mut().mapv_inplace(|x| x / num_snps);
+    }
+    Ok(get_tr_ki_kj_matrix_from_products(&products, &blocks))
+}
+
+/// The probe columns `start..end` of `num_people` Rademacher entries, where
+/// the `j`-th probe is drawn from the stream `derive_seed(seed, j)`
+fn generate_probe_columns(
+    num_people: usize,
+    start: usize,
+    end: usize,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let mut block = Array::<f32, Ix2>::zeros((num_people, end - start));
+    for (k, mut col) in block.axis_iter_mut(Axis(1)).enumerate() {
+        let probe_seed = seed.map(|s| derive_seed(s, (start + k) as u64));
+        col.assign(
+            &generate_sign_matrix(num_people, 1, None, probe_seed).column(0),
+        );
+    }
+    block
+}
+
+/// The seed of the outer probes of a nested GxG estimate, e.g. of
+/// `estimate_gxg_kk_trace`, if `outer_probe` is `None`, and of the nested
+/// probes of the `outer_probe`-th outer probe otherwise
+fn get_nested_probe_seed(
+    seed: Option<u64>,
+    outer_probe: Option<usize>,
+) -> Option<u64> {
+    seed.map(|s| derive_seed(s, outer_probe.map_or(0, |k| k as u64 + 1)))
+}
+
+/// `tr(K_i K_j) ~ sum_b (K_i z_b)^T (K_j z_b) / B` from the products
+/// `K_i Z` of the kernels with the same `B` probes, read a block of probe
+/// columns at a time
+fn get_tr_ki_kj_matrix_from_products(
+    products: &[MatrixWorkspace],
+    blocks: &[(usize, usize)],
+) -> Array<f64, Ix2> {
+    let num_kernels = products.len();
+    let num_random_vecs = blocks.last().map_or(0, |&(_, end)| end);
+    let mut tr = Array::<f64, Ix2>::zeros((num_kernels, num_kernels));
+    for &(start, end) in blocks.iter() {
+        let kz_blocks: Vec<Array<f32, Ix2>> = products
+            .iter()
+            .map(|kz| kz.get_column_block(start, end))
+            .collect();
+        for i in 0..num_kernels {
+            for j in i..num_kernels {
+                tr[[i, j]] +=
+                    sum_f32((&kz_blocks[i] * &kz_blocks[j]).iter()) as f64;
+            }
+        }
+    }
+    for i in 0..num_kernels {
+        for j in i..num_kernels {
+            tr[[i, j]] /= num_random_vecs as f64;
+            tr[[j, i]] = tr[[i, j]];
+        }
+    }
+    tr
+}
+
+/// SNPs failing the `snp_filter` are left out of the kernel. The probes are
+/// reproducible if `seed` is `Some`. The sum of squares of every SNP chunk
+/// is accumulated in the `precision`. Every streamed SNP chunk and the
+/// estimate are reported to the `progress`.
 pub fn estimate_tr_k(
     geno_bed: &mut PlinkBed,
     snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    snp_filter: Option<&SnpChunkFilter>,
     num_random_vecs: usize,
     num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
+    precision: AccumulationPrecision,
+    progress: &dyn ProgressSink,
 ) -> f64 {
     let chunk_size = num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
+    let counter = ProgressCounter::new(
+        progress,
+        "estimating tr(K) on SNP chunk",
+        get_num_chunks(geno_bed, &snp_range, chunk_size),
+    );
 
-    let num_people = geno_bed.num_people;
-    let num_snps = match &snp_range {
-        Some(range) => range.size(),
-        None => geno_bed.total_num_snps(),
-    };
+    let people_rows = get_people_rows(people);
+    let num_people = people_rows
+        .as_ref()
+        .map_or(geno_bed.num_people, |rows| rows.len());
     let rand_mat =
-        generate_plus_minus_one_bernoulli_matrix(num_people, num_random_vecs);
-    let sum_of_squares: f64 = geno_bed
+        generate_sign_matrix(num_people, num_random_vecs, None, seed);
+    let (sum_of_squares, num_snps): (f64, usize) = geno_bed
         .col_chunk_iter(chunk_size, snp_range)
         .into_par_iter()
-        .fold_with(0f64, |mut acc, mut snp_chunk| {
+        .fold_with((0f64, 0usize), |(acc, num_snps), snp_chunk| {
+            let mut snp_chunk = apply_snp_chunk_filter(
+                select_people_rows(snp_chunk, &people_rows),
+                snp_filter,
+            );
             normalize_matrix_columns_inplace(&mut snp_chunk, 0);
-            acc += sum_of_squares_f32(
-                snp_chunk.t().dot(&rand_mat).as_slice().unwrap().into_iter(),
-            ) as f64;
-            acc
+            counter.increment();
+            (
+                acc + precision.sum_of_squares(
+                    snp_chunk.t().dot(&rand_mat).iter().map(|&x| x as f64),
+                ),
+                num_snps + snp_chunk.dim().1,
+            )
         })
-        .sum();
-    sum_of_squares / (num_snps * num_random_vecs) as f64
+        .reduce(|| (0f64, 0usize), |a, b| (a.0 + b.0, a.1 + b.1));
+    let tr_k = sum_of_squares / (num_snps * num_random_vecs) as f64;
+    progress.trace_value("tr(K)", tr_k);
+    tr_k
 }
 
+/// `snp_range` selects the SNPs of the G kernel in `geno_arr` and
+/// `le_snp_range` the columns of `le_snps_arr` that form the GxG kernel.
+/// The probes are reproducible if `seed` is `Some`.
 pub fn estimate_tr_k_gxg_k(
     geno_arr: &mut PlinkBed,
     le_snps_arr: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    le_snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     num_random_vecs: usize,
     num_snps_per_chunk: Option<usize>,
+    seed: Option<u64>,
 ) -> f64 {
-    let u_arr = generate_plus_minus_one_bernoulli_matrix(
-        le_snps_arr.dim().1,
-        num_random_vecs,
-    );
+    let selected =
+        select_people_and_snps(le_snps_arr, le_snp_range.as_ref(), people);
+    let le_snps_arr = selected.as_ref().unwrap_or(le_snps_arr);
+    let people_rows = get_people_rows(people);
+    let num_snps = match &snp_range {
+        Some(range) => range.size(),
+        None => geno_arr.total_num_snps(),
+    };
+    let u_arr =
+        generate_sign_matrix(le_snps_arr.dim().1, num_random_vecs, None, seed);
     let mut sums = Vec::new();
     le_snps_arr
         .axis_iter(Axis(0))
         .into_par_iter()
         .map(|row| sum_of_squares_f32(row.iter()))
         .collect_into_vec(&mut sums);
-    let geno_ssq =
-        Array::from_shape_vec((le_snps_arr.dim().0, 1), sums).unwrap();
+    let geno_ssq = Array::from_vec(sums).insert_axis(Axis(1));
     let mut squashed = le_snps_arr.dot(&u_arr);
     squashed.par_iter_mut().for_each(|x| *x = (*x) * (*x));
     let corrected = (squashed - geno_ssq) / 2.;
 
     let chunk_size = num_snps_per_chunk.unwrap_or(DEFAULT_NUM_SNPS_PER_CHUNK);
     let ssq = geno_arr
-        .col_chunk_iter(chunk_size, None)
+        .col_chunk_iter(chunk_size, snp_range)
         .into_par_iter()
-        .fold_with(0f32, |mut acc, mut snp_chunk| {
+        .fold_with(0f32, |mut acc, snp_chunk| {
+            let mut snp_chunk = select_people_rows(snp_chunk, &people_rows);
             normalize_matrix_columns_inplace(&mut snp_chunk, 0);
-            acc += sum_of_squares_f32(
-                snp_chunk.t().dot(&corrected).as_slice().unwrap().iter(),
-            );
+            acc += sum_of_squares_f32(snp_chunk.t().dot(&corrected).iter());
             acc
         })
         .reduce(|| 0f32, |a, b| a + b);
-    (ssq / (geno_arr.total_num_snps()
-        * n_choose_2(le_snps_arr.dim().1)
-        * num_random_vecs) as f32) as f64
+    (ssq / (num_snps * n_choose_2(le_snps_arr.dim().1) * num_random_vecs)
+        as f32) as f64
 
     //    let gc = geno_arr.t().dot(&corrected);
     //    let mut sums = Vec::new();
@@ -208,14 +1119,29 @@ pub fn estimate_tr_k_gxg_k(
 }
 
 // TODO: test
+/// The probes are reproducible if `seed` is `Some`, where the nested probes
+/// of the `k`-th outer probe are drawn from the stream
+/// `derive_seed(seed, k + 1)`.
 pub fn estimate_tr_gxg_ki_gxg_kj(
     arr_i: &Array<f32, Ix2>,
     arr_j: &Array<f32, Ix2>,
+    snp_range_i: Option<OrderedIntegerSet<usize>>,
+    snp_range_j: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     num_random_vecs: usize,
+    seed: Option<u64>,
 ) -> f64 {
-    let u_arr = generate_plus_minus_one_bernoulli_matrix(
+    let selected_i =
+        select_people_and_snps(arr_i, snp_range_i.as_ref(), people);
+    let arr_i = selected_i.as_ref().unwrap_or(arr_i);
+    let selected_j =
+        select_people_and_snps(arr_j, snp_range_j.as_ref(), people);
+    let arr_j = selected_j.as_ref().unwrap_or(arr_j);
+    let u_arr = generate_sign_matrix(
         arr_i.dim().1,
         num_random_vecs,
+        None,
+        get_nested_probe_seed(seed, None),
     );
     let mut arr_i_row_sq_sums = Vec::new();
     arr_i
@@ -226,8 +1152,7 @@ pub fn estimate_tr_gxg_ki_gxg_kj(
     let mut arr_i_squashed = arr_i.dot(&u_arr);
     arr_i_squashed.par_iter_mut().for_each(|x| *x = (*x) * (*x));
     let arr_i_uugg_sums = (arr_i_squashed
-        - Array::from_shape_vec((arr_i.dim().0, 1), arr_i_row_sq_sums)
-            .unwrap())
+        - Array::from_vec(arr_i_row_sq_sums).insert_axis(Axis(1)))
         / 2.;
 
     let arr_j_sq = arr_j * arr_j;
@@ -236,10 +1161,13 @@ pub fn estimate_tr_gxg_ki_gxg_kj(
     arr_i_uugg_sums
         .axis_iter(Axis(1))
         .into_par_iter()
-        .map(|uugg_sum| {
-            let rand_vecs = generate_plus_minus_one_bernoulli_matrix(
+        .enumerate()
+        .map(|(k, uugg_sum)| {
+            let rand_vecs = generate_sign_matrix(
                 arr_j.dim().1,
                 num_rand_z_vecs,
+                None,
+                get_nested_probe_seed(seed, Some(k)),
             );
             let arr_j_dot_rand_vecs = arr_j.dot(&rand_vecs);
             let wg = &arr_j.t() * &uugg_sum;
@@ -255,10 +1183,16 @@ pub fn estimate_tr_gxg_ki_gxg_kj(
             * num_random_vecs) as f32) as f64
 }
 
+/// The probes are reproducible if `seed` is `Some`.
 pub fn estimate_gxg_gram_trace(
     geno_arr: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     num_random_vecs: usize,
-) -> Result<f64, String> {
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let selected = select_people_and_snps(geno_arr, snp_range.as_ref(), people);
+    let geno_arr = selected.as_ref().unwrap_or(geno_arr);
     let (_num_rows, num_cols) = geno_arr.dim();
 
     let mut row_sums = Vec::new();
@@ -267,11 +1201,9 @@ pub fn estimate_gxg_gram_trace(
         .into_par_iter()
         .map(|row| sum_of_squares_f32(row.iter()))
         .collect_into_vec(&mut row_sums);
-    let geno_ssq =
-        Array::from_shape_vec((row_sums.len(), 1), row_sums).unwrap();
+    let geno_ssq = Array::from_vec(row_sums).insert_axis(Axis(1));
 
-    let u_arr =
-        generate_plus_minus_one_bernoulli_matrix(num_cols, num_random_vecs);
+    let u_arr = generate_sign_matrix(num_cols, num_random_vecs, None, seed);
     let mut squashed = geno_arr.dot(&u_arr);
     squashed.par_iter_mut().for_each(|x| *x = (*x) * (*x));
     squashed = (squashed - &geno_ssq) / 2.;
@@ -285,18 +1217,33 @@ pub fn estimate_gxg_gram_trace(
     Ok(sums.into_iter().sum::<f64>() / num_random_vecs as f64)
 }
 
+/// The probes are reproducible if `seed` is `Some`, where the nested probes
+/// of the `k`-th outer probe are drawn from the stream
+/// `derive_seed(seed, k + 1)`. The numbers of probes are reported to the
+/// `progress`.
 pub fn estimate_gxg_kk_trace(
     gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     num_random_vecs: usize,
-) -> Result<f64, String> {
+    seed: Option<u64>,
+    progress: &dyn ProgressSink,
+) -> Result<f64, Error> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
     let num_rand_z_vecs = 100;
-    println!(
+    progress.message(&format!(
         "estimate_gxg_kk_trace\nnum_random_vecs: {}\nnum_rand_z_vecs: {}",
         num_random_vecs, num_rand_z_vecs
-    );
+    ));
     let (_num_rows, num_le_snps) = gxg_basis.dim();
-    let u_arr =
-        generate_plus_minus_one_bernoulli_matrix(num_le_snps, num_random_vecs);
+    let u_arr = generate_sign_matrix(
+        num_le_snps,
+        num_random_vecs,
+        None,
+        get_nested_probe_seed(seed, None),
+    );
 
     let gxg_basis_sq = gxg_basis * gxg_basis;
     let mut row_sums = Vec::new();
@@ -305,8 +1252,7 @@ pub fn estimate_gxg_kk_trace(
         .into_par_iter()
         .map(|row| sum_f32(row.iter()))
         .collect_into_vec(&mut row_sums);
-    let geno_ssq =
-        Array::from_shape_vec((row_sums.len(), 1), row_sums).unwrap();
+    let geno_ssq = Array::from_vec(row_sums).insert_axis(Axis(1));
 
     let mut uugg_sum_matrix = gxg_basis.dot(&u_arr);
     uugg_sum_matrix
@@ -318,10 +1264,13 @@ pub fn estimate_gxg_kk_trace(
     uugg_sum_matrix
         .axis_iter(Axis(1))
         .into_par_iter()
-        .map(|uugg_sum| {
-            let rand_vecs = generate_plus_minus_one_bernoulli_matrix(
+        .enumerate()
+        .map(|(k, uugg_sum)| {
+            let rand_vecs = generate_sign_matrix(
                 num_le_snps,
                 num_rand_z_vecs,
+                None,
+                get_nested_probe_seed(seed, Some(k)),
             );
             let geno_arr_dot_rand_vecs = gxg_basis.dot(&rand_vecs);
             let wg = &gxg_basis.t() * &uugg_sum;
@@ -348,16 +1297,267 @@ pub fn estimate_gxg_kk_trace(
     //    Ok(avg)
 }
 
+/// An unbiased estimate of `A V` for the columns of `rhs`, where
+/// `A = sum_{i < j} (g_i * g_j)(g_i * g_j)^T` is the unnormalized GxG kernel
+/// of all the pairs of `gxg_basis`. The columns
+/// `((G u)^2 - rowssq(G)) / 2` for random +-1 vectors `u` form a sketch `S`
+/// of the pair products with `E[S S^T] = A` over `num_random_vecs` columns.
+/// The sketches are reproducible if `seed` is `Some`.
+pub fn estimate_gxg_kernel_dot_matrix(
+    gxg_basis: &Array<f32, Ix2>,
+    rhs: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    let (_num_people, num_basis_snps) = gxg_basis.dim();
+    let geno_ssq = Array::from_vec(
+        gxg_basis
+            .axis_iter(Axis(0))
+            .map(|row| sum_of_squares_f32(row.iter()))
+            .collect(),
+    )
+    .insert_axis(Axis(1));
+    let u_arr =
+        generate_sign_matrix(num_basis_snps, num_random_vecs, None, seed);
+    let mut sketch = gxg_basis.dot(&u_arr);
+    sketch.par_iter_mut().for_each(|x| *x = (*x) * (*x));
+    sketch = (sketch - &geno_ssq) / 2.;
+    gram_dot(&sketch, rhs) / num_random_vecs as f32
+}
+
+/// `A V` for the columns of `rhs`, where `A` is the unnormalized GxG kernel
+/// of all the pairs of `gxg_basis` as in `estimate_gxg_kernel_dot_matrix`,
+/// computed exactly without forming the pair products. With
+/// `M = G^T diag(v) G`, `(g_i * g_j)^T v = M_ij`, so that
+/// `A v = (((G M) * G) 1 - (G * G) diag(M)) / 2`.
+pub fn gxg_kernel_dot_matrix(
+    gxg_basis: &Array<f32, Ix2>,
+    rhs: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+) -> Array<f32, Ix2> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    let gxg_basis_sq = gxg_basis * gxg_basis;
+    let mut columns = Vec::new();
+    rhs.axis_iter(Axis(1))
+        .into_par_iter()
+        .map(|v| {
+            let m = (&gxg_basis.t() * &v).dot(gxg_basis);
+            ((&gxg_basis.dot(&m) * gxg_basis).sum_axis(Axis(1))
+                - gxg_basis_sq.dot(&m.diag()))
+                / 2.
+        })
+        .collect_into_vec(&mut columns);
+    let mut product = Array::zeros((gxg_basis.dim().0, rhs.dim().1));
+    for (mut col, av) in product.axis_iter_mut(Axis(1)).zip(columns.iter()) {
+        col.assign(av);
+    }
+    product
+}
+
+/// The Hutch++ counterpart of `estimate_gxg_gram_trace`, i.e. the
+/// unnormalized `tr(A)`
+pub fn estimate_gxg_gram_trace_hutchpp(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    let kernel = GxgKernel::new(gxg_basis);
+    Ok(hutchpp_trace(&kernel, num_random_vecs, probe_type, seed)?
+        * kernel.num_pairs())
+}
+
+/// The Hutch++ counterpart of `estimate_gxg_kk_trace`, i.e. `tr(A A)`
+/// normalized by the squared number of pairs. Unlike the nested sketches of
+/// `estimate_gxg_kk_trace`, the products with `A` are exact, so the only
+/// randomness is in the probes.
+pub fn estimate_gxg_kk_trace_hutchpp(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    hutchpp_trace_of_square(
+        &GxgKernel::new(gxg_basis),
+        num_random_vecs,
+        probe_type,
+        seed,
+    )
+}
+
+/// The GxG components with at most this many pairs of basis SNPs have their
+/// traces computed exactly by `get_gxg_gram_trace` and `get_gxg_kk_trace`.
+/// The pair products then take `num_people * DEFAULT_EXACT_GXG_MAX_NUM_PAIRS`
+/// floats, and their Gram matrix at most `DEFAULT_EXACT_GXG_MAX_NUM_PAIRS^2`.
+pub const DEFAULT_EXACT_GXG_MAX_NUM_PAIRS: usize = 2000;
+
+/// The columns `g_i * g_j` for all the pairs `i < j` of columns of
+/// `gxg_basis`
+fn get_gxg_pair_products(gxg_basis: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+    let (num_people, num_basis_snps) = gxg_basis.dim();
+    let mut products =
+        Array::<f32, Ix2>::zeros((num_people, n_choose_2(num_basis_snps)));
+    let mut k = 0;
+    for i in 0..num_basis_snps {
+        for j in i + 1..num_basis_snps {
+            products
+                .column_mut(k)
+                .assign(&(&gxg_basis.column(i) * &gxg_basis.column(j)));
+            k += 1;
+        }
+    }
+    products
+}
+
+/// The exact unnormalized `tr(A) = sum_{i < j} ||g_i * g_j||^2`, computed
+/// row by row as `((sum_k g_k^2)^2 - sum_k g_k^4) / 2`
+pub fn gxg_gram_trace(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+) -> Result<f64, Error> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    Ok(gxg_basis
+        .axis_iter(Axis(0))
+        .into_par_iter()
+        .map(|row| {
+            let (ssq, sum_4th) = row.iter().fold((0f64, 0f64), |(a, b), &g| {
+                let g_sq = g as f64 * g as f64;
+                (a + g_sq, b + g_sq * g_sq)
+            });
+            (ssq * ssq - sum_4th) / 2.
+        })
+        .sum())
+}
+
+/// The exact `tr(A A)` normalized by the squared number of pairs, the
+/// counterpart of `estimate_gxg_kk_trace`. The pair products `P` are formed
+/// explicitly and `tr(A A)` is the squared Frobenius norm of the smaller of
+/// `P^T P` and `P P^T`.
+pub fn gxg_kk_trace(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+) -> Result<f64, Error> {
+    let selected =
+        select_people_and_snps(gxg_basis, snp_range.as_ref(), people);
+    let gxg_basis = selected.as_ref().unwrap_or(gxg_basis);
+    let num_basis_snps = gxg_basis.dim().1;
+    if num_basis_snps < 2 {
+        return Err(Error::Generic(format!(
+            "expected at least 2 SNPs for the GxG kernel, received {}",
+            num_basis_snps
+        )));
+    }
+    let products = get_gxg_pair_products(gxg_basis);
+    let (num_people, num_pairs) = products.dim();
+    let gram = if num_pairs <= num_people {
+        products.t().dot(&products)
+    } else {
+        products.dot(&products.t())
+    };
+    let mm = n_choose_2(num_basis_snps) as f64;
+    Ok(sum_of_squares_f32(gram.iter()) as f64 / (mm * mm))
+}
+
+/// The unnormalized `tr(A)` of `gxg_gram_trace` for the components with at
+/// most `DEFAULT_EXACT_GXG_MAX_NUM_PAIRS` pairs of basis SNPs, where the
+/// randomized estimate would be noisier than computing the trace, and the
+/// estimate of `estimate_gxg_gram_trace_hutchpp` otherwise
+pub fn get_gxg_gram_trace(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    if is_small_gxg_component(gxg_basis, snp_range.as_ref()) {
+        gxg_gram_trace(gxg_basis, snp_range, people)
+    } else {
+        estimate_gxg_gram_trace_hutchpp(
+            gxg_basis,
+            snp_range,
+            people,
+            num_random_vecs,
+            probe_type,
+            seed,
+        )
+    }
+}
+
+/// The normalized `tr(A A)` of `gxg_kk_trace` for the components with at
+/// most `DEFAULT_EXACT_GXG_MAX_NUM_PAIRS` pairs of basis SNPs, and the
+/// estimate of `estimate_gxg_kk_trace_hutchpp` otherwise
+pub fn get_gxg_kk_trace(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    seed: Option<u64>,
+) -> Result<f64, Error> {
+    if is_small_gxg_component(gxg_basis, snp_range.as_ref()) {
+        gxg_kk_trace(gxg_basis, snp_range, people)
+    } else {
+        estimate_gxg_kk_trace_hutchpp(
+            gxg_basis,
+            snp_range,
+            people,
+            num_random_vecs,
+            probe_type,
+            seed,
+        )
+    }
+}
+
+fn is_small_gxg_component(
+    gxg_basis: &Array<f32, Ix2>,
+    snp_range: Option<&OrderedIntegerSet<usize>>,
+) -> bool {
+    let num_basis_snps = match snp_range {
+        Some(range) => range.size(),
+        None => gxg_basis.dim().1,
+    };
+    n_choose_2(num_basis_snps) <= DEFAULT_EXACT_GXG_MAX_NUM_PAIRS
+}
+
+/// The probes are reproducible if `seed` is `Some`.
 pub fn estimate_gxg_dot_y_norm_sq(
     gxg_basis_arr: &Array<f32, Ix2>,
     y: &Array<f32, Ix1>,
+    snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     num_random_vecs: usize,
+    seed: Option<u64>,
 ) -> f64 {
+    let selected =
+        select_people_and_snps(gxg_basis_arr, snp_range.as_ref(), people);
+    let gxg_basis_arr = selected.as_ref().unwrap_or(gxg_basis_arr);
     let (_num_rows, num_cols) = gxg_basis_arr.dim();
     let gg_sq_dot_y = (gxg_basis_arr * gxg_basis_arr).t().dot(y);
     let s = (&gg_sq_dot_y * &gg_sq_dot_y).sum();
-    let rand_vecs =
-        generate_plus_minus_one_bernoulli_matrix(num_cols, num_random_vecs);
+    let rand_vecs = generate_sign_matrix(num_cols, num_random_vecs, None, seed);
     let geno_arr_dot_rand_vecs = gxg_basis_arr.dot(&rand_vecs);
     let wg = &gxg_basis_arr.t() * y;
     let mut ggz = wg.dot(&geno_arr_dot_rand_vecs);
@@ -365,24 +1565,29 @@ pub fn estimate_gxg_dot_y_norm_sq(
     ((ggz.sum() / num_random_vecs as f32 - s) / 2.) as f64
 }
 
+/// The probes are reproducible if `seed` is `Some`.
 pub fn estimate_gxg_dot_y_norm_sq_from_basis_bed(
     gxg_basis_bed: &PlinkBed,
     snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     snp_mean: &Array<f32, Ix1>,
     snp_std: &Array<f32, Ix1>,
     y: &Array<f32, Ix1>,
     num_random_vecs: usize,
+    seed: Option<u64>,
 ) -> f64 {
     let num_cols = match &snp_range {
         Some(range) => range.size(),
         None => gxg_basis_bed.total_num_snps(),
     };
+    let people_rows = get_people_rows(people);
     let ssq_of_hi_hi = gxg_basis_bed
         .col_chunk_iter(DEFAULT_NUM_SNPS_PER_CHUNK, snp_range.clone())
         .into_par_iter()
         .fold(
             || 0f32,
-            |acc, mut snp_chunk| {
+            |acc, snp_chunk| {
+                let mut snp_chunk = select_people_rows(snp_chunk, &people_rows);
                 normalize_matrix_columns_inplace(&mut snp_chunk, 0);
                 let gg_sq_dot_y = ((&snp_chunk) * (&snp_chunk)).t().dot(y);
                 acc + sum_of_squares_f32(gg_sq_dot_y.iter())
@@ -390,13 +1595,16 @@ pub fn estimate_gxg_dot_y_norm_sq_from_basis_bed(
         )
         .sum::<f32>();
 
+    // the people left out have zero weight in the products over the bed
+    let y_scattered =
+        scatter_people_entries(y, &people_rows, gxg_basis_bed.num_people);
     let y_scaled_basis_dot_rand_vecs = normalized_g_dot_matrix(
         gxg_basis_bed,
         snp_range.clone(),
         snp_mean,
         snp_std,
-        &generate_plus_minus_one_bernoulli_matrix(num_cols, num_random_vecs),
-        Some(y),
+        &generate_sign_matrix(num_cols, num_random_vecs, None, seed),
+        Some(&y_scattered),
         None,
     );
     let mut hhz = normalized_g_transpose_dot_matrix(
@@ -415,16 +1623,19 @@ pub fn estimate_gxg_dot_y_norm_sq_from_basis_bed(
 pub fn get_gxg_dot_y_norm_sq_from_basis_bed(
     gxg_basis_bed: &PlinkBed,
     snp_range: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     snp_mean: &Array<f32, Ix1>,
     snp_std: &Array<f32, Ix1>,
     y: &Array<f32, Ix1>,
-) -> f64 {
+) -> Result<f64, Error> {
+    let people_rows = get_people_rows(people);
     let ssq_of_hi_hi = gxg_basis_bed
         .col_chunk_iter(DEFAULT_NUM_SNPS_PER_CHUNK, snp_range.clone())
         .into_par_iter()
         .fold(
             || 0f32,
-            |acc, mut snp_chunk| {
+            |acc, snp_chunk| {
+                let mut snp_chunk = select_people_rows(snp_chunk, &people_rows);
                 normalize_matrix_columns_inplace(&mut snp_chunk, 0);
                 let gg_sq_dot_y = ((&snp_chunk) * (&snp_chunk)).t().dot(y);
                 acc + sum_of_squares_f32(gg_sq_dot_y.iter())
@@ -432,32 +1643,44 @@ pub fn get_gxg_dot_y_norm_sq_from_basis_bed(
         )
         .sum::<f32>();
 
-    let mut rhs_matrix = gxg_basis_bed
-        .get_genotype_matrix(snp_range.clone())
-        .unwrap();
+    let mut rhs_matrix = select_people_rows(
+        gxg_basis_bed.get_genotype_matrix(snp_range.clone())?,
+        &people_rows,
+    );
     normalize_matrix_columns_inplace(&mut rhs_matrix, 0);
+    if let Some(rows) = &people_rows {
+        rhs_matrix =
+            scatter_people_rows(&rhs_matrix, rows, gxg_basis_bed.num_people);
+    }
     let hh = normalized_g_transpose_dot_matrix(
         &gxg_basis_bed,
         snp_range,
         &snp_mean,
         &snp_std,
         &rhs_matrix,
-        Some(&y),
+        Some(&scatter_people_entries(
+            y,
+            &people_rows,
+            gxg_basis_bed.num_people,
+        )),
         None,
     );
-    ((sum_of_squares_f32(hh.iter()) - ssq_of_hi_hi) / 2.) as f64
+    Ok(((sum_of_squares_f32(hh.iter()) - ssq_of_hi_hi) / 2.) as f64)
 }
 
+/// The probes are reproducible if `seed` is `Some`.
 pub fn estimate_inter_gxg_dot_y_norm_sq_from_basis_bed(
     gxg_basis_bed: &PlinkBed,
     snp_range_1: Option<OrderedIntegerSet<usize>>,
     snp_range_2: Option<OrderedIntegerSet<usize>>,
+    people: Option<&OrderedIntegerSet<usize>>,
     snp_mean_1: &Array<f32, Ix1>,
     snp_std_1: &Array<f32, Ix1>,
     snp_mean_2: &Array<f32, Ix1>,
     snp_std_2: &Array<f32, Ix1>,
     y: &Array<f32, Ix1>,
     num_random_vecs: usize,
+    seed: Option<u64>,
 ) -> f64 {
     let num_snps_1 = match &snp_range_1 {
         Some(range) => range.size(),
@@ -468,8 +1691,12 @@ pub fn estimate_inter_gxg_dot_y_norm_sq_from_basis_bed(
         snp_range_1,
         snp_mean_1,
         snp_std_1,
-        &generate_plus_minus_one_bernoulli_matrix(num_snps_1, num_random_vecs),
-        Some(y),
+        &generate_sign_matrix(num_snps_1, num_random_vecs, None, seed),
+        Some(&scatter_people_entries(
+            y,
+            &get_people_rows(people),
+            gxg_basis_bed.num_people,
+        )),
         None,
     );
     let hhz = normalized_g_transpose_dot_matrix(
@@ -500,3 +1727,921 @@ pub fn estimate_tr_kk(geno_arr: &Array<f32, Ix2>, num_random_vecs: usize) -> f64
     (sums.into_iter().sum::<f32>() / (num_snps * num_snps * num_random_vecs) as f32) as f64
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use math::{
+        set::ordered_integer_set::OrderedIntegerSet,
+        stats::{n_choose_2, sum_of_squares_f32},
+    };
+    use ndarray::{s, Array, Axis, Ix2};
+    use ndarray_rand::RandomExt;
+    use rand::distributions::Uniform;
+
+    use super::{
+        check_num_random_vecs, compute_yky, estimate_gxg_dot_y_norm_sq,
+        estimate_gxg_dot_y_norm_sq_from_basis_bed, estimate_gxg_gram_trace,
+        estimate_gxg_gram_trace_hutchpp, estimate_gxg_kk_trace,
+        estimate_gxg_kk_trace_hutchpp,
+        estimate_inter_gxg_dot_y_norm_sq_from_basis_bed,
+        estimate_partitioned_tr_ki_kj_matrix, estimate_tr_gxg_ki_gxg_kj,
+        estimate_tr_k, estimate_tr_k_gxg_k, estimate_tr_ki_kj,
+        estimate_tr_ki_kj_matrix, estimate_tr_kk,
+        estimate_tr_kk_control_variate, estimate_tr_kk_hutchpp, estimate_trace,
+        get_gxg_dot_y_norm_sq_from_basis_bed, get_gxg_gram_trace,
+        get_gxg_kk_trace, get_refinement_seed, gxg_gram_trace,
+        gxg_kernel_dot_matrix, gxg_kk_trace, hutchinson_trace_of_product,
+        hutchpp_trace, refine_trace_estimate, DEFAULT_EXACT_GXG_MAX_NUM_PAIRS,
+    };
+    use crate::{
+        accumulation::AccumulationPrecision,
+        compute::ComputeConfig,
+        error::Error,
+        linear_operator::MatvecOperator,
+        progress::{ProgressSink, SilentProgress},
+        snp_weights::SnpWeights,
+        util::{
+            brute_force::{
+                assert_unbiased, assert_variance_scales_inversely,
+                column_mean_and_std, grm, gxg_kernel, quadratic_form,
+                random_genotypes, standardize, trace, trace_of_product,
+                TempBed,
+            },
+            matrix_util::{normalize_matrix_columns_inplace, ProbeType},
+        },
+    };
+
+    #[test]
+    fn test_check_num_random_vecs() {
+        #[derive(Default)]
+        struct RecordingProgress {
+            messages: Mutex<Vec<String>>,
+        }
+
+        impl ProgressSink for RecordingProgress {
+            fn message(&self, message: &str) {
+                self.messages.lock().unwrap().push(message.to_string());
+            }
+        }
+
+        let progress = RecordingProgress::default();
+        assert!(check_num_random_vecs(0, 100, 1000, 1, &progress).is_err());
+        assert_eq!(check_num_random_vecs(50, 100, 1000, 1, &progress), Ok(50));
+        assert!(progress.messages.lock().unwrap().is_empty());
+
+        // more probes than people, and too few probes for 4 components
+        assert_eq!(
+            check_num_random_vecs(200, 100, 1000, 1, &progress),
+            Ok(100)
+        );
+        assert_eq!(check_num_random_vecs(10, 100, 1000, 4, &progress), Ok(10));
+        let messages = progress.messages.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("num_random_vecs 200 >= num_people 100"));
+        assert!(messages[1].contains("at least 32 random vectors"));
+    }
+
+    #[test]
+    fn test_hutchpp() {
+        let num_people = 40;
+        let geno = Array::random((num_people, 4), Uniform::from(0..3))
+            .mapv(|g: u8| g as f32);
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k = x.dot(&x.t()) / 4.;
+        // the rank 4 kernel is captured by the sketch of 5 vectors of any
+        // probe type, leaving no residual
+        let tr_kk = estimate_tr_kk_hutchpp(
+            &geno,
+            None,
+            None,
+            None,
+            15,
+            ProbeType::Rademacher,
+            Some(3),
+            Some(1),
+        )
+        .unwrap();
+        let expected = sum_of_squares_f32(k.iter()) as f64;
+        assert!((tr_kk - expected).abs() < 1e-3 * expected);
+        let tr_k = hutchpp_trace(&k, 15, ProbeType::Srht, Some(2)).unwrap();
+        assert!((tr_k - k.diag().sum() as f64).abs() < 1e-3 * tr_k);
+        assert!(hutchpp_trace(&k, 2, ProbeType::Rademacher, None).is_err());
+        // tr(K K) of the rank 4 kernel from 2000 sign probes
+        let tr_kk_product = hutchinson_trace_of_product(
+            &k,
+            &k,
+            2000,
+            ProbeType::Rademacher,
+            Some(5),
+        )
+        .unwrap();
+        assert!((tr_kk_product - expected).abs() < 0.1 * expected);
+        assert!(hutchinson_trace_of_product(
+            &k,
+            &Array::<f32, Ix2>::eye(3),
+            10,
+            ProbeType::Rademacher,
+            None
+        )
+        .is_err());
+
+        // the explicit pair products of the 6 pairs of basis SNPs
+        let mut pairs = Array::<f32, Ix2>::zeros((num_people, 6));
+        let mut col = 0;
+        for i in 0..4 {
+            for j in i + 1..4 {
+                pairs.column_mut(col).assign(&(&x.column(i) * &x.column(j)));
+                col += 1;
+            }
+        }
+        let a = pairs.dot(&pairs.t());
+        let rhs = Array::random((num_people, 3), Uniform::new(-1f32, 1.));
+        let diff = gxg_kernel_dot_matrix(&x, &rhs, None, None) - a.dot(&rhs);
+        assert!(diff.iter().all(|d| d.abs() < 1e-3));
+
+        let gram_trace = estimate_gxg_gram_trace_hutchpp(
+            &x,
+            None,
+            None,
+            24,
+            ProbeType::Gaussian,
+            Some(3),
+        )
+        .unwrap();
+        let expected = a.diag().sum() as f64;
+        assert!((gram_trace - expected).abs() < 1e-3 * expected);
+        let mm = n_choose_2(4) as f64;
+        let kk_trace = estimate_gxg_kk_trace_hutchpp(
+            &x,
+            None,
+            None,
+            24,
+            ProbeType::Srht,
+            Some(4),
+        )
+        .unwrap();
+        let expected = sum_of_squares_f32(a.iter()) as f64 / (mm * mm);
+        assert!((kk_trace - expected).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_people_and_snp_ranges() {
+        let geno =
+            Array::random((30, 5), Uniform::from(0..3)).mapv(|g: u8| g as f32);
+        let people = OrderedIntegerSet::from_slice(&[[0, 9], [15, 29]]);
+        let snp_range = OrderedIntegerSet::from_slice(&[[0, 1], [3, 3]]);
+        let people_rows: Vec<usize> = people.iter().collect();
+        let snp_cols: Vec<usize> = snp_range.iter().collect();
+        let selected = geno
+            .select(Axis(0), &people_rows)
+            .select(Axis(1), &snp_cols);
+
+        // the kernel of 3 SNPs is within the sketch, so the estimate over
+        // the kept people is exact
+        let tr_kk = estimate_tr_kk_hutchpp(
+            &geno,
+            Some(snp_range.clone()),
+            Some(&people),
+            None,
+            15,
+            ProbeType::Rademacher,
+            Some(2),
+            Some(1),
+        )
+        .unwrap();
+        let mut x = selected.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k = x.dot(&x.t()) / 3.;
+        let expected = sum_of_squares_f32(k.iter()) as f64;
+        assert!((tr_kk - expected).abs() < 1e-3 * expected);
+
+        let rhs =
+            Array::random((people_rows.len(), 2), Uniform::new(-1f32, 1.));
+        let diff =
+            gxg_kernel_dot_matrix(&geno, &rhs, Some(snp_range), Some(&people))
+                - gxg_kernel_dot_matrix(&selected, &rhs, None, None);
+        assert!(diff.iter().all(|d| d.abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_tr_kk_control_variate() {
+        let geno = Array::random((60, 200), Uniform::from(0..3))
+            .mapv(|g: u8| g as f32);
+        let mut x = geno.clone();
+        normalize_matrix_columns_inplace(&mut x, 0);
+        let k = x.dot(&x.t()) / 200.;
+        let expected = sum_of_squares_f32(k.iter()) as f64;
+        let est = estimate_tr_kk_control_variate(
+            &geno,
+            None,
+            None,
+            None,
+            200,
+            ProbeType::Rademacher,
+            Some(7),
+            Some(1),
+            AccumulationPrecision::F32,
+        )
+        .unwrap();
+        assert_eq!(est.num_random_vecs, 200);
+        // with many more SNPs than people, K is close to its diagonal and
+        // z^T K K z moves with the control variate z^T D K z
+        assert!(est.variance_reduction.unwrap() > 0.5);
+        assert!((est.value - expected).abs() < 5. * est.mc_standard_error);
+        // on the same probes, the f32 sums of a small problem are within the
+        // f32 rounding of the compensated and f64 ones
+        for &precision in
+            [AccumulationPrecision::KahanF32, AccumulationPrecision::F64].iter()
+        {
+            let est_precise = estimate_tr_kk_control_variate(
+                &geno,
+                None,
+                None,
+                None,
+                200,
+                ProbeType::Rademacher,
+                Some(7),
+                Some(1),
+                precision,
+            )
+            .unwrap();
+            let relative_diff =
+                (est_precise.value - est.value).abs() / est.value;
+            assert!(relative_diff < 1e-4);
+        }
+        assert!(estimate_tr_kk_control_variate(
+            &geno,
+            None,
+            None,
+            None,
+            1,
+            ProbeType::Rademacher,
+            None,
+            None,
+            AccumulationPrecision::F32
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_exact_gxg_traces() {
+        let mut basis =
+            Array::random((40, 8), Uniform::from(0..3)).mapv(|g: u8| g as f32);
+        normalize_matrix_columns_inplace(&mut basis, 0);
+        let mut products = Vec::new();
+        for i in 0..8 {
+            for j in i + 1..8 {
+                products.push(&basis.column(i) * &basis.column(j));
+            }
+        }
+        let mm = n_choose_2(8) as f64;
+        let a = Array::from_shape_fn((40, 40), |(r, s)| {
+            products.iter().map(|p| p[r] * p[s]).sum::<f32>()
+        });
+        let expected_tr_a = (0..40).map(|i| a[[i, i]] as f64).sum::<f64>();
+        let expected_tr_kk = sum_of_squares_f32(a.iter()) as f64 / (mm * mm);
+
+        let tr_a = gxg_gram_trace(&basis, None, None).unwrap();
+        assert!((tr_a - expected_tr_a).abs() < 1e-4 * expected_tr_a);
+        let tr_kk = gxg_kk_trace(&basis, None, None).unwrap();
+        assert!((tr_kk - expected_tr_kk).abs() < 1e-4 * expected_tr_kk);
+        // P P^T is used instead of P^T P with fewer people than pairs
+        let mut selected = basis.slice(s![0..20, ..]).to_owned();
+        normalize_matrix_columns_inplace(&mut selected, 0);
+        let tr_kk_subset = gxg_kk_trace(&selected, None, None).unwrap();
+        let mut products = Vec::new();
+        for i in 0..8 {
+            for j in i + 1..8 {
+                products.push(&selected.column(i) * &selected.column(j));
+            }
+        }
+        let a = Array::from_shape_fn((20, 20), |(r, s)| {
+            products.iter().map(|p| p[r] * p[s]).sum::<f32>()
+        });
+        let expected = sum_of_squares_f32(a.iter()) as f64 / (mm * mm);
+        assert!((tr_kk_subset - expected).abs() < 1e-4 * expected);
+        assert!(gxg_kk_trace(
+            &basis,
+            Some(OrderedIntegerSet::from_slice(&[[0, 0]])),
+            None
+        )
+        .is_err());
+
+        // the small component is dispatched to the exact traces
+        assert!(n_choose_2(8) <= DEFAULT_EXACT_GXG_MAX_NUM_PAIRS);
+        assert_eq!(
+            get_gxg_gram_trace(
+                &basis,
+                None,
+                None,
+                10,
+                ProbeType::Rademacher,
+                None
+            )
+            .unwrap(),
+            tr_a
+        );
+        assert_eq!(
+            get_gxg_kk_trace(
+                &basis,
+                None,
+                None,
+                10,
+                ProbeType::Rademacher,
+                None
+            )
+            .unwrap(),
+            tr_kk
+        );
+    }
+
+    #[test]
+    fn test_g_traces_against_brute_force() {
+        let geno = random_genotypes(60, 30);
+        let mut geno_bed = TempBed::new(&geno);
+        let k = grm(&geno);
+        let range_i = OrderedIntegerSet::from_slice(&[[0, 14]]);
+        let range_j = OrderedIntegerSet::from_slice(&[[15, 29]]);
+        let cols_i: Vec<usize> = (0..15).collect();
+        let cols_j: Vec<usize> = (15..30).collect();
+        let geno_i = geno.select(Axis(1), &cols_i);
+        let geno_j = geno.select(Axis(1), &cols_j);
+
+        assert_unbiased("tr(K)", trace(&k), 40, 1e-3, |r| {
+            estimate_tr_k(
+                &mut geno_bed.bed,
+                None,
+                None,
+                None,
+                10,
+                Some(7),
+                Some(r as u64),
+                AccumulationPrecision::F32,
+                &SilentProgress,
+            )
+        });
+        assert_unbiased("tr(KK)", trace_of_product(&k, &k), 40, 1e-3, |r| {
+            estimate_tr_kk(
+                &mut geno_bed.bed,
+                None,
+                None,
+                None,
+                None,
+                10,
+                Some(7),
+                Some(r as u64),
+                AccumulationPrecision::F32,
+                &SilentProgress,
+            )
+        });
+        let (mean_i, std_i) = column_mean_and_std(&geno_i);
+        let (mean_j, std_j) = column_mean_and_std(&geno_j);
+        assert_unbiased(
+            "tr(K_i K_j)",
+            trace_of_product(&grm(&geno_i), &grm(&geno_j)),
+            40,
+            1e-3,
+            |_| {
+                estimate_tr_ki_kj(
+                    &mut geno_bed.bed,
+                    Some(range_i.clone()),
+                    Some(range_j.clone()),
+                    None,
+                    &mean_i,
+                    &std_i,
+                    &mean_j,
+                    &std_j,
+                    None,
+                    10,
+                    Some(7),
+                )
+            },
+        );
+
+        let k_f32 = k.mapv(|x| x as f32);
+        let k_i = grm(&geno_i).mapv(|x| x as f32);
+        assert_unbiased("Hutch++ tr(K)", trace(&k), 40, 1e-3, |r| {
+            hutchpp_trace(&k_f32, 9, ProbeType::Rademacher, Some(r as u64))
+                .unwrap()
+        });
+        assert_unbiased(
+            "tr(K K_i)",
+            trace_of_product(&k, &grm(&geno_i)),
+            40,
+            1e-3,
+            |r| {
+                hutchinson_trace_of_product(
+                    &k_f32,
+                    &k_i,
+                    10,
+                    ProbeType::Gaussian,
+                    Some(r as u64),
+                )
+                .unwrap()
+            },
+        );
+    }
+
+    #[test]
+    fn test_gxg_traces_against_brute_force() {
+        let geno = random_genotypes(60, 20);
+        let geno_bed = TempBed::new(&geno);
+        let basis = standardize(&random_genotypes(60, 8));
+        let other_basis = standardize(&random_genotypes(60, 6));
+        let mm = n_choose_2(8) as f64;
+        let other_mm = n_choose_2(6) as f64;
+        let a = gxg_kernel(&basis);
+        let k_gxg = &a / mm;
+        let other_k_gxg = gxg_kernel(&other_basis) / other_mm;
+
+        assert_unbiased("tr(A)", trace(&a), 40, 1e-3, |r| {
+            estimate_gxg_gram_trace(&basis, None, None, 10, Some(r as u64))
+                .unwrap()
+        });
+        assert_unbiased(
+            "tr(K_gxg K_gxg)",
+            trace_of_product(&k_gxg, &k_gxg),
+            20,
+            1e-3,
+            |r| {
+                estimate_gxg_kk_trace(
+                    &basis,
+                    None,
+                    None,
+                    10,
+                    Some(r as u64),
+                    &SilentProgress,
+                )
+                .unwrap()
+            },
+        );
+        assert_unbiased("Hutch++ tr(A)", trace(&a), 40, 1e-3, |r| {
+            estimate_gxg_gram_trace_hutchpp(
+                &basis,
+                None,
+                None,
+                9,
+                ProbeType::Rademacher,
+                Some(r as u64),
+            )
+            .unwrap()
+        });
+        assert_unbiased(
+            "Hutch++ tr(K_gxg K_gxg)",
+            trace_of_product(&k_gxg, &k_gxg),
+            40,
+            1e-3,
+            |r| {
+                estimate_gxg_kk_trace_hutchpp(
+                    &basis,
+                    None,
+                    None,
+                    9,
+                    ProbeType::Rademacher,
+                    Some(r as u64),
+                )
+                .unwrap()
+            },
+        );
+        let mut geno_bed = geno_bed;
+        assert_unbiased(
+            "tr(K K_gxg)",
+            trace_of_product(&grm(&geno), &k_gxg),
+            40,
+            1e-3,
+            |r| {
+                estimate_tr_k_gxg_k(
+                    &mut geno_bed.bed,
+                    &basis,
+                    None,
+                    None,
+                    None,
+                    10,
+                    Some(7),
+                    Some(r as u64),
+                )
+            },
+        );
+        assert_unbiased(
+            "tr(K_gxg_i K_gxg_j)",
+            trace_of_product(&k_gxg, &other_k_gxg),
+            20,
+            1e-3,
+            |r| {
+                estimate_tr_gxg_ki_gxg_kj(
+                    &basis,
+                    &other_basis,
+                    None,
+                    None,
+                    None,
+                    10,
+                    Some(r as u64),
+                )
+            },
+        );
+
+        // the nested probes are drawn from the seed as well
+        let kk_trace = |seed| {
+            estimate_gxg_kk_trace(&basis, None, None, 5, seed, &SilentProgress)
+                .unwrap()
+        };
+        assert_eq!(kk_trace(Some(3)), kk_trace(Some(3)));
+        assert_ne!(kk_trace(Some(3)), kk_trace(Some(4)));
+        let ki_kj_trace = |seed| {
+            estimate_tr_gxg_ki_gxg_kj(
+                &basis,
+                &other_basis,
+                None,
+                None,
+                None,
+                5,
+                seed,
+            )
+        };
+        assert_eq!(ki_kj_trace(Some(3)), ki_kj_trace(Some(3)));
+    }
+
+    #[test]
+    fn test_gxg_quadratic_forms_against_brute_force() {
+        let basis_geno = random_genotypes(60, 12);
+        let basis_bed = TempBed::new(&basis_geno);
+        let y = Array::random(60, Uniform::new(-1f32, 1.));
+        let range_1 = OrderedIntegerSet::from_slice(&[[0, 5]]);
+        let range_2 = OrderedIntegerSet::from_slice(&[[6, 11]]);
+        let cols_1: Vec<usize> = (0..6).collect();
+        let cols_2: Vec<usize> = (6..12).collect();
+        let x_1 = standardize(&basis_geno.select(Axis(1), &cols_1));
+        let x_2 = standardize(&basis_geno.select(Axis(1), &cols_2));
+        let (mean_1, std_1) =
+            column_mean_and_std(&basis_geno.select(Axis(1), &cols_1));
+        let (mean_2, std_2) =
+            column_mean_and_std(&basis_geno.select(Axis(1), &cols_2));
+        let a_1 = gxg_kernel(&x_1);
+        let expected = quadratic_form(&a_1, &y);
+
+        assert_unbiased("y^T A y", expected, 40, 1e-3, |r| {
+            estimate_gxg_dot_y_norm_sq(&x_1, &y, None, None, 10, Some(r as u64))
+        });
+        assert_unbiased("y^T A y from the bed", expected, 40, 1e-3, |r| {
+            estimate_gxg_dot_y_norm_sq_from_basis_bed(
+                &basis_bed.bed,
+                Some(range_1.clone()),
+                None,
+                &mean_1,
+                &std_1,
+                &y,
+                10,
+                Some(r as u64),
+            )
+        });
+        let exact = get_gxg_dot_y_norm_sq_from_basis_bed(
+            &basis_bed.bed,
+            Some(range_1.clone()),
+            None,
+            &mean_1,
+            &std_1,
+            &y,
+        )
+        .unwrap();
+        assert!((exact - expected).abs() < 1e-3 * expected.abs());
+
+        let s_1 = x_1.mapv(|x| x as f64).dot(&x_1.t().mapv(|x| x as f64));
+        let s_2 = x_2.mapv(|x| x as f64).dot(&x_2.t().mapv(|x| x as f64));
+        assert_unbiased(
+            "y^T (S_1 * S_2) y",
+            quadratic_form(&(&s_1 * &s_2), &y),
+            40,
+            1e-3,
+            |r| {
+                estimate_inter_gxg_dot_y_norm_sq_from_basis_bed(
+                    &basis_bed.bed,
+                    Some(range_1.clone()),
+                    Some(range_2.clone()),
+                    None,
+                    &mean_1,
+                    &std_1,
+                    &mean_2,
+                    &std_2,
+                    &y,
+                    10,
+                    Some(r as u64),
+                )
+            },
+        );
+    }
+
+    #[test]
+    fn test_variance_scales_with_num_random_vecs() {
+        let geno = random_genotypes(60, 30);
+        let mut geno_bed = TempBed::new(&geno);
+        let k = grm(&geno).mapv(|x| x as f32);
+        let basis = standardize(&random_genotypes(60, 8));
+
+        assert_variance_scales_inversely("tr(K)", 5, 60, |nrv, r| {
+            estimate_tr_k(
+                &mut geno_bed.bed,
+                None,
+                None,
+                None,
+                nrv,
+                Some(7),
+                Some(r as u64),
+                AccumulationPrecision::F32,
+                &SilentProgress,
+            )
+        });
+        assert_variance_scales_inversely("tr(KK)", 5, 60, |nrv, r| {
+            estimate_tr_kk(
+                &mut geno_bed.bed,
+                None,
+                None,
+                None,
+                None,
+                nrv,
+                Some(7),
+                Some(r as u64),
+                AccumulationPrecision::F32,
+                &SilentProgress,
+            )
+        });
+        assert_variance_scales_inversely("tr(K K)", 5, 60, |nrv, r| {
+            hutchinson_trace_of_product(
+                &k,
+                &k,
+                nrv,
+                ProbeType::Rademacher,
+                Some(r as u64),
+            )
+            .unwrap()
+        });
+        assert_variance_scales_inversely("tr(A)", 5, 60, |nrv, r| {
+            estimate_gxg_gram_trace(&basis, None, None, nrv, Some(r as u64))
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_tr_ki_kj_matrix_in_spilled_workspaces() {
+        let geno = random_genotypes(50, 12);
+        let ranges = vec![
+            OrderedIntegerSet::from_slice(&[[0, 5]]),
+            OrderedIntegerSet::from_slice(&[[6, 11]]),
+        ];
+        // the probes and the two products take 12000 bytes, more than half
+        // of the budget, so they are spilled and processed in blocks of 13
+        // probes
+        let in_memory = ComputeConfig::new(Some(1), Some(16_000), None);
+        let spilled = in_memory.with_spill_to_disk(true);
+        assert!(!in_memory.should_spill(50, 20, 3));
+        assert!(spilled.should_spill(50, 20, 3));
+        assert_eq!(spilled.get_num_columns_per_block(50, 20, 3), 13);
+
+        let tr =
+            estimate_tr_ki_kj_matrix(&geno, &ranges, 20, Some(3), &in_memory)
+                .unwrap();
+        let tr_spilled =
+            estimate_tr_ki_kj_matrix(&geno, &ranges, 20, Some(3), &spilled)
+                .unwrap();
+        assert_eq!(tr.dim(), (2, 2));
+        assert_eq!(tr[[0, 1]], tr[[1, 0]]);
+        for (a, b) in tr.iter().zip(tr_spilled.iter()) {
+            assert!((a - b).abs() < 1e-4 * a.abs());
+        }
+        assert!(estimate_tr_ki_kj_matrix(
+            &geno,
+            &[OrderedIntegerSet::new()],
+            20,
+            None,
+            &in_memory
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_partitioned_tr_ki_kj_matrix_in_one_pass() {
+        let geno = random_genotypes(50, 16);
+        // interleaved partitions, with SNP 15 left out of all of them, so
+        // that the chunks of 4 SNPs straddle the partitions
+        let partitions = vec![
+            OrderedIntegerSet::from_slice(&[[0, 2], [9, 11]]),
+            OrderedIntegerSet::from_slice(&[[3, 5], [12, 14]]),
+            OrderedIntegerSet::from_slice(&[[6, 8]]),
+        ];
+        let in_memory = ComputeConfig::new(Some(2), Some(16_000), Some(4));
+        let spilled = in_memory.with_spill_to_disk(true);
+        assert!(spilled.should_spill(50, 20, 4));
+
+        let expected = estimate_tr_ki_kj_matrix(
+            &geno,
+            &partitions,
+            20,
+            Some(5),
+            &in_memory,
+        )
+        .unwrap();
+        for compute in [in_memory, spilled].iter() {
+            let tr = estimate_partitioned_tr_ki_kj_matrix(
+                &geno,
+                &partitions,
+                20,
+                Some(5),
+                compute,
+            )
+            .unwrap();
+            assert_eq!(tr.dim(), (3, 3));
+            assert_eq!(tr[[0, 2]], tr[[2, 0]]);
+            for (a, b) in tr.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-4 * b.abs());
+            }
+        }
+
+        let overlapping = vec![
+            OrderedIntegerSet::from_slice(&[[0, 5]]),
+            OrderedIntegerSet::from_slice(&[[5, 9]]),
+        ];
+        let out_of_range = vec![OrderedIntegerSet::from_slice(&[[10, 16]])];
+        for partitions in
+            [overlapping, out_of_range, vec![OrderedIntegerSet::new()]].iter()
+        {
+            assert!(estimate_partitioned_tr_ki_kj_matrix(
+                &geno, partitions, 20, None, &in_memory
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_refine_trace_estimate() {
+        let geno = random_genotypes(40, 20);
+        let mut geno_bed = TempBed::new(&geno);
+        let mut tr_kk = |num_random_vecs: usize, seed: Option<u64>| {
+            Ok(estimate_tr_kk(
+                &mut geno_bed.bed,
+                None,
+                None,
+                None,
+                None,
+                num_random_vecs,
+                None,
+                seed,
+                AccumulationPrecision::F64,
+                &SilentProgress,
+            ))
+        };
+        let previous = tr_kk(10, Some(5)).unwrap();
+        let more = tr_kk(30, get_refinement_seed(Some(5), 10)).unwrap();
+        assert_ne!(get_refinement_seed(Some(5), 10), Some(5));
+        let refined =
+            refine_trace_estimate(previous, 10, 30, Some(5), |n, seed| {
+                tr_kk(n, seed)
+            })
+            .unwrap();
+        assert!((refined - (previous + 3. * more) / 4.).abs() < 1e-9 * refined);
+        assert_eq!(
+            refine_trace_estimate(previous, 10, 0, Some(5), |_, _| Err(
+                Error::Generic("no probes".to_string())
+            ))
+            .unwrap(),
+            previous
+        );
+
+        // refining runs of the unseeded GxG estimator stays unbiased
+        let basis = standardize(&random_genotypes(30, 6));
+        let expected = trace(&gxg_kernel(&basis));
+        assert_unbiased("refined tr(A)", expected, 40, 1e-3, |_| {
+            let previous =
+                estimate_gxg_gram_trace(&basis, None, None, 5, None).unwrap();
+            refine_trace_estimate(previous, 5, 15, None, |n, seed| {
+                estimate_gxg_gram_trace(&basis, None, None, n, seed)
+            })
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_estimate_trace_of_custom_kernel() {
+        // a kernel of the pairwise products of the SNPs within each of two
+        // annotations, applied without forming it
+        let x = standardize(&random_genotypes(30, 8));
+        let annotations = [
+            x.slice(s![.., ..4]).to_owned(),
+            x.slice(s![.., 4..]).to_owned(),
+        ];
+        let matvec = |z: &Array<f32, Ix2>| {
+            annotations
+                .iter()
+                .map(|a| gxg_kernel_dot_matrix(a, z, None, None))
+                .fold(Array::<f32, Ix2>::zeros(z.dim()), |acc, az| acc + az)
+        };
+        let expected: f64 =
+            annotations.iter().map(|a| trace(&gxg_kernel(a))).sum();
+        assert_unbiased("tr(custom)", expected, 40, 1e-3, |r| {
+            estimate_trace(
+                &matvec,
+                30,
+                10,
+                ProbeType::Rademacher,
+                Some(r as u64),
+            )
+            .unwrap()
+        });
+        assert_unbiased("tr(custom) gaussian", expected, 40, 1e-3, |r| {
+            estimate_trace(&matvec, 30, 10, ProbeType::Gaussian, Some(r as u64))
+                .unwrap()
+        });
+        assert_eq!(
+            estimate_trace(&matvec, 30, 10, ProbeType::Rademacher, Some(1)),
+            estimate_trace(&matvec, 30, 10, ProbeType::Rademacher, Some(1))
+        );
+        // the sketch of 15 vectors captures the kernel of rank 12 exactly
+        let tr_hutchpp = hutchpp_trace(
+            &MatvecOperator::new(&matvec, 30),
+            45,
+            ProbeType::Rademacher,
+            Some(2),
+        )
+        .unwrap();
+        assert!((tr_hutchpp - expected).abs() < 1e-3 * expected);
+
+        assert!(estimate_trace(&matvec, 30, 0, ProbeType::Rademacher, None)
+            .is_err());
+        assert!(estimate_trace(
+            |z: &Array<f32, Ix2>| z.slice(s![..10, ..]).to_owned(),
+            30,
+            5,
+            ProbeType::Rademacher,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_compute_yky() {
+        let geno = random_genotypes(40, 12);
+        let geno_bed = TempBed::new(&geno);
+        let y = Array::random(40, Uniform::new(-1f32, 1.));
+        let range = OrderedIntegerSet::from_slice(&[[2, 10]]);
+        let cols: Vec<usize> = (2..11).collect();
+        let expected = quadratic_form(&grm(&geno.select(Axis(1), &cols)), &y);
+        for chunk_size in [1, 4, 20].iter() {
+            let arr_yky = compute_yky(
+                &geno,
+                &y,
+                Some(range.clone()),
+                None,
+                Some(*chunk_size),
+            )
+            .unwrap();
+            let bed_yky = compute_yky(
+                &geno_bed.bed,
+                &y,
+                Some(range.clone()),
+                None,
+                Some(*chunk_size),
+            )
+            .unwrap();
+            for yky in [arr_yky, bed_yky].iter() {
+                assert!((yky.yky - expected).abs() < 1e-4 * expected);
+                assert_eq!(yky.num_snps, 9.);
+                assert_eq!(
+                    yky.chunk_contributions.len(),
+                    (9 + chunk_size - 1) / chunk_size
+                );
+                assert!(
+                    (yky.chunk_contributions.iter().sum::<f64>() - yky.yky)
+                        .abs()
+                        < 1e-12 * yky.yky
+                );
+            }
+        }
+        // a chunk per SNP gives the contribution of every SNP in order
+        let per_snp = compute_yky(&geno, &y, None, None, Some(1)).unwrap();
+        let x = standardize(&geno);
+        for (j, c) in per_snp.chunk_contributions.iter().enumerate() {
+            let xty = y.dot(&x.column(j)) as f64;
+            assert!((c - xty * xty / 12.).abs() < 1e-4 * c.max(1.));
+        }
+
+        // the weights scale the SNPs by their square roots and normalize by
+        // their sum
+        let weights = SnpWeights::new(vec![2.; 12]).unwrap();
+        let weighted =
+            compute_yky(&geno, &y, None, Some(&weights), Some(5)).unwrap();
+        assert_eq!(weighted.num_snps, 24.);
+        let unweighted = compute_yky(&geno, &y, None, None, Some(5)).unwrap();
+        assert!((weighted.yky - unweighted.yky).abs() < 1e-4 * unweighted.yky);
+
+        assert!(compute_yky(&geno, &Array::zeros(3), None, None, None).is_err());
+        assert!(compute_yky(
+            &geno,
+            &y,
+            Some(OrderedIntegerSet::new()),
+            None,
+            None
+        )
+        .is_err());
+    }
+}
@@ -9,13 +9,26 @@ use biofile::{
     plink_bim::PlinkBim,
     util::get_buf,
 };
-use ndarray::{Array, Ix1, Ix2, ShapeBuilder};
+use math::set::{ordered_integer_set::OrderedIntegerSet, traits::Finite};
+use ndarray::{Array, Axis, Ix1, Ix2, ShapeBuilder};
 
-use crate::error::Error;
+use crate::{
+    error::Error,
+    integer_set::{collect::SortedCollecting, iter::Iter, ops::IntersectAll},
+    partitioned_jackknife_estimates::PartitionedJackknifeEstimates,
+};
 use num::{FromPrimitive, Integer, ToPrimitive};
+use trace_metadata::{TraceMetadata, TRACE_METADATA_LINE_PREFIX};
 
+#[cfg(test)]
+pub mod brute_force;
 pub mod matrix_util;
+pub mod recipe;
+#[cfg(feature = "cli")]
+pub mod summary_table;
+#[cfg(feature = "cli")]
 pub mod timer;
+pub mod trace_metadata;
 
 pub fn get_line_count(filepath: &str) -> Result<usize, String> {
     let buf = match OpenOptions::new().read(true).open(filepath) {
@@ -82,20 +95,64 @@ pub fn get_bed_bim_from_prefix_and_partition<
     Ok((bed, bim))
 }
 
+/// The additive and the dominance SNPs of the bed returned by
+/// `get_bed_bim_from_prefix_and_partition`, in which the dominance SNPs
+/// follow all the additive SNPs. The dominance set is empty if there are no
+/// dominance files.
+pub fn get_additive_and_dominance_snps(
+    plink_filename_prefixes: &Vec<String>,
+    total_num_snps: usize,
+) -> Result<(OrderedIntegerSet<usize>, OrderedIntegerSet<usize>), String> {
+    let num_additive_snps = plink_filename_prefixes
+        .iter()
+        .map(|prefix| get_line_count(&get_bed_bim_fam_path(prefix).1))
+        .sum::<Result<usize, String>>()?;
+    if num_additive_snps == 0 || num_additive_snps > total_num_snps {
+        return Err(format!(
+            "the additive bim files list {} SNPs for a bed of {} SNPs",
+            num_additive_snps, total_num_snps
+        ));
+    }
+    let dominance = if total_num_snps > num_additive_snps {
+        OrderedIntegerSet::from_slice(&[[
+            num_additive_snps,
+            total_num_snps - 1,
+        ]])
+    } else {
+        OrderedIntegerSet::new()
+    };
+    Ok((
+        OrderedIntegerSet::from_slice(&[[0, num_additive_snps - 1]]),
+        dominance,
+    ))
+}
+
 pub fn get_fid_iid_list(
     fam_file_path: &str,
 ) -> Result<Vec<(String, String)>, biofile::error::Error> {
-    Ok(get_buf(fam_file_path)?
+    get_buf(fam_file_path)?
         .lines()
-        .map(|l| {
-            let toks: Vec<String> = l
-                .unwrap()
-                .split_whitespace()
-                .map(|t| t.to_string())
-                .collect();
-            (toks[0].to_owned(), toks[1].to_owned())
+        .enumerate()
+        .map(|(i, l)| {
+            let line = l.map_err(|io_error| biofile::error::Error::IO {
+                why: format!(
+                    "failed to read line {} of {}",
+                    i + 1,
+                    fam_file_path
+                ),
+                io_error,
+            })?;
+            let toks: Vec<&str> = line.split_whitespace().collect();
+            if toks.len() < 2 {
+                return Err(biofile::error::Error::BadFormat(format!(
+                    "line {} of {} has no FID and IID",
+                    i + 1,
+                    fam_file_path
+                )));
+            }
+            Ok((toks[0].to_string(), toks[1].to_string()))
         })
-        .collect())
+        .collect()
 }
 
 pub fn get_file_line_tokens(
@@ -107,7 +164,10 @@ pub fn get_file_line_tokens(
             .lines()
             .map(|l| {
                 let toks: Vec<String> = l
-                    .unwrap()
+                    .map_err(|io_error| Error::IO {
+                        why: format!("failed to read {}", filepath),
+                        io_error,
+                    })?
                     .split_whitespace()
                     .map(|t| t.to_string())
                     .collect();
@@ -125,39 +185,90 @@ pub fn get_file_line_tokens(
     )
 }
 
-pub fn load_trace_estimates(
+pub fn load_trace_estimates(load_path: &str) -> Result<Array<f64, Ix2>, Error> {
+    Ok(load_trace_estimates_with_metadata(load_path)?.0)
+}
+
+/// Returns the trace estimates together with the metadata saved alongside
+/// them, where the metadata is `None` if the file was written without any.
+pub fn load_trace_estimates_with_metadata(
     load_path: &str,
-) -> Result<Array<f64, Ix2>, String> {
-    let num_rows = get_line_count(load_path)?;
+) -> Result<(Array<f64, Ix2>, Option<TraceMetadata>), Error> {
     let buf = match OpenOptions::new().read(true).open(load_path) {
-        Err(why) => {
-            return Err(format!(
-                "failed to read the trace estimates from file {}: {}",
-                load_path, why
-            ))
+        Err(io_error) => {
+            return Err(Error::IO {
+                why: format!(
+                    "failed to read the trace estimates from file {}",
+                    load_path
+                ),
+                io_error,
+            })
         }
         Ok(f) => BufReader::new(f),
     };
-    let trace_vec: Vec<f64> = buf
+    let (metadata_lines, trace_lines): (Vec<String>, Vec<String>) = buf
         .lines()
-        .flat_map(|l| {
-            l.unwrap()
-                .split_whitespace()
-                .map(|val| val.parse::<f64>().unwrap())
-                .collect::<Vec<f64>>()
-        })
-        .collect();
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|io_error| Error::IO {
+            why: format!("failed to read {}", load_path),
+            io_error,
+        })?
+        .into_iter()
+        .partition(|l| l.starts_with(TRACE_METADATA_LINE_PREFIX));
+    let num_rows = trace_lines.len();
+    if num_rows == 0 {
+        return Err(Error::Generic(format!(
+            "{} contains no trace estimates",
+            load_path
+        )));
+    }
+    let mut trace_vec: Vec<f64> = Vec::new();
+    for l in trace_lines.iter() {
+        for val in l.split_whitespace() {
+            trace_vec.push(val.parse::<f64>().map_err(|why| {
+                Error::Generic(format!(
+                    "failed to parse the trace estimate {} in {}: {}",
+                    val, load_path, why
+                ))
+            })?);
+        }
+    }
     let num_cols = trace_vec.len() / num_rows;
-    Ok(Array::from_shape_vec(
-        (num_rows, num_cols).strides((num_cols, 1)),
-        trace_vec,
-    )
-    .unwrap())
+    let metadata = TraceMetadata::from_header_lines(&metadata_lines)?;
+    Ok((
+        Array::from_shape_vec(
+            (num_rows, num_cols).strides((num_cols, 1)),
+            trace_vec,
+        )?,
+        metadata,
+    ))
 }
 
 pub fn write_trace_estimates(
     trace_estimates: &Array<f64, Ix2>,
     out_path: &str,
+) -> Result<(), String> {
+    write_trace_estimates_with_header(trace_estimates, &[], out_path)
+}
+
+/// Writes the `metadata` as header lines preceding the trace estimates so
+/// that a later run can validate the estimates before reusing them.
+pub fn write_trace_estimates_with_metadata(
+    trace_estimates: &Array<f64, Ix2>,
+    metadata: &TraceMetadata,
+    out_path: &str,
+) -> Result<(), String> {
+    write_trace_estimates_with_header(
+        trace_estimates,
+        &metadata.to_header_lines(),
+        out_path,
+    )
+}
+
+fn write_trace_estimates_with_header(
+    trace_estimates: &Array<f64, Ix2>,
+    header_lines: &[String],
+    out_path: &str,
 ) -> Result<(), String> {
     let mut buf = match OpenOptions::new()
         .truncate(true)
@@ -173,6 +284,14 @@ pub fn write_trace_estimates(
         }
         Ok(f) => BufWriter::new(f),
     };
+    for line in header_lines.iter() {
+        if let Err(why) = buf.write_fmt(format_args!("{}\n", line)) {
+            return Err(format!(
+                "failed to write the trace metadata to file {}: {}",
+                out_path, why
+            ));
+        }
+    }
     for row in trace_estimates.genrows() {
         for val in row.iter() {
             if let Err(why) = buf.write_fmt(format_args!("{} ", val)) {
@@ -192,6 +311,73 @@ pub fn write_trace_estimates(
     Ok(())
 }
 
+/// Writes a JSON object mapping each phenotype path to its estimates,
+/// including the jackknife covariance matrix of the partition estimates.
+pub fn write_estimates_json(
+    pheno_path_to_est: &HashMap<String, PartitionedJackknifeEstimates>,
+    out_path: &str,
+) -> Result<(), String> {
+    let buf = match OpenOptions::new()
+        .truncate(true)
+        .create(true)
+        .write(true)
+        .open(out_path)
+    {
+        Err(why) => {
+            return Err(format!(
+                "failed to write the estimates to file {}: {}",
+                out_path, why
+            ))
+        }
+        Ok(f) => BufWriter::new(f),
+    };
+    serde_json::to_writer_pretty(buf, pheno_path_to_est).map_err(|why| {
+        format!("failed to serialize the estimates to {}: {}", out_path, why)
+    })
+}
+
+/// Writes the jackknife replicate estimates in long format, one
+/// `phenotype<TAB>replicate<TAB>component<TAB>estimate` row per line, so that
+/// the jackknife distributions can be plotted directly.
+pub fn write_jackknife_replicates_tsv(
+    pheno_path_to_est: &HashMap<String, PartitionedJackknifeEstimates>,
+    out_path: &str,
+) -> Result<(), String> {
+    let mut buf = match OpenOptions::new()
+        .truncate(true)
+        .create(true)
+        .write(true)
+        .open(out_path)
+    {
+        Err(why) => {
+            return Err(format!(
+                "failed to write the jackknife replicates to file {}: {}",
+                out_path, why
+            ))
+        }
+        Ok(f) => BufWriter::new(f),
+    };
+    let mut pheno_paths: Vec<&String> = pheno_path_to_est.keys().collect();
+    pheno_paths.sort();
+    let write_err = |why: std::io::Error| {
+        format!("failed to write to file {}: {}", out_path, why)
+    };
+    buf.write_fmt(format_args!("phenotype\treplicate\tcomponent\testimate\n"))
+        .map_err(write_err)?;
+    for path in pheno_paths {
+        for (replicate, component, estimate) in
+            pheno_path_to_est[path].jackknife_replicate_rows()
+        {
+            buf.write_fmt(format_args!(
+                "{}\t{}\t{}\t{}\n",
+                path, replicate, component, estimate
+            ))
+            .map_err(write_err)?;
+        }
+    }
+    Ok(())
+}
+
 fn validate_header(
     header: &str,
     expected_first_n_tokens: Vec<String>,
@@ -220,63 +406,519 @@ fn read_and_validate_plink_header(
     Ok(header)
 }
 
+fn open_plink_file(path: &str) -> Result<BufReader<File>, Error> {
+    match OpenOptions::new().read(true).open(path) {
+        Err(io_error) => Err(Error::IO {
+            why: format!("failed to open {}", path),
+            io_error,
+        }),
+        Ok(f) => Ok(BufReader::new(f)),
+    }
+}
+
+/// The fields of the line with the 0-based index `line_index` after the
+/// header of the file at `path`, which starts with the FID and the IID
+fn read_plink_line(
+    line: std::io::Result<String>,
+    line_index: usize,
+    path: &str,
+) -> Result<Vec<String>, Error> {
+    let line = line.map_err(|io_error| Error::IO {
+        why: format!("failed to read line {} of {}", line_index + 2, path),
+        io_error,
+    })?;
+    let toks: Vec<String> =
+        line.split_whitespace().map(|t| t.to_string()).collect();
+    if toks.len() < 2 {
+        return Err(Error::Generic(format!(
+            "line {} of {} has no FID and IID",
+            line_index + 2,
+            path
+        )));
+    }
+    Ok(toks)
+}
+
+/// Parses the `column`-th field of a line read by `read_plink_line`
+fn parse_plink_value(
+    toks: &[String],
+    column: usize,
+    line_index: usize,
+    path: &str,
+) -> Result<f32, Error> {
+    let tok = toks.get(column).ok_or_else(|| {
+        Error::Generic(format!(
+            "line {} of {} has no column {}",
+            line_index + 2,
+            path,
+            column + 1
+        ))
+    })?;
+    tok.parse::<f32>().map_err(|why| {
+        Error::Generic(format!(
+            "failed to parse {} on line {} of {}: {}",
+            tok,
+            line_index + 2,
+            path,
+            why
+        ))
+    })
+}
+
 /// The first line of the file is FID IID pheno
 /// Each of the remaining lines have the three corresponding fields
 ///
 /// returns an array containing only the phenotype values in the order listed in
 /// the file
-pub fn get_pheno_arr(pheno_path: &str) -> Result<Array<f32, Ix1>, String> {
-    let mut buf = match OpenOptions::new().read(true).open(pheno_path) {
-        Err(why) => {
-            return Err(format!("failed to open {}: {}", pheno_path, why))
-        }
-        Ok(f) => BufReader::new(f),
-    };
+pub fn get_pheno_arr(pheno_path: &str) -> Result<Array<f32, Ix1>, Error> {
+    let mut buf = open_plink_file(pheno_path)?;
 
     let header = read_and_validate_plink_header(&mut buf)?;
     println!("\n{} header:\n{}", pheno_path, header);
 
     let pheno_vec = buf
         .lines()
-        .map(|l| {
-            l.unwrap()
-                .split_whitespace()
+        .enumerate()
+        .map(|(i, l)| {
+            let toks = read_plink_line(l, i, pheno_path)?;
+            parse_plink_value(&toks, 2, i, pheno_path)
+        })
+        .collect::<Result<Vec<f32>, Error>>()?;
+
+    Ok(Array::from_vec(pheno_vec))
+}
+
+/// Reads the third column of a file in the same format as `get_pheno_arr`,
+/// without parsing the values, e.g. for the labels of a discrete variable.
+pub fn get_plink_str_column(path: &str) -> Result<Vec<String>, String> {
+    let mut buf = match OpenOptions::new().read(true).open(path) {
+        Err(why) => return Err(format!("failed to open {}: {}", path, why)),
+        Ok(f) => BufReader::new(f),
+    };
+
+    let header = read_and_validate_plink_header(&mut buf)?;
+    println!("\n{} header:\n{}", path, header);
+
+    buf.lines()
+        .enumerate()
+        .map(|(i, l)| {
+            let line = l.map_err(|why| {
+                format!("failed to read line {} of {}: {}", i + 2, path, why)
+            })?;
+            line.split_whitespace()
                 .nth(2)
-                .unwrap()
-                .parse::<f32>()
-                .unwrap()
+                .map(|t| t.to_string())
+                .ok_or_else(|| {
+                    format!("line {} of {} has no third column", i + 2, path)
+                })
         })
+        .collect()
+}
+
+/// Returns a descriptive error if the number of rows read from `rows_source`
+/// differs from the number of people in `people_source`, instead of letting
+/// the mismatch surface later as a shape error inside a matrix product.
+pub fn check_num_people(
+    num_rows: usize,
+    rows_source: &str,
+    num_people: usize,
+    people_source: &str,
+) -> Result<(), Error> {
+    if num_rows == num_people {
+        Ok(())
+    } else {
+        Err(Error::DimensionMismatch(format!(
+            "{} has {} people, but {} has {} people",
+            rows_source, num_rows, people_source, num_people
+        )))
+    }
+}
+
+/// Checks that the phenotype named `pheno` has a value for each of the
+/// `num_geno_people` people in the genotypes
+pub fn check_pheno_num_people(
+    num_pheno_people: usize,
+    pheno: &str,
+    num_geno_people: usize,
+) -> Result<(), Error> {
+    if num_pheno_people == num_geno_people {
+        Ok(())
+    } else {
+        Err(Error::PhenotypeSampleMismatch {
+            pheno: pheno.to_string(),
+            num_pheno_people,
+            num_geno_people,
+        })
+    }
+}
+
+/// Joins the phenotypes in `pheno_path`, in the format of `get_pheno_arr`,
+/// to the people in the fam file by their (FID, IID).
+///
+/// returns the phenotype values reordered to follow the fam file, and the
+/// indices of the fam people kept. The people missing from either file are
+/// dropped and reported.
+pub fn align_pheno_to_fam(
+    fam_path: &str,
+    pheno_path: &str,
+) -> Result<(Array<f32, Ix1>, OrderedIntegerSet<usize>), Error> {
+    let fid_iid_list = get_fid_iid_list(fam_path)?;
+    let (_header, fid_vec, iid_vec, pheno_arr) =
+        get_plink_pheno_data(pheno_path)?;
+    let mut id_to_pheno: HashMap<(String, String), f32> = HashMap::new();
+    for ((fid, iid), &val) in fid_vec
+        .into_iter()
+        .zip(iid_vec.into_iter())
+        .zip(pheno_arr.iter())
+    {
+        if id_to_pheno
+            .insert((fid.clone(), iid.clone()), val)
+            .is_some()
+        {
+            return Err(Error::Generic(format!(
+                "{} lists the person with FID {} and IID {} more than once",
+                pheno_path, fid, iid
+            )));
+        }
+    }
+    let mut aligned = Vec::new();
+    let mut kept_indices = Vec::new();
+    for (i, id) in fid_iid_list.iter().enumerate() {
+        if let Some(&val) = id_to_pheno.get(id) {
+            aligned.push(val);
+            kept_indices.push(i);
+        }
+    }
+    let num_missing_in_pheno = fid_iid_list.len() - kept_indices.len();
+    let num_missing_in_fam = id_to_pheno.len() - kept_indices.len();
+    if num_missing_in_pheno > 0 || num_missing_in_fam > 0 {
+        println!(
+            "\n=> aligning {} to {}: dropping {} people without a phenotype \
+            and {} people missing from the fam file",
+            pheno_path, fam_path, num_missing_in_pheno, num_missing_in_fam
+        );
+    }
+    Ok((
+        Array::from_vec(aligned),
+        OrderedIntegerSet::collect_from_sorted_iter(kept_indices.into_iter()),
+    ))
+}
+
+/// Aligns the phenotypes to the fam file with `align_pheno_to_fam`, for the
+/// estimators that need a phenotype value for every person in the fam file.
+/// The people in `pheno_path` missing from the fam file are dropped.
+pub fn get_fam_aligned_pheno_arr(
+    fam_path: &str,
+    pheno_path: &str,
+) -> Result<Array<f32, Ix1>, Error> {
+    let num_fam_people = get_line_count(fam_path)?;
+    let (pheno_arr, _kept_indices) = align_pheno_to_fam(fam_path, pheno_path)?;
+    check_pheno_num_people(pheno_arr.len(), pheno_path, num_fam_people)?;
+    Ok(pheno_arr)
+}
+
+/// The phenotype matrix of `get_pheno_matrix`, with every phenotype aligned
+/// to the fam file with `get_fam_aligned_pheno_arr`
+pub fn get_fam_aligned_pheno_matrix(
+    fam_path: &str,
+    pheno_path_vec: &Vec<String>,
+) -> Result<Array<f32, Ix2>, Error> {
+    let num_fam_people = get_line_count(fam_path)?;
+    let mut pheno_matrix =
+        Array::<f32, Ix2>::zeros((num_fam_people, pheno_path_vec.len()));
+    for (i, path) in pheno_path_vec.iter().enumerate() {
+        pheno_matrix
+            .column_mut(i)
+            .assign(&get_fam_aligned_pheno_arr(fam_path, path)?);
+    }
+    Ok(pheno_matrix)
+}
+
+/// The first line of the file is FID IID PHENO_1 PHENO_2 ...
+/// Each of the remaining lines has a field for every column of the header.
+///
+/// returns (phenotype names, (FID, IID) list, phenotype matrix) where the
+/// matrix has one row per line in the order listed in the file and one
+/// column per phenotype in the order of the header
+pub fn get_plink_multi_pheno_data(
+    pheno_path: &str,
+) -> Result<(Vec<String>, Vec<(String, String)>, Array<f32, Ix2>), Error> {
+    let mut buf = open_plink_file(pheno_path)?;
+
+    let header = read_and_validate_plink_header(&mut buf)?;
+    let pheno_names: Vec<String> = header
+        .split_whitespace()
+        .skip(2)
+        .map(|t| t.to_string())
         .collect();
+    if pheno_names.is_empty() {
+        return Err(Error::Generic(format!(
+            "{} has no phenotype columns",
+            pheno_path
+        )));
+    }
+    let num_phenos = pheno_names.len();
 
-    Ok(Array::from_vec(pheno_vec))
+    let mut fid_iid_list = Vec::new();
+    let mut values = Vec::new();
+    for (i, l) in buf.lines().enumerate() {
+        let toks = read_plink_line(l, i, pheno_path)?;
+        if toks.len() != num_phenos + 2 {
+            return Err(Error::DimensionMismatch(format!(
+                "line {} of {} has {} fields, expected {} from the header",
+                i + 2,
+                pheno_path,
+                toks.len(),
+                num_phenos + 2
+            )));
+        }
+        for j in 2..toks.len() {
+            values.push(parse_plink_value(&toks, j, i, pheno_path)?);
+        }
+        fid_iid_list.push((toks[0].to_string(), toks[1].to_string()));
+    }
+    let pheno_matrix =
+        Array::from_shape_vec((fid_iid_list.len(), num_phenos), values)?;
+    Ok((pheno_names, fid_iid_list, pheno_matrix))
+}
+
+/// The columns of `pheno_matrix`, named by `pheno_names`, of the `selected`
+/// phenotypes in the order of `selected`
+pub fn select_pheno_columns(
+    pheno_names: &[String],
+    pheno_matrix: &Array<f32, Ix2>,
+    selected: &[String],
+) -> Result<Array<f32, Ix2>, String> {
+    let indices = selected
+        .iter()
+        .map(|name| {
+            pheno_names.iter().position(|n| n == name).ok_or_else(|| {
+                format!(
+                    "no phenotype named {}, expected one of {:?}",
+                    name, pheno_names
+                )
+            })
+        })
+        .collect::<Result<Vec<usize>, String>>()?;
+    Ok(pheno_matrix.select(Axis(1), &indices))
+}
+
+/// Joins the `selected` phenotypes of a file in the format of
+/// `get_plink_multi_pheno_data`, or all of them if `None`, to the people in
+/// the fam file by their (FID, IID).
+///
+/// returns the phenotype names, the phenotype matrix with one row for each of
+/// the fam people kept in the order of the fam file, and the indices of the
+/// fam people kept. The people missing from either file are dropped and
+/// reported.
+pub fn align_multi_pheno_to_fam(
+    fam_path: &str,
+    pheno_path: &str,
+    selected: Option<&[String]>,
+) -> Result<(Vec<String>, Array<f32, Ix2>, OrderedIntegerSet<usize>), Error> {
+    let fam_fid_iid_list = get_fid_iid_list(fam_path)?;
+    let (pheno_names, fid_iid_list, pheno_matrix) =
+        get_plink_multi_pheno_data(pheno_path)?;
+    let (pheno_names, pheno_matrix) = match selected {
+        Some(selected) => (
+            selected.to_vec(),
+            select_pheno_columns(&pheno_names, &pheno_matrix, selected)?,
+        ),
+        None => (pheno_names, pheno_matrix),
+    };
+    let mut id_to_row: HashMap<&(String, String), usize> = HashMap::new();
+    for (row, id) in fid_iid_list.iter().enumerate() {
+        if id_to_row.insert(id, row).is_some() {
+            return Err(Error::Generic(format!(
+                "{} lists the person with FID {} and IID {} more than once",
+                pheno_path, id.0, id.1
+            )));
+        }
+    }
+    let mut rows = Vec::new();
+    let mut kept_indices = Vec::new();
+    for (i, id) in fam_fid_iid_list.iter().enumerate() {
+        if let Some(&row) = id_to_row.get(id) {
+            rows.push(row);
+            kept_indices.push(i);
+        }
+    }
+    let num_missing_in_pheno = fam_fid_iid_list.len() - kept_indices.len();
+    let num_missing_in_fam = id_to_row.len() - kept_indices.len();
+    if num_missing_in_pheno > 0 || num_missing_in_fam > 0 {
+        println!(
+            "\n=> aligning {} to {}: dropping {} people without phenotypes \
+            and {} people missing from the fam file",
+            pheno_path, fam_path, num_missing_in_pheno, num_missing_in_fam
+        );
+    }
+    Ok((
+        pheno_names,
+        pheno_matrix.select(Axis(0), &rows),
+        OrderedIntegerSet::collect_from_sorted_iter(kept_indices.into_iter()),
+    ))
+}
+
+/// The phenotypes of `align_multi_pheno_to_fam`, for the estimators that
+/// need the phenotypes of every person in the fam file
+///
+/// returns the phenotype names and the phenotype matrix with one row per
+/// person in the fam file
+pub fn get_fam_aligned_multi_pheno_matrix(
+    fam_path: &str,
+    pheno_path: &str,
+    selected: Option<&[String]>,
+) -> Result<(Vec<String>, Array<f32, Ix2>), Error> {
+    let num_fam_people = get_line_count(fam_path)?;
+    let (pheno_names, pheno_matrix, _kept_indices) =
+        align_multi_pheno_to_fam(fam_path, pheno_path, selected)?;
+    check_pheno_num_people(pheno_matrix.dim().0, pheno_path, num_fam_people)?;
+    Ok((pheno_names, pheno_matrix))
+}
+
+/// The phenotypes of `align_multi_pheno_to_fam` for the `people` by their
+/// indices in the fam file, dropping the people without phenotypes
+///
+/// returns the phenotype names, the phenotype matrix with one row per person
+/// kept, and the indices of the people kept in the fam file
+pub fn get_fam_aligned_multi_pheno_matrix_of_people(
+    fam_path: &str,
+    pheno_path: &str,
+    selected: Option<&[String]>,
+    people: &OrderedIntegerSet<usize>,
+) -> Result<(Vec<String>, Array<f32, Ix2>, OrderedIntegerSet<usize>), Error> {
+    let (pheno_names, pheno_matrix, kept_indices) =
+        align_multi_pheno_to_fam(fam_path, pheno_path, selected)?;
+    let kept = OrderedIntegerSet::intersect_all(&[
+        people.clone(),
+        kept_indices.clone(),
+    ]);
+    if kept.size() < people.size() {
+        println!(
+            "\n=> dropping {} of the {} people kept for missing phenotypes",
+            people.size() - kept.size(),
+            people.size()
+        );
+    }
+    let index_to_row: HashMap<usize, usize> = kept_indices
+        .iter()
+        .enumerate()
+        .map(|(row, i)| (i, row))
+        .collect();
+    let rows: Vec<usize> = kept.iter().map(|i| index_to_row[&i]).collect();
+    Ok((pheno_names, pheno_matrix.select(Axis(0), &rows), kept))
+}
+
+/// Reads a keep list in the format of `plink --keep`, with the FID and IID
+/// of one person per line and no header.
+///
+/// returns the indices of the people on the list in the fam file. The people
+/// on the list missing from the fam file are reported.
+pub fn get_keep_indices(
+    fam_path: &str,
+    keep_path: &str,
+) -> Result<OrderedIntegerSet<usize>, Error> {
+    let keep: HashSet<(String, String)> = get_file_line_tokens(keep_path, 2)?
+        .into_iter()
+        .map(|toks| (toks[0].to_owned(), toks[1].to_owned()))
+        .collect();
+    let fid_iid_list = get_fid_iid_list(fam_path)?;
+    let kept_indices: Vec<usize> = fid_iid_list
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| keep.contains(id))
+        .map(|(i, _)| i)
+        .collect();
+    if kept_indices.len() < keep.len() {
+        println!(
+            "\n=> {} of the people in {} are not in {}",
+            keep.len() - kept_indices.len(),
+            keep_path,
+            fam_path
+        );
+    }
+    println!(
+        "\n=> keeping {} of the {} people in {}",
+        kept_indices.len(),
+        fid_iid_list.len(),
+        fam_path
+    );
+    Ok(OrderedIntegerSet::collect_from_sorted_iter(
+        kept_indices.into_iter(),
+    ))
+}
+
+/// The phenotypes aligned to the fam file with `align_pheno_to_fam`, for the
+/// `people` by their indices in the fam file. The people without a value for
+/// every phenotype are dropped.
+///
+/// returns the phenotype matrix with one row per person kept, and the indices
+/// of the people kept in the fam file
+pub fn get_fam_aligned_pheno_matrix_of_people(
+    fam_path: &str,
+    pheno_path_vec: &Vec<String>,
+    people: &OrderedIntegerSet<usize>,
+) -> Result<(Array<f32, Ix2>, OrderedIntegerSet<usize>), Error> {
+    let mut kept_sets = vec![people.clone()];
+    let mut index_to_pheno_list = Vec::new();
+    for path in pheno_path_vec.iter() {
+        let (pheno_arr, kept_indices) = align_pheno_to_fam(fam_path, path)?;
+        index_to_pheno_list.push(
+            kept_indices
+                .iter()
+                .zip(pheno_arr.iter().cloned())
+                .collect::<HashMap<usize, f32>>(),
+        );
+        kept_sets.push(kept_indices);
+    }
+    let kept = OrderedIntegerSet::intersect_all(&kept_sets);
+    if kept.size() < people.size() {
+        println!(
+            "\n=> dropping {} of the {} people kept for missing phenotypes",
+            people.size() - kept.size(),
+            people.size()
+        );
+    }
+    let kept_vec: Vec<usize> = kept.iter().collect();
+    let pheno_matrix = Array::from_shape_fn(
+        (kept_vec.len(), pheno_path_vec.len()),
+        |(i, p)| index_to_pheno_list[p][&kept_vec[i]],
+    );
+    Ok((pheno_matrix, kept))
 }
 
 pub fn get_pheno_path_to_arr(
     pheno_path_vec: &Vec<String>,
-) -> Result<HashMap<String, Array<f32, Ix1>>, String> {
+) -> Result<HashMap<String, Array<f32, Ix1>>, Error> {
     pheno_path_vec
         .iter()
         .map(|p| Ok((p.to_string(), get_pheno_arr(p)?)))
-        .collect::<Result<HashMap<String, Array<f32, Ix1>>, String>>()
+        .collect::<Result<HashMap<String, Array<f32, Ix1>>, Error>>()
 }
 
 pub fn get_pheno_matrix(
     pheno_path_vec: &Vec<String>,
-) -> Result<Array<f32, Ix2>, String> {
-    let v: Vec<f32> = pheno_path_vec
+) -> Result<Array<f32, Ix2>, Error> {
+    let arr_list = pheno_path_vec
         .iter()
-        .map(|p| Ok(get_pheno_arr(p)?.to_vec()))
-        .collect::<Result<Vec<Vec<f32>>, String>>()?
-        .into_iter()
-        .flat_map(|v| v)
-        .collect();
+        .map(|p| get_pheno_arr(p))
+        .collect::<Result<Vec<Array<f32, Ix1>>, Error>>()?;
+    for (path, arr) in pheno_path_vec.iter().zip(arr_list.iter()).skip(1) {
+        check_num_people(
+            arr.len(),
+            path,
+            arr_list[0].len(),
+            &pheno_path_vec[0],
+        )?;
+    }
+    let v: Vec<f32> =
+        arr_list.into_iter().flat_map(|arr| arr.to_vec()).collect();
     let num_pheno_types = pheno_path_vec.len();
     let num_rows = v.len() / num_pheno_types;
     Ok(Array::from_shape_vec(
         (num_rows, num_pheno_types).strides((1, num_rows)),
         v,
-    )
-    .unwrap())
+    )?)
 }
 
 /// The first line of the file is FID IID pheno
@@ -286,13 +928,8 @@ pub fn get_pheno_matrix(
 /// in the order listed in the file
 pub fn get_plink_pheno_data(
     pheno_path: &str,
-) -> Result<(String, Vec<String>, Vec<String>, Array<f32, Ix1>), String> {
-    let mut buf = match OpenOptions::new().read(true).open(pheno_path) {
-        Err(why) => {
-            return Err(format!("failed to open {}: {}", pheno_path, why))
-        }
-        Ok(f) => BufReader::new(f),
-    };
+) -> Result<(String, Vec<String>, Vec<String>, Array<f32, Ix1>), Error> {
+    let mut buf = open_plink_file(pheno_path)?;
 
     let header = read_and_validate_plink_header(&mut buf)?;
     println!("\n{} header:\n{}", pheno_path, header);
@@ -300,15 +937,11 @@ pub fn get_plink_pheno_data(
     let mut pheno_vec = Vec::new();
     let mut fid_vec = Vec::new();
     let mut iid_vec = Vec::new();
-    for l in buf.lines() {
-        let toks: Vec<String> = l
-            .unwrap()
-            .split_whitespace()
-            .map(|t| t.to_string())
-            .collect();
+    for (i, l) in buf.lines().enumerate() {
+        let toks = read_plink_line(l, i, pheno_path)?;
+        pheno_vec.push(parse_plink_value(&toks, 2, i, pheno_path)?);
         fid_vec.push(toks[0].to_owned());
         iid_vec.push(toks[1].to_owned());
-        pheno_vec.push(toks[2].parse::<f32>().unwrap());
     }
     Ok((header, fid_vec, iid_vec, Array::from_vec(pheno_vec)))
 }
@@ -327,16 +960,11 @@ enum PhenoVal<T> {
 pub fn get_plink_pheno_data_replace_missing_with_mean(
     pheno_path: &str,
     missing_reps_vec: &Vec<String>,
-) -> Result<(String, Vec<String>, Vec<String>, Array<f32, Ix1>), String> {
+) -> Result<(String, Vec<String>, Vec<String>, Array<f32, Ix1>), Error> {
     let missing_reps: HashSet<String> =
         missing_reps_vec.iter().cloned().collect();
 
-    let mut buf = match OpenOptions::new().read(true).open(pheno_path) {
-        Err(why) => {
-            return Err(format!("failed to open {}: {}", pheno_path, why))
-        }
-        Ok(f) => BufReader::new(f),
-    };
+    let mut buf = open_plink_file(pheno_path)?;
 
     let header = read_and_validate_plink_header(&mut buf)?;
     println!("\n{} header:\n{}", pheno_path, header);
@@ -344,19 +972,17 @@ pub fn get_plink_pheno_data_replace_missing_with_mean(
     let mut pheno = Vec::new();
     let mut fid_vec = Vec::new();
     let mut iid_vec = Vec::new();
-    for l in buf.lines() {
-        let toks: Vec<String> = l
-            .unwrap()
-            .split_whitespace()
-            .map(|t| t.to_string())
-            .collect();
-        fid_vec.push(toks[0].to_owned());
-        iid_vec.push(toks[1].to_owned());
-        if missing_reps.contains(&toks[2]) {
+    for (i, l) in buf.lines().enumerate() {
+        let toks = read_plink_line(l, i, pheno_path)?;
+        if toks.get(2).map_or(false, |t| missing_reps.contains(t)) {
             pheno.push(PhenoVal::Missing);
         } else {
-            pheno.push(PhenoVal::Present(toks[2].parse::<f32>().unwrap()));
+            pheno.push(PhenoVal::Present(parse_plink_value(
+                &toks, 2, i, pheno_path,
+            )?));
         }
+        fid_vec.push(toks[0].to_owned());
+        iid_vec.push(toks[1].to_owned());
     }
     let non_missing_count_sum =
         pheno
@@ -389,44 +1015,36 @@ pub fn get_plink_pheno_data_replace_missing_with_mean(
 /// corresponding fields.
 pub fn get_plink_covariate_arr(
     covariate_path: &str,
-) -> Result<Array<f32, Ix2>, String> {
+) -> Result<Array<f32, Ix2>, Error> {
     let num_people = get_line_count(covariate_path)? - 1;
     println!("\n{} contains {} people", covariate_path, num_people);
 
-    let mut buf = match OpenOptions::new().read(true).open(covariate_path) {
-        Err(why) => {
-            return Err(format!("failed to open {}: {}", covariate_path, why))
-        }
-        Ok(f) => BufReader::new(f),
-    };
+    let mut buf = open_plink_file(covariate_path)?;
 
     let header = read_and_validate_plink_header(&mut buf)?;
     println!("\n{} header:\n{}", covariate_path, header);
 
-    let covariate_vec: Vec<f32> = buf
-        .lines()
-        .flat_map(|l| {
-            l.unwrap()
-                .split_whitespace()
-                .skip(2)
-                .map(|s| s.parse::<f32>().unwrap())
-                .collect::<Vec<f32>>()
-        })
-        .collect();
+    let mut covariate_vec: Vec<f32> = Vec::new();
+    for (i, l) in buf.lines().enumerate() {
+        let toks = read_plink_line(l, i, covariate_path)?;
+        for j in 2..toks.len() {
+            covariate_vec.push(parse_plink_value(&toks, j, i, covariate_path)?);
+        }
+    }
 
-    assert_eq!(
-        covariate_vec.len() % num_people,
-        0,
-        "total number of elements {} is not divisible by num_people {}",
-        covariate_vec.len(),
-        num_people
-    );
-    let arr = Array::<f32, Ix2>::from_shape_vec(
+    if num_people == 0 || covariate_vec.len() % num_people != 0 {
+        return Err(Error::DimensionMismatch(format!(
+            "the {} covariate values of {} do not form a row for each of the \
+            {} people",
+            covariate_vec.len(),
+            covariate_path,
+            num_people
+        )));
+    }
+    Ok(Array::<f32, Ix2>::from_shape_vec(
         (num_people, covariate_vec.len() / num_people),
         covariate_vec,
-    )
-    .unwrap();
-    Ok(arr)
+    )?)
 }
 
 #[cfg(test)]
@@ -436,14 +1054,129 @@ mod tests {
         io::{BufWriter, Write},
     };
 
+    use math::set::ordered_integer_set::OrderedIntegerSet;
     use ndarray::Array;
     use tempfile::NamedTempFile;
 
     use crate::util::{
-        get_fid_iid_list, load_trace_estimates, validate_header,
-        write_trace_estimates,
+        align_pheno_to_fam, check_num_people,
+        get_fam_aligned_multi_pheno_matrix,
+        get_fam_aligned_multi_pheno_matrix_of_people,
+        get_fam_aligned_pheno_arr, get_fid_iid_list, load_trace_estimates,
+        load_trace_estimates_with_metadata,
+        trace_metadata::{Standardization, TraceMetadata},
+        validate_header, write_trace_estimates,
+        write_trace_estimates_with_metadata,
     };
 
+    #[test]
+    fn test_check_num_people() {
+        assert!(check_num_people(10, "pheno", 10, "x.fam").is_ok());
+        let why = check_num_people(9, "pheno", 10, "x.fam")
+            .unwrap_err()
+            .to_string();
+        assert!(why.contains("pheno has 9 people"));
+        assert!(why.contains("x.fam has 10 people"));
+    }
+
+    #[test]
+    fn test_align_pheno_to_fam() {
+        let mut fam = NamedTempFile::new().unwrap();
+        for (fid, iid) in
+            [("f1", "a"), ("f1", "b"), ("f2", "c"), ("f3", "d")].iter()
+        {
+            writeln!(fam, "{} {} 0 0 1 -9", fid, iid).unwrap();
+        }
+        let fam_path = fam.path().to_str().unwrap().to_string();
+
+        let mut pheno = NamedTempFile::new().unwrap();
+        writeln!(pheno, "FID IID pheno").unwrap();
+        writeln!(pheno, "f3 d 4").unwrap();
+        writeln!(pheno, "f9 z 9").unwrap();
+        writeln!(pheno, "f1 a 1").unwrap();
+        writeln!(pheno, "f2 c 3").unwrap();
+        let pheno_path = pheno.path().to_str().unwrap().to_string();
+
+        let (arr, kept_indices) =
+            align_pheno_to_fam(&fam_path, &pheno_path).unwrap();
+        assert_eq!(arr.to_vec(), vec![1., 3., 4.]);
+        assert_eq!(
+            kept_indices,
+            OrderedIntegerSet::from_slice(&[[0, 0], [2, 3]])
+        );
+        // the person b in the fam file has no phenotype
+        assert!(get_fam_aligned_pheno_arr(&fam_path, &pheno_path).is_err());
+
+        writeln!(pheno, "f1 b 2").unwrap();
+        assert_eq!(
+            get_fam_aligned_pheno_arr(&fam_path, &pheno_path)
+                .unwrap()
+                .to_vec(),
+            vec![1., 2., 3., 4.]
+        );
+
+        writeln!(pheno, "f1 b 5").unwrap();
+        assert!(align_pheno_to_fam(&fam_path, &pheno_path).is_err());
+    }
+
+    #[test]
+    fn test_get_fam_aligned_multi_pheno_matrix() {
+        let mut fam = NamedTempFile::new().unwrap();
+        for (fid, iid) in [("f1", "a"), ("f1", "b"), ("f2", "c")].iter() {
+            writeln!(fam, "{} {} 0 0 1 -9", fid, iid).unwrap();
+        }
+        let fam_path = fam.path().to_str().unwrap().to_string();
+
+        let mut pheno = NamedTempFile::new().unwrap();
+        writeln!(pheno, "FID IID height bmi ldl").unwrap();
+        writeln!(pheno, "f2 c 3 30 0.3").unwrap();
+        writeln!(pheno, "f1 a 1 10 0.1").unwrap();
+        writeln!(pheno, "f9 z 9 90 0.9").unwrap();
+        writeln!(pheno, "f1 b 2 20 0.2").unwrap();
+        let pheno_path = pheno.path().to_str().unwrap().to_string();
+
+        let (names, matrix) =
+            get_fam_aligned_multi_pheno_matrix(&fam_path, &pheno_path, None)
+                .unwrap();
+        assert_eq!(names, vec!["height", "bmi", "ldl"]);
+        assert_eq!(matrix.column(0).to_vec(), vec![1., 2., 3.]);
+        assert_eq!(matrix.row(2).to_vec(), vec![3., 30., 0.3]);
+
+        let selected = vec!["ldl".to_string(), "height".to_string()];
+        let (names, matrix) = get_fam_aligned_multi_pheno_matrix(
+            &fam_path,
+            &pheno_path,
+            Some(&selected),
+        )
+        .unwrap();
+        assert_eq!(names, selected);
+        assert_eq!(matrix.row(0).to_vec(), vec![0.1, 1.]);
+
+        let (_, matrix, kept) = get_fam_aligned_multi_pheno_matrix_of_people(
+            &fam_path,
+            &pheno_path,
+            Some(&selected[1..]),
+            &OrderedIntegerSet::from_slice(&[[1, 2]]),
+        )
+        .unwrap();
+        assert_eq!(kept, OrderedIntegerSet::from_slice(&[[1, 2]]));
+        assert_eq!(matrix.column(0).to_vec(), vec![2., 3.]);
+
+        assert!(get_fam_aligned_multi_pheno_matrix(
+            &fam_path,
+            &pheno_path,
+            Some(&["weight".to_string()])
+        )
+        .is_err());
+        writeln!(pheno, "f3 d 4 40").unwrap();
+        assert!(get_fam_aligned_multi_pheno_matrix(
+            &fam_path,
+            &pheno_path,
+            None
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_validate_header() {
         assert_eq!(
@@ -507,6 +1240,28 @@ mod tests {
         assert_eq!(loaded_estimates, estimates);
     }
 
+    #[test]
+    fn test_write_trace_estimates_with_metadata() {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.into_temp_path().to_str().unwrap().to_string();
+        let estimates =
+            Array::from_shape_vec((2, 2), vec![2., 0.5, 0.5, 1.]).unwrap();
+        let metadata = TraceMetadata::new(
+            vec!["G".to_string(), "noise".to_string()],
+            vec![("G".to_string(), OrderedIntegerSet::from_slice(&[[0, 9]]))],
+            10,
+            Standardization::UnitVariance,
+        );
+        write_trace_estimates_with_metadata(&estimates, &metadata, &path)
+            .unwrap();
+
+        let (loaded_estimates, loaded_metadata) =
+            load_trace_estimates_with_metadata(&path).unwrap();
+        assert_eq!(loaded_estimates, estimates);
+        assert_eq!(loaded_metadata, Some(metadata));
+        assert_eq!(load_trace_estimates(&path).unwrap(), estimates);
+    }
+
     #[test]
     fn test_get_fid_iid_list() {
         let fam_path = NamedTempFile::new().unwrap().into_temp_path();
@@ -0,0 +1,209 @@
+//! The kernels of small random genotype matrices formed explicitly in f64,
+//! and the statistics of repeated randomized estimates, against which the
+//! trace estimators are validated in the tests. An estimator passes if the
+//! mean of its replicates is within a few standard errors of the exact
+//! value, and if the variance of the replicates scales as
+//! `1 / num_random_vecs`.
+
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+};
+
+use biofile::plink_bed::{geno_to_lowest_two_bits, PlinkBed, PlinkSnpType};
+use ndarray::{Array, Axis, Ix1, Ix2};
+use tempfile::TempDir;
+
+use crate::{
+    simulation::sim_geno::{generate_g_matrix, get_gxg_arr},
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+/// A `num_people x num_snps` genotype matrix with the allele frequency 0.5
+pub fn random_genotypes(num_people: usize, num_snps: usize) -> Array<f32, Ix2> {
+    generate_g_matrix(num_people, num_snps, 0.25, 0.25)
+        .unwrap()
+        .mapv(|g| g as f32)
+}
+
+pub fn standardize(geno: &Array<f32, Ix2>) -> Array<f32, Ix2> {
+    let mut x = geno.clone();
+    normalize_matrix_columns_inplace(&mut x, 0);
+    x
+}
+
+/// `K = X X^T / m` of the standardized genotypes `X` of `geno`
+pub fn grm(geno: &Array<f32, Ix2>) -> Array<f64, Ix2> {
+    let x = standardize(geno).mapv(|g| g as f64);
+    x.dot(&x.t()) / geno.dim().1 as f64
+}
+
+/// The unnormalized GxG kernel `A = sum_{i < j} (x_i * x_j)(x_i * x_j)^T` of
+/// the columns of `basis`, which are used as they are
+pub fn gxg_kernel(basis: &Array<f32, Ix2>) -> Array<f64, Ix2> {
+    let pairs = get_gxg_arr(basis).mapv(|g| g as f64);
+    pairs.dot(&pairs.t())
+}
+
+pub fn trace(k: &Array<f64, Ix2>) -> f64 {
+    k.diag().sum()
+}
+
+/// `tr(A B)` of the symmetric `a` and `b`
+pub fn trace_of_product(a: &Array<f64, Ix2>, b: &Array<f64, Ix2>) -> f64 {
+    (a * b).sum()
+}
+
+pub fn quadratic_form(k: &Array<f64, Ix2>, y: &Array<f32, Ix1>) -> f64 {
+    let y = y.mapv(|v| v as f64);
+    y.dot(&k.dot(&y))
+}
+
+/// The column means and standard deviations of `geno`, as expected by the
+/// estimators that standardize the genotypes of a bed on the fly
+pub fn column_mean_and_std(
+    geno: &Array<f32, Ix2>,
+) -> (Array<f32, Ix1>, Array<f32, Ix1>) {
+    let num_people = geno.dim().0 as f32;
+    let mean = geno.sum_axis(Axis(0)) / num_people;
+    let std = Array::from_shape_fn(geno.dim().1, |j| {
+        let col = geno.column(j);
+        (col.iter()
+            .map(|g| (g - mean[j]) * (g - mean[j]))
+            .sum::<f32>()
+            / num_people)
+            .sqrt()
+    });
+    (mean, std)
+}
+
+/// A PLINK bed of a genotype matrix in a temporary directory, which is
+/// removed when the `TempBed` is dropped
+pub struct TempBed {
+    pub bed: PlinkBed,
+    _dir: TempDir,
+}
+
+impl TempBed {
+    pub fn new(geno: &Array<f32, Ix2>) -> TempBed {
+        let dir = TempDir::new().unwrap();
+        let prefix = dir.path().join("geno").to_str().unwrap().to_string();
+        let (num_people, num_snps) = geno.dim();
+        let create = |ext: &str| {
+            BufWriter::new(
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(true)
+                    .write(true)
+                    .open(format!("{}.{}", prefix, ext))
+                    .unwrap(),
+            )
+        };
+        let mut bed = create("bed");
+        bed.write_all(&[0x6c, 0x1b, 0x1]).unwrap();
+        for col in geno.axis_iter(Axis(1)) {
+            for start in (0..num_people).step_by(4) {
+                let mut byte = 0u8;
+                for k in start..std::cmp::min(start + 4, num_people) {
+                    byte |= geno_to_lowest_two_bits(col[k] as u8)
+                        << ((k - start) * 2);
+                }
+                bed.write_all(&[byte]).unwrap();
+            }
+        }
+        bed.flush().unwrap();
+        let mut bim = create("bim");
+        for j in 0..num_snps {
+            writeln!(bim, "1\trs{}\t0\t{}\tA\tG", j + 1, j + 1).unwrap();
+        }
+        bim.flush().unwrap();
+        let mut fam = create("fam");
+        for i in 0..num_people {
+            writeln!(fam, "{} {} 0 0 0 -9", i + 1, i + 1).unwrap();
+        }
+        fam.flush().unwrap();
+        TempBed {
+            bed: PlinkBed::new(&vec![(
+                format!("{}.bed", prefix),
+                format!("{}.bim", prefix),
+                format!("{}.fam", prefix),
+                PlinkSnpType::Additive,
+            )])
+            .unwrap(),
+            _dir: dir,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ReplicateStats {
+    pub mean: f64,
+    pub variance: f64,
+    pub standard_error: f64,
+}
+
+impl ReplicateStats {
+    pub fn new(values: &[f64]) -> ReplicateStats {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>()
+                / (n - 1.);
+        ReplicateStats {
+            mean,
+            variance,
+            standard_error: (variance / n).sqrt(),
+        }
+    }
+}
+
+/// Runs `estimate(replicate)` for `num_replicates` replicates and asserts
+/// that their mean is within 4 standard errors of `expected`, up to the
+/// relative f32 tolerance `rel_tol`. Returns the replicate statistics.
+pub fn assert_unbiased<F: FnMut(usize) -> f64>(
+    name: &str,
+    expected: f64,
+    num_replicates: usize,
+    rel_tol: f64,
+    mut estimate: F,
+) -> ReplicateStats {
+    let values: Vec<f64> = (0..num_replicates).map(&mut estimate).collect();
+    let stats = ReplicateStats::new(&values);
+    assert!(
+        (stats.mean - expected).abs()
+            <= 4. * stats.standard_error + rel_tol * expected.abs(),
+        "{}: the mean {} of {} replicates with the standard error {} is not \
+        close to the exact {}",
+        name,
+        stats.mean,
+        num_replicates,
+        stats.standard_error,
+        expected
+    );
+    stats
+}
+
+/// Asserts that the variance of `estimate(num_random_vecs, replicate)` over
+/// `num_replicates` replicates drops by about 4 from `num_random_vecs` to
+/// `4 * num_random_vecs`. The bounds leave room for the sampling error of the
+/// two variances, which is about 25% each with 60 replicates.
+pub fn assert_variance_scales_inversely<F: FnMut(usize, usize) -> f64>(
+    name: &str,
+    num_random_vecs: usize,
+    num_replicates: usize,
+    mut estimate: F,
+) {
+    let mut variance = |nrv: usize| {
+        let values: Vec<f64> =
+            (0..num_replicates).map(|r| estimate(nrv, r)).collect();
+        ReplicateStats::new(&values).variance
+    };
+    let ratio = variance(num_random_vecs) / variance(4 * num_random_vecs);
+    assert!(
+        ratio > 1.5 && ratio < 10.,
+        "{}: quadrupling the random vectors divides the variance by {} \
+        instead of about 4",
+        name,
+        ratio
+    );
+}
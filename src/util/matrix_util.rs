@@ -1,9 +1,16 @@
+use std::{fmt, str::FromStr};
+
 use math::stats::{mean, standard_deviation};
 use ndarray::{Array, Axis, Ix1, Ix2, ScalarOperand};
 use ndarray_parallel::prelude::*;
 use ndarray_rand::RandomExt;
 use num_traits::{Float, FromPrimitive, NumAssign, ToPrimitive};
-use rand::distributions::{Bernoulli, StandardNormal};
+use rand::{
+    distributions::{Bernoulli, StandardNormal},
+    rngs::StdRng,
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
 
 pub fn generate_plus_minus_one_bernoulli_matrix(
     num_rows: usize,
@@ -13,6 +20,252 @@ pub fn generate_plus_minus_one_bernoulli_matrix(
         .mapv(|e| (e as i32 * 2 - 1) as f32)
 }
 
+/// Each entry is nonzero with probability `sparsity`, in which case it is
+/// `+-1 / sqrt(sparsity)` with equal probability, so that `E[z z^T] = I` as
+/// for the dense +-1 probes.
+pub fn generate_sparse_sign_matrix(
+    num_rows: usize,
+    num_cols: usize,
+    sparsity: f64,
+) -> Array<f32, Ix2> {
+    check_sparsity(sparsity);
+    let mut rng = rand::thread_rng();
+    Array::from_shape_fn((num_rows, num_cols), |_| {
+        draw_sign(&mut rng, Some(sparsity))
+    })
+}
+
+/// Mixes `task` into `seed` with the SplitMix64 finalizer, so that the tasks
+/// of a seeded computation draw from independent streams regardless of the
+/// threads they run on.
+pub fn derive_seed(seed: u64, task: u64) -> u64 {
+    let mut z = seed
+        .wrapping_add(task.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Dense +-1 entries, or sparse sign entries if `sparsity` is given. If
+/// `seed` is `Some`, the `j`-th column is drawn from the stream
+/// `derive_seed(seed, j)`, so that the matrix is reproducible and does not
+/// depend on the number of threads generating the columns in parallel.
+pub fn generate_sign_matrix(
+    num_rows: usize,
+    num_cols: usize,
+    sparsity: Option<f64>,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let seed = match seed {
+        Some(seed) => seed,
+        None => {
+            return match sparsity {
+                Some(q) => generate_sparse_sign_matrix(num_rows, num_cols, q),
+                None => {
+                    generate_plus_minus_one_bernoulli_matrix(num_rows, num_cols)
+                }
+            };
+        }
+    };
+    if let Some(q) = sparsity {
+        check_sparsity(q);
+    }
+    let mut matrix = Array::<f32, Ix2>::zeros((num_rows, num_cols));
+    matrix
+        .axis_iter_mut(Axis(1))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(j, mut col)| {
+            let mut rng = StdRng::seed_from_u64(derive_seed(seed, j as u64));
+            for x in col.iter_mut() {
+                *x = draw_sign(&mut rng, sparsity);
+            }
+        });
+    matrix
+}
+
+fn check_sparsity(sparsity: f64) {
+    assert!(
+        sparsity > 0. && sparsity <= 1.,
+        "the probe sparsity has to be in (0, 1], received {}",
+        sparsity
+    );
+}
+
+fn draw_sign<R: Rng>(rng: &mut R, sparsity: Option<f64>) -> f32 {
+    match sparsity {
+        Some(q) => {
+            if rng.gen_bool(q) {
+                let scale = (1. / q.sqrt()) as f32;
+                if rng.gen_bool(0.5) {
+                    scale
+                } else {
+                    -scale
+                }
+            } else {
+                0.
+            }
+        }
+        None => {
+            if rng.gen_bool(0.5) {
+                1.
+            } else {
+                -1.
+            }
+        }
+    }
+}
+
+/// The distribution of the probe vectors `z` of the trace estimates, all of
+/// which satisfy `E[z z^T] = I`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ProbeType {
+    /// independent +-1 entries, or sparse sign entries with a sparsity
+    Rademacher,
+    /// independent standard normal entries
+    Gaussian,
+    /// the columns of a subsampled randomized Hadamard transform, see
+    /// `generate_srht_matrix`
+    Srht,
+}
+
+impl Default for ProbeType {
+    fn default() -> ProbeType {
+        ProbeType::Rademacher
+    }
+}
+
+impl FromStr for ProbeType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProbeType, String> {
+        match s {
+            "rademacher" => Ok(ProbeType::Rademacher),
+            "gaussian" => Ok(ProbeType::Gaussian),
+            "srht" => Ok(ProbeType::Srht),
+            _ => Err(format!(
+                "unrecognized probe type {}, expected one of rademacher, \
+                gaussian and srht",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ProbeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeType::Rademacher => write!(f, "rademacher"),
+            ProbeType::Gaussian => write!(f, "gaussian"),
+            ProbeType::Srht => write!(f, "srht"),
+        }
+    }
+}
+
+/// Standard normal entries. If `seed` is `Some`, the `j`-th column is drawn
+/// from the stream `derive_seed(seed, j)` as in `generate_sign_matrix`.
+pub fn generate_gaussian_matrix(
+    num_rows: usize,
+    num_cols: usize,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let seed = match seed {
+        Some(seed) => seed,
+        None => return generate_standard_normal_matrix(num_rows, num_cols),
+    };
+    let mut matrix = Array::<f32, Ix2>::zeros((num_rows, num_cols));
+    matrix
+        .axis_iter_mut(Axis(1))
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(j, mut col)| {
+            let mut rng = StdRng::seed_from_u64(derive_seed(seed, j as u64));
+            for x in col.iter_mut() {
+                *x = rng.sample(StandardNormal) as f32;
+            }
+        });
+    matrix
+}
+
+/// Row `i` of column `j` is `d_i H[i, c_j]`, where `H` is the +-1 Sylvester
+/// Hadamard matrix of the smallest power of two `N >= num_rows`, `d` are
+/// random signs shared by the columns, and the `c_j` are drawn from the `N`
+/// columns of `H` without replacement, starting over once all of them have
+/// been drawn. Every column is marginally a uniformly random column, so that
+/// `E[z z^T] = I`, while the columns drawn without replacement are
+/// orthogonal when `num_rows` is a power of two and nearly so otherwise.
+pub fn generate_srht_matrix(
+    num_rows: usize,
+    num_cols: usize,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+    };
+    let signs: Vec<f32> = (0..num_rows)
+        .map(|_| if rng.gen_bool(0.5) { 1. } else { -1. })
+        .collect();
+    let hadamard_size = num_rows.next_power_of_two();
+    let mut hadamard_cols = Vec::with_capacity(num_cols + hadamard_size);
+    while hadamard_cols.len() < num_cols {
+        let mut cols: Vec<usize> = (0..hadamard_size).collect();
+        cols.shuffle(&mut rng);
+        hadamard_cols.append(&mut cols);
+    }
+    Array::from_shape_fn((num_rows, num_cols), |(i, j)| {
+        if (i & hadamard_cols[j]).count_ones() % 2 == 0 {
+            signs[i]
+        } else {
+            -signs[i]
+        }
+    })
+}
+
+/// Draws the probes of the `probe_type` with the `seed`. The `sparsity`
+/// only applies to the Rademacher probes.
+pub fn generate_probe_matrix(
+    num_rows: usize,
+    num_cols: usize,
+    probe_type: ProbeType,
+    sparsity: Option<f64>,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    assert!(
+        sparsity.is_none() || probe_type == ProbeType::Rademacher,
+        "the probe sparsity only applies to the rademacher probes, received \
+        {} probes",
+        probe_type
+    );
+    match probe_type {
+        ProbeType::Rademacher => {
+            generate_sign_matrix(num_rows, num_cols, sparsity, seed)
+        }
+        ProbeType::Gaussian => {
+            generate_gaussian_matrix(num_rows, num_cols, seed)
+        }
+        ProbeType::Srht => generate_srht_matrix(num_rows, num_cols, seed),
+    }
+}
+
+/// Returns the probe vectors `Z` used to estimate traces as `tr(A Z Z^T) / b`
+/// for `b` probe vectors. If `num_random_vecs >= num_rows`, the random
+/// vectors are replaced by the `num_rows` columns of `sqrt(num_rows) * I`, for
+/// which `Z Z^T / b = I` and the estimate becomes exact. Otherwise the probes
+/// are drawn as in `generate_probe_matrix` with the `seed`.
+pub fn generate_trace_probe_matrix(
+    num_rows: usize,
+    num_random_vecs: usize,
+    probe_type: ProbeType,
+    sparsity: Option<f64>,
+    seed: Option<u64>,
+) -> Array<f32, Ix2> {
+    if num_random_vecs >= num_rows {
+        return Array::eye(num_rows) * (num_rows as f32).sqrt();
+    }
+    generate_probe_matrix(num_rows, num_random_vecs, probe_type, sparsity, seed)
+}
+
 pub fn generate_standard_normal_matrix(
     num_rows: usize,
     num_cols: usize,
@@ -76,6 +329,47 @@ pub fn normalize_matrix_columns_inplace<A>(
         });
 }
 
+/// The alpha of GCTA, under which the standardized SNPs have unit variance
+pub const DEFAULT_ALPHA: f64 = -1.;
+
+/// The variance `[2p(1-p)]^(1 + alpha)` of a SNP with the allele frequency `p`
+/// under the alpha model, in which the centered genotypes are scaled by
+/// `[2p(1-p)]^(alpha / 2)`. This is also the weight of the SNP relative to
+/// the unit-variance standardization. Monomorphic SNPs have a weight of zero.
+pub fn get_alpha_model_weight(allele_frequency: f32, alpha: f64) -> f32 {
+    let heterozygosity =
+        2. * allele_frequency as f64 * (1. - allele_frequency as f64);
+    if heterozygosity > 0. {
+        heterozygosity.powf(1. + alpha) as f32
+    } else {
+        0.
+    }
+}
+
+/// Centers every SNP column of the `matrix` and scales it by
+/// `[2p(1-p)]^(alpha / 2)`, where the allele frequency `p` is half the mean of
+/// the column. `alpha = DEFAULT_ALPHA` amounts to
+/// `normalize_matrix_columns_inplace` with `ddof = 0` for the SNPs in
+/// Hardy-Weinberg equilibrium.
+pub fn normalize_snp_columns_with_alpha_inplace(
+    matrix: &mut Array<f32, Ix2>,
+    alpha: f64,
+) {
+    let num_rows = matrix.dim().0 as f32;
+    matrix
+        .axis_iter_mut(Axis(1))
+        .into_par_iter()
+        .for_each(|mut col| {
+            let col_mean = col.sum() / num_rows;
+            col -= col_mean;
+            let std = ((&col * &col).sum() / num_rows).sqrt();
+            if std > 0. {
+                col *=
+                    get_alpha_model_weight(col_mean / 2., alpha).sqrt() / std;
+            }
+        });
+}
+
 pub fn normalize_vector_inplace<A>(vec: &mut Array<A, Ix1>, ddof: usize)
 where
     A: ToPrimitive
@@ -132,13 +426,18 @@ where
 #[cfg(test)]
 mod tests {
     use math::stats::{mean, standard_deviation};
-    use ndarray::Array;
+    use ndarray::{array, Array, Ix2};
     use ndarray_rand::RandomExt;
     use rand::distributions::Uniform;
 
     use super::{
-        get_correlation, mean_center_vector, normalize_matrix_columns_inplace,
-        normalize_matrix_row_wise_inplace, normalize_vector_inplace,
+        derive_seed, generate_plus_minus_one_bernoulli_matrix,
+        generate_probe_matrix, generate_sign_matrix,
+        generate_sparse_sign_matrix, generate_trace_probe_matrix,
+        get_alpha_model_weight, get_correlation, mean_center_vector,
+        normalize_matrix_columns_inplace, normalize_matrix_row_wise_inplace,
+        normalize_snp_columns_with_alpha_inplace, normalize_vector_inplace,
+        ProbeType, DEFAULT_ALPHA,
     };
 
     #[test]
@@ -173,6 +472,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_normalize_snp_columns_with_alpha() {
+        // allele frequencies of 0.25 and 0.5
+        let genotypes = array![[0f32, 2.], [1., 0.], [0., 1.], [1., 1.]];
+        assert!(
+            (get_alpha_model_weight(0.25, -0.25) - 0.375f32.powf(0.75)).abs()
+                < 1e-6
+        );
+        assert_eq!(get_alpha_model_weight(0., -0.25), 0.);
+        assert_eq!(get_alpha_model_weight(0.3, DEFAULT_ALPHA), 1.);
+
+        let mut unit = genotypes.clone();
+        normalize_matrix_columns_inplace(&mut unit, 0);
+        let mut matrix = genotypes.clone();
+        normalize_snp_columns_with_alpha_inplace(&mut matrix, DEFAULT_ALPHA);
+        assert!((&matrix - &unit).iter().all(|e| e.abs() < 1e-6));
+
+        let alpha = -0.25;
+        let mut matrix = genotypes;
+        normalize_snp_columns_with_alpha_inplace(&mut matrix, alpha);
+        for (j, p) in [0.25f32, 0.5].iter().enumerate() {
+            let col = matrix.column(j);
+            assert!(mean(col.iter()).abs() < 1e-6);
+            let var = (&col * &col).sum() / 4.;
+            assert!((var - get_alpha_model_weight(*p, alpha)).abs() < 1e-6);
+        }
+    }
+
     #[test]
     fn test_normalize_vector_inplace() {
         let num_elements = 1000;
@@ -211,4 +538,114 @@ mod tests {
         assert!((get_correlation(&v1, &v1_clone) - 1.).abs() < 1e-6);
     }
     // TODO: test row_mean_vec and row_std_vec
+
+    #[test]
+    fn test_generate_trace_probe_matrix() {
+        let probes = generate_trace_probe_matrix(
+            10,
+            4,
+            ProbeType::Rademacher,
+            None,
+            None,
+        );
+        assert_eq!(probes.dim(), (10, 4));
+        assert!(probes.iter().all(|&x| x == 1. || x == -1.));
+
+        let sparse = generate_trace_probe_matrix(
+            10,
+            4,
+            ProbeType::Rademacher,
+            Some(0.25),
+            None,
+        );
+        assert!(sparse.iter().all(|&x| x == 0. || x.abs() == 2.));
+
+        let exact = generate_trace_probe_matrix(
+            4,
+            100,
+            ProbeType::Rademacher,
+            Some(0.1),
+            None,
+        );
+        assert_eq!(exact.dim(), (4, 4));
+        assert_eq!(exact.dot(&exact.t()) / 4., Array::eye(4));
+    }
+
+    #[test]
+    fn test_probe_types() {
+        let gaussian =
+            generate_probe_matrix(200, 50, ProbeType::Gaussian, None, Some(3));
+        assert_eq!(
+            gaussian,
+            generate_probe_matrix(200, 50, ProbeType::Gaussian, None, Some(3))
+        );
+        let mean_sq = gaussian.iter().map(|x| x * x).sum::<f32>() / 10000.;
+        assert!((mean_sq - 1.).abs() < 0.1);
+
+        // the columns drawn without replacement are orthogonal for a power
+        // of two rows
+        let srht =
+            generate_probe_matrix(16, 16, ProbeType::Srht, None, Some(5));
+        assert!(srht.iter().all(|&x| x == 1. || x == -1.));
+        assert_eq!(srht.t().dot(&srht), Array::eye(16) * 16.);
+        assert_eq!(
+            srht,
+            generate_probe_matrix(16, 16, ProbeType::Srht, None, Some(5))
+        );
+        // columns beyond the size of the Hadamard matrix start over
+        let srht = generate_probe_matrix(10, 20, ProbeType::Srht, None, None);
+        assert_eq!(srht.dim(), (10, 20));
+
+        assert_eq!("srht".parse::<ProbeType>(), Ok(ProbeType::Srht));
+        assert_eq!(ProbeType::Gaussian.to_string(), "gaussian");
+        assert!("normal".parse::<ProbeType>().is_err());
+    }
+
+    #[test]
+    fn test_seeded_sign_matrix() {
+        let probes = generate_sign_matrix(50, 8, None, Some(7));
+        assert!(probes.iter().all(|&x| x == 1. || x == -1.));
+        assert_eq!(probes, generate_sign_matrix(50, 8, None, Some(7)));
+        assert_ne!(probes, generate_sign_matrix(50, 8, None, Some(8)));
+        // every column has its own stream, so fewer columns are a prefix
+        assert_eq!(
+            generate_sign_matrix(50, 3, None, Some(7)),
+            probes.slice(ndarray::s![.., ..3])
+        );
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let sparse = generate_sign_matrix(50, 8, Some(0.25), Some(7));
+        assert!(sparse.iter().all(|&x| x == 0. || x.abs() == 2.));
+        assert_eq!(
+            pool.install(|| generate_sign_matrix(50, 8, Some(0.25), Some(7))),
+            sparse
+        );
+        assert_ne!(derive_seed(7, 0), derive_seed(7, 1));
+        assert_ne!(derive_seed(7, 0), derive_seed(8, 0));
+    }
+
+    #[test]
+    fn test_sparse_sign_probes_are_unbiased() {
+        // estimates tr(A) as mean(z^T A z) for a fixed A with sparse and
+        // dense probes, both of which should be close to the true trace
+        let n = 20;
+        let num_probes = 20000;
+        let a = Array::random((n, n), Uniform::new(-1f32, 1f32));
+        let a = &a + &a.t();
+        let true_trace: f32 = a.diag().sum();
+        let estimate =
+            |z: &Array<f32, Ix2>| (z * &a.dot(z)).sum() / num_probes as f32;
+        let dense =
+            estimate(&generate_plus_minus_one_bernoulli_matrix(n, num_probes));
+        let sparse = estimate(&generate_sparse_sign_matrix(n, num_probes, 0.2));
+        assert!((dense - true_trace).abs() < 1., "{} {}", dense, true_trace);
+        assert!(
+            (sparse - true_trace).abs() < 1.,
+            "{} {}",
+            sparse,
+            true_trace
+        );
+    }
 }
@@ -0,0 +1,136 @@
+/// A curated preset of the estimation options. Options given explicitly on
+/// the command line take precedence over the ones in the recipe.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Recipe {
+    pub name: &'static str,
+    pub executable: &'static str,
+    pub description: &'static str,
+    pub num_random_vecs: usize,
+    /// only used by the G + GxG estimator
+    pub num_rand_vecs_gxg: Option<usize>,
+    pub num_jackknife_partitions: usize,
+    pub lowest_allowed_maf: Option<f32>,
+    /// the `--maf-bins` boundaries, only used by the single-kernel estimator
+    pub maf_bins: Option<&'static str>,
+    /// form one GxG component per chromosome of the LE SNPs
+    pub gxg_by_chrom: bool,
+}
+
+pub const RECIPE_LIST_NAME: &str = "list";
+
+pub const RECIPES: [Recipe; 3] = [
+    Recipe {
+        name: "quick-h2",
+        executable: "estimate_heritability",
+        description: "A fast single-component SNP heritability estimate \
+                      with few random vectors and jackknife partitions",
+        num_random_vecs: 10,
+        num_rand_vecs_gxg: None,
+        num_jackknife_partitions: 10,
+        lowest_allowed_maf: None,
+        maf_bins: None,
+        gxg_by_chrom: false,
+    },
+    Recipe {
+        name: "partitioned-maf-ld",
+        executable: "estimate_heritability",
+        description: "Heritability partitioned into MAF bins, crossed with \
+                      the LD bins of --partition if given, dropping SNPs \
+                      with MAF < 0.01",
+        num_random_vecs: 100,
+        num_rand_vecs_gxg: None,
+        num_jackknife_partitions: 20,
+        lowest_allowed_maf: Some(0.01),
+        // the first bin holds the SNPs of MAF in [0.01, 0.05) once the rarer
+        // ones are dropped
+        maf_bins: Some("0.05,0.1,0.2"),
+        gxg_by_chrom: false,
+    },
+    Recipe {
+        name: "g-plus-gxg-chrwise",
+        executable: "estimate_g_gxg_heritability",
+        description: "Additive plus one GxG component per chromosome of the \
+                      LE SNPs",
+        num_random_vecs: 100,
+        num_rand_vecs_gxg: Some(50),
+        num_jackknife_partitions: 20,
+        lowest_allowed_maf: None,
+        maf_bins: None,
+        gxg_by_chrom: true,
+    },
+];
+
+/// `executable` is the name of the calling executable, and recipes meant for
+/// a different executable are rejected.
+pub fn get_recipe(
+    name: &str,
+    executable: &str,
+) -> Result<&'static Recipe, String> {
+    match RECIPES.iter().find(|r| r.name == name) {
+        None => Err(format!(
+            "unknown recipe {}, use --recipe {} to see the available recipes",
+            name, RECIPE_LIST_NAME
+        )),
+        Some(r) if r.executable != executable => Err(format!(
+            "recipe {} is meant for {}, not {}",
+            name, r.executable, executable
+        )),
+        Some(r) => Ok(r),
+    }
+}
+
+pub fn format_recipe_list() -> String {
+    RECIPES
+        .iter()
+        .map(|r| {
+            let mut options = vec![
+                format!("--nrv {}", r.num_random_vecs),
+                format!("--num-jackknifes {}", r.num_jackknife_partitions),
+            ];
+            if let Some(n) = r.num_rand_vecs_gxg {
+                options.push(format!("--nrv-gxg {}", n));
+            }
+            if let Some(maf) = r.lowest_allowed_maf {
+                options.push(format!("--lowest-maf {}", maf));
+            }
+            if let Some(bins) = r.maf_bins {
+                options.push(format!("--maf-bins {}", bins));
+            }
+            if r.gxg_by_chrom {
+                options.push("GxG components by chromosome".to_string());
+            }
+            format!(
+                "{} ({})\n    {}\n    {}",
+                r.name,
+                r.executable,
+                r.description,
+                options.join(", ")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_recipe_list, get_recipe, RECIPES};
+    use crate::maf_bins::MafBins;
+
+    #[test]
+    fn test_get_recipe() {
+        assert_eq!(
+            get_recipe("quick-h2", "estimate_heritability").unwrap(),
+            &RECIPES[0]
+        );
+        assert!(get_recipe("quick-h2", "estimate_g_gxg_heritability").is_err());
+        assert!(get_recipe("unknown", "estimate_heritability").is_err());
+        let list = format_recipe_list();
+        assert!(RECIPES.iter().all(|r| list.contains(r.name)));
+
+        let recipe =
+            get_recipe("partitioned-maf-ld", "estimate_heritability").unwrap();
+        let bins = recipe.maf_bins.unwrap().parse::<MafBins>().unwrap();
+        assert_eq!(bins.num_bins(), 4);
+        assert!(list.contains("--maf-bins 0.05,0.1,0.2"));
+    }
+}
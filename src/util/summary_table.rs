@@ -0,0 +1,105 @@
+use std::io::{stdout, IsTerminal};
+
+use colored::Colorize;
+
+use crate::partitioned_jackknife_estimates::{
+    format_summary_table, PartitionedJackknifeEstimates,
+};
+
+/// Number of bins in the ASCII histograms of the jackknife distributions
+const NUM_HISTOGRAM_BINS: usize = 30;
+
+const NUM_CI_DECIMALS: usize = 5;
+
+/// Prints the estimates as an aligned table with a bold header and total,
+/// followed by the bootstrap and parametric bootstrap confidence intervals if
+/// there are any, an ASCII histogram of the replicate estimates of each
+/// component, the liability scale estimates and the warnings.
+/// The emphasis is dropped when stdout is not a terminal.
+pub fn print_summary_table(
+    title: &str,
+    estimates: &PartitionedJackknifeEstimates,
+) {
+    colored::control::set_override(stdout().is_terminal());
+    let rows = estimates.summary_rows();
+    let lines = format_summary_table(&rows);
+    println!("\n{}", title.bold());
+    println!("{}", lines[0].bold());
+    for (row, line) in rows.iter().zip(lines.iter().skip(1)) {
+        if row.is_total {
+            println!("{}", line.bold().green());
+        } else {
+            println!("{}", line);
+        }
+    }
+    if let Some(intervals) = &estimates.bootstrap_intervals {
+        let name_width = intervals
+            .iter()
+            .map(|i| i.component.len())
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{}",
+            format!(
+                "{:.0}% bootstrap CIs (percentile, BCa)",
+                intervals[0].level * 100.
+            )
+            .bold()
+        );
+        for interval in intervals.iter() {
+            println!(
+                "{:<w$}  {:.*}  {:.*}",
+                interval.component,
+                NUM_CI_DECIMALS,
+                interval.percentile,
+                NUM_CI_DECIMALS,
+                interval.bca,
+                w = name_width
+            );
+        }
+    }
+    if let Some(intervals) = &estimates.parametric_bootstrap_intervals {
+        let name_width = intervals
+            .iter()
+            .map(|i| i.component.len())
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{}",
+            format!(
+                "{:.0}% parametric bootstrap CIs of the normal equations \
+                ({} replicates)",
+                intervals[0].level * 100.,
+                intervals[0].num_reps
+            )
+            .bold()
+        );
+        for interval in intervals.iter() {
+            println!(
+                "{:<w$}  {:.*}",
+                interval.component,
+                NUM_CI_DECIMALS,
+                interval.percentile,
+                w = name_width
+            );
+        }
+    }
+    let histograms = estimates.format_jackknife_histograms(NUM_HISTOGRAM_BINS);
+    if !histograms.is_empty() {
+        println!("{}", "jackknife distributions (min |histogram| max)".bold());
+        for line in histograms.iter() {
+            println!("{}", line);
+        }
+    }
+    if let Some(analytical) = &estimates.analytical_estimate {
+        println!("{}", "analytical sandwich estimates".bold());
+        println!("{}", analytical);
+    }
+    if let Some(liability) = &estimates.liability_scale {
+        println!("{}", "case-control estimates".bold());
+        println!("{}", liability);
+    }
+    for warning in estimates.warnings.iter() {
+        println!("{} {}", "WARNING".bold().yellow(), warning);
+    }
+}
@@ -0,0 +1,976 @@
+use std::{fmt, fs::OpenOptions, io::Read, str::FromStr};
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use ndarray::{Array, Ix2};
+use rayon::prelude::*;
+
+use crate::{
+    trace_estimator::{combine_trace_estimates, get_refinement_seed},
+    util::{
+        load_trace_estimates_with_metadata, matrix_util::DEFAULT_ALPHA,
+        write_trace_estimates_with_metadata,
+    },
+};
+
+/// Lines in a trace estimates file starting with this prefix carry metadata
+/// and are skipped when reading the matrix itself.
+pub const TRACE_METADATA_LINE_PREFIX: &str = "#";
+
+const COMPONENT_KEY: &str = "component";
+const SNP_SET_KEY: &str = "snp_set";
+const NUM_RANDOM_VECS_KEY: &str = "num_random_vecs";
+const STANDARDIZATION_KEY: &str = "standardization";
+const BED_CHECKSUM_KEY: &str = "bed_checksum";
+const SEED_KEY: &str = "seed";
+const SHARD_KEY: &str = "shard";
+const ALPHA_PREFIX: &str = "alpha=";
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+/// `get_file_checksum` reads the files in blocks of this many bytes
+const CHECKSUM_BLOCK_SIZE: usize = 1 << 22;
+/// and hashes the sub-blocks of this many bytes of each block in parallel
+const CHECKSUM_SUB_BLOCK_SIZE: usize = 1 << 16;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Standardization {
+    /// every SNP column is centered and scaled to unit variance
+    UnitVariance,
+    /// every SNP column is centered and scaled by `[2p(1-p)]^(alpha / 2)` for
+    /// its allele frequency `p`, where the alpha is not `DEFAULT_ALPHA`
+    Alpha(f64),
+}
+
+impl Standardization {
+    pub fn from_alpha(alpha: f64) -> Standardization {
+        if alpha == DEFAULT_ALPHA {
+            Standardization::UnitVariance
+        } else {
+            Standardization::Alpha(alpha)
+        }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        match self {
+            Standardization::UnitVariance => DEFAULT_ALPHA,
+            Standardization::Alpha(alpha) => *alpha,
+        }
+    }
+
+    pub fn from_str(s: &str) -> Result<Standardization, String> {
+        if s == "unit_variance" {
+            Ok(Standardization::UnitVariance)
+        } else if s.starts_with(ALPHA_PREFIX) {
+            Ok(Standardization::from_alpha(
+                s[ALPHA_PREFIX.len()..].parse::<f64>().map_err(|why| {
+                    format!("invalid alpha in {}: {}", s, why)
+                })?,
+            ))
+        } else {
+            Err(format!("unrecognized standardization mode: {}", s))
+        }
+    }
+}
+
+impl fmt::Display for Standardization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Standardization::UnitVariance => write!(f, "unit_variance"),
+            Standardization::Alpha(alpha) => {
+                write!(f, "{}{}", ALPHA_PREFIX, alpha)
+            }
+        }
+    }
+}
+
+/// One of `num_shards` shards of the probes of a run, which independent
+/// processes, e.g. the tasks of a cluster array job, estimate separately.
+/// The partial traces of all the shards are merged by
+/// `merge_partial_traces`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ProbeShard {
+    pub index: usize,
+    pub num_shards: usize,
+}
+
+impl ProbeShard {
+    pub fn new(index: usize, num_shards: usize) -> Result<ProbeShard, String> {
+        if index >= num_shards {
+            return Err(format!(
+                "the shard index {} has to be less than the number of shards {}",
+                index, num_shards
+            ));
+        }
+        Ok(ProbeShard {
+            index,
+            num_shards,
+        })
+    }
+
+    /// The range `start..end` of the probes of the shard among the
+    /// `num_random_vecs` probes of the whole run, split as evenly as possible
+    pub fn get_probe_range(&self, num_random_vecs: usize) -> (usize, usize) {
+        let start = |index: usize| {
+            index * (num_random_vecs / self.num_shards)
+                + index.min(num_random_vecs % self.num_shards)
+        };
+        (start(self.index), start(self.index + 1))
+    }
+
+    pub fn get_num_random_vecs(&self, num_random_vecs: usize) -> usize {
+        let (start, end) = self.get_probe_range(num_random_vecs);
+        end - start
+    }
+
+    /// The seed of the probes of the shard, whose streams are independent of
+    /// those of the other shards
+    pub fn get_seed(
+        &self,
+        seed: Option<u64>,
+        num_random_vecs: usize,
+    ) -> Option<u64> {
+        get_refinement_seed(seed, self.get_probe_range(num_random_vecs).0)
+    }
+}
+
+/// Parses `index/num_shards`, e.g. `3/10` for the fourth of ten shards
+impl FromStr for ProbeShard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProbeShard, String> {
+        let toks: Vec<&str> = s.split('/').collect();
+        if toks.len() != 2 {
+            return Err(format!(
+                "expected the shard in the form index/num_shards, received {}",
+                s
+            ));
+        }
+        let parse = |tok: &str| {
+            tok.parse::<usize>()
+                .map_err(|why| format!("invalid shard {}: {}", s, why))
+        };
+        ProbeShard::new(parse(toks[0])?, parse(toks[1])?)
+    }
+}
+
+impl fmt::Display for ProbeShard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.num_shards)
+    }
+}
+
+/// Describes the run that produced a matrix of trace estimates, so that the
+/// estimates are only reused for a run with the same components, SNP sets,
+/// number of random vectors and genotype standardization, and with the same
+/// genotypes and probe seed where those are recorded.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceMetadata {
+    pub component_labels: Vec<String>,
+    pub snp_sets: Vec<(String, OrderedIntegerSet<usize>)>,
+    pub num_random_vecs: usize,
+    pub standardization: Standardization,
+    /// The `get_file_checksum` of the bed files of the run
+    pub bed_checksum: Option<u64>,
+    pub seed: Option<u64>,
+    /// The shard of the probes of a partial run, whose `num_random_vecs`
+    /// are the probes of the shard
+    pub shard: Option<ProbeShard>,
+}
+
+impl TraceMetadata {
+    pub fn new(
+        component_labels: Vec<String>,
+        snp_sets: Vec<(String, OrderedIntegerSet<usize>)>,
+        num_random_vecs: usize,
+        standardization: Standardization,
+    ) -> TraceMetadata {
+        TraceMetadata {
+            component_labels,
+            snp_sets,
+            num_random_vecs,
+            standardization,
+            bed_checksum: None,
+            seed: None,
+            shard: None,
+        }
+    }
+
+    pub fn with_bed_checksum(mut self, bed_checksum: u64) -> TraceMetadata {
+        self.bed_checksum = Some(bed_checksum);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> TraceMetadata {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_shard(mut self, shard: ProbeShard) -> TraceMetadata {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Each metadata line is of the form
+    /// `#key<TAB>value[<TAB>value]`
+    pub fn to_header_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .component_labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{}{}\t{}",
+                    TRACE_METADATA_LINE_PREFIX, COMPONENT_KEY, label
+                )
+            })
+            .collect();
+        for (label, set) in self.snp_sets.iter() {
+            lines.push(format!(
+                "{}{}\t{}\t{}",
+                TRACE_METADATA_LINE_PREFIX,
+                SNP_SET_KEY,
+                label,
+                snp_set_to_string(set)
+            ));
+        }
+        lines.push(format!(
+            "{}{}\t{}",
+            TRACE_METADATA_LINE_PREFIX,
+            NUM_RANDOM_VECS_KEY,
+            self.num_random_vecs
+        ));
+        lines.push(format!(
+            "{}{}\t{}",
+            TRACE_METADATA_LINE_PREFIX,
+            STANDARDIZATION_KEY,
+            self.standardization
+        ));
+        if let Some(bed_checksum) = self.bed_checksum {
+            lines.push(format!(
+                "{}{}\t{:016x}",
+                TRACE_METADATA_LINE_PREFIX, BED_CHECKSUM_KEY, bed_checksum
+            ));
+        }
+        if let Some(seed) = self.seed {
+            lines.push(format!(
+                "{}{}\t{}",
+                TRACE_METADATA_LINE_PREFIX, SEED_KEY, seed
+            ));
+        }
+        if let Some(shard) = self.shard {
+            lines.push(format!(
+                "{}{}\t{}",
+                TRACE_METADATA_LINE_PREFIX, SHARD_KEY, shard
+            ));
+        }
+        lines
+    }
+
+    /// Returns `Ok(None)` if `lines` is empty, i.e. the trace estimates were
+    /// saved without metadata.
+    pub fn from_header_lines(
+        lines: &[String],
+    ) -> Result<Option<TraceMetadata>, String> {
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let mut component_labels = Vec::new();
+        let mut snp_sets = Vec::new();
+        let mut num_random_vecs = None;
+        let mut standardization = None;
+        let mut bed_checksum = None;
+        let mut seed = None;
+        let mut shard = None;
+        for line in lines.iter() {
+            let toks: Vec<&str> = line
+                .trim_start_matches(TRACE_METADATA_LINE_PREFIX)
+                .split('\t')
+                .collect();
+            match (toks[0], toks.len()) {
+                (COMPONENT_KEY, 2) => {
+                    component_labels.push(toks[1].to_string())
+                }
+                (SNP_SET_KEY, 3) => snp_sets
+                    .push((toks[1].to_string(), snp_set_from_str(toks[2])?)),
+                (NUM_RANDOM_VECS_KEY, 2) => {
+                    num_random_vecs =
+                        Some(toks[1].parse::<usize>().map_err(|why| {
+                            format!(
+                                "failed to parse {} as num_random_vecs: {}",
+                                toks[1], why
+                            )
+                        })?)
+                }
+                (STANDARDIZATION_KEY, 2) => {
+                    standardization = Some(Standardization::from_str(toks[1])?)
+                }
+                (BED_CHECKSUM_KEY, 2) => {
+                    bed_checksum = Some(
+                        u64::from_str_radix(toks[1], 16).map_err(|why| {
+                            format!(
+                                "failed to parse {} as the bed checksum: {}",
+                                toks[1], why
+                            )
+                        })?,
+                    )
+                }
+                (SEED_KEY, 2) => {
+                    seed = Some(toks[1].parse::<u64>().map_err(|why| {
+                        format!(
+                            "failed to parse {} as the seed: {}",
+                            toks[1], why
+                        )
+                    })?)
+                }
+                (SHARD_KEY, 2) => shard = Some(toks[1].parse::<ProbeShard>()?),
+                _ => {
+                    return Err(format!(
+                        "invalid trace metadata line: {}",
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(Some(TraceMetadata {
+            component_labels,
+            snp_sets,
+            num_random_vecs: num_random_vecs.ok_or_else(|| {
+                format!("{} missing in the trace metadata", NUM_RANDOM_VECS_KEY)
+            })?,
+            standardization: standardization.ok_or_else(|| {
+                format!("{} missing in the trace metadata", STANDARDIZATION_KEY)
+            })?,
+            bed_checksum,
+            seed,
+            shard,
+        }))
+    }
+
+    /// `self` is the metadata of the saved traces and `current` describes the
+    /// current run. Returns an error listing every mismatch. The bed checksum
+    /// and the seed are compared if the current run records them, and the
+    /// saved traces are refused if they lack a field the current run records,
+    /// as they cannot be validated against it.
+    pub fn check_compatible_with(
+        &self,
+        current: &TraceMetadata,
+    ) -> Result<(), String> {
+        let mut mismatches = Vec::new();
+        if self.component_labels != current.component_labels {
+            mismatches.push(format!(
+                "component labels: saved {:?} vs current {:?}",
+                self.component_labels, current.component_labels
+            ));
+        }
+        if self.snp_sets != current.snp_sets {
+            let saved: Vec<&String> =
+                self.snp_sets.iter().map(|(label, _)| label).collect();
+            let differing: Vec<&String> = self
+                .snp_sets
+                .iter()
+                .zip(current.snp_sets.iter())
+                .filter(|(s, c)| s != c)
+                .map(|((label, _), _)| label)
+                .collect();
+            mismatches.push(format!(
+                "SNP sets: saved {} sets {:?} vs current {} sets, \
+                differing at {:?}",
+                self.snp_sets.len(),
+                saved,
+                current.snp_sets.len(),
+                differing
+            ));
+        }
+        if self.num_random_vecs != current.num_random_vecs {
+            mismatches.push(format!(
+                "num_random_vecs: saved {} vs current {}",
+                self.num_random_vecs, current.num_random_vecs
+            ));
+        }
+        if self.standardization != current.standardization {
+            mismatches.push(format!(
+                "standardization: saved {} vs current {}",
+                self.standardization, current.standardization
+            ));
+        }
+        match (self.bed_checksum, current.bed_checksum) {
+            (Some(saved), Some(current)) if saved != current => mismatches
+                .push(format!(
+                    "bed checksum: saved {:016x} vs current {:016x}",
+                    saved, current
+                )),
+            (None, Some(current)) => mismatches.push(format!(
+                "bed checksum: none saved vs current {:016x}",
+                current
+            )),
+            _ => {}
+        }
+        if self.shard != current.shard {
+            mismatches.push(format!(
+                "probe shard: saved {:?} vs current {:?}",
+                self.shard, current.shard
+            ));
+        }
+        match (self.seed, current.seed) {
+            (Some(saved), Some(current)) if saved != current => mismatches
+                .push(format!("seed: saved {} vs current {}", saved, current)),
+            (None, Some(current)) => mismatches
+                .push(format!("seed: none saved vs current {}", current)),
+            _ => {}
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "the saved trace estimates are incompatible with the current \
+                run:\n{}",
+                mismatches.join("\n")
+            ))
+        }
+    }
+}
+
+/// A matrix of trace estimates together with the metadata of the run that
+/// produced it, which is written as the header of the trace estimates file
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceEstimates {
+    pub traces: Array<f64, Ix2>,
+    pub metadata: TraceMetadata,
+}
+
+impl TraceEstimates {
+    /// The traces have a row and a column for every component of the
+    /// `metadata`.
+    pub fn new(
+        traces: Array<f64, Ix2>,
+        metadata: TraceMetadata,
+    ) -> Result<TraceEstimates, String> {
+        let num_components = metadata.component_labels.len();
+        if traces.dim() != (num_components, num_components) {
+            return Err(format!(
+                "the trace estimates of dim {:?} do not match the {} \
+                components {:?}",
+                traces.dim(),
+                num_components,
+                metadata.component_labels
+            ));
+        }
+        Ok(TraceEstimates {
+            traces,
+            metadata,
+        })
+    }
+
+    pub fn write(&self, out_path: &str) -> Result<(), String> {
+        write_trace_estimates_with_metadata(
+            &self.traces,
+            &self.metadata,
+            out_path,
+        )
+    }
+
+    /// Fails on a file without metadata, e.g. one written by
+    /// `write_trace_estimates`, as its traces cannot be validated.
+    pub fn load(load_path: &str) -> Result<TraceEstimates, String> {
+        match load_trace_estimates_with_metadata(load_path)
+            .map_err(|why| why.to_string())?
+        {
+            (traces, Some(metadata)) => TraceEstimates::new(traces, metadata),
+            (_, None) => Err(format!(
+                "{} has no trace metadata, so its trace estimates cannot be \
+                validated against the current run",
+                load_path
+            )),
+        }
+    }
+
+    /// Loads the traces of `load_path` if their metadata is compatible with
+    /// the `current` run.
+    pub fn load_for_run(
+        load_path: &str,
+        current: &TraceMetadata,
+    ) -> Result<TraceEstimates, String> {
+        let estimates = TraceEstimates::load(load_path)?;
+        estimates.metadata.check_compatible_with(current)?;
+        Ok(estimates)
+    }
+
+    /// Loads the traces of `load_path` to be refined by the `current` run,
+    /// which has to match them in everything but the number of probes.
+    pub fn load_for_refinement(
+        load_path: &str,
+        current: &TraceMetadata,
+    ) -> Result<TraceEstimates, String> {
+        let estimates = TraceEstimates::load(load_path)?;
+        let mut expected = current.clone();
+        expected.num_random_vecs = estimates.metadata.num_random_vecs;
+        expected.seed = estimates.metadata.seed;
+        estimates.metadata.check_compatible_with(&expected)?;
+        Ok(estimates)
+    }
+
+    /// The seed of the probes of a run that refines these traces
+    pub fn get_refinement_seed(&self) -> Option<u64> {
+        get_refinement_seed(self.metadata.seed, self.metadata.num_random_vecs)
+    }
+
+    /// Combines the traces with the `more_traces` estimated on
+    /// `num_more_random_vecs` new probes, e.g. drawn from
+    /// `get_refinement_seed`, into the average over all the probes. The exact
+    /// entries, which are the same in both runs, are kept as they are.
+    pub fn refine(
+        self,
+        more_traces: &Array<f64, Ix2>,
+        num_more_random_vecs: usize,
+    ) -> Result<TraceEstimates, String> {
+        if more_traces.dim() != self.traces.dim() {
+            return Err(format!(
+                "cannot refine the trace estimates of dim {:?} with traces of \
+                dim {:?}",
+                self.traces.dim(),
+                more_traces.dim()
+            ));
+        }
+        let num_random_vecs = self.metadata.num_random_vecs;
+        let mut traces = self.traces;
+        traces.zip_mut_with(more_traces, |a, &b| {
+            *a = combine_trace_estimates(
+                *a,
+                num_random_vecs,
+                b,
+                num_more_random_vecs,
+            )
+        });
+        let mut metadata = self.metadata;
+        metadata.num_random_vecs += num_more_random_vecs;
+        Ok(TraceEstimates {
+            traces,
+            metadata,
+        })
+    }
+}
+
+/// Merges the partial traces of every shard of a run, e.g. loaded from the
+/// files written by the processes of the shards, into the traces of the
+/// whole run. The partial traces are the averages over the probes of their
+/// shards, and are combined into the average over all the probes. Fails
+/// unless every shard is given exactly once and the partial runs match in
+/// everything but their probes.
+pub fn merge_partial_traces(
+    partials: Vec<TraceEstimates>,
+) -> Result<TraceEstimates, String> {
+    let num_shards = match partials.first().and_then(|p| p.metadata.shard) {
+        Some(shard) => shard.num_shards,
+        None => {
+            return Err("expected partial trace estimates with a probe shard"
+                .to_string())
+        }
+    };
+    let mut has_shard = vec![false; num_shards];
+    for partial in partials.iter() {
+        match partial.metadata.shard {
+            Some(shard) if shard.num_shards == num_shards => {
+                if has_shard[shard.index] {
+                    return Err(format!(
+                        "the shard {} is given more than once",
+                        shard
+                    ));
+                }
+                has_shard[shard.index] = true;
+            }
+            shard => {
+                return Err(format!(
+                    "expected a shard of {} shards, found {:?}",
+                    num_shards, shard
+                ))
+            }
+        }
+    }
+    let missing: Vec<usize> =
+        (0..num_shards).filter(|&i| !has_shard[i]).collect();
+    if !missing.is_empty() {
+        return Err(format!(
+            "missing the partial traces of the shards {:?}",
+            missing
+        ));
+    }
+
+    let mut partials = partials.into_iter();
+    let first = partials.next().unwrap();
+    let mut merged = TraceEstimates {
+        traces: first.traces,
+        metadata: TraceMetadata {
+            shard: None,
+            ..first.metadata
+        },
+    };
+    for partial in partials {
+        let mut expected = merged.metadata.clone();
+        expected.num_random_vecs = partial.metadata.num_random_vecs;
+        expected.shard = partial.metadata.shard;
+        partial.metadata.check_compatible_with(&expected)?;
+        merged =
+            merged.refine(&partial.traces, partial.metadata.num_random_vecs)?;
+    }
+    Ok(merged)
+}
+
+/// A 64-bit checksum of the contents of the `paths` in order, e.g. of the
+/// bed files of a run, to tell whether saved traces were estimated on the
+/// same genotypes. The files are read in blocks of `CHECKSUM_BLOCK_SIZE`
+/// bytes, whose sub-blocks are hashed in parallel with FNV-1a over 8-byte
+/// words, and the hashes of the sub-blocks are combined with FNV-1a in order.
+pub fn get_file_checksum(paths: &[String]) -> Result<u64, String> {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut block = Vec::with_capacity(CHECKSUM_BLOCK_SIZE);
+    for path in paths.iter() {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|why| format!("failed to open {}: {}", path, why))?;
+        loop {
+            // the blocks are always filled up to the end of the file, so that
+            // the sub-blocks do not depend on the sizes of the reads
+            block.clear();
+            (&mut file)
+                .take(CHECKSUM_BLOCK_SIZE as u64)
+                .read_to_end(&mut block)
+                .map_err(|why| format!("failed to read {}: {}", path, why))?;
+            if block.is_empty() {
+                break;
+            }
+            let sub_block_hashes: Vec<u64> = block
+                .par_chunks(CHECKSUM_SUB_BLOCK_SIZE)
+                .map(hash_words)
+                .collect();
+            hash = sub_block_hashes.into_iter().fold(hash, fnv_1a_mix);
+        }
+    }
+    Ok(hash)
+}
+
+fn fnv_1a_mix(hash: u64, word: u64) -> u64 {
+    (hash ^ word).wrapping_mul(FNV_PRIME)
+}
+
+/// FNV-1a over the little-endian 8-byte words of `bytes`, the last of which
+/// is padded with zeros, followed by the number of bytes
+fn hash_words(bytes: &[u8]) -> u64 {
+    let hash = bytes.chunks(8).fold(FNV_OFFSET_BASIS, |hash, word| {
+        let mut padded = [0u8; 8];
+        padded[..word.len()].copy_from_slice(word);
+        fnv_1a_mix(hash, u64::from_le_bytes(padded))
+    });
+    fnv_1a_mix(hash, bytes.len() as u64)
+}
+
+/// Formats the set as comma separated inclusive intervals, e.g. `0-9,20-29`
+fn snp_set_to_string(set: &OrderedIntegerSet<usize>) -> String {
+    set.get_intervals_by_ref()
+        .iter()
+        .map(|i| format!("{}-{}", i.get_start(), i.get_end()))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn snp_set_from_str(s: &str) -> Result<OrderedIntegerSet<usize>, String> {
+    if s.is_empty() {
+        return Ok(OrderedIntegerSet::new());
+    }
+    Ok(OrderedIntegerSet::from(
+        s.split(',')
+            .map(|interval| {
+                let bounds = interval
+                    .split('-')
+                    .map(|b| b.parse::<usize>())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .map_err(|why| {
+                        format!("invalid SNP interval {}: {}", interval, why)
+                    })?;
+                if bounds.len() != 2 {
+                    return Err(format!("invalid SNP interval {}", interval));
+                }
+                Ok(ContiguousIntegerSet::new(bounds[0], bounds[1]))
+            })
+            .collect::<Result<Vec<ContiguousIntegerSet<usize>>, String>>()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::Array;
+    use tempfile::NamedTempFile;
+
+    use super::{
+        get_file_checksum, merge_partial_traces, ProbeShard, Standardization,
+        TraceEstimates, TraceMetadata, CHECKSUM_SUB_BLOCK_SIZE,
+    };
+    use crate::{
+        trace_estimator::get_refinement_seed, util::write_trace_estimates,
+    };
+
+    fn get_metadata() -> TraceMetadata {
+        TraceMetadata::new(
+            vec!["G".to_string(), "GxG 1".to_string(), "noise".to_string()],
+            vec![
+                ("G".to_string(), OrderedIntegerSet::from_slice(&[[0, 99]])),
+                (
+                    "GxG 1".to_string(),
+                    OrderedIntegerSet::from_slice(&[[3, 5], [10, 20]]),
+                ),
+            ],
+            50,
+            Standardization::UnitVariance,
+        )
+    }
+
+    #[test]
+    fn test_header_lines_round_trip() {
+        let metadata = get_metadata();
+        let lines = metadata.to_header_lines();
+        assert_eq!(
+            TraceMetadata::from_header_lines(&lines).unwrap(),
+            Some(metadata)
+        );
+        assert_eq!(TraceMetadata::from_header_lines(&[]).unwrap(), None);
+
+        let mut metadata = get_metadata();
+        metadata.standardization = Standardization::from_alpha(-0.25);
+        let lines = metadata.to_header_lines();
+        assert_eq!(
+            TraceMetadata::from_header_lines(&lines).unwrap(),
+            Some(metadata)
+        );
+        assert_eq!(
+            Standardization::from_str("alpha=-1").unwrap(),
+            Standardization::UnitVariance
+        );
+        assert!(Standardization::from_str("alpha=x").is_err());
+
+        let metadata = get_metadata()
+            .with_bed_checksum(0xabc)
+            .with_seed(7)
+            .with_shard(ProbeShard::new(2, 5).unwrap());
+        let lines = metadata.to_header_lines();
+        assert_eq!(
+            TraceMetadata::from_header_lines(&lines).unwrap(),
+            Some(metadata)
+        );
+    }
+
+    #[test]
+    fn test_check_compatible_with() {
+        let saved = get_metadata();
+        assert!(saved.check_compatible_with(&get_metadata()).is_ok());
+
+        let mut current = get_metadata();
+        current.num_random_vecs = 10;
+        assert!(saved.check_compatible_with(&current).is_err());
+
+        let mut current = get_metadata();
+        current.snp_sets[1].1 = OrderedIntegerSet::from_slice(&[[3, 5]]);
+        assert!(saved.check_compatible_with(&current).is_err());
+
+        let mut current = get_metadata();
+        current.component_labels.pop();
+        assert!(saved.check_compatible_with(&current).is_err());
+
+        let mut current = get_metadata();
+        current.standardization = Standardization::Alpha(-0.25);
+        assert!(saved.check_compatible_with(&current).is_err());
+
+        let saved = get_metadata().with_bed_checksum(1).with_seed(7);
+        assert!(saved
+            .check_compatible_with(&get_metadata().with_bed_checksum(2))
+            .is_err());
+        assert!(saved
+            .check_compatible_with(&get_metadata().with_seed(8))
+            .is_err());
+        // runs that do not record the checksum or the seed are not compared
+        // on them
+        assert!(saved.check_compatible_with(&get_metadata()).is_ok());
+        // saved traces that lack a field recorded by the current run are
+        // refused
+        assert!(get_metadata()
+            .check_compatible_with(&get_metadata().with_bed_checksum(2))
+            .is_err());
+        assert!(get_metadata()
+            .check_compatible_with(&get_metadata().with_seed(7))
+            .is_err());
+        // partial traces are only reused once merged
+        let partial = get_metadata().with_shard(ProbeShard::new(0, 2).unwrap());
+        assert!(partial.check_compatible_with(&get_metadata()).is_err());
+    }
+
+    #[test]
+    fn test_probe_shard() {
+        let shards: Vec<ProbeShard> =
+            (0..3).map(|i| ProbeShard::new(i, 3).unwrap()).collect();
+        let ranges: Vec<(usize, usize)> =
+            shards.iter().map(|s| s.get_probe_range(10)).collect();
+        assert_eq!(ranges, vec![(0, 4), (4, 7), (7, 10)]);
+        assert_eq!(shards[2].get_num_random_vecs(10), 3);
+        assert_eq!(shards[0].get_seed(None, 10), None);
+        assert_ne!(
+            shards[0].get_seed(Some(1), 10),
+            shards[1].get_seed(Some(1), 10)
+        );
+        assert!(ProbeShard::new(3, 3).is_err());
+        assert_eq!("1/3".parse::<ProbeShard>(), Ok(shards[1]));
+        assert_eq!(shards[1].to_string(), "1/3");
+        assert!("1".parse::<ProbeShard>().is_err());
+        assert!("3/3".parse::<ProbeShard>().is_err());
+    }
+
+    #[test]
+    fn test_merge_partial_traces() {
+        let partial = |index: usize, num_random_vecs: usize, value: f64| {
+            let mut metadata =
+                get_metadata().with_shard(ProbeShard::new(index, 3).unwrap());
+            metadata.num_random_vecs = num_random_vecs;
+            TraceEstimates::new(Array::from_elem((3, 3), value), metadata)
+                .unwrap()
+        };
+        let merged = merge_partial_traces(vec![
+            partial(2, 10, 3.),
+            partial(0, 20, 1.),
+            partial(1, 10, 2.),
+        ])
+        .unwrap();
+        let mut expected_metadata = get_metadata();
+        expected_metadata.num_random_vecs = 40;
+        assert_eq!(merged.metadata, expected_metadata);
+        assert!(merged.traces.iter().all(|&t| (t - 1.75).abs() < 1e-12));
+
+        assert!(merge_partial_traces(vec![
+            partial(0, 20, 1.),
+            partial(1, 10, 2.)
+        ])
+        .is_err());
+        assert!(merge_partial_traces(vec![
+            partial(0, 20, 1.),
+            partial(0, 20, 1.),
+            partial(1, 10, 2.),
+            partial(2, 10, 3.),
+        ])
+        .is_err());
+        let mut other = partial(1, 10, 2.);
+        other.metadata.standardization = Standardization::Alpha(-0.25);
+        assert!(merge_partial_traces(vec![
+            partial(0, 20, 1.),
+            other,
+            partial(2, 10, 3.),
+        ])
+        .is_err());
+        assert!(merge_partial_traces(vec![]).is_err());
+        assert!(merge_partial_traces(vec![TraceEstimates::new(
+            Array::eye(3),
+            get_metadata()
+        )
+        .unwrap()])
+        .is_err());
+    }
+
+    #[test]
+    fn test_trace_estimates() {
+        let path = NamedTempFile::new()
+            .unwrap()
+            .into_temp_path()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let metadata = get_metadata().with_bed_checksum(0xabc).with_seed(7);
+        assert!(TraceEstimates::new(Array::eye(2), metadata.clone()).is_err());
+        let estimates = TraceEstimates::new(
+            Array::from_shape_fn((3, 3), |(i, j)| (i * j) as f64 + 0.5),
+            metadata.clone(),
+        )
+        .unwrap();
+        estimates.write(&path).unwrap();
+        assert_eq!(TraceEstimates::load(&path).unwrap(), estimates);
+        assert_eq!(
+            TraceEstimates::load_for_run(&path, &metadata).unwrap(),
+            estimates
+        );
+        assert!(TraceEstimates::load_for_run(
+            &path,
+            &get_metadata().with_bed_checksum(0xabd)
+        )
+        .is_err());
+        // the traces of the probes of another seed are rejected
+        assert!(TraceEstimates::load_for_run(
+            &path,
+            &get_metadata().with_bed_checksum(0xabc).with_seed(8)
+        )
+        .is_err());
+
+        // refining 50 probes with 150 more weights the new traces by 3 / 4
+        let current = get_metadata().with_bed_checksum(0xabc);
+        let mut refining = current.clone();
+        refining.num_random_vecs = 150;
+        let loaded =
+            TraceEstimates::load_for_refinement(&path, &refining).unwrap();
+        assert_eq!(
+            loaded.get_refinement_seed(),
+            get_refinement_seed(Some(7), 50)
+        );
+        let refined =
+            loaded.refine(&Array::from_elem((3, 3), 4.5), 150).unwrap();
+        assert_eq!(refined.metadata.num_random_vecs, 200);
+        assert_eq!(refined.metadata.seed, Some(7));
+        let expected = Array::from_shape_fn((3, 3), |(i, j)| {
+            ((i * j) as f64 + 0.5) / 4. + 4.5 * 3. / 4.
+        });
+        assert!(refined
+            .traces
+            .iter()
+            .zip(expected.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-12));
+        assert!(estimates.clone().refine(&Array::eye(2), 10).is_err());
+        let mut other_labels = refining.clone();
+        other_labels.component_labels[0] = "G2".to_string();
+        assert!(
+            TraceEstimates::load_for_refinement(&path, &other_labels).is_err()
+        );
+
+        write_trace_estimates(&estimates.traces, &path).unwrap();
+        assert!(TraceEstimates::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_get_file_checksum() {
+        let mut a = NamedTempFile::new().unwrap();
+        let mut b = NamedTempFile::new().unwrap();
+        a.write_all(b"abc").unwrap();
+        b.write_all(b"abd").unwrap();
+        let path = |f: &NamedTempFile| f.path().to_str().unwrap().to_string();
+        let checksum = get_file_checksum(&[path(&a)]).unwrap();
+        assert_eq!(checksum, get_file_checksum(&[path(&a)]).unwrap());
+        assert_ne!(checksum, get_file_checksum(&[path(&b)]).unwrap());
+        assert_ne!(
+            get_file_checksum(&[path(&a), path(&b)]).unwrap(),
+            get_file_checksum(&[path(&b), path(&a)]).unwrap()
+        );
+        // the FNV-1a hash of the empty input is the offset basis
+        assert_eq!(get_file_checksum(&[]).unwrap(), 0xcbf2_9ce4_8422_2325);
+        assert!(get_file_checksum(&["/nonexistent/x.bed".to_string()]).is_err());
+
+        // a byte in the last of several sub-blocks changes the checksum
+        let mut bytes: Vec<u8> = (0..3 * CHECKSUM_SUB_BLOCK_SIZE + 5)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut c = NamedTempFile::new().unwrap();
+        c.write_all(&bytes).unwrap();
+        let checksum = get_file_checksum(&[path(&c)]).unwrap();
+        assert_eq!(checksum, get_file_checksum(&[path(&c)]).unwrap());
+        *bytes.last_mut().unwrap() ^= 1;
+        let mut d = NamedTempFile::new().unwrap();
+        d.write_all(&bytes).unwrap();
+        assert_ne!(checksum, get_file_checksum(&[path(&d)]).unwrap());
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+/// The conditions that make a run questionable without failing it. Each
+/// condition has a stable code, which is printed with the warning and
+/// written to the JSON results, so that pipelines can flag the runs without
+/// parsing the messages. A code is never reused for a different condition.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum WarningCode {
+    /// A variance component estimate is negative. The estimates are reported
+    /// as is rather than clamped at zero, so that they stay unbiased.
+    NegativeVariance,
+    /// A matrix had to be repaired to be positive semi-definite.
+    MatrixPsdRepaired,
+    /// SNPs with zero variance were left out of the kernels.
+    ZeroVarianceSnpsDropped,
+    /// Samples with a missing phenotype were left out.
+    MissingPhenotypeSamplesDropped,
+    /// An iterative estimator stopped at its maximum number of iterations
+    /// before converging.
+    NotConverged,
+    /// SNPs of the G component are also LE SNPs of the GxG components, so
+    /// their variance is counted by both.
+    GxgSnpsOverlapG,
+}
+
+impl WarningCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningCode::NegativeVariance => "W001",
+            WarningCode::MatrixPsdRepaired => "W002",
+            WarningCode::ZeroVarianceSnpsDropped => "W003",
+            WarningCode::MissingPhenotypeSamplesDropped => "W004",
+            WarningCode::NotConverged => "W005",
+            WarningCode::GxgSnpsOverlapG => "W006",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl Serialize for WarningCode {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(code: WarningCode, message: String) -> Warning {
+        Warning {
+            code,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}]: {}", self.code, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Warning, WarningCode};
+
+    #[test]
+    fn test_warning_serialization() {
+        let warning = Warning::new(
+            WarningCode::NegativeVariance,
+            "the estimate of g is negative".to_string(),
+        );
+        assert_eq!(
+            format!("{}", warning),
+            "[W001]: the estimate of g is negative"
+        );
+        assert_eq!(
+            serde_json::to_string(&warning).unwrap(),
+            r#"{"code":"W001","message":"the estimate of g is negative"}"#
+        );
+    }
+}
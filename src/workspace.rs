@@ -0,0 +1,195 @@
+//! The workspaces of the probe matrices and their products with the kernels.
+//! With hundreds of probes on hundreds of thousands of people, the probes
+//! `Z` and the products `K_i Z` of every kernel take more memory than the
+//! streamed SNP chunks. A workspace holds such an f32 matrix either in memory
+//! or, when it is spilled, in a memory-mapped temporary file that is removed
+//! once the workspace is dropped, and it is read and written in blocks of
+//! columns so that only a block of each matrix is in memory at a time.
+//!
+//! Whether the workspaces are spilled, and the width of the blocks, are
+//! derived from the memory budget of the `ComputeConfig`.
+
+use std::fs::File;
+
+use memmap::MmapMut;
+use ndarray::{s, Array, ArrayView, ArrayViewMut, Ix2};
+
+/// An `f32` matrix of `num_rows x num_cols` entries in row-major order in a
+/// memory-mapped temporary file
+pub struct MappedMatrix {
+    mmap: MmapMut,
+    num_rows: usize,
+    num_cols: usize,
+    _file: File,
+}
+
+impl MappedMatrix {
+    pub fn zeros(
+        num_rows: usize,
+        num_cols: usize,
+    ) -> Result<MappedMatrix, String> {
+        let num_bytes = num_rows * num_cols * std::mem::size_of::<f32>();
+        let file = tempfile::tempfile().map_err(|why| {
+            format!("failed to create a temporary workspace file: {}", why)
+        })?;
+        file.set_len(num_bytes as u64).map_err(|why| {
+            format!(
+                "failed to allocate {} bytes for the workspace file: {}",
+                num_bytes, why
+            )
+        })?;
+        // the file is private to the workspace, so no other process can
+        // change it under the mapping
+        let mmap = unsafe { MmapMut::map_mut(&file) }.map_err(|why| {
+            format!("failed to memory-map the workspace file: {}", why)
+        })?;
+        Ok(MappedMatrix {
+            mmap,
+            num_rows,
+            num_cols,
+            _file: file,
+        })
+    }
+
+    // The mapping is page aligned and hence aligned for f32, and a new file
+    // is zero-filled, so every entry is a valid f32.
+    fn as_slice(&self) -> &[f32] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.as_ptr() as *const f32,
+                self.num_rows * self.num_cols,
+            )
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [f32] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.mmap.as_mut_ptr() as *mut f32,
+                self.num_rows * self.num_cols,
+            )
+        }
+    }
+}
+
+pub enum MatrixWorkspace {
+    InMemory(Array<f32, Ix2>),
+    Mapped(MappedMatrix),
+}
+
+impl MatrixWorkspace {
+    /// An empty matrix is always held in memory, as a file of zero bytes
+    /// cannot be mapped.
+    pub fn zeros(
+        num_rows: usize,
+        num_cols: usize,
+        spill: bool,
+    ) -> Result<MatrixWorkspace, String> {
+        if spill && num_rows * num_cols > 0 {
+            Ok(MatrixWorkspace::Mapped(MappedMatrix::zeros(
+                num_rows, num_cols,
+            )?))
+        } else {
+            Ok(MatrixWorkspace::InMemory(Array::zeros((
+                num_rows, num_cols,
+            ))))
+        }
+    }
+
+    pub fn dim(&self) -> (usize, usize) {
+        match self {
+            MatrixWorkspace::InMemory(arr) => arr.dim(),
+            MatrixWorkspace::Mapped(m) => (m.num_rows, m.num_cols),
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            MatrixWorkspace::InMemory(_) => false,
+            MatrixWorkspace::Mapped(_) => true,
+        }
+    }
+
+    pub fn view(&self) -> ArrayView<f32, Ix2> {
+        match self {
+            MatrixWorkspace::InMemory(arr) => arr.view(),
+            MatrixWorkspace::Mapped(m) => {
+                ArrayView::from_shape((m.num_rows, m.num_cols), m.as_slice())
+                    .unwrap()
+            }
+        }
+    }
+
+    pub fn view_mut(&mut self) -> ArrayViewMut<f32, Ix2> {
+        match self {
+            MatrixWorkspace::InMemory(arr) => arr.view_mut(),
+            MatrixWorkspace::Mapped(m) => {
+                let shape = (m.num_rows, m.num_cols);
+                ArrayViewMut::from_shape(shape, m.as_mut_slice()).unwrap()
+            }
+        }
+    }
+
+    /// A copy of the columns `start..end` in memory
+    pub fn get_column_block(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Array<f32, Ix2> {
+        self.view().slice(s![.., start..end]).to_owned()
+    }
+
+    /// Overwrites the columns from `start` on with the `block`
+    pub fn set_column_block(&mut self, start: usize, block: &Array<f32, Ix2>) {
+        let end = start + block.dim().1;
+        self.view_mut().slice_mut(s![.., start..end]).assign(block);
+    }
+}
+
+/// The ranges of columns of width at most `num_columns_per_block` that cover
+/// `num_columns` columns
+pub fn get_column_blocks(
+    num_columns: usize,
+    num_columns_per_block: usize,
+) -> Vec<(usize, usize)> {
+    let width = std::cmp::max(1, num_columns_per_block);
+    (0..num_columns)
+        .step_by(width)
+        .map(|start| (start, std::cmp::min(start + width, num_columns)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{array, Array};
+
+    use super::{get_column_blocks, MatrixWorkspace};
+
+    #[test]
+    fn test_matrix_workspace() {
+        for &spill in [false, true].iter() {
+            let mut workspace = MatrixWorkspace::zeros(3, 5, spill).unwrap();
+            assert_eq!(workspace.is_mapped(), spill);
+            assert_eq!(workspace.dim(), (3, 5));
+            assert_eq!(workspace.view(), Array::<f32, _>::zeros((3, 5)));
+            workspace
+                .set_column_block(1, &array![[1f32, 2.], [3., 4.], [5., 6.]]);
+            workspace.view_mut()[[2, 4]] = 7.;
+            assert_eq!(workspace.view(), array![
+                [0f32, 1., 2., 0., 0.],
+                [0., 3., 4., 0., 0.],
+                [0., 5., 6., 0., 7.]
+            ]);
+            assert_eq!(workspace.get_column_block(2, 5), array![
+                [2f32, 0., 0.],
+                [4., 0., 0.],
+                [6., 0., 7.]
+            ]);
+        }
+        assert!(!MatrixWorkspace::zeros(0, 5, true).unwrap().is_mapped());
+
+        assert_eq!(get_column_blocks(7, 3), vec![(0, 3), (3, 6), (6, 7)]);
+        assert_eq!(get_column_blocks(2, 5), vec![(0, 2)]);
+        assert!(get_column_blocks(0, 5).is_empty());
+    }
+}
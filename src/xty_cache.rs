@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use math::set::{
+    contiguous_integer_set::ContiguousIntegerSet,
+    ordered_integer_set::OrderedIntegerSet,
+};
+use ndarray::{Array, Ix2};
+
+use crate::{
+    integer_set::ops::IntervalList,
+    snp_weights::{weight_snp_chunk_inplace, SnpWeights},
+    stochastic::GenotypeSource,
+    util::matrix_util::normalize_matrix_columns_inplace,
+};
+
+/// Caches the products `X^T y` of the standardized genotypes `X` with the
+/// phenotypes `y`, one chunk of SNPs at a time. Chunk `c` covers the SNPs
+/// `[c * chunk_size, (c + 1) * chunk_size)`, so every chunk is read from the
+/// bed file and multiplied at most once, no matter how many overlapping SNP
+/// ranges are queried.
+///
+/// Since `y^T X X^T y` is a sum over the SNPs, the quadratic form restricted
+/// to any set of SNPs, e.g. a partition without a jackknife block, is the sum
+/// of squares of the cached products of those SNPs.
+pub struct XtyCache {
+    chunk_size: usize,
+    num_phenos: usize,
+    /// `chunks[&c][[i, p]]` is the product of the `i`-th SNP in chunk `c`
+    /// with phenotype `p`
+    chunks: HashMap<usize, Array<f32, Ix2>>,
+}
+
+impl XtyCache {
+    pub fn new(chunk_size: usize, num_phenos: usize) -> XtyCache {
+        assert!(chunk_size > 0, "the chunk size has to be positive");
+        XtyCache {
+            chunk_size,
+            num_phenos,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn num_cached_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The indices of the chunks overlapping `snp_range` in increasing order
+    fn get_chunk_indices(
+        &self,
+        snp_range: &OrderedIntegerSet<usize>,
+    ) -> Vec<usize> {
+        let mut indices: Vec<usize> = Vec::new();
+        for interval in snp_range.interval_list().iter() {
+            let first = interval.get_start() / self.chunk_size;
+            let last = interval.get_end() / self.chunk_size;
+            for c in first..=last {
+                if indices.last() != Some(&c) {
+                    indices.push(c);
+                }
+            }
+        }
+        indices
+    }
+
+    /// Computes the products for the chunks overlapping `snp_range` that are
+    /// not cached yet. `pheno_matrix` has one normalized phenotype per column
+    /// and, like the `snp_weights` that scale the products by the square roots
+    /// of the weights, has to be the same across the calls.
+    pub fn extend<G: GenotypeSource>(
+        &mut self,
+        geno: &G,
+        snp_range: &OrderedIntegerSet<usize>,
+        snp_weights: Option<&SnpWeights>,
+        pheno_matrix: &Array<f32, Ix2>,
+    ) -> Result<(), String> {
+        if pheno_matrix.dim() != (geno.num_people(), self.num_phenos) {
+            return Err(format!(
+                "expected a phenotype matrix of shape ({}, {}), received {:?}",
+                geno.num_people(),
+                self.num_phenos,
+                pheno_matrix.dim()
+            ));
+        }
+        let total_num_snps = geno.num_snps();
+        let missing: Vec<usize> = self
+            .get_chunk_indices(snp_range)
+            .into_iter()
+            .filter(|c| !self.chunks.contains_key(c))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let mut missing_intervals = Vec::new();
+        for &c in missing.iter() {
+            let start = c * self.chunk_size;
+            if start >= total_num_snps {
+                return Err(format!(
+                    "SNP index {} out of range for {} SNPs",
+                    start, total_num_snps
+                ));
+            }
+            let end =
+                std::cmp::min((c + 1) * self.chunk_size, total_num_snps) - 1;
+            missing_intervals.push(ContiguousIntegerSet::new(start, end));
+        }
+        // all the missing chunks are full except possibly the last chunk of
+        // the genome, so the chunks streamed line up with them
+        let chunk_size = self.chunk_size;
+        let missing_range = OrderedIntegerSet::from(missing_intervals);
+        let sqrt_weights =
+            snp_weights.map(|w| w.get_sqrt_weights(Some(&missing_range)));
+        let sqrt_weights = sqrt_weights.as_deref();
+        let products: Vec<(usize, Array<f32, Ix2>)> = geno.fold_snp_chunks(
+            Some(missing_range),
+            chunk_size,
+            Vec::new,
+            |mut acc, rank, mut snp_chunk| {
+                normalize_matrix_columns_inplace(&mut snp_chunk, 0);
+                weight_snp_chunk_inplace(&mut snp_chunk, sqrt_weights, rank);
+                acc.push((rank / chunk_size, snp_chunk.t().dot(pheno_matrix)));
+                acc
+            },
+            |mut a, mut b| {
+                a.append(&mut b);
+                a
+            },
+        );
+        for (i, product) in products.into_iter() {
+            self.chunks.insert(missing[i], product);
+        }
+        Ok(())
+    }
+
+    /// `y^T X X^T y` restricted to the SNPs in `snp_range` for each of the
+    /// phenotypes. All the chunks overlapping `snp_range` have to be cached.
+    pub fn sum_of_squares(
+        &self,
+        snp_range: &OrderedIntegerSet<usize>,
+    ) -> Result<Vec<f64>, String> {
+        let mut sums = vec![0f64; self.num_phenos];
+        for interval in snp_range.interval_list().iter() {
+            let mut start = interval.get_start();
+            let end = interval.get_end();
+            loop {
+                let c = start / self.chunk_size;
+                let chunk_start = c * self.chunk_size;
+                let chunk_end =
+                    std::cmp::min(end, chunk_start + self.chunk_size - 1);
+                let products = self.chunks.get(&c).ok_or_else(|| {
+                    format!("the chunk containing SNP {} is not cached", start)
+                })?;
+                if chunk_end - chunk_start >= products.dim().0 {
+                    return Err(format!(
+                        "SNP index {} out of range for the cached chunk",
+                        chunk_end
+                    ));
+                }
+                for i in start - chunk_start..=chunk_end - chunk_start {
+                    for (p, sum) in sums.iter_mut().enumerate() {
+                        let x = products[[i, p]] as f64;
+                        *sum += x * x;
+                    }
+                }
+                if chunk_end == end {
+                    break;
+                }
+                start = chunk_end + 1;
+            }
+        }
+        Ok(sums)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::set::ordered_integer_set::OrderedIntegerSet;
+    use ndarray::array;
+
+    use super::XtyCache;
+
+    #[test]
+    fn test_sum_of_squares() {
+        let mut cache = XtyCache::new(3, 2);
+        cache.chunks.insert(0, array![[1., 0.], [2., 1.], [3., 0.]]);
+        cache.chunks.insert(1, array![[1., 1.], [1., 2.], [0., 3.]]);
+        cache.chunks.insert(2, array![[2., 2.]]);
+        assert_eq!(
+            cache.get_chunk_indices(&OrderedIntegerSet::from_slice(&[
+                [1, 1],
+                [2, 4],
+                [6, 6]
+            ])),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            cache
+                .sum_of_squares(&OrderedIntegerSet::from_slice(&[[1, 4]]))
+                .unwrap(),
+            vec![4. + 9. + 1. + 1., 1. + 1. + 4.]
+        );
+        assert_eq!(
+            cache
+                .sum_of_squares(&OrderedIntegerSet::from_slice(&[[0, 0], [
+                    5, 6
+                ]]))
+                .unwrap(),
+            vec![1. + 4., 9. + 4.]
+        );
+        assert_eq!(
+            cache.sum_of_squares(&OrderedIntegerSet::new()).unwrap(),
+            vec![0., 0.]
+        );
+        // SNP 7 is past the end of the last chunk
+        assert!(cache
+            .sum_of_squares(&OrderedIntegerSet::from_slice(&[[6, 7]]))
+            .is_err());
+        assert!(cache
+            .sum_of_squares(&OrderedIntegerSet::from_slice(&[[9, 9]]))
+            .is_err());
+    }
+}